@@ -0,0 +1,21 @@
+fn main() {
+    #[cfg(feature = "zhtlc")]
+    compile_lightwalletd_protos();
+}
+
+/// Generates the lightwalletd gRPC client of the ZHTLC light client.
+#[cfg(feature = "zhtlc")]
+fn compile_lightwalletd_protos() {
+    const PROTOS: &[&str] = &["z_coin/z_proto/service.proto"];
+    let res = tonic_build::configure()
+        .build_server(false)
+        .compile(PROTOS, &["z_coin/z_proto"]);
+    if let Err(e) = res {
+        eprintln!(
+            "Error on compiling the lightwalletd protos {:?}: {}. \
+             Make sure the protos are valid and `protoc` is available or set by the PROTOC env variable.",
+            PROTOS, e
+        );
+        std::process::exit(1);
+    }
+}
@@ -25,12 +25,13 @@ use common::executor::Timer;
 use common::log::error;
 use common::mm_ctx::{MmArc, MmWeak};
 use common::mm_error::prelude::*;
+use common::proxy::coin_proxy;
 use common::{now_ms, slurp_url, small_rng, DEX_FEE_ADDR_RAW_PUBKEY};
 use derive_more::Display;
 use ethabi::{Contract, Token};
 use ethcore_transaction::{Action, Transaction as UnSignedEthTx, UnverifiedTransaction};
-use ethereum_types::{Address, H160, U256};
-use ethkey::{public_to_address, KeyPair, Public};
+use ethereum_types::{Address, H160, H256, U256};
+use ethkey::{public_to_address, recover, KeyPair, Public, Signature};
 use futures::compat::Future01CompatExt;
 use futures::future::{join_all, select, Either, FutureExt, TryFutureExt};
 use futures01::Future;
@@ -52,12 +53,15 @@ use web3::types::{Action as TraceAction, BlockId, BlockNumber, Bytes, CallReques
                   TraceFilterBuilder, Transaction as Web3Transaction, TransactionId};
 use web3::{self, Web3};
 
+use super::message_signing::{SignatureError, SignatureResult};
+use super::signer::{ExternalSigner, Signer};
+use super::watch_only::{watch_only_coin_key, WatchOnlyCoinKey, WatchOnlyError};
 use super::{BalanceError, BalanceFut, CoinBalance, CoinProtocol, CoinTransportMetrics, CoinsContext, FeeApproxStage,
             FoundSwapTxSpend, HistorySyncState, MarketCoinOps, MmCoin, NegotiateSwapContractAddrErr, NumConversError,
             NumConversResult, RpcClientType, RpcTransportEventHandler, RpcTransportEventHandlerShared, SwapOps,
             TradeFee, TradePreimageError, TradePreimageFut, TradePreimageValue, Transaction, TransactionDetails,
-            TransactionEnum, TransactionFut, ValidateAddressResult, WithdrawError, WithdrawFee, WithdrawFut,
-            WithdrawRequest, WithdrawResult};
+            TransactionEnum, TransactionErr, TransactionFut, ValidateAddressResult, WithdrawError, WithdrawFee,
+            WithdrawFut, WithdrawRequest, WithdrawResult};
 pub use ethcore_transaction::SignedTransaction as SignedEthTx;
 pub use rlp;
 
@@ -240,6 +244,41 @@ struct SavedErc20Events {
     latest_block: U256,
 }
 
+/// The key pair the coin is activated with.
+#[derive(Debug)]
+pub enum EthKeyPair {
+    KeyPair(KeyPair),
+    /// The private key is kept by the external signer.
+    External(ExternalSigner),
+    /// Only the address is known if MM2 runs in the watch-only mode.
+    WatchOnly,
+}
+
+impl From<KeyPair> for EthKeyPair {
+    fn from(key_pair: KeyPair) -> Self { EthKeyPair::KeyPair(key_pair) }
+}
+
+impl EthKeyPair {
+    /// Returns the signer to sign the transactions with.
+    pub fn signer(&self) -> Result<&dyn Signer, WatchOnlyError> {
+        match self {
+            EthKeyPair::KeyPair(key_pair) => Ok(key_pair),
+            EthKeyPair::External(signer) => Ok(signer),
+            EthKeyPair::WatchOnly => Err(WatchOnlyError),
+        }
+    }
+
+    /// Signs the transaction according to EIP-155 if `chain_id` is set.
+    fn sign_tx(&self, tx: UnSignedEthTx, chain_id: Option<u64>) -> Result<SignedEthTx, String> {
+        let hash = tx.hash(chain_id);
+        let signature = try_s!(try_s!(self.signer()).sign_hash_recoverable(&hash.0.into()));
+        let mut rsv = [0; 65];
+        rsv.copy_from_slice(&*signature);
+        let unverified = tx.with_signature(rsv.into(), chain_id);
+        Ok(try_s!(SignedEthTx::new(unverified)))
+    }
+}
+
 #[derive(Debug, PartialEq, Eq)]
 enum EthCoinType {
     /// Ethereum itself or it's forks: ETC/others
@@ -254,7 +293,7 @@ enum EthCoinType {
 pub struct EthCoinImpl {
     ticker: String,
     coin_type: EthCoinType,
-    key_pair: KeyPair,
+    key_pair: EthKeyPair,
     my_address: Address,
     swap_contract_address: Address,
     fallback_swap_contract: Option<Address>,
@@ -496,9 +535,63 @@ impl EthCoinImpl {
     pub fn address_from_str(&self, address: &str) -> Result<Address, String> {
         Ok(try_s!(valid_addr_from_str(address)))
     }
+
+    /// Signs the message according to EIP-191 (`personal_sign`).
+    /// Returns the `0x`-prefixed `r || s || v` signature where `v` is 27 or 28.
+    pub fn sign_message(&self, message: &str) -> SignatureResult<String> {
+        let hash = personal_message_hash(message);
+        let mut rsv = self
+            .key_pair
+            .signer()?
+            .sign_hash_recoverable(&hash.0.into())
+            .map_to_mm(SignatureError::InternalError)?;
+        rsv[64] += 27;
+        Ok(format!("0x{}", hex::encode(&rsv[..])))
+    }
+
+    /// Checks if the EIP-191 `signature` of the message is made by the key of the `address`.
+    pub fn verify_message(&self, message: &str, signature: &str, address: &str) -> SignatureResult<bool> {
+        let address = self
+            .address_from_str(address)
+            .map_to_mm(SignatureError::InvalidAddress)?;
+        let signature = hex::decode(signature.trim_start_matches("0x"))
+            .map_to_mm(|e| SignatureError::InvalidSignature(e.to_string()))?;
+        if signature.len() != 65 {
+            return MmError::err(SignatureError::InvalidSignature(format!(
+                "Expected 65 bytes, found {}",
+                signature.len()
+            )));
+        }
+        // both 27/28 and 0/1 recovery ids are accepted
+        let v = match signature[64] {
+            v @ 0..=1 => v,
+            v @ 27..=28 => v - 27,
+            v => return MmError::err(SignatureError::InvalidSignature(format!("Invalid recovery id {}", v))),
+        };
+        let signature = Signature::from_rsv(
+            &H256::from_slice(&signature[..32]),
+            &H256::from_slice(&signature[32..64]),
+            v,
+        );
+        let public = match recover(&signature, &personal_message_hash(message)) {
+            Ok(public) => public,
+            Err(_) => return Ok(false),
+        };
+        Ok(public_to_address(&public) == address)
+    }
+}
+
+/// Returns the hash of the message prefixed according to EIP-191:
+/// `keccak256("\x19Ethereum Signed Message:\n" || len(message) || message)`.
+fn personal_message_hash(message: &str) -> H256 {
+    let mut hasher = Keccak256::new();
+    hasher.input(format!("\x19Ethereum Signed Message:\n{}", message.len()).as_bytes());
+    hasher.input(message.as_bytes());
+    H256::from_slice(hasher.result().as_slice())
 }
 
 async fn withdraw_impl(ctx: MmArc, coin: EthCoin, req: WithdrawRequest) -> WithdrawResult {
+    req.ensure_no_utxo_specific_params()?;
     let to_addr = coin
         .address_from_str(&req.to)
         .map_to_mm(WithdrawError::InvalidAddress)?;
@@ -597,7 +690,11 @@ async fn withdraw_impl(ctx: MmArc, coin: EthCoin, req: WithdrawRequest) -> Withd
         gas_price,
     };
 
-    let signed = tx.sign(coin.key_pair.secret(), coin.chain_id);
+    coin.key_pair.signer()?;
+    let signed = coin
+        .key_pair
+        .sign_tx(tx, coin.chain_id)
+        .map_to_mm(WithdrawError::InternalError)?;
     let bytes = rlp::encode(&signed);
     let amount_decimal = u256_to_big_decimal(wei_amount, coin.decimals)?;
     let mut spent_by_me = amount_decimal.clone();
@@ -626,6 +723,7 @@ async fn withdraw_impl(ctx: MmArc, coin: EthCoin, req: WithdrawRequest) -> Withd
         internal_id: vec![].into(),
         timestamp: now_ms() / 1000,
         kmd_rewards: None,
+        psbt: None,
     })
 }
 
@@ -638,11 +736,13 @@ impl Deref for EthCoin {
 
 impl SwapOps for EthCoin {
     fn send_taker_fee(&self, fee_addr: &[u8], amount: BigDecimal) -> TransactionFut {
-        let address = try_fus!(addr_from_raw_pubkey(fee_addr));
+        try_f!(self.key_pair.signer().map_err(TransactionErr::from));
+        let address = try_tx_fus!(addr_from_raw_pubkey(fee_addr));
 
         Box::new(
-            self.send_to_address(address, try_fus!(wei_from_big_decimal(&amount, self.decimals)))
-                .map(TransactionEnum::from),
+            self.send_to_address(address, try_tx_fus!(wei_from_big_decimal(&amount, self.decimals)))
+                .map(TransactionEnum::from)
+                .map_err(TransactionErr::Plain),
         )
     }
 
@@ -653,20 +753,23 @@ impl SwapOps for EthCoin {
         secret_hash: &[u8],
         amount: BigDecimal,
         swap_contract_address: &Option<BytesJson>,
+        _other_protocol_info: &Option<Vec<u8>>,
     ) -> TransactionFut {
-        let taker_addr = try_fus!(addr_from_raw_pubkey(taker_pub));
-        let swap_contract_address = try_fus!(swap_contract_address.try_to_address());
+        try_f!(self.key_pair.signer().map_err(TransactionErr::from));
+        let taker_addr = try_tx_fus!(addr_from_raw_pubkey(taker_pub));
+        let swap_contract_address = try_tx_fus!(swap_contract_address.try_to_address());
 
         Box::new(
             self.send_hash_time_locked_payment(
                 self.etomic_swap_id(time_lock, secret_hash),
-                try_fus!(wei_from_big_decimal(&amount, self.decimals)),
+                try_tx_fus!(wei_from_big_decimal(&amount, self.decimals)),
                 time_lock,
                 secret_hash,
                 taker_addr,
                 swap_contract_address,
             )
-            .map(TransactionEnum::from),
+            .map(TransactionEnum::from)
+            .map_err(TransactionErr::Plain),
         )
     }
 
@@ -677,20 +780,23 @@ impl SwapOps for EthCoin {
         secret_hash: &[u8],
         amount: BigDecimal,
         swap_contract_address: &Option<BytesJson>,
+        _other_protocol_info: &Option<Vec<u8>>,
     ) -> TransactionFut {
-        let maker_addr = try_fus!(addr_from_raw_pubkey(maker_pub));
-        let swap_contract_address = try_fus!(swap_contract_address.try_to_address());
+        try_f!(self.key_pair.signer().map_err(TransactionErr::from));
+        let maker_addr = try_tx_fus!(addr_from_raw_pubkey(maker_pub));
+        let swap_contract_address = try_tx_fus!(swap_contract_address.try_to_address());
 
         Box::new(
             self.send_hash_time_locked_payment(
                 self.etomic_swap_id(time_lock, secret_hash),
-                try_fus!(wei_from_big_decimal(&amount, self.decimals)),
+                try_tx_fus!(wei_from_big_decimal(&amount, self.decimals)),
                 time_lock,
                 secret_hash,
                 maker_addr,
                 swap_contract_address,
             )
-            .map(TransactionEnum::from),
+            .map(TransactionEnum::from)
+            .map_err(TransactionErr::Plain),
         )
     }
 
@@ -702,13 +808,15 @@ impl SwapOps for EthCoin {
         secret: &[u8],
         swap_contract_address: &Option<BytesJson>,
     ) -> TransactionFut {
-        let tx: UnverifiedTransaction = try_fus!(rlp::decode(taker_payment_tx));
-        let signed = try_fus!(SignedEthTx::new(tx));
-        let swap_contract_address = try_fus!(swap_contract_address.try_to_address());
+        try_f!(self.key_pair.signer().map_err(TransactionErr::from));
+        let tx: UnverifiedTransaction = try_tx_fus!(rlp::decode(taker_payment_tx));
+        let signed = try_tx_fus!(SignedEthTx::new(tx));
+        let swap_contract_address = try_tx_fus!(swap_contract_address.try_to_address());
 
         Box::new(
             self.spend_hash_time_locked_payment(signed, swap_contract_address, secret)
-                .map(TransactionEnum::from),
+                .map(TransactionEnum::from)
+                .map_err(TransactionErr::Plain),
         )
     }
 
@@ -720,12 +828,14 @@ impl SwapOps for EthCoin {
         secret: &[u8],
         swap_contract_address: &Option<BytesJson>,
     ) -> TransactionFut {
-        let tx: UnverifiedTransaction = try_fus!(rlp::decode(maker_payment_tx));
-        let signed = try_fus!(SignedEthTx::new(tx));
-        let swap_contract_address = try_fus!(swap_contract_address.try_to_address());
+        try_f!(self.key_pair.signer().map_err(TransactionErr::from));
+        let tx: UnverifiedTransaction = try_tx_fus!(rlp::decode(maker_payment_tx));
+        let signed = try_tx_fus!(SignedEthTx::new(tx));
+        let swap_contract_address = try_tx_fus!(swap_contract_address.try_to_address());
         Box::new(
             self.spend_hash_time_locked_payment(signed, swap_contract_address, secret)
-                .map(TransactionEnum::from),
+                .map(TransactionEnum::from)
+                .map_err(TransactionErr::Plain),
         )
     }
 
@@ -737,13 +847,15 @@ impl SwapOps for EthCoin {
         _secret_hash: &[u8],
         swap_contract_address: &Option<BytesJson>,
     ) -> TransactionFut {
-        let tx: UnverifiedTransaction = try_fus!(rlp::decode(taker_payment_tx));
-        let signed = try_fus!(SignedEthTx::new(tx));
-        let swap_contract_address = try_fus!(swap_contract_address.try_to_address());
+        try_f!(self.key_pair.signer().map_err(TransactionErr::from));
+        let tx: UnverifiedTransaction = try_tx_fus!(rlp::decode(taker_payment_tx));
+        let signed = try_tx_fus!(SignedEthTx::new(tx));
+        let swap_contract_address = try_tx_fus!(swap_contract_address.try_to_address());
 
         Box::new(
             self.refund_hash_time_locked_payment(swap_contract_address, signed)
-                .map(TransactionEnum::from),
+                .map(TransactionEnum::from)
+                .map_err(TransactionErr::Plain),
         )
     }
 
@@ -755,13 +867,15 @@ impl SwapOps for EthCoin {
         _secret_hash: &[u8],
         swap_contract_address: &Option<BytesJson>,
     ) -> TransactionFut {
-        let tx: UnverifiedTransaction = try_fus!(rlp::decode(maker_payment_tx));
-        let signed = try_fus!(SignedEthTx::new(tx));
-        let swap_contract_address = try_fus!(swap_contract_address.try_to_address());
+        try_f!(self.key_pair.signer().map_err(TransactionErr::from));
+        let tx: UnverifiedTransaction = try_tx_fus!(rlp::decode(maker_payment_tx));
+        let signed = try_tx_fus!(SignedEthTx::new(tx));
+        let swap_contract_address = try_tx_fus!(swap_contract_address.try_to_address());
 
         Box::new(
             self.refund_hash_time_locked_payment(swap_contract_address, signed)
-                .map(TransactionEnum::from),
+                .map(TransactionEnum::from)
+                .map_err(TransactionErr::Plain),
         )
     }
 
@@ -1165,17 +1279,17 @@ impl MarketCoinOps for EthCoin {
         from_block: u64,
         swap_contract_address: &Option<BytesJson>,
     ) -> TransactionFut {
-        let unverified: UnverifiedTransaction = try_fus!(rlp::decode(tx_bytes));
-        let tx = try_fus!(SignedEthTx::new(unverified));
-        let swap_contract_address = try_fus!(swap_contract_address.try_to_address());
+        let unverified: UnverifiedTransaction = try_tx_fus!(rlp::decode(tx_bytes));
+        let tx = try_tx_fus!(SignedEthTx::new(unverified));
+        let swap_contract_address = try_tx_fus!(swap_contract_address.try_to_address());
 
         let func_name = match self.coin_type {
             EthCoinType::Eth => "ethPayment",
             EthCoinType::Erc20 { .. } => "erc20Payment",
         };
 
-        let payment_func = try_fus!(SWAP_CONTRACT.function(func_name));
-        let decoded = try_fus!(payment_func.decode_input(&tx.data));
+        let payment_func = try_tx_fus!(SWAP_CONTRACT.function(func_name));
+        let decoded = try_tx_fus!(payment_func.decode_input(&tx.data));
         let id = match &decoded[0] {
             Token::FixedBytes(bytes) => bytes.clone(),
             _ => panic!(),
@@ -1245,7 +1359,7 @@ impl MarketCoinOps for EthCoin {
                 continue;
             }
         };
-        Box::new(fut.boxed().compat())
+        Box::new(fut.boxed().compat().map_err(TransactionErr::Plain))
     }
 
     fn tx_enum_from_bytes(&self, bytes: &[u8]) -> Result<TransactionEnum, String> {
@@ -1262,7 +1376,13 @@ impl MarketCoinOps for EthCoin {
         )
     }
 
-    fn display_priv_key(&self) -> String { format!("{:#02x}", self.key_pair.secret()) }
+    fn display_priv_key(&self) -> String {
+        match &self.key_pair {
+            EthKeyPair::KeyPair(key_pair) => format!("{:#02x}", key_pair.secret()),
+            // `show_priv_key` is rejected if the private key is not kept by MM2
+            EthKeyPair::External(_) | EthKeyPair::WatchOnly => String::new(),
+        }
+    }
 
     fn min_tx_amount(&self) -> BigDecimal { BigDecimal::from(0) }
 
@@ -1329,7 +1449,7 @@ async fn sign_and_send_transaction_impl(
         value,
         data,
     };
-    let signed = tx.sign(coin.key_pair.secret(), coin.chain_id);
+    let signed = try_s!(coin.key_pair.sign_tx(tx, coin.chain_id));
     let bytes = web3::types::Bytes(rlp::encode(&signed).to_vec());
     status.status(tags!(), "send_raw_transaction…");
     try_s!(
@@ -1696,6 +1816,7 @@ impl EthCoin {
                     internal_id,
                     timestamp: block.timestamp.into(),
                     kmd_rewards: None,
+                    psbt: None,
                 };
 
                 existing_history.push(details);
@@ -2070,6 +2191,7 @@ impl EthCoin {
                     internal_id: BytesJson(internal_id.to_vec()),
                     timestamp: block.timestamp.into(),
                     kmd_rewards: None,
+                    psbt: None,
                 };
 
                 existing_history.push(details);
@@ -3095,6 +3217,20 @@ pub fn addr_from_raw_pubkey(pubkey: &[u8]) -> Result<Address, String> {
     Ok(public_to_address(&eth_public))
 }
 
+/// Returns the address the coin is watched at in the watch-only mode.
+/// ERC20 tokens are watched at the address of their platform coin unless the token has its own `watch_only` config.
+fn watch_only_address(ctx: &MmArc, ticker: &str, protocol: &CoinProtocol) -> Result<Address, String> {
+    let conf_ticker = match protocol {
+        CoinProtocol::ERC20 { platform, .. } if ctx.conf["watch_only"]["coins"][ticker].is_null() => platform.as_str(),
+        _ => ticker,
+    };
+    match try_s!(watch_only_coin_key(ctx, conf_ticker)) {
+        WatchOnlyCoinKey::Address(address) => Ok(try_s!(valid_addr_from_str(&address))),
+        WatchOnlyCoinKey::Pubkey(public) => addr_from_raw_pubkey(&*public),
+        WatchOnlyCoinKey::Xpub(_) => ERR!("{} can't be watched with 'xpub', set its 'address' or 'pubkey'", ticker),
+    }
+}
+
 pub fn addr_from_pubkey_str(pubkey: &str) -> Result<String, String> {
     let pubkey_bytes = try_s!(hex::decode(pubkey));
     let addr = try_s!(addr_from_raw_pubkey(&pubkey_bytes));
@@ -3283,15 +3419,27 @@ pub async fn eth_coin_from_conf_and_request(
         }
     }
 
-    let key_pair: KeyPair = try_s!(KeyPair::from_secret_slice(priv_key));
-    let my_address = key_pair.address();
+    let (key_pair, my_address) = if ctx.is_watch_only() {
+        let my_address = try_s!(watch_only_address(ctx, ticker, &protocol));
+        (EthKeyPair::WatchOnly, my_address)
+    } else if ctx.uses_external_signer() {
+        let signer = try_s!(ExternalSigner::new(ctx, Some(ticker)));
+        let my_address = try_s!(addr_from_raw_pubkey(signer.public_key()));
+        (EthKeyPair::External(signer), my_address)
+    } else {
+        let key_pair: KeyPair = try_s!(KeyPair::from_secret_slice(priv_key));
+        let my_address = key_pair.address();
+        (key_pair.into(), my_address)
+    };
 
     let mut web3_instances = vec![];
     let event_handlers = rpc_event_handlers_for_eth_transport(ctx, ticker.to_string());
+    let proxy = try_s!(coin_proxy(conf, &ctx.conf));
     for url in urls.iter() {
         let transport = try_s!(Web3Transport::with_event_handlers(
             vec![url.clone()],
-            event_handlers.clone()
+            event_handlers.clone(),
+            proxy.clone()
         ));
         let web3 = Web3::new(transport);
         let version = match web3.web3().client_version().compat().await {
@@ -3311,7 +3459,7 @@ pub async fn eth_coin_from_conf_and_request(
         return ERR!("Failed to get client version for all urls");
     }
 
-    let transport = try_s!(Web3Transport::with_event_handlers(urls, event_handlers));
+    let transport = try_s!(Web3Transport::with_event_handlers(urls, event_handlers, proxy));
     let web3 = Web3::new(transport);
 
     let (coin_type, decimals) = match protocol {
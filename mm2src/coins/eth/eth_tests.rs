@@ -1,4 +1,5 @@
 use super::*;
+use crate::WithdrawOutput;
 use common::block_on;
 use common::mm_ctx::{MmArc, MmCtxBuilder};
 use mocktopus::mocking::*;
@@ -48,7 +49,7 @@ fn eth_coin_for_test(
         history_sync_state: Mutex::new(HistorySyncState::NotEnabled),
         gas_station_policy: GasStationPricePolicy::MeanAverageFast,
         my_address: key_pair.address(),
-        key_pair,
+        key_pair: key_pair.into(),
         swap_contract_address: Address::from("0x7Bc1bBDD6A0a722fC9bffC49c921B685ECB84b94"),
         fallback_swap_contract,
         ticker,
@@ -210,7 +211,7 @@ fn send_and_refund_erc20_payment() {
             token_addr: Address::from("0xc0eb7AeD740E1796992A08962c15661bDEB58003"),
         },
         my_address: key_pair.address(),
-        key_pair,
+        key_pair: key_pair.into(),
         swap_contract_address: Address::from("0x7Bc1bBDD6A0a722fC9bffC49c921B685ECB84b94"),
         fallback_swap_contract: None,
         web3_instances: vec![Web3Instance {
@@ -236,6 +237,7 @@ fn send_and_refund_erc20_payment() {
             &[1; 20],
             "0.001".parse().unwrap(),
             &coin.swap_contract_address(),
+            &None,
         )
         .wait()
         .unwrap();
@@ -273,7 +275,7 @@ fn send_and_refund_eth_payment() {
         ticker: "ETH".into(),
         coin_type: EthCoinType::Eth,
         my_address: key_pair.address(),
-        key_pair,
+        key_pair: key_pair.into(),
         swap_contract_address: Address::from("0x7Bc1bBDD6A0a722fC9bffC49c921B685ECB84b94"),
         fallback_swap_contract: None,
         web3_instances: vec![Web3Instance {
@@ -299,6 +301,7 @@ fn send_and_refund_eth_payment() {
             &[1; 20],
             "0.001".parse().unwrap(),
             &coin.swap_contract_address(),
+            &None,
         )
         .wait()
         .unwrap();
@@ -345,7 +348,7 @@ fn test_nonce_several_urls() {
         ticker: "ETH".into(),
         coin_type: EthCoinType::Eth,
         my_address: key_pair.address(),
-        key_pair,
+        key_pair: key_pair.into(),
         swap_contract_address: Address::from("0x7Bc1bBDD6A0a722fC9bffC49c921B685ECB84b94"),
         fallback_swap_contract: None,
         web3_instances: vec![
@@ -406,7 +409,7 @@ fn test_wait_for_payment_spend_timeout() {
         gas_station_policy: GasStationPricePolicy::MeanAverageFast,
         history_sync_state: Mutex::new(HistorySyncState::NotEnabled),
         my_address: key_pair.address(),
-        key_pair,
+        key_pair: key_pair.into(),
         swap_contract_address: Address::from("0x7Bc1bBDD6A0a722fC9bffC49c921B685ECB84b94"),
         fallback_swap_contract: None,
         ticker: "ETH".into(),
@@ -466,7 +469,7 @@ fn test_search_for_swap_tx_spend_was_spent() {
         gas_station_policy: GasStationPricePolicy::MeanAverageFast,
         history_sync_state: Mutex::new(HistorySyncState::NotEnabled),
         my_address: key_pair.address(),
-        key_pair,
+        key_pair: key_pair.into(),
         swap_contract_address,
         fallback_swap_contract: None,
         ticker: "ETH".into(),
@@ -574,7 +577,7 @@ fn test_search_for_swap_tx_spend_was_refunded() {
         gas_station_policy: GasStationPricePolicy::MeanAverageFast,
         history_sync_state: Mutex::new(HistorySyncState::NotEnabled),
         my_address: key_pair.address(),
-        key_pair,
+        key_pair: key_pair.into(),
         swap_contract_address,
         fallback_swap_contract: None,
         ticker: "ETH".into(),
@@ -644,6 +647,11 @@ fn test_withdraw_impl_manual_fee() {
             gas: 150000,
             gas_price: 1.into(),
         }),
+        outputs: Vec::new(),
+        memo: None,
+        inputs: Vec::new(),
+        coin_selection: None,
+        psbt: false,
     };
     coin.my_balance().wait().unwrap();
 
@@ -660,6 +668,97 @@ fn test_withdraw_impl_manual_fee() {
     assert_eq!(expected, tx_details.fee_details);
 }
 
+#[test]
+fn test_withdraw_impl_batch_not_supported() {
+    let (ctx, coin) = eth_coin_for_test(EthCoinType::Eth, vec!["http://dummy.dummy".into()], None);
+
+    let withdraw_req = WithdrawRequest {
+        amount: 0.into(),
+        to: String::new(),
+        coin: "ETH".to_string(),
+        max: false,
+        fee: None,
+        outputs: vec![
+            WithdrawOutput {
+                to: "0x7Bc1bBDD6A0a722fC9bffC49c921B685ECB84b94".to_string(),
+                amount: 1.into(),
+                max: false,
+            },
+            WithdrawOutput {
+                to: "0x2b294F029Fde858b2c62184e8390591755521d8E".to_string(),
+                amount: 1.into(),
+                max: false,
+            },
+        ],
+        memo: None,
+        inputs: Vec::new(),
+        coin_selection: None,
+        psbt: false,
+    };
+
+    match block_on(withdraw_impl(ctx, coin, withdraw_req))
+        .unwrap_err()
+        .into_inner()
+    {
+        WithdrawError::BatchWithdrawNotSupported { coin } => assert_eq!(coin, "ETH"),
+        e => panic!("Expected WithdrawError::BatchWithdrawNotSupported, found {:?}", e),
+    }
+}
+
+#[test]
+fn test_watch_only_eth_fails_to_sign() {
+    let address = "0x7Bc1bBDD6A0a722fC9bffC49c921B685ECB84b94";
+    let conf = json!({
+        "watch_only": {
+            "coins": {
+                "ETH": {"address": address}
+            }
+        }
+    });
+    let ctx = MmCtxBuilder::new().with_conf(conf).into_mm_arc();
+    let jst_protocol = CoinProtocol::ERC20 {
+        platform: "ETH".into(),
+        contract_address: "0x2b294F029Fde858b2c62184e8390591755521d8E".into(),
+    };
+    let expected_address = Address::from(address);
+    assert_eq!(
+        watch_only_address(&ctx, "ETH", &CoinProtocol::ETH).unwrap(),
+        expected_address
+    );
+    // JST has no `watch_only` config, so it's watched at the address of its platform coin
+    assert_eq!(
+        watch_only_address(&ctx, "JST", &jst_protocol).unwrap(),
+        expected_address
+    );
+
+    let (_ctx, coin) = eth_coin_for_test(EthCoinType::Eth, vec!["http://dummy.dummy".into()], None);
+    let coin = EthCoin(Arc::new(EthCoinImpl {
+        key_pair: EthKeyPair::WatchOnly,
+        my_address: expected_address,
+        coin_type: EthCoinType::Eth,
+        ticker: coin.ticker.clone(),
+        gas_station_url: None,
+        gas_station_decimals: ETH_GAS_STATION_DECIMALS,
+        gas_station_policy: GasStationPricePolicy::MeanAverageFast,
+        swap_contract_address: coin.swap_contract_address,
+        fallback_swap_contract: None,
+        web3: coin.web3.clone(),
+        web3_instances: coin.web3_instances.clone(),
+        decimals: 18,
+        history_sync_state: Mutex::new(HistorySyncState::NotEnabled),
+        required_confirmations: 1.into(),
+        ctx: ctx.weak(),
+        chain_id: None,
+        logs_block_range: DEFAULT_LOGS_BLOCK_RANGE,
+    }));
+
+    let error = coin
+        .send_taker_fee(&DEX_FEE_ADDR_RAW_PUBKEY, 1.into())
+        .wait()
+        .unwrap_err();
+    assert_eq!(error, TransactionErr::WatchOnly(WatchOnlyError));
+}
+
 #[test]
 fn test_withdraw_impl_fee_details() {
     let (ctx, coin) = eth_coin_for_test(
@@ -686,6 +785,11 @@ fn test_withdraw_impl_fee_details() {
             gas: 150000,
             gas_price: 1.into(),
         }),
+        outputs: Vec::new(),
+        memo: None,
+        inputs: Vec::new(),
+        coin_selection: None,
+        psbt: false,
     };
     coin.my_balance().wait().unwrap();
 
@@ -1215,3 +1319,26 @@ fn polygon_check_if_my_payment_sent() {
     let expected_hash = BytesJson::from("69a20008cea0c15ee483b5bbdff942752634aa072dfd2ff715fe87eec302de11");
     assert_eq!(expected_hash, my_payment.tx_hash());
 }
+
+#[test]
+fn test_sign_verify_message() {
+    let (_ctx, coin) = eth_coin_for_test(EthCoinType::Eth, vec!["http://dummy.dummy".into()], None);
+    let my_address = checksum_address(&format!("{:#02x}", coin.my_address));
+    let message = "test";
+
+    let signature = coin.sign_message(message).unwrap();
+    let signature_bytes = hex::decode(&signature[2..]).unwrap();
+    assert_eq!(signature_bytes.len(), 65);
+    assert!(signature_bytes[64] == 27 || signature_bytes[64] == 28);
+    assert!(coin.verify_message(message, &signature, &my_address).unwrap());
+    assert!(!coin.verify_message("another message", &signature, &my_address).unwrap());
+
+    // the recovery id 0/1 is accepted too
+    let mut raw_v_signature = signature_bytes;
+    raw_v_signature[64] -= 27;
+    let raw_v_signature = format!("0x{}", hex::encode(&raw_v_signature));
+    assert!(coin.verify_message(message, &raw_v_signature, &my_address).unwrap());
+
+    let other_address = "0x7Bc1bBDD6A0a722fC9bffC49c921B685ECB84b94";
+    assert!(!coin.verify_message(message, &signature, other_address).unwrap());
+}
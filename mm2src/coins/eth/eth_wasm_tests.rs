@@ -26,7 +26,7 @@ async fn test_send() {
         ticker: "ETH".into(),
         coin_type: EthCoinType::Eth,
         my_address: key_pair.address(),
-        key_pair,
+        key_pair: key_pair.into(),
         swap_contract_address: Address::from("0x7Bc1bBDD6A0a722fC9bffC49c921B685ECB84b94"),
         fallback_swap_contract: None,
         web3_instances: vec![Web3Instance {
@@ -51,6 +51,7 @@ async fn test_send() {
             &[1; 20],
             "0.001".parse().unwrap(),
             &None,
+            &None,
         )
         .compat()
         .await;
@@ -1,4 +1,7 @@
 use super::{RpcTransportEventHandler, RpcTransportEventHandlerShared};
+use common::proxy::Socks5Proxy;
+#[cfg(not(target_arch = "wasm32"))]
+use common::proxy::{proxy_http_client, ProxyHttpClient};
 #[cfg(not(target_arch = "wasm32"))] use futures::FutureExt;
 use futures::TryFutureExt;
 use futures01::{Future, Poll};
@@ -72,6 +75,9 @@ pub struct Web3Transport {
     id: Arc<AtomicUsize>,
     uris: Vec<http::Uri>,
     event_handlers: Vec<RpcTransportEventHandlerShared>,
+    /// The client sending the requests through the proxy if it's configured.
+    #[cfg(not(target_arch = "wasm32"))]
+    proxy_client: Option<ProxyHttpClient>,
 }
 
 impl Web3Transport {
@@ -85,21 +91,32 @@ impl Web3Transport {
             id: Arc::new(AtomicUsize::new(0)),
             uris,
             event_handlers: Default::default(),
+            #[cfg(not(target_arch = "wasm32"))]
+            proxy_client: None,
         })
     }
 
     pub fn with_event_handlers(
         urls: Vec<String>,
         event_handlers: Vec<RpcTransportEventHandlerShared>,
+        proxy: Option<Socks5Proxy>,
     ) -> Result<Self, String> {
         let mut uris = vec![];
         for url in urls.iter() {
             uris.push(try_s!(url.parse()));
         }
+        #[cfg(target_arch = "wasm32")]
+        {
+            if proxy.is_some() {
+                return ERR!("The proxy can't be used in a browser");
+            }
+        }
         Ok(Web3Transport {
             id: Arc::new(AtomicUsize::new(0)),
             uris,
             event_handlers,
+            #[cfg(not(target_arch = "wasm32"))]
+            proxy_client: proxy.map(proxy_http_client),
         })
     }
 }
@@ -130,9 +147,14 @@ impl Transport for Web3Transport {
     #[cfg(not(target_arch = "wasm32"))]
     fn send(&self, _id: RequestId, request: Call) -> Self::Out {
         Box::new(
-            send_request(request, self.uris.clone(), self.event_handlers.clone())
-                .boxed()
-                .compat(),
+            send_request(
+                request,
+                self.uris.clone(),
+                self.event_handlers.clone(),
+                self.proxy_client.clone(),
+            )
+            .boxed()
+            .compat(),
         )
     }
 
@@ -148,9 +170,10 @@ async fn send_request(
     request: Call,
     uris: Vec<http::Uri>,
     event_handlers: Vec<RpcTransportEventHandlerShared>,
+    proxy_client: Option<ProxyHttpClient>,
 ) -> Result<Json, Error> {
     use common::executor::Timer;
-    use common::wio::slurp_reqʹ;
+    use common::wio::{slurp_req_with_client, slurp_reqʹ};
     use futures::future::{select, Either};
     use gstuff::binprint;
     use http::header::HeaderValue;
@@ -166,7 +189,10 @@ async fn send_request(
         req.headers_mut()
             .insert(http::header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
         let timeout = Timer::sleep(60.);
-        let req = Box::pin(slurp_reqʹ(req));
+        let req = match proxy_client {
+            Some(ref client) => slurp_req_with_client(client, req).boxed(),
+            None => slurp_reqʹ(req).boxed(),
+        };
         let rc = select(req, timeout).await;
         let res = match rc {
             Either::Left((r, _t)) => r,
@@ -0,0 +1,304 @@
+//! BIP44 derivation of the coin keys when MM2 runs in the HD mode (`"enable_hd": true`).
+//!
+//! The coin key is derived along `m/purpose'/coin_type'/account'/change/address_index`,
+//! where `m/purpose'/coin_type'` is taken from the coin config `derivation_path` field
+//! and the rest is taken from the optional `path_to_address` field of the enable request.
+//!
+//! UTXO coins are activated with the whole BIP44 account, see [`crate::utxo::utxo_hd_wallet`].
+
+use crate::utxo::utxo_hd_wallet::{UtxoHDAccount, UtxoHDAddress};
+use crate::utxo::{utxo_common, UtxoCoinFields};
+use crate::{lp_coinfind_or_err, CoinFindError, MmCoinEnum};
+use bigdecimal::BigDecimal;
+use common::mm_ctx::MmArc;
+use common::mm_error::prelude::*;
+use common::privkey::key_pair_from_hd_master_key;
+use common::HttpStatusCode;
+use derive_more::Display;
+use futures::compat::Future01CompatExt;
+use http::StatusCode;
+use keys::bip32::{ChildNumber, DerivationPath};
+use keys::Secret;
+use serde_json::{self as json, Value as Json};
+
+/// The BIP44 chain of addresses.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum Bip44Chain {
+    /// The chain of the receiving addresses.
+    External = 0,
+    /// The chain of the change addresses.
+    Internal = 1,
+}
+
+impl Default for Bip44Chain {
+    fn default() -> Self { Bip44Chain::External }
+}
+
+impl Bip44Chain {
+    pub fn child_number(self) -> ChildNumber { ChildNumber::from(self as u32) }
+}
+
+/// The path to the address relative to the coin `derivation_path`.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct HDPathToAddress {
+    /// The hardened account index.
+    pub account: u32,
+    /// Whether the address belongs to the internal (change) chain.
+    pub is_change: bool,
+    pub address_index: u32,
+}
+
+/// Parses the `m/purpose'/coin_type'` path from the coin config.
+pub fn derivation_path_from_conf(ticker: &str, conf: &Json) -> Result<DerivationPath, String> {
+    let path = match conf["derivation_path"].as_str() {
+        Some(path) => path,
+        None => {
+            return ERR!(
+                "'derivation_path' is not set in the {} coin config, but it's required in the HD mode",
+                ticker
+            )
+        },
+    };
+    let path: DerivationPath = match path.parse() {
+        Ok(path) => path,
+        Err(e) => return ERR!("Invalid 'derivation_path' {} of the {} coin: {}", path, ticker, e),
+    };
+    let is_valid = path.len() == 2 && path.as_slice().iter().all(ChildNumber::is_hardened);
+    if !is_valid {
+        return ERR!(
+            "Invalid 'derivation_path' {} of the {} coin, expected m/purpose'/coin_type'",
+            path,
+            ticker
+        );
+    }
+    Ok(path)
+}
+
+/// Returns the full BIP44 path to the address: `m/purpose'/coin_type'/account'/change/address_index`.
+pub fn full_derivation_path(
+    coin_path: &DerivationPath,
+    path_to_address: &HDPathToAddress,
+) -> Result<DerivationPath, String> {
+    let account = try_s!(ChildNumber::hardened(path_to_address.account));
+    let change = try_s!(ChildNumber::normal(path_to_address.is_change as u32));
+    let address_index = try_s!(ChildNumber::normal(path_to_address.address_index));
+    Ok(coin_path.extend(vec![account, change, address_index]))
+}
+
+/// Parses the optional `path_to_address` field of the enable request.
+pub fn path_to_address_from_req(req: &Json) -> Result<HDPathToAddress, String> {
+    if req["path_to_address"].is_null() {
+        return Ok(HDPathToAddress::default());
+    }
+    Ok(try_s!(json::from_value(req["path_to_address"].clone())))
+}
+
+/// Returns the secret the coin should be activated with.
+/// This is the node key in the legacy mode or the BIP44 derived key in the HD mode.
+pub fn coin_secret(ctx: &MmArc, ticker: &str, conf: &Json, req: &Json) -> Result<Secret, String> {
+    let master_key = match ctx.hd_master_key() {
+        Some(master_key) => master_key,
+        None => return Ok(ctx.secp256k1_key_pair().private().secret.clone()),
+    };
+
+    let coin_path = try_s!(derivation_path_from_conf(ticker, conf));
+    let path_to_address = try_s!(path_to_address_from_req(req));
+    let path = try_s!(full_derivation_path(&coin_path, &path_to_address));
+    let key_pair = try_s!(key_pair_from_hd_master_key(master_key, &path));
+    Ok(key_pair.private().secret.clone())
+}
+
+#[derive(Debug, Display, Serialize, SerializeErrorType)]
+#[serde(tag = "error_type", content = "error_data")]
+pub enum HDWalletRpcError {
+    #[display(fmt = "No such coin {}", coin)]
+    NoSuchCoin { coin: String },
+    #[display(fmt = "{} is not activated with an HD account", coin)]
+    CoinIsNotActivatedWithHDAccount { coin: String },
+    #[display(
+        fmt = "Cannot generate a new address: the last {} addresses of the chain have no transactions",
+        gap_limit
+    )]
+    GapLimitReached { gap_limit: u32 },
+    #[display(fmt = "Transport error: {}", _0)]
+    Transport(String),
+    #[display(fmt = "Internal error: {}", _0)]
+    Internal(String),
+}
+
+impl HttpStatusCode for HDWalletRpcError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            HDWalletRpcError::NoSuchCoin { .. }
+            | HDWalletRpcError::CoinIsNotActivatedWithHDAccount { .. }
+            | HDWalletRpcError::GapLimitReached { .. } => StatusCode::BAD_REQUEST,
+            HDWalletRpcError::Transport(_) | HDWalletRpcError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl From<CoinFindError> for HDWalletRpcError {
+    fn from(e: CoinFindError) -> Self {
+        match e {
+            CoinFindError::NoSuchCoin { coin } => HDWalletRpcError::NoSuchCoin { coin },
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct GetNewAddressRequest {
+    coin: String,
+    #[serde(default)]
+    chain: Bip44Chain,
+}
+
+#[derive(Serialize)]
+pub struct GetNewAddressResponse {
+    new_address: HDAddressInfo,
+}
+
+#[derive(Deserialize)]
+pub struct ListAddressesRequest {
+    coin: String,
+}
+
+#[derive(Serialize)]
+pub struct ListAddressesResponse {
+    account_id: u32,
+    derivation_path: String,
+    gap_limit: u32,
+    addresses: Vec<HDAddressInfo>,
+}
+
+#[derive(Serialize)]
+pub struct HDAddressInfo {
+    address: String,
+    derivation_path: String,
+    chain: Bip44Chain,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    balance: Option<BigDecimal>,
+}
+
+impl HDAddressInfo {
+    fn new(hd_address: &UtxoHDAddress, balance: Option<BigDecimal>) -> Result<HDAddressInfo, String> {
+        Ok(HDAddressInfo {
+            address: try_s!(hd_address.address.display_address()),
+            derivation_path: hd_address.derivation_path.to_string(),
+            chain: hd_address.chain,
+            balance,
+        })
+    }
+}
+
+/// Generates a new address of the given chain of the coin HD account.
+/// Fails if the last `gap_limit` addresses of the chain have no transactions,
+/// otherwise the new address wouldn't be found by the gap limit discovery.
+pub async fn get_new_address(
+    ctx: MmArc,
+    req: GetNewAddressRequest,
+) -> Result<GetNewAddressResponse, MmError<HDWalletRpcError>> {
+    let coin = lp_coinfind_or_err(&ctx, &req.coin).await?;
+    let (utxo, hd_account) = utxo_hd_account(&coin, &req.coin)?;
+
+    let unused_number = hd_account
+        .trailing_unused_addresses_number(&utxo.rpc_client, req.chain)
+        .await
+        .map_to_mm(HDWalletRpcError::Transport)?;
+    if unused_number >= hd_account.gap_limit {
+        return MmError::err(HDWalletRpcError::GapLimitReached {
+            gap_limit: hd_account.gap_limit,
+        });
+    }
+
+    let hd_address = hd_account
+        .new_address(&utxo.conf, req.chain)
+        .map_to_mm(HDWalletRpcError::Internal)?;
+    hd_account
+        .import_fresh_address(&utxo.rpc_client, &hd_address)
+        .await
+        .map_to_mm(HDWalletRpcError::Transport)?;
+    utxo_common::subscribe_balance_updates(utxo, std::slice::from_ref(&hd_address.address));
+    let new_address = HDAddressInfo::new(&hd_address, None).map_to_mm(HDWalletRpcError::Internal)?;
+    Ok(GetNewAddressResponse { new_address })
+}
+
+/// Returns the known addresses of the coin HD account with their balances.
+pub async fn list_addresses(
+    ctx: MmArc,
+    req: ListAddressesRequest,
+) -> Result<ListAddressesResponse, MmError<HDWalletRpcError>> {
+    let coin = lp_coinfind_or_err(&ctx, &req.coin).await?;
+    let (utxo, hd_account) = utxo_hd_account(&coin, &req.coin)?;
+
+    let mut addresses = Vec::new();
+    for hd_address in hd_account.all_addresses() {
+        let balance = utxo
+            .rpc_client
+            .display_balance(hd_address.address.clone(), utxo.decimals)
+            .compat()
+            .await
+            .map_to_mm(|e| HDWalletRpcError::Transport(e.to_string()))?;
+        let info = HDAddressInfo::new(&hd_address, Some(balance)).map_to_mm(HDWalletRpcError::Internal)?;
+        addresses.push(info);
+    }
+
+    Ok(ListAddressesResponse {
+        account_id: hd_account.account_id,
+        derivation_path: hd_account.derivation_path.to_string(),
+        gap_limit: hd_account.gap_limit,
+        addresses,
+    })
+}
+
+fn utxo_hd_account<'a>(
+    coin: &'a MmCoinEnum,
+    ticker: &str,
+) -> Result<(&'a UtxoCoinFields, &'a UtxoHDAccount), MmError<HDWalletRpcError>> {
+    let utxo: &UtxoCoinFields = match coin {
+        MmCoinEnum::UtxoCoin(utxo) => utxo.as_ref(),
+        MmCoinEnum::QtumCoin(qtum) => qtum.as_ref(),
+        _ => {
+            return MmError::err(HDWalletRpcError::CoinIsNotActivatedWithHDAccount {
+                coin: ticker.to_owned(),
+            })
+        },
+    };
+    let hd_account = utxo
+        .hd_account
+        .as_ref()
+        .or_mm_err(|| HDWalletRpcError::CoinIsNotActivatedWithHDAccount {
+            coin: ticker.to_owned(),
+        })?;
+    Ok((utxo, hd_account))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_full_derivation_path() {
+        let conf = json!({"coin": "KMD", "derivation_path": "m/44'/141'"});
+        let coin_path = derivation_path_from_conf("KMD", &conf).unwrap();
+
+        let path = full_derivation_path(&coin_path, &HDPathToAddress::default()).unwrap();
+        assert_eq!(path.to_string(), "m/44'/141'/0'/0/0");
+
+        let path_to_address = HDPathToAddress {
+            account: 1,
+            is_change: true,
+            address_index: 5,
+        };
+        let path = full_derivation_path(&coin_path, &path_to_address).unwrap();
+        assert_eq!(path.to_string(), "m/44'/141'/1'/1/5");
+    }
+
+    #[test]
+    fn test_derivation_path_from_conf_invalid() {
+        derivation_path_from_conf("KMD", &json!({"coin": "KMD"})).unwrap_err();
+        derivation_path_from_conf("KMD", &json!({"derivation_path": "m/44'/141'/0'"})).unwrap_err();
+        derivation_path_from_conf("KMD", &json!({"derivation_path": "m/44'/141"})).unwrap_err();
+        derivation_path_from_conf("KMD", &json!({"derivation_path": "44'/141'"})).unwrap_err();
+    }
+}
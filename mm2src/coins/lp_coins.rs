@@ -32,6 +32,7 @@
 
 use async_trait::async_trait;
 use bigdecimal::{BigDecimal, ParseBigDecimalError};
+use common::custom_futures::FutureTimerExt;
 use common::executor::{spawn, Timer};
 use common::mm_ctx::{from_ctx, MmArc, MmWeak};
 use common::mm_error::prelude::*;
@@ -39,13 +40,14 @@ use common::mm_metrics::MetricsWeak;
 use common::mm_number::MmNumber;
 use common::{calc_total_pages, now_ms, HttpStatusCode};
 use derive_more::Display;
+use futures::channel::mpsc::UnboundedReceiver;
 use futures::compat::Future01CompatExt;
 use futures::lock::{MappedMutexGuard as AsyncMappedMutexGuard, Mutex as AsyncMutex, MutexGuard as AsyncMutexGuard};
-use futures::{FutureExt, TryFutureExt};
+use futures::{FutureExt, StreamExt, TryFutureExt};
 use futures01::Future;
 use http::{Response, StatusCode};
-use keys::AddressFormat as UtxoAddressFormat;
-use rpc::v1::types::Bytes as BytesJson;
+use keys::{AddressFormat as UtxoAddressFormat, Secret};
+use rpc::v1::types::{Bytes as BytesJson, H256 as H256Json};
 use serde::{Deserialize, Deserializer};
 use serde_json::{self as json, Value as Json};
 use std::collections::hash_map::{HashMap, RawEntryMut};
@@ -65,6 +67,16 @@ macro_rules! try_fus {
     };
 }
 
+/// `try_fus!` of the functions returning [`TransactionFut`].
+macro_rules! try_tx_fus {
+    ($e: expr) => {
+        match $e {
+            Ok(ok) => ok,
+            Err(err) => return Box::new(futures01::future::err(crate::TransactionErr::Plain(ERRL!("{}", err)))),
+        }
+    };
+}
+
 macro_rules! try_f {
     ($e: expr) => {
         match $e {
@@ -82,11 +94,16 @@ pub mod eth;
 use eth::{eth_coin_from_conf_and_request, EthCoin, EthTxFeeDetails, SignedEthTx};
 
 pub mod utxo;
+use utxo::coin_selection::CoinSelectionStrategy;
 use utxo::qtum::{self, qtum_coin_from_conf_and_request, QtumCoin};
+use utxo::slp::{self, slp_token_from_conf_and_request, SlpToken};
 use utxo::utxo_common::big_decimal_from_sat_unsigned;
 use utxo::utxo_standard::{utxo_standard_coin_from_conf_and_request, UtxoStandardCoin};
 use utxo::{GenerateTxError, UtxoFeeDetails, UtxoTx};
 
+pub mod hd_wallet;
+use hd_wallet::coin_secret;
+
 pub mod qrc20;
 use qrc20::{qrc20_coin_from_conf_and_request, Qrc20Coin, Qrc20FeeDetails};
 
@@ -98,6 +115,11 @@ pub use test_coin::TestCoin;
 pub mod tx_history_db;
 use tx_history_db::{TxHistoryDb, TxHistoryError, TxHistoryOps, TxHistoryResult};
 
+pub mod message_signing;
+pub mod signer;
+pub mod watch_only;
+use watch_only::WatchOnlyError;
+
 #[cfg(all(not(target_arch = "wasm32"), feature = "zhtlc"))]
 pub mod z_coin;
 use crate::utxo::UnsupportedAddr;
@@ -141,7 +163,29 @@ impl Deref for TransactionEnum {
     }
 }
 
-pub type TransactionFut = Box<dyn Future<Item = TransactionEnum, Error = String> + Send>;
+/// The error of sending a swap transaction or waiting for it.
+#[derive(Clone, Debug, Display, PartialEq)]
+pub enum TransactionErr {
+    /// The transaction can't be signed since MM2 runs in the watch-only mode.
+    #[display(fmt = "{}", _0)]
+    WatchOnly(WatchOnlyError),
+    #[display(fmt = "{}", _0)]
+    Plain(String),
+}
+
+impl From<WatchOnlyError> for TransactionErr {
+    fn from(e: WatchOnlyError) -> Self { TransactionErr::WatchOnly(e) }
+}
+
+impl From<String> for TransactionErr {
+    fn from(e: String) -> Self { TransactionErr::Plain(e) }
+}
+
+impl From<TransactionErr> for String {
+    fn from(e: TransactionErr) -> Self { e.to_string() }
+}
+
+pub type TransactionFut = Box<dyn Future<Item = TransactionEnum, Error = TransactionErr> + Send>;
 
 #[derive(Debug, PartialEq)]
 pub enum FoundSwapTxSpend {
@@ -168,6 +212,8 @@ pub enum NegotiateSwapContractAddrErr {
 pub trait SwapOps {
     fn send_taker_fee(&self, fee_addr: &[u8], amount: BigDecimal) -> TransactionFut;
 
+    /// `other_protocol_info` is the `coin_protocol_info` of the coin received from the other side,
+    /// it's used to choose the payment output type supported by the other side.
     fn send_maker_payment(
         &self,
         time_lock: u32,
@@ -175,8 +221,11 @@ pub trait SwapOps {
         secret_hash: &[u8],
         amount: BigDecimal,
         swap_contract_address: &Option<BytesJson>,
+        other_protocol_info: &Option<Vec<u8>>,
     ) -> TransactionFut;
 
+    /// `other_protocol_info` is the `coin_protocol_info` of the coin received from the other side,
+    /// it's used to choose the payment output type supported by the other side.
     fn send_taker_payment(
         &self,
         time_lock: u32,
@@ -184,6 +233,7 @@ pub trait SwapOps {
         secret_hash: &[u8],
         amount: BigDecimal,
         swap_contract_address: &Option<BytesJson>,
+        other_protocol_info: &Option<Vec<u8>>,
     ) -> TransactionFut;
 
     fn send_maker_spends_taker_payment(
@@ -371,16 +421,53 @@ pub enum WithdrawFee {
     },
 }
 
+/// The max length of the withdraw memo, it's the max OP_RETURN data size relayed by the Bitcoin Core by default.
+pub const MAX_WITHDRAW_MEMO_LEN: usize = 80;
+
 #[allow(dead_code)]
 #[derive(Deserialize)]
 pub struct WithdrawRequest {
     coin: String,
+    #[serde(default)]
     to: String,
     #[serde(default)]
     amount: BigDecimal,
     #[serde(default)]
     max: bool,
     fee: Option<WithdrawFee>,
+    /// The recipients of the batch withdraw. `to`, `amount` and `max` are ignored if it's not empty.
+    #[serde(default)]
+    outputs: Vec<WithdrawOutput>,
+    /// The text stored in an OP_RETURN output. Supported by UTXO coins only.
+    #[serde(default)]
+    memo: Option<String>,
+    /// The outputs to spend. The coin selects the inputs itself if it's empty. Supported by UTXO coins only.
+    #[serde(default)]
+    inputs: Vec<TxOutPoint>,
+    /// Overrides the coin config strategy choosing the inputs. Supported by UTXO coins only.
+    #[serde(default)]
+    coin_selection: Option<CoinSelectionStrategy>,
+    /// Whether to return the unsigned PSBT (BIP 174) instead of the signed transaction. Supported by UTXO coins only.
+    #[serde(default)]
+    psbt: bool,
+}
+
+/// The recipient of the batch withdraw.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct WithdrawOutput {
+    pub to: String,
+    #[serde(default)]
+    pub amount: BigDecimal,
+    /// Whether the rest of the balance is sent to this recipient. Can be set for one output only.
+    #[serde(default)]
+    pub max: bool,
+}
+
+/// The transaction output identified by the hash of the transaction and the index of the output.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct TxOutPoint {
+    pub tx_hash: H256Json,
+    pub index: u32,
 }
 
 impl WithdrawRequest {
@@ -391,7 +478,63 @@ impl WithdrawRequest {
             amount: 0.into(),
             max: true,
             fee: None,
+            outputs: Vec::new(),
+            memo: None,
+            inputs: Vec::new(),
+            coin_selection: None,
+            psbt: false,
+        }
+    }
+
+    /// Whether the request has the batch `outputs` rather than the only recipient.
+    pub fn is_batch(&self) -> bool { !self.outputs.is_empty() }
+
+    /// Returns the recipients of the withdraw: the batch `outputs` or the only one given by `to`, `amount` and `max`.
+    pub fn withdraw_outputs(&self) -> Result<Vec<WithdrawOutput>, MmError<WithdrawError>> {
+        if !self.is_batch() {
+            return Ok(vec![WithdrawOutput {
+                to: self.to.clone(),
+                amount: self.amount.clone(),
+                max: self.max,
+            }]);
+        }
+        if self.outputs.iter().filter(|output| output.max).count() > 1 {
+            return MmError::err(WithdrawError::InvalidOutputs(
+                "'max' can be set for one output only".to_owned(),
+            ));
         }
+        Ok(self.outputs.clone())
+    }
+
+    /// Returns an error if the request has the batch `outputs`, the `memo`, the explicit `inputs`,
+    /// the `coin_selection` or the `psbt` flag that aren't supported by the coin.
+    pub fn ensure_no_utxo_specific_params(&self) -> Result<(), MmError<WithdrawError>> {
+        if self.is_batch() {
+            return MmError::err(WithdrawError::BatchWithdrawNotSupported {
+                coin: self.coin.clone(),
+            });
+        }
+        if self.memo.is_some() {
+            return MmError::err(WithdrawError::MemoNotSupported {
+                coin: self.coin.clone(),
+            });
+        }
+        if !self.inputs.is_empty() {
+            return MmError::err(WithdrawError::InputsNotSupported {
+                coin: self.coin.clone(),
+            });
+        }
+        if self.coin_selection.is_some() {
+            return MmError::err(WithdrawError::CoinSelectionNotSupported {
+                coin: self.coin.clone(),
+            });
+        }
+        if self.psbt {
+            return MmError::err(WithdrawError::PsbtNotSupported {
+                coin: self.coin.clone(),
+            });
+        }
+        Ok(())
     }
 }
 
@@ -460,7 +603,7 @@ pub struct TransactionDetails {
     /// Raw bytes of signed transaction in hexadecimal string, this should be sent as is to send_raw_transaction RPC to broadcast the transaction
     pub tx_hex: BytesJson,
     /// Transaction hash in hexadecimal format
-    tx_hash: BytesJson,
+    pub tx_hash: BytesJson,
     /// Coins are sent from these addresses
     from: Vec<String>,
     /// Coins are sent to these addresses
@@ -488,6 +631,10 @@ pub struct TransactionDetails {
     /// Amount of accrued rewards.
     #[serde(skip_serializing_if = "Option::is_none")]
     kmd_rewards: Option<KmdRewardsDetails>,
+    /// The unsigned PSBT (BIP 174) if it's requested by the withdraw. `tx_hex` is the unsigned transaction then,
+    /// and `tx_hash` is its legacy txid that doesn't match the signed transaction unless every input is segwit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    psbt: Option<BytesJson>,
 }
 
 impl TransactionDetails {
@@ -696,8 +843,26 @@ pub enum WithdrawError {
     InvalidAddress(String),
     #[display(fmt = "Invalid fee policy: {}", _0)]
     InvalidFeePolicy(String),
+    #[display(fmt = "Invalid outputs: {}", _0)]
+    InvalidOutputs(String),
+    #[display(fmt = "The memo is {} bytes long, the max length is {}", length, max_length)]
+    MemoTooLong { length: usize, max_length: usize },
+    #[display(fmt = "{} doesn't support the batch withdraw", coin)]
+    BatchWithdrawNotSupported { coin: String },
+    #[display(fmt = "{} doesn't support the withdraw memo", coin)]
+    MemoNotSupported { coin: String },
+    #[display(fmt = "{} doesn't support the explicit withdraw inputs", coin)]
+    InputsNotSupported { coin: String },
+    #[display(fmt = "Invalid inputs: {}", _0)]
+    InvalidInputs(String),
+    #[display(fmt = "{} doesn't support the coin selection strategies", coin)]
+    CoinSelectionNotSupported { coin: String },
+    #[display(fmt = "{} doesn't support PSBT", coin)]
+    PsbtNotSupported { coin: String },
     #[display(fmt = "No such coin {}", coin)]
     NoSuchCoin { coin: String },
+    #[display(fmt = "MM2 runs in the watch-only mode, withdraw is not available")]
+    WatchOnly,
     #[display(fmt = "Transport error: {}", _0)]
     Transport(String),
     #[display(fmt = "Internal error: {}", _0)]
@@ -712,7 +877,16 @@ impl HttpStatusCode for WithdrawError {
             | WithdrawError::AmountTooLow { .. }
             | WithdrawError::InvalidAddress(_)
             | WithdrawError::InvalidFeePolicy(_)
+            | WithdrawError::InvalidOutputs(_)
+            | WithdrawError::MemoTooLong { .. }
+            | WithdrawError::BatchWithdrawNotSupported { .. }
+            | WithdrawError::MemoNotSupported { .. }
+            | WithdrawError::InputsNotSupported { .. }
+            | WithdrawError::InvalidInputs(_)
+            | WithdrawError::CoinSelectionNotSupported { .. }
+            | WithdrawError::PsbtNotSupported { .. }
             | WithdrawError::NoSuchCoin { .. } => StatusCode::BAD_REQUEST,
+            WithdrawError::WatchOnly => StatusCode::METHOD_NOT_ALLOWED,
             WithdrawError::Transport(_) | WithdrawError::InternalError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
@@ -731,6 +905,10 @@ impl From<BalanceError> for WithdrawError {
     }
 }
 
+impl From<WatchOnlyError> for WithdrawError {
+    fn from(_: WatchOnlyError) -> Self { WithdrawError::WatchOnly }
+}
+
 impl From<CoinFindError> for WithdrawError {
     fn from(e: CoinFindError) -> Self {
         match e {
@@ -900,6 +1078,10 @@ pub trait MmCoin: SwapOps + MarketCoinOps + fmt::Debug + Send + Sync + 'static {
 
     /// Check if serialized coin protocol info is supported by current version.
     fn is_coin_protocol_supported(&self, info: &Option<Vec<u8>>) -> bool;
+
+    /// Notifications sent when the balance of the coin may have changed.
+    /// If `None`, the balance is polled periodically.
+    fn balance_update_notifications(&self) -> Option<CoinNotifications> { None }
 }
 
 #[derive(Clone, Debug)]
@@ -908,6 +1090,7 @@ pub enum MmCoinEnum {
     QtumCoin(QtumCoin),
     Qrc20Coin(Qrc20Coin),
     EthCoin(EthCoin),
+    SlpToken(SlpToken),
     #[cfg(all(not(target_arch = "wasm32"), feature = "zhtlc"))]
     ZCoin(ZCoin),
     Test(TestCoin),
@@ -933,6 +1116,10 @@ impl From<Qrc20Coin> for MmCoinEnum {
     fn from(c: Qrc20Coin) -> MmCoinEnum { MmCoinEnum::Qrc20Coin(c) }
 }
 
+impl From<SlpToken> for MmCoinEnum {
+    fn from(c: SlpToken) -> MmCoinEnum { MmCoinEnum::SlpToken(c) }
+}
+
 #[cfg(all(not(target_arch = "wasm32"), feature = "zhtlc"))]
 impl From<ZCoin> for MmCoinEnum {
     fn from(c: ZCoin) -> MmCoinEnum { MmCoinEnum::ZCoin(c) }
@@ -947,6 +1134,7 @@ impl Deref for MmCoinEnum {
             MmCoinEnum::QtumCoin(ref c) => c,
             MmCoinEnum::Qrc20Coin(ref c) => c,
             MmCoinEnum::EthCoin(ref c) => c,
+            MmCoinEnum::SlpToken(ref c) => c,
             #[cfg(all(not(target_arch = "wasm32"), feature = "zhtlc"))]
             MmCoinEnum::ZCoin(ref c) => c,
             MmCoinEnum::Test(ref c) => c,
@@ -1021,6 +1209,10 @@ pub enum CoinProtocol {
         platform: String,
         contract_address: String,
     },
+    SLP {
+        platform: String,
+        token_id: H256Json,
+    },
     #[cfg(all(not(target_arch = "wasm32"), feature = "zhtlc"))]
     ZHTLC,
 }
@@ -1200,7 +1392,6 @@ pub async fn lp_coininit(ctx: &MmArc, ticker: &str, req: &Json) -> Result<MmCoin
             "assuming that coin is not supported"
         ));
     }
-    let secret = &*ctx.secp256k1_key_pair().private().secret;
 
     if coins_en["protocol"].is_null() {
         return ERR!(
@@ -1209,6 +1400,34 @@ pub async fn lp_coininit(ctx: &MmArc, ticker: &str, req: &Json) -> Result<MmCoin
     }
     let protocol: CoinProtocol = try_s!(json::from_value(coins_en["protocol"].clone()));
 
+    // UTXO and ETH coins take their public keys or addresses from the `watch_only` config, so the secret is left empty
+    let secret = if let CoinProtocol::SLP { .. } = protocol {
+        // SLP tokens use the keys of the platform coin
+        Secret::default()
+    } else if ctx.is_watch_only() {
+        match protocol {
+            CoinProtocol::UTXO
+            | CoinProtocol::QTUM
+            | CoinProtocol::QRC20 { .. }
+            | CoinProtocol::ETH
+            | CoinProtocol::ERC20 { .. } => Secret::default(),
+            _ => return ERR!("{} doesn't support the watch-only mode", ticker),
+        }
+    } else if ctx.uses_external_signer() {
+        // the coins request their public keys from the external signer
+        match protocol {
+            CoinProtocol::UTXO
+            | CoinProtocol::QTUM
+            | CoinProtocol::QRC20 { .. }
+            | CoinProtocol::ETH
+            | CoinProtocol::ERC20 { .. } => Secret::default(),
+            _ => return ERR!("{} doesn't support the external signer", ticker),
+        }
+    } else {
+        try_s!(coin_secret(ctx, ticker, &coins_en, req))
+    };
+    let secret = &*secret;
+
     let coin: MmCoinEnum = match &protocol {
         CoinProtocol::UTXO => {
             try_s!(utxo_standard_coin_from_conf_and_request(ctx, ticker, &coins_en, req, secret).await).into()
@@ -1227,6 +1446,13 @@ pub async fn lp_coininit(ctx: &MmArc, ticker: &str, req: &Json) -> Result<MmCoin
             )
             .into()
         },
+        CoinProtocol::SLP { platform, token_id } => {
+            let token =
+                try_s!(slp_token_from_conf_and_request(ctx, ticker, platform, token_id.clone(), &coins_en, req).await);
+            // the platform coin must not spend the outputs carrying the token
+            try_s!(token.freeze_platform_token_outpoints().await);
+            token.into()
+        },
         #[cfg(all(not(target_arch = "wasm32"), feature = "zhtlc"))]
         CoinProtocol::ZHTLC => try_s!(z_coin_from_conf_and_request(ctx, ticker, &coins_en, req, secret).await).into(),
     };
@@ -1369,6 +1595,10 @@ pub async fn validate_address(ctx: MmArc, req: Json) -> Result<Response<Vec<u8>>
 }
 
 pub async fn withdraw(ctx: MmArc, req: WithdrawRequest) -> WithdrawResult {
+    // the unsigned PSBT can be signed by the external wallet
+    if ctx.is_watch_only() && !req.psbt {
+        return MmError::err(WithdrawError::WatchOnly);
+    }
     let coin = lp_coinfind_or_err(&ctx, &req.coin).await?;
     coin.withdraw(req).compat().await
 }
@@ -1519,6 +1749,10 @@ pub async fn disable_coin(ctx: &MmArc, ticker: &str) -> Result<(), String> {
     let coins_ctx = try_s!(CoinsContext::from_ctx(ctx));
     let mut coins = coins_ctx.coins.lock().await;
     match coins.remove(ticker) {
+        Some(MmCoinEnum::SlpToken(token)) => {
+            token.unfreeze_platform_token_outpoints().await;
+            Ok(())
+        },
         Some(_) => Ok(()),
         None => ERR!("{} is disabled already", ticker),
     }
@@ -1571,6 +1805,12 @@ pub async fn set_requires_notarization(ctx: MmArc, req: Json) -> Result<Response
 }
 
 pub async fn show_priv_key(ctx: MmArc, req: Json) -> Result<Response<Vec<u8>>, String> {
+    if ctx.is_watch_only() {
+        return ERR!("{}", WatchOnlyError);
+    }
+    if ctx.uses_external_signer() {
+        return ERR!("The private keys are kept by the external signer");
+    }
     let ticker = try_s!(req["coin"].as_str().ok_or("No 'coin' field")).to_owned();
     let coin = match lp_coinfind(&ctx, &ticker).await {
         Ok(Some(t)) => t,
@@ -1586,11 +1826,40 @@ pub async fn show_priv_key(ctx: MmArc, req: Json) -> Result<Response<Vec<u8>>, S
     Ok(try_s!(Response::builder().body(res)))
 }
 
+/// The receiver of the notifications sent by the coin RPC client, e.g. on the Electrum subscription updates.
+pub type CoinNotifications = UnboundedReceiver<()>;
+
+/// Waits for a notification at most `timeout` seconds draining the pending ones.
+/// Returns `false` on timeout.
+/// If the notifications channel is closed, sleeps `timeout` seconds and returns `true` to fall back to polling.
+pub async fn wait_for_notification(notifications: &mut CoinNotifications, timeout: f64) -> bool {
+    match notifications.next().timeout_secs(timeout).await {
+        Ok(Some(_)) => (),
+        Ok(None) => {
+            Timer::sleep(timeout).await;
+            return true;
+        },
+        Err(_timeout) => return false,
+    }
+    // several notifications can be received at once, e.g. a few transactions are mined in one block
+    while let Ok(Some(_)) = notifications.try_next() {}
+    true
+}
+
+/// The balance is requested on every notification, it's still polled with this interval as a fallback.
+const BALANCE_NOTIFICATIONS_TIMEOUT: f64 = 60.;
+
 // TODO: Refactor this, it's actually not required to check balance and trade fee when there no orders using the coin
 pub async fn check_balance_update_loop(ctx: MmWeak, ticker: String) {
     let mut current_balance = None;
+    let mut notifications: Option<CoinNotifications> = None;
     loop {
-        Timer::sleep(10.).await;
+        match notifications {
+            Some(ref mut notifications) => {
+                wait_for_notification(notifications, BALANCE_NOTIFICATIONS_TIMEOUT).await;
+            },
+            None => Timer::sleep(10.).await,
+        }
         let ctx = match MmArc::from_weak(&ctx) {
             Some(ctx) => ctx,
             None => return,
@@ -1598,6 +1867,9 @@ pub async fn check_balance_update_loop(ctx: MmWeak, ticker: String) {
 
         match lp_coinfind(&ctx, &ticker).await {
             Ok(Some(coin)) => {
+                if notifications.is_none() {
+                    notifications = coin.balance_update_notifications();
+                }
                 let balance = match coin.my_spendable_balance().compat().await {
                     Ok(balance) => balance,
                     Err(_) => continue,
@@ -1702,6 +1974,7 @@ pub fn address_by_coin_conf_and_pubkey_str(
         CoinProtocol::UTXO | CoinProtocol::QTUM | CoinProtocol::QRC20 { .. } => {
             utxo::address_by_conf_and_pubkey_str(coin, conf, pubkey, addr_format)
         },
+        CoinProtocol::SLP { .. } => slp::slp_addr_from_pubkey_str(pubkey, conf),
         #[cfg(all(not(target_arch = "wasm32"), feature = "zhtlc"))]
         CoinProtocol::ZHTLC => utxo::address_by_conf_and_pubkey_str(coin, conf, pubkey, addr_format),
     }
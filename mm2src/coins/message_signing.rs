@@ -0,0 +1,109 @@
+//! Signing and verification of the arbitrary messages with the key of a coin.
+//!
+//! UTXO coins use the Bitcoin "Signed Message" format: the double SHA-256 of the coin's `sign_message_prefix`
+//! and the message, each preceded by its length, signed with the compact recoverable signature encoded in base64.
+//! `EthCoin` signs the messages according to EIP-191 (`personal_sign`), the signature is `0x`-prefixed hex.
+
+use crate::utxo::utxo_common;
+use crate::watch_only::WatchOnlyError;
+use crate::{lp_coinfind_or_err, CoinFindError, MmCoinEnum};
+use common::mm_ctx::MmArc;
+use common::mm_error::prelude::*;
+use common::HttpStatusCode;
+use derive_more::Display;
+use http::StatusCode;
+
+pub type SignatureResult<T> = Result<T, MmError<SignatureError>>;
+
+#[derive(Deserialize)]
+pub struct SignMessageRequest {
+    coin: String,
+    message: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SignMessageResponse {
+    signature: String,
+}
+
+#[derive(Deserialize)]
+pub struct VerifyMessageRequest {
+    coin: String,
+    message: String,
+    signature: String,
+    address: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VerifyMessageResponse {
+    is_valid: bool,
+}
+
+#[derive(Debug, Display, Serialize, SerializeErrorType)]
+#[serde(tag = "error_type", content = "error_data")]
+pub enum SignatureError {
+    #[display(fmt = "No such coin {}", coin)]
+    NoSuchCoin { coin: String },
+    #[display(fmt = "{} doesn't support the message signing", coin)]
+    CoinDoesNotSupportSigning { coin: String },
+    #[display(fmt = "'sign_message_prefix' is not set in the {} config", coin)]
+    PrefixNotFound { coin: String },
+    #[display(fmt = "Invalid signature: {}", _0)]
+    InvalidSignature(String),
+    #[display(fmt = "Invalid address: {}", _0)]
+    InvalidAddress(String),
+    #[display(fmt = "MM2 runs in the watch-only mode, the message can't be signed")]
+    WatchOnly,
+    #[display(fmt = "Internal error: {}", _0)]
+    InternalError(String),
+}
+
+impl HttpStatusCode for SignatureError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            SignatureError::NoSuchCoin { .. }
+            | SignatureError::CoinDoesNotSupportSigning { .. }
+            | SignatureError::PrefixNotFound { .. }
+            | SignatureError::InvalidSignature(_)
+            | SignatureError::InvalidAddress(_) => StatusCode::BAD_REQUEST,
+            SignatureError::WatchOnly => StatusCode::METHOD_NOT_ALLOWED,
+            SignatureError::InternalError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl From<CoinFindError> for SignatureError {
+    fn from(e: CoinFindError) -> Self {
+        match e {
+            CoinFindError::NoSuchCoin { coin } => SignatureError::NoSuchCoin { coin },
+        }
+    }
+}
+
+impl From<WatchOnlyError> for SignatureError {
+    fn from(_: WatchOnlyError) -> Self { SignatureError::WatchOnly }
+}
+
+impl From<keys::Error> for SignatureError {
+    fn from(e: keys::Error) -> Self { SignatureError::InvalidSignature(e.to_string()) }
+}
+
+pub async fn sign_message(ctx: MmArc, req: SignMessageRequest) -> SignatureResult<SignMessageResponse> {
+    let signature = match lp_coinfind_or_err(&ctx, &req.coin).await? {
+        MmCoinEnum::UtxoCoin(utxo) => utxo_common::sign_message(&utxo, &req.message)?,
+        MmCoinEnum::QtumCoin(qtum) => utxo_common::sign_message(&qtum, &req.message)?,
+        MmCoinEnum::EthCoin(eth) => eth.sign_message(&req.message)?,
+        _ => return MmError::err(SignatureError::CoinDoesNotSupportSigning { coin: req.coin }),
+    };
+    Ok(SignMessageResponse { signature })
+}
+
+pub async fn verify_message(ctx: MmArc, req: VerifyMessageRequest) -> SignatureResult<VerifyMessageResponse> {
+    let is_valid = match lp_coinfind_or_err(&ctx, &req.coin).await? {
+        MmCoinEnum::UtxoCoin(utxo) => utxo_common::verify_message(&utxo, &req.message, &req.signature, &req.address)?,
+        MmCoinEnum::QtumCoin(qtum) => utxo_common::verify_message(&qtum, &req.message, &req.signature, &req.address)?,
+        MmCoinEnum::EthCoin(eth) => eth.verify_message(&req.message, &req.signature, &req.address)?,
+        _ => return MmError::err(SignatureError::CoinDoesNotSupportSigning { coin: req.coin }),
+    };
+    Ok(VerifyMessageResponse { is_valid })
+}
@@ -10,8 +10,9 @@ use crate::utxo::{qtum, sign_tx, ActualTxFee, AdditionalTxData, FeePolicy, Gener
                   UtxoCoinFields, UtxoCommonOps, UtxoTx, VerboseTransactionFrom, UTXO_LOCK};
 use crate::{BalanceError, BalanceFut, CoinBalance, FeeApproxStage, FoundSwapTxSpend, HistorySyncState, MarketCoinOps,
             MmCoin, NegotiateSwapContractAddrErr, SwapOps, TradeFee, TradePreimageError, TradePreimageFut,
-            TradePreimageResult, TradePreimageValue, TransactionDetails, TransactionEnum, TransactionFut,
-            ValidateAddressResult, WithdrawError, WithdrawFee, WithdrawFut, WithdrawRequest, WithdrawResult};
+            TradePreimageResult, TradePreimageValue, TransactionDetails, TransactionEnum, TransactionErr,
+            TransactionFut, ValidateAddressResult, WithdrawError, WithdrawFee, WithdrawFut, WithdrawRequest,
+            WithdrawResult};
 use async_trait::async_trait;
 use bigdecimal::BigDecimal;
 use bitcrypto::{dhash160, sha256};
@@ -39,6 +40,7 @@ use script::{Builder as ScriptBuilder, Opcode, Script, TransactionInputSigner};
 use script_pubkey::generate_contract_call_script_pubkey;
 use serde_json::{self as json, Value as Json};
 use serialization::{deserialize, serialize, CoinVariant};
+use std::collections::{HashMap, HashSet};
 use std::ops::{Deref, Neg};
 #[cfg(not(target_arch = "wasm32"))] use std::path::PathBuf;
 use std::str::FromStr;
@@ -47,15 +49,15 @@ use std::sync::Arc;
 mod history;
 #[cfg(test)] mod qrc20_tests;
 pub mod rpc_clients;
-mod script_pubkey;
+pub(crate) mod script_pubkey;
 mod swap;
 
 /// Qtum amount is always 0 for the QRC20 UTXO outputs,
 /// because we should pay only a fee in Qtum to send the QRC20 transaction.
-const OUTPUT_QTUM_AMOUNT: u64 = 0;
+pub(crate) const OUTPUT_QTUM_AMOUNT: u64 = 0;
 const QRC20_GAS_LIMIT_DEFAULT: u64 = 100_000;
 const QRC20_PAYMENT_GAS_LIMIT: u64 = 200_000;
-const QRC20_GAS_PRICE_DEFAULT: u64 = 40;
+pub(crate) const QRC20_GAS_PRICE_DEFAULT: u64 = 40;
 const QRC20_DUST: u64 = 0;
 // Keccak-256 hash of `Transfer` event
 const QRC20_TRANSFER_TOPIC: &str = "ddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef";
@@ -157,6 +159,9 @@ impl UtxoCoinBuilder for Qrc20CoinBuilder<'_> {
 
     fn dust_amount(&self) -> u64 { QRC20_DUST }
 
+    /// QRC20 tokens are sent from `my_address` only, so the change must not go to the other addresses.
+    fn supports_hd_account(&self) -> bool { false }
+
     #[cfg(not(target_arch = "wasm32"))]
     fn confpath(&self) -> Result<PathBuf, String> {
         use crate::utxo::coin_daemon_data_dir;
@@ -308,10 +313,14 @@ impl MutContractCallType {
     fn short_signature(&self) -> [u8; 4] { self.as_function().short_signature() }
 }
 
-struct GenerateQrc20TxResult {
-    signed: UtxoTx,
-    miner_fee: u64,
-    gas_fee: u64,
+pub(crate) struct GenerateQrc20TxResult {
+    pub(crate) signed: UtxoTx,
+    pub(crate) miner_fee: u64,
+    pub(crate) gas_fee: u64,
+    /// The total value of the spent unspents.
+    pub(crate) spent_by_me: u64,
+    /// The value of the change output.
+    pub(crate) received_by_me: u64,
 }
 
 #[derive(Debug, Display)]
@@ -380,36 +389,7 @@ impl Qrc20Coin {
         &self,
         contract_outputs: Vec<ContractCallOutput>,
     ) -> Result<GenerateQrc20TxResult, MmError<GenerateTxError>> {
-        let (unspents, _) = self.ordered_mature_unspents(&self.utxo.my_address).await?;
-
-        let mut gas_fee = 0;
-        let mut outputs = Vec::with_capacity(contract_outputs.len());
-        for output in contract_outputs {
-            gas_fee += output.gas_limit * output.gas_price;
-            outputs.push(TransactionOutput::from(output));
-        }
-        let fee_policy = FeePolicy::SendExact;
-        let tx_fee = None;
-
-        let (unsigned, data) = self
-            .generate_transaction(unspents, outputs, fee_policy, tx_fee, Some(gas_fee))
-            .await?;
-        let prev_script = ScriptBuilder::build_p2pkh(&self.utxo.my_address.hash);
-        let signed = sign_tx(
-            unsigned,
-            &self.utxo.key_pair,
-            prev_script,
-            self.utxo.conf.signature_version,
-            self.utxo.conf.fork_id,
-        )
-        .map_to_mm(GenerateTxError::Internal)?;
-
-        let miner_fee = data.fee_amount + data.unused_change.unwrap_or_default();
-        Ok(GenerateQrc20TxResult {
-            signed,
-            miner_fee,
-            gas_fee,
-        })
+        generate_contract_call_transaction(self, contract_outputs).await
     }
 
     fn transfer_output(
@@ -547,12 +527,12 @@ impl UtxoCommonOps for Qrc20Coin {
         utxo_common::ordered_mature_unspents(self, address).await
     }
 
-    fn get_verbose_transaction_from_cache_or_rpc(
+    fn get_verbose_transactions_from_cache_or_rpc(
         &self,
-        txid: H256Json,
-    ) -> Box<dyn Future<Item = VerboseTransactionFrom, Error = String> + Send> {
+        txids: HashSet<H256Json>,
+    ) -> Box<dyn Future<Item = HashMap<H256Json, VerboseTransactionFrom>, Error = String> + Send> {
         let selfi = self.clone();
-        let fut = async move { utxo_common::get_verbose_transaction_from_cache_or_rpc(&selfi.utxo, txid).await };
+        let fut = async move { utxo_common::get_verbose_transactions_from_cache_or_rpc(&selfi.utxo, txids).await };
         Box::new(fut.boxed().compat())
     }
 
@@ -592,16 +572,17 @@ impl UtxoCommonOps for Qrc20Coin {
 
 impl SwapOps for Qrc20Coin {
     fn send_taker_fee(&self, fee_addr: &[u8], amount: BigDecimal) -> TransactionFut {
-        let to_address = try_fus!(self.contract_address_from_raw_pubkey(fee_addr));
-        let amount = try_fus!(wei_from_big_decimal(&amount, self.utxo.decimals));
+        try_f!(self.utxo.key_pair.signer().map_err(TransactionErr::from));
+        let to_address = try_tx_fus!(self.contract_address_from_raw_pubkey(fee_addr));
+        let amount = try_tx_fus!(wei_from_big_decimal(&amount, self.utxo.decimals));
         let transfer_output =
-            try_fus!(self.transfer_output(to_address, amount, QRC20_GAS_LIMIT_DEFAULT, QRC20_GAS_PRICE_DEFAULT));
+            try_tx_fus!(self.transfer_output(to_address, amount, QRC20_GAS_LIMIT_DEFAULT, QRC20_GAS_PRICE_DEFAULT));
         let outputs = vec![transfer_output];
 
         let selfi = self.clone();
         let fut = async move { selfi.send_contract_calls(outputs).await };
 
-        Box::new(fut.boxed().compat())
+        Box::new(fut.boxed().compat().map_err(TransactionErr::Plain))
     }
 
     fn send_maker_payment(
@@ -611,12 +592,14 @@ impl SwapOps for Qrc20Coin {
         secret_hash: &[u8],
         amount: BigDecimal,
         swap_contract_address: &Option<BytesJson>,
+        _other_protocol_info: &Option<Vec<u8>>,
     ) -> TransactionFut {
-        let taker_addr = try_fus!(self.contract_address_from_raw_pubkey(taker_pub));
+        try_f!(self.utxo.key_pair.signer().map_err(TransactionErr::from));
+        let taker_addr = try_tx_fus!(self.contract_address_from_raw_pubkey(taker_pub));
         let id = qrc20_swap_id(time_lock, secret_hash);
-        let value = try_fus!(wei_from_big_decimal(&amount, self.utxo.decimals));
+        let value = try_tx_fus!(wei_from_big_decimal(&amount, self.utxo.decimals));
         let secret_hash = Vec::from(secret_hash);
-        let swap_contract_address = try_fus!(swap_contract_address.try_to_address());
+        let swap_contract_address = try_tx_fus!(swap_contract_address.try_to_address());
 
         let selfi = self.clone();
         let fut = async move {
@@ -624,7 +607,7 @@ impl SwapOps for Qrc20Coin {
                 .send_hash_time_locked_payment(id, value, time_lock, secret_hash, taker_addr, swap_contract_address)
                 .await
         };
-        Box::new(fut.boxed().compat())
+        Box::new(fut.boxed().compat().map_err(TransactionErr::Plain))
     }
 
     fn send_taker_payment(
@@ -634,12 +617,14 @@ impl SwapOps for Qrc20Coin {
         secret_hash: &[u8],
         amount: BigDecimal,
         swap_contract_address: &Option<BytesJson>,
+        _other_protocol_info: &Option<Vec<u8>>,
     ) -> TransactionFut {
-        let maker_addr = try_fus!(self.contract_address_from_raw_pubkey(maker_pub));
+        try_f!(self.utxo.key_pair.signer().map_err(TransactionErr::from));
+        let maker_addr = try_tx_fus!(self.contract_address_from_raw_pubkey(maker_pub));
         let id = qrc20_swap_id(time_lock, secret_hash);
-        let value = try_fus!(wei_from_big_decimal(&amount, self.utxo.decimals));
+        let value = try_tx_fus!(wei_from_big_decimal(&amount, self.utxo.decimals));
         let secret_hash = Vec::from(secret_hash);
-        let swap_contract_address = try_fus!(swap_contract_address.try_to_address());
+        let swap_contract_address = try_tx_fus!(swap_contract_address.try_to_address());
 
         let selfi = self.clone();
         let fut = async move {
@@ -647,7 +632,7 @@ impl SwapOps for Qrc20Coin {
                 .send_hash_time_locked_payment(id, value, time_lock, secret_hash, maker_addr, swap_contract_address)
                 .await
         };
-        Box::new(fut.boxed().compat())
+        Box::new(fut.boxed().compat().map_err(TransactionErr::Plain))
     }
 
     fn send_maker_spends_taker_payment(
@@ -658,8 +643,9 @@ impl SwapOps for Qrc20Coin {
         secret: &[u8],
         swap_contract_address: &Option<BytesJson>,
     ) -> TransactionFut {
-        let payment_tx: UtxoTx = try_fus!(deserialize(taker_payment_tx).map_err(|e| ERRL!("{:?}", e)));
-        let swap_contract_address = try_fus!(swap_contract_address.try_to_address());
+        try_f!(self.utxo.key_pair.signer().map_err(TransactionErr::from));
+        let payment_tx: UtxoTx = try_tx_fus!(deserialize(taker_payment_tx).map_err(|e| ERRL!("{:?}", e)));
+        let swap_contract_address = try_tx_fus!(swap_contract_address.try_to_address());
         let secret = secret.to_vec();
 
         let selfi = self.clone();
@@ -668,7 +654,7 @@ impl SwapOps for Qrc20Coin {
                 .spend_hash_time_locked_payment(payment_tx, swap_contract_address, secret)
                 .await
         };
-        Box::new(fut.boxed().compat())
+        Box::new(fut.boxed().compat().map_err(TransactionErr::Plain))
     }
 
     fn send_taker_spends_maker_payment(
@@ -679,9 +665,10 @@ impl SwapOps for Qrc20Coin {
         secret: &[u8],
         swap_contract_address: &Option<BytesJson>,
     ) -> TransactionFut {
-        let payment_tx: UtxoTx = try_fus!(deserialize(maker_payment_tx).map_err(|e| ERRL!("{:?}", e)));
+        try_f!(self.utxo.key_pair.signer().map_err(TransactionErr::from));
+        let payment_tx: UtxoTx = try_tx_fus!(deserialize(maker_payment_tx).map_err(|e| ERRL!("{:?}", e)));
         let secret = secret.to_vec();
-        let swap_contract_address = try_fus!(swap_contract_address.try_to_address());
+        let swap_contract_address = try_tx_fus!(swap_contract_address.try_to_address());
 
         let selfi = self.clone();
         let fut = async move {
@@ -689,7 +676,7 @@ impl SwapOps for Qrc20Coin {
                 .spend_hash_time_locked_payment(payment_tx, swap_contract_address, secret)
                 .await
         };
-        Box::new(fut.boxed().compat())
+        Box::new(fut.boxed().compat().map_err(TransactionErr::Plain))
     }
 
     fn send_taker_refunds_payment(
@@ -700,8 +687,9 @@ impl SwapOps for Qrc20Coin {
         _secret_hash: &[u8],
         swap_contract_address: &Option<BytesJson>,
     ) -> TransactionFut {
-        let payment_tx: UtxoTx = try_fus!(deserialize(taker_payment_tx).map_err(|e| ERRL!("{:?}", e)));
-        let swap_contract_address = try_fus!(swap_contract_address.try_to_address());
+        try_f!(self.utxo.key_pair.signer().map_err(TransactionErr::from));
+        let payment_tx: UtxoTx = try_tx_fus!(deserialize(taker_payment_tx).map_err(|e| ERRL!("{:?}", e)));
+        let swap_contract_address = try_tx_fus!(swap_contract_address.try_to_address());
 
         let selfi = self.clone();
         let fut = async move {
@@ -709,7 +697,7 @@ impl SwapOps for Qrc20Coin {
                 .refund_hash_time_locked_payment(swap_contract_address, payment_tx)
                 .await
         };
-        Box::new(fut.boxed().compat())
+        Box::new(fut.boxed().compat().map_err(TransactionErr::Plain))
     }
 
     fn send_maker_refunds_payment(
@@ -720,8 +708,9 @@ impl SwapOps for Qrc20Coin {
         _secret_hash: &[u8],
         swap_contract_address: &Option<BytesJson>,
     ) -> TransactionFut {
-        let payment_tx: UtxoTx = try_fus!(deserialize(maker_payment_tx).map_err(|e| ERRL!("{:?}", e)));
-        let swap_contract_address = try_fus!(swap_contract_address.try_to_address());
+        try_f!(self.utxo.key_pair.signer().map_err(TransactionErr::from));
+        let payment_tx: UtxoTx = try_tx_fus!(deserialize(maker_payment_tx).map_err(|e| ERRL!("{:?}", e)));
+        let swap_contract_address = try_tx_fus!(swap_contract_address.try_to_address());
 
         let selfi = self.clone();
         let fut = async move {
@@ -729,7 +718,7 @@ impl SwapOps for Qrc20Coin {
                 .refund_hash_time_locked_payment(swap_contract_address, payment_tx)
                 .await
         };
-        Box::new(fut.boxed().compat())
+        Box::new(fut.boxed().compat().map_err(TransactionErr::Plain))
     }
 
     fn validate_fee(
@@ -975,11 +964,11 @@ impl MarketCoinOps for Qrc20Coin {
         from_block: u64,
         _swap_contract_address: &Option<BytesJson>,
     ) -> TransactionFut {
-        let tx: UtxoTx = try_fus!(deserialize(transaction).map_err(|e| ERRL!("{:?}", e)));
+        let tx: UtxoTx = try_tx_fus!(deserialize(transaction).map_err(|e| ERRL!("{:?}", e)));
 
         let selfi = self.clone();
         let fut = async move { selfi.wait_for_tx_spend_impl(tx, wait_until, from_block).await };
-        Box::new(fut.boxed().compat())
+        Box::new(fut.boxed().compat().map_err(TransactionErr::Plain))
     }
 
     fn tx_enum_from_bytes(&self, bytes: &[u8]) -> Result<TransactionEnum, String> {
@@ -1181,21 +1170,71 @@ pub fn qrc20_swap_id(time_lock: u32, secret_hash: &[u8]) -> Vec<u8> {
 
 fn contract_addr_into_rpc_format(address: &H160) -> H160Json { H160Json::from(address.0) }
 
+/// Generate Qtum UTXO transaction with contract calls spending the mature unspents of the `coin`.
+/// The contract calls can be sent by any Qtum based coin, e.g. the delegation calls are sent by `QtumCoin`.
+/// Note: lock the UTXO_LOCK mutex before this function will be called.
+pub(crate) async fn generate_contract_call_transaction<T>(
+    coin: &T,
+    contract_outputs: Vec<ContractCallOutput>,
+) -> Result<GenerateQrc20TxResult, MmError<GenerateTxError>>
+where
+    T: AsRef<UtxoCoinFields> + UtxoCommonOps,
+{
+    let utxo = coin.as_ref();
+    let (unspents, _) = coin.ordered_mature_unspents(&utxo.my_address).await?;
+
+    let mut gas_fee = 0;
+    let mut outputs = Vec::with_capacity(contract_outputs.len());
+    for output in contract_outputs {
+        gas_fee += output.gas_limit * output.gas_price;
+        outputs.push(TransactionOutput::from(output));
+    }
+    let fee_policy = FeePolicy::SendExact;
+    let tx_fee = None;
+
+    let (unsigned, data) = coin
+        .generate_transaction(unspents, outputs, fee_policy, tx_fee, Some(gas_fee))
+        .await?;
+    let key_pair = utxo
+        .key_pair
+        .signer()
+        .map_to_mm(|e| GenerateTxError::Internal(e.to_string()))?;
+    let prev_script = ScriptBuilder::build_p2pkh(&utxo.my_address.hash);
+    let signed = sign_tx(
+        unsigned,
+        key_pair,
+        prev_script,
+        utxo.conf.signature_version,
+        utxo.conf.fork_id,
+    )
+    .map_to_mm(GenerateTxError::Internal)?;
+
+    let miner_fee = data.fee_amount + data.unused_change.unwrap_or_default();
+    Ok(GenerateQrc20TxResult {
+        signed,
+        miner_fee,
+        gas_fee,
+        spent_by_me: data.spent_by_me,
+        received_by_me: data.received_by_me,
+    })
+}
+
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct Qrc20FeeDetails {
     /// Coin name
-    coin: String,
+    pub(crate) coin: String,
     /// Standard UTXO miner fee based on transaction size
-    miner_fee: BigDecimal,
+    pub(crate) miner_fee: BigDecimal,
     /// Gas limit in satoshi.
-    gas_limit: u64,
+    pub(crate) gas_limit: u64,
     /// Gas price in satoshi.
-    gas_price: u64,
+    pub(crate) gas_price: u64,
     /// Total used gas.
-    total_gas_fee: BigDecimal,
+    pub(crate) total_gas_fee: BigDecimal,
 }
 
 async fn qrc20_withdraw(coin: Qrc20Coin, req: WithdrawRequest) -> WithdrawResult {
+    req.ensure_no_utxo_specific_params()?;
     let to_addr = UtxoAddress::from_str(&req.to)
         .map_err(|e| e.to_string())
         .map_to_mm(WithdrawError::InvalidAddress)?;
@@ -1253,6 +1292,7 @@ async fn qrc20_withdraw(coin: Qrc20Coin, req: WithdrawRequest) -> WithdrawResult
         signed,
         miner_fee,
         gas_fee,
+        ..
     } = coin.generate_qrc20_transaction(outputs).await.mm_err(|gen_tx_error| {
         WithdrawError::from_generate_tx_error(gen_tx_error, coin.platform.clone(), coin.utxo.decimals)
     })?;
@@ -1291,6 +1331,7 @@ async fn qrc20_withdraw(coin: Qrc20Coin, req: WithdrawRequest) -> WithdrawResult
         internal_id: vec![].into(),
         timestamp: now_ms() / 1000,
         kmd_rewards: None,
+        psbt: None,
     })
 }
 
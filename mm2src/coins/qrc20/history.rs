@@ -349,23 +349,25 @@ impl Qrc20Coin {
         let history = match history_res {
             Ok(h) => h,
             Err(e) => match e.into_inner() {
-                UtxoRpcError::Transport(json_rpc_e) | UtxoRpcError::ResponseParseError(json_rpc_e) => {
-                    match json_rpc_e.error {
-                        JsonRpcErrorType::Response(_addr, err) => {
-                            return if HISTORY_TOO_LARGE_ERROR.eq(&err) {
-                                RequestTxHistoryResult::HistoryTooLarge
-                            } else {
-                                RequestTxHistoryResult::Retry {
-                                    error: ERRL!("Error {:?} on blockchain_contract_event_get_history", err),
-                                }
+                UtxoRpcError::Transport(json_rpc_e)
+                | UtxoRpcError::ResponseParseError(json_rpc_e)
+                | UtxoRpcError::Unauthorized(json_rpc_e) => match json_rpc_e.error {
+                    JsonRpcErrorType::Response(_addr, err) => {
+                        return if HISTORY_TOO_LARGE_ERROR.eq(&err) {
+                            RequestTxHistoryResult::HistoryTooLarge
+                        } else {
+                            RequestTxHistoryResult::Retry {
+                                error: ERRL!("Error {:?} on blockchain_contract_event_get_history", err),
                             }
-                        },
-                        JsonRpcErrorType::Transport(err) | JsonRpcErrorType::Parse(_, err) => {
-                            return RequestTxHistoryResult::Retry {
-                                error: ERRL!("Error {} on blockchain_contract_event_get_history", err),
-                            };
-                        },
-                    }
+                        }
+                    },
+                    JsonRpcErrorType::Transport(err)
+                    | JsonRpcErrorType::Parse(_, err)
+                    | JsonRpcErrorType::Unauthorized(err) => {
+                        return RequestTxHistoryResult::Retry {
+                            error: ERRL!("Error {} on blockchain_contract_event_get_history", err),
+                        };
+                    },
                 },
                 UtxoRpcError::InvalidResponse(e) | UtxoRpcError::Internal(e) => {
                     return RequestTxHistoryResult::Retry {
@@ -81,6 +81,11 @@ fn test_withdraw_impl_fee_details() {
             gas_limit: 2_500_000,
             gas_price: 40,
         }),
+        outputs: Vec::new(),
+        memo: None,
+        inputs: Vec::new(),
+        coin_selection: None,
+        psbt: false,
     };
     let tx_details = coin.withdraw(withdraw_req).wait().unwrap();
 
@@ -496,6 +501,7 @@ fn test_transfer_details_by_hash() {
         .unwrap()
         .into(),
         kmd_rewards: None,
+        psbt: None,
     };
     assert_eq!(actual, expected);
 
@@ -519,6 +525,7 @@ fn test_transfer_details_by_hash() {
         .unwrap()
         .into(),
         kmd_rewards: None,
+        psbt: None,
     };
     assert_eq!(actual, expected);
 
@@ -542,6 +549,7 @@ fn test_transfer_details_by_hash() {
         .unwrap()
         .into(),
         kmd_rewards: None,
+        psbt: None,
     };
     assert_eq!(actual, expected);
 
@@ -565,6 +573,7 @@ fn test_transfer_details_by_hash() {
         .unwrap()
         .into(),
         kmd_rewards: None,
+        psbt: None,
     };
     assert_eq!(actual, expected);
 
@@ -588,6 +597,7 @@ fn test_transfer_details_by_hash() {
         .unwrap()
         .into(),
         kmd_rewards: None,
+        psbt: None,
     };
     assert_eq!(actual, expected);
     assert!(it.next().is_none());
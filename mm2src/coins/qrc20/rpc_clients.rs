@@ -1,4 +1,5 @@
 use super::*;
+use crate::utxo::qtum_delegation::DELEGATIONS_CONTRACT;
 use crate::utxo::rpc_clients::{UtxoRpcError, UtxoRpcFut};
 use rpc::v1::types::H256;
 
@@ -161,6 +162,8 @@ pub enum ViewContractCallType {
     Decimals,
     /// EtomicSwap function.
     Payments,
+    /// Qtum Delegations contract function.
+    Delegations,
 }
 
 impl ViewContractCallType {
@@ -170,6 +173,7 @@ impl ViewContractCallType {
             ViewContractCallType::Allowance => "allowance",
             ViewContractCallType::Decimals => "decimals",
             ViewContractCallType::Payments => "payments",
+            ViewContractCallType::Delegations => "delegations",
         }
     }
 
@@ -179,6 +183,7 @@ impl ViewContractCallType {
                 eth::ERC20_CONTRACT.function(self.as_function_name()).unwrap()
             },
             ViewContractCallType::Payments => eth::SWAP_CONTRACT.function(self.as_function_name()).unwrap(),
+            ViewContractCallType::Delegations => DELEGATIONS_CONTRACT.function(self.as_function_name()).unwrap(),
         }
     }
 }
@@ -0,0 +1,380 @@
+//! The signers the transactions of the UTXO and ETH coins are signed with.
+//!
+//! By default the keys are kept in-process: [`Signer`] is implemented for the UTXO and ETH key pairs.
+//! If the `external_signer` config field is set, the keys are kept by a separate process instead:
+//!
+//! ```json
+//! "external_signer": {
+//!     "socket_path": "/run/mm2-signer/signer.sock",
+//!     "timeout": 30
+//! }
+//! ```
+//!
+//! `timeout` is the number of seconds to wait for each response of the signer, 30 by default.
+//!
+//! # Protocol
+//!
+//! MM2 connects to the Unix socket at `socket_path` per request, writes a single JSON request
+//! terminated by `\n` and reads a single JSON response terminated by `\n`:
+//!
+//! ```json
+//! {"id": 1, "method": "get_public_key", "params": {"coin": "KMD"}}
+//! {"id": 1, "result": {"pubkey": "03..."}}
+//!
+//! {"id": 2, "method": "sign_hash", "params": {"coin": "KMD", "pubkey": "03...", "hash": "5f...", "recoverable": false}}
+//! {"id": 2, "result": {"signature": "3044..."}}
+//!
+//! {"id": 3, "method": "sign_hash", "params": {"coin": "KMD", "pubkey": "03...", "hash": "ff..."}}
+//! {"id": 3, "error": "Signing is rejected by the operator"}
+//! ```
+//!
+//! * `get_public_key` returns the compressed public key of the `coin`.
+//!   The `coin` param is null when the public key of the node itself is requested
+//!   (it's used to pick the `dbdir` subdirectory).
+//! * `sign_hash` signs the 32-byte `hash` with the key of `pubkey`.
+//!   If `recoverable` is false, the DER encoded ECDSA signature is returned (UTXO coins),
+//!   otherwise the 65-byte `r || s || v` signature where `v` is the recovery id 0 or 1 (ETH/ERC20).
+//!
+//! Every binary value is hex encoded. `id` of the response must match `id` of the request.
+//!
+//! The swap payments are locked with the node public key, so the coins can be traded only if the signer
+//! returns the node public key for them as well.
+
+use common::mm_ctx::MmArc;
+use ethereum_types::H256 as EthH256;
+use keys::{KeyPair, Public, Signature};
+use primitives::hash::{H256, H264, H520};
+use serde::de::DeserializeOwned;
+use serde_json::{self as json, Value as Json};
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+const DEFAULT_EXTERNAL_SIGNER_TIMEOUT: u64 = 30;
+
+/// Signs the transaction hashes with the key of a coin.
+pub trait Signer: fmt::Debug + Send + Sync {
+    /// Returns the compressed public key of the signing key.
+    fn public(&self) -> Public;
+
+    /// Returns the DER encoded ECDSA signature of the `hash`.
+    fn sign_hash(&self, hash: &H256) -> Result<Signature, String>;
+
+    /// Returns the recoverable ECDSA signature of the `hash` in the `r || s || v` form.
+    fn sign_hash_recoverable(&self, hash: &H256) -> Result<H520, String>;
+}
+
+impl Signer for KeyPair {
+    fn public(&self) -> Public {
+        match self.public() {
+            Public::Compressed(public) => Public::Compressed(public.clone()),
+            Public::Normal(public) => compressed_public(&**public).expect("KeyPair contains a valid public key"),
+        }
+    }
+
+    fn sign_hash(&self, hash: &H256) -> Result<Signature, String> { Ok(try_s!(self.private().sign(hash))) }
+
+    fn sign_hash_recoverable(&self, hash: &H256) -> Result<H520, String> {
+        let key_pair = try_s!(ethkey::KeyPair::from_secret_slice(&*self.private().secret));
+        key_pair.sign_hash_recoverable(hash)
+    }
+}
+
+impl Signer for ethkey::KeyPair {
+    fn public(&self) -> Public {
+        let mut uncompressed = vec![4];
+        uncompressed.extend_from_slice(&**self.public());
+        compressed_public(&uncompressed).expect("KeyPair contains a valid public key")
+    }
+
+    fn sign_hash(&self, hash: &H256) -> Result<Signature, String> {
+        let private = keys::Private {
+            secret: H256::from(self.secret().to_vec().as_slice()),
+            compressed: true,
+            ..Default::default()
+        };
+        Ok(try_s!(private.sign(hash)))
+    }
+
+    fn sign_hash_recoverable(&self, hash: &H256) -> Result<H520, String> {
+        let signature = try_s!(ethkey::sign(self.secret(), &EthH256::from_slice(&**hash)));
+        Ok(H520::from(&signature[..]))
+    }
+}
+
+fn compressed_public(public: &[u8]) -> Result<Public, String> {
+    let public = try_s!(secp256k1::PublicKey::from_slice(public));
+    Ok(Public::Compressed(H264::from(public.serialize())))
+}
+
+#[derive(Debug, Deserialize)]
+struct ExternalSignerConf {
+    socket_path: PathBuf,
+    #[serde(default)]
+    timeout: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct ExternalSignerRequest<'a> {
+    id: u64,
+    method: &'a str,
+    params: Json,
+}
+
+#[derive(Deserialize)]
+struct ExternalSignerResponse {
+    id: u64,
+    #[serde(default)]
+    result: Option<Json>,
+    #[serde(default)]
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PublicKeyResult {
+    pubkey: String,
+}
+
+#[derive(Deserialize)]
+struct SignatureResult {
+    signature: String,
+}
+
+/// The signer that keeps the keys in a separate process (see the module docs for the protocol).
+#[derive(Debug)]
+pub struct ExternalSigner {
+    socket_path: PathBuf,
+    timeout: Duration,
+    /// `None` if it's the key of the node itself.
+    coin: Option<String>,
+    public: Public,
+    next_id: AtomicU64,
+}
+
+impl ExternalSigner {
+    /// Connects to the external signer configured in MM2 and requests the public key of the `coin`.
+    pub fn new(ctx: &MmArc, coin: Option<&str>) -> Result<ExternalSigner, String> {
+        if ctx.conf["external_signer"].is_null() {
+            return ERR!("'external_signer' is not set in the config");
+        }
+        let conf: ExternalSignerConf = try_s!(json::from_value(ctx.conf["external_signer"].clone()));
+        let mut signer = ExternalSigner {
+            socket_path: conf.socket_path,
+            timeout: Duration::from_secs(conf.timeout.unwrap_or(DEFAULT_EXTERNAL_SIGNER_TIMEOUT)),
+            coin: coin.map(String::from),
+            public: Public::default(),
+            next_id: AtomicU64::new(0),
+        };
+
+        let result: PublicKeyResult = try_s!(signer.request("get_public_key", json!({ "coin": signer.coin })));
+        let public = try_s!(hex::decode(&result.pubkey));
+        if public.len() != 33 {
+            return ERR!("Expected a compressed public key, found {} bytes", public.len());
+        }
+        signer.public = try_s!(compressed_public(&public));
+        Ok(signer)
+    }
+
+    /// The compressed public key of the key kept by the signer.
+    pub fn public_key(&self) -> &Public { &self.public }
+
+    fn sign(&self, hash: &H256, recoverable: bool) -> Result<Vec<u8>, String> {
+        let params = json!({
+            "coin": self.coin,
+            "pubkey": hex::encode(&*self.public),
+            "hash": hex::encode(&**hash),
+            "recoverable": recoverable,
+        });
+        let result: SignatureResult = try_s!(self.request("sign_hash", params));
+        Ok(try_s!(hex::decode(&result.signature)))
+    }
+
+    fn request<T: DeserializeOwned>(&self, method: &str, params: Json) -> Result<T, String> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let request = ExternalSignerRequest { id, method, params };
+        let mut request = try_s!(json::to_vec(&request));
+        request.push(b'\n');
+
+        let response = try_s!(self.exchange(&request));
+        let response: ExternalSignerResponse = try_s!(json::from_str(&response));
+        if response.id != id {
+            return ERR!("Expected the response to the request {}, found {}", id, response.id);
+        }
+        match (response.result, response.error) {
+            (_, Some(error)) => ERR!("External signer error: {}", error),
+            (Some(result), None) => Ok(try_s!(json::from_value(result))),
+            (None, None) => ERR!("The response has neither 'result' nor 'error'"),
+        }
+    }
+
+    /// The signing is called by the sync code of the async withdraw, swap and activation paths,
+    /// so the runtime is asked to move the other tasks of the current worker away until the signer responds.
+    #[cfg(unix)]
+    fn exchange(&self, request: &[u8]) -> Result<String, String> {
+        tokio::task::block_in_place(|| self.exchange_blocking(request))
+    }
+
+    #[cfg(unix)]
+    fn exchange_blocking(&self, request: &[u8]) -> Result<String, String> {
+        use std::io::{BufRead, BufReader, Write};
+        use std::os::unix::net::UnixStream;
+
+        let mut stream = match UnixStream::connect(&self.socket_path) {
+            Ok(stream) => stream,
+            Err(e) => return ERR!("Error connecting to the external signer {:?}: {}", self.socket_path, e),
+        };
+        try_s!(stream.set_read_timeout(Some(self.timeout)));
+        try_s!(stream.set_write_timeout(Some(self.timeout)));
+        try_s!(stream.write_all(request));
+
+        let mut response = String::new();
+        try_s!(BufReader::new(stream).read_line(&mut response));
+        Ok(response)
+    }
+
+    #[cfg(not(unix))]
+    fn exchange(&self, _request: &[u8]) -> Result<String, String> {
+        ERR!("The external signer is only supported on Unix")
+    }
+}
+
+impl Signer for ExternalSigner {
+    fn public(&self) -> Public { Public::Compressed(H264::from(&**self.public_key())) }
+
+    fn sign_hash(&self, hash: &H256) -> Result<Signature, String> {
+        let signature = Signature::from(try_s!(self.sign(hash, false)));
+        // the signer is not trusted to sign with the right key
+        if !try_s!(self.public.verify(hash, &signature)) {
+            return ERR!("The external signer returned an invalid signature");
+        }
+        Ok(signature)
+    }
+
+    fn sign_hash_recoverable(&self, hash: &H256) -> Result<H520, String> {
+        let signature = try_s!(self.sign(hash, true));
+        if signature.len() != 65 {
+            return ERR!(
+                "Expected a 65-byte recoverable signature, found {} bytes",
+                signature.len()
+            );
+        }
+        // the signer is not trusted to sign with the right key
+        let recoverable = ethkey::Signature::from_rsv(
+            &EthH256::from_slice(&signature[..32]),
+            &EthH256::from_slice(&signature[32..64]),
+            signature[64],
+        );
+        let recovered = try_s!(ethkey::recover(&recoverable, &EthH256::from_slice(&**hash)));
+        let mut uncompressed = vec![4];
+        uncompressed.extend_from_slice(&*recovered);
+        if try_s!(compressed_public(&uncompressed)) != self.public {
+            return ERR!("The external signer returned a signature of another key");
+        }
+        Ok(H520::from(signature.as_slice()))
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use common::mm_ctx::MmCtxBuilder;
+    use common::privkey::key_pair_from_seed;
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixListener;
+    use std::thread;
+
+    /// Serves `requests_number` requests of the protocol returning the public key of the `key_pair`
+    /// and signing with the `signing_key_pair`.
+    fn spawn_stub_signer(
+        socket_path: PathBuf,
+        key_pair: KeyPair,
+        signing_key_pair: KeyPair,
+        requests_number: usize,
+    ) -> thread::JoinHandle<()> {
+        let _ = std::fs::remove_file(&socket_path);
+        let listener = UnixListener::bind(&socket_path).unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming().take(requests_number) {
+                let mut stream = stream.unwrap();
+                let mut request = String::new();
+                BufReader::new(&stream).read_line(&mut request).unwrap();
+                let request: Json = json::from_str(&request).unwrap();
+
+                let result = match request["method"].as_str().unwrap() {
+                    "get_public_key" => json!({ "pubkey": hex::encode(&*Signer::public(&key_pair)) }),
+                    "sign_hash" => {
+                        let hash = H256::from(
+                            hex::decode(request["params"]["hash"].as_str().unwrap())
+                                .unwrap()
+                                .as_slice(),
+                        );
+                        let signature = if request["params"]["recoverable"].as_bool().unwrap() {
+                            signing_key_pair.sign_hash_recoverable(&hash).unwrap().to_vec()
+                        } else {
+                            signing_key_pair.sign_hash(&hash).unwrap().to_vec()
+                        };
+                        json!({ "signature": hex::encode(signature) })
+                    },
+                    _ => panic!("Unexpected request {}", request),
+                };
+                let mut response = json::to_vec(&json!({"id": request["id"], "result": result})).unwrap();
+                response.push(b'\n');
+                stream.write_all(&response).unwrap();
+            }
+        })
+    }
+
+    #[test]
+    fn test_external_signer_signs_with_stub_signer() {
+        let seed = "spice describe gravity federal blast come thank unfair canal monkey style afraid";
+        let key_pair = key_pair_from_seed(seed).unwrap();
+        let socket_path = std::env::temp_dir().join(format!("mm2-stub-signer-{}.sock", std::process::id()));
+        let stub = spawn_stub_signer(
+            socket_path.clone(),
+            key_pair_from_seed(seed).unwrap(),
+            key_pair_from_seed(seed).unwrap(),
+            3,
+        );
+
+        let ctx = MmCtxBuilder::new()
+            .with_conf(json!({"external_signer": {"socket_path": socket_path, "timeout": 5}}))
+            .into_mm_arc();
+        let signer = ExternalSigner::new(&ctx, Some("RICK")).unwrap();
+        assert_eq!(*signer.public_key(), Signer::public(&key_pair));
+
+        let hash = H256::from([1; 32]);
+        let signature = signer.sign_hash(&hash).unwrap();
+        assert!(key_pair.public().verify(&hash, &signature).unwrap());
+
+        let recoverable = signer.sign_hash_recoverable(&hash).unwrap();
+        assert_eq!(recoverable, key_pair.sign_hash_recoverable(&hash).unwrap());
+
+        stub.join().unwrap();
+        std::fs::remove_file(&socket_path).unwrap();
+    }
+
+    #[test]
+    fn test_external_signer_rejects_signatures_of_another_key() {
+        let seed = "spice describe gravity federal blast come thank unfair canal monkey style afraid";
+        let socket_path = std::env::temp_dir().join(format!("mm2-stub-signer-other-{}.sock", std::process::id()));
+        let stub = spawn_stub_signer(
+            socket_path.clone(),
+            key_pair_from_seed(seed).unwrap(),
+            key_pair_from_seed("another seed").unwrap(),
+            3,
+        );
+
+        let ctx = MmCtxBuilder::new()
+            .with_conf(json!({"external_signer": {"socket_path": socket_path, "timeout": 5}}))
+            .into_mm_arc();
+        let signer = ExternalSigner::new(&ctx, Some("ETH")).unwrap();
+
+        let hash = H256::from([1; 32]);
+        assert!(signer.sign_hash(&hash).is_err());
+        assert!(signer.sign_hash_recoverable(&hash).is_err());
+
+        stub.join().unwrap();
+        std::fs::remove_file(&socket_path).unwrap();
+    }
+}
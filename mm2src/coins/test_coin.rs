@@ -83,6 +83,7 @@ impl SwapOps for TestCoin {
         secret_hash: &[u8],
         amount: BigDecimal,
         swap_contract_address: &Option<BytesJson>,
+        other_protocol_info: &Option<Vec<u8>>,
     ) -> TransactionFut {
         unimplemented!()
     }
@@ -94,6 +95,7 @@ impl SwapOps for TestCoin {
         secret_hash: &[u8],
         amount: BigDecimal,
         swap_contract_address: &Option<BytesJson>,
+        other_protocol_info: &Option<Vec<u8>>,
     ) -> TransactionFut {
         unimplemented!()
     }
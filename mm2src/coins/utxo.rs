@@ -19,10 +19,19 @@
 //  Copyright © 2017-2019 SuperNET. All rights reserved.
 //
 
+pub mod coin_control;
+pub mod coin_selection;
+pub mod consolidation;
+pub mod electrum_status;
+pub mod fee_bump;
+pub mod psbt;
 pub mod qtum;
+pub mod qtum_delegation;
 pub mod rpc_clients;
 pub mod slp;
+pub mod spv;
 pub mod utxo_common;
+pub mod utxo_hd_wallet;
 pub mod utxo_standard;
 
 #[cfg(not(target_arch = "wasm32"))] pub mod tx_cache;
@@ -38,6 +47,7 @@ use common::jsonrpc_client::JsonRpcError;
 use common::mm_ctx::MmArc;
 use common::mm_error::prelude::*;
 use common::mm_metrics::MetricsArc;
+use common::proxy::coin_proxy;
 use common::{now_ms, small_rng};
 use derive_more::Display;
 #[cfg(not(target_arch = "wasm32"))] use dirs::home_dir;
@@ -46,6 +56,7 @@ use futures::compat::Future01CompatExt;
 use futures::lock::{Mutex as AsyncMutex, MutexGuard as AsyncMutexGuard};
 use futures::stream::StreamExt;
 use futures01::Future;
+use keys::bip32::{ChildNumber, DerivationPath};
 use keys::bytes::Bytes;
 pub use keys::{Address, AddressFormat as UtxoAddressFormat, KeyPair, Private, Public, Secret, Type as ScriptType};
 #[cfg(test)] use mocktopus::macros::*;
@@ -65,19 +76,26 @@ use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::atomic::{AtomicBool, AtomicU64};
 use std::sync::{Arc, Mutex, Weak};
-use utxo_common::big_decimal_from_sat;
+use utxo_common::{big_decimal_from_sat, generate_transaction_with_coin_selection};
 
 pub use chain::Transaction as UtxoTx;
 
+use self::coin_control::FrozenOutPoints;
+use self::coin_selection::CoinSelectionStrategy;
 #[cfg(not(target_arch = "wasm32"))]
-use self::rpc_clients::{ConcurrentRequestMap, NativeClient, NativeClientImpl};
+use self::rpc_clients::{ConcurrentRequestMap, NativeClient, NativeClientAuth, NativeClientAuthError, NativeClientImpl};
 use self::rpc_clients::{ElectrumClient, ElectrumClientImpl, ElectrumRpcRequest, EstimateFeeMethod, EstimateFeeMode,
                         UnspentInfo, UtxoRpcClientEnum, UtxoRpcError, UtxoRpcResult};
+use self::spv::{check_spv_pow_algo, SpvConf};
+use self::utxo_hd_wallet::{UtxoHDAccount, UtxoHDAddress, DEFAULT_GAP_LIMIT};
+use super::hd_wallet::{derivation_path_from_conf, path_to_address_from_req, Bip44Chain};
+use super::signer::{ExternalSigner, Signer};
+use super::watch_only::{watch_only_coin_key, WatchOnlyCoinKey, WatchOnlyError};
 use super::{BalanceError, BalanceFut, BalanceResult, CoinTransportMetrics, CoinsContext, FeeApproxStage,
             FoundSwapTxSpend, HistorySyncState, KmdRewardsDetails, MarketCoinOps, MmCoin, NumConversError,
             NumConversResult, RpcClientType, RpcTransportEventHandler, RpcTransportEventHandlerShared, TradeFee,
             TradePreimageError, TradePreimageFut, TradePreimageResult, Transaction, TransactionDetails,
-            TransactionEnum, TransactionFut, WithdrawError, WithdrawFee, WithdrawRequest};
+            TransactionEnum, TransactionErr, TransactionFut, WithdrawError, WithdrawFee, WithdrawRequest};
 
 #[cfg(test)] pub mod utxo_tests;
 #[cfg(target_arch = "wasm32")] pub mod utxo_wasm_tests;
@@ -152,9 +170,9 @@ impl From<UtxoRpcError> for BalanceError {
 impl From<UtxoRpcError> for WithdrawError {
     fn from(e: UtxoRpcError) -> Self {
         match e {
-            UtxoRpcError::Transport(transport) | UtxoRpcError::ResponseParseError(transport) => {
-                WithdrawError::Transport(transport.to_string())
-            },
+            UtxoRpcError::Transport(transport)
+            | UtxoRpcError::ResponseParseError(transport)
+            | UtxoRpcError::Unauthorized(transport) => WithdrawError::Transport(transport.to_string()),
             UtxoRpcError::InvalidResponse(resp) => WithdrawError::Transport(resp),
             UtxoRpcError::Internal(internal) => WithdrawError::InternalError(internal),
         }
@@ -168,9 +186,9 @@ impl From<JsonRpcError> for TradePreimageError {
 impl From<UtxoRpcError> for TradePreimageError {
     fn from(e: UtxoRpcError) -> Self {
         match e {
-            UtxoRpcError::Transport(transport) | UtxoRpcError::ResponseParseError(transport) => {
-                TradePreimageError::Transport(transport.to_string())
-            },
+            UtxoRpcError::Transport(transport)
+            | UtxoRpcError::ResponseParseError(transport)
+            | UtxoRpcError::Unauthorized(transport) => TradePreimageError::Transport(transport.to_string()),
             UtxoRpcError::InvalidResponse(resp) => TradePreimageError::Transport(resp),
             UtxoRpcError::Internal(internal) => TradePreimageError::InternalError(internal),
         }
@@ -193,6 +211,9 @@ pub struct AdditionalTxData {
     pub fee_amount: u64,
     pub unused_change: Option<u64>,
     pub kmd_rewards: Option<KmdRewardsDetails>,
+    /// The HD account change address that receives the change output,
+    /// see [`utxo_common::remember_change_address`].
+    pub change_address: Option<UtxoHDAddress>,
 }
 
 /// The fee set from coins config
@@ -205,7 +226,7 @@ pub enum TxFee {
 }
 
 /// The actual "runtime" fee that is received from RPC in case of dynamic calculation
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum ActualTxFee {
     /// fee amount per Kbyte received from coin RPC
     Dynamic(u64),
@@ -341,6 +362,15 @@ impl RecentlySpentOutPoints {
         outputs.extend(replacement_unspents.into_iter().map(From::from));
         self.replace_spent_outputs_with_cache(outputs)
     }
+
+    /// Removes the outputs of the transaction that is replaced (e.g. by `bump_fee`), since they'll never exist.
+    pub fn remove_tx_outputs(&mut self, tx_hash: &H256) {
+        self.output_to_input_map
+            .retain(|output, _| &output.outpoint.hash != tx_hash);
+        for outputs in self.input_to_output_map.values_mut() {
+            outputs.retain(|output| &output.outpoint.hash != tx_hash);
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -420,6 +450,51 @@ pub struct UtxoCoinConf {
     pub mature_confirmations: u32,
     /// The number of blocks used for estimate_fee/estimate_smart_fee RPC calls
     pub estimate_fee_blocks: u32,
+    /// If set to true the inputs of the generated transactions signal the opt-in Replace-by-Fee,
+    /// so the transactions can be replaced with `bump_fee` while they're unconfirmed.
+    /// https://github.com/bitcoin/bips/blob/master/bip-0125.mediawiki
+    pub signal_rbf: bool,
+    /// The strategy choosing the inputs of the `withdraw` transactions
+    pub coin_selection: CoinSelectionStrategy,
+    /// The prefix of the messages signed with `sign_message`, e.g. "Bitcoin Signed Message:\n".
+    /// The message signing is not supported if not set.
+    pub sign_message_prefix: Option<String>,
+    /// If set, the transactions confirmed according to an Electrum server are also verified by SPV.
+    /// See the `utxo::spv` module.
+    pub spv_conf: Option<SpvConf>,
+}
+
+/// The key pair the coin is activated with.
+#[derive(Debug)]
+pub enum UtxoKeyPair {
+    KeyPair(KeyPair),
+    /// The private key is kept by the external signer.
+    External(ExternalSigner),
+    /// Only the public key is known if MM2 runs in the watch-only mode.
+    WatchOnly(Public),
+}
+
+impl From<KeyPair> for UtxoKeyPair {
+    fn from(key_pair: KeyPair) -> Self { UtxoKeyPair::KeyPair(key_pair) }
+}
+
+impl UtxoKeyPair {
+    pub fn public(&self) -> &Public {
+        match self {
+            UtxoKeyPair::KeyPair(key_pair) => key_pair.public(),
+            UtxoKeyPair::External(signer) => signer.public_key(),
+            UtxoKeyPair::WatchOnly(public) => public,
+        }
+    }
+
+    /// Returns the signer to sign the transactions with.
+    pub fn signer(&self) -> Result<&dyn Signer, WatchOnlyError> {
+        match self {
+            UtxoKeyPair::KeyPair(key_pair) => Ok(key_pair),
+            UtxoKeyPair::External(signer) => Ok(signer),
+            UtxoKeyPair::WatchOnly(_) => Err(WatchOnlyError),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -438,7 +513,7 @@ pub struct UtxoCoinFields {
     /// RPC client
     pub rpc_client: UtxoRpcClientEnum,
     /// ECDSA key pair
-    pub key_pair: KeyPair,
+    pub key_pair: UtxoKeyPair,
     /// Lock the mutex when we deal with address utxos
     pub my_address: Address,
     pub history_sync_state: Mutex<HistorySyncState>,
@@ -448,7 +523,15 @@ pub struct UtxoCoinFields {
     /// The daemon needs some time to update the listunspent list for address which makes it return already spent UTXOs
     /// This cache helps to prevent UTXO reuse in such cases
     pub recently_spent_outpoints: AsyncMutex<RecentlySpentOutPoints>,
+    /// The outputs excluded from the automatic input selection by the user
+    pub frozen_outpoints: AsyncMutex<FrozenOutPoints>,
     pub tx_hash_algo: TxHashAlgo,
+    /// The BIP44 account the coin is activated with in the HD mode.
+    /// `key_pair` and `my_address` belong to this account then.
+    pub hd_account: Option<UtxoHDAccount>,
+    /// The listeners of the Electrum notifications of `my_addresses`, see `utxo_common::balance_update_notifications`.
+    /// They are notified on the changes of the addresses derived later as well.
+    pub balance_listeners: Mutex<Vec<mpsc::UnboundedSender<()>>>,
 }
 
 #[derive(Debug, Display)]
@@ -500,6 +583,25 @@ impl UtxoCoinFields {
         }
     }
 
+    /// Returns `my_address` and the known addresses of the HD account if the coin is activated with it.
+    pub fn my_addresses(&self) -> Vec<Address> {
+        let mut addresses = vec![self.my_address.clone()];
+        if let Some(hd_account) = &self.hd_account {
+            let hd_addresses = hd_account
+                .all_addresses()
+                .into_iter()
+                .map(|hd_address| hd_address.address);
+            addresses.extend(hd_addresses.filter(|address| address.hash != self.my_address.hash));
+        }
+        addresses
+    }
+
+    pub fn is_my_address(&self, address: &Address) -> bool {
+        self.my_addresses()
+            .iter()
+            .any(|my_address| my_address.hash == address.hash)
+    }
+
     pub fn check_withdraw_address_supported(&self, addr: &Address) -> Result<(), MmError<UnsupportedAddr>> {
         let conf = &self.conf;
 
@@ -619,11 +721,12 @@ pub trait UtxoCommonOps {
         address: &Address,
     ) -> UtxoRpcResult<(Vec<UnspentInfo>, AsyncMutexGuard<'a, RecentlySpentOutPoints>)>;
 
-    /// Try to load verbose transaction from cache or try to request it from Rpc client.
-    fn get_verbose_transaction_from_cache_or_rpc(
+    /// Try to load verbose transactions from cache or try to request them from Rpc client in batches.
+    /// The transactions that couldn't be loaded or requested are absent in the result.
+    fn get_verbose_transactions_from_cache_or_rpc(
         &self,
-        txid: H256Json,
-    ) -> Box<dyn Future<Item = VerboseTransactionFrom, Error = String> + Send>;
+        txids: HashSet<H256Json>,
+    ) -> Box<dyn Future<Item = HashMap<H256Json, VerboseTransactionFrom>, Error = String> + Send>;
 
     /// Cache transaction if the coin supports `TX_CACHE` and tx height is set and not zero.
     async fn cache_transaction_if_possible(&self, tx: &RpcTransaction) -> Result<(), String>;
@@ -662,6 +765,14 @@ pub trait UtxoStandardOps {
         input_transactions: &mut HistoryUtxoTxMap,
     ) -> Result<TransactionDetails, String>;
 
+    /// Gets tx details of the already requested verbose transaction.
+    /// * `input_transactions` - the cache of the already requested transactions.
+    async fn tx_details_from_verbose_tx(
+        &self,
+        verbose_tx: RpcTransaction,
+        input_transactions: &mut HistoryUtxoTxMap,
+    ) -> Result<TransactionDetails, String>;
+
     async fn request_tx_history(&self, metrics: MetricsArc) -> RequestTxHistoryResult;
 
     /// Calculate the KMD rewards and re-calculate the transaction fee
@@ -755,7 +866,7 @@ impl From<JsonRpcError> for GenerateTxError {
 impl From<UtxoRpcError> for GenerateTxError {
     fn from(e: UtxoRpcError) -> Self {
         match e {
-            UtxoRpcError::Transport(rpc) | UtxoRpcError::ResponseParseError(rpc) => {
+            UtxoRpcError::Transport(rpc) | UtxoRpcError::ResponseParseError(rpc) | UtxoRpcError::Unauthorized(rpc) => {
                 GenerateTxError::Transport(rpc.to_string())
             },
             UtxoRpcError::InvalidResponse(error) => GenerateTxError::Transport(error),
@@ -775,6 +886,7 @@ pub enum RequestTxHistoryResult {
     UnknownError(String),
 }
 
+#[derive(Clone)]
 pub enum VerboseTransactionFrom {
     Cache(RpcTransaction),
     Rpc(RpcTransaction),
@@ -858,12 +970,20 @@ pub fn coin_daemon_data_dir(name: &str, is_asset_chain: bool) -> PathBuf {
     data_dir
 }
 
-/// Attempts to parse native daemon conf file and return rpcport, rpcuser and rpcpassword
+/// The RPC settings of the native daemon conf file.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Default)]
+struct NativeModeConf {
+    rpc_port: Option<u16>,
+    rpc_user: Option<String>,
+    rpc_password: Option<String>,
+    /// The `rpccookiefile` option, the path is relative to the network data directory if it's not absolute.
+    rpc_cookie_file: Option<String>,
+}
+
+/// Attempts to parse native daemon conf file and return rpcport, rpcuser, rpcpassword and rpccookiefile
 #[cfg(not(target_arch = "wasm32"))]
-fn read_native_mode_conf(
-    filename: &dyn AsRef<Path>,
-    network: &BlockchainNetwork,
-) -> Result<(Option<u16>, String, String), String> {
+fn read_native_mode_conf(filename: &dyn AsRef<Path>, network: &BlockchainNetwork) -> Result<NativeModeConf, String> {
     use ini::Ini;
 
     fn read_property<'a>(conf: &'a ini::Ini, network: &BlockchainNetwork, property: &str) -> Option<&'a String> {
@@ -891,15 +1011,30 @@ fn read_native_mode_conf(
         Some(port) => port.parse::<u16>().ok(),
         None => None,
     };
-    let rpc_user = try_s!(read_property(&conf, network, "rpcuser").ok_or(ERRL!(
-        "Conf file {} doesn't have the rpcuser key",
-        filename.as_ref().display()
-    )));
-    let rpc_password = try_s!(read_property(&conf, network, "rpcpassword").ok_or(ERRL!(
-        "Conf file {} doesn't have the rpcpassword key",
-        filename.as_ref().display()
-    )));
-    Ok((rpc_port, rpc_user.clone(), rpc_password.clone()))
+    let rpc_user = read_property(&conf, network, "rpcuser").cloned();
+    let rpc_password = read_property(&conf, network, "rpcpassword").cloned();
+    if rpc_user.is_some() != rpc_password.is_some() {
+        return ERR!(
+            "Conf file {} is expected to have both the rpcuser and rpcpassword keys or neither of them",
+            filename.as_ref().display()
+        );
+    }
+    Ok(NativeModeConf {
+        rpc_port,
+        rpc_user,
+        rpc_password,
+        rpc_cookie_file: read_property(&conf, network, "rpccookiefile").cloned(),
+    })
+}
+
+/// The directory the daemon keeps the data of the `network` in, e.g. the cookie file.
+#[cfg(not(target_arch = "wasm32"))]
+fn network_data_dir(data_dir: &Path, network: &BlockchainNetwork) -> PathBuf {
+    match network {
+        BlockchainNetwork::Mainnet => data_dir.to_path_buf(),
+        BlockchainNetwork::Testnet => data_dir.join("testnet3"),
+        BlockchainNetwork::Regtest => data_dir.join("regtest"),
+    }
 }
 
 /// Electrum protocol version verifier.
@@ -971,6 +1106,12 @@ impl<'a> UtxoConfBuilder<'a> {
         let mtp_block_count = self.mtp_block_count();
         let estimate_fee_mode = self.estimate_fee_mode();
         let estimate_fee_blocks = self.estimate_fee_blocks();
+        let signal_rbf = self.conf["signal_rbf"].as_bool().unwrap_or(false);
+        let coin_selection = self.coin_selection();
+        let sign_message_prefix = self.conf["sign_message_prefix"]
+            .as_str()
+            .map(|prefix| prefix.to_owned());
+        let spv_conf = try_s!(self.spv_conf(is_pos));
 
         Ok(UtxoCoinConf {
             ticker: self.ticker.to_owned(),
@@ -1000,6 +1141,10 @@ impl<'a> UtxoConfBuilder<'a> {
             estimate_fee_mode,
             mature_confirmations,
             estimate_fee_blocks,
+            signal_rbf,
+            coin_selection,
+            sign_message_prefix,
+            spv_conf,
         })
     }
 
@@ -1160,6 +1305,25 @@ impl<'a> UtxoConfBuilder<'a> {
     }
 
     fn estimate_fee_blocks(&self) -> u32 { json::from_value(self.conf["estimate_fee_blocks"].clone()).unwrap_or(1) }
+
+    fn coin_selection(&self) -> CoinSelectionStrategy {
+        json::from_value(self.conf["coin_selection"].clone()).unwrap_or_default()
+    }
+
+    fn spv_conf(&self, is_pos: bool) -> Result<Option<SpvConf>, String> {
+        if !self.conf["spv"].as_bool().unwrap_or(false) {
+            return Ok(None);
+        }
+        if is_pos || self.conf["protocol"]["type"] == "QTUM" {
+            return ERR!("SPV is supported for Proof-of-Work coins only");
+        }
+        if self.conf["spv_conf"].is_null() {
+            return ERR!("'spv_conf' with 'max_target_bits' is required if 'spv' is enabled");
+        }
+        let spv_conf: SpvConf = try_s!(json::from_value(self.conf["spv_conf"].clone()));
+        try_s!(spv_conf.max_target());
+        Ok(Some(spv_conf))
+    }
 }
 
 #[derive(Debug)]
@@ -1179,6 +1343,21 @@ impl Default for ElectrumBuilderArgs {
     }
 }
 
+/// The error of creating the native daemon RPC client on the coin activation.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Display)]
+pub enum NativeClientInitError {
+    #[display(fmt = "Native daemon authentication error: {}", _0)]
+    Auth(NativeClientAuthError),
+    #[display(fmt = "Internal error: {}", _0)]
+    Internal(String),
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl From<NativeClientAuthError> for NativeClientInitError {
+    fn from(e: NativeClientAuthError) -> Self { NativeClientInitError::Auth(e) }
+}
+
 #[async_trait]
 pub trait UtxoCoinBuilder {
     type ResultCoin;
@@ -1198,13 +1377,7 @@ pub trait UtxoCoinBuilder {
     async fn build_utxo_fields(&self) -> Result<UtxoCoinFields, String> {
         let conf = try_s!(UtxoConfBuilder::new(self.conf(), self.req(), self.ticker()).build());
 
-        let private = Private {
-            prefix: conf.wif_prefix,
-            secret: H256::from(self.priv_key()),
-            compressed: true,
-            checksum_type: conf.checksum_type,
-        };
-        let key_pair = try_s!(KeyPair::from_private(private));
+        let key_pair = try_s!(self.key_pair(&conf));
         let addr_format = try_s!(self.address_format());
         let my_address = Address {
             prefix: conf.pub_addr_prefix,
@@ -1213,15 +1386,27 @@ pub trait UtxoCoinBuilder {
             checksum_type: conf.checksum_type,
             hrp: conf.bech32_hrp.clone(),
             addr_format,
+            taproot_output_key: None,
         };
         let my_script_pubkey = output_script(&my_address, ScriptType::P2PKH).to_bytes();
         let rpc_client = try_s!(self.rpc_client().await);
+        if let (Some(_), UtxoRpcClientEnum::Electrum(electrum)) = (&conf.spv_conf, &rpc_client) {
+            try_s!(check_spv_pow_algo(electrum).await);
+        }
         let tx_fee = try_s!(self.tx_fee(&rpc_client).await);
         let decimals = try_s!(self.decimals(&rpc_client).await);
         let dust_amount = self.dust_amount();
 
+        let hd_account = try_s!(self.hd_account(&conf, &my_address.addr_format, &rpc_client).await);
+
         let initial_history_state = self.initial_history_state();
         let tx_cache_directory = Some(self.ctx().dbdir().join("TX_CACHE"));
+        let frozen_outpoints_path = self
+            .ctx()
+            .dbdir()
+            .join("FROZEN_UTXOS")
+            .join(format!("{}.json", conf.ticker));
+        let frozen_outpoints = try_s!(FrozenOutPoints::load(frozen_outpoints_path));
         let tx_hash_algo = self.tx_hash_algo();
 
         let coin = UtxoCoinFields {
@@ -1234,12 +1419,120 @@ pub trait UtxoCoinBuilder {
             history_sync_state: Mutex::new(initial_history_state),
             tx_cache_directory,
             recently_spent_outpoints: AsyncMutex::new(RecentlySpentOutPoints::new(my_script_pubkey)),
+            frozen_outpoints: AsyncMutex::new(frozen_outpoints),
             tx_fee,
             tx_hash_algo,
+            hd_account,
+            balance_listeners: Mutex::new(Vec::new()),
         };
+        utxo_common::subscribe_balance_updates(&coin, &coin.my_addresses());
         Ok(coin)
     }
 
+    /// Returns the key pair of `priv_key`, the key pair kept by the external signer
+    /// or the watched public key if MM2 runs in the watch-only mode.
+    fn key_pair(&self, conf: &UtxoCoinConf) -> Result<UtxoKeyPair, String> {
+        if self.ctx().uses_external_signer() {
+            let signer = try_s!(ExternalSigner::new(self.ctx(), Some(self.ticker())));
+            return Ok(UtxoKeyPair::External(signer));
+        }
+        if !self.ctx().is_watch_only() {
+            let private = Private {
+                prefix: conf.wif_prefix,
+                secret: H256::from(self.priv_key()),
+                compressed: true,
+                checksum_type: conf.checksum_type,
+            };
+            return Ok(try_s!(KeyPair::from_private(private)).into());
+        }
+
+        let public = match try_s!(watch_only_coin_key(self.ctx(), self.ticker())) {
+            WatchOnlyCoinKey::Pubkey(public) => public,
+            WatchOnlyCoinKey::Xpub(account_xpub) => {
+                let path_to_address = try_s!(path_to_address_from_req(self.req()));
+                let path = DerivationPath::from(vec![
+                    try_s!(ChildNumber::normal(path_to_address.is_change as u32)),
+                    try_s!(ChildNumber::normal(path_to_address.address_index)),
+                ]);
+                try_s!(account_xpub.derive_path(&path)).public()
+            },
+            WatchOnlyCoinKey::Address(_) => {
+                return ERR!(
+                    "{} can't be watched with 'address', set its 'xpub' or 'pubkey'",
+                    self.ticker()
+                )
+            },
+        };
+        Ok(UtxoKeyPair::WatchOnly(public))
+    }
+
+    /// Whether the coin should be activated with a BIP44 account in the HD mode.
+    fn supports_hd_account(&self) -> bool { true }
+
+    /// Creates the BIP44 account and discovers its used addresses if MM2 runs in the HD mode
+    /// or if the coin is watched with an `xpub`.
+    async fn hd_account(
+        &self,
+        conf: &UtxoCoinConf,
+        address_format: &UtxoAddressFormat,
+        rpc_client: &UtxoRpcClientEnum,
+    ) -> Result<Option<UtxoHDAccount>, String> {
+        let path_to_address = try_s!(path_to_address_from_req(self.req()));
+        let gap_limit: Option<u32> = try_s!(json::from_value(self.req()["gap_limit"].clone()));
+        let gap_limit = gap_limit.unwrap_or(DEFAULT_GAP_LIMIT);
+
+        let hd_account = if self.ctx().is_watch_only() {
+            let account_xpub = match try_s!(watch_only_coin_key(self.ctx(), self.ticker())) {
+                WatchOnlyCoinKey::Xpub(xpub) => xpub,
+                WatchOnlyCoinKey::Pubkey(_) | WatchOnlyCoinKey::Address(_) => return Ok(None),
+            };
+            if !self.supports_hd_account() {
+                return ERR!(
+                    "{} can't be watched with an 'xpub', use 'pubkey' instead",
+                    self.ticker()
+                );
+            }
+            let coin_path = try_s!(derivation_path_from_conf(self.ticker(), self.conf()));
+            let hd_account = try_s!(UtxoHDAccount::from_xpub(
+                account_xpub,
+                &coin_path,
+                gap_limit,
+                address_format.clone()
+            ));
+            if hd_account.account_id != path_to_address.account {
+                return ERR!(
+                    "'path_to_address' account {} doesn't match the 'xpub' account {}",
+                    path_to_address.account,
+                    hd_account.account_id
+                );
+            }
+            hd_account
+        } else {
+            let master_key = match self.ctx().hd_master_key() {
+                Some(master_key) if self.supports_hd_account() => master_key,
+                _ => return Ok(None),
+            };
+            let coin_path = try_s!(derivation_path_from_conf(self.ticker(), self.conf()));
+            try_s!(UtxoHDAccount::new(
+                master_key,
+                &coin_path,
+                path_to_address.account,
+                gap_limit,
+                address_format.clone(),
+            ))
+        };
+
+        try_s!(hd_account.discover_addresses(conf, rpc_client).await);
+        // `my_address` is derived from `path_to_address`, so it should be known too
+        let chain = if path_to_address.is_change {
+            Bip44Chain::Internal
+        } else {
+            Bip44Chain::External
+        };
+        try_s!(hd_account.extend_addresses(conf, chain, path_to_address.address_index));
+        Ok(Some(hd_account))
+    }
+
     fn address_format(&self) -> Result<UtxoAddressFormat, String> {
         let format_from_req: Option<UtxoAddressFormat> = try_s!(json::from_value(self.req()["address_format"].clone()));
         let format_from_conf = try_s!(json::from_value::<Option<UtxoAddressFormat>>(
@@ -1370,7 +1663,8 @@ pub trait UtxoCoinBuilder {
         let mut servers: Vec<ElectrumRpcRequest> = try_s!(json::from_value(self.req()["servers"].clone()));
         let mut rng = small_rng();
         servers.as_mut_slice().shuffle(&mut rng);
-        let client = ElectrumClientImpl::new(ticker, event_handlers);
+        let proxy = try_s!(coin_proxy(self.conf(), &ctx.conf));
+        let client = ElectrumClientImpl::new(ticker, event_handlers).with_proxy(proxy);
         for server in servers.iter() {
             match client.add_server(server).await {
                 Ok(_) => (),
@@ -1406,19 +1700,34 @@ pub trait UtxoCoinBuilder {
         Ok(ElectrumClient(client))
     }
 
+    /// The `rpc_url`, `rpc_user` and `rpc_password` or `rpc_cookie_path` of the `enable` request
+    /// take precedence over the settings of the native daemon config.
+    /// The cookie file is used if the static credentials are not set.
     #[cfg(not(target_arch = "wasm32"))]
-    fn native_client(&self) -> Result<NativeClient, String> {
-        use base64::{encode_config as base64_encode, URL_SAFE};
-
-        let native_conf_path = try_s!(self.confpath());
-        let network = try_s!(self.network());
-        let (rpc_port, rpc_user, rpc_password) = try_s!(read_native_mode_conf(&native_conf_path, &network));
-        let auth_str = fomat!((rpc_user)":"(rpc_password));
-        let rpc_port = match rpc_port {
-            Some(p) => p,
-            None => try_s!(self.conf()["rpcport"].as_u64().ok_or(ERRL!(
-                "Rpc port is not set neither in `coins` file nor in native daemon config"
-            ))) as u16,
+    fn native_client(&self) -> MmResult<NativeClient, NativeClientInitError> {
+        let native_conf_path = self.confpath().map_to_mm(NativeClientInitError::Internal)?;
+        let network = self.network().map_to_mm(NativeClientInitError::Internal)?;
+        // the daemon may run without the config, e.g. with the cookie authentication
+        let native_conf = if native_conf_path.exists() {
+            read_native_mode_conf(&native_conf_path, &network).map_to_mm(NativeClientInitError::Internal)?
+        } else {
+            NativeModeConf::default()
+        };
+        let auth = self.native_client_auth(&native_conf_path, &native_conf, &network)?;
+
+        let uri = match self.req()["rpc_url"].as_str() {
+            Some(url) => url.to_owned(),
+            None => {
+                let rpc_port = match native_conf.rpc_port {
+                    Some(p) => p,
+                    None => self.conf()["rpcport"].as_u64().or_mm_err(|| {
+                        NativeClientInitError::Internal(
+                            "Rpc port is not set neither in `coins` file nor in native daemon config".to_owned(),
+                        )
+                    })? as u16,
+                };
+                fomat!("http://127.0.0.1:"(rpc_port))
+            },
         };
 
         let ctx = self.ctx();
@@ -1429,8 +1738,8 @@ pub trait UtxoCoinBuilder {
             ];
         let client = Arc::new(NativeClientImpl {
             coin_ticker,
-            uri: fomat!("http://127.0.0.1:"(rpc_port)),
-            auth: format!("Basic {}", base64_encode(&auth_str, URL_SAFE)),
+            uri,
+            auth: Arc::new(auth),
             event_handlers,
             request_id: 0u64.into(),
             list_unspent_concurrent_map: ConcurrentRequestMap::new(),
@@ -1439,6 +1748,36 @@ pub trait UtxoCoinBuilder {
         Ok(NativeClient(client))
     }
 
+    #[cfg(not(target_arch = "wasm32"))]
+    fn native_client_auth(
+        &self,
+        native_conf_path: &Path,
+        native_conf: &NativeModeConf,
+        network: &BlockchainNetwork,
+    ) -> Result<NativeClientAuth, NativeClientAuthError> {
+        let req = self.req();
+        match (req["rpc_user"].as_str(), req["rpc_password"].as_str()) {
+            (Some(user), Some(password)) => return Ok(NativeClientAuth::basic(user, password)),
+            (None, None) => (),
+            _ => return Err(NativeClientAuthError::IncompleteCredentials),
+        }
+        if let Some(cookie_path) = req["rpc_cookie_path"].as_str() {
+            return NativeClientAuth::cookie(cookie_path.into());
+        }
+        if let (Some(user), Some(password)) = (&native_conf.rpc_user, &native_conf.rpc_password) {
+            return Ok(NativeClientAuth::basic(user, password));
+        }
+
+        // the daemon config is expected to be in the data directory
+        let data_dir = native_conf_path.parent().unwrap_or_else(|| Path::new(""));
+        let network_dir = network_data_dir(data_dir, network);
+        let cookie_path = match native_conf.rpc_cookie_file {
+            Some(ref cookie_file) => network_dir.join(cookie_file),
+            None => network_dir.join(".cookie"),
+        };
+        NativeClientAuth::cookie(cookie_path)
+    }
+
     #[cfg(not(target_arch = "wasm32"))]
     fn confpath(&self) -> Result<PathBuf, String> {
         let conf = self.conf();
@@ -1557,6 +1896,8 @@ async fn check_electrum_server_version(
         match client.set_protocol_version(&electrum_addr, actual_version).await {
             Ok(()) => {
                 log!("Use protocol version " [actual_version] " for Electrum " [electrum_addr]);
+                // the subscriptions are lost on reconnection
+                client.resubscribe(&electrum_addr).await;
             },
             Err(e) => {
                 log!("Error on set protocol_version "[e]);
@@ -1799,7 +2140,7 @@ pub fn sat_from_big_decimal(amount: &BigDecimal, decimals: u8) -> NumConversResu
 
 pub(crate) fn sign_tx(
     unsigned: TransactionInputSigner,
-    key_pair: &KeyPair,
+    key_pair: &dyn Signer,
     prev_script: Script,
     signature_version: SignatureVersion,
     fork_id: u32,
@@ -1832,10 +2173,57 @@ pub(crate) fn sign_tx(
         },
     }
 
-    Ok(UtxoTx {
+    Ok(signed_tx_from_inputs(unsigned, signed_inputs))
+}
+
+/// Signs every input of the `unsigned` transaction with the corresponding key pair of `input_key_pairs`.
+/// Each input is expected to spend a P2PKH (or P2WPKH if `signature_version` is `WitnessV0`) output.
+pub(crate) fn sign_tx_with_key_pairs(
+    unsigned: TransactionInputSigner,
+    input_key_pairs: &[KeyPair],
+    signature_version: SignatureVersion,
+    fork_id: u32,
+) -> Result<UtxoTx, String> {
+    if unsigned.inputs.len() != input_key_pairs.len() {
+        return ERR!(
+            "Expected {} key pairs, found {}",
+            unsigned.inputs.len(),
+            input_key_pairs.len()
+        );
+    }
+
+    let mut signed_inputs = Vec::with_capacity(unsigned.inputs.len());
+    for (i, key_pair) in input_key_pairs.iter().enumerate() {
+        let prev_script = Builder::build_p2pkh(&key_pair.public().address_hash());
+        let signed_input = match signature_version {
+            SignatureVersion::WitnessV0 => try_s!(p2wpkh_spend(
+                &unsigned,
+                i,
+                key_pair,
+                &prev_script,
+                signature_version,
+                fork_id
+            )),
+            _ => try_s!(p2pkh_spend(
+                &unsigned,
+                i,
+                key_pair,
+                &prev_script,
+                signature_version,
+                fork_id
+            )),
+        };
+        signed_inputs.push(signed_input);
+    }
+
+    Ok(signed_tx_from_inputs(unsigned, signed_inputs))
+}
+
+fn signed_tx_from_inputs(unsigned: TransactionInputSigner, signed_inputs: Vec<TransactionInput>) -> UtxoTx {
+    UtxoTx {
         inputs: signed_inputs,
         n_time: unsigned.n_time,
-        outputs: unsigned.outputs.clone(),
+        outputs: unsigned.outputs,
         version: unsigned.version,
         overwintered: unsigned.overwintered,
         lock_time: unsigned.lock_time,
@@ -1851,7 +2239,7 @@ pub(crate) fn sign_tx(
         zcash: unsigned.zcash,
         str_d_zeel: unsigned.str_d_zeel,
         tx_hash_algo: unsigned.hash_algo.into(),
-    })
+    }
 }
 
 async fn send_outputs_from_my_address_impl<T>(coin: T, outputs: Vec<TransactionOutput>) -> Result<UtxoTx, String>
@@ -1859,23 +2247,45 @@ where
     T: AsRef<UtxoCoinFields> + UtxoCommonOps,
 {
     let (unspents, recently_sent_txs) = try_s!(coin.list_unspent_ordered(&coin.as_ref().my_address).await);
-    generate_and_send_tx(&coin, unspents, outputs, FeePolicy::SendExact, recently_sent_txs).await
-}
-
-/// Generates and sends tx using unspents and outputs adding new record to the recently_spent in case of success
+    generate_and_send_tx(
+        &coin,
+        Vec::new(),
+        unspents,
+        outputs,
+        FeePolicy::SendExact,
+        None,
+        recently_sent_txs,
+    )
+    .await
+}
+
+/// Generates and sends tx using unspents and outputs adding new record to the recently_spent in case of success.
+/// The `must_spend` unspents are spent first regardless of the other unspents, see [`generate_transaction_with_coin_selection`].
+/// The current fee rate of the coin is requested if `fee` is not set.
 async fn generate_and_send_tx<T>(
     coin: &T,
+    must_spend: Vec<UnspentInfo>,
     unspents: Vec<UnspentInfo>,
     outputs: Vec<TransactionOutput>,
     fee_policy: FeePolicy,
+    fee: Option<ActualTxFee>,
     mut recently_spent: AsyncMutexGuard<'_, RecentlySpentOutPoints>,
 ) -> Result<UtxoTx, String>
 where
     T: AsRef<UtxoCoinFields> + UtxoCommonOps,
 {
-    let (unsigned, _) = try_s!(
-        coin.generate_transaction(unspents, outputs, fee_policy, None, None)
-            .await
+    let (unsigned, data) = try_s!(
+        generate_transaction_with_coin_selection(
+            coin,
+            must_spend,
+            unspents,
+            outputs,
+            fee_policy,
+            fee,
+            None,
+            CoinSelectionStrategy::SmallestFirst,
+        )
+        .await
     );
 
     let spent_unspents = unsigned
@@ -1896,7 +2306,7 @@ where
     let prev_script = Builder::build_p2pkh(&coin.as_ref().my_address.hash);
     let signed = try_s!(sign_tx(
         unsigned,
-        &coin.as_ref().key_pair,
+        try_s!(coin.as_ref().key_pair.signer()),
         prev_script,
         signature_version,
         coin.as_ref().conf.fork_id
@@ -1912,6 +2322,7 @@ where
     );
 
     recently_spent.add_spent(spent_unspents, signed.hash(), signed.outputs.clone());
+    try_s!(utxo_common::remember_change_address(coin.as_ref(), &data));
 
     Ok(signed)
 }
@@ -1920,7 +2331,7 @@ where
 pub fn p2pkh_spend(
     signer: &TransactionInputSigner,
     input_index: usize,
-    key_pair: &KeyPair,
+    key_pair: &dyn Signer,
     prev_script: &Script,
     signature_version: SignatureVersion,
     fork_id: u32,
@@ -1956,11 +2367,11 @@ pub fn p2pkh_spend(
 pub fn p2pk_spend(
     signer: &TransactionInputSigner,
     input_index: usize,
-    key_pair: &KeyPair,
+    key_pair: &dyn Signer,
     signature_version: SignatureVersion,
     fork_id: u32,
 ) -> Result<TransactionInput, String> {
-    let script = Builder::build_p2pk(key_pair.public());
+    let script = Builder::build_p2pk(&key_pair.public());
     let sighash_type = 1 | fork_id;
     let sighash = signer.signature_hash(
         input_index,
@@ -1984,7 +2395,7 @@ pub fn p2pk_spend(
 fn p2wpkh_spend(
     signer: &TransactionInputSigner,
     input_index: usize,
-    key_pair: &KeyPair,
+    key_pair: &dyn Signer,
     prev_script: &Script,
     signature_version: SignatureVersion,
     fork_id: u32,
@@ -2017,7 +2428,7 @@ fn p2wpkh_spend(
     })
 }
 
-fn script_sig_with_pub(message: &H256, key_pair: &KeyPair, fork_id: u32) -> Result<Bytes, String> {
+fn script_sig_with_pub(message: &H256, key_pair: &dyn Signer, fork_id: u32) -> Result<Bytes, String> {
     let sig_script = try_s!(script_sig(message, key_pair, fork_id));
 
     let builder = Builder::default();
@@ -2028,8 +2439,8 @@ fn script_sig_with_pub(message: &H256, key_pair: &KeyPair, fork_id: u32) -> Resu
         .into_bytes())
 }
 
-fn script_sig(message: &H256, key_pair: &KeyPair, fork_id: u32) -> Result<Bytes, String> {
-    let signature = try_s!(key_pair.private().sign(message));
+fn script_sig(message: &H256, key_pair: &dyn Signer, fork_id: u32) -> Result<Bytes, String> {
+    let signature = try_s!(key_pair.sign_hash(message));
 
     let mut sig_script = Bytes::default();
     sig_script.append(&mut Bytes::from((*signature).to_vec()));
@@ -2040,12 +2451,17 @@ fn script_sig(message: &H256, key_pair: &KeyPair, fork_id: u32) -> Result<Bytes,
 }
 
 pub fn output_script(address: &Address, script_type: ScriptType) -> Script {
+    if let Some(ref output_key) = address.taproot_output_key {
+        return Builder::build_p2tr(output_key);
+    }
     match address.addr_format {
         UtxoAddressFormat::Segwit => Builder::build_p2wpkh(&address.hash),
         _ => match script_type {
             ScriptType::P2PKH => Builder::build_p2pkh(&address.hash),
             ScriptType::P2SH => Builder::build_p2sh(&address.hash),
             ScriptType::P2WPKH => Builder::build_p2wpkh(&address.hash),
+            // every P2TR address has the output key, the address hash can't be paid with P2TR
+            ScriptType::P2TR => unreachable!("P2TR address without the taproot output key"),
         },
     }
 }
@@ -2069,6 +2485,7 @@ pub fn address_by_conf_and_pubkey_str(
         checksum_type: utxo_conf.checksum_type,
         hrp: utxo_conf.bech32_hrp,
         addr_format,
+        taproot_output_key: None,
     };
     address.display_address()
 }
@@ -0,0 +1,301 @@
+//! Coin control of the UTXO coins.
+//!
+//! `list_unspents` shows the unspent outputs of our addresses with their maturity and confirmations,
+//! so the user can pick the `inputs` of the withdraw explicitly.
+//!
+//! `freeze_utxos` adds the outputs to the frozen set persisted in the `FROZEN_UTXOS` db directory.
+//! The frozen outputs are never spent by [`super::utxo_common::generate_transaction`],
+//! so neither withdraws nor swap payments touch them until `unfreeze_utxos` is called.
+//! This allows to keep apart e.g. the dust sent by a deanonymization attacker.
+//!
+//! The outputs carrying the SLP tokens enabled on top of the coin are frozen in the memory too,
+//! so the platform coin doesn't burn the tokens spending them as plain outputs.
+
+use super::rpc_clients::{UtxoRpcClientOps, UtxoRpcError};
+use super::utxo_common::{big_decimal_from_sat_unsigned, list_unspent_ordered};
+use super::{UtxoCoinFields, UtxoCommonOps};
+use crate::{lp_coinfind_or_err, CoinFindError, MmCoinEnum, TxOutPoint};
+use bigdecimal::BigDecimal;
+use chain::OutPoint;
+use common::mm_ctx::MmArc;
+use common::mm_error::prelude::*;
+use common::HttpStatusCode;
+use derive_more::Display;
+use futures::compat::Future01CompatExt;
+use http::StatusCode;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+pub type ListUnspentsResult = Result<ListUnspentsResponse, MmError<CoinControlError>>;
+pub type FrozenUtxosResult = Result<FrozenUtxosResponse, MmError<CoinControlError>>;
+
+#[derive(Deserialize)]
+pub struct ListUnspentsRequest {
+    coin: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ListUnspentsResponse {
+    pub unspents: Vec<UnspentEntry>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UnspentEntry {
+    #[serde(flatten)]
+    pub outpoint: TxOutPoint,
+    /// The address the output belongs to, one of the HD account addresses in the HD mode.
+    pub address: String,
+    pub value: BigDecimal,
+    /// The block height the transaction mined in. None if the transaction is not mined yet.
+    pub height: Option<u64>,
+    pub confirmations: u64,
+    /// Whether the output can be spent. Immature are the coinbase outputs having not enough confirmations.
+    pub is_mature: bool,
+    /// Whether the output is excluded from the automatic input selection.
+    pub is_frozen: bool,
+}
+
+#[derive(Deserialize)]
+pub struct FrozenUtxosRequest {
+    coin: String,
+    outpoints: Vec<TxOutPoint>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FrozenUtxosResponse {
+    /// All the frozen outputs of the coin after the request is applied.
+    pub frozen: Vec<TxOutPoint>,
+}
+
+#[derive(Debug, Display, Serialize, SerializeErrorType)]
+#[serde(tag = "error_type", content = "error_data")]
+pub enum CoinControlError {
+    #[display(fmt = "No such coin {}", coin)]
+    NoSuchCoin { coin: String },
+    #[display(fmt = "{} doesn't support the coin control", coin)]
+    CoinDoesNotSupportCoinControl { coin: String },
+    #[display(fmt = "Transport error: {}", _0)]
+    Transport(String),
+    #[display(fmt = "Internal error: {}", _0)]
+    InternalError(String),
+}
+
+impl HttpStatusCode for CoinControlError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            CoinControlError::NoSuchCoin { .. } | CoinControlError::CoinDoesNotSupportCoinControl { .. } => {
+                StatusCode::BAD_REQUEST
+            },
+            CoinControlError::Transport(_) | CoinControlError::InternalError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl From<CoinFindError> for CoinControlError {
+    fn from(e: CoinFindError) -> Self {
+        match e {
+            CoinFindError::NoSuchCoin { coin } => CoinControlError::NoSuchCoin { coin },
+        }
+    }
+}
+
+impl From<UtxoRpcError> for CoinControlError {
+    fn from(e: UtxoRpcError) -> Self {
+        match e {
+            UtxoRpcError::Transport(rpc) | UtxoRpcError::ResponseParseError(rpc) | UtxoRpcError::Unauthorized(rpc) => {
+                CoinControlError::Transport(rpc.to_string())
+            },
+            UtxoRpcError::InvalidResponse(error) => CoinControlError::Transport(error),
+            UtxoRpcError::Internal(error) => CoinControlError::InternalError(error),
+        }
+    }
+}
+
+impl From<OutPoint> for TxOutPoint {
+    fn from(outpoint: OutPoint) -> Self {
+        TxOutPoint {
+            tx_hash: outpoint.hash.reversed().into(),
+            index: outpoint.index,
+        }
+    }
+}
+
+impl From<TxOutPoint> for OutPoint {
+    fn from(outpoint: TxOutPoint) -> Self {
+        OutPoint {
+            hash: outpoint.tx_hash.reversed().into(),
+            index: outpoint.index,
+        }
+    }
+}
+
+/// The set of the outputs that must not be spent automatically.
+/// It's stored in the JSON file if the `file_path` is set.
+#[derive(Debug, Default)]
+pub struct FrozenOutPoints {
+    file_path: Option<PathBuf>,
+    outpoints: HashSet<OutPoint>,
+    /// The outputs carrying the tokens by the ticker of the enabled token.
+    /// They're not stored, the token freezes them again each time it lists the unspents.
+    token_outpoints: HashMap<String, HashSet<OutPoint>>,
+}
+
+impl FrozenOutPoints {
+    /// Loads the frozen outputs from the file. The set is empty if the file doesn't exist yet.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load(file_path: PathBuf) -> Result<FrozenOutPoints, String> {
+        let content = try_s!(common::safe_slurp(&file_path));
+        let outpoints = if content.is_empty() {
+            HashSet::new()
+        } else {
+            let outpoints: Vec<TxOutPoint> = try_s!(serde_json::from_slice(&content));
+            outpoints.into_iter().map(OutPoint::from).collect()
+        };
+        Ok(FrozenOutPoints {
+            file_path: Some(file_path),
+            outpoints,
+            token_outpoints: HashMap::new(),
+        })
+    }
+
+    /// The frozen outputs are kept in the memory only in the browser.
+    #[cfg(target_arch = "wasm32")]
+    pub fn load(_file_path: PathBuf) -> Result<FrozenOutPoints, String> { Ok(FrozenOutPoints::default()) }
+
+    pub fn contains(&self, outpoint: &OutPoint) -> bool {
+        self.outpoints.contains(outpoint) || self.token_outpoints.values().any(|frozen| frozen.contains(outpoint))
+    }
+
+    pub fn to_vec(&self) -> Vec<TxOutPoint> { self.outpoints.iter().cloned().map(TxOutPoint::from).collect() }
+
+    pub fn freeze(&mut self, outpoints: impl IntoIterator<Item = OutPoint>) -> Result<(), String> {
+        let mut updated = self.outpoints.clone();
+        updated.extend(outpoints);
+        try_s!(self.save(&updated));
+        self.outpoints = updated;
+        Ok(())
+    }
+
+    pub fn unfreeze(&mut self, outpoints: impl IntoIterator<Item = OutPoint>) -> Result<(), String> {
+        let mut updated = self.outpoints.clone();
+        for outpoint in outpoints {
+            updated.remove(&outpoint);
+        }
+        try_s!(self.save(&updated));
+        self.outpoints = updated;
+        Ok(())
+    }
+
+    /// Replaces the outputs carrying the `token`.
+    pub fn freeze_token_outpoints(&mut self, token: &str, outpoints: impl IntoIterator<Item = OutPoint>) {
+        self.token_outpoints
+            .insert(token.to_owned(), outpoints.into_iter().collect());
+    }
+
+    /// Unfreezes the outputs carrying the `token` once it's disabled.
+    pub fn unfreeze_token_outpoints(&mut self, token: &str) { self.token_outpoints.remove(token); }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn save(&self, outpoints: &HashSet<OutPoint>) -> Result<(), String> {
+        let file_path = match self.file_path {
+            Some(ref file_path) => file_path,
+            None => return Ok(()),
+        };
+        if let Some(dir) = file_path.parent() {
+            try_s!(std::fs::create_dir_all(dir));
+        }
+        let outpoints: Vec<TxOutPoint> = outpoints.iter().cloned().map(TxOutPoint::from).collect();
+        let content = try_s!(serde_json::to_vec(&outpoints));
+        let tmp_path = format!("{}.tmp", file_path.display());
+        try_s!(std::fs::write(&tmp_path, content));
+        try_s!(std::fs::rename(tmp_path, file_path));
+        Ok(())
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn save(&self, _outpoints: &HashSet<OutPoint>) -> Result<(), String> { Ok(()) }
+}
+
+/// Returns the unspent outputs of our address including the immature and the frozen ones.
+pub async fn list_unspents(ctx: MmArc, req: ListUnspentsRequest) -> ListUnspentsResult {
+    match lp_coinfind_or_err(&ctx, &req.coin).await? {
+        MmCoinEnum::UtxoCoin(utxo) => list_utxo_unspents(&utxo).await,
+        MmCoinEnum::QtumCoin(qtum) => list_utxo_unspents(&qtum).await,
+        _ => MmError::err(CoinControlError::CoinDoesNotSupportCoinControl { coin: req.coin }),
+    }
+}
+
+/// Excludes the given outputs from the automatic input selection.
+pub async fn freeze_utxos(ctx: MmArc, req: FrozenUtxosRequest) -> FrozenUtxosResult {
+    let coin = lp_coinfind_or_err(&ctx, &req.coin).await?;
+    let mut frozen = utxo_fields(&coin, &req.coin)?.frozen_outpoints.lock().await;
+    frozen
+        .freeze(req.outpoints.into_iter().map(OutPoint::from))
+        .map_to_mm(CoinControlError::InternalError)?;
+    Ok(FrozenUtxosResponse {
+        frozen: frozen.to_vec(),
+    })
+}
+
+/// Returns the given outputs to the automatic input selection.
+pub async fn unfreeze_utxos(ctx: MmArc, req: FrozenUtxosRequest) -> FrozenUtxosResult {
+    let coin = lp_coinfind_or_err(&ctx, &req.coin).await?;
+    let mut frozen = utxo_fields(&coin, &req.coin)?.frozen_outpoints.lock().await;
+    frozen
+        .unfreeze(req.outpoints.into_iter().map(OutPoint::from))
+        .map_to_mm(CoinControlError::InternalError)?;
+    Ok(FrozenUtxosResponse {
+        frozen: frozen.to_vec(),
+    })
+}
+
+pub async fn list_utxo_unspents<T>(coin: &T) -> ListUnspentsResult
+where
+    T: AsRef<UtxoCoinFields> + UtxoCommonOps,
+{
+    let utxo = coin.as_ref();
+    let block_count = utxo.rpc_client.get_block_count().compat().await?;
+
+    let mut entries = Vec::new();
+    for address in utxo.my_addresses() {
+        let (unspents, recently_spent) = list_unspent_ordered(coin, &address).await?;
+        // `ordered_mature_unspents` locks the recently spent outputs too
+        drop(recently_spent);
+        let mature: HashSet<OutPoint> = coin
+            .ordered_mature_unspents(&address)
+            .await?
+            .0
+            .into_iter()
+            .map(|unspent| unspent.outpoint)
+            .collect();
+        let display_address = address.display_address().map_to_mm(CoinControlError::InternalError)?;
+
+        let frozen = utxo.frozen_outpoints.lock().await;
+        entries.extend(unspents.into_iter().map(|unspent| {
+            let confirmations = match unspent.height {
+                Some(height) if height > 0 && height <= block_count => block_count - height + 1,
+                _ => 0,
+            };
+            UnspentEntry {
+                is_mature: mature.contains(&unspent.outpoint),
+                is_frozen: frozen.contains(&unspent.outpoint),
+                address: display_address.clone(),
+                value: big_decimal_from_sat_unsigned(unspent.value, utxo.decimals),
+                height: unspent.height,
+                confirmations,
+                outpoint: unspent.outpoint.into(),
+            }
+        }));
+    }
+    Ok(ListUnspentsResponse { unspents: entries })
+}
+
+fn utxo_fields<'a>(coin: &'a MmCoinEnum, ticker: &str) -> Result<&'a UtxoCoinFields, MmError<CoinControlError>> {
+    match coin {
+        MmCoinEnum::UtxoCoin(utxo) => Ok(utxo.as_ref()),
+        MmCoinEnum::QtumCoin(qtum) => Ok(qtum.as_ref()),
+        _ => MmError::err(CoinControlError::CoinDoesNotSupportCoinControl {
+            coin: ticker.to_owned(),
+        }),
+    }
+}
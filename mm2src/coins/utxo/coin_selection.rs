@@ -0,0 +1,226 @@
+//! The strategies choosing the unspent outputs spent by [`super::utxo_common::generate_transaction_with_coin_selection`].
+//!
+//! `generate_transaction_with_coin_selection` accumulates the unspents in the order returned by the [`CoinSelector`]
+//! until their sum covers the outputs and the fee, but not less than the number the selector asks to spend.
+//! The strategy is applied to the `withdraw` transactions only, it's set by the `coin_selection` coin config field
+//! and can be overridden by the `withdraw` request. The other transactions spend the unspents in the given order.
+
+use super::rpc_clients::UnspentInfo;
+use super::KILO_BYTE;
+
+/// The vsize of P2PKH input spending the output of the compressed public key.
+const P2PKH_INPUT_V_SIZE: u64 = 148;
+/// The vsize of P2WPKH input.
+const P2WPKH_INPUT_V_SIZE: u64 = 68;
+/// The limit of the search iterations of the branch and bound algorithm, the same as Bitcoin Core uses.
+const BNB_TOTAL_TRIES: usize = 100_000;
+/// The consolidation doesn't spend more unspents at once to keep the transaction standard.
+const MAX_CONSOLIDATION_INPUTS: usize = 500;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CoinSelectionStrategy {
+    /// Accumulates the unspents in the given order that is from the smallest ones
+    /// as `list_unspent_ordered` returns them. This is the default behaviour.
+    SmallestFirst,
+    /// Accumulates the unspents starting from the largest ones, so the transaction has less inputs.
+    LargestFirst,
+    /// Looks for the unspents that cover the target without the change output (Bitcoin Core-like branch and bound).
+    /// Falls back to the [`CoinSelectionStrategy::SmallestFirst`] if there is no such combination.
+    BranchAndBound,
+    /// Spends as many small unspents as possible merging them into the change output.
+    /// Makes sense when the fee is low.
+    Consolidate,
+}
+
+impl Default for CoinSelectionStrategy {
+    fn default() -> Self { CoinSelectionStrategy::SmallestFirst }
+}
+
+impl CoinSelectionStrategy {
+    pub fn selector(&self) -> Box<dyn CoinSelector + Send + Sync> {
+        match self {
+            CoinSelectionStrategy::SmallestFirst => Box::new(SmallestFirstSelector),
+            CoinSelectionStrategy::LargestFirst => Box::new(LargestFirstSelector),
+            CoinSelectionStrategy::BranchAndBound => Box::new(BranchAndBoundSelector),
+            CoinSelectionStrategy::Consolidate => Box::new(ConsolidationSelector),
+        }
+    }
+}
+
+/// What the selected unspents should cover.
+#[derive(Debug)]
+pub struct CoinSelectionParams {
+    /// The sum of the outputs and the gas fee.
+    pub target: u64,
+    /// The fee of the transaction without inputs.
+    pub base_fee: u64,
+    /// The fee paid for every input.
+    pub input_fee: u64,
+    /// The change that doesn't exceed this threshold goes to the miners instead of the change output.
+    pub change_threshold: u64,
+}
+
+impl CoinSelectionParams {
+    /// Approximates the fees by the given fee rate per kilobyte.
+    pub fn new(target: u64, base_v_size: u64, is_segwit: bool, fee_per_kb: u64, change_threshold: u64) -> Self {
+        let input_v_size = if is_segwit {
+            P2WPKH_INPUT_V_SIZE
+        } else {
+            P2PKH_INPUT_V_SIZE
+        };
+        CoinSelectionParams {
+            target,
+            base_fee: fee_per_kb * base_v_size / KILO_BYTE,
+            input_fee: fee_per_kb * input_v_size / KILO_BYTE,
+            change_threshold,
+        }
+    }
+
+    fn effective_value(&self, unspent: &UnspentInfo) -> Option<u64> { unspent.value.checked_sub(self.input_fee) }
+}
+
+#[derive(Debug)]
+pub struct CoinSelection {
+    /// The unspents in the order they should be accumulated.
+    pub utxos: Vec<UnspentInfo>,
+    /// The number of the first `utxos` that are spent even if a part of them is enough.
+    pub must_spend: usize,
+}
+
+impl CoinSelection {
+    fn accumulate(utxos: Vec<UnspentInfo>) -> Self { CoinSelection { utxos, must_spend: 0 } }
+}
+
+pub trait CoinSelector {
+    fn select_coins(&self, utxos: Vec<UnspentInfo>, params: &CoinSelectionParams) -> CoinSelection;
+}
+
+pub struct SmallestFirstSelector;
+
+impl CoinSelector for SmallestFirstSelector {
+    fn select_coins(&self, utxos: Vec<UnspentInfo>, _params: &CoinSelectionParams) -> CoinSelection {
+        CoinSelection::accumulate(utxos)
+    }
+}
+
+pub struct LargestFirstSelector;
+
+impl CoinSelector for LargestFirstSelector {
+    fn select_coins(&self, mut utxos: Vec<UnspentInfo>, _params: &CoinSelectionParams) -> CoinSelection {
+        utxos.sort_by(|a, b| b.value.cmp(&a.value));
+        CoinSelection::accumulate(utxos)
+    }
+}
+
+pub struct BranchAndBoundSelector;
+
+impl CoinSelector for BranchAndBoundSelector {
+    fn select_coins(&self, mut utxos: Vec<UnspentInfo>, params: &CoinSelectionParams) -> CoinSelection {
+        utxos.sort_by(|a, b| b.value.cmp(&a.value));
+        let effective_values: Vec<u64> = utxos
+            .iter()
+            .map(|utxo| params.effective_value(utxo).unwrap_or(0))
+            .collect();
+        let target = params.target + params.base_fee;
+        let upper_bound = target + params.change_threshold;
+
+        match branch_and_bound(&effective_values, target, upper_bound) {
+            // the rest of the unspents is kept in case the fee estimation is not precise
+            Some(selected) => {
+                let mut ordered: Vec<UnspentInfo> = selected.iter().map(|idx| utxos[*idx].clone()).collect();
+                ordered.extend(
+                    utxos
+                        .into_iter()
+                        .enumerate()
+                        .filter(|(idx, _)| !selected.contains(idx))
+                        .map(|(_, utxo)| utxo),
+                );
+                CoinSelection::accumulate(ordered)
+            },
+            None => {
+                utxos.sort_by_key(|utxo| utxo.value);
+                CoinSelection::accumulate(utxos)
+            },
+        }
+    }
+}
+
+/// Returns the indexes of the `values` which sum is within `[target, upper_bound]` and is the closest to `target`.
+/// `values` are expected to be sorted in the descending order.
+fn branch_and_bound(values: &[u64], target: u64, upper_bound: u64) -> Option<Vec<usize>> {
+    struct Search<'a> {
+        values: &'a [u64],
+        target: u64,
+        upper_bound: u64,
+        tries: usize,
+        selection: Vec<usize>,
+        best: Option<(u64, Vec<usize>)>,
+    }
+
+    impl<'a> Search<'a> {
+        fn run(&mut self, idx: usize, current: u64, remaining: u64) {
+            if self.tries == 0 || current > self.upper_bound {
+                return;
+            }
+            self.tries -= 1;
+            if current >= self.target {
+                let excess = current - self.target;
+                if self
+                    .best
+                    .as_ref()
+                    .map_or(true, |(best_excess, _)| excess < *best_excess)
+                {
+                    self.best = Some((excess, self.selection.clone()));
+                }
+                // the exact match can't be improved
+                if excess == 0 {
+                    self.tries = 0;
+                }
+                return;
+            }
+            if idx == self.values.len() || current + remaining < self.target {
+                return;
+            }
+
+            let value = self.values[idx];
+            // the unspent that doesn't cover the fee of its spending is never worth to be included
+            if value > 0 {
+                self.selection.push(idx);
+                self.run(idx + 1, current + value, remaining - value);
+                self.selection.pop();
+            }
+            self.run(idx + 1, current, remaining - value);
+        }
+    }
+
+    let mut search = Search {
+        values,
+        target,
+        upper_bound,
+        tries: BNB_TOTAL_TRIES,
+        selection: Vec::new(),
+        best: None,
+    };
+    search.run(0, 0, values.iter().sum());
+    search.best.map(|(_, selection)| selection)
+}
+
+pub struct ConsolidationSelector;
+
+impl CoinSelector for ConsolidationSelector {
+    fn select_coins(&self, mut utxos: Vec<UnspentInfo>, params: &CoinSelectionParams) -> CoinSelection {
+        utxos.sort_by_key(|utxo| utxo.value);
+        // the unspents worth less than the fee of their spending are not consolidated,
+        // they're moved to the end to be spent only if the rest is not enough
+        let (mut ordered, not_worth): (Vec<_>, Vec<_>) = utxos
+            .into_iter()
+            .partition(|utxo| params.effective_value(utxo).unwrap_or(0) > 0);
+        let must_spend = ordered.len().min(MAX_CONSOLIDATION_INPUTS);
+        ordered.extend(not_worth);
+        CoinSelection {
+            utxos: ordered,
+            must_spend,
+        }
+    }
+}
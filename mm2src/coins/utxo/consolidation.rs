@@ -0,0 +1,238 @@
+//! The consolidation of the small unspent outputs.
+//!
+//! Market makers receive a lot of small swap outputs, so the later transactions have to spend many inputs
+//! and get expensive. `consolidate_utxos` merges up to `max_inputs` smallest unspents into one output at our address
+//! while the fee rate is low. It returns the preview of the consolidation unless `broadcast` is set.
+
+use super::rpc_clients::UtxoRpcError;
+use super::utxo_common::big_decimal_from_sat_unsigned;
+use super::{generate_and_send_tx, output_script, sat_from_big_decimal, ActualTxFee, FeePolicy, GenerateTxError,
+            ScriptType, UtxoAddressFormat, UtxoCoinFields, UtxoCommonOps, UtxoTx, UTXO_LOCK};
+use crate::{lp_coinfind_or_err, CoinFindError, MmCoinEnum, NumConversError};
+use bigdecimal::BigDecimal;
+use chain::TransactionOutput;
+use common::jsonrpc_client::JsonRpcError;
+use common::mm_ctx::MmArc;
+use common::mm_error::prelude::*;
+use common::HttpStatusCode;
+use derive_more::Display;
+use http::StatusCode;
+use rpc::v1::types::{Bytes as BytesJson, H256 as H256Json};
+use serialization::{serialize, serialize_with_flags, SERIALIZE_TRANSACTION_WITNESS};
+
+pub type ConsolidateUtxosResult = Result<ConsolidateUtxosResponse, MmError<ConsolidateUtxosError>>;
+
+#[derive(Deserialize)]
+pub struct ConsolidateUtxosRequest {
+    coin: String,
+    /// The maximum number of the smallest unspents merged at once.
+    max_inputs: usize,
+    /// The consolidation is refused if the current fee rate (coin units per kilobyte) exceeds this ceiling.
+    max_fee_per_kbyte: BigDecimal,
+    /// Whether to sign and broadcast the consolidation transaction. Only the preview is returned if false.
+    #[serde(default)]
+    broadcast: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ConsolidateUtxosResponse {
+    /// The number of the merged unspents.
+    pub inputs_count: usize,
+    /// The total amount of the merged unspents.
+    pub spent_amount: BigDecimal,
+    /// The value of the resulting output at our address.
+    pub received_amount: BigDecimal,
+    pub fee: BigDecimal,
+    /// The fee rate the transaction is generated with, coin units per kilobyte.
+    pub fee_per_kbyte: BigDecimal,
+    /// The hash of the broadcasted transaction. Not set if it's a preview.
+    pub tx_hash: Option<H256Json>,
+    /// The broadcasted transaction. Not set if it's a preview.
+    pub tx_hex: Option<BytesJson>,
+}
+
+#[derive(Debug, Display, Serialize, SerializeErrorType)]
+#[serde(tag = "error_type", content = "error_data")]
+pub enum ConsolidateUtxosError {
+    #[display(fmt = "No such coin {}", coin)]
+    NoSuchCoin { coin: String },
+    #[display(fmt = "{} doesn't support the UTXO consolidation", coin)]
+    CoinDoesNotSupportConsolidation { coin: String },
+    #[display(
+        fmt = "Fee rate {} per kbyte exceeds the ceiling {}",
+        fee_per_kbyte,
+        max_fee_per_kbyte
+    )]
+    FeeRateTooHigh {
+        fee_per_kbyte: BigDecimal,
+        max_fee_per_kbyte: BigDecimal,
+    },
+    #[display(fmt = "Nothing to consolidate: there are {} spendable unspents only", unspents)]
+    NothingToConsolidate { unspents: usize },
+    #[display(fmt = "The unspents don't cover the consolidation fee: {}", _0)]
+    NotSufficientBalance(String),
+    #[display(fmt = "MM2 runs in the watch-only mode, the consolidation can't be broadcasted")]
+    WatchOnly,
+    #[display(fmt = "Transport error: {}", _0)]
+    Transport(String),
+    #[display(fmt = "Internal error: {}", _0)]
+    InternalError(String),
+}
+
+impl HttpStatusCode for ConsolidateUtxosError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ConsolidateUtxosError::NoSuchCoin { .. }
+            | ConsolidateUtxosError::CoinDoesNotSupportConsolidation { .. }
+            | ConsolidateUtxosError::FeeRateTooHigh { .. }
+            | ConsolidateUtxosError::NothingToConsolidate { .. }
+            | ConsolidateUtxosError::NotSufficientBalance(_) => StatusCode::BAD_REQUEST,
+            ConsolidateUtxosError::WatchOnly => StatusCode::METHOD_NOT_ALLOWED,
+            ConsolidateUtxosError::Transport(_) | ConsolidateUtxosError::InternalError(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            },
+        }
+    }
+}
+
+impl From<CoinFindError> for ConsolidateUtxosError {
+    fn from(e: CoinFindError) -> Self {
+        match e {
+            CoinFindError::NoSuchCoin { coin } => ConsolidateUtxosError::NoSuchCoin { coin },
+        }
+    }
+}
+
+impl From<NumConversError> for ConsolidateUtxosError {
+    fn from(e: NumConversError) -> Self { ConsolidateUtxosError::InternalError(e.to_string()) }
+}
+
+impl From<UtxoRpcError> for ConsolidateUtxosError {
+    fn from(e: UtxoRpcError) -> Self {
+        match e {
+            UtxoRpcError::Transport(rpc) | UtxoRpcError::ResponseParseError(rpc) | UtxoRpcError::Unauthorized(rpc) => {
+                ConsolidateUtxosError::Transport(rpc.to_string())
+            },
+            UtxoRpcError::InvalidResponse(error) => ConsolidateUtxosError::Transport(error),
+            UtxoRpcError::Internal(error) => ConsolidateUtxosError::InternalError(error),
+        }
+    }
+}
+
+impl From<JsonRpcError> for ConsolidateUtxosError {
+    fn from(e: JsonRpcError) -> Self { ConsolidateUtxosError::Transport(e.to_string()) }
+}
+
+impl From<GenerateTxError> for ConsolidateUtxosError {
+    fn from(e: GenerateTxError) -> Self {
+        match e {
+            GenerateTxError::DeductFeeFromOutputFailed { .. } | GenerateTxError::NotEnoughUtxos { .. } => {
+                ConsolidateUtxosError::NotSufficientBalance(e.to_string())
+            },
+            GenerateTxError::Transport(error) => ConsolidateUtxosError::Transport(error),
+            _ => ConsolidateUtxosError::InternalError(e.to_string()),
+        }
+    }
+}
+
+pub async fn consolidate_utxos(ctx: MmArc, req: ConsolidateUtxosRequest) -> ConsolidateUtxosResult {
+    if req.broadcast && ctx.is_watch_only() {
+        return MmError::err(ConsolidateUtxosError::WatchOnly);
+    }
+    match lp_coinfind_or_err(&ctx, &req.coin).await? {
+        MmCoinEnum::UtxoCoin(utxo) => consolidate_utxo_coin_unspents(&utxo, req).await,
+        MmCoinEnum::QtumCoin(qtum) => consolidate_utxo_coin_unspents(&qtum, req).await,
+        _ => MmError::err(ConsolidateUtxosError::CoinDoesNotSupportConsolidation { coin: req.coin }),
+    }
+}
+
+/// Spends up to `req.max_inputs` smallest mature unspents, excluding the frozen ones,
+/// to the only output at our address that pays the fee.
+pub async fn consolidate_utxo_coin_unspents<T>(coin: &T, req: ConsolidateUtxosRequest) -> ConsolidateUtxosResult
+where
+    T: AsRef<UtxoCoinFields> + UtxoCommonOps,
+{
+    let utxo = coin.as_ref();
+    let decimals = utxo.decimals;
+
+    let max_fee_per_kbyte = sat_from_big_decimal(&req.max_fee_per_kbyte, decimals)?;
+    let actual_fee = coin.get_tx_fee().await?;
+    let fee_per_kbyte = match actual_fee {
+        ActualTxFee::Dynamic(fee) | ActualTxFee::FixedPerKb(fee) => fee,
+    };
+    if fee_per_kbyte > max_fee_per_kbyte {
+        return MmError::err(ConsolidateUtxosError::FeeRateTooHigh {
+            fee_per_kbyte: big_decimal_from_sat_unsigned(fee_per_kbyte, decimals),
+            max_fee_per_kbyte: req.max_fee_per_kbyte,
+        });
+    }
+
+    let _utxo_lock = UTXO_LOCK.lock().await;
+    let (unspents, recently_spent) = coin.ordered_mature_unspents(&utxo.my_address).await?;
+    // the unspents are ordered from the smallest ones
+    let unspents: Vec<_> = {
+        let frozen_outpoints = utxo.frozen_outpoints.lock().await;
+        unspents
+            .into_iter()
+            .filter(|unspent| !frozen_outpoints.contains(&unspent.outpoint))
+            .take(req.max_inputs)
+            .collect()
+    };
+    if unspents.len() < 2 {
+        return MmError::err(ConsolidateUtxosError::NothingToConsolidate {
+            unspents: unspents.len(),
+        });
+    }
+
+    let spent: u64 = unspents.iter().map(|unspent| unspent.value).sum();
+    let outputs = vec![TransactionOutput {
+        value: spent,
+        script_pubkey: output_script(&utxo.my_address, ScriptType::P2PKH).to_bytes(),
+    }];
+    let inputs_count = unspents.len();
+
+    let (received, tx_hash, tx_hex) = if req.broadcast {
+        let signed: UtxoTx = generate_and_send_tx(
+            coin,
+            Vec::new(),
+            unspents,
+            outputs,
+            FeePolicy::DeductFromOutput(0),
+            // pay the fee rate that was checked against the ceiling above
+            Some(actual_fee),
+            recently_spent,
+        )
+        .await
+        .map_to_mm(ConsolidateUtxosError::InternalError)?;
+        let received = signed.outputs.iter().map(|output| output.value).sum();
+        let tx_hash = signed.hash().reversed().into();
+        let tx_hex = match utxo.my_address.addr_format {
+            UtxoAddressFormat::Segwit => serialize_with_flags(&signed, SERIALIZE_TRANSACTION_WITNESS).into(),
+            _ => serialize(&signed).into(),
+        };
+        (received, Some(tx_hash), Some(tx_hex))
+    } else {
+        drop(recently_spent);
+        let (unsigned, _) = coin
+            .generate_transaction(
+                unspents,
+                outputs,
+                FeePolicy::DeductFromOutput(0),
+                Some(actual_fee),
+                None,
+            )
+            .await?;
+        let received = unsigned.outputs.iter().map(|output| output.value).sum();
+        (received, None, None)
+    };
+
+    Ok(ConsolidateUtxosResponse {
+        inputs_count,
+        spent_amount: big_decimal_from_sat_unsigned(spent, decimals),
+        received_amount: big_decimal_from_sat_unsigned(received, decimals),
+        fee: big_decimal_from_sat_unsigned(spent - received, decimals),
+        fee_per_kbyte: big_decimal_from_sat_unsigned(fee_per_kbyte, decimals),
+        tx_hash,
+        tx_hex,
+    })
+}
@@ -0,0 +1,70 @@
+//! The health of the Electrum servers of a coin.
+//!
+//! Every Electrum connection tracks the latency and the error rate of the requests and the chain tip
+//! reported by the server. The requests are sent to the servers with the best score first.
+//! The servers that time out repeatedly or lag behind the best tip are suspended with the exponential backoff.
+
+use super::rpc_clients::{ElectrumServerStatus, UtxoRpcClientEnum};
+use super::UtxoCoinFields;
+use crate::{lp_coinfind_or_err, CoinFindError, MmCoinEnum};
+use common::mm_ctx::MmArc;
+use common::mm_error::prelude::*;
+use common::HttpStatusCode;
+use derive_more::Display;
+use http::StatusCode;
+
+pub type ElectrumServersStatusResult = Result<ElectrumServersStatusResponse, MmError<ElectrumServersStatusError>>;
+
+#[derive(Deserialize)]
+pub struct ElectrumServersStatusRequest {
+    coin: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ElectrumServersStatusResponse {
+    /// The servers ordered by score, the best first.
+    servers: Vec<ElectrumServerStatus>,
+}
+
+#[derive(Debug, Display, Serialize, SerializeErrorType)]
+#[serde(tag = "error_type", content = "error_data")]
+pub enum ElectrumServersStatusError {
+    #[display(fmt = "No such coin {}", coin)]
+    NoSuchCoin { coin: String },
+    #[display(fmt = "{} is not activated with Electrum servers", coin)]
+    NotElectrumCoin { coin: String },
+}
+
+impl HttpStatusCode for ElectrumServersStatusError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ElectrumServersStatusError::NoSuchCoin { .. } | ElectrumServersStatusError::NotElectrumCoin { .. } => {
+                StatusCode::BAD_REQUEST
+            },
+        }
+    }
+}
+
+impl From<CoinFindError> for ElectrumServersStatusError {
+    fn from(e: CoinFindError) -> Self {
+        match e {
+            CoinFindError::NoSuchCoin { coin } => ElectrumServersStatusError::NoSuchCoin { coin },
+        }
+    }
+}
+
+pub async fn electrum_servers_status(ctx: MmArc, req: ElectrumServersStatusRequest) -> ElectrumServersStatusResult {
+    let coin = lp_coinfind_or_err(&ctx, &req.coin).await?;
+    let fields: &UtxoCoinFields = match coin {
+        MmCoinEnum::UtxoCoin(ref utxo) => utxo.as_ref(),
+        MmCoinEnum::QtumCoin(ref qtum) => qtum.as_ref(),
+        MmCoinEnum::Qrc20Coin(ref qrc20) => qrc20.as_ref(),
+        _ => return MmError::err(ElectrumServersStatusError::NotElectrumCoin { coin: req.coin }),
+    };
+    match fields.rpc_client {
+        UtxoRpcClientEnum::Electrum(ref client) => Ok(ElectrumServersStatusResponse {
+            servers: client.servers_status().await,
+        }),
+        UtxoRpcClientEnum::Native(_) => MmError::err(ElectrumServersStatusError::NotElectrumCoin { coin: req.coin }),
+    }
+}
@@ -0,0 +1,678 @@
+//! Fee bumping of the stuck UTXO transactions.
+//!
+//! `bump_fee` replaces an unconfirmed transaction sent by us with the transaction spending the same inputs,
+//! but paying a higher fee taken from the change output (BIP125 Replace-by-Fee).
+//! The replaced transaction must signal the replaceability, that is the case
+//! if the coin config has `"signal_rbf": true` at the moment the transaction is generated.
+//!
+//! `cpfp` accelerates an unconfirmed transaction that can't be replaced, e.g. sent to us by another wallet.
+//! It spends the outputs of the transaction paying to us with a child transaction
+//! whose fee is enough for the parent and the child to reach the target fee rate together (Child-Pays-For-Parent).
+
+use super::rpc_clients::{UnspentInfo, UtxoRpcClientOps, UtxoRpcError, UtxoRpcResult};
+use super::utxo_common::{big_decimal_from_sat, big_decimal_from_sat_unsigned, generate_transaction, tx_size_in_v_bytes};
+use super::{output_script, sat_from_big_decimal, sign_tx, sign_tx_with_key_pairs, ActualTxFee, AsyncMutexGuard,
+            FeePolicy, GenerateTxError, ScriptType, UtxoAddressFormat, UtxoCoinFields, UtxoCommonOps, UtxoFeeDetails,
+            UtxoTx, KILO_BYTE, UTXO_LOCK};
+use crate::watch_only::WatchOnlyError;
+use crate::{lp_coinfind_or_err, CoinFindError, MmCoinEnum, NumConversError, TransactionDetails, WithdrawFee};
+use bigdecimal::BigDecimal;
+use chain::constants::MAX_BIP125_RBF_SEQUENCE;
+use chain::{OutPoint, TransactionOutput};
+use common::mm_ctx::MmArc;
+use common::mm_error::prelude::*;
+use common::{now_ms, HttpStatusCode};
+use derive_more::Display;
+use futures::compat::Future01CompatExt;
+use http::StatusCode;
+use keys::bytes::Bytes;
+use rpc::v1::types::{Bytes as BytesJson, H256 as H256Json};
+use script::{Builder, Script, SignatureVersion, TransactionInputSigner};
+use serialization::{deserialize, serialize, serialize_with_flags, SERIALIZE_TRANSACTION_WITNESS};
+
+/// The minimum fee rate (satoshis per kilobyte) the replacement must pay for its own relay
+/// in addition to the fee of the replaced transaction.
+/// This is `-incrementalrelayfee` default value of the Bitcoin Core.
+const INCREMENTAL_RELAY_FEE_PER_KB: u64 = 1000;
+
+pub type BumpFeeResult = Result<BumpFeeResponse, MmError<BumpFeeError>>;
+pub type CpfpResult = Result<TransactionDetails, MmError<BumpFeeError>>;
+
+#[derive(Deserialize)]
+pub struct BumpFeeRequest {
+    coin: String,
+    /// The hash of the unconfirmed transaction sent by us.
+    tx_hash: H256Json,
+    /// The fee rate of the replacement. The current coin fee rate is used if not set.
+    #[serde(default)]
+    fee: Option<WithdrawFee>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BumpFeeResponse {
+    /// The hash of the replaced transaction.
+    pub replaced_tx_hash: BytesJson,
+    /// The replacement transaction. It's already broadcasted.
+    #[serde(flatten)]
+    pub tx_details: TransactionDetails,
+}
+
+#[derive(Deserialize)]
+pub struct CpfpRequest {
+    coin: String,
+    /// The hash of the unconfirmed transaction having the outputs to our address.
+    tx_hash: H256Json,
+    /// The fee rate the parent and the child should reach together. The current coin fee rate is used if not set.
+    #[serde(default)]
+    fee: Option<WithdrawFee>,
+}
+
+/// The error of the `bump_fee` and `cpfp` RPCs.
+#[derive(Debug, Display, Serialize, SerializeErrorType)]
+#[serde(tag = "error_type", content = "error_data")]
+pub enum BumpFeeError {
+    #[display(fmt = "No such coin {}", coin)]
+    NoSuchCoin { coin: String },
+    #[display(fmt = "{} doesn't support the fee bumping", coin)]
+    CoinDoesNotSupportFeeBumping { coin: String },
+    #[display(fmt = "Invalid fee policy: {}", _0)]
+    InvalidFeePolicy(String),
+    #[display(fmt = "The transaction doesn't signal the replaceability")]
+    TxDoesNotSignalRbf,
+    #[display(fmt = "The transaction is already confirmed")]
+    TxIsConfirmed,
+    #[display(fmt = "The transaction spends the outputs that are not ours")]
+    TxIsNotMine,
+    #[display(fmt = "The transaction has no change output to take the fee from")]
+    NoChangeOutput,
+    #[display(fmt = "The transaction has no unspent outputs to our address")]
+    NoOutputsToMe,
+    #[display(
+        fmt = "Not enough change to bump the fee: available {}, required at least {}",
+        available,
+        required
+    )]
+    NotSufficientChange {
+        available: BigDecimal,
+        required: BigDecimal,
+    },
+    #[display(
+        fmt = "The outputs to our address are too small to pay the child fee: available {}, required at least {}",
+        available,
+        required
+    )]
+    NotSufficientOutputsToMe {
+        available: BigDecimal,
+        required: BigDecimal,
+    },
+    #[display(fmt = "MM2 runs in the watch-only mode, the fee bumping is not available")]
+    WatchOnly,
+    #[display(fmt = "Transport error: {}", _0)]
+    Transport(String),
+    #[display(fmt = "Internal error: {}", _0)]
+    InternalError(String),
+}
+
+impl HttpStatusCode for BumpFeeError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            BumpFeeError::NoSuchCoin { .. }
+            | BumpFeeError::CoinDoesNotSupportFeeBumping { .. }
+            | BumpFeeError::InvalidFeePolicy(_)
+            | BumpFeeError::TxDoesNotSignalRbf
+            | BumpFeeError::TxIsConfirmed
+            | BumpFeeError::TxIsNotMine
+            | BumpFeeError::NoChangeOutput
+            | BumpFeeError::NoOutputsToMe
+            | BumpFeeError::NotSufficientChange { .. }
+            | BumpFeeError::NotSufficientOutputsToMe { .. } => StatusCode::BAD_REQUEST,
+            BumpFeeError::WatchOnly => StatusCode::METHOD_NOT_ALLOWED,
+            BumpFeeError::Transport(_) | BumpFeeError::InternalError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl From<CoinFindError> for BumpFeeError {
+    fn from(e: CoinFindError) -> Self {
+        match e {
+            CoinFindError::NoSuchCoin { coin } => BumpFeeError::NoSuchCoin { coin },
+        }
+    }
+}
+
+impl From<NumConversError> for BumpFeeError {
+    fn from(e: NumConversError) -> Self { BumpFeeError::InternalError(e.to_string()) }
+}
+
+impl From<UtxoRpcError> for BumpFeeError {
+    fn from(e: UtxoRpcError) -> Self {
+        match e {
+            UtxoRpcError::Transport(rpc) | UtxoRpcError::ResponseParseError(rpc) | UtxoRpcError::Unauthorized(rpc) => {
+                BumpFeeError::Transport(rpc.to_string())
+            },
+            UtxoRpcError::InvalidResponse(error) => BumpFeeError::Transport(error),
+            UtxoRpcError::Internal(error) => BumpFeeError::InternalError(error),
+        }
+    }
+}
+
+impl From<WatchOnlyError> for BumpFeeError {
+    fn from(_: WatchOnlyError) -> Self { BumpFeeError::WatchOnly }
+}
+
+/// The signed replacement that is not broadcasted yet.
+struct SignedReplacement {
+    replaced: UtxoTx,
+    signed: UtxoTx,
+    prev_outputs: Vec<TransactionOutput>,
+    response: BumpFeeResponse,
+}
+
+/// The replacement prepared by [`prepare_bump_fee`].
+/// [`UTXO_LOCK`] is held until the replacement is sent or dropped,
+/// so the replaced transaction inputs can't be spent by another transaction meanwhile.
+pub struct PreparedBumpFee {
+    coin: MmCoinEnum,
+    replacement: SignedReplacement,
+    _utxo_lock: AsyncMutexGuard<'static, ()>,
+}
+
+impl PreparedBumpFee {
+    /// The response that is returned once the replacement is broadcasted.
+    pub fn response(&self) -> &BumpFeeResponse { &self.replacement.response }
+
+    /// The serialized replaced transaction.
+    pub fn replaced_tx_hex(&self) -> BytesJson { tx_hex(self.utxo_fields(), &self.replacement.replaced) }
+
+    /// Broadcasts the replacement.
+    pub async fn send(self) -> BumpFeeResult {
+        send_replacement(self.utxo_fields(), &self.replacement).await?;
+        Ok(self.replacement.response)
+    }
+
+    fn utxo_fields(&self) -> &UtxoCoinFields {
+        match self.coin {
+            MmCoinEnum::UtxoCoin(ref utxo) => utxo.as_ref(),
+            MmCoinEnum::QtumCoin(ref qtum) => qtum.as_ref(),
+            _ => unreachable!("PreparedBumpFee is created for the UTXO coins only"),
+        }
+    }
+}
+
+/// Builds and signs the transaction replacing the unconfirmed transaction sent by us with a higher fee,
+/// the replacement is broadcasted by [`PreparedBumpFee::send`].
+pub async fn prepare_bump_fee(ctx: &MmArc, req: BumpFeeRequest) -> Result<PreparedBumpFee, MmError<BumpFeeError>> {
+    if ctx.is_watch_only() {
+        return MmError::err(BumpFeeError::WatchOnly);
+    }
+    let coin = lp_coinfind_or_err(ctx, &req.coin).await?;
+    let utxo_lock = UTXO_LOCK.lock().await;
+    let replacement = match coin {
+        MmCoinEnum::UtxoCoin(ref utxo) => sign_utxo_replacement(utxo, req.tx_hash, req.fee).await?,
+        MmCoinEnum::QtumCoin(ref qtum) => sign_utxo_replacement(qtum, req.tx_hash, req.fee).await?,
+        _ => return MmError::err(BumpFeeError::CoinDoesNotSupportFeeBumping { coin: req.coin }),
+    };
+    Ok(PreparedBumpFee {
+        coin,
+        replacement,
+        _utxo_lock: utxo_lock,
+    })
+}
+
+/// Accelerates the unconfirmed transaction by spending its outputs to our address with the high fee child.
+pub async fn cpfp(ctx: MmArc, req: CpfpRequest) -> CpfpResult {
+    if ctx.is_watch_only() {
+        return MmError::err(BumpFeeError::WatchOnly);
+    }
+    match lp_coinfind_or_err(&ctx, &req.coin).await? {
+        MmCoinEnum::UtxoCoin(utxo) => utxo_cpfp(&utxo, req.tx_hash, req.fee).await,
+        MmCoinEnum::QtumCoin(qtum) => utxo_cpfp(&qtum, req.tx_hash, req.fee).await,
+        _ => MmError::err(BumpFeeError::CoinDoesNotSupportFeeBumping { coin: req.coin }),
+    }
+}
+
+/// Rebuilds the transaction with the same inputs and outputs, but reduces the change by the fee increase,
+/// signs and broadcasts it.
+pub async fn bump_utxo_tx_fee<T>(coin: &T, tx_hash: H256Json, fee: Option<WithdrawFee>) -> BumpFeeResult
+where
+    T: AsRef<UtxoCoinFields> + UtxoCommonOps,
+{
+    let _utxo_lock = UTXO_LOCK.lock().await;
+    let replacement = sign_utxo_replacement(coin, tx_hash, fee).await?;
+    send_replacement(coin.as_ref(), &replacement).await?;
+    Ok(replacement.response)
+}
+
+/// Rebuilds the transaction with the same inputs and outputs, but reduces the change by the fee increase, and signs it.
+/// The new fee is calculated with the given fee rate, but it's at least the replaced transaction fee
+/// increased by [`INCREMENTAL_RELAY_FEE_PER_KB`] as BIP125 requires.
+/// [`UTXO_LOCK`] is expected to be held by the caller.
+async fn sign_utxo_replacement<T>(
+    coin: &T,
+    tx_hash: H256Json,
+    fee: Option<WithdrawFee>,
+) -> Result<SignedReplacement, MmError<BumpFeeError>>
+where
+    T: AsRef<UtxoCoinFields> + UtxoCommonOps,
+{
+    let utxo = coin.as_ref();
+    let decimals = utxo.decimals;
+
+    let replaced = unconfirmed_utxo_tx(utxo, tx_hash).await?;
+    if !replaced
+        .inputs
+        .iter()
+        .any(|input| input.sequence <= MAX_BIP125_RBF_SEQUENCE)
+    {
+        return MmError::err(BumpFeeError::TxDoesNotSignalRbf);
+    }
+
+    let prev_outputs = prev_outputs(utxo, &replaced).await?;
+    let my_scripts = my_script_pubkeys(utxo);
+    let is_mine = |script_pubkey: &Bytes| my_scripts.contains(script_pubkey);
+    if !prev_outputs.iter().all(|output| is_mine(&output.script_pubkey)) {
+        return MmError::err(BumpFeeError::TxIsNotMine);
+    }
+    // the change is the last output of the transactions generated by `generate_transaction`
+    let change_index = replaced
+        .outputs
+        .iter()
+        .rposition(|output| is_mine(&output.script_pubkey))
+        .or_mm_err(|| BumpFeeError::NoChangeOutput)?;
+    let old_fee = tx_fee(&replaced, &prev_outputs)?;
+
+    let mut unsigned = TransactionInputSigner::from(replaced.clone());
+    unsigned.consensus_branch_id = utxo.conf.consensus_branch_id;
+    for (input, prev_output) in unsigned.inputs.iter_mut().zip(prev_outputs.iter()) {
+        input.amount = prev_output.value;
+    }
+
+    let fee_rate = actual_fee_rate(coin, fee).await?;
+    let v_size = tx_size_in_v_bytes(&utxo.my_address.addr_format, &UtxoTx::from(unsigned.clone())) as u64;
+    let min_fee = old_fee + INCREMENTAL_RELAY_FEE_PER_KB * v_size / KILO_BYTE;
+    let new_fee = std::cmp::max(fee_for_v_size(&fee_rate, v_size), min_fee);
+
+    let fee_increase = new_fee - old_fee;
+    let change = unsigned.outputs[change_index].value;
+    if change < fee_increase + utxo.dust_amount {
+        return MmError::err(BumpFeeError::NotSufficientChange {
+            available: big_decimal_from_sat_unsigned(change, decimals),
+            required: big_decimal_from_sat_unsigned(fee_increase + utxo.dust_amount, decimals),
+        });
+    }
+    unsigned.outputs[change_index].value -= fee_increase;
+
+    let signed = sign_my_tx(utxo, unsigned, &prev_outputs)?;
+
+    let to_outputs = signed
+        .outputs
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| *index != change_index)
+        .map(|(_, output)| output);
+    let to = display_addresses(coin, to_outputs);
+    let tx_details = my_tx_details(coin, &signed, &prev_outputs, to, new_fee);
+    let response = BumpFeeResponse {
+        replaced_tx_hash: replaced.hash().reversed().to_vec().into(),
+        tx_details,
+    };
+    Ok(SignedReplacement {
+        replaced,
+        signed,
+        prev_outputs,
+        response,
+    })
+}
+
+/// Broadcasts the replacement, the change of the replaced transaction is not available for spending anymore.
+async fn send_replacement(coin: &UtxoCoinFields, replacement: &SignedReplacement) -> Result<(), MmError<BumpFeeError>> {
+    send_my_tx(coin, &replacement.signed, &replacement.prev_outputs).await?;
+    coin.recently_spent_outpoints
+        .lock()
+        .await
+        .remove_tx_outputs(&replacement.replaced.hash());
+    Ok(())
+}
+
+/// Spends all outputs of the parent transaction paying to us to `my_address`, signs and broadcasts the child.
+/// The child fee is `fee_rate * (parent_v_size + child_v_size) - parent_fee`,
+/// but it's at least the fee the child would pay alone.
+pub async fn utxo_cpfp<T>(coin: &T, parent_hash: H256Json, fee: Option<WithdrawFee>) -> CpfpResult
+where
+    T: AsRef<UtxoCoinFields> + UtxoCommonOps,
+{
+    let utxo = coin.as_ref();
+    let decimals = utxo.decimals;
+    let _utxo_lock = UTXO_LOCK.lock().await;
+
+    let parent = unconfirmed_utxo_tx(utxo, parent_hash).await?;
+    let parent_fee = tx_fee(&parent, &prev_outputs(utxo, &parent).await?)?;
+
+    let my_scripts = my_script_pubkeys(utxo);
+    let parent_hash = parent.hash();
+    let mut unspents: Vec<UnspentInfo> = parent
+        .outputs
+        .iter()
+        .enumerate()
+        .filter(|(_, output)| my_scripts.contains(&output.script_pubkey))
+        .map(|(index, output)| UnspentInfo {
+            outpoint: OutPoint {
+                hash: parent_hash,
+                index: index as u32,
+            },
+            value: output.value,
+            height: None,
+        })
+        .collect();
+    // the frozen outputs are not spent by the child
+    {
+        let frozen_outpoints = utxo.frozen_outpoints.lock().await;
+        unspents.retain(|unspent| !frozen_outpoints.contains(&unspent.outpoint));
+    }
+    if unspents.is_empty() {
+        return MmError::err(BumpFeeError::NoOutputsToMe);
+    }
+    unspents.sort_by(|a, b| a.value.cmp(&b.value));
+
+    let fee_rate = actual_fee_rate(coin, fee).await?;
+    let sum_outputs_to_me = unspents.iter().map(|unspent| unspent.value).sum();
+    let outputs = vec![TransactionOutput {
+        value: sum_outputs_to_me,
+        script_pubkey: output_script(&utxo.my_address, ScriptType::P2PKH).to_bytes(),
+    }];
+    let (mut unsigned, data) = generate_transaction(
+        coin,
+        unspents,
+        outputs,
+        FeePolicy::DeductFromOutput(0),
+        Some(fee_rate.clone()),
+        None,
+    )
+    .await
+    .mm_err(|e| match e {
+        GenerateTxError::DeductFeeFromOutputFailed {
+            output_value, required, ..
+        } => BumpFeeError::NotSufficientOutputsToMe {
+            available: big_decimal_from_sat_unsigned(output_value, decimals),
+            required: big_decimal_from_sat_unsigned(required, decimals),
+        },
+        GenerateTxError::Transport(e) => BumpFeeError::Transport(e),
+        e => BumpFeeError::InternalError(e.to_string()),
+    })?;
+
+    let child_v_size = tx_size_in_v_bytes(&utxo.my_address.addr_format, &UtxoTx::from(unsigned.clone())) as u64;
+    let package_fee = fee_for_v_size(&fee_rate, signed_tx_v_size(&parent) + child_v_size);
+    let child_fee = std::cmp::max(data.fee_amount, package_fee.saturating_sub(parent_fee));
+
+    let fee_increase = child_fee - data.fee_amount;
+    let output_value = unsigned.outputs[0].value;
+    if output_value < fee_increase + utxo.dust_amount {
+        return MmError::err(BumpFeeError::NotSufficientOutputsToMe {
+            available: big_decimal_from_sat_unsigned(output_value + data.fee_amount, decimals),
+            required: big_decimal_from_sat_unsigned(child_fee + utxo.dust_amount, decimals),
+        });
+    }
+    unsigned.outputs[0].value -= fee_increase;
+
+    // the previous outputs are in the order of the child inputs
+    let prev_outputs = unsigned
+        .inputs
+        .iter()
+        .map(|input| {
+            parent
+                .outputs
+                .get(input.previous_output.index as usize)
+                .cloned()
+                .or_mm_err(|| {
+                    BumpFeeError::InternalError(format!("{:?} is not the parent output", input.previous_output))
+                })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let signed = sign_my_tx(utxo, unsigned, &prev_outputs)?;
+    send_my_tx(utxo, &signed, &prev_outputs).await?;
+
+    let to = display_addresses(coin, signed.outputs.iter());
+    Ok(my_tx_details(coin, &signed, &prev_outputs, to, child_fee))
+}
+
+/// Returns the transaction if it's not confirmed yet.
+async fn unconfirmed_utxo_tx(coin: &UtxoCoinFields, tx_hash: H256Json) -> Result<UtxoTx, MmError<BumpFeeError>> {
+    let verbose = coin
+        .rpc_client
+        .get_verbose_transaction(tx_hash)
+        .compat()
+        .await
+        .map_to_mm(|e| BumpFeeError::Transport(e.to_string()))?;
+    if verbose.confirmations > 0 {
+        return MmError::err(BumpFeeError::TxIsConfirmed);
+    }
+    let mut tx: UtxoTx =
+        deserialize(verbose.hex.as_slice()).map_to_mm(|e| BumpFeeError::InternalError(format!("{:?}", e)))?;
+    tx.tx_hash_algo = coin.tx_hash_algo;
+    Ok(tx)
+}
+
+async fn utxo_tx_by_hash(coin: &UtxoCoinFields, tx_hash: H256Json) -> UtxoRpcResult<UtxoTx> {
+    let bytes = coin.rpc_client.get_transaction_bytes(tx_hash).compat().await?;
+    let mut tx: UtxoTx = deserialize(bytes.as_slice()).map_to_mm(UtxoRpcError::from)?;
+    tx.tx_hash_algo = coin.tx_hash_algo;
+    Ok(tx)
+}
+
+/// Returns the outputs spent by the transaction inputs.
+async fn prev_outputs(coin: &UtxoCoinFields, tx: &UtxoTx) -> Result<Vec<TransactionOutput>, MmError<BumpFeeError>> {
+    let mut prev_outputs = Vec::with_capacity(tx.inputs.len());
+    for input in tx.inputs.iter() {
+        let prev_tx = utxo_tx_by_hash(coin, input.previous_output.hash.reversed().into()).await?;
+        let prev_output = prev_tx
+            .outputs
+            .get(input.previous_output.index as usize)
+            .cloned()
+            .or_mm_err(|| BumpFeeError::InternalError(format!("{:?} output not found", input.previous_output)))?;
+        prev_outputs.push(prev_output);
+    }
+    Ok(prev_outputs)
+}
+
+fn tx_fee(tx: &UtxoTx, prev_outputs: &[TransactionOutput]) -> Result<u64, MmError<BumpFeeError>> {
+    let sum_inputs: u64 = prev_outputs.iter().map(|output| output.value).sum();
+    let sum_outputs: u64 = tx.outputs.iter().map(|output| output.value).sum();
+    sum_inputs
+        .checked_sub(sum_outputs)
+        .or_mm_err(|| BumpFeeError::InternalError("The transaction outputs exceed the inputs".into()))
+}
+
+/// Returns the virtual size of the signed transaction.
+/// Unlike [`tx_size_in_v_bytes`] it doesn't expect the inputs to be unsigned.
+fn signed_tx_v_size(tx: &UtxoTx) -> u64 {
+    let base_size = serialize(tx).len() as u64;
+    let total_size = serialize_with_flags(tx, SERIALIZE_TRANSACTION_WITNESS).len() as u64;
+    // https://github.com/bitcoin/bips/blob/master/bip-0141.mediawiki#transaction-size-calculations
+    (base_size * 3 + total_size + 3) / 4
+}
+
+async fn actual_fee_rate<T>(coin: &T, fee: Option<WithdrawFee>) -> Result<ActualTxFee, MmError<BumpFeeError>>
+where
+    T: AsRef<UtxoCoinFields> + UtxoCommonOps,
+{
+    let decimals = coin.as_ref().decimals;
+    match fee {
+        Some(WithdrawFee::UtxoFixed { amount }) => {
+            Ok(ActualTxFee::FixedPerKb(sat_from_big_decimal(&amount, decimals)?))
+        },
+        Some(WithdrawFee::UtxoPerKbyte { amount }) => {
+            Ok(ActualTxFee::Dynamic(sat_from_big_decimal(&amount, decimals)?))
+        },
+        Some(fee_policy) => {
+            let error = format!(
+                "Expected 'UtxoFixed' or 'UtxoPerKbyte' fee types, found {:?}",
+                fee_policy
+            );
+            MmError::err(BumpFeeError::InvalidFeePolicy(error))
+        },
+        None => coin
+            .get_tx_fee()
+            .await
+            .map_to_mm(|e| BumpFeeError::Transport(e.to_string())),
+    }
+}
+
+fn fee_for_v_size(fee_rate: &ActualTxFee, v_size: u64) -> u64 {
+    match fee_rate {
+        ActualTxFee::Dynamic(fee_per_kb) => fee_per_kb * v_size / KILO_BYTE,
+        ActualTxFee::FixedPerKb(fee_per_kb) => fee_per_kb * ((v_size + KILO_BYTE - 1) / KILO_BYTE),
+    }
+}
+
+/// Returns the script pubkeys of the coin address or of the known HD account addresses.
+fn my_script_pubkeys(coin: &UtxoCoinFields) -> Vec<Bytes> {
+    match coin.hd_account {
+        Some(ref hd_account) => hd_account
+            .all_addresses()
+            .iter()
+            .map(|hd_address| output_script(&hd_address.address, ScriptType::P2PKH).to_bytes())
+            .collect(),
+        None => vec![output_script(&coin.my_address, ScriptType::P2PKH).to_bytes()],
+    }
+}
+
+/// Signs the transaction spending the `prev_outputs` paying to our address or to the HD account addresses.
+fn sign_my_tx(
+    coin: &UtxoCoinFields,
+    unsigned: TransactionInputSigner,
+    prev_outputs: &[TransactionOutput],
+) -> Result<UtxoTx, MmError<BumpFeeError>> {
+    let signature_version = match coin.my_address.addr_format {
+        UtxoAddressFormat::Segwit => SignatureVersion::WitnessV0,
+        _ => coin.conf.signature_version,
+    };
+    match coin.hd_account {
+        Some(ref hd_account) => {
+            let hd_addresses = hd_account.all_addresses();
+            let mut key_pairs = Vec::with_capacity(prev_outputs.len());
+            for prev_output in prev_outputs.iter() {
+                let owner = hd_addresses
+                    .iter()
+                    .find(|hd_address| {
+                        output_script(&hd_address.address, ScriptType::P2PKH).to_bytes() == prev_output.script_pubkey
+                    })
+                    .or_mm_err(|| BumpFeeError::TxIsNotMine)?;
+                let key_pair = hd_account
+                    .key_pair(&coin.conf, owner)
+                    .map_to_mm(BumpFeeError::InternalError)?;
+                key_pairs.push(key_pair);
+            }
+            sign_tx_with_key_pairs(unsigned, &key_pairs, signature_version, coin.conf.fork_id)
+        },
+        None => sign_tx(
+            unsigned,
+            coin.key_pair.signer()?,
+            Builder::build_p2pkh(&coin.my_address.hash),
+            signature_version,
+            coin.conf.fork_id,
+        ),
+    }
+    .map_to_mm(BumpFeeError::InternalError)
+}
+
+/// Broadcasts the transaction and marks the `prev_outputs` spent by it.
+async fn send_my_tx(
+    coin: &UtxoCoinFields,
+    signed: &UtxoTx,
+    prev_outputs: &[TransactionOutput],
+) -> Result<(), MmError<BumpFeeError>> {
+    coin.rpc_client
+        .send_transaction(signed)
+        .compat()
+        .await
+        .map_to_mm(BumpFeeError::Transport)?;
+
+    let spent_unspents = signed
+        .inputs
+        .iter()
+        .zip(prev_outputs.iter())
+        .map(|(input, prev_output)| UnspentInfo {
+            outpoint: input.previous_output.clone(),
+            value: prev_output.value,
+            height: None,
+        })
+        .collect();
+    coin.recently_spent_outpoints
+        .lock()
+        .await
+        .add_spent(spent_unspents, signed.hash(), signed.outputs.clone());
+    Ok(())
+}
+
+fn my_tx_details<T>(
+    coin: &T,
+    signed: &UtxoTx,
+    prev_outputs: &[TransactionOutput],
+    to: Vec<String>,
+    fee: u64,
+) -> TransactionDetails
+where
+    T: AsRef<UtxoCoinFields> + UtxoCommonOps,
+{
+    let utxo = coin.as_ref();
+    let decimals = utxo.decimals;
+    let my_scripts = my_script_pubkeys(utxo);
+    let spent_by_me: u64 = prev_outputs.iter().map(|output| output.value).sum();
+    let received_by_me: u64 = signed
+        .outputs
+        .iter()
+        .filter(|output| my_scripts.contains(&output.script_pubkey))
+        .map(|output| output.value)
+        .sum();
+    let tx_hex = tx_hex(utxo, signed);
+    let fee_details = UtxoFeeDetails {
+        amount: big_decimal_from_sat_unsigned(fee, decimals),
+    };
+    TransactionDetails {
+        from: display_addresses(coin, prev_outputs.iter()),
+        to,
+        total_amount: big_decimal_from_sat_unsigned(spent_by_me, decimals),
+        spent_by_me: big_decimal_from_sat_unsigned(spent_by_me, decimals),
+        received_by_me: big_decimal_from_sat_unsigned(received_by_me, decimals),
+        my_balance_change: big_decimal_from_sat(received_by_me as i64 - spent_by_me as i64, decimals),
+        tx_hash: signed.hash().reversed().to_vec().into(),
+        tx_hex,
+        fee_details: Some(fee_details.into()),
+        block_height: 0,
+        coin: utxo.conf.ticker.clone(),
+        internal_id: vec![].into(),
+        timestamp: now_ms() / 1000,
+        kmd_rewards: None,
+        psbt: None,
+    }
+}
+
+fn tx_hex(coin: &UtxoCoinFields, tx: &UtxoTx) -> BytesJson {
+    match coin.my_address.addr_format {
+        UtxoAddressFormat::Segwit => serialize_with_flags(tx, SERIALIZE_TRANSACTION_WITNESS).into(),
+        _ => serialize(tx).into(),
+    }
+}
+
+fn display_addresses<'a, T, I>(coin: &T, outputs: I) -> Vec<String>
+where
+    T: UtxoCommonOps,
+    I: Iterator<Item = &'a TransactionOutput>,
+{
+    let mut addresses = Vec::new();
+    for output in outputs {
+        let script: Script = output.script_pubkey.clone().into();
+        let displayed = coin
+            .addresses_from_script(&script)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|address| address.display_address().ok());
+        for address in displayed {
+            if !addresses.contains(&address) {
+                addresses.push(address);
+            }
+        }
+    }
+    addresses
+}
@@ -0,0 +1,307 @@
+//! PSBT (BIP 174) workflow of the UTXO coins.
+//!
+//! `withdraw` with the `psbt` flag returns the unsigned PSBT containing the spent outputs
+//! and the BIP32 derivation of the HD account keys instead of the signed transaction.
+//! The PSBT can be signed by mm2 itself with `sign_psbt` or by an external wallet.
+//! `sign_psbt` and `finalize_psbt` combine the signatures of the externally signed PSBTs,
+//! then `finalize_psbt` returns the transaction to be broadcasted with `send_raw_transaction`.
+//!
+//! Only the P2PKH and P2WPKH inputs signed with `SIGHASH_ALL` are supported.
+
+use super::rpc_clients::UtxoRpcClientOps;
+use super::utxo_hd_wallet::UtxoHDAddress;
+use super::{output_script, script_sig, ScriptType, UtxoAddressFormat, UtxoCoinFields, UtxoTx};
+use crate::signer::Signer;
+use crate::watch_only::WatchOnlyError;
+use crate::{lp_coinfind_or_err, CoinFindError, MmCoinEnum, WithdrawError};
+use chain::{TransactionOutput, TxHashAlgo};
+use common::mm_ctx::MmArc;
+use common::mm_error::prelude::*;
+use common::HttpStatusCode;
+use derive_more::Display;
+use futures::compat::Future01CompatExt;
+use http::StatusCode;
+use rpc::v1::types::{Bytes as BytesJson, H256 as H256Json};
+use script::{Builder, Psbt, PsbtError, Script, SignatureVersion, TransactionInputSigner};
+use serialization::{deserialize, serialize_with_flags, SERIALIZE_TRANSACTION_WITNESS};
+
+pub type SignPsbtResult = Result<SignPsbtResponse, MmError<PsbtRpcError>>;
+pub type FinalizePsbtResult = Result<FinalizePsbtResponse, MmError<PsbtRpcError>>;
+
+#[derive(Deserialize)]
+pub struct SignPsbtRequest {
+    coin: String,
+    psbt: BytesJson,
+    /// The PSBTs of the same transaction signed externally, their signatures are combined with `psbt`.
+    #[serde(default)]
+    merge: Vec<BytesJson>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SignPsbtResponse {
+    pub psbt: BytesJson,
+    /// The number of the inputs signed by mm2.
+    pub signed_inputs: usize,
+    /// Whether every input has the signature required to finalize the PSBT.
+    pub is_complete: bool,
+}
+
+#[derive(Deserialize)]
+pub struct FinalizePsbtRequest {
+    coin: String,
+    psbt: BytesJson,
+    /// The PSBTs of the same transaction signed externally, their signatures are combined with `psbt`.
+    #[serde(default)]
+    merge: Vec<BytesJson>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FinalizePsbtResponse {
+    /// The signed transaction that should be sent as is to `send_raw_transaction`.
+    pub tx_hex: BytesJson,
+    pub tx_hash: H256Json,
+}
+
+#[derive(Debug, Display, Serialize, SerializeErrorType)]
+#[serde(tag = "error_type", content = "error_data")]
+pub enum PsbtRpcError {
+    #[display(fmt = "No such coin {}", coin)]
+    NoSuchCoin { coin: String },
+    #[display(fmt = "{} doesn't support PSBT", coin)]
+    CoinDoesNotSupportPsbt { coin: String },
+    #[display(fmt = "Invalid PSBT: {}", _0)]
+    InvalidPsbt(String),
+    #[display(fmt = "PSBT is not signed completely: {}", _0)]
+    NotFullySigned(String),
+    #[display(
+        fmt = "Only SIGHASH_ALL is supported, PSBT input {} requires {}",
+        input,
+        sighash_type
+    )]
+    UnsupportedSighashType { input: usize, sighash_type: u32 },
+    #[display(fmt = "Internal error: {}", _0)]
+    InternalError(String),
+}
+
+impl HttpStatusCode for PsbtRpcError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            PsbtRpcError::NoSuchCoin { .. }
+            | PsbtRpcError::CoinDoesNotSupportPsbt { .. }
+            | PsbtRpcError::InvalidPsbt(_)
+            | PsbtRpcError::NotFullySigned(_)
+            | PsbtRpcError::UnsupportedSighashType { .. } => StatusCode::BAD_REQUEST,
+            PsbtRpcError::InternalError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl From<CoinFindError> for PsbtRpcError {
+    fn from(e: CoinFindError) -> Self {
+        match e {
+            CoinFindError::NoSuchCoin { coin } => PsbtRpcError::NoSuchCoin { coin },
+        }
+    }
+}
+
+impl From<PsbtError> for PsbtRpcError {
+    fn from(e: PsbtError) -> Self {
+        match e {
+            PsbtError::MissingSignature(_) | PsbtError::NotFinalized(_) => PsbtRpcError::NotFullySigned(e.to_string()),
+            _ => PsbtRpcError::InvalidPsbt(e.to_string()),
+        }
+    }
+}
+
+/// Adds the signatures of the inputs spending the outputs of our address or the HD account addresses.
+pub async fn sign_psbt(ctx: MmArc, req: SignPsbtRequest) -> SignPsbtResult {
+    let coin = lp_coinfind_or_err(&ctx, &req.coin).await?;
+    let utxo = utxo_fields(&coin, &req.coin)?;
+    let mut psbt = parse_and_merge(&req.psbt, &req.merge)?;
+    let signed_inputs = sign_utxo_psbt(utxo, &mut psbt)?;
+    let is_complete = psbt.clone().finalize().is_ok();
+    Ok(SignPsbtResponse {
+        psbt: psbt.to_bytes().into(),
+        signed_inputs,
+        is_complete,
+    })
+}
+
+/// Builds the final script sigs and witnesses and returns the signed transaction.
+pub async fn finalize_psbt(ctx: MmArc, req: FinalizePsbtRequest) -> FinalizePsbtResult {
+    let coin = lp_coinfind_or_err(&ctx, &req.coin).await?;
+    let utxo = utxo_fields(&coin, &req.coin)?;
+    let mut psbt = parse_and_merge(&req.psbt, &req.merge)?;
+    set_prev_txs_hash_algo(&mut psbt, utxo.tx_hash_algo);
+    psbt.finalize()?;
+    let mut tx: UtxoTx = psbt.extract_tx()?;
+    tx.tx_hash_algo = utxo.tx_hash_algo;
+    Ok(FinalizePsbtResponse {
+        tx_hex: serialize_with_flags(&tx, SERIALIZE_TRANSACTION_WITNESS).into(),
+        tx_hash: tx.hash().reversed().into(),
+    })
+}
+
+/// Creates the PSBT of the withdraw transaction spending the outputs of our address
+/// or the outputs of the `input_owners` HD account addresses if they're given.
+pub async fn withdraw_psbt(
+    utxo: &UtxoCoinFields,
+    unsigned: TransactionInputSigner,
+    input_owners: Option<&[UtxoHDAddress]>,
+) -> Result<Psbt, MmError<WithdrawError>> {
+    let amounts: Vec<u64> = unsigned.inputs.iter().map(|input| input.amount).collect();
+    let mut psbt =
+        Psbt::from_unsigned_tx(unsigned.into()).map_to_mm(|e| WithdrawError::InternalError(e.to_string()))?;
+
+    for (input_index, amount) in amounts.into_iter().enumerate() {
+        let owner = input_owners.map(|owners| &owners[input_index]);
+        let address = owner.map_or(&utxo.my_address, |owner| &owner.address);
+        let prev_hash = psbt.unsigned_tx.inputs[input_index].previous_output.hash;
+
+        let (witness_utxo, non_witness_utxo) = match address.addr_format {
+            UtxoAddressFormat::Segwit => {
+                let output = TransactionOutput {
+                    value: amount,
+                    script_pubkey: output_script(address, ScriptType::P2PKH).to_bytes(),
+                };
+                (Some(output), None)
+            },
+            // the whole previous transaction is required to sign the non-segwit input securely
+            _ => {
+                let prev_tx_bytes = utxo
+                    .rpc_client
+                    .get_transaction_bytes(prev_hash.reversed().into())
+                    .compat()
+                    .await?;
+                let mut prev_tx: UtxoTx = deserialize(prev_tx_bytes.as_slice())
+                    .map_to_mm(|e| WithdrawError::Transport(format!("Error deserializing {:?}: {:?}", prev_hash, e)))?;
+                prev_tx.tx_hash_algo = utxo.tx_hash_algo;
+                (None, Some(prev_tx))
+            },
+        };
+
+        let psbt_input = &mut psbt.inputs[input_index];
+        psbt_input.witness_utxo = witness_utxo;
+        psbt_input.non_witness_utxo = non_witness_utxo;
+        psbt_input.sighash_type = Some(1 | utxo.conf.fork_id);
+        if let (Some(hd_account), Some(owner)) = (&utxo.hd_account, owner) {
+            psbt_input
+                .bip32_derivation
+                .insert(owner.pubkey.to_vec(), hd_account.key_source(owner));
+        }
+    }
+    Ok(psbt)
+}
+
+/// The previous transactions are deserialized with the default hash algo,
+/// so their txids have to be recalculated with the coin's one to be checked against the spent outpoints.
+fn set_prev_txs_hash_algo(psbt: &mut Psbt, tx_hash_algo: TxHashAlgo) {
+    for prev_tx in psbt
+        .inputs
+        .iter_mut()
+        .filter_map(|input| input.non_witness_utxo.as_mut())
+    {
+        prev_tx.tx_hash_algo = tx_hash_algo;
+    }
+}
+
+/// Adds the partial signatures of the inputs which keys are available and returns the number of the signed inputs.
+/// The inputs of the other keys and the finalized inputs are skipped.
+pub fn sign_utxo_psbt(utxo: &UtxoCoinFields, psbt: &mut Psbt) -> Result<usize, MmError<PsbtRpcError>> {
+    set_prev_txs_hash_algo(psbt, utxo.tx_hash_algo);
+    let mut signer = TransactionInputSigner::from(psbt.unsigned_tx.clone());
+    signer.consensus_branch_id = utxo.conf.consensus_branch_id;
+    signer.hash_algo = utxo.tx_hash_algo.into();
+    let mut spent_outputs = Vec::with_capacity(psbt.inputs.len());
+    for (input_index, signer_input) in signer.inputs.iter_mut().enumerate() {
+        let spent_output = psbt.spent_output(input_index)?;
+        signer_input.amount = spent_output.value;
+        spent_outputs.push(spent_output);
+    }
+
+    let sighash_type = 1 | utxo.conf.fork_id;
+    let mut signed_inputs = 0;
+    for (input_index, spent_output) in spent_outputs.into_iter().enumerate() {
+        if psbt.inputs[input_index].is_finalized() {
+            continue;
+        }
+        let script: Script = spent_output.script_pubkey.into();
+        let (key_hash, signature_version) = if script.is_pay_to_public_key_hash() {
+            (&script[3..23], utxo.conf.signature_version)
+        } else if script.is_pay_to_witness_key_hash() {
+            (&script[2..22], SignatureVersion::WitnessV0)
+        } else {
+            continue;
+        };
+
+        let hd_key_pair;
+        let key_pair: &dyn Signer = if &*utxo.my_address.hash == key_hash {
+            match utxo.key_pair.signer() {
+                Ok(key_pair) => key_pair,
+                Err(WatchOnlyError) => continue,
+            }
+        } else {
+            let hd_account = match utxo.hd_account {
+                Some(ref hd_account) => hd_account,
+                None => continue,
+            };
+            let hd_address = hd_account
+                .all_addresses()
+                .into_iter()
+                .find(|hd_address| &*hd_address.address.hash == key_hash);
+            match hd_address {
+                // the watch-only account can't derive the key
+                Some(hd_address) => match hd_account.key_pair(&utxo.conf, &hd_address) {
+                    Ok(key_pair) => hd_key_pair = key_pair,
+                    Err(_) => continue,
+                },
+                None => continue,
+            }
+            &hd_key_pair
+        };
+
+        if let Some(requested) = psbt.inputs[input_index].sighash_type {
+            if requested != sighash_type {
+                return MmError::err(PsbtRpcError::UnsupportedSighashType {
+                    input: input_index,
+                    sighash_type: requested,
+                });
+            }
+        }
+        let prev_script = Builder::build_p2pkh(&key_pair.public().address_hash());
+        let sighash = signer.signature_hash(
+            input_index,
+            signer.inputs[input_index].amount,
+            &prev_script,
+            signature_version,
+            sighash_type,
+        );
+        let signature = script_sig(&sighash, key_pair, utxo.conf.fork_id).map_to_mm(PsbtRpcError::InternalError)?;
+
+        let psbt_input = &mut psbt.inputs[input_index];
+        psbt_input
+            .partial_sigs
+            .insert(key_pair.public().to_vec(), signature.take());
+        psbt_input.sighash_type = Some(sighash_type);
+        signed_inputs += 1;
+    }
+    Ok(signed_inputs)
+}
+
+fn parse_and_merge(psbt: &BytesJson, merge: &[BytesJson]) -> Result<Psbt, MmError<PsbtRpcError>> {
+    let mut psbt = Psbt::from_bytes(psbt)?;
+    for other in merge {
+        psbt.merge(Psbt::from_bytes(other)?)?;
+    }
+    Ok(psbt)
+}
+
+fn utxo_fields<'a>(coin: &'a MmCoinEnum, ticker: &str) -> Result<&'a UtxoCoinFields, MmError<PsbtRpcError>> {
+    match coin {
+        MmCoinEnum::UtxoCoin(utxo) => Ok(utxo.as_ref()),
+        MmCoinEnum::QtumCoin(qtum) => Ok(qtum.as_ref()),
+        _ => MmError::err(PsbtRpcError::CoinDoesNotSupportPsbt {
+            coin: ticker.to_owned(),
+        }),
+    }
+}
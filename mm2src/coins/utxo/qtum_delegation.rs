@@ -0,0 +1,451 @@
+//! Qtum offline staking: the delegation of the coins of our address to a staker (a super staker).
+//!
+//! The delegation is the `addDelegation` call of the Delegations contract deployed at `0x...86`.
+//! The call carries the Proof of Delegation (PoD): the hex of the staker address hash signed by our key.
+//! The delegated coins stay at our address and can be traded, the staker keeps `fee` percent of the rewards.
+//! `add_delegation` and `remove_delegation` return the signed transaction that should be sent with `send_raw_transaction`,
+//! the same as `withdraw`. `get_delegation_info` reads the state of our delegation from the contract.
+
+use super::qtum::{self, QtumBasedCoin, QtumCoin};
+use super::rpc_clients::UtxoRpcError;
+use super::utxo_common::{self, big_decimal_from_sat_unsigned};
+use super::{GenerateTxError, UtxoAddressFormat, UtxoCoinFields, UTXO_LOCK};
+use crate::qrc20::rpc_clients::{Qrc20RpcOps, ViewContractCallType};
+use crate::qrc20::script_pubkey::generate_contract_call_script_pubkey;
+use crate::qrc20::{generate_contract_call_transaction, ContractCallOutput, GenerateQrc20TxResult, Qrc20AbiError,
+                   Qrc20FeeDetails, OUTPUT_QTUM_AMOUNT, QRC20_GAS_PRICE_DEFAULT};
+use crate::watch_only::WatchOnlyError;
+use crate::{lp_coinfind_or_err, BalanceError, CoinFindError, MarketCoinOps, MmCoinEnum, TransactionDetails};
+use bigdecimal::BigDecimal;
+use common::mm_ctx::MmArc;
+use common::mm_error::prelude::*;
+use common::{now_ms, HttpStatusCode};
+use derive_more::Display;
+use ethabi::{Contract, Token};
+use ethereum_types::H160;
+use futures::compat::Future01CompatExt;
+use http::StatusCode;
+use keys::{CompactSignature, Public};
+use serialization::serialize;
+
+pub type DelegationResult<T> = Result<T, MmError<DelegationError>>;
+
+const DELEGATIONS_CONTRACT_ABI: &str = r#"[{"constant":false,"inputs":[{"name":"_staker","type":"address"},{"name":"_fee","type":"uint8"},{"name":"_PoD","type":"bytes"}],"name":"addDelegation","outputs":[],"payable":false,"stateMutability":"nonpayable","type":"function"},{"constant":false,"inputs":[],"name":"removeDelegation","outputs":[],"payable":false,"stateMutability":"nonpayable","type":"function"},{"constant":true,"inputs":[{"name":"","type":"address"}],"name":"delegations","outputs":[{"name":"staker","type":"address"},{"name":"fee","type":"uint8"},{"name":"blockHeight","type":"uint256"},{"name":"PoD","type":"bytes"}],"payable":false,"stateMutability":"view","type":"function"}]"#;
+/// The address of the Delegations contract is the same in the Qtum mainnet and testnet.
+const DELEGATIONS_CONTRACT_ADDRESS: &str = "0x0000000000000000000000000000000000000086";
+/// The gas limit of the delegation calls used by Qtum Core and Qtum Electrum.
+const QTUM_DELEGATION_GAS_LIMIT: u64 = 2_250_000;
+/// The default percent of the staking rewards taken by the staker.
+const QTUM_DELEGATION_DEFAULT_FEE: u8 = 10;
+/// Qtum Core verifies the PoD with this prefix regardless of the `sign_message_prefix` of the coin config.
+const QTUM_SIGNED_MESSAGE_PREFIX: &str = "Qtum Signed Message:\n";
+
+lazy_static! {
+    pub static ref DELEGATIONS_CONTRACT: Contract = Contract::load(DELEGATIONS_CONTRACT_ABI.as_bytes()).unwrap();
+}
+
+#[derive(Deserialize)]
+pub struct AddDelegationRequest {
+    coin: String,
+    /// The address of the staker in the wallet or the contract format.
+    staker_address: String,
+    /// The percent of the staking rewards taken by the staker, 10 by default.
+    fee: Option<u8>,
+}
+
+#[derive(Deserialize)]
+pub struct RemoveDelegationRequest {
+    coin: String,
+}
+
+#[derive(Deserialize)]
+pub struct GetDelegationInfoRequest {
+    coin: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DelegationInfo {
+    /// Whether our coins are delegated to a staker.
+    pub am_i_staking: bool,
+    /// The address of the staker. Not set if there is no delegation.
+    pub staker_address: Option<String>,
+    /// The percent of the staking rewards taken by the staker. Not set if there is no delegation.
+    pub fee: Option<u8>,
+    /// The height of the block the delegation was added at. Not set if there is no delegation.
+    pub block_height: Option<u64>,
+    /// The spendable balance of our address staked by the staker.
+    pub staked_amount: BigDecimal,
+}
+
+#[derive(Debug, Display, Serialize, SerializeErrorType)]
+#[serde(tag = "error_type", content = "error_data")]
+pub enum DelegationError {
+    #[display(fmt = "No such coin {}", coin)]
+    NoSuchCoin { coin: String },
+    #[display(fmt = "{} doesn't support the staking delegation", coin)]
+    CoinDoesNotSupportDelegation { coin: String },
+    #[display(fmt = "Invalid staker address: {}", _0)]
+    InvalidStakerAddress(String),
+    #[display(fmt = "Staker fee {} exceeds 100 percent", fee)]
+    InvalidFee { fee: u8 },
+    #[display(fmt = "The coins are already delegated to {}, remove the delegation first", staker)]
+    AlreadyDelegating { staker: String },
+    #[display(fmt = "The coins are not delegated")]
+    NotDelegating,
+    #[display(fmt = "Only P2PKH addresses can delegate the coins, {} is not", address)]
+    AddressNotSupported { address: String },
+    #[display(fmt = "Not sufficient balance to pay the delegation fee: {}", _0)]
+    NotSufficientBalance(String),
+    #[display(fmt = "MM2 runs in the watch-only mode, the delegation can't be signed")]
+    WatchOnly,
+    #[display(fmt = "Transport error: {}", _0)]
+    Transport(String),
+    #[display(fmt = "Internal error: {}", _0)]
+    InternalError(String),
+}
+
+impl HttpStatusCode for DelegationError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            DelegationError::NoSuchCoin { .. }
+            | DelegationError::CoinDoesNotSupportDelegation { .. }
+            | DelegationError::InvalidStakerAddress(_)
+            | DelegationError::InvalidFee { .. }
+            | DelegationError::AlreadyDelegating { .. }
+            | DelegationError::NotDelegating
+            | DelegationError::AddressNotSupported { .. }
+            | DelegationError::NotSufficientBalance(_) => StatusCode::BAD_REQUEST,
+            DelegationError::WatchOnly => StatusCode::METHOD_NOT_ALLOWED,
+            DelegationError::Transport(_) | DelegationError::InternalError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl From<CoinFindError> for DelegationError {
+    fn from(e: CoinFindError) -> Self {
+        match e {
+            CoinFindError::NoSuchCoin { coin } => DelegationError::NoSuchCoin { coin },
+        }
+    }
+}
+
+impl From<WatchOnlyError> for DelegationError {
+    fn from(_: WatchOnlyError) -> Self { DelegationError::WatchOnly }
+}
+
+impl From<Qrc20AbiError> for DelegationError {
+    fn from(e: Qrc20AbiError) -> Self { DelegationError::InternalError(e.to_string()) }
+}
+
+impl From<ethabi::Error> for DelegationError {
+    fn from(e: ethabi::Error) -> Self { DelegationError::InternalError(e.to_string()) }
+}
+
+impl From<UtxoRpcError> for DelegationError {
+    fn from(e: UtxoRpcError) -> Self {
+        match e {
+            UtxoRpcError::Transport(rpc) | UtxoRpcError::ResponseParseError(rpc) | UtxoRpcError::Unauthorized(rpc) => {
+                DelegationError::Transport(rpc.to_string())
+            },
+            UtxoRpcError::InvalidResponse(error) => DelegationError::Transport(error),
+            UtxoRpcError::Internal(error) => DelegationError::InternalError(error),
+        }
+    }
+}
+
+impl From<BalanceError> for DelegationError {
+    fn from(e: BalanceError) -> Self {
+        match e {
+            BalanceError::Transport(error) | BalanceError::InvalidResponse(error) => DelegationError::Transport(error),
+            BalanceError::Internal(error) => DelegationError::InternalError(error),
+        }
+    }
+}
+
+impl From<GenerateTxError> for DelegationError {
+    fn from(e: GenerateTxError) -> Self {
+        match e {
+            GenerateTxError::EmptyUtxoSet { .. }
+            | GenerateTxError::DeductFeeFromOutputFailed { .. }
+            | GenerateTxError::NotEnoughUtxos { .. } => DelegationError::NotSufficientBalance(e.to_string()),
+            GenerateTxError::Transport(error) => DelegationError::Transport(error),
+            _ => DelegationError::InternalError(e.to_string()),
+        }
+    }
+}
+
+pub async fn add_delegation(ctx: MmArc, req: AddDelegationRequest) -> DelegationResult<TransactionDetails> {
+    if ctx.is_watch_only() {
+        return MmError::err(DelegationError::WatchOnly);
+    }
+    match lp_coinfind_or_err(&ctx, &req.coin).await? {
+        MmCoinEnum::QtumCoin(qtum) => qtum_add_delegation(&qtum, req).await,
+        _ => MmError::err(DelegationError::CoinDoesNotSupportDelegation { coin: req.coin }),
+    }
+}
+
+pub async fn remove_delegation(ctx: MmArc, req: RemoveDelegationRequest) -> DelegationResult<TransactionDetails> {
+    if ctx.is_watch_only() {
+        return MmError::err(DelegationError::WatchOnly);
+    }
+    match lp_coinfind_or_err(&ctx, &req.coin).await? {
+        MmCoinEnum::QtumCoin(qtum) => qtum_remove_delegation(&qtum).await,
+        _ => MmError::err(DelegationError::CoinDoesNotSupportDelegation { coin: req.coin }),
+    }
+}
+
+pub async fn get_delegation_info(ctx: MmArc, req: GetDelegationInfoRequest) -> DelegationResult<DelegationInfo> {
+    match lp_coinfind_or_err(&ctx, &req.coin).await? {
+        MmCoinEnum::QtumCoin(qtum) => qtum_delegation_info(&qtum).await,
+        _ => MmError::err(DelegationError::CoinDoesNotSupportDelegation { coin: req.coin }),
+    }
+}
+
+async fn qtum_add_delegation(coin: &QtumCoin, req: AddDelegationRequest) -> DelegationResult<TransactionDetails> {
+    ensure_p2pkh_address(coin.as_ref())?;
+    let fee = req.fee.unwrap_or(QTUM_DELEGATION_DEFAULT_FEE);
+    if fee > 100 {
+        return MmError::err(DelegationError::InvalidFee { fee });
+    }
+    let staker_address = coin
+        .utxo_address_from_any_format(&req.staker_address)
+        .map_to_mm(DelegationError::InvalidStakerAddress)?;
+    let staker = qtum::contract_addr_from_utxo_addr(staker_address.clone());
+
+    if let Some((current_staker, ..)) = delegation_state(coin).await? {
+        return MmError::err(DelegationError::AlreadyDelegating {
+            staker: display_staker_address(coin, current_staker)?,
+        });
+    }
+
+    let pod = generate_pod(coin.as_ref(), &staker)?;
+    let output = add_delegation_output(staker, fee, pod)?;
+    let to = staker_address
+        .display_address()
+        .map_to_mm(DelegationError::InternalError)?;
+    send_delegation_call(coin, output, to).await
+}
+
+async fn qtum_remove_delegation(coin: &QtumCoin) -> DelegationResult<TransactionDetails> {
+    ensure_p2pkh_address(coin.as_ref())?;
+    let staker = match delegation_state(coin).await? {
+        Some((staker, ..)) => staker,
+        None => return MmError::err(DelegationError::NotDelegating),
+    };
+    let output = remove_delegation_output()?;
+    let to = display_staker_address(coin, staker)?;
+    send_delegation_call(coin, output, to).await
+}
+
+async fn qtum_delegation_info(coin: &QtumCoin) -> DelegationResult<DelegationInfo> {
+    let (staker, fee, block_height) = match delegation_state(coin).await? {
+        Some(state) => state,
+        None => {
+            return Ok(DelegationInfo {
+                am_i_staking: false,
+                staker_address: None,
+                fee: None,
+                block_height: None,
+                staked_amount: 0.into(),
+            })
+        },
+    };
+    let staked_amount = coin.my_spendable_balance().compat().await?;
+    Ok(DelegationInfo {
+        am_i_staking: true,
+        staker_address: Some(display_staker_address(coin, staker)?),
+        fee: Some(fee),
+        block_height: Some(block_height),
+        staked_amount,
+    })
+}
+
+/// The Delegations contract takes the delegator from the sender of the call, that has to be a P2PKH address.
+fn ensure_p2pkh_address(utxo: &UtxoCoinFields) -> DelegationResult<()> {
+    if utxo.my_address.addr_format == UtxoAddressFormat::Segwit {
+        let address = utxo
+            .my_address
+            .display_address()
+            .map_to_mm(DelegationError::InternalError)?;
+        return MmError::err(DelegationError::AddressNotSupported { address });
+    }
+    Ok(())
+}
+
+fn display_staker_address(coin: &QtumCoin, staker: H160) -> DelegationResult<String> {
+    coin.utxo_address_from_contract_addr(staker)
+        .display_address()
+        .map_to_mm(DelegationError::InternalError)
+}
+
+/// Returns the staker, the fee and the block height of our delegation if there is one.
+async fn delegation_state(coin: &QtumCoin) -> DelegationResult<Option<(H160, u8, u64)>> {
+    let contract_address = delegations_contract_address()?;
+    let params = [Token::Address(coin.my_addr_as_contract_addr())];
+    let tokens = coin
+        .as_ref()
+        .rpc_client
+        .rpc_contract_call(ViewContractCallType::Delegations, &contract_address, &params)
+        .compat()
+        .await?;
+    parse_delegation_state(&tokens)
+}
+
+fn parse_delegation_state(tokens: &[Token]) -> DelegationResult<Option<(H160, u8, u64)>> {
+    match tokens {
+        [Token::Address(staker), Token::Uint(fee), Token::Uint(block_height), Token::Bytes(_pod)] => {
+            // the contract returns the zero staker address if there is no delegation
+            if staker.is_zero() {
+                return Ok(None);
+            }
+            Ok(Some((*staker, fee.low_u32() as u8, block_height.low_u64())))
+        },
+        _ => MmError::err(DelegationError::Transport(format!(
+            "Unexpected 'delegations' result {:?}",
+            tokens
+        ))),
+    }
+}
+
+fn delegations_contract_address() -> DelegationResult<H160> {
+    qtum::contract_addr_from_str(DELEGATIONS_CONTRACT_ADDRESS).map_to_mm(DelegationError::InternalError)
+}
+
+/// Returns the hash of the message signed to get the PoD: the hex of the staker address hash.
+fn pod_message_hash(staker: &H160) -> primitives::hash::H256 {
+    utxo_common::signed_message_hash(QTUM_SIGNED_MESSAGE_PREFIX, &hex::encode(staker.0))
+}
+
+/// Generates the Proof of Delegation: the compact recoverable signature of the staker address hash hex.
+fn generate_pod(utxo: &UtxoCoinFields, staker: &H160) -> DelegationResult<Vec<u8>> {
+    let rsv = utxo
+        .key_pair
+        .signer()?
+        .sign_hash_recoverable(&pod_message_hash(staker))
+        .map_to_mm(DelegationError::InternalError)?;
+    let compressed = matches!(utxo.key_pair.public(), Public::Compressed(_));
+    let signature = CompactSignature::from_parts(rsv[64], &rsv[..64], compressed);
+    Ok(signature[..].to_vec())
+}
+
+fn add_delegation_output(staker: H160, fee: u8, pod: Vec<u8>) -> DelegationResult<ContractCallOutput> {
+    let function = DELEGATIONS_CONTRACT.function("addDelegation")?;
+    let params = function.encode_input(&[Token::Address(staker), Token::Uint(fee.into()), Token::Bytes(pod)])?;
+    delegation_call_output(&params)
+}
+
+fn remove_delegation_output() -> DelegationResult<ContractCallOutput> {
+    let function = DELEGATIONS_CONTRACT.function("removeDelegation")?;
+    let params = function.encode_input(&[])?;
+    delegation_call_output(&params)
+}
+
+fn delegation_call_output(params: &[u8]) -> DelegationResult<ContractCallOutput> {
+    let contract_address = delegations_contract_address()?;
+    let gas_limit = QTUM_DELEGATION_GAS_LIMIT;
+    let gas_price = QRC20_GAS_PRICE_DEFAULT;
+    let script_pubkey =
+        generate_contract_call_script_pubkey(params, gas_limit, gas_price, &contract_address)?.to_bytes();
+    Ok(ContractCallOutput {
+        value: OUTPUT_QTUM_AMOUNT,
+        script_pubkey,
+        gas_limit,
+        gas_price,
+    })
+}
+
+/// Generates and signs the transaction with the delegation call. The transaction isn't broadcasted.
+async fn send_delegation_call(
+    coin: &QtumCoin,
+    output: ContractCallOutput,
+    to: String,
+) -> DelegationResult<TransactionDetails> {
+    let (gas_limit, gas_price) = (output.gas_limit, output.gas_price);
+    let utxo = coin.as_ref();
+    let decimals = utxo.decimals;
+
+    let _utxo_lock = UTXO_LOCK.lock().await;
+    let GenerateQrc20TxResult {
+        signed,
+        miner_fee,
+        gas_fee,
+        spent_by_me,
+        received_by_me,
+    } = generate_contract_call_transaction(coin, vec![output]).await?;
+
+    let my_address = coin.my_address().map_to_mm(DelegationError::InternalError)?;
+    let fee_details = Qrc20FeeDetails {
+        coin: utxo.conf.ticker.clone(),
+        miner_fee: big_decimal_from_sat_unsigned(miner_fee, decimals),
+        gas_limit,
+        gas_price,
+        total_gas_fee: big_decimal_from_sat_unsigned(gas_fee, decimals),
+    };
+    let spent_by_me = big_decimal_from_sat_unsigned(spent_by_me, decimals);
+    let received_by_me = big_decimal_from_sat_unsigned(received_by_me, decimals);
+    Ok(TransactionDetails {
+        from: vec![my_address],
+        to: vec![to],
+        total_amount: spent_by_me.clone(),
+        my_balance_change: &received_by_me - &spent_by_me,
+        spent_by_me,
+        received_by_me,
+        tx_hash: signed.hash().reversed().to_vec().into(),
+        tx_hex: serialize(&signed).into(),
+        fee_details: Some(fee_details.into()),
+        block_height: 0,
+        coin: utxo.conf.ticker.clone(),
+        internal_id: vec![].into(),
+        timestamp: now_ms() / 1000,
+        kmd_rewards: None,
+        psbt: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethereum_types::U256;
+
+    #[test]
+    fn test_parse_delegation_state() {
+        let staker = qtum::contract_addr_from_str("0x7d7d65d6c6c8f2e5f7d8d6f53ee4fe4d9b4e3d70").unwrap();
+        let tokens = vec![
+            Token::Address(staker),
+            Token::Uint(10.into()),
+            Token::Uint(U256::from(658_345)),
+            Token::Bytes(vec![1; 65]),
+        ];
+        assert_eq!(parse_delegation_state(&tokens).unwrap(), Some((staker, 10, 658_345)));
+
+        let tokens = vec![
+            Token::Address(H160::zero()),
+            Token::Uint(0.into()),
+            Token::Uint(0.into()),
+            Token::Bytes(vec![]),
+        ];
+        assert_eq!(parse_delegation_state(&tokens).unwrap(), None);
+
+        parse_delegation_state(&[Token::Bool(true)]).unwrap_err();
+    }
+
+    #[test]
+    fn test_add_delegation_output() {
+        let staker = qtum::contract_addr_from_str("0x7d7d65d6c6c8f2e5f7d8d6f53ee4fe4d9b4e3d70").unwrap();
+        let output = add_delegation_output(staker, 10, vec![1; 65]).unwrap();
+        assert_eq!(output.value, OUTPUT_QTUM_AMOUNT);
+        assert_eq!(output.gas_limit, QTUM_DELEGATION_GAS_LIMIT);
+
+        let short_signature = DELEGATIONS_CONTRACT
+            .function("addDelegation")
+            .unwrap()
+            .short_signature();
+        let script: &[u8] = &output.script_pubkey;
+        assert!(script
+            .windows(short_signature.len())
+            .any(|window| window == short_signature));
+        // the script ends with the contract address followed by OP_CALL
+        let contract_address = delegations_contract_address().unwrap();
+        assert_eq!(&script[script.len() - 21..script.len() - 1], &contract_address.0[..]);
+    }
+}
@@ -1,20 +1,25 @@
 #![cfg_attr(target_arch = "wasm32", allow(unused_macros))]
 #![cfg_attr(target_arch = "wasm32", allow(dead_code))]
 
+use crate::utxo::spv::BlockHeaderStorage;
 use crate::utxo::{output_script, sat_from_big_decimal};
-use crate::{NumConversError, RpcTransportEventHandler, RpcTransportEventHandlerShared};
+use crate::{wait_for_notification, CoinNotifications, NumConversError, RpcTransportEventHandler,
+            RpcTransportEventHandlerShared};
 use bigdecimal::BigDecimal;
 use chain::{BlockHeader, OutPoint, Transaction as UtxoTx};
 use common::custom_futures::{select_ok_sequential, FutureTimerExt};
 use common::executor::{spawn, Timer};
-use common::jsonrpc_client::{JsonRpcClient, JsonRpcError, JsonRpcErrorType, JsonRpcMultiClient, JsonRpcRemoteAddr,
-                             JsonRpcRequest, JsonRpcResponse, JsonRpcResponseFut, RpcRes};
+use common::jsonrpc_client::{JsonRpcBatchClient, JsonRpcBatchResponseFut, JsonRpcClient, JsonRpcError,
+                             JsonRpcErrorType, JsonRpcMultiClient, JsonRpcRemoteAddr, JsonRpcRequest, JsonRpcResponse,
+                             JsonRpcResponseFut, RpcRes};
 use common::log::{error, info, warn};
 use common::mm_error::prelude::*;
 use common::mm_number::MmNumber;
+use common::proxy::Socks5Proxy;
 use common::wio::slurp_req;
 use common::{median, now_float, now_ms, OrdRange};
 use derive_more::Display;
+use futures::channel::mpsc::{unbounded, UnboundedSender};
 use futures::channel::oneshot as async_oneshot;
 use futures::compat::{Future01CompatExt, Stream01CompatExt};
 use futures::future::{select as select_func, FutureExt, TryFutureExt};
@@ -29,6 +34,8 @@ use http::{Request, StatusCode};
 use keys::{Address, Type as ScriptType};
 #[cfg(test)] use mocktopus::macros::*;
 use rpc::v1::types::{Bytes as BytesJson, Transaction as RpcTransaction, H256 as H256Json};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use serde_json::{self as json, Value as Json};
 use serialization::{deserialize, serialize, serialize_with_flags, CoinVariant, CompactInteger, Reader,
                     SERIALIZE_TRANSACTION_WITNESS};
@@ -39,8 +46,9 @@ use std::io;
 use std::net::{SocketAddr, ToSocketAddrs};
 use std::num::NonZeroU64;
 use std::ops::Deref;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 cfg_native! {
@@ -123,6 +131,11 @@ impl UtxoRpcClientEnum {
         let tx = tx.clone();
         let selfi = self.clone();
         let fut = async move {
+            // Electrum notifies about the new blocks, so the transaction is checked as soon as a block is mined
+            let mut new_blocks = match selfi {
+                UtxoRpcClientEnum::Electrum(ref electrum) => Some(electrum.header_notifications()),
+                UtxoRpcClientEnum::Native(_) => None,
+            };
             loop {
                 if now_ms() / 1000 > wait_until {
                     return ERR!(
@@ -163,7 +176,12 @@ impl UtxoRpcClientEnum {
                     ),
                 }
 
-                Timer::sleep(check_every as f64).await;
+                match new_blocks {
+                    Some(ref mut new_blocks) => {
+                        wait_for_notification(new_blocks, check_every as f64).await;
+                    },
+                    None => Timer::sleep(check_every as f64).await,
+                }
             }
         };
         Box::new(fut.boxed().compat())
@@ -201,6 +219,8 @@ pub type UtxoRpcFut<T> = Box<dyn Future<Item = T, Error = MmError<UtxoRpcError>>
 pub enum UtxoRpcError {
     Transport(JsonRpcError),
     ResponseParseError(JsonRpcError),
+    /// The native daemon rejected the credentials, see `NativeClientAuthError`.
+    Unauthorized(JsonRpcError),
     InvalidResponse(String),
     Internal(String),
 }
@@ -210,6 +230,7 @@ impl From<JsonRpcError> for UtxoRpcError {
         match e.error {
             JsonRpcErrorType::Transport(_) => UtxoRpcError::Transport(e),
             JsonRpcErrorType::Parse(_, _) | JsonRpcErrorType::Response(_, _) => UtxoRpcError::ResponseParseError(e),
+            JsonRpcErrorType::Unauthorized(_) => UtxoRpcError::Unauthorized(e),
         }
     }
 }
@@ -234,6 +255,9 @@ pub trait UtxoRpcClientOps: fmt::Debug + Send + Sync + 'static {
 
     fn get_verbose_transaction(&self, txid: H256Json) -> RpcRes<RpcTransaction>;
 
+    /// Requests the verbose transactions in one batch, the transactions are returned in the order of `txids`.
+    fn get_verbose_transactions(&self, txids: &[H256Json]) -> RpcRes<Vec<RpcTransaction>>;
+
     fn get_block_count(&self) -> UtxoRpcFut<u64>;
 
     fn display_balance(&self, address: Address, decimals: u8) -> RpcRes<BigDecimal>;
@@ -469,8 +493,8 @@ pub struct NativeClientImpl {
     pub coin_ticker: String,
     /// The uri to send requests to
     pub uri: String,
-    /// Value of Authorization header, e.g. "Basic base64(user:password)"
-    pub auth: String,
+    /// The credentials used to build the Authorization header
+    pub auth: Arc<NativeClientAuth>,
     /// Transport event handlers
     pub event_handlers: Vec<RpcTransportEventHandlerShared>,
     pub request_id: AtomicU64,
@@ -483,7 +507,7 @@ impl Default for NativeClientImpl {
         NativeClientImpl {
             coin_ticker: "TEST".to_string(),
             uri: "".to_string(),
-            auth: "".to_string(),
+            auth: Arc::new(NativeClientAuth::Basic("".to_string())),
             event_handlers: vec![],
             request_id: Default::default(),
             list_unspent_concurrent_map: ConcurrentRequestMap::new(),
@@ -491,6 +515,87 @@ impl Default for NativeClientImpl {
     }
 }
 
+#[derive(Debug, Display)]
+pub enum NativeClientAuthError {
+    #[display(fmt = "Both 'rpc_user' and 'rpc_password' are expected in the request")]
+    IncompleteCredentials,
+    #[display(fmt = "Couldn't read the cookie file '{}': {}", path, error)]
+    CookieRead { path: String, error: String },
+    #[display(fmt = "The cookie file '{}' is expected to contain 'user:password'", path)]
+    InvalidCookie { path: String },
+    #[display(
+        fmt = "The daemon {} rejected the credentials, check 'rpcuser' and 'rpcpassword' or the cookie file",
+        uri
+    )]
+    Unauthorized { uri: String },
+}
+
+/// The credentials of the native daemon RPC.
+#[derive(Debug)]
+pub enum NativeClientAuth {
+    /// The value of Authorization header built from the static `rpcuser` and `rpcpassword`.
+    Basic(String),
+    /// The cookie file the daemon writes random credentials to on every start.
+    Cookie { path: PathBuf, header: Mutex<String> },
+}
+
+impl NativeClientAuth {
+    pub fn basic(user: &str, password: &str) -> NativeClientAuth {
+        NativeClientAuth::Basic(basic_auth_header(user, password))
+    }
+
+    pub fn cookie(path: PathBuf) -> Result<NativeClientAuth, NativeClientAuthError> {
+        let header = read_cookie_auth_header(&path)?;
+        Ok(NativeClientAuth::Cookie {
+            path,
+            header: Mutex::new(header),
+        })
+    }
+
+    /// Value of Authorization header, e.g. "Basic base64(user:password)"
+    pub fn header(&self) -> String {
+        match self {
+            NativeClientAuth::Basic(header) => header.clone(),
+            NativeClientAuth::Cookie { header, .. } => header.lock().unwrap().clone(),
+        }
+    }
+
+    /// Re-reads the cookie file, returns `true` if the daemon has rotated the credentials.
+    pub fn refresh(&self) -> Result<bool, NativeClientAuthError> {
+        match self {
+            NativeClientAuth::Basic(_) => Ok(false),
+            NativeClientAuth::Cookie { path, header } => {
+                let actual = read_cookie_auth_header(path)?;
+                let mut header = header.lock().unwrap();
+                if *header == actual {
+                    return Ok(false);
+                }
+                *header = actual;
+                Ok(true)
+            },
+        }
+    }
+}
+
+fn basic_auth_header(user: &str, password: &str) -> String {
+    format!("Basic {}", base64::encode(&format!("{}:{}", user, password)))
+}
+
+/// The cookie file contains `__cookie__:password`.
+fn read_cookie_auth_header(path: &Path) -> Result<String, NativeClientAuthError> {
+    let content = std::fs::read_to_string(path).map_err(|e| NativeClientAuthError::CookieRead {
+        path: path.display().to_string(),
+        error: e.to_string(),
+    })?;
+    let mut credentials = content.trim().splitn(2, ':');
+    match (credentials.next(), credentials.next()) {
+        (Some(user), Some(password)) if !user.is_empty() => Ok(basic_auth_header(user, password)),
+        _ => Err(NativeClientAuthError::InvalidCookie {
+            path: path.display().to_string(),
+        }),
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct NativeClient(pub Arc<NativeClientImpl>);
 impl Deref for NativeClient {
@@ -498,6 +603,16 @@ impl Deref for NativeClient {
     fn deref(&self) -> &NativeClientImpl { &*self.0 }
 }
 
+/// Builds the request to be sent in a batch.
+fn batch_request_item<C: JsonRpcClient>(client: &C, method: &str, params: Vec<Json>) -> JsonRpcRequest {
+    JsonRpcRequest {
+        jsonrpc: client.version().into(),
+        id: client.next_id(),
+        method: method.into(),
+        params,
+    }
+}
+
 /// The trait provides methods to generate the JsonRpcClient instance info such as name of coin.
 pub trait UtxoJsonRpcClientInfo: JsonRpcClient {
     /// Name of coin the rpc client is intended to work with
@@ -518,41 +633,81 @@ impl JsonRpcClient for NativeClientImpl {
 
     fn client_info(&self) -> String { UtxoJsonRpcClientInfo::client_info(self) }
 
-    fn transport(&self, request: JsonRpcRequest) -> JsonRpcResponseFut {
-        let request_body = try_fus!(json::to_string(&request));
-        // measure now only body length, because the `hyper` crate doesn't allow to get total HTTP packet length
-        self.event_handlers.on_outgoing_request(request_body.as_bytes());
+    fn transport(&self, request: JsonRpcRequest) -> JsonRpcResponseFut { self.post_json(request) }
+}
+
+impl JsonRpcBatchClient for NativeClientImpl {
+    fn transport_batch(&self, requests: Vec<JsonRpcRequest>) -> JsonRpcBatchResponseFut { self.post_json(requests) }
+}
 
+impl NativeClientImpl {
+    /// Posts the request or the batch of the requests and parses the response body as `Res`.
+    fn post_json<Req, Res>(
+        &self,
+        request: Req,
+    ) -> Box<dyn Future<Item = (JsonRpcRemoteAddr, Res), Error = JsonRpcErrorType> + Send + 'static>
+    where
+        Req: Serialize + fmt::Debug + Send + 'static,
+        Res: DeserializeOwned + Send + 'static,
+    {
+        let request_body = match json::to_string(&request) {
+            Ok(body) => body,
+            Err(e) => return Box::new(futures01::future::err(JsonRpcErrorType::Transport(ERRL!("{}", e)))),
+        };
         let uri = self.uri.clone();
+        let auth = self.auth.clone();
+        let event_handles = self.event_handlers.clone();
 
-        let http_request = try_fus!(Request::builder()
-            .method("POST")
-            .header(AUTHORIZATION, self.auth.clone())
-            .uri(uri.clone())
-            .body(Vec::from(request_body)));
+        let fut = async move {
+            let mut auth_refreshed = false;
+            // the auth errors are returned as `JsonRpcErrorType::Unauthorized`, the others as `Transport`
+            loop {
+                // measure now only body length, because the `hyper` crate doesn't allow to get total HTTP packet length
+                event_handles.on_outgoing_request(request_body.as_bytes());
 
-        let event_handles = self.event_handlers.clone();
-        Box::new(slurp_req(http_request).boxed().compat().then(
-            move |result| -> Result<(JsonRpcRemoteAddr, JsonRpcResponse), String> {
-                let res = try_s!(result);
+                let http_request = Request::builder()
+                    .method("POST")
+                    .header(AUTHORIZATION, auth.header())
+                    .uri(uri.clone())
+                    .body(Vec::from(request_body.clone()))
+                    .map_err(|e| JsonRpcErrorType::Transport(ERRL!("{}", e)))?;
+
+                let res = slurp_req(http_request)
+                    .await
+                    .map_err(|e| JsonRpcErrorType::Transport(ERRL!("{}", e)))?;
                 // measure now only body length, because the `hyper` crate doesn't allow to get total HTTP packet length
                 event_handles.on_incoming_response(&res.2);
 
-                let body = try_s!(std::str::from_utf8(&res.2));
+                if res.0 == StatusCode::UNAUTHORIZED {
+                    // the daemon writes the new cookie on restart, retry once with the actual credentials
+                    let refreshed = !auth_refreshed
+                        && auth
+                            .refresh()
+                            .map_err(|e| JsonRpcErrorType::Unauthorized(ERRL!("{}", e)))?;
+                    if refreshed {
+                        auth_refreshed = true;
+                        continue;
+                    }
+                    let error = NativeClientAuthError::Unauthorized { uri };
+                    return Err(JsonRpcErrorType::Unauthorized(ERRL!("{}", error)));
+                }
+
+                let body = std::str::from_utf8(&res.2).map_err(|e| JsonRpcErrorType::Transport(ERRL!("{}", e)))?;
 
                 if res.0 != StatusCode::OK {
-                    return ERR!(
+                    return Err(JsonRpcErrorType::Transport(ERRL!(
                         "Rpc request {:?} failed with HTTP status code {}, response body: {}",
                         request,
                         res.0,
                         body
-                    );
+                    )));
                 }
 
-                let response = try_s!(json::from_str(body));
-                Ok((uri.into(), response))
-            },
-        ))
+                let response = json::from_str(body).map_err(|e| JsonRpcErrorType::Transport(ERRL!("{}", e)))?;
+                return Ok((uri.into(), response));
+            }
+        };
+        Box::new(fut.boxed().compat())
     }
 }
 
@@ -603,6 +758,15 @@ impl UtxoRpcClientOps for NativeClient {
         self.get_raw_transaction_verbose(txid)
     }
 
+    fn get_verbose_transactions(&self, txids: &[H256Json]) -> RpcRes<Vec<RpcTransaction>> {
+        let verbose = 1;
+        let requests = txids
+            .iter()
+            .map(|txid| batch_request_item(&*self.0, "getrawtransaction", vec![json!(txid), json!(verbose)]))
+            .collect();
+        self.send_batch_request(requests)
+    }
+
     fn get_block_count(&self) -> UtxoRpcFut<u64> {
         Box::new(self.0.get_block_count().map_to_mm_fut(UtxoRpcError::from))
     }
@@ -922,6 +1086,25 @@ pub struct ElectrumBlockHeadersRes {
     max: u64,
 }
 
+impl ElectrumBlockHeadersRes {
+    /// Deserializes the concatenated headers.
+    pub fn headers(&self, coin_variant: CoinVariant) -> Result<Vec<BlockHeader>, serialization::Error> {
+        let len = CompactInteger::from(self.count);
+        let mut serialized = serialize(&len).take();
+        serialized.extend(self.hex.0.iter());
+        let mut reader = Reader::new_with_coin_variant(serialized.as_slice(), coin_variant);
+        reader.read_list::<BlockHeader>()
+    }
+}
+
+/// https://electrumx.readthedocs.io/en/latest/protocol-methods.html#blockchain-transaction-get-merkle
+#[derive(Debug, Deserialize)]
+pub struct TxMerkleBranch {
+    pub merkle: Vec<H256Json>,
+    pub block_height: u64,
+    pub pos: usize,
+}
+
 /// The block header compatible with Electrum 1.2
 #[derive(Debug, Deserialize)]
 pub struct ElectrumBlockHeaderV12 {
@@ -1065,6 +1248,8 @@ fn addr_to_socket_addr(input: &str) -> Result<SocketAddr, String> {
 pub fn spawn_electrum(
     req: &ElectrumRpcRequest,
     event_handlers: Vec<RpcTransportEventHandlerShared>,
+    subscriptions: Arc<ElectrumSubscriptions>,
+    proxy: Option<Socks5Proxy>,
 ) -> Result<ElectrumConnection, String> {
     let config = match req.protocol {
         ElectrumProtocol::TCP => ElectrumConfig::TCP,
@@ -1087,7 +1272,13 @@ pub fn spawn_electrum(
         },
     };
 
-    Ok(electrum_connect(req.url.clone(), config, event_handlers))
+    Ok(electrum_connect(
+        req.url.clone(),
+        config,
+        event_handlers,
+        subscriptions,
+        proxy,
+    ))
 }
 
 /// Attempts to process the request (parse url, etc), build up the config and create new electrum connection
@@ -1095,6 +1286,8 @@ pub fn spawn_electrum(
 pub fn spawn_electrum(
     req: &ElectrumRpcRequest,
     event_handlers: Vec<RpcTransportEventHandlerShared>,
+    subscriptions: Arc<ElectrumSubscriptions>,
+    proxy: Option<Socks5Proxy>,
 ) -> Result<ElectrumConnection, String> {
     let mut url = req.url.clone();
     let uri: Uri = try_s!(req.url.parse());
@@ -1122,7 +1315,11 @@ pub fn spawn_electrum(
         },
     };
 
-    Ok(electrum_connect(url, config, event_handlers))
+    if proxy.is_some() {
+        return ERR!("The proxy can't be used in a browser");
+    }
+
+    Ok(electrum_connect(url, config, event_handlers, subscriptions, None))
 }
 
 #[derive(Debug)]
@@ -1140,6 +1337,8 @@ pub struct ElectrumConnection {
     responses: Arc<AsyncMutex<HashMap<String, async_oneshot::Sender<JsonRpcResponse>>>>,
     /// Selected protocol version. The value is initialized after the server.version RPC call.
     protocol_version: AsyncMutex<Option<f32>>,
+    /// The health of the server used to rank it among the others.
+    stats: Arc<Mutex<ElectrumServerStats>>,
 }
 
 impl ElectrumConnection {
@@ -1148,6 +1347,125 @@ impl ElectrumConnection {
     async fn set_protocol_version(&self, version: f32) { self.protocol_version.lock().await.replace(version); }
 }
 
+/// The server is suspended after this number of the failed requests in a row.
+const ELECTRUM_MAX_CONSECUTIVE_FAILURES: u32 = 3;
+/// The server is suspended if its chain tip is behind the best known tip by more than this number of blocks.
+const ELECTRUM_MAX_TIP_LAG: u64 = 2;
+/// The first suspension of a server lasts this number of seconds, every next suspension in a row is twice as long.
+const ELECTRUM_SUSPEND_BASE_SECS: u64 = 30;
+const ELECTRUM_SUSPEND_MAX_SECS: u64 = 1800;
+
+/// The health of an Electrum server collected from the requests sent to it.
+#[derive(Debug, Default)]
+pub struct ElectrumServerStats {
+    /// The exponential moving average of the response time in milliseconds.
+    avg_latency_ms: Option<f64>,
+    requests: u64,
+    errors: u64,
+    consecutive_failures: u32,
+    /// The height of the chain tip reported by the server.
+    tip_height: Option<u64>,
+    /// The number of the suspensions since the last successful request.
+    suspensions: u32,
+    /// The server isn't requested until this time (in milliseconds) unless all servers are suspended.
+    suspended_until: Option<u64>,
+}
+
+impl ElectrumServerStats {
+    pub fn on_success(&mut self, latency_ms: u64) {
+        let latency_ms = latency_ms as f64;
+        self.avg_latency_ms = Some(match self.avg_latency_ms {
+            Some(avg) => avg * 0.8 + latency_ms * 0.2,
+            None => latency_ms,
+        });
+        self.requests += 1;
+        self.consecutive_failures = 0;
+        self.suspensions = 0;
+    }
+
+    /// Returns true if the server has been suspended due to this failure.
+    pub fn on_failure(&mut self) -> bool {
+        self.requests += 1;
+        self.errors += 1;
+        self.consecutive_failures += 1;
+        if self.consecutive_failures >= ELECTRUM_MAX_CONSECUTIVE_FAILURES {
+            self.suspend();
+            return true;
+        }
+        false
+    }
+
+    pub fn tip_height(&self) -> Option<u64> { self.tip_height }
+
+    fn suspend(&mut self) {
+        let suspend_secs = (ELECTRUM_SUSPEND_BASE_SECS << self.suspensions.min(6)).min(ELECTRUM_SUSPEND_MAX_SECS);
+        self.suspended_until = Some(now_ms() + suspend_secs * 1000);
+        self.suspensions += 1;
+        self.consecutive_failures = 0;
+    }
+
+    pub fn is_suspended(&self, now_ms: u64) -> bool { matches!(self.suspended_until, Some(until) if until > now_ms) }
+
+    /// The share of the successful requests discounted by the average latency in seconds. The higher is the better.
+    pub fn score(&self) -> f64 {
+        let success_rate = if self.requests == 0 {
+            1.
+        } else {
+            (self.requests - self.errors) as f64 / self.requests as f64
+        };
+        let latency_secs = self.avg_latency_ms.unwrap_or(0.) / 1000.;
+        success_rate / (1. + latency_secs)
+    }
+}
+
+/// The health of an Electrum server returned by the `electrum_servers_status` RPC.
+#[derive(Debug, Serialize)]
+pub struct ElectrumServerStatus {
+    pub url: String,
+    pub is_connected: bool,
+    pub protocol_version: Option<f32>,
+    pub avg_latency_ms: Option<u64>,
+    pub requests: u64,
+    pub errors: u64,
+    pub tip_height: Option<u64>,
+    pub score: f64,
+    /// The timestamp (in seconds) until which the server is suspended.
+    pub suspended_until: Option<u64>,
+}
+
+/// Returns the connections that aren't suspended ordered by score, the best first.
+/// The servers lagging behind the best known tip are suspended.
+/// If all servers are suspended, all of them are returned, so the requests are still sent somewhere.
+fn rank_connections(connections: &[ElectrumConnection]) -> Vec<&ElectrumConnection> {
+    let now = now_ms();
+    let best_tip = connections
+        .iter()
+        .filter_map(|conn| conn.stats.lock().unwrap().tip_height)
+        .max();
+    let mut ranked = Vec::with_capacity(connections.len());
+    for conn in connections {
+        let mut stats = conn.stats.lock().unwrap();
+        if let (Some(best_tip), Some(tip_height)) = (best_tip, stats.tip_height) {
+            if tip_height + ELECTRUM_MAX_TIP_LAG < best_tip && !stats.is_suspended(now) {
+                warn!(
+                    "Electrum {} tip {} lags behind the best tip {}, suspending it",
+                    conn.addr, tip_height, best_tip
+                );
+                stats.suspend();
+            }
+        }
+        if !stats.is_suspended(now) {
+            ranked.push((stats.score(), conn));
+        }
+    }
+    if ranked.is_empty() {
+        return connections.iter().collect();
+    }
+    // the sort is stable, so the servers with the equal scores keep their order
+    ranked.sort_by(|(score_a, _), (score_b, _)| score_b.partial_cmp(score_a).unwrap_or(std::cmp::Ordering::Equal));
+    ranked.into_iter().map(|(_, conn)| conn).collect()
+}
+
 impl Drop for ElectrumConnection {
     fn drop(&mut self) {
         if let Some(shutdown_tx) = self.shutdown_tx.take() {
@@ -1226,6 +1544,93 @@ pub struct ElectrumClientImpl {
     protocol_version: OrdRange<f32>,
     get_balance_concurrent_map: ConcurrentRequestMap<String, ElectrumBalance>,
     list_unspent_concurrent_map: ConcurrentRequestMap<String, Vec<ElectrumUnspent>>,
+    /// The headers validated by SPV if the coin is configured with `spv: true`.
+    block_headers_storage: BlockHeaderStorage,
+    subscriptions: Arc<ElectrumSubscriptions>,
+    /// The proxy the connections are established through.
+    proxy: Option<Socks5Proxy>,
+}
+
+/// The listeners of the `blockchain.scripthash.subscribe` and `blockchain.headers.subscribe` notifications.
+/// Shared between all connections of the client, the subscriptions are restored by `ElectrumClient::resubscribe`
+/// once a server is (re)connected.
+#[derive(Debug, Default)]
+pub struct ElectrumSubscriptions {
+    scripthash_listeners: Mutex<HashMap<String, Vec<UnboundedSender<()>>>>,
+    header_listeners: Mutex<Vec<UnboundedSender<()>>>,
+}
+
+impl ElectrumSubscriptions {
+    pub fn add_scripthash_listener(&self, script_hashes: &[String]) -> CoinNotifications {
+        let (tx, rx) = unbounded();
+        self.add_scripthash_sender(script_hashes, &tx);
+        rx
+    }
+
+    /// Notifies the `tx` on the status changes of the script hashes as well.
+    pub fn add_scripthash_sender(&self, script_hashes: &[String], tx: &UnboundedSender<()>) {
+        let mut listeners = self.scripthash_listeners.lock().unwrap();
+        for script_hash in script_hashes {
+            listeners
+                .entry(script_hash.clone())
+                .or_insert_with(Vec::new)
+                .push(tx.clone());
+        }
+    }
+
+    /// Adds the script hashes that aren't known yet notifying the `senders` on their status changes.
+    /// Returns the added script hashes, they should be subscribed on the servers.
+    pub fn add_script_hashes(&self, script_hashes: Vec<String>, senders: &[UnboundedSender<()>]) -> Vec<String> {
+        let mut listeners = self.scripthash_listeners.lock().unwrap();
+        script_hashes
+            .into_iter()
+            .filter(|script_hash| {
+                if listeners.contains_key(script_hash) {
+                    return false;
+                }
+                listeners.insert(script_hash.clone(), senders.to_vec());
+                true
+            })
+            .collect()
+    }
+
+    pub fn add_header_listener(&self) -> CoinNotifications {
+        let (tx, rx) = unbounded();
+        self.header_listeners.lock().unwrap().push(tx);
+        rx
+    }
+
+    /// The subscribed script hashes.
+    /// The listeners of a script hash can be added after it's subscribed, so it's kept even if it has no listeners.
+    fn script_hashes(&self) -> Vec<String> {
+        let mut listeners = self.scripthash_listeners.lock().unwrap();
+        for senders in listeners.values_mut() {
+            senders.retain(|tx| !tx.is_closed());
+        }
+        listeners.keys().cloned().collect()
+    }
+
+    fn notify_scripthash(&self, script_hash: &str) {
+        if let Some(senders) = self.scripthash_listeners.lock().unwrap().get_mut(script_hash) {
+            // the receiver may be dropped, remove the corresponding sender then
+            senders.retain(|tx| tx.unbounded_send(()).is_ok());
+        }
+    }
+
+    fn notify_header(&self) {
+        self.header_listeners
+            .lock()
+            .unwrap()
+            .retain(|tx| tx.unbounded_send(()).is_ok());
+    }
+
+    /// Notifies all listeners, e.g. to poll the updates that could be missed while the connection was lost.
+    fn notify_all(&self) {
+        for senders in self.scripthash_listeners.lock().unwrap().values_mut() {
+            senders.retain(|tx| tx.unbounded_send(()).is_ok());
+        }
+        self.notify_header();
+    }
 }
 
 async fn electrum_request_multi(
@@ -1233,16 +1638,24 @@ async fn electrum_request_multi(
     request: JsonRpcRequest,
 ) -> Result<(JsonRpcRemoteAddr, JsonRpcResponse), String> {
     let mut futures = vec![];
-    let connections = client.connections.lock().await;
+    let all_connections = client.connections.lock().await;
+    // server.ping must be sent to all servers including the suspended ones to keep all connections alive
+    let connections = if request.method != "server.ping" {
+        rank_connections(&all_connections)
+    } else {
+        all_connections.iter().collect()
+    };
     for (i, connection) in connections.iter().enumerate() {
         let connection_addr = connection.addr.clone();
         match &*connection.tx.lock().await {
             Some(tx) => {
-                let fut = electrum_request(
+                let fut = electrum_request_with_stats(
                     request.clone(),
                     tx.clone(),
                     connection.responses.clone(),
                     ELECTRUM_TIMEOUT / (connections.len() - i) as u64,
+                    connection.addr.clone(),
+                    connection.stats.clone(),
                 )
                 .map(|response| (JsonRpcRemoteAddr(connection_addr), response));
                 futures.push(fut)
@@ -1251,6 +1664,7 @@ async fn electrum_request_multi(
         }
     }
     drop(connections);
+    drop(all_connections);
     if futures.is_empty() {
         return ERR!("All electrums are currently disconnected");
     }
@@ -1274,12 +1688,53 @@ async fn electrum_request_multi(
     }
 }
 
+/// Sends the batch to the best server, the next one is tried if the server fails.
+async fn electrum_batch_request_multi(
+    client: ElectrumClient,
+    requests: Vec<JsonRpcRequest>,
+) -> Result<(JsonRpcRemoteAddr, Vec<JsonRpcResponse>), String> {
+    let mut futures = vec![];
+    let all_connections = client.connections.lock().await;
+    let connections = rank_connections(&all_connections);
+    for (i, connection) in connections.iter().enumerate() {
+        let connection_addr = connection.addr.clone();
+        match &*connection.tx.lock().await {
+            Some(tx) => {
+                let requests = requests.clone();
+                let tx = tx.clone();
+                let responses = connection.responses.clone();
+                let timeout = ELECTRUM_TIMEOUT / (connections.len() - i) as u64;
+                let fut = with_electrum_stats(
+                    move || electrum_batch_request(requests, tx, responses, timeout),
+                    connection.addr.clone(),
+                    connection.stats.clone(),
+                )
+                .map(|responses| (JsonRpcRemoteAddr(connection_addr), responses));
+                futures.push(fut)
+            },
+            None => (),
+        }
+    }
+    drop(connections);
+    drop(all_connections);
+    if futures.is_empty() {
+        return ERR!("All electrums are currently disconnected");
+    }
+    match select_ok_sequential(futures).compat().await {
+        Ok((res, no_of_failed_requests)) => {
+            client.clone().rotate_servers(no_of_failed_requests).await;
+            Ok(res)
+        },
+        Err(e) => ERR!("{:?}", e),
+    }
+}
+
 async fn electrum_request_to(
     client: ElectrumClient,
     request: JsonRpcRequest,
     to_addr: String,
 ) -> Result<(JsonRpcRemoteAddr, JsonRpcResponse), String> {
-    let (tx, responses) = {
+    let (tx, responses, stats) = {
         let connections = client.connections.lock().await;
         let connection = connections
             .iter()
@@ -1292,11 +1747,11 @@ async fn electrum_request_to(
                 None => return ERR!("Connection {} is not established yet", to_addr),
             }
         };
-        (tx, responses)
+        (tx, responses, connection.stats.clone())
     };
 
     let response = try_s!(
-        electrum_request(request.clone(), tx, responses, ELECTRUM_TIMEOUT)
+        electrum_request_with_stats(request.clone(), tx, responses, ELECTRUM_TIMEOUT, to_addr.clone(), stats)
             .compat()
             .await
     );
@@ -1306,7 +1761,12 @@ async fn electrum_request_to(
 impl ElectrumClientImpl {
     /// Create an Electrum connection and spawn a green thread actor to handle it.
     pub async fn add_server(&self, req: &ElectrumRpcRequest) -> Result<(), String> {
-        let connection = try_s!(spawn_electrum(req, self.event_handlers.clone()));
+        let connection = try_s!(spawn_electrum(
+            req,
+            self.event_handlers.clone(),
+            self.subscriptions.clone(),
+            self.proxy.clone()
+        ));
         self.connections.lock().await.push(connection);
         Ok(())
     }
@@ -1365,6 +1825,52 @@ impl ElectrumClientImpl {
 
     /// Get available protocol versions.
     pub fn protocol_version(&self) -> &OrdRange<f32> { &self.protocol_version }
+
+    async fn set_tip_height(&self, server_addr: &str, height: u64) {
+        if let Some(con) = self.connections.lock().await.iter().find(|con| con.addr == server_addr) {
+            con.stats.lock().unwrap().tip_height = Some(height);
+        }
+    }
+
+    /// Returns the health of the servers, the best first.
+    pub async fn servers_status(&self) -> Vec<ElectrumServerStatus> {
+        let now = now_ms();
+        let mut servers = Vec::new();
+        for con in self.connections.lock().await.iter() {
+            let is_connected = con.is_connected().await;
+            let protocol_version = *con.protocol_version.lock().await;
+            let stats = con.stats.lock().unwrap();
+            servers.push(ElectrumServerStatus {
+                url: con.addr.clone(),
+                is_connected,
+                protocol_version,
+                avg_latency_ms: stats.avg_latency_ms.map(|latency| latency as u64),
+                requests: stats.requests,
+                errors: stats.errors,
+                tip_height: stats.tip_height,
+                score: stats.score(),
+                suspended_until: stats
+                    .suspended_until
+                    .filter(|until| *until > now)
+                    .map(|until| until / 1000),
+            });
+        }
+        servers.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
+        servers
+    }
+
+    /// The addresses of the servers the protocol version was checked for.
+    async fn protocol_version_checked_servers(&self) -> Vec<String> {
+        let mut servers = Vec::new();
+        for connection in self.connections.lock().await.iter() {
+            if connection.protocol_version.lock().await.is_some() {
+                servers.push(connection.addr.clone());
+            }
+        }
+        servers
+    }
+
+    pub fn block_headers_storage(&self) -> &BlockHeaderStorage { &self.block_headers_storage }
 }
 
 #[derive(Clone, Debug)]
@@ -1376,6 +1882,8 @@ impl Deref for ElectrumClient {
 
 const BLOCKCHAIN_HEADERS_SUB_ID: &str = "blockchain.headers.subscribe";
 
+const BLOCKCHAIN_SCRIPTHASH_SUB_ID: &str = "blockchain.scripthash.subscribe";
+
 impl UtxoJsonRpcClientInfo for ElectrumClient {
     fn coin_name(&self) -> &str { self.coin_ticker.as_str() }
 }
@@ -1388,13 +1896,34 @@ impl JsonRpcClient for ElectrumClient {
     fn client_info(&self) -> String { UtxoJsonRpcClientInfo::client_info(self) }
 
     fn transport(&self, request: JsonRpcRequest) -> JsonRpcResponseFut {
-        Box::new(electrum_request_multi(self.clone(), request).boxed().compat())
+        Box::new(
+            electrum_request_multi(self.clone(), request)
+                .boxed()
+                .compat()
+                .map_err(JsonRpcErrorType::Transport),
+        )
     }
 }
 
 impl JsonRpcMultiClient for ElectrumClient {
     fn transport_exact(&self, to_addr: String, request: JsonRpcRequest) -> JsonRpcResponseFut {
-        Box::new(electrum_request_to(self.clone(), request, to_addr).boxed().compat())
+        Box::new(
+            electrum_request_to(self.clone(), request, to_addr)
+                .boxed()
+                .compat()
+                .map_err(JsonRpcErrorType::Transport),
+        )
+    }
+}
+
+impl JsonRpcBatchClient for ElectrumClient {
+    fn transport_batch(&self, requests: Vec<JsonRpcRequest>) -> JsonRpcBatchResponseFut {
+        Box::new(
+            electrum_batch_request_multi(self.clone(), requests)
+                .boxed()
+                .compat()
+                .map_err(JsonRpcErrorType::Transport),
+        )
     }
 }
 
@@ -1460,6 +1989,70 @@ impl ElectrumClient {
         rpc_func!(self, "blockchain.headers.subscribe")
     }
 
+    /// https://electrumx.readthedocs.io/en/latest/protocol-methods.html#blockchain-headers-subscribe
+    /// Subscribes to the new blocks on the specified server.
+    fn blockchain_headers_subscribe_from(&self, server_address: &str) -> RpcRes<ElectrumBlockHeader> {
+        rpc_func_from!(self, server_address, "blockchain.headers.subscribe")
+    }
+
+    /// https://electrumx.readthedocs.io/en/latest/protocol-methods.html#blockchain-scripthash-subscribe
+    /// Subscribes to the status changes of the script hash on the specified server.
+    fn scripthash_subscribe_from(&self, server_address: &str, hash: &str) -> RpcRes<Option<String>> {
+        rpc_func_from!(self, server_address, "blockchain.scripthash.subscribe", hash)
+    }
+
+    /// Subscribes to the status changes of the script hashes notifying the `listeners` on every change,
+    /// i.e. when a transaction spending from or paying to one of the scripts is added to the mempool or mined.
+    /// The script hashes subscribed before are skipped, so every script hash is subscribed on the servers once.
+    pub fn subscribe_scripthashes(&self, script_hashes: Vec<String>, listeners: &[UnboundedSender<()>]) {
+        let script_hashes = self.subscriptions.add_script_hashes(script_hashes, listeners);
+        if script_hashes.is_empty() {
+            return;
+        }
+        let client = self.clone();
+        let fut = async move {
+            for server_address in client.protocol_version_checked_servers().await {
+                for script_hash in script_hashes.iter() {
+                    if let Err(e) = client
+                        .scripthash_subscribe_from(&server_address, script_hash)
+                        .compat()
+                        .await
+                    {
+                        error!("Error subscribing to {} on {}: {}", script_hash, server_address, e);
+                    }
+                }
+            }
+        };
+        spawn(fut);
+    }
+
+    /// Notifies the `tx` on the status changes of the script hashes subscribed by `subscribe_scripthashes`.
+    pub fn add_scripthash_listener(&self, script_hashes: &[String], tx: &UnboundedSender<()>) {
+        self.subscriptions.add_scripthash_sender(script_hashes, tx)
+    }
+
+    /// Returns the notifications sent on every new block.
+    pub fn header_notifications(&self) -> CoinNotifications { self.subscriptions.add_header_listener() }
+
+    /// Restores the subscriptions on the (re)connected server.
+    /// The listeners are notified afterwards to poll the updates that could be missed while the server was disconnected.
+    pub async fn resubscribe(&self, server_address: &str) {
+        match self.blockchain_headers_subscribe_from(server_address).compat().await {
+            Ok(header) => self.set_tip_height(server_address, header.block_height()).await,
+            Err(e) => error!("Error subscribing to the headers on {}: {}", server_address, e),
+        }
+        for script_hash in self.subscriptions.script_hashes() {
+            if let Err(e) = self
+                .scripthash_subscribe_from(server_address, &script_hash)
+                .compat()
+                .await
+            {
+                error!("Error subscribing to {} on {}: {}", script_hash, server_address, e);
+            }
+        }
+        self.subscriptions.notify_all();
+    }
+
     /// https://electrumx.readthedocs.io/en/latest/protocol-methods.html#blockchain-transaction-broadcast
     fn blockchain_transaction_broadcast(&self, tx: BytesJson) -> RpcRes<H256Json> {
         rpc_func!(self, "blockchain.transaction.broadcast", tx)
@@ -1475,11 +2068,20 @@ impl ElectrumClient {
             None => rpc_func!(self, "blockchain.estimatefee", n_blocks),
         }
     }
+}
 
+/// The requests used by SPV, see the `utxo::spv` module.
+#[cfg_attr(test, mockable)]
+impl ElectrumClient {
     /// https://electrumx.readthedocs.io/en/latest/protocol-methods.html#blockchain-block-headers
     pub fn blockchain_block_headers(&self, start_height: u64, count: NonZeroU64) -> RpcRes<ElectrumBlockHeadersRes> {
         rpc_func!(self, "blockchain.block.headers", start_height, count)
     }
+
+    /// https://electrumx.readthedocs.io/en/latest/protocol-methods.html#blockchain-transaction-get-merkle
+    pub fn blockchain_transaction_get_merkle(&self, txid: H256Json, height: u64) -> RpcRes<TxMerkleBranch> {
+        rpc_func!(self, "blockchain.transaction.get_merkle", txid, height)
+    }
 }
 
 #[cfg_attr(test, mockable)]
@@ -1536,6 +2138,16 @@ impl UtxoRpcClientOps for ElectrumClient {
         rpc_func!(self, "blockchain.transaction.get", txid, verbose)
     }
 
+    /// https://electrumx.readthedocs.io/en/latest/protocol-basics.html#batch-requests
+    fn get_verbose_transactions(&self, txids: &[H256Json]) -> RpcRes<Vec<RpcTransaction>> {
+        let verbose = true;
+        let requests = txids
+            .iter()
+            .map(|txid| batch_request_item(self, "blockchain.transaction.get", vec![json!(txid), json!(verbose)]))
+            .collect();
+        self.send_batch_request(requests)
+    }
+
     fn get_block_count(&self) -> UtxoRpcFut<u64> {
         Box::new(
             self.blockchain_headers_subscribe()
@@ -1620,11 +2232,7 @@ impl UtxoRpcClientOps for ElectrumClient {
                     if res.count == 0 {
                         return MmError::err(UtxoRpcError::InvalidResponse("Server returned zero count".to_owned()));
                     }
-                    let len = CompactInteger::from(res.count);
-                    let mut serialized = serialize(&len).take();
-                    serialized.extend(res.hex.0.into_iter());
-                    let mut reader = Reader::new_with_coin_variant(serialized.as_slice(), coin_variant);
-                    let headers = reader.read_list::<BlockHeader>()?;
+                    let headers = res.headers(coin_variant)?;
                     let mut timestamps: Vec<_> = headers.into_iter().map(|block| block.time).collect();
                     // can unwrap because count is non zero
                     Ok(median(timestamps.as_mut_slice()).unwrap())
@@ -1645,9 +2253,15 @@ impl ElectrumClientImpl {
             protocol_version,
             get_balance_concurrent_map: ConcurrentRequestMap::new(),
             list_unspent_concurrent_map: ConcurrentRequestMap::new(),
+            block_headers_storage: BlockHeaderStorage::default(),
+            subscriptions: Arc::new(ElectrumSubscriptions::default()),
+            proxy: None,
         }
     }
 
+    /// Connects to the servers added afterwards through the proxy.
+    pub fn with_proxy(self, proxy: Option<Socks5Proxy>) -> ElectrumClientImpl { ElectrumClientImpl { proxy, ..self } }
+
     #[cfg(test)]
     pub fn with_protocol_version(
         coin_ticker: String,
@@ -1666,10 +2280,28 @@ fn rx_to_stream(rx: mpsc::Receiver<Vec<u8>>) -> impl Stream<Item = Vec<u8>, Erro
     rx.map_err(|_| panic!("errors not possible on rx"))
 }
 
-async fn electrum_process_json(
+pub(crate) async fn electrum_process_json(
     raw_json: Json,
     arc: &Arc<AsyncMutex<HashMap<String, async_oneshot::Sender<JsonRpcResponse>>>>,
+    subscriptions: &ElectrumSubscriptions,
+    stats: &Mutex<ElectrumServerStats>,
 ) {
+    // the response to the batch is the array of the responses to the requests of the batch
+    if let Json::Array(batch) = raw_json {
+        let mut resp = arc.lock().await;
+        for raw_response in batch {
+            match json::from_value::<JsonRpcResponse>(raw_response) {
+                Ok(response) => {
+                    if let Some(tx) = resp.remove(&response.id) {
+                        tx.send(response).unwrap_or(())
+                    }
+                },
+                Err(e) => error!("{}", e),
+            }
+        }
+        return;
+    }
+
     // detect if we got standard JSONRPC response or subscription response as JSONRPC request
     if raw_json["method"].is_null() && raw_json["params"].is_null() {
         let response: JsonRpcResponse = match json::from_value(raw_json) {
@@ -1695,7 +2327,25 @@ async fn electrum_process_json(
             },
         };
         let id = match request.method.as_ref() {
-            BLOCKCHAIN_HEADERS_SUB_ID => BLOCKCHAIN_HEADERS_SUB_ID,
+            BLOCKCHAIN_HEADERS_SUB_ID => {
+                match request
+                    .params
+                    .get(0)
+                    .map(|header| json::from_value::<ElectrumBlockHeader>(header.clone()))
+                {
+                    Some(Ok(header)) => stats.lock().unwrap().tip_height = Some(header.block_height()),
+                    _ => error!("Couldn't get the block height of notification {:?}", request),
+                }
+                subscriptions.notify_header();
+                BLOCKCHAIN_HEADERS_SUB_ID
+            },
+            BLOCKCHAIN_SCRIPTHASH_SUB_ID => {
+                match request.params.get(0).and_then(Json::as_str) {
+                    Some(script_hash) => subscriptions.notify_scripthash(script_hash),
+                    None => error!("Couldn't get the script hash of notification {:?}", request),
+                }
+                return;
+            },
             _ => {
                 error!("Couldn't get id of request {:?}", request);
                 return;
@@ -1721,6 +2371,8 @@ async fn electrum_process_json(
 async fn electrum_process_chunk(
     chunk: &[u8],
     arc: &Arc<AsyncMutex<HashMap<String, async_oneshot::Sender<JsonRpcResponse>>>>,
+    subscriptions: &ElectrumSubscriptions,
+    stats: &Mutex<ElectrumServerStats>,
 ) {
     // we should split the received chunk because we can get several responses in 1 chunk.
     let split = chunk.split(|item| *item == b'\n');
@@ -1734,7 +2386,7 @@ async fn electrum_process_chunk(
                     return;
                 },
             };
-            electrum_process_json(raw_json, arc).await
+            electrum_process_json(raw_json, arc, subscriptions, stats).await
         }
     }
 }
@@ -1833,6 +2485,9 @@ async fn connect_loop(
     responses: Arc<AsyncMutex<HashMap<String, async_oneshot::Sender<JsonRpcResponse>>>>,
     connection_tx: Arc<AsyncMutex<Option<mpsc::Sender<Vec<u8>>>>>,
     event_handlers: Vec<RpcTransportEventHandlerShared>,
+    subscriptions: Arc<ElectrumSubscriptions>,
+    stats: Arc<Mutex<ElectrumServerStats>>,
+    proxy: Option<Socks5Proxy>,
 ) -> Result<(), ()> {
     let delay = Arc::new(AtomicU64::new(0));
 
@@ -1842,10 +2497,17 @@ async fn connect_loop(
             Timer::sleep(current_delay as f64).await;
         };
 
-        let socket_addr = try_loop!(addr_to_socket_addr(&addr), addr, delay);
+        let tcp_connect_f = match proxy {
+            // the address is resolved by the proxy
+            Some(ref proxy) => Either::Left(proxy.connect_addr(&addr)),
+            None => {
+                let socket_addr = try_loop!(addr_to_socket_addr(&addr), addr, delay);
+                Either::Right(TcpStream::connect(socket_addr).map_err(|e| ERRL!("{}", e)))
+            },
+        };
 
         let connect_f = match config.clone() {
-            ElectrumConfig::TCP => Either::Left(TcpStream::connect(&socket_addr).map_ok(ElectrumStream::Tcp)),
+            ElectrumConfig::TCP => Either::Left(tcp_connect_f.map_ok(ElectrumStream::Tcp)),
             ElectrumConfig::SSL {
                 dns_name,
                 skip_validation,
@@ -1859,12 +2521,15 @@ async fn connect_loop(
                 }
                 let tls_connector = TlsConnector::from(Arc::new(ssl_config));
 
-                Either::Right(TcpStream::connect(&socket_addr).and_then(move |stream| {
+                Either::Right(tcp_connect_f.and_then(move |stream| {
                     // Can use `unwrap` cause `dns_name` is pre-checked.
                     let dns = DNSNameRef::try_from_ascii_str(&dns_name)
                         .map_err(|e| fomat!([e]))
                         .unwrap();
-                    tls_connector.connect(dns, stream).map_ok(ElectrumStream::Tls)
+                    tls_connector
+                        .connect(dns, stream)
+                        .map_ok(ElectrumStream::Tls)
+                        .map_err(|e| ERRL!("{}", e))
                 }))
             },
         };
@@ -1889,6 +2554,8 @@ async fn connect_loop(
             let addr = addr.clone();
             let responses = responses.clone();
             let event_handlers = event_handlers.clone();
+            let subscriptions = subscriptions.clone();
+            let stats = stats.clone();
             async move {
                 let mut buffer = String::with_capacity(1024);
                 let mut buf_reader = BufReader::new(read);
@@ -1911,7 +2578,7 @@ async fn connect_loop(
                     event_handlers.on_incoming_response(buffer.as_bytes());
                     last_chunk.store(now_ms(), AtomicOrdering::Relaxed);
 
-                    electrum_process_chunk(buffer.as_bytes(), &responses).await;
+                    electrum_process_chunk(buffer.as_bytes(), &responses, &subscriptions, &stats).await;
                     buffer.clear();
                 }
             }
@@ -1954,6 +2621,9 @@ async fn connect_loop(
     responses: Arc<AsyncMutex<HashMap<String, async_oneshot::Sender<JsonRpcResponse>>>>,
     connection_tx: Arc<AsyncMutex<Option<mpsc::Sender<Vec<u8>>>>>,
     event_handlers: Vec<RpcTransportEventHandlerShared>,
+    subscriptions: Arc<ElectrumSubscriptions>,
+    stats: Arc<Mutex<ElectrumServerStats>>,
+    _proxy: Option<Socks5Proxy>,
 ) -> Result<(), ()> {
     use std::sync::atomic::AtomicUsize;
 
@@ -1987,6 +2657,8 @@ async fn connect_loop(
             let addr = addr.clone();
             let responses = responses.clone();
             let event_handlers = event_handlers.clone();
+            let subscriptions = subscriptions.clone();
+            let stats = stats.clone();
             async move {
                 while let Some(incoming_res) = transport_rx.next().await {
                     last_chunk.store(now_ms(), AtomicOrdering::Relaxed);
@@ -1998,7 +2670,7 @@ async fn connect_loop(
                             let incoming_str = incoming_json.to_string();
                             event_handlers.on_incoming_response(incoming_str.as_bytes());
 
-                            electrum_process_json(incoming_json, &responses).await;
+                            electrum_process_json(incoming_json, &responses, &subscriptions, &stats).await;
                         },
                         Err(e) => {
                             error!("{} error: {:?}", addr, e);
@@ -2056,10 +2728,13 @@ fn electrum_connect(
     addr: String,
     config: ElectrumConfig,
     event_handlers: Vec<RpcTransportEventHandlerShared>,
+    subscriptions: Arc<ElectrumSubscriptions>,
+    proxy: Option<Socks5Proxy>,
 ) -> ElectrumConnection {
     let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
     let responses = Arc::new(AsyncMutex::new(HashMap::new()));
     let tx = Arc::new(AsyncMutex::new(None));
+    let stats = Arc::new(Mutex::new(ElectrumServerStats::default()));
 
     let connect_loop = connect_loop(
         config.clone(),
@@ -2067,6 +2742,9 @@ fn electrum_connect(
         responses.clone(),
         tx.clone(),
         event_handlers,
+        subscriptions,
+        stats.clone(),
+        proxy,
     );
 
     let connect_loop = select_func(connect_loop.boxed(), shutdown_rx.compat());
@@ -2078,6 +2756,7 @@ fn electrum_connect(
         shutdown_tx: Some(shutdown_tx),
         responses,
         protocol_version: AsyncMutex::new(None),
+        stats,
     }
 }
 
@@ -2087,6 +2766,29 @@ fn electrum_request(
     responses: Arc<AsyncMutex<HashMap<String, async_oneshot::Sender<JsonRpcResponse>>>>,
     timeout: u64,
 ) -> Box<dyn Future<Item = JsonRpcResponse, Error = String> + Send + 'static> {
+    let ids = vec![request.get_id().to_string()];
+    Box::new(electrum_send_json(request, ids, tx, responses, timeout).map(|mut responses| responses.remove(0)))
+}
+
+/// Sends the requests as the JSON-RPC batch, the responses are returned in the order of the requests.
+fn electrum_batch_request(
+    requests: Vec<JsonRpcRequest>,
+    tx: mpsc::Sender<Vec<u8>>,
+    responses: Arc<AsyncMutex<HashMap<String, async_oneshot::Sender<JsonRpcResponse>>>>,
+    timeout: u64,
+) -> Box<dyn Future<Item = Vec<JsonRpcResponse>, Error = String> + Send + 'static> {
+    let ids = requests.iter().map(|request| request.get_id().to_string()).collect();
+    electrum_send_json(requests, ids, tx, responses, timeout)
+}
+
+/// Sends the request or the batch of the requests and waits for the responses with the given `ids`.
+fn electrum_send_json<Req: Serialize + Send + 'static>(
+    request: Req,
+    ids: Vec<String>,
+    tx: mpsc::Sender<Vec<u8>>,
+    responses: Arc<AsyncMutex<HashMap<String, async_oneshot::Sender<JsonRpcResponse>>>>,
+    timeout: u64,
+) -> Box<dyn Future<Item = Vec<JsonRpcResponse>, Error = String> + Send + 'static> {
     let send_fut = async move {
         let mut json = try_s!(json::to_string(&request));
         #[cfg(not(target_arch = "wasm"))]
@@ -2096,12 +2798,21 @@ fn electrum_request(
             json.push('\n');
         }
 
-        let request_id = request.get_id().to_string();
-        let (req_tx, resp_rx) = async_oneshot::channel();
-        responses.lock().await.insert(request_id, req_tx);
+        let mut receivers = Vec::with_capacity(ids.len());
+        {
+            let mut responses = responses.lock().await;
+            for request_id in ids {
+                let (req_tx, resp_rx) = async_oneshot::channel();
+                responses.insert(request_id, req_tx);
+                receivers.push(resp_rx);
+            }
+        }
         try_s!(tx.send(json.into_bytes()).compat().await);
-        let response = try_s!(resp_rx.await);
-        Ok(response)
+        let mut result = Vec::with_capacity(receivers.len());
+        for resp_rx in receivers {
+            result.push(try_s!(resp_rx.await));
+        }
+        Ok(result)
     };
     let send_fut = send_fut
         .boxed()
@@ -2114,3 +2825,44 @@ fn electrum_request(
         .map_err(|e| ERRL!("{}", e));
     Box::new(send_fut)
 }
+
+/// Sends the request updating the latency and the error rate of the server.
+fn electrum_request_with_stats(
+    request: JsonRpcRequest,
+    tx: mpsc::Sender<Vec<u8>>,
+    responses: Arc<AsyncMutex<HashMap<String, async_oneshot::Sender<JsonRpcResponse>>>>,
+    timeout: u64,
+    addr: String,
+    stats: Arc<Mutex<ElectrumServerStats>>,
+) -> Box<dyn Future<Item = JsonRpcResponse, Error = String> + Send + 'static> {
+    with_electrum_stats(move || electrum_request(request, tx, responses, timeout), addr, stats)
+}
+
+/// Runs the future created by `send` updating the latency and the error rate of the server.
+fn with_electrum_stats<T, F>(
+    send: F,
+    addr: String,
+    stats: Arc<Mutex<ElectrumServerStats>>,
+) -> Box<dyn Future<Item = T, Error = String> + Send + 'static>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Box<dyn Future<Item = T, Error = String> + Send + 'static> + Send + 'static,
+{
+    // the request is sent on the first poll, so the latency should be measured since then
+    let fut = futures01::future::lazy(move || {
+        let started_at = now_ms();
+        send().then(move |res| {
+            let mut stats = stats.lock().unwrap();
+            match res {
+                Ok(_) => stats.on_success(now_ms() - started_at),
+                Err(_) => {
+                    if stats.on_failure() {
+                        warn!("Electrum {} fails repeatedly, suspending it", addr);
+                    }
+                },
+            }
+            res
+        })
+    });
+    Box::new(fut)
+}
@@ -1,47 +1,65 @@
 use super::p2pkh_spend;
 use super::utxo_standard::UtxoStandardCoin;
 
+use crate::utxo::coin_selection::CoinSelectionStrategy;
 use crate::utxo::rpc_clients::{UnspentInfo, UtxoRpcClientEnum, UtxoRpcError};
-use crate::utxo::utxo_common::{self, big_decimal_from_sat_unsigned, generate_transaction, p2sh_spend, payment_script};
-use crate::utxo::{generate_and_send_tx, sat_from_big_decimal, FeePolicy, GenerateTxError, RecentlySpentOutPoints,
-                  UtxoCommonOps, UtxoTx};
-use crate::{BalanceError, BalanceFut, CoinBalance, FeeApproxStage, FoundSwapTxSpend, HistorySyncState, MarketCoinOps,
-            MmCoin, NegotiateSwapContractAddrErr, NumConversError, SwapOps, TradeFee, TradePreimageFut,
-            TradePreimageValue, TransactionEnum, TransactionFut, ValidateAddressResult, WithdrawFut, WithdrawRequest};
+use crate::utxo::utxo_common::{self, big_decimal_from_sat, big_decimal_from_sat_unsigned,
+                               generate_transaction_with_coin_selection, p2sh_spend, payment_script,
+                               HISTORY_TOO_LARGE_ERR_CODE};
+use crate::utxo::utxo_hd_wallet::{UtxoHDAccount, UtxoHDAddress};
+use crate::utxo::{generate_and_send_tx, output_script, sat_from_big_decimal, sign_tx, sign_tx_with_key_pairs,
+                  ActualTxFee, Address, FeePolicy, GenerateTxError, HistoryUtxoTxMap, RecentlySpentOutPoints,
+                  RequestTxHistoryResult, ScriptType, UtxoAddressFormat, UtxoCommonOps, UtxoFeeDetails, UtxoTx,
+                  UTXO_LOCK};
+use crate::{lp_coinfind, BalanceError, BalanceFut, CoinBalance, CoinsContext, FeeApproxStage, FoundSwapTxSpend,
+            HistorySyncState, MarketCoinOps, MmCoin, MmCoinEnum, NegotiateSwapContractAddrErr, NumConversError,
+            SwapOps, TradeFee, TradePreimageError, TradePreimageFut, TradePreimageResult, TradePreimageValue,
+            TransactionDetails, TransactionEnum, TransactionErr, TransactionFut, ValidateAddressResult, WithdrawError,
+            WithdrawFee, WithdrawFut, WithdrawRequest, WithdrawResult};
 
 use bitcoin_cash_slp::{slp_send_output, SlpTokenType, TokenId};
 use bitcrypto::dhash160;
 use chain::constants::SEQUENCE_FINAL;
 use chain::{OutPoint, TransactionOutput};
+use common::executor::Timer;
 use common::mm_ctx::MmArc;
 use common::mm_error::prelude::*;
 use common::mm_number::{BigDecimal, MmNumber};
+use common::now_ms;
 use derive_more::Display;
 use futures::compat::Future01CompatExt;
 use futures::lock::MutexGuard as AsyncMutexGuard;
 use futures::{FutureExt, TryFutureExt};
 use futures01::Future;
-use keys::Public;
+use keys::{CashAddrType, CashAddress, Public};
 use primitives::hash::H256;
-use rpc::v1::types::Bytes as BytesJson;
+use rpc::v1::types::{Bytes as BytesJson, H256 as H256Json};
 use script::bytes::Bytes;
 use script::{Builder as ScriptBuilder, Opcode, Script};
 use serde_json::Value as Json;
 use serialization::{deserialize, serialize, Deserializable, Error, Reader};
 use serialization_derive::Deserializable;
+use std::cmp::Ordering;
+use std::collections::hash_map::{Entry, HashMap};
+use std::collections::HashSet;
 use std::convert::TryInto;
-use std::sync::atomic::AtomicU64;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+use std::sync::{Arc, Mutex};
 
 const SLP_SWAP_VOUT: usize = 1;
 const SLP_FEE_VOUT: usize = 1;
+/// The CashAddr prefix of the SLP addresses on the mainnet, can be overridden with `slp_prefix` of the token config.
+pub const SLP_MAINNET_PREFIX: &str = "simpleledger";
 
 #[derive(Debug)]
 pub struct SlpTokenConf {
     decimals: u8,
     ticker: String,
     token_id: H256,
+    /// The CashAddr prefix of the token addresses, e.g. `simpleledger` or `slptest`.
+    slp_prefix: String,
     required_confirmations: AtomicU64,
+    history_sync_state: Mutex<HistorySyncState>,
 }
 
 #[derive(Clone, Debug)]
@@ -64,7 +82,10 @@ struct SlpOutput {
 
 /// The SLP transaction preimage
 struct SlpTxPreimage<'a> {
-    inputs: Vec<UnspentInfo>,
+    /// The SLP-bearing unspents that must be spent all, otherwise the tokens are burned.
+    slp_inputs: Vec<UnspentInfo>,
+    /// The platform coin unspents paying the fee.
+    bch_inputs: Vec<UnspentInfo>,
     outputs: Vec<TransactionOutput>,
     recently_spent: AsyncMutexGuard<'a, RecentlySpentOutPoints>,
 }
@@ -124,6 +145,15 @@ impl From<String> for SpendP2SHError {
     fn from(err: String) -> SpendP2SHError { SpendP2SHError::String(err) }
 }
 
+impl From<GenSlpSpendErr> for SpendP2SHError {
+    fn from(err: GenSlpSpendErr) -> SpendP2SHError {
+        match err {
+            GenSlpSpendErr::GetUnspentsErr(e) => SpendP2SHError::GetUnspentsErr(e),
+            e => SpendP2SHError::String(e.to_string()),
+        }
+    }
+}
+
 #[derive(Debug, Display)]
 pub enum SpendHtlcError {
     TxLackOfOutputs,
@@ -163,18 +193,28 @@ impl SlpToken {
         decimals: u8,
         ticker: String,
         token_id: H256,
+        slp_prefix: String,
         platform_utxo: UtxoStandardCoin,
         required_confirmations: u64,
+        history_sync_state: HistorySyncState,
     ) -> SlpToken {
         let conf = Arc::new(SlpTokenConf {
             decimals,
             ticker,
             token_id,
+            slp_prefix,
             required_confirmations: AtomicU64::new(required_confirmations),
+            history_sync_state: Mutex::new(history_sync_state),
         });
         SlpToken { conf, platform_utxo }
     }
 
+    /// Fails with `WatchOnlyError` if the platform coin has no signer to sign the swap transactions.
+    fn check_platform_signer(&self) -> Result<(), TransactionErr> {
+        self.platform_utxo.as_ref().key_pair.signer()?;
+        Ok(())
+    }
+
     fn rpc(&self) -> &UtxoRpcClientEnum { &self.platform_utxo.as_ref().rpc_client }
 
     /// Returns unspents of the SLP token plus plain BCH UTXOs plus RecentlySpentOutPoints mutex guard
@@ -192,7 +232,95 @@ impl SlpToken {
             .platform_utxo
             .list_unspent_ordered(&self.platform_utxo.as_ref().my_address)
             .await?;
+        let outpoints: Vec<OutPoint> = unspents.iter().map(|unspent| unspent.outpoint.clone()).collect();
+        let (slp_unspents, bch_unspents) = self.split_slp_unspents(unspents).await?;
+        self.freeze_token_outpoints(outpoints, &bch_unspents).await;
+        Ok((slp_unspents, bch_unspents, recently_spent))
+    }
+
+    /// Returns unspents of the SLP token plus plain BCH UTXOs of every address of the platform coin HD account,
+    /// the addresses owning them plus RecentlySpentOutPoints mutex guard
+    async fn hd_account_slp_unspents(
+        &self,
+        hd_account: &UtxoHDAccount,
+    ) -> Result<
+        (
+            Vec<SlpUnspent>,
+            Vec<UnspentInfo>,
+            Vec<(UnspentInfo, UtxoHDAddress)>,
+            AsyncMutexGuard<'_, RecentlySpentOutPoints>,
+        ),
+        MmError<SlpUnspentsErr>,
+    > {
+        let mut slp_unspents = Vec::new();
+        let mut bch_unspents = Vec::new();
+        let mut owners = Vec::new();
+        let mut outpoints = Vec::new();
+        for hd_address in hd_account.all_addresses() {
+            let (unspents, _recently_spent) = self.platform_utxo.list_unspent_ordered(&hd_address.address).await?;
+            outpoints.extend(unspents.iter().map(|unspent| unspent.outpoint.clone()));
+            let (address_slp_unspents, address_bch_unspents) = self.split_slp_unspents(unspents).await?;
+            owners.extend(
+                address_slp_unspents
+                    .iter()
+                    .map(|unspent| &unspent.bch_unspent)
+                    .chain(address_bch_unspents.iter())
+                    .map(|unspent| (unspent.clone(), hd_address.clone())),
+            );
+            slp_unspents.extend(address_slp_unspents);
+            bch_unspents.extend(address_bch_unspents);
+        }
+        slp_unspents.sort_by(|a, b| a.slp_amount.cmp(&b.slp_amount));
+        bch_unspents.sort_by(|a, b| a.value.cmp(&b.value));
+        let recently_spent = self.platform_utxo.as_ref().recently_spent_outpoints.lock().await;
+        self.freeze_token_outpoints(outpoints, &bch_unspents).await;
+        Ok((slp_unspents, bch_unspents, owners, recently_spent))
+    }
+
+    /// Freezes the platform coin `outpoints` except for the plain BCH ones,
+    /// so neither the platform coin withdraws nor its swaps burn the SLP tokens.
+    /// The outputs of the tokens not enabled are frozen too since they're not plain BCH.
+    async fn freeze_token_outpoints(&self, outpoints: Vec<OutPoint>, bch_unspents: &[UnspentInfo]) {
+        let token_outpoints = outpoints
+            .into_iter()
+            .filter(|outpoint| bch_unspents.iter().all(|unspent| unspent.outpoint != *outpoint));
+        self.platform_utxo
+            .as_ref()
+            .frozen_outpoints
+            .lock()
+            .await
+            .freeze_token_outpoints(self.ticker(), token_outpoints);
+    }
+
+    /// Freezes the outputs of the platform coin carrying the SLP tokens, see [`SlpToken::freeze_token_outpoints`].
+    /// Called on the token activation, the outputs are frozen again each time the token lists its unspents.
+    pub async fn freeze_platform_token_outpoints(&self) -> Result<(), MmError<SlpUnspentsErr>> {
+        match self.platform_utxo.as_ref().hd_account {
+            Some(ref hd_account) => {
+                self.hd_account_slp_unspents(hd_account).await?;
+            },
+            None => {
+                self.slp_unspents().await?;
+            },
+        }
+        Ok(())
+    }
+
+    /// Unfreezes the outputs of the platform coin carrying the SLP tokens once the token is disabled.
+    pub async fn unfreeze_platform_token_outpoints(&self) {
+        self.platform_utxo
+            .as_ref()
+            .frozen_outpoints
+            .lock()
+            .await
+            .unfreeze_token_outpoints(self.ticker());
+    }
 
+    /// Splits the platform coin unspents into the unspents of the SLP token and plain BCH UTXOs
+    async fn split_slp_unspents(
+        &self,
+        unspents: Vec<UnspentInfo>,
+    ) -> Result<(Vec<SlpUnspent>, Vec<UnspentInfo>), MmError<SlpUnspentsErr>> {
         let mut slp_unspents = vec![];
         let mut bch_unspents = vec![];
 
@@ -252,7 +380,23 @@ impl SlpToken {
         }
 
         slp_unspents.sort_by(|a, b| a.slp_amount.cmp(&b.slp_amount));
-        Ok((slp_unspents, bch_unspents, recently_spent))
+        Ok((slp_unspents, bch_unspents))
+    }
+
+    /// Returns the OP_RETURN output of the SLP SEND transaction transferring the `amounts` to the outputs 1..N
+    fn send_op_return_output(&self, amounts: &[u64]) -> Result<TransactionOutput, MmError<GenSlpSpendErr>> {
+        // TODO generate the script in MM2 instead of using the external library
+        let token_id = TokenId::from_slice(self.token_id().as_slice())
+            .map_to_mm(|e| GenSlpSpendErr::OpReturnErr(format!("Invalid token id: {:?}", e)))?;
+        let op_return_out = slp_send_output(SlpTokenType::Fungible, &token_id, amounts);
+        let script_pubkey = op_return_out
+            .script
+            .serialize()
+            .map_to_mm(|e| GenSlpSpendErr::OpReturnErr(format!("Error on OP_RETURN script serialization: {:?}", e)))?;
+        Ok(TransactionOutput {
+            value: 0,
+            script_pubkey: script_pubkey.to_vec().into(),
+        })
     }
 
     /// Generates the tx preimage that spends the SLP from my address to the desired destinations (script pubkeys)
@@ -261,17 +405,29 @@ impl SlpToken {
         slp_outputs: Vec<SlpOutput>,
     ) -> Result<SlpTxPreimage<'_>, MmError<GenSlpSpendErr>> {
         let (slp_unspents, bch_unspents, recently_spent) = self.slp_unspents().await?;
+        self.slp_tx_preimage(slp_unspents, bch_unspents, recently_spent, slp_outputs)
+    }
+
+    /// Selects the SLP unspents covering the `slp_outputs` and builds the outputs of the tx preimage
+    /// sending the SLP change back to my address.
+    fn slp_tx_preimage<'a>(
+        &self,
+        slp_unspents: Vec<SlpUnspent>,
+        bch_unspents: Vec<UnspentInfo>,
+        recently_spent: AsyncMutexGuard<'a, RecentlySpentOutPoints>,
+        slp_outputs: Vec<SlpOutput>,
+    ) -> Result<SlpTxPreimage<'a>, MmError<GenSlpSpendErr>> {
         let total_slp_output = slp_outputs.iter().fold(0, |cur, slp_out| cur + slp_out.amount);
         let mut total_slp_input = 0;
 
-        let mut inputs = vec![];
+        let mut slp_inputs = vec![];
         for slp_utxo in slp_unspents {
             if total_slp_input >= total_slp_output {
                 break;
             }
 
             total_slp_input += slp_utxo.slp_amount;
-            inputs.push(slp_utxo.bch_unspent);
+            slp_inputs.push(slp_utxo.bch_unspent);
         }
 
         if total_slp_input < total_slp_output {
@@ -279,24 +435,12 @@ impl SlpToken {
         }
         let change = total_slp_input - total_slp_output;
 
-        inputs.extend(bch_unspents);
-
         let mut amounts_for_op_return: Vec<_> = slp_outputs.iter().map(|spend_to| spend_to.amount).collect();
         if change > 0 {
             amounts_for_op_return.push(change);
         }
 
-        // TODO generate the script in MM2 instead of using the external library
-        let op_return_out = slp_send_output(
-            SlpTokenType::Fungible,
-            &TokenId::from_slice(self.token_id().as_slice()).unwrap(),
-            &amounts_for_op_return,
-        );
-        let op_return_out_mm = TransactionOutput {
-            value: 0,
-            script_pubkey: op_return_out.script.serialize().unwrap().to_vec().into(),
-        };
-        let mut outputs = vec![op_return_out_mm];
+        let mut outputs = vec![self.send_op_return_output(&amounts_for_op_return)?];
 
         outputs.extend(slp_outputs.into_iter().map(|spend_to| TransactionOutput {
             value: self.dust(),
@@ -313,7 +457,8 @@ impl SlpToken {
         }
 
         Ok(SlpTxPreimage {
-            inputs,
+            slp_inputs,
+            bch_inputs: bch_unspents,
             outputs,
             recently_spent,
         })
@@ -332,9 +477,11 @@ impl SlpToken {
         let preimage = try_s!(self.generate_slp_tx_preimage(vec![slp_out]).await);
         generate_and_send_tx(
             &self.platform_utxo,
-            preimage.inputs,
+            preimage.slp_inputs,
+            preimage.bch_inputs,
             preimage.outputs,
             FeePolicy::SendExact,
+            None,
             preimage.recently_spent,
         )
         .await
@@ -500,17 +647,8 @@ impl SlpToken {
         script_data: Script,
         redeem_script: Script,
     ) -> Result<UtxoTx, MmError<SpendP2SHError>> {
-        let op_return = slp_send_output(
-            SlpTokenType::Fungible,
-            &TokenId::from_slice(self.token_id().as_slice()).unwrap(),
-            &[p2sh_utxo.slp_amount],
-        );
-        let op_return_out_mm = TransactionOutput {
-            value: 0,
-            script_pubkey: op_return.script.serialize().unwrap().to_vec().into(),
-        };
         let mut outputs = Vec::with_capacity(3);
-        outputs.push(op_return_out_mm);
+        outputs.push(self.send_op_return_output(&[p2sh_utxo.slp_amount])?);
 
         let my_script_pubkey = ScriptBuilder::build_p2pkh(&self.platform_utxo.my_public_key().address_hash());
         let slp_output = TransactionOutput {
@@ -519,25 +657,33 @@ impl SlpToken {
         };
         outputs.push(slp_output);
 
-        let (_, mut bch_inputs, _recently_spent) = self.slp_unspents().await?;
-        bch_inputs.insert(0, p2sh_utxo.bch_unspent);
-        let (mut unsigned, _) = generate_transaction(
+        let (_, bch_inputs, _recently_spent) = self.slp_unspents().await?;
+        // the p2sh output is always spent by the first input
+        let (mut unsigned, data) = generate_transaction_with_coin_selection(
             &self.platform_utxo,
+            vec![p2sh_utxo.bch_unspent],
             bch_inputs,
             outputs,
             FeePolicy::SendExact,
             None,
             None,
+            CoinSelectionStrategy::SmallestFirst,
         )
         .await?;
 
         unsigned.lock_time = tx_locktime;
         unsigned.inputs[0].sequence = input_sequence;
 
+        let key_pair = self
+            .platform_utxo
+            .as_ref()
+            .key_pair
+            .signer()
+            .map_to_mm(|e| SpendP2SHError::String(e.to_string()))?;
         let signed_p2sh_input = p2sh_spend(
             &unsigned,
             0,
-            &self.platform_utxo.as_ref().key_pair,
+            key_pair,
             script_data,
             redeem_script,
             self.platform_utxo.as_ref().conf.signature_version,
@@ -553,7 +699,7 @@ impl SlpToken {
                 p2pkh_spend(
                     &unsigned,
                     i,
-                    &self.platform_utxo.as_ref().key_pair,
+                    key_pair,
                     &my_script_pubkey,
                     self.platform_utxo.as_ref().conf.signature_version,
                     self.platform_utxo.as_ref().conf.fork_id,
@@ -591,6 +737,7 @@ impl SlpToken {
             .send_raw_transaction(serialize(&signed).into())
             .compat()
             .await?;
+        utxo_common::remember_change_address(self.platform_utxo.as_ref(), &data).map_to_mm(SpendP2SHError::String)?;
         Ok(signed)
     }
 
@@ -647,6 +794,338 @@ impl SlpToken {
         Ok(())
     }
 
+    /// Returns the platform coin fee of the transaction sending the token to the `slp_outputs`.
+    /// The token change output is taken into account as the whole balance is rarely sent.
+    async fn slp_outputs_trade_fee(
+        &self,
+        slp_outputs: Vec<SlpOutput>,
+        stage: FeeApproxStage,
+    ) -> TradePreimageResult<TradeFee> {
+        let mut amounts: Vec<u64> = slp_outputs.iter().map(|slp_out| slp_out.amount).collect();
+        // the change amount doesn't affect the transaction size
+        amounts.push(0);
+
+        let op_return_out = self
+            .send_op_return_output(&amounts)
+            .mm_err(|e| TradePreimageError::InternalError(e.to_string()))?;
+        let mut outputs = vec![op_return_out];
+        outputs.extend(slp_outputs.into_iter().map(|slp_out| TransactionOutput {
+            value: self.dust(),
+            script_pubkey: slp_out.script_pubkey,
+        }));
+        outputs.push(TransactionOutput {
+            value: self.dust(),
+            script_pubkey: ScriptBuilder::build_p2pkh(&self.platform_utxo.as_ref().my_address.hash).to_bytes(),
+        });
+
+        let fee = self
+            .platform_utxo
+            .preimage_trade_fee_required_to_send_outputs(outputs, FeePolicy::SendExact, None, &stage)
+            .await?;
+        Ok(TradeFee {
+            coin: self.platform_ticker().to_owned(),
+            amount: fee.into(),
+            paid_from_trading_vol: false,
+        })
+    }
+
+    /// Returns the details of the platform transaction if it transfers the token from or to my address.
+    /// Please note the SLP validity of the transaction isn't checked, the same as for the balance.
+    async fn tx_details_by_hash(
+        &self,
+        txid: &H256Json,
+        input_transactions: &mut HistoryUtxoTxMap,
+    ) -> Result<Option<TransactionDetails>, String> {
+        let platform = &self.platform_utxo;
+        let verbose_tx = try_s!(self.rpc().get_verbose_transaction(txid.clone()).compat().await);
+        let mut tx: UtxoTx = try_s!(deserialize(verbose_tx.hex.as_slice()).map_err(|e| ERRL!("{:?}", e)));
+        tx.tx_hash_algo = platform.as_ref().tx_hash_algo;
+
+        let mut from_addresses = Vec::new();
+        let mut to_addresses = Vec::new();
+        let mut input_amount = 0;
+        let mut output_amount = 0;
+        let mut spent_by_me = 0;
+        let mut received_by_me = 0;
+        // the fee is paid in the platform coin
+        let mut platform_input = 0;
+        let mut platform_output = 0;
+
+        for input in tx.inputs.iter() {
+            // input transaction is zero if the tx is the coinbase transaction
+            if input.previous_output.hash.is_zero() {
+                continue;
+            }
+
+            let prev_tx_hash: H256Json = input.previous_output.hash.reversed().into();
+            let prev_tx = try_s!(
+                platform
+                    .get_mut_verbose_transaction_from_map_or_rpc(prev_tx_hash.clone(), input_transactions)
+                    .await
+            );
+            let prev_tx = &mut prev_tx.tx;
+            prev_tx.tx_hash_algo = platform.as_ref().tx_hash_algo;
+
+            let index = input.previous_output.index as usize;
+            let prev_output = match prev_tx.outputs.get(index) {
+                Some(output) => output,
+                None => return ERR!("{:?} has no output {}", prev_tx_hash, index),
+            };
+            platform_input += prev_output.value;
+
+            let amount = match slp_output_amount(prev_tx, self.token_id(), index) {
+                Some(amount) => amount,
+                None => continue,
+            };
+            input_amount += amount;
+            let from = try_s!(platform.addresses_from_script(&prev_output.script_pubkey.clone().into()));
+            if from.iter().any(|address| platform.as_ref().is_my_address(address)) {
+                spent_by_me += amount;
+            }
+            from_addresses.extend(from.into_iter());
+        }
+
+        for (index, output) in tx.outputs.iter().enumerate() {
+            platform_output += output.value;
+
+            let amount = match slp_output_amount(&tx, self.token_id(), index) {
+                Some(amount) => amount,
+                None => continue,
+            };
+            output_amount += amount;
+            let to = try_s!(platform.addresses_from_script(&output.script_pubkey.clone().into()));
+            if to.iter().any(|address| platform.as_ref().is_my_address(address)) {
+                received_by_me += amount;
+            }
+            to_addresses.extend(to.into_iter());
+        }
+
+        if spent_by_me == 0 && received_by_me == 0 {
+            return Ok(None);
+        }
+
+        let mut from_addresses: Vec<String> =
+            try_s!(from_addresses.iter().map(|addr| self.slp_address(addr)).collect());
+        from_addresses.sort();
+        from_addresses.dedup();
+        let mut to_addresses: Vec<String> = try_s!(to_addresses.iter().map(|addr| self.slp_address(addr)).collect());
+        to_addresses.sort();
+        to_addresses.dedup();
+
+        // the GENESIS and MINT transactions don't spend the token
+        let total_amount = if input_amount > 0 { input_amount } else { output_amount };
+        let fee = platform_input as i64 - platform_output as i64;
+        let fee_details = UtxoFeeDetails {
+            amount: big_decimal_from_sat(fee, platform.as_ref().decimals),
+        };
+
+        Ok(Some(TransactionDetails {
+            from: from_addresses,
+            to: to_addresses,
+            received_by_me: big_decimal_from_sat_unsigned(received_by_me, self.decimals()),
+            spent_by_me: big_decimal_from_sat_unsigned(spent_by_me, self.decimals()),
+            my_balance_change: big_decimal_from_sat(received_by_me as i64 - spent_by_me as i64, self.decimals()),
+            total_amount: big_decimal_from_sat_unsigned(total_amount, self.decimals()),
+            tx_hash: tx.hash().reversed().to_vec().into(),
+            tx_hex: verbose_tx.hex,
+            fee_details: Some(fee_details.into()),
+            block_height: verbose_tx.height.unwrap_or(0),
+            coin: self.ticker().to_owned(),
+            internal_id: tx.hash().reversed().to_vec().into(),
+            timestamp: verbose_tx.time.into(),
+            kmd_rewards: None,
+            psbt: None,
+        }))
+    }
+
+    /// Builds the token history from the transactions of the platform coin address.
+    async fn history_loop(self, ctx: MmArc) {
+        let ticker = self.ticker().to_owned();
+        let history = match self.load_history_from_file(&ctx).compat().await {
+            Ok(history) => history,
+            Err(e) => {
+                ctx.log.log(
+                    "",
+                    &[&"tx_history", &ticker],
+                    &ERRL!("Error {} on 'load_history_from_file', stop the history loop", e),
+                );
+                return;
+            },
+        };
+        let mut history_map: HashMap<H256Json, TransactionDetails> = history
+            .into_iter()
+            .map(|tx| (H256Json::from(tx.tx_hash.as_slice()), tx))
+            .collect();
+        // the platform transactions that don't transfer the token from or to my address
+        let mut skipped_txs = HashSet::new();
+
+        let mut success_iteration = 0i32;
+        loop {
+            if ctx.is_stopping() {
+                break;
+            };
+            {
+                let coins_ctx = CoinsContext::from_ctx(&ctx).unwrap();
+                let coins = coins_ctx.coins.lock().await;
+                if !coins.contains_key(&ticker) {
+                    ctx.log.log("", &[&"tx_history", &ticker], "Loop stopped");
+                    break;
+                };
+            }
+
+            let tx_ids = match utxo_common::request_tx_history(&self.platform_utxo, ctx.metrics.clone()).await {
+                RequestTxHistoryResult::Ok(tx_ids) => tx_ids,
+                RequestTxHistoryResult::Retry { error } => {
+                    ctx.log
+                        .log("", &[&"tx_history", &ticker], &ERRL!("{}, retrying", error));
+                    Timer::sleep(10.).await;
+                    continue;
+                },
+                RequestTxHistoryResult::HistoryTooLarge => {
+                    ctx.log.log(
+                        "",
+                        &[&"tx_history", &ticker],
+                        &ERRL!("Got `history too large`, stopping further attempts to retrieve it"),
+                    );
+                    *self.conf.history_sync_state.lock().unwrap() = HistorySyncState::Error(json!({
+                        "code": HISTORY_TOO_LARGE_ERR_CODE,
+                        "message": "Got `history too large` error from Electrum server. History is not available",
+                    }));
+                    break;
+                },
+                RequestTxHistoryResult::UnknownError(e) => {
+                    ctx.log.log(
+                        "",
+                        &[&"tx_history", &ticker],
+                        &ERRL!("{}, stopping futher attempts to retreive it", e),
+                    );
+                    break;
+                },
+            };
+
+            let mut transactions_left = tx_ids
+                .iter()
+                .filter(|(txid, _)| !history_map.contains_key(txid) && !skipped_txs.contains(txid))
+                .count();
+            *self.conf.history_sync_state.lock().unwrap() =
+                HistorySyncState::InProgress(json!({ "transactions_left": transactions_left }));
+
+            // This is the cache of the already requested transactions.
+            let mut input_transactions = HistoryUtxoTxMap::default();
+            let mut updated = false;
+            for (txid, height) in tx_ids {
+                if skipped_txs.contains(&txid) {
+                    continue;
+                }
+
+                match history_map.entry(txid.clone()) {
+                    Entry::Vacant(e) => match self.tx_details_by_hash(&txid, &mut input_transactions).await {
+                        Ok(tx_details) => {
+                            match tx_details {
+                                Some(mut tx_details) => {
+                                    if tx_details.block_height == 0 && height > 0 {
+                                        tx_details.block_height = height;
+                                    }
+                                    e.insert(tx_details);
+                                    updated = true;
+                                },
+                                None => {
+                                    skipped_txs.insert(txid);
+                                },
+                            }
+                            transactions_left = transactions_left.saturating_sub(1);
+                            *self.conf.history_sync_state.lock().unwrap() =
+                                HistorySyncState::InProgress(json!({ "transactions_left": transactions_left }));
+                        },
+                        Err(e) => ctx.log.log(
+                            "",
+                            &[&"tx_history", &ticker],
+                            &ERRL!("Error {:?} on getting the details of {:?}, skipping the tx", e, txid),
+                        ),
+                    },
+                    Entry::Occupied(mut e) => {
+                        if e.get().should_update_timestamp() {
+                            if let Ok(Some(tx_details)) = self.tx_details_by_hash(&txid, &mut input_transactions).await
+                            {
+                                e.insert(tx_details);
+                                updated = true;
+                            }
+                        }
+                        // update block height for previously unconfirmed transaction
+                        if e.get().should_update_block_height() && height > 0 {
+                            e.get_mut().block_height = height;
+                            updated = true;
+                        }
+                    },
+                }
+            }
+
+            if updated {
+                let mut to_write: Vec<TransactionDetails> = history_map.values().cloned().collect();
+                // the transactions with block_height == 0 are the most recent so we need to separately handle them while sorting
+                to_write.sort_unstable_by(|a, b| {
+                    if a.block_height == 0 {
+                        Ordering::Less
+                    } else if b.block_height == 0 {
+                        Ordering::Greater
+                    } else {
+                        b.block_height.cmp(&a.block_height)
+                    }
+                });
+                if let Err(e) = self.save_history_to_file(&ctx, to_write).compat().await {
+                    ctx.log.log(
+                        "",
+                        &[&"tx_history", &ticker],
+                        &ERRL!("Error {} on 'save_history_to_file', stop the history loop", e),
+                    );
+                    return;
+                };
+            }
+            *self.conf.history_sync_state.lock().unwrap() = HistorySyncState::Finished;
+
+            if success_iteration == 0 {
+                ctx.log.log(
+                    "😅",
+                    &[&"tx_history", &("coin", ticker.as_str())],
+                    "history has been loaded successfully",
+                );
+            }
+
+            success_iteration += 1;
+            Timer::sleep(30.).await;
+        }
+    }
+
+    /// Displays the address in the CashAddr format with the SLP prefix
+    pub fn slp_address(&self, address: &Address) -> Result<String, String> {
+        let conf = &self.platform_utxo.as_ref().conf;
+        address
+            .to_cashaddress(&self.conf.slp_prefix, conf.pub_addr_prefix, conf.p2sh_addr_prefix)
+            .and_then(|cashaddress| cashaddress.encode())
+    }
+
+    /// Parses the CashAddr address with the SLP prefix
+    pub fn address_from_str(&self, address: &str) -> Result<Address, String> {
+        let cashaddress = try_s!(CashAddress::decode(address));
+        if cashaddress.prefix.to_string() != self.conf.slp_prefix {
+            return ERR!(
+                "Expected '{}' address prefix, found '{}'",
+                self.conf.slp_prefix,
+                cashaddress.prefix
+            );
+        }
+        let platform = self.platform_utxo.as_ref();
+        Address::from_cashaddress(
+            address,
+            platform.conf.checksum_type,
+            platform.conf.pub_addr_prefix,
+            platform.conf.p2sh_addr_prefix,
+            platform.my_address.t_addr_prefix,
+        )
+    }
+
+    pub fn platform_ticker(&self) -> &str { self.platform_utxo.ticker() }
+
     pub fn dust(&self) -> u64 { self.platform_utxo.as_ref().dust_amount }
 
     pub fn decimals(&self) -> u8 { self.conf.decimals }
@@ -816,24 +1295,280 @@ impl From<SlpUnspentsErr> for BalanceError {
 }
 
 #[derive(Debug, Display)]
-enum GenSlpSpendErr {
+pub enum GenSlpSpendErr {
     GetUnspentsErr(SlpUnspentsErr),
     InsufficientSlpBalance,
+    OpReturnErr(String),
 }
 
 impl From<SlpUnspentsErr> for GenSlpSpendErr {
     fn from(err: SlpUnspentsErr) -> GenSlpSpendErr { GenSlpSpendErr::GetUnspentsErr(err) }
 }
 
+impl From<GenSlpSpendErr> for WithdrawError {
+    fn from(err: GenSlpSpendErr) -> WithdrawError {
+        match err {
+            GenSlpSpendErr::GetUnspentsErr(SlpUnspentsErr::RpcError(e)) => WithdrawError::Transport(e.to_string()),
+            e => WithdrawError::InternalError(e.to_string()),
+        }
+    }
+}
+
+/// Returns the amount of the token held by the `vout` output of the transaction
+/// or `None` if the output doesn't hold the token.
+fn slp_output_amount(tx: &UtxoTx, token_id: &H256, vout: usize) -> Option<u64> {
+    let slp_data = parse_slp_script(&tx.outputs.first()?.script_pubkey).ok()?;
+    match slp_data.transaction {
+        SlpTransaction::Send {
+            token_id: send_token_id,
+            amounts,
+        } if send_token_id == *token_id && vout > 0 => amounts.get(vout - 1).copied(),
+        SlpTransaction::Genesis {
+            initial_token_mint_quantity,
+            ..
+        } if tx.hash().reversed() == *token_id && vout == 1 => {
+            initial_token_mint_quantity.try_into().ok().map(u64::from_be_bytes)
+        },
+        SlpTransaction::Mint {
+            token_id: mint_token_id,
+            additional_token_quantity,
+            ..
+        } if mint_token_id == *token_id && vout == 1 => {
+            additional_token_quantity.try_into().ok().map(u64::from_be_bytes)
+        },
+        _ => None,
+    }
+}
+
+/// Returns the SLP address of the `pubkey` hex, the prefix is taken from the token config.
+pub fn slp_addr_from_pubkey_str(pubkey: &str, conf: &Json) -> Result<String, String> {
+    let pubkey_bytes = try_s!(hex::decode(pubkey));
+    let hash = dhash160(&pubkey_bytes);
+    let slp_prefix = conf["slp_prefix"].as_str().unwrap_or(SLP_MAINNET_PREFIX);
+    let address = try_s!(CashAddress::new(slp_prefix, hash.take().to_vec(), CashAddrType::P2PKH));
+    address.encode()
+}
+
+/// Activates the SLP token on top of the already enabled platform coin, e.g. BCH.
+pub async fn slp_token_from_conf_and_request(
+    ctx: &MmArc,
+    ticker: &str,
+    platform: &str,
+    token_id: H256Json,
+    conf: &Json,
+    req: &Json,
+) -> Result<SlpToken, String> {
+    let platform_utxo = match lp_coinfind(ctx, platform).await {
+        Ok(Some(MmCoinEnum::UtxoCoin(coin))) => coin,
+        Ok(Some(_)) => return ERR!("Platform coin {} of {} is not a UTXO coin", platform, ticker),
+        Ok(None) => return ERR!("Platform coin {} should be enabled before {}", platform, ticker),
+        Err(e) => return ERR!("Error {} on getting the platform coin {}", e, platform),
+    };
+
+    let decimals = match conf["decimals"].as_u64() {
+        Some(decimals) => decimals as u8,
+        None => return ERR!("'decimals' is not set in the {} config", ticker),
+    };
+    let slp_prefix = conf["slp_prefix"].as_str().unwrap_or(SLP_MAINNET_PREFIX).to_owned();
+    // param from request should override the config
+    let required_confirmations = req["required_confirmations"]
+        .as_u64()
+        .unwrap_or_else(|| conf["required_confirmations"].as_u64().unwrap_or(1));
+    let history_sync_state = if req["tx_history"].as_bool().unwrap_or(false) {
+        HistorySyncState::NotStarted
+    } else {
+        HistorySyncState::NotEnabled
+    };
+
+    Ok(SlpToken::new(
+        decimals,
+        ticker.to_owned(),
+        token_id.into(),
+        slp_prefix,
+        platform_utxo,
+        required_confirmations,
+        history_sync_state,
+    ))
+}
+
+async fn slp_withdraw(coin: SlpToken, req: WithdrawRequest) -> WithdrawResult {
+    req.ensure_no_utxo_specific_params()?;
+    let to = coin
+        .address_from_str(&req.to)
+        .map_to_mm(WithdrawError::InvalidAddress)?;
+    let platform = coin.platform_utxo.as_ref();
+    let script_type = if to.prefix == platform.conf.pub_addr_prefix {
+        ScriptType::P2PKH
+    } else if to.prefix == platform.conf.p2sh_addr_prefix {
+        ScriptType::P2SH
+    } else {
+        return MmError::err(WithdrawError::InvalidAddress("Expected either P2PKH or P2SH".into()));
+    };
+
+    let _utxo_lock = UTXO_LOCK.lock().await;
+
+    let balance = coin.my_balance().compat().await?.spendable;
+    let (amount_sat, amount) = if req.max {
+        let amount_sat = sat_from_big_decimal(&balance, coin.decimals())?;
+        if amount_sat == 0 {
+            return MmError::err(WithdrawError::ZeroBalanceToWithdrawMax);
+        }
+        (amount_sat, balance)
+    } else {
+        if req.amount > balance {
+            return MmError::err(WithdrawError::NotSufficientBalance {
+                coin: coin.ticker().to_owned(),
+                available: balance,
+                required: req.amount,
+            });
+        }
+        let amount_sat = sat_from_big_decimal(&req.amount, coin.decimals())?;
+        if amount_sat == 0 {
+            return MmError::err(WithdrawError::AmountTooLow {
+                amount: req.amount,
+                threshold: coin.min_tx_amount(),
+            });
+        }
+        (amount_sat, req.amount)
+    };
+
+    // the fee is paid in the platform coin
+    let fee = match req.fee {
+        Some(WithdrawFee::UtxoFixed { amount }) => {
+            let fixed = sat_from_big_decimal(&amount, platform.decimals)?;
+            Some(ActualTxFee::FixedPerKb(fixed))
+        },
+        Some(WithdrawFee::UtxoPerKbyte { amount }) => {
+            let dynamic = sat_from_big_decimal(&amount, platform.decimals)?;
+            Some(ActualTxFee::Dynamic(dynamic))
+        },
+        Some(fee_policy) => {
+            let error = format!(
+                "Expected 'UtxoFixed' or 'UtxoPerKbyte' fee types, found {:?}",
+                fee_policy
+            );
+            return MmError::err(WithdrawError::InvalidFeePolicy(error));
+        },
+        None => None,
+    };
+
+    let slp_out = SlpOutput {
+        amount: amount_sat,
+        script_pubkey: output_script(&to, script_type).to_bytes(),
+    };
+    let hd_account = platform.hd_account.as_ref();
+    let (preimage, hd_unspents) = match hd_account {
+        Some(hd_account) => {
+            let (slp_unspents, bch_unspents, hd_unspents, recently_spent) = coin
+                .hd_account_slp_unspents(hd_account)
+                .await
+                .mm_err(GenSlpSpendErr::from)?;
+            let preimage = coin.slp_tx_preimage(slp_unspents, bch_unspents, recently_spent, vec![slp_out])?;
+            (preimage, Some(hd_unspents))
+        },
+        None => (coin.generate_slp_tx_preimage(vec![slp_out]).await?, None),
+    };
+    let (unsigned, data) = generate_transaction_with_coin_selection(
+        &coin.platform_utxo,
+        preimage.slp_inputs,
+        preimage.bch_inputs,
+        preimage.outputs,
+        FeePolicy::SendExact,
+        fee,
+        None,
+        CoinSelectionStrategy::SmallestFirst,
+    )
+    .await
+    .mm_err(|gen_tx_error| {
+        WithdrawError::from_generate_tx_error(gen_tx_error, coin.platform_ticker().to_owned(), platform.decimals)
+    })?;
+
+    let (signed, from) = match (hd_account, hd_unspents) {
+        (Some(hd_account), Some(hd_unspents)) => {
+            let input_owners =
+                utxo_common::input_owners(&unsigned, &hd_unspents).map_to_mm(WithdrawError::InternalError)?;
+            let mut key_pairs = Vec::with_capacity(input_owners.len());
+            let mut from = Vec::new();
+            for owner in input_owners {
+                let key_pair = hd_account
+                    .key_pair(&platform.conf, &owner)
+                    .map_to_mm(WithdrawError::InternalError)?;
+                key_pairs.push(key_pair);
+                let address = coin
+                    .slp_address(&owner.address)
+                    .map_to_mm(WithdrawError::InternalError)?;
+                if !from.contains(&address) {
+                    from.push(address);
+                }
+            }
+            let signed = sign_tx_with_key_pairs(
+                unsigned,
+                &key_pairs,
+                platform.conf.signature_version,
+                platform.conf.fork_id,
+            )
+            .map_to_mm(WithdrawError::InternalError)?;
+            (signed, from)
+        },
+        _ => {
+            let prev_script = ScriptBuilder::build_p2pkh(&platform.my_address.hash);
+            let signed = sign_tx(
+                unsigned,
+                platform.key_pair.signer()?,
+                prev_script,
+                platform.conf.signature_version,
+                platform.conf.fork_id,
+            )
+            .map_to_mm(WithdrawError::InternalError)?;
+            let my_address = coin.my_address().map_to_mm(WithdrawError::InternalError)?;
+            (signed, vec![my_address])
+        },
+    };
+    utxo_common::remember_change_address(platform, &data).map_to_mm(WithdrawError::InternalError)?;
+
+    let to_address = coin.slp_address(&to).map_to_mm(WithdrawError::InternalError)?;
+    let received_by_me = if platform.is_my_address(&to) {
+        amount.clone()
+    } else {
+        0.into()
+    };
+    let my_balance_change = &received_by_me - &amount;
+    let fee_amount = data.fee_amount + data.unused_change.unwrap_or_default();
+    let fee_details = UtxoFeeDetails {
+        amount: big_decimal_from_sat_unsigned(fee_amount, platform.decimals),
+    };
+
+    Ok(TransactionDetails {
+        from,
+        to: vec![to_address],
+        total_amount: amount.clone(),
+        spent_by_me: amount,
+        received_by_me,
+        my_balance_change,
+        tx_hash: signed.hash().reversed().to_vec().into(),
+        tx_hex: serialize(&signed).into(),
+        fee_details: Some(fee_details.into()),
+        block_height: 0,
+        coin: coin.ticker().to_owned(),
+        internal_id: vec![].into(),
+        timestamp: now_ms() / 1000,
+        kmd_rewards: None,
+        psbt: None,
+    })
+}
+
 impl MarketCoinOps for SlpToken {
     fn ticker(&self) -> &str { &self.conf.ticker }
 
-    fn my_address(&self) -> Result<String, String> { unimplemented!() }
+    fn my_address(&self) -> Result<String, String> { self.slp_address(&self.platform_utxo.as_ref().my_address) }
 
     fn my_balance(&self) -> BalanceFut<CoinBalance> {
         let coin = self.clone();
         let fut = async move {
-            let (slp_unspents, _, _) = coin.slp_unspents().await?;
+            let slp_unspents = match coin.platform_utxo.as_ref().hd_account {
+                Some(ref hd_account) => coin.hd_account_slp_unspents(hd_account).await?.0,
+                None => coin.slp_unspents().await?.0,
+            };
             let spendable_sat = slp_unspents.iter().fold(0, |cur, unspent| cur + unspent.slp_amount);
             let spendable = big_decimal_from_sat_unsigned(spendable_sat, coin.decimals());
             Ok(CoinBalance {
@@ -896,24 +1631,27 @@ impl MarketCoinOps for SlpToken {
 
 impl SwapOps for SlpToken {
     fn send_taker_fee(&self, fee_addr: &[u8], amount: BigDecimal) -> TransactionFut {
+        try_f!(self.check_platform_signer());
         let coin = self.clone();
-        let fee_pubkey = try_fus!(Public::from_slice(fee_addr));
+        let fee_pubkey = try_tx_fus!(Public::from_slice(fee_addr));
         let script_pubkey = ScriptBuilder::build_p2pkh(&fee_pubkey.address_hash()).into();
-        let amount = try_fus!(sat_from_big_decimal(&amount, self.decimals()));
+        let amount = try_tx_fus!(sat_from_big_decimal(&amount, self.decimals()));
 
         let fut = async move {
             let slp_out = SlpOutput { amount, script_pubkey };
             let preimage = try_s!(coin.generate_slp_tx_preimage(vec![slp_out]).await);
             generate_and_send_tx(
                 &coin.platform_utxo,
-                preimage.inputs,
+                preimage.slp_inputs,
+                preimage.bch_inputs,
                 preimage.outputs,
                 FeePolicy::SendExact,
+                None,
                 preimage.recently_spent,
             )
             .await
         };
-        Box::new(fut.boxed().compat().map(|tx| tx.into()))
+        Box::new(fut.boxed().compat().map(|tx| tx.into()).map_err(TransactionErr::Plain))
     }
 
     fn send_maker_payment(
@@ -923,9 +1661,11 @@ impl SwapOps for SlpToken {
         secret_hash: &[u8],
         amount: BigDecimal,
         _swap_contract_address: &Option<BytesJson>,
+        _other_protocol_info: &Option<Vec<u8>>,
     ) -> TransactionFut {
-        let taker_pub = try_fus!(Public::from_slice(taker_pub));
-        let amount = try_fus!(sat_from_big_decimal(&amount, self.decimals()));
+        try_f!(self.check_platform_signer());
+        let taker_pub = try_tx_fus!(Public::from_slice(taker_pub));
+        let amount = try_tx_fus!(sat_from_big_decimal(&amount, self.decimals()));
         let secret_hash = secret_hash.to_owned();
 
         let coin = self.clone();
@@ -933,7 +1673,7 @@ impl SwapOps for SlpToken {
             let tx = try_s!(coin.send_htlc(&taker_pub, time_lock, &secret_hash, amount).await);
             Ok(tx.into())
         };
-        Box::new(fut.boxed().compat())
+        Box::new(fut.boxed().compat().map_err(TransactionErr::Plain))
     }
 
     fn send_taker_payment(
@@ -943,9 +1683,11 @@ impl SwapOps for SlpToken {
         secret_hash: &[u8],
         amount: BigDecimal,
         _swap_contract_address: &Option<BytesJson>,
+        _other_protocol_info: &Option<Vec<u8>>,
     ) -> TransactionFut {
-        let maker_pub = try_fus!(Public::from_slice(maker_pub));
-        let amount = try_fus!(sat_from_big_decimal(&amount, self.decimals()));
+        try_f!(self.check_platform_signer());
+        let maker_pub = try_tx_fus!(Public::from_slice(maker_pub));
+        let amount = try_tx_fus!(sat_from_big_decimal(&amount, self.decimals()));
         let secret_hash = secret_hash.to_owned();
 
         let coin = self.clone();
@@ -953,7 +1695,7 @@ impl SwapOps for SlpToken {
             let tx = try_s!(coin.send_htlc(&maker_pub, time_lock, &secret_hash, amount).await);
             Ok(tx.into())
         };
-        Box::new(fut.boxed().compat())
+        Box::new(fut.boxed().compat().map_err(TransactionErr::Plain))
     }
 
     fn send_maker_spends_taker_payment(
@@ -964,8 +1706,9 @@ impl SwapOps for SlpToken {
         secret: &[u8],
         _swap_contract_address: &Option<BytesJson>,
     ) -> TransactionFut {
+        try_f!(self.check_platform_signer());
         let tx = taker_payment_tx.to_owned();
-        let taker_pub = try_fus!(Public::from_slice(taker_pub));
+        let taker_pub = try_tx_fus!(Public::from_slice(taker_pub));
         let secret = secret.to_owned();
         let coin = self.clone();
 
@@ -973,7 +1716,7 @@ impl SwapOps for SlpToken {
             let tx = try_s!(coin.spend_htlc(&tx, &taker_pub, time_lock, &secret).await);
             Ok(tx.into())
         };
-        Box::new(fut.boxed().compat())
+        Box::new(fut.boxed().compat().map_err(TransactionErr::Plain))
     }
 
     fn send_taker_spends_maker_payment(
@@ -984,8 +1727,9 @@ impl SwapOps for SlpToken {
         secret: &[u8],
         _swap_contract_address: &Option<BytesJson>,
     ) -> TransactionFut {
+        try_f!(self.check_platform_signer());
         let tx = maker_payment_tx.to_owned();
-        let maker_pub = try_fus!(Public::from_slice(maker_pub));
+        let maker_pub = try_tx_fus!(Public::from_slice(maker_pub));
         let secret = secret.to_owned();
         let coin = self.clone();
 
@@ -993,7 +1737,7 @@ impl SwapOps for SlpToken {
             let tx = try_s!(coin.spend_htlc(&tx, &maker_pub, time_lock, &secret).await);
             Ok(tx.into())
         };
-        Box::new(fut.boxed().compat())
+        Box::new(fut.boxed().compat().map_err(TransactionErr::Plain))
     }
 
     fn send_taker_refunds_payment(
@@ -1004,8 +1748,9 @@ impl SwapOps for SlpToken {
         secret_hash: &[u8],
         _swap_contract_address: &Option<BytesJson>,
     ) -> TransactionFut {
+        try_f!(self.check_platform_signer());
         let tx = taker_payment_tx.to_owned();
-        let maker_pub = try_fus!(Public::from_slice(maker_pub));
+        let maker_pub = try_tx_fus!(Public::from_slice(maker_pub));
         let secret_hash = secret_hash.to_owned();
         let coin = self.clone();
 
@@ -1013,7 +1758,7 @@ impl SwapOps for SlpToken {
             let tx = try_s!(coin.refund_htlc(&tx, &maker_pub, time_lock, &secret_hash).await);
             Ok(tx.into())
         };
-        Box::new(fut.boxed().compat())
+        Box::new(fut.boxed().compat().map_err(TransactionErr::Plain))
     }
 
     fn send_maker_refunds_payment(
@@ -1024,8 +1769,9 @@ impl SwapOps for SlpToken {
         secret_hash: &[u8],
         _swap_contract_address: &Option<BytesJson>,
     ) -> TransactionFut {
+        try_f!(self.check_platform_signer());
         let tx = maker_payment_tx.to_owned();
-        let taker_pub = try_fus!(Public::from_slice(taker_pub));
+        let taker_pub = try_tx_fus!(Public::from_slice(taker_pub));
         let secret_hash = secret_hash.to_owned();
         let coin = self.clone();
 
@@ -1033,7 +1779,7 @@ impl SwapOps for SlpToken {
             let tx = try_s!(coin.refund_htlc(&tx, &taker_pub, time_lock, &secret_hash).await);
             Ok(tx.into())
         };
-        Box::new(fut.boxed().compat())
+        Box::new(fut.boxed().compat().map_err(TransactionErr::Plain))
     }
 
     fn validate_fee(
@@ -1175,59 +1921,462 @@ impl SwapOps for SlpToken {
 impl MmCoin for SlpToken {
     fn is_asset_chain(&self) -> bool { false }
 
-    fn withdraw(&self, _req: WithdrawRequest) -> WithdrawFut { unimplemented!() }
+    fn withdraw(&self, req: WithdrawRequest) -> WithdrawFut {
+        Box::new(slp_withdraw(self.clone(), req).boxed().compat())
+    }
 
     fn decimals(&self) -> u8 { self.decimals() }
 
-    fn convert_to_address(&self, _from: &str, _to_address_format: Json) -> Result<String, String> { unimplemented!() }
+    /// Converts the SLP or the platform coin address, e.g. to the `simpleledger` or `bitcoincash` CashAddr format.
+    fn convert_to_address(&self, from: &str, to_address_format: Json) -> Result<String, String> {
+        let from = match self.address_from_str(from) {
+            Ok(mut address) => {
+                address.addr_format = UtxoAddressFormat::Standard;
+                address.to_string()
+            },
+            Err(_) => from.to_owned(),
+        };
+        self.platform_utxo.convert_to_address(&from, to_address_format)
+    }
 
-    fn validate_address(&self, _address: &str) -> ValidateAddressResult { unimplemented!() }
+    fn validate_address(&self, address: &str) -> ValidateAddressResult {
+        match self.address_from_str(address) {
+            Ok(_) => ValidateAddressResult {
+                is_valid: true,
+                reason: None,
+            },
+            Err(e) => ValidateAddressResult {
+                is_valid: false,
+                reason: Some(e),
+            },
+        }
+    }
 
-    fn process_history_loop(&self, _ctx: MmArc) -> Box<dyn Future<Item = (), Error = ()> + Send> { unimplemented!() }
+    fn process_history_loop(&self, ctx: MmArc) -> Box<dyn Future<Item = (), Error = ()> + Send> {
+        Box::new(self.clone().history_loop(ctx).map(|_| Ok(())).boxed().compat())
+    }
 
-    fn history_sync_status(&self) -> HistorySyncState { unimplemented!() }
+    fn history_sync_status(&self) -> HistorySyncState { self.conf.history_sync_state.lock().unwrap().clone() }
 
     /// Get fee to be paid per 1 swap transaction
-    fn get_trade_fee(&self) -> Box<dyn Future<Item = TradeFee, Error = String> + Send> { unimplemented!() }
+    fn get_trade_fee(&self) -> Box<dyn Future<Item = TradeFee, Error = String> + Send> {
+        self.platform_utxo.get_trade_fee()
+    }
 
-    fn get_sender_trade_fee(&self, _value: TradePreimageValue, _stage: FeeApproxStage) -> TradePreimageFut<TradeFee> {
-        unimplemented!()
+    fn get_sender_trade_fee(&self, value: TradePreimageValue, stage: FeeApproxStage) -> TradePreimageFut<TradeFee> {
+        let coin = self.clone();
+        let fut = async move {
+            let amount = match value {
+                TradePreimageValue::Exact(amount) | TradePreimageValue::UpperBound(amount) => amount,
+            };
+            let amount = sat_from_big_decimal(&amount, coin.decimals())?;
+            // the P2SH script pubkey has the same size for any payment script
+            let slp_out = SlpOutput {
+                amount,
+                script_pubkey: ScriptBuilder::build_p2sh(&Default::default()).to_bytes(),
+            };
+            coin.slp_outputs_trade_fee(vec![slp_out], stage).await
+        };
+        Box::new(fut.boxed().compat())
     }
 
-    fn get_receiver_trade_fee(&self, _stage: FeeApproxStage) -> TradePreimageFut<TradeFee> { unimplemented!() }
+    fn get_receiver_trade_fee(&self, _stage: FeeApproxStage) -> TradePreimageFut<TradeFee> {
+        let coin = self.clone();
+        let fut = async move {
+            // the spending transaction fee is paid in the platform coin
+            let amount_sat = coin.platform_utxo.get_htlc_spend_fee().await?;
+            let amount = big_decimal_from_sat_unsigned(amount_sat, coin.platform_utxo.as_ref().decimals).into();
+            Ok(TradeFee {
+                coin: coin.platform_ticker().to_owned(),
+                amount,
+                paid_from_trading_vol: false,
+            })
+        };
+        Box::new(fut.boxed().compat())
+    }
 
     fn get_fee_to_send_taker_fee(
         &self,
-        _dex_fee_amount: BigDecimal,
-        _stage: FeeApproxStage,
+        dex_fee_amount: BigDecimal,
+        stage: FeeApproxStage,
     ) -> TradePreimageFut<TradeFee> {
-        unimplemented!()
+        let coin = self.clone();
+        let fut = async move {
+            let amount = sat_from_big_decimal(&dex_fee_amount, coin.decimals())?;
+            // the P2PKH script pubkey has the same size for any fee address
+            let slp_out = SlpOutput {
+                amount,
+                script_pubkey: ScriptBuilder::build_p2pkh(&Default::default()).to_bytes(),
+            };
+            coin.slp_outputs_trade_fee(vec![slp_out], stage).await
+        };
+        Box::new(fut.boxed().compat())
     }
 
-    fn required_confirmations(&self) -> u64 { 1 }
+    fn required_confirmations(&self) -> u64 { self.conf.required_confirmations.load(AtomicOrdering::Relaxed) }
 
     fn requires_notarization(&self) -> bool { false }
 
-    fn set_required_confirmations(&self, _confirmations: u64) { unimplemented!() }
+    fn set_required_confirmations(&self, confirmations: u64) {
+        self.conf
+            .required_confirmations
+            .store(confirmations, AtomicOrdering::Relaxed);
+    }
 
-    fn set_requires_notarization(&self, _requires_nota: bool) { unimplemented!() }
+    fn set_requires_notarization(&self, _requires_nota: bool) {
+        log!("Warning: set_requires_notarization doesn't take any effect on SLP tokens");
+    }
 
     fn swap_contract_address(&self) -> Option<BytesJson> { None }
 
     fn mature_confirmations(&self) -> Option<u32> { self.platform_utxo.mature_confirmations() }
 
-    fn coin_protocol_info(&self) -> Vec<u8> { unimplemented!() }
+    fn coin_protocol_info(&self) -> Vec<u8> { self.platform_utxo.coin_protocol_info() }
 
-    fn is_coin_protocol_supported(&self, _info: &Option<Vec<u8>>) -> bool { unimplemented!() }
+    fn is_coin_protocol_supported(&self, info: &Option<Vec<u8>>) -> bool {
+        self.platform_utxo.is_coin_protocol_supported(info)
+    }
 }
 
 #[cfg(test)]
 mod slp_tests {
     use super::*;
+    use crate::utxo::rpc_clients::{NativeClient, NativeClientImpl, UtxoRpcClientOps};
     use crate::utxo::utxo_standard::utxo_standard_coin_from_conf_and_request;
+    use crate::utxo::utxo_tests::{utxo_coin_fields_for_test, utxo_coin_from_fields};
+    use crate::TxOutPoint;
+    use chain::TransactionInput;
     use common::mm_ctx::MmCtxBuilder;
     use common::privkey::key_pair_from_seed;
     use common::{block_on, now_ms};
+    use mocktopus::mocking::*;
+
+    const TEST_TOKEN_ID: &str = "550d19eb820e616a54b8a73372c4420b5a0567d8dc00f613b71c5234dc884b35";
+
+    fn test_token(platform_utxo: UtxoStandardCoin) -> SlpToken {
+        SlpToken::new(
+            2,
+            "ADEXSLP".into(),
+            TEST_TOKEN_ID.into(),
+            "slptest".into(),
+            platform_utxo,
+            1,
+            HistorySyncState::NotEnabled,
+        )
+    }
+
+    /// Mocks the platform coin RPC so that each of the `addresses` has the unspent holding `slp_amount` tokens
+    /// and the plain unspent of 1 BCH.
+    fn mock_slp_unspents(addresses: Vec<Address>, slp_amount: u64) {
+        let prev_tx = |lock_time: u32, outputs: Vec<TransactionOutput>| UtxoTx {
+            inputs: vec![TransactionInput {
+                previous_output: OutPoint {
+                    hash: H256::default(),
+                    index: lock_time,
+                },
+                script_sig: Bytes::default(),
+                sequence: SEQUENCE_FINAL,
+                script_witness: vec![],
+            }],
+            outputs,
+            lock_time,
+            ..Default::default()
+        };
+        let op_return = hex::decode(format!(
+            "6a04534c500001010453454e4420{}08{:016x}",
+            TEST_TOKEN_ID, slp_amount
+        ))
+        .unwrap();
+
+        let mut prev_txs = Vec::new();
+        for (index, address) in addresses.iter().enumerate() {
+            let script_pubkey = ScriptBuilder::build_p2pkh(&address.hash).to_bytes();
+            let slp_tx = prev_tx(index as u32, vec![
+                TransactionOutput {
+                    value: 0,
+                    script_pubkey: op_return.clone().into(),
+                },
+                TransactionOutput {
+                    value: 1000,
+                    script_pubkey: script_pubkey.clone(),
+                },
+            ]);
+            let bch_tx = prev_tx(100 + index as u32, vec![TransactionOutput {
+                value: 100000000,
+                script_pubkey,
+            }]);
+            prev_txs.push((slp_tx, bch_tx));
+        }
+
+        let unspents_txs = prev_txs.clone();
+        NativeClient::list_unspent.mock_safe(move |_, address, _| {
+            let index = addresses.iter().position(|addr| addr.hash == address.hash).unwrap();
+            let (slp_tx, bch_tx) = &unspents_txs[index];
+            let unspents = vec![
+                UnspentInfo {
+                    outpoint: OutPoint {
+                        hash: slp_tx.hash(),
+                        index: 1,
+                    },
+                    value: 1000,
+                    height: Default::default(),
+                },
+                UnspentInfo {
+                    outpoint: OutPoint {
+                        hash: bch_tx.hash(),
+                        index: 0,
+                    },
+                    value: 100000000,
+                    height: Default::default(),
+                },
+            ];
+            MockResult::Return(Box::new(futures01::future::ok(unspents)))
+        });
+        NativeClient::get_transaction_bytes.mock_safe(move |_, txid| {
+            let tx = prev_txs
+                .iter()
+                .flat_map(|(slp_tx, bch_tx)| vec![slp_tx, bch_tx])
+                .find(|tx| H256Json::from(tx.hash().reversed()) == txid)
+                .unwrap();
+            MockResult::Return(Box::new(futures01::future::ok(serialize(tx).into())))
+        });
+    }
+
+    fn withdraw_req(to: String, amount: BigDecimal) -> WithdrawRequest {
+        WithdrawRequest {
+            amount,
+            to,
+            coin: "ADEXSLP".into(),
+            max: false,
+            fee: Some(WithdrawFee::UtxoFixed {
+                amount: "0.00001".parse().unwrap(),
+            }),
+            outputs: Vec::new(),
+            memo: None,
+            inputs: Vec::new(),
+            coin_selection: None,
+            psbt: false,
+        }
+    }
+
+    #[test]
+    fn test_slp_withdraw() {
+        let client = NativeClient(Arc::new(NativeClientImpl::default()));
+        let platform = utxo_coin_from_fields(utxo_coin_fields_for_test(
+            UtxoRpcClientEnum::Native(client),
+            None,
+            false,
+        ));
+        let my_address = platform.as_ref().my_address.clone();
+        mock_slp_unspents(vec![my_address.clone()], 1000);
+        let token = test_token(platform);
+
+        assert_eq!(token.my_balance().wait().unwrap().spendable, BigDecimal::from(10));
+
+        let mut to = my_address.clone();
+        to.hash = [1; 20].into();
+        let to_str = token.slp_address(&to).unwrap();
+        let tx_details = block_on(slp_withdraw(token.clone(), withdraw_req(to_str.clone(), 6.into()))).unwrap();
+        assert_eq!(tx_details.from, vec![token.my_address().unwrap()]);
+        assert_eq!(tx_details.to, vec![to_str.clone()]);
+        assert_eq!(tx_details.spent_by_me, BigDecimal::from(6));
+
+        let tx: UtxoTx = deserialize(tx_details.tx_hex.as_slice()).unwrap();
+        let token_id = TEST_TOKEN_ID.into();
+        assert_eq!(slp_output_amount(&tx, &token_id, 1), Some(600));
+        assert_eq!(slp_output_amount(&tx, &token_id, 2), Some(400));
+        assert_eq!(
+            tx.outputs[1].script_pubkey,
+            output_script(&to, ScriptType::P2PKH).to_bytes()
+        );
+        assert_eq!(
+            tx.outputs[2].script_pubkey,
+            output_script(&my_address, ScriptType::P2PKH).to_bytes()
+        );
+        // the SLP unspent is spent first, the fee is paid from the plain BCH unspent
+        assert_eq!(tx.inputs.len(), 2);
+        assert_eq!(tx.inputs[0].previous_output.index, 1);
+
+        match block_on(slp_withdraw(token, withdraw_req(to_str, 11.into())))
+            .unwrap_err()
+            .into_inner()
+        {
+            WithdrawError::NotSufficientBalance { .. } => (),
+            e => panic!("Expected WithdrawError::NotSufficientBalance, found {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_slp_withdraw_hd_account_spends_several_addresses() {
+        use crate::hd_wallet::Bip44Chain;
+        use crate::utxo::rpc_clients::ReceivedByAddressItem;
+        use common::privkey::hd_master_key_from_mnemonic;
+
+        // no address has transactions, so the first address of the internal chain receives the change
+        NativeClientImpl::list_received_by_address.mock_safe(|_, _, _, _| {
+            MockResult::Return(Box::new(futures01::future::ok(Vec::<ReceivedByAddressItem>::new())))
+        });
+        NativeClientImpl::import_address
+            .mock_safe(|_, _, _, _| MockResult::Return(Box::new(futures01::future::ok(()))));
+        let client = NativeClient(Arc::new(NativeClientImpl::default()));
+        let mut fields = utxo_coin_fields_for_test(UtxoRpcClientEnum::Native(client), None, false);
+        let master_key = hd_master_key_from_mnemonic(
+            "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about",
+        )
+        .unwrap();
+        let hd_account = UtxoHDAccount::new(
+            &master_key,
+            &"m/44'/145'".parse().unwrap(),
+            0,
+            3,
+            UtxoAddressFormat::Standard,
+        )
+        .unwrap();
+        hd_account
+            .extend_addresses(&fields.conf, Bip44Chain::External, 1)
+            .unwrap();
+        let used_addresses = hd_account.addresses(Bip44Chain::External);
+        fields.key_pair = hd_account.key_pair(&fields.conf, &used_addresses[0]).unwrap().into();
+        fields.my_address = used_addresses[0].address.clone();
+        fields.hd_account = Some(hd_account);
+        mock_slp_unspents(used_addresses.iter().map(|hd| hd.address.clone()).collect(), 1000);
+        let token = test_token(utxo_coin_from_fields(fields));
+
+        assert_eq!(token.my_balance().wait().unwrap().spendable, BigDecimal::from(20));
+
+        let to_str = token.slp_address(&used_addresses[1].address).unwrap();
+        let tx_details = block_on(slp_withdraw(token.clone(), withdraw_req(to_str, 15.into()))).unwrap();
+        let expected_from: Vec<String> = used_addresses
+            .iter()
+            .map(|hd| token.slp_address(&hd.address).unwrap())
+            .collect();
+        assert_eq!(tx_details.from, expected_from);
+
+        let tx: UtxoTx = deserialize(tx_details.tx_hex.as_slice()).unwrap();
+        let token_id = TEST_TOKEN_ID.into();
+        assert_eq!(slp_output_amount(&tx, &token_id, 1), Some(1500));
+        assert_eq!(slp_output_amount(&tx, &token_id, 2), Some(500));
+        // both SLP unspents are signed by the keys of their addresses
+        for (input, owner) in tx.inputs.iter().zip(used_addresses.iter()) {
+            assert_eq!(input.previous_output.index, 1);
+            assert!(input.script_sig.ends_with(&owner.pubkey.to_vec()));
+        }
+    }
+
+    #[test]
+    fn test_platform_withdraw_doesnt_spend_slp_unspents() {
+        let client = NativeClient(Arc::new(NativeClientImpl::default()));
+        let platform = utxo_coin_from_fields(utxo_coin_fields_for_test(
+            UtxoRpcClientEnum::Native(client),
+            None,
+            false,
+        ));
+        let my_address = platform.as_ref().my_address.clone();
+        mock_slp_unspents(vec![my_address], 1000);
+        // both the SLP and the plain BCH unspents are mature
+        UtxoStandardCoin::ordered_mature_unspents.mock_safe(|coin, address| {
+            let (unspents, recently_spent) = block_on(utxo_common::list_unspent_ordered(coin, address)).unwrap();
+            MockResult::Return(Box::pin(futures::future::ok((unspents, recently_spent))))
+        });
+        let token = test_token(platform.clone());
+        block_on(token.freeze_platform_token_outpoints()).unwrap();
+
+        let max_withdraw_req = |inputs: Vec<TxOutPoint>| {
+            let mut req = withdraw_req(platform.my_address().unwrap(), 0.into());
+            req.coin = platform.ticker().to_owned();
+            req.max = true;
+            req.inputs = inputs;
+            req
+        };
+        let tx_details = platform.withdraw(max_withdraw_req(Vec::new())).wait().unwrap();
+        let tx: UtxoTx = deserialize(tx_details.tx_hex.as_slice()).unwrap();
+        // only the plain BCH unspent is spent
+        assert_eq!(tx.inputs.len(), 1);
+        assert_eq!(tx.inputs[0].previous_output.index, 0);
+        assert_eq!(tx_details.spent_by_me, BigDecimal::from(1));
+
+        // the SLP unspent can't be listed as the withdraw input either
+        let slp_outpoint = block_on(token.slp_unspents()).unwrap().0[0]
+            .bch_unspent
+            .outpoint
+            .clone();
+        match platform
+            .withdraw(max_withdraw_req(vec![slp_outpoint.into()]))
+            .wait()
+            .unwrap_err()
+            .into_inner()
+        {
+            WithdrawError::InvalidInputs(_) => (),
+            e => panic!("Expected WithdrawError::InvalidInputs, found {:?}", e),
+        }
+
+        // the SLP unspent is unfrozen once the token is disabled
+        block_on(token.unfreeze_platform_token_outpoints());
+        let tx_details = platform.withdraw(max_withdraw_req(Vec::new())).wait().unwrap();
+        let tx: UtxoTx = deserialize(tx_details.tx_hex.as_slice()).unwrap();
+        assert_eq!(tx.inputs.len(), 2);
+    }
+
+    #[test]
+    fn test_slp_token_from_conf_and_request() {
+        let ctx = MmCtxBuilder::default().into_mm_arc();
+        let conf = json!({"decimals": 4, "slp_prefix": "slptest", "required_confirmations": 2});
+        let token_id: H256Json = H256::from(TEST_TOKEN_ID).into();
+        let client = NativeClient(Arc::new(NativeClientImpl::default()));
+        let platform = utxo_coin_from_fields(utxo_coin_fields_for_test(
+            UtxoRpcClientEnum::Native(client),
+            None,
+            false,
+        ));
+        let platform_ticker = platform.ticker().to_owned();
+
+        let err = block_on(slp_token_from_conf_and_request(
+            &ctx,
+            "ADEXSLP",
+            &platform_ticker,
+            token_id.clone(),
+            &conf,
+            &json!({}),
+        ))
+        .unwrap_err();
+        assert!(err.contains("should be enabled before"), "{}", err);
+
+        let cctx = CoinsContext::from_ctx(&ctx).unwrap();
+        block_on(cctx.coins.lock()).insert(platform_ticker.clone(), platform.clone().into());
+
+        let err = block_on(slp_token_from_conf_and_request(
+            &ctx,
+            "ADEXSLP",
+            &platform_ticker,
+            token_id.clone(),
+            &json!({}),
+            &json!({}),
+        ))
+        .unwrap_err();
+        assert!(err.contains("'decimals' is not set"), "{}", err);
+
+        let req = json!({"required_confirmations": 3, "tx_history": true});
+        let token = block_on(slp_token_from_conf_and_request(
+            &ctx,
+            "ADEXSLP",
+            &platform_ticker,
+            token_id,
+            &conf,
+            &req,
+        ))
+        .unwrap();
+        assert_eq!(token.decimals(), 4);
+        assert_eq!(*token.token_id(), H256::from(TEST_TOKEN_ID));
+        assert_eq!(token.platform_ticker(), platform_ticker);
+        assert_eq!(token.required_confirmations(), 3);
+        assert!(matches!(
+            *token.conf.history_sync_state.lock().unwrap(),
+            HistorySyncState::NotStarted
+        ));
+        let expected_address = token.slp_address(&platform.as_ref().my_address).unwrap();
+        assert!(expected_address.starts_with("slptest:"));
+        assert_eq!(token.my_address().unwrap(), expected_address);
+    }
 
     // https://slp.dev/specs/slp-token-type-1/#examples
     #[test]
@@ -1307,6 +2456,50 @@ mod slp_tests {
         assert_eq!(expected_transaction, slp_data.transaction);
     }
 
+    #[test]
+    fn test_slp_output_amount() {
+        let script = hex::decode("6a04534c500001010453454e4420550d19eb820e616a54b8a73372c4420b5a0567d8dc00f613b71c5234dc884b350800000000000003e80800000000000003e90800000000000003ea").unwrap();
+        let tx = UtxoTx {
+            outputs: vec![TransactionOutput {
+                value: 0,
+                script_pubkey: script.into(),
+            }],
+            ..Default::default()
+        };
+        let token_id = "550d19eb820e616a54b8a73372c4420b5a0567d8dc00f613b71c5234dc884b35".into();
+        assert_eq!(slp_output_amount(&tx, &token_id, 0), None);
+        assert_eq!(slp_output_amount(&tx, &token_id, 1), Some(1000));
+        assert_eq!(slp_output_amount(&tx, &token_id, 3), Some(1002));
+        assert_eq!(slp_output_amount(&tx, &token_id, 4), None);
+
+        let other_token_id = "e73b2b28c14db8ebbf97749988b539508990e1708021067f206f49d55807dbf4".into();
+        assert_eq!(slp_output_amount(&tx, &other_token_id, 1), None);
+
+        // Mint
+        let script =
+            hex::decode("6a04534c50000101044d494e5420550d19eb820e616a54b8a73372c4420b5a0567d8dc00f613b71c5234dc884b35010208002386f26fc10000").unwrap();
+        let tx = UtxoTx {
+            outputs: vec![TransactionOutput {
+                value: 0,
+                script_pubkey: script.into(),
+            }],
+            ..Default::default()
+        };
+        assert_eq!(slp_output_amount(&tx, &token_id, 1), Some(10000000000000000));
+        // the minting baton doesn't hold the token
+        assert_eq!(slp_output_amount(&tx, &token_id, 2), None);
+    }
+
+    #[test]
+    fn test_slp_addr_from_pubkey_str() {
+        let pubkey = "03c6a78589e18b482aea046975e6d0acbdea7bf7dbf04d9d5bd67fda917815e3ed";
+        let address = slp_addr_from_pubkey_str(pubkey, &json!({})).unwrap();
+        assert_eq!(address, "simpleledger:qpke6264f4mgyv3jqkra7awyxw8vezln05052h60gr");
+
+        let address = slp_addr_from_pubkey_str(pubkey, &json!({"slp_prefix": "slptest"})).unwrap();
+        assert_eq!(address, "slptest:qpke6264f4mgyv3jqkra7awyxw8vezln05ufzsh0ru");
+    }
+
     #[test]
     #[ignore]
     fn send_and_spend_htlc_on_testnet() {
@@ -1336,7 +2529,15 @@ mod slp_tests {
         println!("{}", address);
 
         let token_id = H256::from("bb309e48930671582bea508f9a1d9b491e49b69be3d6f372dc08da2ac6e90eb7");
-        let fusd = SlpToken::new(4, "FUSD".into(), token_id, bch, 0);
+        let fusd = SlpToken::new(
+            4,
+            "FUSD".into(),
+            token_id,
+            "slptest".into(),
+            bch,
+            0,
+            HistorySyncState::NotEnabled,
+        );
 
         let fusd_balance = fusd.my_balance().wait().unwrap();
         println!("FUSD {}", fusd_balance.spendable);
@@ -1347,7 +2548,14 @@ mod slp_tests {
         let amount: BigDecimal = "0.1".parse().unwrap();
 
         let tx = fusd
-            .send_taker_payment(time_lock, &*keypair.public(), &*secret_hash, amount.clone(), &None)
+            .send_taker_payment(
+                time_lock,
+                &*keypair.public(),
+                &*secret_hash,
+                amount.clone(),
+                &None,
+                &None,
+            )
             .wait()
             .unwrap();
         println!("{}", hex::encode(tx.tx_hex()));
@@ -1410,7 +2618,15 @@ mod slp_tests {
         println!("{}", address);
 
         let token_id = H256::from("bb309e48930671582bea508f9a1d9b491e49b69be3d6f372dc08da2ac6e90eb7");
-        let fusd = SlpToken::new(4, "FUSD".into(), token_id, bch, 0);
+        let fusd = SlpToken::new(
+            4,
+            "FUSD".into(),
+            token_id,
+            "slptest".into(),
+            bch,
+            0,
+            HistorySyncState::NotEnabled,
+        );
 
         let fusd_balance = fusd.my_balance().wait().unwrap();
         println!("FUSD {}", fusd_balance.spendable);
@@ -1420,7 +2636,7 @@ mod slp_tests {
         let time_lock = (now_ms() / 1000) as u32 - 7200;
 
         let tx = fusd
-            .send_taker_payment(time_lock, &[1; 33], &*secret_hash, 1.into(), &None)
+            .send_taker_payment(time_lock, &[1; 33], &*secret_hash, 1.into(), &None, &None)
             .wait()
             .unwrap();
         println!("{}", hex::encode(tx.tx_hex()));
@@ -0,0 +1,434 @@
+//! Simplified payment verification of the transactions confirmed according to an Electrum server.
+//!
+//! If the coin is configured with `spv: true`, a transaction waited by `wait_for_confirmations` is considered confirmed
+//! only if its merkle branch (`blockchain.transaction.get_merkle`) leads to the merkle root of a block header,
+//! and there are enough headers built on top of that block.
+//! The headers are downloaded with `blockchain.block.headers` and kept as a contiguous chain:
+//! every header must satisfy its proof-of-work and link to the previous one.
+//!
+//! The server can't feed a cheap chain of its own, because the `spv_conf` entry of the coin config pins
+//! the easiest allowed target (`max_target_bits`) and optionally a trusted `checkpoint` the local chain starts from:
+//! `"spv_conf": {"max_target_bits": 486604799, "checkpoint": {"height": 700000, "hash": "0000..."}}`.
+//! Note the difficulty adjustment rules of the coin are not checked,
+//! and the proof-of-work is the double SHA-256 of the header, so the coins with other PoW algorithms aren't supported.
+
+use super::rpc_clients::{ElectrumClient, UtxoRpcClientOps};
+use super::UtxoTx;
+use chain::{merkle_root_from_branch, BlockHeader};
+use common::log::info;
+use futures::compat::Future01CompatExt;
+use futures::lock::Mutex as AsyncMutex;
+use primitives::compact::Compact;
+use primitives::hash::H256;
+use primitives::U256;
+use rpc::v1::types::H256 as H256Json;
+use serialization::CoinVariant;
+use std::collections::BTreeMap;
+use std::num::NonZeroU64;
+
+/// Electrum servers return at most 2016 headers per `blockchain.block.headers` request.
+const MAX_HEADERS_PER_REQUEST: u64 = 2016;
+
+/// The SPV settings of the coin config.
+#[derive(Clone, Debug, Deserialize)]
+pub struct SpvConf {
+    /// The compact encoding (`bits`) of the easiest target the headers may have.
+    pub max_target_bits: u32,
+    /// The trusted header the local chain starts from. The transactions below it can't be verified.
+    pub checkpoint: Option<SpvCheckpoint>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct SpvCheckpoint {
+    pub height: u64,
+    /// The header hash in the RPC (reversed) byte order.
+    pub hash: H256Json,
+}
+
+impl SpvConf {
+    /// Returns the easiest allowed target or an error if `max_target_bits` encode a negative or overflowed value.
+    pub fn max_target(&self) -> Result<U256, String> {
+        Compact::new(self.max_target_bits)
+            .to_u256()
+            .map_err(|_| ERRL!("Invalid 'max_target_bits' {}", self.max_target_bits))
+    }
+}
+
+/// The contiguous chain of the validated block headers by height.
+#[derive(Debug, Default)]
+pub struct BlockHeaderStorage {
+    headers: AsyncMutex<BTreeMap<u64, BlockHeader>>,
+}
+
+impl BlockHeaderStorage {
+    /// Returns the merkle root of the validated header at `height`.
+    pub async fn merkle_root(&self, height: u64) -> Option<H256> {
+        let headers = self.headers.lock().await;
+        headers.get(&height).map(|header| header.merkle_root_hash.clone())
+    }
+
+    /// Drops the headers from `height` up to the tip, e.g. if they're orphaned by a reorganization.
+    async fn truncate(&self, height: u64) {
+        let mut headers = self.headers.lock().await;
+        let _orphaned = headers.split_off(&height);
+    }
+}
+
+/// Checks that every header satisfies its proof-of-work not easier than `max_target` and links to the previous one.
+/// `headers[0]` is expected to be at the `from` height.
+pub fn validate_headers(from: u64, headers: &[BlockHeader], max_target: U256) -> Result<(), String> {
+    let mut prev_hash: Option<H256> = None;
+    for (height, header) in (from..).zip(headers) {
+        match header.target() {
+            Some(target) if target <= max_target => (),
+            _ => {
+                return ERR!(
+                    "Header at height {} has the target above the configured maximum",
+                    height
+                )
+            },
+        }
+        if !header.is_pow_valid() {
+            return ERR!("Header at height {} doesn't satisfy its proof-of-work", height);
+        }
+        if let Some(prev_hash) = prev_hash {
+            if header.previous_header_hash != prev_hash {
+                return ERR!("Header at height {} doesn't link to the previous header", height);
+            }
+        }
+        prev_hash = Some(header.hash());
+    }
+    Ok(())
+}
+
+/// Downloads the headers `[from, to]` in batches.
+async fn download_headers(client: &ElectrumClient, from: u64, to: u64) -> Result<Vec<BlockHeader>, String> {
+    let mut headers = Vec::with_capacity((to + 1 - from) as usize);
+    let mut start = from;
+    while start <= to {
+        let count = (to + 1 - start).min(MAX_HEADERS_PER_REQUEST);
+        // can unwrap because `start <= to`
+        let res = try_s!(
+            client
+                .blockchain_block_headers(start, NonZeroU64::new(count).unwrap())
+                .compat()
+                .await
+        );
+        let batch = try_s!(res.headers(CoinVariant::Standard));
+        if batch.len() as u64 != count {
+            return ERR!(
+                "Expected {} headers from height {}, found {}",
+                count,
+                start,
+                batch.len()
+            );
+        }
+        headers.extend(batch);
+        start += count;
+    }
+    Ok(headers)
+}
+
+/// Extends the local chain with the validated headers so it covers `[from, to]`.
+/// If the checkpoint is configured, the local chain starts from it.
+/// If the downloaded headers don't link to the local tip, the tip is dropped to pick up a reorganization next time.
+async fn sync_headers(client: &ElectrumClient, conf: &SpvConf, from: u64, to: u64) -> Result<(), String> {
+    let max_target = try_s!(conf.max_target());
+    let from = match conf.checkpoint {
+        Some(ref checkpoint) if from < checkpoint.height => {
+            return ERR!(
+                "Height {} is below the SPV checkpoint at height {}",
+                from,
+                checkpoint.height
+            )
+        },
+        Some(ref checkpoint) => checkpoint.height,
+        None => from,
+    };
+
+    let mut headers = client.block_headers_storage().headers.lock().await;
+    let (first, last) = match (headers.keys().next(), headers.keys().next_back()) {
+        (Some(first), Some(last)) => (*first, *last),
+        _ => {
+            let downloaded = try_s!(download_headers(client, from, to).await);
+            try_s!(validate_headers(from, &downloaded, max_target));
+            if let Some(ref checkpoint) = conf.checkpoint {
+                if downloaded[0].hash().reversed() != checkpoint.hash.0.into() {
+                    return ERR!("Header at height {} doesn't match the SPV checkpoint", from);
+                }
+            }
+            headers.extend((from..).zip(downloaded));
+            return Ok(());
+        },
+    };
+
+    // the local chain already starts from the checkpoint if it's configured
+    if from < first {
+        let downloaded = try_s!(download_headers(client, from, first - 1).await);
+        try_s!(validate_headers(from, &downloaded, max_target));
+        // can unwrap because at least one header is downloaded
+        if headers[&first].previous_header_hash != downloaded.last().unwrap().hash() {
+            return ERR!("Header at height {} doesn't link to the local chain", first - 1);
+        }
+        headers.extend((from..).zip(downloaded));
+    }
+
+    if to > last {
+        let downloaded = try_s!(download_headers(client, last + 1, to).await);
+        try_s!(validate_headers(last + 1, &downloaded, max_target));
+        if downloaded[0].previous_header_hash != headers[&last].hash() {
+            if last == first && conf.checkpoint.is_some() {
+                return ERR!("Header at height {} doesn't link to the SPV checkpoint", last + 1);
+            }
+            headers.remove(&last);
+            return ERR!(
+                "Header at height {} doesn't link to the local chain, the local tip is dropped",
+                last + 1
+            );
+        }
+        headers.extend((last + 1..).zip(downloaded));
+    }
+    Ok(())
+}
+
+/// Checks that the proof-of-work of the coin is the double SHA-256 of the header by validating the tip header.
+/// The headers of Scrypt, merged mined (AuxPoW) and other coins never satisfy it, so SPV would never verify a transaction.
+pub async fn check_spv_pow_algo(client: &ElectrumClient) -> Result<(), String> {
+    let height = try_s!(client.get_block_count().compat().await);
+    let tip = try_s!(download_headers(client, height, height).await);
+    // can index because `download_headers` returns exactly one header
+    if tip[0].aux_pow.is_some() || !tip[0].is_pow_valid() {
+        return ERR!("SPV is supported for the coins with the double SHA-256 proof-of-work only");
+    }
+    Ok(())
+}
+
+/// Checks that the transaction is included in a block with at least `confirmations` validated headers
+/// including the block itself.
+/// If the merkle branch doesn't match the local header, the header may be orphaned by a reorganization,
+/// so the local chain is downloaded again from the transaction height and the proof is checked once more.
+pub async fn validate_spv_proof(
+    client: &ElectrumClient,
+    conf: &SpvConf,
+    tx: &UtxoTx,
+    confirmations: u32,
+) -> Result<(), String> {
+    let tx_hash = tx.hash();
+    let verbose = try_s!(client.get_verbose_transaction(tx_hash.reversed().into()).compat().await);
+    let current_height = try_s!(client.get_block_count().compat().await);
+    let height = match verbose.height {
+        Some(height) if height > 0 => height,
+        _ if verbose.confirmations > 0 => (current_height + 1).saturating_sub(verbose.confirmations as u64),
+        _ => return ERR!("Transaction {:?} is not confirmed yet", tx_hash.reversed()),
+    };
+    let confirmed_height = height + confirmations.max(1) as u64 - 1;
+    if confirmed_height > current_height {
+        return ERR!(
+            "Transaction {:?} at height {} has less than {} confirmations, current height {}",
+            tx_hash.reversed(),
+            height,
+            confirmations,
+            current_height
+        );
+    }
+
+    try_s!(sync_headers(client, conf, height, confirmed_height).await);
+    if !try_s!(merkle_branch_matches(client, &tx_hash, height).await) {
+        info!(
+            "Merkle branch of {:?} doesn't match the header at height {}, the headers are downloaded again",
+            tx_hash.reversed(),
+            height
+        );
+        client.block_headers_storage().truncate(height).await;
+        try_s!(sync_headers(client, conf, height, confirmed_height).await);
+        if !try_s!(merkle_branch_matches(client, &tx_hash, height).await) {
+            return ERR!(
+                "Merkle branch of {:?} doesn't match the header at height {}",
+                tx_hash.reversed(),
+                height
+            );
+        }
+    }
+
+    info!(
+        "Transaction {:?} is verified by SPV at height {}",
+        tx_hash.reversed(),
+        height
+    );
+    Ok(())
+}
+
+/// Checks whether the merkle branch of the transaction given by the server leads to the merkle root
+/// of the validated header at `height`.
+async fn merkle_branch_matches(client: &ElectrumClient, tx_hash: &H256, height: u64) -> Result<bool, String> {
+    let branch = try_s!(
+        client
+            .blockchain_transaction_get_merkle(tx_hash.reversed().into(), height)
+            .compat()
+            .await
+    );
+    if branch.block_height != height {
+        return ERR!(
+            "Merkle branch of {:?} is for height {}, expected {}",
+            tx_hash.reversed(),
+            branch.block_height,
+            height
+        );
+    }
+    let branch_hashes: Vec<H256> = branch.merkle.into_iter().map(|hash| hash.reversed().into()).collect();
+    let merkle_root = merkle_root_from_branch(tx_hash, &branch_hashes, branch.pos);
+    match client.block_headers_storage().merkle_root(height).await {
+        Some(expected) => Ok(expected == merkle_root),
+        None => ERR!("There is no validated header at height {}", height),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utxo::rpc_clients::{ElectrumBlockHeadersRes, ElectrumClientImpl, TxMerkleBranch};
+    use common::block_on;
+    use mocktopus::mocking::*;
+    use rpc::v1::types::Transaction as RpcTransaction;
+    use serde_json::{self as json};
+    use serialization::deserialize;
+    use std::sync::Arc;
+
+    // Bitcoin blocks 0 and 1
+    const GENESIS_HEADER: &str = "0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c";
+    const BLOCK_1_HEADER: &str = "010000006fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d6190000000000982051fd1e4ba744bbbe680e1fee14677ba1a3c3540bf7b1cdb606e857233e0e61bc6649ffff001d01e36299";
+    const GENESIS_HASH: &str = "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f";
+    // the only transaction of the Bitcoin block 1, so its hash is the merkle root of the block
+    const BLOCK_1_COINBASE: &str = "01000000010000000000000000000000000000000000000000000000000000000000000000ffffffff0704ffff001d0104ffffffff0100f2052a0100000043410496b538e853519c726a2c91e61ec11600ae1390813a627c66fb8be7947be63c52da7589379515d4e0a604f8141781e62294721166bf621e73a82cbf2342c858eeac00000000";
+    // the target of the Bitcoin genesis block
+    const MAX_TARGET_BITS: u32 = 0x1d00ffff;
+
+    fn header(hex_str: &str) -> BlockHeader { deserialize(hex::decode(hex_str).unwrap().as_slice()).unwrap() }
+
+    fn max_target() -> U256 { Compact::new(MAX_TARGET_BITS).to_u256().unwrap() }
+
+    fn spv_conf_for_test() -> SpvConf {
+        json::from_value(json!({
+            "max_target_bits": MAX_TARGET_BITS,
+            "checkpoint": {"height": 0, "hash": GENESIS_HASH},
+        }))
+        .unwrap()
+    }
+
+    /// Mocks the server which has the Bitcoin blocks 0 and 1 and returns the `merkle` branch of the block 1 coinbase.
+    fn mock_electrum_block_1(merkle: Vec<H256Json>) {
+        ElectrumClient::get_block_count.mock_safe(|_| MockResult::Return(Box::new(futures01::future::ok(1))));
+        ElectrumClient::get_verbose_transaction.mock_safe(|_, txid| {
+            let tx = RpcTransaction {
+                hex: hex::decode(BLOCK_1_COINBASE).unwrap().into(),
+                txid,
+                hash: None,
+                size: None,
+                vsize: None,
+                version: 1,
+                locktime: 0,
+                vin: Vec::new(),
+                vout: Vec::new(),
+                blockhash: H256Json::default(),
+                confirmations: 1,
+                rawconfirmations: None,
+                time: 0,
+                blocktime: 0,
+                height: Some(1),
+            };
+            MockResult::Return(Box::new(futures01::future::ok(tx)))
+        });
+        ElectrumClient::blockchain_block_headers.mock_safe(|_, start_height, count| {
+            let headers = [GENESIS_HEADER, BLOCK_1_HEADER];
+            let hex: String = headers
+                .iter()
+                .skip(start_height as usize)
+                .take(count.get() as usize)
+                .cloned()
+                .collect();
+            let res: ElectrumBlockHeadersRes =
+                json::from_value(json!({"count": count.get(), "hex": hex, "max": 2016})).unwrap();
+            MockResult::Return(Box::new(futures01::future::ok(res)))
+        });
+        ElectrumClient::blockchain_transaction_get_merkle.mock_safe(move |_, _, height| {
+            let branch = TxMerkleBranch {
+                merkle: merkle.clone(),
+                block_height: height,
+                pos: 0,
+            };
+            MockResult::Return(Box::new(futures01::future::ok(branch)))
+        });
+    }
+
+    fn electrum_client_for_test() -> ElectrumClient {
+        ElectrumClient(Arc::new(ElectrumClientImpl::new("BTC".into(), Default::default())))
+    }
+
+    #[test]
+    fn test_validate_headers() {
+        let genesis = header(GENESIS_HEADER);
+        let block_1 = header(BLOCK_1_HEADER);
+        validate_headers(0, &[genesis.clone(), block_1.clone()], max_target()).unwrap();
+
+        // the headers don't link
+        validate_headers(0, &[block_1.clone(), genesis.clone()], max_target()).unwrap_err();
+
+        let tampered = BlockHeader {
+            time: block_1.time + 1,
+            ..block_1.clone()
+        };
+        validate_headers(0, &[genesis.clone(), tampered], max_target()).unwrap_err();
+
+        // the headers are easier than the configured maximum target
+        let harder_max_target = Compact::new(0x1c00ffff).to_u256().unwrap();
+        validate_headers(0, &[genesis, block_1], harder_max_target).unwrap_err();
+    }
+
+    #[test]
+    fn test_validate_spv_proof() {
+        let tx: UtxoTx = deserialize(hex::decode(BLOCK_1_COINBASE).unwrap().as_slice()).unwrap();
+        assert_eq!(header(BLOCK_1_HEADER).merkle_root_hash, tx.hash());
+
+        mock_electrum_block_1(Vec::new());
+        let client = electrum_client_for_test();
+        block_on(validate_spv_proof(&client, &spv_conf_for_test(), &tx, 1)).unwrap();
+        // the headers are validated from the checkpoint
+        assert!(block_on(client.block_headers_storage().merkle_root(0)).is_some());
+
+        // block 1 has no header on top of it
+        block_on(validate_spv_proof(&client, &spv_conf_for_test(), &tx, 2)).unwrap_err();
+
+        // the branch doesn't lead to the merkle root of the header
+        mock_electrum_block_1(vec![H256Json::from([1; 32])]);
+        let client = electrum_client_for_test();
+        block_on(validate_spv_proof(&client, &spv_conf_for_test(), &tx, 1)).unwrap_err();
+    }
+
+    #[test]
+    fn test_validate_spv_proof_after_reorg() {
+        let tx: UtxoTx = deserialize(hex::decode(BLOCK_1_COINBASE).unwrap().as_slice()).unwrap();
+        mock_electrum_block_1(Vec::new());
+        let client = electrum_client_for_test();
+        // the local chain has the block 1 header of the branch orphaned by a reorganization
+        let stale_block_1 = BlockHeader {
+            merkle_root_hash: H256::from([3; 32]),
+            ..header(BLOCK_1_HEADER)
+        };
+        block_on(client.block_headers_storage().headers.lock())
+            .extend(vec![(0, header(GENESIS_HEADER)), (1, stale_block_1)]);
+
+        block_on(validate_spv_proof(&client, &spv_conf_for_test(), &tx, 1)).unwrap();
+        assert_eq!(block_on(client.block_headers_storage().merkle_root(1)), Some(tx.hash()));
+    }
+
+    #[test]
+    fn test_validate_spv_proof_wrong_checkpoint() {
+        let tx: UtxoTx = deserialize(hex::decode(BLOCK_1_COINBASE).unwrap().as_slice()).unwrap();
+        mock_electrum_block_1(Vec::new());
+        let client = electrum_client_for_test();
+        let mut spv_conf = spv_conf_for_test();
+        spv_conf.checkpoint.as_mut().unwrap().hash = H256Json::from([2; 32]);
+        block_on(validate_spv_proof(&client, &spv_conf, &tx, 1)).unwrap_err();
+        assert!(block_on(client.block_headers_storage().merkle_root(0)).is_none());
+    }
+}
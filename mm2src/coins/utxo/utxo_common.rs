@@ -1,7 +1,7 @@
 use super::*;
 use bigdecimal::{BigDecimal, Zero};
-pub use bitcrypto::{dhash160, sha256, ChecksumType};
-use chain::constants::SEQUENCE_FINAL;
+pub use bitcrypto::{dhash160, dhash256, sha256, ChecksumType};
+use chain::constants::{MAX_BIP125_RBF_SEQUENCE, SEQUENCE_FINAL};
 use chain::{OutPoint, TransactionInput, TransactionOutput};
 use common::executor::Timer;
 use common::jsonrpc_client::{JsonRpcError, JsonRpcErrorType};
@@ -15,28 +15,38 @@ use futures::compat::Future01CompatExt;
 use futures::future::{FutureExt, TryFutureExt};
 use futures01::future::Either;
 use keys::bytes::Bytes;
-use keys::{Address, AddressFormat as UtxoAddressFormat, AddressHash, KeyPair, Public, SegwitAddress,
+use keys::{Address, AddressFormat as UtxoAddressFormat, AddressHash, CompactSignature, Public, SegwitAddress,
            Type as ScriptType};
-use primitives::hash::H512;
+use primitives::hash::{H256, H512, H520};
 use rpc::v1::types::{Bytes as BytesJson, TransactionInputEnum, H256 as H256Json};
 use script::{Builder, Opcode, Script, ScriptAddress, SignatureVersion, TransactionInputSigner,
              UnsignedTransactionInput};
 use secp256k1::{PublicKey, Signature};
 use serde_json::{self as json};
-use serialization::{deserialize, serialize, serialize_with_flags, CoinVariant, SERIALIZE_TRANSACTION_WITNESS};
+use serialization::{deserialize, serialize, serialize_with_flags, CoinVariant, CompactInteger, Stream,
+                    SERIALIZE_TRANSACTION_WITNESS};
 use std::cmp::Ordering;
 use std::collections::hash_map::{Entry, HashMap};
+use std::collections::HashSet;
 use std::str::FromStr;
 use std::sync::atomic::Ordering as AtomicOrderding;
 
 pub use chain::Transaction as UtxoTx;
 
+use self::coin_selection::{CoinSelectionParams, CoinSelectionStrategy};
+use self::psbt::withdraw_psbt;
 use self::rpc_clients::{electrum_script_hash, UnspentInfo, UtxoRpcClientEnum, UtxoRpcClientOps, UtxoRpcResult};
-use crate::{CanRefundHtlc, CoinBalance, TradePreimageValue, TxFeeDetails, ValidateAddressResult, WithdrawResult};
+use self::spv::validate_spv_proof;
+use self::utxo_hd_wallet::{UtxoHDAccount, UtxoHDAddress};
+use crate::message_signing::{SignatureError, SignatureResult};
+use crate::{wait_for_notification, CanRefundHtlc, CoinBalance, CoinNotifications, TradePreimageValue, TxFeeDetails,
+            TxOutPoint, ValidateAddressResult, WithdrawResult, MAX_WITHDRAW_MEMO_LEN};
 
 const MIN_BTC_TRADING_VOL: &str = "0.00777";
 pub const DEFAULT_SWAP_VOUT: usize = 0;
 pub const DEFAULT_FEE_VOUT: usize = 0;
+/// The number of the transactions requested from the RPC in one batch.
+const VERBOSE_TRANSACTIONS_BATCH_SIZE: usize = 50;
 
 macro_rules! true_or {
     ($cond: expr, $etype: expr) => {
@@ -202,6 +212,8 @@ pub fn addresses_from_script<T: AsRef<UtxoCoinFields> + UtxoCommonOps>(
                     coin.addr_format_for_standard_scripts(),
                 ),
                 ScriptType::P2WPKH => (conf.pub_addr_prefix, conf.pub_t_addr_prefix, UtxoAddressFormat::Segwit),
+                // the output key is kept by `taproot_output_key`, the address hash isn't used
+                ScriptType::P2TR => (conf.pub_addr_prefix, conf.pub_t_addr_prefix, UtxoAddressFormat::Segwit),
             };
 
             Address {
@@ -211,6 +223,7 @@ pub fn addresses_from_script<T: AsRef<UtxoCoinFields> + UtxoCommonOps>(
                 t_addr_prefix,
                 hrp: conf.bech32_hrp.clone(),
                 addr_format,
+                taproot_output_key: dst.taproot_output_key,
             }
         })
         .collect();
@@ -262,6 +275,84 @@ pub fn checked_address_from_str(coin: &UtxoCoinFields, address: &str) -> Result<
     Ok(addr)
 }
 
+/// Returns the hash the message is signed with in the Bitcoin "Signed Message" format:
+/// `dhash256(len(prefix) || prefix || len(message) || message)`.
+pub fn sign_message_hash(coin: &UtxoCoinFields, message: &str) -> SignatureResult<H256> {
+    let prefix = match coin.conf.sign_message_prefix {
+        Some(ref prefix) => prefix,
+        None => {
+            return MmError::err(SignatureError::PrefixNotFound {
+                coin: coin.conf.ticker.clone(),
+            })
+        },
+    };
+    Ok(signed_message_hash(prefix, message))
+}
+
+/// The double SHA-256 of the `prefix` and the `message`, each preceded by its length.
+pub fn signed_message_hash(prefix: &str, message: &str) -> H256 {
+    let mut stream = Stream::new();
+    stream.append(&CompactInteger::from(prefix.len()));
+    stream.append_slice(prefix.as_bytes());
+    stream.append(&CompactInteger::from(message.len()));
+    stream.append_slice(message.as_bytes());
+    dhash256(&stream.out())
+}
+
+/// Signs the message with the compact recoverable signature and returns it encoded in base64.
+pub fn sign_message<T>(coin: &T, message: &str) -> SignatureResult<String>
+where
+    T: AsRef<UtxoCoinFields>,
+{
+    let coin = coin.as_ref();
+    let hash = sign_message_hash(coin, message)?;
+    let rsv = coin
+        .key_pair
+        .signer()?
+        .sign_hash_recoverable(&hash)
+        .map_to_mm(SignatureError::InternalError)?;
+    let compressed = matches!(coin.key_pair.public(), Public::Compressed(_));
+    let signature = CompactSignature::from_parts(rsv[64], &rsv[..64], compressed);
+    Ok(base64::encode(&signature[..]))
+}
+
+/// Checks if the base64 encoded compact `signature` of the message is made by the key of the `address`.
+/// Only P2PKH and P2WPKH addresses are supported.
+pub fn verify_message<T>(coin: &T, message: &str, signature: &str, address: &str) -> SignatureResult<bool>
+where
+    T: AsRef<UtxoCoinFields> + UtxoCommonOps,
+{
+    let address = coin
+        .address_from_str(address)
+        .map_to_mm(SignatureError::InvalidAddress)?;
+    let conf = &coin.as_ref().conf;
+    let is_p2sh = address.hrp.is_none()
+        && address.prefix == conf.p2sh_addr_prefix
+        && address.t_addr_prefix == conf.p2sh_t_addr_prefix;
+    if is_p2sh || address.taproot_output_key.is_some() {
+        return MmError::err(SignatureError::InvalidAddress(
+            "Only P2PKH and P2WPKH addresses are supported".into(),
+        ));
+    }
+
+    let hash = sign_message_hash(coin.as_ref(), message)?;
+    let signature = base64::decode(signature).map_to_mm(|e| SignatureError::InvalidSignature(e.to_string()))?;
+    if signature.len() != 65 {
+        return MmError::err(SignatureError::InvalidSignature(format!(
+            "Expected 65 bytes, found {}",
+            signature.len()
+        )));
+    }
+    let signature = CompactSignature::from(H520::from(signature.as_slice()));
+    let public = match Public::recover_compact(&hash, &signature) {
+        Ok(public) => public,
+        // the signature is well-formed, but it's not made for this message
+        Err(keys::Error::InvalidSignature) => return Ok(false),
+        Err(e) => return MmError::err(e.into()),
+    };
+    Ok(public.address_hash() == address.hash)
+}
+
 pub async fn get_current_mtp(coin: &UtxoCoinFields, coin_variant: CoinVariant) -> UtxoRpcResult<u32> {
     let current_block = coin.rpc_client.get_block_count().compat().await?;
     coin.rpc_client
@@ -274,8 +365,9 @@ pub fn send_outputs_from_my_address<T>(coin: T, outputs: Vec<TransactionOutput>)
 where
     T: AsRef<UtxoCoinFields> + UtxoCommonOps + Send + Sync + 'static,
 {
+    try_f!(coin.as_ref().key_pair.signer().map_err(TransactionErr::from));
     let fut = send_outputs_from_my_address_impl(coin, outputs);
-    Box::new(fut.boxed().compat().map(|tx| tx.into()))
+    Box::new(fut.boxed().compat().map(|tx| tx.into()).map_err(TransactionErr::Plain))
 }
 
 pub fn tx_size_in_v_bytes(from_addr_format: &UtxoAddressFormat, tx: &UtxoTx) -> usize {
@@ -299,7 +391,7 @@ pub fn tx_size_in_v_bytes(from_addr_format: &UtxoAddressFormat, tx: &UtxoTx) ->
 /// Generates unsigned transaction (TransactionInputSigner) from specified utxos and outputs.
 /// This function expects that utxos are sorted by amounts in ascending order
 /// Consider sorting before calling this function
-/// Sends the change (inputs amount - outputs amount) to "my_address"
+/// Sends the change (inputs amount - outputs amount) to "my_address" or to a fresh change address of the HD account
 /// Also returns additional transaction data
 ///
 /// Note `gas_fee` should be enough to execute all of the contract calls within UTXO outputs.
@@ -313,13 +405,58 @@ pub async fn generate_transaction<T>(
     fee: Option<ActualTxFee>,
     gas_fee: Option<u64>,
 ) -> GenerateTxResult
+where
+    T: AsRef<UtxoCoinFields> + UtxoCommonOps,
+{
+    generate_transaction_with_coin_selection(
+        coin,
+        Vec::new(),
+        utxos,
+        outputs,
+        fee_policy,
+        fee,
+        gas_fee,
+        CoinSelectionStrategy::SmallestFirst,
+    )
+    .await
+}
+
+/// The same as [`generate_transaction`], but the `must_spend` unspents are spent first in the given order
+/// and the rest inputs are chosen from `utxos` with the given `coin_selection` strategy.
+/// The `must_spend` unspents are spent even if they're frozen, e.g. the SLP-bearing outputs or the HTLC being spent.
+#[allow(clippy::too_many_arguments)]
+pub async fn generate_transaction_with_coin_selection<T>(
+    coin: &T,
+    must_spend: Vec<UnspentInfo>,
+    mut utxos: Vec<UnspentInfo>,
+    outputs: Vec<TransactionOutput>,
+    fee_policy: FeePolicy,
+    fee: Option<ActualTxFee>,
+    gas_fee: Option<u64>,
+    coin_selection: CoinSelectionStrategy,
+) -> GenerateTxResult
 where
     T: AsRef<UtxoCoinFields> + UtxoCommonOps,
 {
     let dust: u64 = coin.as_ref().dust_amount;
     let lock_time = (now_ms() / 1000) as u32;
 
-    let change_script_pubkey = output_script(&coin.as_ref().my_address, ScriptType::P2PKH).to_bytes();
+    let my_script_pubkey = output_script(&coin.as_ref().my_address, ScriptType::P2PKH).to_bytes();
+    let hd_change_address = match coin.as_ref().hd_account {
+        Some(ref hd_account) => Some(
+            hd_account
+                .fresh_change_address(&coin.as_ref().conf, &coin.as_ref().rpc_client)
+                .await
+                .map_to_mm(GenerateTxError::Transport)?,
+        ),
+        None => None,
+    };
+    let change_script_pubkey = match hd_change_address {
+        Some(ref change_address) => output_script(&change_address.address, ScriptType::P2PKH).to_bytes(),
+        None => my_script_pubkey.clone(),
+    };
+    let is_my_script_pubkey =
+        |script_pubkey: &Bytes| *script_pubkey == change_script_pubkey || *script_pubkey == my_script_pubkey;
     let coin_tx_fee = match fee {
         Some(f) => f,
         None => coin.get_tx_fee().await?,
@@ -327,6 +464,15 @@ where
 
     true_or!(!outputs.is_empty(), GenerateTxError::EmptyOutputs);
 
+    // the outputs frozen by the user are never spent unless they're forced
+    {
+        let frozen_outpoints = coin.as_ref().frozen_outpoints.lock().await;
+        utxos.retain(|utxo| {
+            !frozen_outpoints.contains(&utxo.outpoint)
+                && must_spend.iter().all(|forced| forced.outpoint != utxo.outpoint)
+        });
+    }
+
     let mut sum_outputs_value = 0;
     let mut received_by_me = 0;
     for output in outputs.iter() {
@@ -338,7 +484,7 @@ where
             });
         }
         sum_outputs_value += output.value;
-        if output.script_pubkey == change_script_pubkey {
+        if is_my_script_pubkey(&output.script_pubkey) {
             received_by_me += output.value;
         }
     }
@@ -347,9 +493,12 @@ where
         sum_outputs_value += gas_fee;
     }
 
-    true_or!(!utxos.is_empty(), GenerateTxError::EmptyUtxoSet {
-        required: sum_outputs_value
-    });
+    true_or!(
+        !must_spend.is_empty() || !utxos.is_empty(),
+        GenerateTxError::EmptyUtxoSet {
+            required: sum_outputs_value
+        }
+    );
 
     let str_d_zeel = if coin.as_ref().conf.ticker == "NAV" {
         Some("".into())
@@ -379,6 +528,11 @@ where
         str_d_zeel,
         hash_algo,
     };
+    let sequence = if coin.as_ref().conf.signal_rbf {
+        MAX_BIP125_RBF_SEQUENCE
+    } else {
+        SEQUENCE_FINAL
+    };
     let mut sum_inputs = 0;
     let mut tx_fee = 0;
     let min_relay_fee = if coin.as_ref().conf.force_min_relay_fee {
@@ -388,11 +542,26 @@ where
     } else {
         None
     };
-    for utxo in utxos.iter() {
+
+    let fee_per_kb = match coin_tx_fee {
+        ActualTxFee::Dynamic(f) | ActualTxFee::FixedPerKb(f) => f,
+    };
+    let addr_format = &coin.as_ref().my_address.addr_format;
+    let base_v_size = tx_size_in_v_bytes(addr_format, &UtxoTx::from(tx.clone())) as u64;
+    let is_segwit = *addr_format == UtxoAddressFormat::Segwit;
+    // the selected unspents should cover what isn't covered by the forced ones
+    let must_spend_value: u64 = must_spend.iter().map(|utxo| utxo.value).sum();
+    let target = sum_outputs_value.saturating_sub(must_spend_value);
+    let selection_params = CoinSelectionParams::new(target, base_v_size, is_segwit, fee_per_kb, dust);
+    let selection = coin_selection.selector().select_coins(utxos, &selection_params);
+    let must_spend_count = must_spend.len() + selection.must_spend;
+
+    for (idx, utxo) in must_spend.iter().chain(selection.utxos.iter()).enumerate() {
+        let must_spend_more = idx + 1 < must_spend_count;
         sum_inputs += utxo.value;
         tx.inputs.push(UnsignedTransactionInput {
             previous_output: utxo.outpoint.clone(),
-            sequence: SEQUENCE_FINAL,
+            sequence,
             amount: utxo.value,
             witness: Vec::new(),
         });
@@ -433,7 +602,7 @@ where
                             tx_fee = min_relay;
                         }
                     }
-                    if sum_inputs >= outputs_plus_fee {
+                    if sum_inputs >= outputs_plus_fee && !must_spend_more {
                         break;
                     }
                 }
@@ -451,7 +620,9 @@ where
                             tx_fee = min_relay;
                         }
                     }
-                    break;
+                    if !must_spend_more {
+                        break;
+                    }
                 }
             },
         };
@@ -467,7 +638,7 @@ where
                 required: min_output,
             });
             tx.outputs[i].value -= tx_fee;
-            if tx.outputs[i].script_pubkey == change_script_pubkey {
+            if is_my_script_pubkey(&tx.outputs[i].script_pubkey) {
                 received_by_me -= tx_fee;
             }
         },
@@ -500,9 +671,31 @@ where
         unused_change,
         // will be changed if the ticker is KMD
         kmd_rewards: None,
+        change_address: None,
     };
 
-    Ok(coin.calc_interest_if_required(tx, data, change_script_pubkey).await?)
+    let (tx, mut data) = coin
+        .calc_interest_if_required(tx, data, change_script_pubkey.clone())
+        .await?;
+    if tx
+        .outputs
+        .iter()
+        .any(|output| output.script_pubkey == change_script_pubkey)
+    {
+        data.change_address = hd_change_address;
+    }
+    Ok((tx, data))
+}
+
+/// Adds the HD account change address of the signed transaction to the known addresses
+/// and subscribes to its balance updates.
+/// The change address isn't remembered by `generate_transaction` to not extend the internal chain on the fee previews.
+pub fn remember_change_address(coin: &UtxoCoinFields, data: &AdditionalTxData) -> Result<(), String> {
+    if let (Some(hd_account), Some(change_address)) = (&coin.hd_account, &data.change_address) {
+        try_s!(hd_account.extend_addresses(&coin.conf, change_address.chain, change_address.address_index));
+        subscribe_balance_updates(coin, std::slice::from_ref(&change_address.address));
+    }
+    Ok(())
 }
 
 /// Calculates interest if the coin is KMD
@@ -613,15 +806,27 @@ where
         str_d_zeel,
         hash_algo,
     };
-    let signed_input = try_s!(p2sh_spend(
-        &unsigned,
-        DEFAULT_SWAP_VOUT,
-        &coin.as_ref().key_pair,
-        script_data,
-        redeem_script.into(),
-        coin.as_ref().conf.signature_version,
-        coin.as_ref().conf.fork_id
-    ));
+    let prev_script: Script = prev_transaction.outputs[DEFAULT_SWAP_VOUT].script_pubkey.clone().into();
+    let signed_input = if prev_script.is_pay_to_witness_script_hash() {
+        try_s!(p2wsh_spend(
+            &unsigned,
+            DEFAULT_SWAP_VOUT,
+            try_s!(coin.as_ref().key_pair.signer()),
+            script_data,
+            redeem_script.into(),
+            coin.as_ref().conf.fork_id
+        ))
+    } else {
+        try_s!(p2sh_spend(
+            &unsigned,
+            DEFAULT_SWAP_VOUT,
+            try_s!(coin.as_ref().key_pair.signer()),
+            script_data,
+            redeem_script.into(),
+            coin.as_ref().conf.signature_version,
+            coin.as_ref().conf.fork_id
+        ))
+    };
     Ok(UtxoTx {
         version: unsigned.version,
         n_time: unsigned.n_time,
@@ -648,7 +853,7 @@ pub fn send_taker_fee<T>(coin: T, fee_pub_key: &[u8], amount: BigDecimal) -> Tra
 where
     T: AsRef<UtxoCoinFields> + UtxoCommonOps + Send + Sync + 'static,
 {
-    let address = try_fus!(address_from_raw_pubkey(
+    let address = try_tx_fus!(address_from_raw_pubkey(
         fee_pub_key,
         coin.as_ref().conf.pub_addr_prefix,
         coin.as_ref().conf.pub_t_addr_prefix,
@@ -656,7 +861,7 @@ where
         coin.as_ref().conf.bech32_hrp.clone(),
         coin.as_ref().my_address.addr_format.clone()
     ));
-    let amount = try_fus!(sat_from_big_decimal(&amount, coin.as_ref().decimals));
+    let amount = try_tx_fus!(sat_from_big_decimal(&amount, coin.as_ref().decimals));
     let output = TransactionOutput {
         value: amount,
         script_pubkey: Builder::build_p2pkh(&address.hash).to_bytes(),
@@ -670,31 +875,32 @@ pub fn send_maker_payment<T>(
     taker_pub: &[u8],
     secret_hash: &[u8],
     amount: BigDecimal,
+    other_protocol_info: &Option<Vec<u8>>,
 ) -> TransactionFut
 where
     T: AsRef<UtxoCoinFields> + UtxoCommonOps + Clone + Send + Sync + 'static,
 {
+    try_f!(coin.as_ref().key_pair.signer().map_err(TransactionErr::from));
+    let script_type = htlc_script_type(coin.as_ref(), other_protocol_info);
     let SwapPaymentOutputsResult {
         payment_address,
         outputs,
-    } = try_fus!(generate_swap_payment_outputs(
+    } = try_tx_fus!(generate_swap_payment_outputs(
         &coin,
         time_lock,
         taker_pub,
         secret_hash,
-        amount
+        amount,
+        script_type
     ));
     let send_fut = match &coin.as_ref().rpc_client {
         UtxoRpcClientEnum::Electrum(_) => Either::A(send_outputs_from_my_address(coin, outputs)),
-        UtxoRpcClientEnum::Native(client) => {
-            let addr_string = try_fus!(payment_address.display_address());
-            Either::B(
-                client
-                    .import_address(&addr_string, &addr_string, false)
-                    .map_err(|e| ERRL!("{}", e))
-                    .and_then(move |_| send_outputs_from_my_address(coin, outputs)),
-            )
-        },
+        UtxoRpcClientEnum::Native(client) => Either::B(
+            client
+                .import_address(&payment_address, &payment_address, false)
+                .map_err(|e| TransactionErr::Plain(ERRL!("{}", e)))
+                .and_then(move |_| send_outputs_from_my_address(coin, outputs)),
+        ),
     };
     Box::new(send_fut)
 }
@@ -705,31 +911,32 @@ pub fn send_taker_payment<T>(
     maker_pub: &[u8],
     secret_hash: &[u8],
     amount: BigDecimal,
+    other_protocol_info: &Option<Vec<u8>>,
 ) -> TransactionFut
 where
     T: AsRef<UtxoCoinFields> + UtxoCommonOps + Clone + Send + Sync + 'static,
 {
+    try_f!(coin.as_ref().key_pair.signer().map_err(TransactionErr::from));
+    let script_type = htlc_script_type(coin.as_ref(), other_protocol_info);
     let SwapPaymentOutputsResult {
         payment_address,
         outputs,
-    } = try_fus!(generate_swap_payment_outputs(
+    } = try_tx_fus!(generate_swap_payment_outputs(
         &coin,
         time_lock,
         maker_pub,
         secret_hash,
-        amount
+        amount,
+        script_type
     ));
     let send_fut = match &coin.as_ref().rpc_client {
         UtxoRpcClientEnum::Electrum(_) => Either::A(send_outputs_from_my_address(coin, outputs)),
-        UtxoRpcClientEnum::Native(client) => {
-            let addr_string = try_fus!(payment_address.display_address());
-            Either::B(
-                client
-                    .import_address(&addr_string, &addr_string, false)
-                    .map_err(|e| ERRL!("{}", e))
-                    .and_then(move |_| send_outputs_from_my_address(coin, outputs)),
-            )
-        },
+        UtxoRpcClientEnum::Native(client) => Either::B(
+            client
+                .import_address(&payment_address, &payment_address, false)
+                .map_err(|e| TransactionErr::Plain(ERRL!("{}", e)))
+                .and_then(move |_| send_outputs_from_my_address(coin, outputs)),
+        ),
     };
     Box::new(send_fut)
 }
@@ -744,7 +951,8 @@ pub fn send_maker_spends_taker_payment<T>(
 where
     T: AsRef<UtxoCoinFields> + UtxoCommonOps + Send + Sync + 'static,
 {
-    let mut prev_tx: UtxoTx = try_fus!(deserialize(taker_payment_tx).map_err(|e| ERRL!("{:?}", e)));
+    try_f!(coin.as_ref().key_pair.signer().map_err(TransactionErr::from));
+    let mut prev_tx: UtxoTx = try_tx_fus!(deserialize(taker_payment_tx).map_err(|e| ERRL!("{:?}", e)));
     prev_tx.tx_hash_algo = coin.as_ref().tx_hash_algo;
     let script_data = Builder::default()
         .push_data(secret)
@@ -753,7 +961,7 @@ where
     let redeem_script = payment_script(
         time_lock,
         &*dhash160(secret),
-        &try_fus!(Public::from_slice(taker_pub)),
+        &try_tx_fus!(Public::from_slice(taker_pub)),
         coin.as_ref().key_pair.public(),
     );
     let fut = async move {
@@ -778,7 +986,7 @@ where
         try_s!(tx_fut.await);
         Ok(transaction.into())
     };
-    Box::new(fut.boxed().compat())
+    Box::new(fut.boxed().compat().map_err(TransactionErr::Plain))
 }
 
 pub fn send_taker_spends_maker_payment<T>(
@@ -791,7 +999,8 @@ pub fn send_taker_spends_maker_payment<T>(
 where
     T: AsRef<UtxoCoinFields> + UtxoCommonOps + Send + Sync + 'static,
 {
-    let mut prev_tx: UtxoTx = try_fus!(deserialize(maker_payment_tx).map_err(|e| ERRL!("{:?}", e)));
+    try_f!(coin.as_ref().key_pair.signer().map_err(TransactionErr::from));
+    let mut prev_tx: UtxoTx = try_tx_fus!(deserialize(maker_payment_tx).map_err(|e| ERRL!("{:?}", e)));
     prev_tx.tx_hash_algo = coin.as_ref().tx_hash_algo;
     let script_data = Builder::default()
         .push_data(secret)
@@ -800,7 +1009,7 @@ where
     let redeem_script = payment_script(
         time_lock,
         &*dhash160(secret),
-        &try_fus!(Public::from_slice(maker_pub)),
+        &try_tx_fus!(Public::from_slice(maker_pub)),
         coin.as_ref().key_pair.public(),
     );
     let fut = async move {
@@ -825,7 +1034,7 @@ where
         try_s!(tx_fut.await);
         Ok(transaction.into())
     };
-    Box::new(fut.boxed().compat())
+    Box::new(fut.boxed().compat().map_err(TransactionErr::Plain))
 }
 
 pub fn send_taker_refunds_payment<T>(
@@ -838,14 +1047,15 @@ pub fn send_taker_refunds_payment<T>(
 where
     T: AsRef<UtxoCoinFields> + UtxoCommonOps + Send + Sync + 'static,
 {
-    let mut prev_tx: UtxoTx = try_fus!(deserialize(taker_payment_tx).map_err(|e| ERRL!("{:?}", e)));
+    try_f!(coin.as_ref().key_pair.signer().map_err(TransactionErr::from));
+    let mut prev_tx: UtxoTx = try_tx_fus!(deserialize(taker_payment_tx).map_err(|e| ERRL!("{:?}", e)));
     prev_tx.tx_hash_algo = coin.as_ref().tx_hash_algo;
     let script_data = Builder::default().push_opcode(Opcode::OP_1).into_script();
     let redeem_script = payment_script(
         time_lock,
         secret_hash,
         coin.as_ref().key_pair.public(),
-        &try_fus!(Public::from_slice(maker_pub)),
+        &try_tx_fus!(Public::from_slice(maker_pub)),
     );
     let fut = async move {
         let fee = try_s!(coin.get_htlc_spend_fee().await);
@@ -869,7 +1079,7 @@ where
         try_s!(tx_fut.await);
         Ok(transaction.into())
     };
-    Box::new(fut.boxed().compat())
+    Box::new(fut.boxed().compat().map_err(TransactionErr::Plain))
 }
 
 pub fn send_maker_refunds_payment<T>(
@@ -882,14 +1092,15 @@ pub fn send_maker_refunds_payment<T>(
 where
     T: AsRef<UtxoCoinFields> + UtxoCommonOps + Send + Sync + 'static,
 {
-    let mut prev_tx: UtxoTx = try_fus!(deserialize(maker_payment_tx).map_err(|e| ERRL!("{:?}", e)));
+    try_f!(coin.as_ref().key_pair.signer().map_err(TransactionErr::from));
+    let mut prev_tx: UtxoTx = try_tx_fus!(deserialize(maker_payment_tx).map_err(|e| ERRL!("{:?}", e)));
     prev_tx.tx_hash_algo = coin.as_ref().tx_hash_algo;
     let script_data = Builder::default().push_opcode(Opcode::OP_1).into_script();
     let redeem_script = payment_script(
         time_lock,
         secret_hash,
         coin.as_ref().key_pair.public(),
-        &try_fus!(Public::from_slice(taker_pub)),
+        &try_tx_fus!(Public::from_slice(taker_pub)),
     );
     let fut = async move {
         let fee = try_s!(coin.get_htlc_spend_fee().await);
@@ -913,7 +1124,7 @@ where
         try_s!(tx_fut.await);
         Ok(transaction.into())
     };
-    Box::new(fut.boxed().compat())
+    Box::new(fut.boxed().compat().map_err(TransactionErr::Plain))
 }
 
 /// Extracts pubkey from script sig
@@ -1150,47 +1361,59 @@ where
     );
     let hash = dhash160(&script);
     let p2sh = Builder::build_p2sh(&hash);
-    let script_hash = electrum_script_hash(&p2sh);
+    let p2sh_addr = Address {
+        t_addr_prefix: coin.as_ref().conf.p2sh_t_addr_prefix,
+        prefix: coin.as_ref().conf.p2sh_addr_prefix,
+        hash,
+        checksum_type: coin.as_ref().conf.checksum_type,
+        hrp: coin.as_ref().conf.bech32_hrp.clone(),
+        addr_format: coin.as_ref().my_address.addr_format.clone(),
+        taproot_output_key: None,
+    };
+    // the payment could be sent to any of the HTLC outputs we support
+    let mut htlc_outputs = vec![(p2sh, p2sh_addr.to_string())];
+    if let Some(hrp) = p2wsh_htlc_hrp(coin.as_ref()) {
+        let witness_script_hash = sha256(&script);
+        let p2wsh_addr = SegwitAddress::new_p2wsh(&witness_script_hash, hrp.clone());
+        htlc_outputs.push((Builder::build_p2wsh(&witness_script_hash), p2wsh_addr.to_string()));
+    }
     let fut = async move {
-        match &coin.as_ref().rpc_client {
-            UtxoRpcClientEnum::Electrum(client) => {
-                let history = try_s!(client.scripthash_get_history(&hex::encode(script_hash)).compat().await);
-                match history.first() {
-                    Some(item) => {
-                        let tx_bytes = try_s!(client.get_transaction_bytes(item.tx_hash.clone()).compat().await);
-                        let mut tx: UtxoTx = try_s!(deserialize(tx_bytes.0.as_slice()).map_err(|e| ERRL!("{:?}", e)));
-                        tx.tx_hash_algo = coin.as_ref().tx_hash_algo;
-                        Ok(Some(tx.into()))
-                    },
-                    None => Ok(None),
-                }
-            },
-            UtxoRpcClientEnum::Native(client) => {
-                let target_addr = Address {
-                    t_addr_prefix: coin.as_ref().conf.p2sh_t_addr_prefix,
-                    prefix: coin.as_ref().conf.p2sh_addr_prefix,
-                    hash,
-                    checksum_type: coin.as_ref().conf.checksum_type,
-                    hrp: coin.as_ref().conf.bech32_hrp.clone(),
-                    addr_format: coin.as_ref().my_address.addr_format.clone(),
-                };
-                let target_addr = target_addr.to_string();
-                let is_imported = try_s!(client.is_address_imported(&target_addr).await);
-                if !is_imported {
-                    return Ok(None);
-                }
-                let received_by_addr = try_s!(client.list_received_by_address(0, true, true).compat().await);
-                for item in received_by_addr {
-                    if item.address == target_addr && !item.txids.is_empty() {
-                        let tx_bytes = try_s!(client.get_transaction_bytes(item.txids[0].clone()).compat().await);
-                        let mut tx: UtxoTx = try_s!(deserialize(tx_bytes.0.as_slice()).map_err(|e| ERRL!("{:?}", e)));
-                        tx.tx_hash_algo = coin.as_ref().tx_hash_algo;
-                        return Ok(Some(tx.into()));
+        for (script_pubkey, target_addr) in htlc_outputs {
+            let tx = match &coin.as_ref().rpc_client {
+                UtxoRpcClientEnum::Electrum(client) => {
+                    let script_hash = electrum_script_hash(&script_pubkey);
+                    let history = try_s!(client.scripthash_get_history(&hex::encode(script_hash)).compat().await);
+                    match history.first() {
+                        Some(item) => Some(try_s!(
+                            client.get_transaction_bytes(item.tx_hash.clone()).compat().await
+                        )),
+                        None => None,
                     }
-                }
-                Ok(None)
-            },
+                },
+                UtxoRpcClientEnum::Native(client) => {
+                    let is_imported = try_s!(client.is_address_imported(&target_addr).await);
+                    if !is_imported {
+                        continue;
+                    }
+                    let received_by_addr = try_s!(client.list_received_by_address(0, true, true).compat().await);
+                    match received_by_addr
+                        .into_iter()
+                        .find(|item| item.address == target_addr && !item.txids.is_empty())
+                    {
+                        Some(item) => Some(try_s!(
+                            client.get_transaction_bytes(item.txids[0].clone()).compat().await
+                        )),
+                        None => None,
+                    }
+                },
+            };
+            if let Some(tx_bytes) = tx {
+                let mut tx: UtxoTx = try_s!(deserialize(tx_bytes.0.as_slice()).map_err(|e| ERRL!("{:?}", e)));
+                tx.tx_hash_algo = coin.as_ref().tx_hash_algo;
+                return Ok(Some(tx.into()));
+            }
         }
+        Ok(None)
     };
     Box::new(fut.boxed().compat())
 }
@@ -1238,10 +1461,27 @@ pub fn search_for_swap_tx_spend_other(
 }
 
 /// Extract a secret from the `spend_tx`.
-/// Note spender could generate the spend with several inputs where the only one input is the p2sh script.
+/// Note spender could generate the spend with several inputs where the only one input is the p2sh or p2wsh script.
 pub fn extract_secret(secret_hash: &[u8], spend_tx: &[u8]) -> Result<Vec<u8>, String> {
     let spend_tx: UtxoTx = try_s!(deserialize(spend_tx).map_err(|e| ERRL!("{:?}", e)));
     for (input_idx, input) in spend_tx.inputs.into_iter().enumerate() {
+        if input.has_witness() {
+            // the witness of the p2wsh spend is [signature, secret, OP_0, redeem_script]
+            let secret = match input.script_witness.get(1) {
+                Some(secret) if secret.len() == 32 => secret.to_vec(),
+                _ => {
+                    log!("Warning: couldn't find secret in the witness of "[input_idx]" input");
+                    continue;
+                },
+            };
+            let actual_secret_hash = &*dhash160(&secret);
+            if actual_secret_hash != secret_hash {
+                log!("Warning: invalid 'dhash160(secret)' "[actual_secret_hash]", expected "[secret_hash]);
+                continue;
+            }
+            return Ok(secret);
+        }
+
         let script: Script = input.script_sig.clone().into();
         let instruction = match script.get_instruction(1) {
             Some(Ok(instr)) => instr,
@@ -1286,6 +1526,10 @@ where
 }
 
 pub fn my_balance(coin: &UtxoCoinFields) -> BalanceFut<CoinBalance> {
+    if coin.hd_account.is_some() {
+        return hd_account_balance(coin);
+    }
+
     Box::new(
         coin.rpc_client
             .display_balance(coin.my_address.clone(), coin.decimals)
@@ -1298,6 +1542,28 @@ pub fn my_balance(coin: &UtxoCoinFields) -> BalanceFut<CoinBalance> {
     )
 }
 
+/// Sums up the balances of `my_address` and the known addresses of the HD account.
+fn hd_account_balance(coin: &UtxoCoinFields) -> BalanceFut<CoinBalance> {
+    async fn sum_balances(
+        rpc_client: UtxoRpcClientEnum,
+        addresses: Vec<Address>,
+        decimals: u8,
+    ) -> BalanceResult<CoinBalance> {
+        let mut spendable = BigDecimal::from(0);
+        for address in addresses {
+            let balance = rpc_client.display_balance(address, decimals).compat().await?;
+            spendable += &balance;
+        }
+        Ok(CoinBalance {
+            spendable,
+            unspendable: BigDecimal::from(0),
+        })
+    }
+
+    let fut = sum_balances(coin.rpc_client.clone(), coin.my_addresses(), coin.decimals);
+    Box::new(fut.boxed().compat())
+}
+
 pub fn send_raw_tx(coin: &UtxoCoinFields, tx: &str) -> Box<dyn Future<Item = String, Error = String> + Send> {
     let bytes = try_fus!(hex::decode(tx));
     Box::new(
@@ -1318,8 +1584,35 @@ pub fn wait_for_confirmations(
 ) -> Box<dyn Future<Item = (), Error = String> + Send> {
     let mut tx: UtxoTx = try_fus!(deserialize(tx).map_err(|e| ERRL!("{:?}", e)));
     tx.tx_hash_algo = coin.tx_hash_algo;
-    coin.rpc_client
-        .wait_for_confirmations(&tx, confirmations as u32, requires_nota, wait_until, check_every)
+    let confirmed =
+        coin.rpc_client
+            .wait_for_confirmations(&tx, confirmations as u32, requires_nota, wait_until, check_every);
+    let (client, spv_conf) = match (&coin.rpc_client, &coin.conf.spv_conf) {
+        // an unconfirmed transaction has no merkle branch to verify
+        _ if confirmations == 0 => return confirmed,
+        (UtxoRpcClientEnum::Electrum(client), Some(spv_conf)) => (client.clone(), spv_conf.clone()),
+        // the native node validates the blocks itself
+        _ => return confirmed,
+    };
+
+    let fut = async move {
+        confirmed.compat().await?;
+        loop {
+            match validate_spv_proof(&client, &spv_conf, &tx, confirmations as u32).await {
+                Ok(()) => return Ok(()),
+                Err(e) => error!("SPV validation of {:?} failed: {}", tx.hash().reversed(), e),
+            }
+            if now_ms() / 1000 > wait_until {
+                return ERR!(
+                    "Waited too long until {} for transaction {:?} to be verified by SPV",
+                    wait_until,
+                    tx.hash().reversed()
+                );
+            }
+            Timer::sleep(check_every as f64).await;
+        }
+    };
+    Box::new(fut.boxed().compat())
 }
 
 pub fn wait_for_output_spend(
@@ -1329,7 +1622,7 @@ pub fn wait_for_output_spend(
     from_block: u64,
     wait_until: u64,
 ) -> TransactionFut {
-    let mut tx: UtxoTx = try_fus!(deserialize(tx_bytes).map_err(|e| ERRL!("{:?}", e)));
+    let mut tx: UtxoTx = try_tx_fus!(deserialize(tx_bytes).map_err(|e| ERRL!("{:?}", e)));
     tx.tx_hash_algo = coin.tx_hash_algo;
     let client = coin.rpc_client.clone();
     let tx_hash_algo = coin.tx_hash_algo;
@@ -1357,7 +1650,7 @@ pub fn wait_for_output_spend(
             Timer::sleep(10.).await;
         }
     };
-    Box::new(fut.boxed().compat())
+    Box::new(fut.boxed().compat().map_err(TransactionErr::Plain))
 }
 
 pub fn tx_enum_from_bytes(coin: &UtxoCoinFields, bytes: &[u8]) -> Result<TransactionEnum, String> {
@@ -1370,7 +1663,13 @@ pub fn current_block(coin: &UtxoCoinFields) -> Box<dyn Future<Item = u64, Error
     Box::new(coin.rpc_client.get_block_count().map_err(|e| ERRL!("{}", e)))
 }
 
-pub fn display_priv_key(coin: &UtxoCoinFields) -> String { format!("{}", coin.key_pair.private()) }
+pub fn display_priv_key(coin: &UtxoCoinFields) -> String {
+    match &coin.key_pair {
+        UtxoKeyPair::KeyPair(key_pair) => format!("{}", key_pair.private()),
+        // `show_priv_key` is rejected if the private key is not kept by MM2
+        UtxoKeyPair::External(_) | UtxoKeyPair::WatchOnly(_) => String::new(),
+    }
+}
 
 pub fn min_tx_amount(coin: &UtxoCoinFields) -> BigDecimal {
     big_decimal_from_sat(coin.dust_amount as i64, coin.decimals)
@@ -1386,6 +1685,46 @@ pub fn min_trading_vol(coin: &UtxoCoinFields) -> MmNumber {
 
 pub fn is_asset_chain(coin: &UtxoCoinFields) -> bool { coin.conf.asset_chain }
 
+/// Returns the unspents given by the withdraw `inputs` or all the unspents except for the frozen ones
+/// if the `inputs` are not set.
+/// Returns the inputs that must be spent and the candidates for the coin selection.
+/// Every explicitly listed input is spent, the unlisted unspents aren't selected then.
+async fn select_withdraw_inputs(
+    coin: &UtxoCoinFields,
+    unspents: Vec<UnspentInfo>,
+    inputs: &[TxOutPoint],
+) -> Result<(Vec<UnspentInfo>, Vec<UnspentInfo>), MmError<WithdrawError>> {
+    let frozen_outpoints = coin.frozen_outpoints.lock().await;
+    if inputs.is_empty() {
+        let unspents = unspents
+            .into_iter()
+            .filter(|unspent| !frozen_outpoints.contains(&unspent.outpoint))
+            .collect();
+        return Ok((Vec::new(), unspents));
+    }
+
+    let mut selected: Vec<UnspentInfo> = Vec::with_capacity(inputs.len());
+    for input in inputs {
+        let outpoint = OutPoint::from(input.clone());
+        if selected.iter().any(|unspent| unspent.outpoint == outpoint) {
+            let error = format!("{:?}:{} is listed more than once", input.tx_hash, input.index);
+            return MmError::err(WithdrawError::InvalidInputs(error));
+        }
+        if frozen_outpoints.contains(&outpoint) {
+            let error = format!("{:?}:{} is frozen", input.tx_hash, input.index);
+            return MmError::err(WithdrawError::InvalidInputs(error));
+        }
+        match unspents.iter().find(|unspent| unspent.outpoint == outpoint) {
+            Some(unspent) => selected.push(unspent.clone()),
+            None => {
+                let error = format!("{:?}:{} is not a mature unspent output", input.tx_hash, input.index);
+                return MmError::err(WithdrawError::InvalidInputs(error));
+            },
+        }
+    }
+    Ok((selected, Vec::new()))
+}
+
 pub async fn withdraw<T>(coin: T, req: WithdrawRequest) -> WithdrawResult
 where
     T: AsRef<UtxoCoinFields> + UtxoCommonOps + MarketCoinOps,
@@ -1394,22 +1733,51 @@ where
 
     let conf = &coin.as_ref().conf;
 
-    let to = coin
-        .address_from_str(&req.to)
-        .map_to_mm(WithdrawError::InvalidAddress)?;
+    let withdraw_outputs = req.withdraw_outputs()?;
+    let mut outputs = Vec::with_capacity(withdraw_outputs.len() + 1);
+    for withdraw_output in withdraw_outputs.iter() {
+        let to = coin
+            .address_from_str(&withdraw_output.to)
+            .map_to_mm(WithdrawError::InvalidAddress)?;
 
-    let is_p2pkh = to.prefix == conf.pub_addr_prefix && to.t_addr_prefix == conf.pub_t_addr_prefix;
-    let is_p2sh = to.prefix == conf.p2sh_addr_prefix && to.t_addr_prefix == conf.p2sh_t_addr_prefix && conf.segwit;
+        let is_p2pkh = to.prefix == conf.pub_addr_prefix && to.t_addr_prefix == conf.pub_t_addr_prefix;
+        let is_p2sh = to.prefix == conf.p2sh_addr_prefix && to.t_addr_prefix == conf.p2sh_t_addr_prefix && conf.segwit;
 
-    let script_type = if is_p2pkh {
-        ScriptType::P2PKH
-    } else if is_p2sh {
-        ScriptType::P2SH
-    } else {
-        return MmError::err(WithdrawError::InvalidAddress("Expected either P2PKH or P2SH".into()));
-    };
+        let script_type = if is_p2pkh {
+            ScriptType::P2PKH
+        } else if is_p2sh {
+            ScriptType::P2SH
+        } else {
+            return MmError::err(WithdrawError::InvalidAddress("Expected either P2PKH or P2SH".into()));
+        };
 
-    let script_pubkey = output_script(&to, script_type).to_bytes();
+        // the value of the `max` output is set when the unspents are known
+        let value = if withdraw_output.max {
+            0
+        } else {
+            sat_from_big_decimal(&withdraw_output.amount, decimals)?
+        };
+        outputs.push(TransactionOutput {
+            value,
+            script_pubkey: output_script(&to, script_type).to_bytes(),
+        });
+    }
+    if let Some(ref memo) = req.memo {
+        if memo.len() > MAX_WITHDRAW_MEMO_LEN {
+            return MmError::err(WithdrawError::MemoTooLong {
+                length: memo.len(),
+                max_length: MAX_WITHDRAW_MEMO_LEN,
+            });
+        }
+        let script_pubkey = Builder::default()
+            .push_opcode(Opcode::OP_RETURN)
+            .push_data(memo.as_bytes())
+            .into_bytes();
+        outputs.push(TransactionOutput {
+            value: 0,
+            script_pubkey,
+        });
+    }
 
     let signature_version = match coin.as_ref().my_address.addr_format {
         UtxoAddressFormat::Segwit => SignatureVersion::WitnessV0,
@@ -1417,17 +1785,34 @@ where
     };
 
     let _utxo_lock = UTXO_LOCK.lock().await;
-    let (unspents, _) = coin.ordered_mature_unspents(&coin.as_ref().my_address).await?;
-    let (value, fee_policy) = if req.max {
-        (
-            unspents.iter().fold(0, |sum, unspent| sum + unspent.value),
-            FeePolicy::DeductFromOutput(0),
-        )
-    } else {
-        let value = sat_from_big_decimal(&req.amount, decimals)?;
-        (value, FeePolicy::SendExact)
+    let hd_unspents = match coin.as_ref().hd_account {
+        Some(ref hd_account) => Some(hd_account_ordered_mature_unspents(&coin, hd_account).await?),
+        None => None,
+    };
+    let unspents = match hd_unspents {
+        Some(ref hd_unspents) => hd_unspents.iter().map(|(unspent, _)| unspent.clone()).collect(),
+        None => coin.ordered_mature_unspents(&coin.as_ref().my_address).await?.0,
+    };
+    let (must_spend, unspents) = select_withdraw_inputs(coin.as_ref(), unspents, &req.inputs).await?;
+    let fee_policy = match withdraw_outputs.iter().position(|output| output.max) {
+        Some(max_index) => {
+            let balance = must_spend
+                .iter()
+                .chain(unspents.iter())
+                .fold(0, |sum, unspent| sum + unspent.value);
+            let other_outputs: u64 = outputs.iter().map(|output| output.value).sum();
+            if balance <= other_outputs {
+                return MmError::err(WithdrawError::NotSufficientBalance {
+                    coin: coin.ticker().to_owned(),
+                    available: big_decimal_from_sat_unsigned(balance, decimals),
+                    required: big_decimal_from_sat_unsigned(other_outputs, decimals),
+                });
+            }
+            outputs[max_index].value = balance - other_outputs;
+            FeePolicy::DeductFromOutput(max_index)
+        },
+        None => FeePolicy::SendExact,
     };
-    let outputs = vec![TransactionOutput { value, script_pubkey }];
     let fee = match req.fee {
         Some(WithdrawFee::UtxoFixed { amount }) => {
             let fixed = sat_from_big_decimal(&amount, decimals)?;
@@ -1447,39 +1832,103 @@ where
         None => None,
     };
     let gas_fee = None;
-    let (unsigned, data) = coin
-        .generate_transaction(unspents, outputs, fee_policy, fee, gas_fee)
-        .await
-        .mm_err(|gen_tx_error| {
-            WithdrawError::from_generate_tx_error(gen_tx_error, coin.ticker().to_owned(), decimals)
-        })?;
-    let prev_script = Builder::build_p2pkh(&coin.as_ref().my_address.hash);
-    let signed = sign_tx(
-        unsigned,
-        &coin.as_ref().key_pair,
-        prev_script,
-        signature_version,
-        coin.as_ref().conf.fork_id,
+    let coin_selection = req.coin_selection.unwrap_or(conf.coin_selection);
+    let (unsigned, data) = generate_transaction_with_coin_selection(
+        &coin,
+        must_spend,
+        unspents,
+        outputs,
+        fee_policy,
+        fee,
+        gas_fee,
+        coin_selection,
     )
-    .map_to_mm(WithdrawError::InternalError)?;
+    .await
+    .mm_err(|gen_tx_error| WithdrawError::from_generate_tx_error(gen_tx_error, coin.ticker().to_owned(), decimals))?;
+    let (tx, from, psbt) = if req.psbt {
+        let owners = match hd_unspents {
+            Some(ref hd_unspents) => {
+                Some(input_owners(&unsigned, hd_unspents).map_to_mm(WithdrawError::InternalError)?)
+            },
+            None => None,
+        };
+        let from = match owners {
+            Some(ref owners) => {
+                let mut from = Vec::new();
+                for owner in owners.iter() {
+                    let address = owner
+                        .address
+                        .display_address()
+                        .map_to_mm(WithdrawError::InternalError)?;
+                    if !from.contains(&address) {
+                        from.push(address);
+                    }
+                }
+                from
+            },
+            None => vec![coin.my_address().map_to_mm(WithdrawError::InternalError)?],
+        };
+        let psbt = withdraw_psbt(coin.as_ref(), unsigned, owners.as_deref()).await?;
+        (psbt.unsigned_tx.clone(), from, Some(psbt.to_bytes().into()))
+    } else {
+        let (signed, from) = match (&coin.as_ref().hd_account, hd_unspents) {
+            (Some(hd_account), Some(hd_unspents)) => {
+                let input_owners = input_owners(&unsigned, &hd_unspents).map_to_mm(WithdrawError::InternalError)?;
+                let mut key_pairs = Vec::with_capacity(input_owners.len());
+                let mut from = Vec::new();
+                for owner in input_owners {
+                    let key_pair = hd_account
+                        .key_pair(&coin.as_ref().conf, &owner)
+                        .map_to_mm(WithdrawError::InternalError)?;
+                    key_pairs.push(key_pair);
+                    let address = owner
+                        .address
+                        .display_address()
+                        .map_to_mm(WithdrawError::InternalError)?;
+                    if !from.contains(&address) {
+                        from.push(address);
+                    }
+                }
+                let signed =
+                    sign_tx_with_key_pairs(unsigned, &key_pairs, signature_version, coin.as_ref().conf.fork_id)
+                        .map_to_mm(WithdrawError::InternalError)?;
+                (signed, from)
+            },
+            _ => {
+                let prev_script = Builder::build_p2pkh(&coin.as_ref().my_address.hash);
+                let key_pair = coin.as_ref().key_pair.signer()?;
+                let signed = sign_tx(
+                    unsigned,
+                    key_pair,
+                    prev_script,
+                    signature_version,
+                    coin.as_ref().conf.fork_id,
+                )
+                .map_to_mm(WithdrawError::InternalError)?;
+                let my_address = coin.my_address().map_to_mm(WithdrawError::InternalError)?;
+                (signed, vec![my_address])
+            },
+        };
+        (signed, from, None)
+    };
+    remember_change_address(coin.as_ref(), &data).map_to_mm(WithdrawError::InternalError)?;
 
     let fee_amount = data.fee_amount + data.unused_change.unwrap_or_default();
     let fee_details = UtxoFeeDetails {
         amount: big_decimal_from_sat(fee_amount as i64, decimals),
     };
-    let my_address = coin.my_address().map_to_mm(WithdrawError::InternalError)?;
     let tx_hex = match coin.as_ref().my_address.addr_format {
-        UtxoAddressFormat::Segwit => serialize_with_flags(&signed, SERIALIZE_TRANSACTION_WITNESS).into(),
-        _ => serialize(&signed).into(),
+        UtxoAddressFormat::Segwit => serialize_with_flags(&tx, SERIALIZE_TRANSACTION_WITNESS).into(),
+        _ => serialize(&tx).into(),
     };
     Ok(TransactionDetails {
-        from: vec![my_address],
-        to: vec![req.to],
+        from,
+        to: withdraw_outputs.into_iter().map(|output| output.to).collect(),
         total_amount: big_decimal_from_sat(data.spent_by_me as i64, decimals),
         spent_by_me: big_decimal_from_sat(data.spent_by_me as i64, decimals),
         received_by_me: big_decimal_from_sat(data.received_by_me as i64, decimals),
         my_balance_change: big_decimal_from_sat(data.received_by_me as i64 - data.spent_by_me as i64, decimals),
-        tx_hash: signed.hash().reversed().to_vec().into(),
+        tx_hash: tx.hash().reversed().to_vec().into(),
         tx_hex,
         fee_details: Some(fee_details.into()),
         block_height: 0,
@@ -1487,9 +1936,47 @@ where
         internal_id: vec![].into(),
         timestamp: now_ms() / 1000,
         kmd_rewards: data.kmd_rewards,
+        psbt,
     })
 }
 
+/// Returns the mature unspents of the known HD account addresses ordered by value
+/// along with the addresses the unspents belong to.
+pub async fn hd_account_ordered_mature_unspents<T>(
+    coin: &T,
+    hd_account: &UtxoHDAccount,
+) -> UtxoRpcResult<Vec<(UnspentInfo, UtxoHDAddress)>>
+where
+    T: AsRef<UtxoCoinFields> + UtxoCommonOps,
+{
+    let mut result = Vec::new();
+    for hd_address in hd_account.all_addresses() {
+        let (unspents, _recently_spent) = coin.ordered_mature_unspents(&hd_address.address).await?;
+        result.extend(unspents.into_iter().map(|unspent| (unspent, hd_address.clone())));
+    }
+    result.sort_by_key(|(unspent, _)| unspent.value);
+    Ok(result)
+}
+
+/// Finds the HD account addresses owning the outputs spent by the `unsigned` transaction inputs.
+pub fn input_owners(
+    unsigned: &TransactionInputSigner,
+    hd_unspents: &[(UnspentInfo, UtxoHDAddress)],
+) -> Result<Vec<UtxoHDAddress>, String> {
+    let mut owners = Vec::with_capacity(unsigned.inputs.len());
+    for input in unsigned.inputs.iter() {
+        let owner = hd_unspents
+            .iter()
+            .find(|(unspent, _)| unspent.outpoint == input.previous_output)
+            .map(|(_, hd_address)| hd_address.clone());
+        match owner {
+            Some(owner) => owners.push(owner),
+            None => return ERR!("Unknown owner of the {:?} output", input.previous_output),
+        }
+    }
+    Ok(owners)
+}
+
 pub fn decimals(coin: &UtxoCoinFields) -> u8 { coin.decimals }
 
 pub fn convert_to_address<T>(coin: &T, from: &str, to_address_format: Json) -> Result<String, String>
@@ -1501,13 +1988,19 @@ where
     let mut from_address = try_s!(coin.address_from_str(from));
     match to_address_format {
         UtxoAddressFormat::Standard => {
+            if from_address.is_taproot() {
+                return ERR!("Taproot address can't be converted to the standard format");
+            }
             from_address.addr_format = UtxoAddressFormat::Standard;
             Ok(from_address.to_string())
         },
         UtxoAddressFormat::Segwit => {
             let bech32_hrp = &coin.as_ref().conf.bech32_hrp;
             match bech32_hrp {
-                Some(hrp) => Ok(SegwitAddress::new(&from_address.hash, hrp.clone()).to_string()),
+                Some(hrp) => {
+                    from_address.hrp = Some(hrp.clone());
+                    Ok(try_s!(from_address.to_segwitaddress()).to_string())
+                },
                 None => ERR!("Cannot convert to a segwit address for a coin with no bech32_hrp in config"),
             }
         },
@@ -1556,6 +2049,42 @@ where
     }
 }
 
+fn address_script_hashes(addresses: &[Address]) -> Vec<String> {
+    addresses
+        .iter()
+        .map(|address| hex::encode(electrum_script_hash(&output_script(address, ScriptType::P2PKH))))
+        .collect()
+}
+
+/// Returns the notifications sent by the Electrum server when the history of one of `my_addresses` is changed.
+/// The addresses are subscribed on the servers by `subscribe_balance_updates`, only the listener is added here.
+/// The native client doesn't support notifications, the balance should be polled then.
+pub fn balance_update_notifications(coin: &UtxoCoinFields) -> Option<CoinNotifications> {
+    match coin.rpc_client {
+        UtxoRpcClientEnum::Electrum(ref client) => {
+            let (tx, rx) = mpsc::unbounded();
+            client.add_scripthash_listener(&address_script_hashes(&coin.my_addresses()), &tx);
+            coin.balance_listeners.lock().unwrap().push(tx);
+            Some(rx)
+        },
+        UtxoRpcClientEnum::Native(_) => None,
+    }
+}
+
+/// Subscribes to the Electrum notifications of the coin addresses notifying the balance update listeners.
+/// Called on the coin activation and once a new address of the HD account is derived,
+/// the addresses subscribed before are skipped.
+pub fn subscribe_balance_updates(coin: &UtxoCoinFields, addresses: &[Address]) {
+    if let UtxoRpcClientEnum::Electrum(ref client) = coin.rpc_client {
+        let mut listeners = coin.balance_listeners.lock().unwrap();
+        listeners.retain(|tx| !tx.is_closed());
+        client.subscribe_scripthashes(address_script_hashes(addresses), &listeners);
+    }
+}
+
+/// If the history is updated on the Electrum notifications, it's still polled with this interval as a fallback.
+const HISTORY_NOTIFICATIONS_TIMEOUT: f64 = 300.;
+
 #[allow(clippy::cognitive_complexity)]
 pub async fn process_history_loop<T>(coin: T, ctx: MmArc)
 where
@@ -1578,6 +2107,7 @@ where
         .map(|tx| (H256Json::from(tx.tx_hash.as_slice()), tx))
         .collect();
 
+    let mut notifications = balance_update_notifications(coin.as_ref());
     let mut success_iteration = 0i32;
     loop {
         if ctx.is_stopping() {
@@ -1609,7 +2139,12 @@ where
         match (&my_balance, &actual_balance) {
             (Some(prev_balance), Some(actual_balance)) if prev_balance == actual_balance && !need_update => {
                 // my balance hasn't been changed, there is no need to reload tx_history
-                Timer::sleep(30.).await;
+                match notifications {
+                    Some(ref mut notifications) => {
+                        wait_for_notification(notifications, HISTORY_NOTIFICATIONS_TIMEOUT).await;
+                    },
+                    None => Timer::sleep(30.).await,
+                }
                 continue;
             },
             _ => (),
@@ -1662,13 +2197,28 @@ where
 
         // This is the cache of the already requested transactions.
         let mut input_transactions = HistoryUtxoTxMap::default();
-        for (txid, height) in tx_ids {
+        // The transactions of the current page requested in one batch.
+        let mut page_txs = HashMap::new();
+        for (i, (txid, height)) in tx_ids.iter().cloned().enumerate() {
+            if i % VERBOSE_TRANSACTIONS_BATCH_SIZE == 0 {
+                let page_end = std::cmp::min(i + VERBOSE_TRANSACTIONS_BATCH_SIZE, tx_ids.len());
+                let to_request = tx_ids[i..page_end]
+                    .iter()
+                    .filter(|(txid, _)| match history_map.get(txid) {
+                        Some(tx) => tx.should_update_timestamp() || tx.firo_negative_fee(),
+                        None => true,
+                    })
+                    .map(|(txid, _)| txid.clone())
+                    .collect();
+                page_txs = prefetch_history_page(&coin, to_request, &mut input_transactions).await;
+            }
+
             let mut updated = false;
             match history_map.entry(txid.clone()) {
                 Entry::Vacant(e) => {
                     mm_counter!(ctx.metrics, "tx.history.request.count", 1, "coin" => coin.as_ref().conf.ticker.clone(), "method" => "tx_detail_by_hash");
 
-                    match coin.tx_details_by_hash(&txid.0, &mut input_transactions).await {
+                    match history_tx_details(&coin, &txid, &mut page_txs, &mut input_transactions).await {
                         Ok(mut tx_details) => {
                             mm_counter!(ctx.metrics, "tx.history.response.count", 1, "coin" => coin.as_ref().conf.ticker.clone(), "method" => "tx_detail_by_hash");
 
@@ -1700,7 +2250,9 @@ where
                     if e.get().should_update_timestamp() || e.get().firo_negative_fee() {
                         mm_counter!(ctx.metrics, "tx.history.request.count", 1, "coin" => coin.as_ref().conf.ticker.clone(), "method" => "tx_detail_by_hash");
 
-                        if let Ok(tx_details) = coin.tx_details_by_hash(&txid.0, &mut input_transactions).await {
+                        if let Ok(tx_details) =
+                            history_tx_details(&coin, &txid, &mut page_txs, &mut input_transactions).await
+                        {
                             mm_counter!(ctx.metrics, "tx.history.response.count", 1, "coin" => coin.as_ref().conf.ticker.clone(), "method" => "tx_detail_by_hash");
                             // replace with new tx details in case we need to update any data
                             e.insert(tx_details);
@@ -1768,8 +2320,11 @@ pub async fn request_tx_history<T>(coin: &T, metrics: MetricsArc) -> RequestTxHi
 where
     T: AsRef<UtxoCoinFields> + MmCoin + MarketCoinOps,
 {
-    let my_address = match coin.my_address() {
-        Ok(addr) => addr,
+    let my_addresses = coin.as_ref().my_addresses();
+    let my_displayed_addresses: Result<Vec<String>, String> =
+        my_addresses.iter().map(|address| address.display_address()).collect();
+    let my_displayed_addresses = match my_displayed_addresses {
+        Ok(addresses) => addresses,
         Err(e) => {
             return RequestTxHistoryResult::UnknownError(ERRL!("Error on getting self address: {}. Stop tx history", e))
         },
@@ -1808,7 +2363,7 @@ where
             all_transactions
                 .into_iter()
                 .filter_map(|item| {
-                    if item.address == my_address {
+                    if my_displayed_addresses.contains(&item.address) {
                         Some((item.txid, item.blockindex))
                     } else {
                         None
@@ -1817,37 +2372,51 @@ where
                 .collect()
         },
         UtxoRpcClientEnum::Electrum(client) => {
-            let script = output_script(&coin.as_ref().my_address, ScriptType::P2PKH);
-            let script_hash = electrum_script_hash(&script);
+            let mut electrum_history = Vec::new();
+            for address in my_addresses.iter() {
+                let script = output_script(address, ScriptType::P2PKH);
+                let script_hash = electrum_script_hash(&script);
 
-            mm_counter!(metrics, "tx.history.request.count", 1,
+                mm_counter!(metrics, "tx.history.request.count", 1,
                 "coin" => coin.as_ref().conf.ticker.clone(), "client" => "electrum", "method" => "blockchain.scripthash.get_history");
 
-            let electrum_history = match client.scripthash_get_history(&hex::encode(script_hash)).compat().await {
-                Ok(value) => value,
-                Err(e) => match &e.error {
-                    JsonRpcErrorType::Transport(e) | JsonRpcErrorType::Parse(_, e) => {
-                        return RequestTxHistoryResult::Retry {
-                            error: ERRL!("Error {} on scripthash_get_history", e),
-                        };
-                    },
-                    JsonRpcErrorType::Response(_addr, err) => {
-                        if HISTORY_TOO_LARGE_ERROR.eq(err) {
-                            return RequestTxHistoryResult::HistoryTooLarge;
-                        } else {
+                let address_history = match client.scripthash_get_history(&hex::encode(script_hash)).compat().await {
+                    Ok(value) => value,
+                    Err(e) => match &e.error {
+                        JsonRpcErrorType::Transport(e)
+                        | JsonRpcErrorType::Parse(_, e)
+                        | JsonRpcErrorType::Unauthorized(e) => {
                             return RequestTxHistoryResult::Retry {
-                                error: ERRL!("Error {:?} on scripthash_get_history", e),
+                                error: ERRL!("Error {} on scripthash_get_history", e),
                             };
-                        }
+                        },
+                        JsonRpcErrorType::Response(_addr, err) => {
+                            if HISTORY_TOO_LARGE_ERROR.eq(err) {
+                                return RequestTxHistoryResult::HistoryTooLarge;
+                            } else {
+                                return RequestTxHistoryResult::Retry {
+                                    error: ERRL!("Error {:?} on scripthash_get_history", e),
+                                };
+                            }
+                        },
                     },
-                },
-            };
-            mm_counter!(metrics, "tx.history.response.count", 1,
+                };
+                mm_counter!(metrics, "tx.history.response.count", 1,
                 "coin" => coin.as_ref().conf.ticker.clone(), "client" => "electrum", "method" => "blockchain.scripthash.get_history");
 
-            mm_counter!(metrics, "tx.history.response.total_length", electrum_history.len() as u64,
+                mm_counter!(metrics, "tx.history.response.total_length", address_history.len() as u64,
                 "coin" => coin.as_ref().conf.ticker.clone(), "client" => "electrum", "method" => "blockchain.scripthash.get_history");
 
+                electrum_history.extend(address_history);
+            }
+
+            if my_addresses.len() > 1 {
+                // the same transaction may belong to several addresses of the HD account
+                let mut unique_hashes = HashSet::new();
+                electrum_history.retain(|item| unique_hashes.insert(item.tx_hash.clone()));
+                electrum_history.sort_by_key(|item| if item.height > 0 { item.height } else { i64::MAX });
+            }
+
             // electrum returns the most recent transactions in the end but we need to
             // process them first so rev is required
             electrum_history
@@ -1863,6 +2432,72 @@ where
     RequestTxHistoryResult::Ok(tx_ids)
 }
 
+/// Requests the transactions of the history page and their previous transactions in batches.
+/// The previous transactions are put to `input_transactions`, the transactions of the page are returned.
+async fn prefetch_history_page<T>(
+    coin: &T,
+    txids: Vec<H256Json>,
+    input_transactions: &mut HistoryUtxoTxMap,
+) -> HashMap<H256Json, RpcTransaction>
+where
+    T: AsRef<UtxoCoinFields>,
+{
+    let page_txs = request_verbose_transactions(coin.as_ref(), txids).await;
+
+    let mut prev_txids = HashSet::new();
+    for (txid, verbose_tx) in page_txs.iter() {
+        let tx: UtxoTx = match deserialize(verbose_tx.hex.as_slice()) {
+            Ok(tx) => tx,
+            // the error will be reported on getting the tx details
+            Err(_) => continue,
+        };
+        for input in tx.inputs.iter() {
+            // input transaction is zero if the tx is the coinbase transaction
+            if !input.previous_output.hash.is_zero() {
+                prev_txids.insert(H256Json::from(input.previous_output.hash.reversed()));
+            }
+        }
+        // the transaction of the page can be spent by another one of the same page
+        input_transactions.insert(txid.clone(), HistoryUtxoTx {
+            tx,
+            height: verbose_tx.height,
+        });
+    }
+
+    let prev_txids = prev_txids
+        .into_iter()
+        .filter(|txid| !input_transactions.contains_key(txid))
+        .collect();
+    for (txid, verbose_tx) in request_verbose_transactions(coin.as_ref(), prev_txids).await {
+        match deserialize(verbose_tx.hex.as_slice()) {
+            Ok(tx) => {
+                input_transactions.insert(txid, HistoryUtxoTx {
+                    tx,
+                    height: verbose_tx.height,
+                });
+            },
+            Err(e) => log!("Error " [e] " deserializing the transaction " [txid]),
+        }
+    }
+    page_txs
+}
+
+/// Gets the details of the prefetched transaction or requests the transaction if it wasn't prefetched.
+async fn history_tx_details<T>(
+    coin: &T,
+    txid: &H256Json,
+    page_txs: &mut HashMap<H256Json, RpcTransaction>,
+    input_transactions: &mut HistoryUtxoTxMap,
+) -> Result<TransactionDetails, String>
+where
+    T: UtxoStandardOps,
+{
+    match page_txs.remove(txid) {
+        Some(verbose_tx) => coin.tx_details_from_verbose_tx(verbose_tx, input_transactions).await,
+        None => coin.tx_details_by_hash(&txid.0, input_transactions).await,
+    }
+}
+
 pub async fn tx_details_by_hash<T>(
     coin: &T,
     hash: &[u8],
@@ -1871,7 +2506,6 @@ pub async fn tx_details_by_hash<T>(
 where
     T: AsRef<UtxoCoinFields> + UtxoCommonOps + Send + Sync + 'static,
 {
-    let ticker = &coin.as_ref().conf.ticker;
     let hash = H256Json::from(hash);
     let verbose_tx = try_s!(
         coin.as_ref()
@@ -1880,10 +2514,22 @@ where
             .compat()
             .await
     );
+    tx_details_from_verbose_tx(coin, verbose_tx, input_transactions).await
+}
+
+pub async fn tx_details_from_verbose_tx<T>(
+    coin: &T,
+    verbose_tx: RpcTransaction,
+    input_transactions: &mut HistoryUtxoTxMap,
+) -> Result<TransactionDetails, String>
+where
+    T: AsRef<UtxoCoinFields> + UtxoCommonOps + Send + Sync + 'static,
+{
+    let ticker = &coin.as_ref().conf.ticker;
     let mut tx: UtxoTx = try_s!(deserialize(verbose_tx.hex.as_slice()).map_err(|e| ERRL!("{:?}", e)));
     tx.tx_hash_algo = coin.as_ref().tx_hash_algo;
 
-    input_transactions.insert(hash, HistoryUtxoTx {
+    input_transactions.insert(verbose_tx.txid.clone(), HistoryUtxoTx {
         tx: tx.clone(),
         height: verbose_tx.height,
     });
@@ -1917,7 +2563,7 @@ where
                 .clone()
                 .into()
         ));
-        if from.contains(&coin.as_ref().my_address) {
+        if from.iter().any(|address| coin.as_ref().is_my_address(address)) {
             spent_by_me += prev_tx_value;
         }
         from_addresses.extend(from.into_iter());
@@ -1926,7 +2572,7 @@ where
     for output in tx.outputs.iter() {
         output_amount += output.value;
         let to = try_s!(coin.addresses_from_script(&output.script_pubkey.clone().into()));
-        if to.contains(&coin.as_ref().my_address) {
+        if to.iter().any(|address| coin.as_ref().is_my_address(address)) {
             received_by_me += output.value;
         }
         to_addresses.extend(to.into_iter());
@@ -2003,6 +2649,7 @@ where
         internal_id: tx.hash().reversed().to_vec().into(),
         timestamp: verbose_tx.time.into(),
         kmd_rewards,
+        psbt: None,
     })
 }
 
@@ -2319,8 +2966,62 @@ pub fn set_requires_notarization(coin: &UtxoCoinFields, requires_nota: bool) {
         .store(requires_nota, AtomicOrderding::Relaxed);
 }
 
+/// The type of the HTLC output of the swap payment.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum HtlcScriptType {
+    P2SH,
+    P2WSH,
+}
+
+/// The protocol info is the address format optionally followed by the list of the supported HTLC script types.
+/// The old versions read the address format only and ignore the trailing bytes.
 pub fn coin_protocol_info(coin: &UtxoCoinFields) -> Vec<u8> {
-    rmp_serde::to_vec(&coin.my_address.addr_format).expect("Serialization should not fail")
+    let mut info = rmp_serde::to_vec(&coin.my_address.addr_format).expect("Serialization should not fail");
+    if p2wsh_htlc_hrp(coin).is_some() {
+        let script_types = vec![HtlcScriptType::P2SH, HtlcScriptType::P2WSH];
+        info.extend(rmp_serde::to_vec(&script_types).expect("Serialization should not fail"));
+    }
+    info
+}
+
+/// Returns the HTLC script types supported by the other side according to its `coin_protocol_info`.
+/// The P2SH is the only type supported by the versions that don't send the list.
+pub fn htlc_script_types_from_protocol_info(info: &[u8]) -> Vec<HtlcScriptType> {
+    let mut reader = info;
+    rmp_serde::from_read::<_, UtxoAddressFormat>(&mut reader)
+        .and_then(|_| rmp_serde::from_read::<_, Vec<HtlcScriptType>>(&mut reader))
+        .unwrap_or_else(|_| vec![HtlcScriptType::P2SH])
+}
+
+/// Chooses the type of the HTLC output of the payment sent to the other side.
+/// The P2WSH is used only if both sides support it.
+pub fn htlc_script_type(coin: &UtxoCoinFields, other_protocol_info: &Option<Vec<u8>>) -> HtlcScriptType {
+    match other_protocol_info {
+        Some(info)
+            if p2wsh_htlc_hrp(coin).is_some()
+                && htlc_script_types_from_protocol_info(info).contains(&HtlcScriptType::P2WSH) =>
+        {
+            HtlcScriptType::P2WSH
+        },
+        _ => HtlcScriptType::P2SH,
+    }
+}
+
+/// Returns the bech32 HRP if the coin supports the P2WSH HTLC outputs.
+fn p2wsh_htlc_hrp(coin: &UtxoCoinFields) -> Option<&String> {
+    if coin.conf.segwit {
+        coin.conf.bech32_hrp.as_ref()
+    } else {
+        None
+    }
+}
+
+/// Builds the script pubkey of the HTLC output locked by the `redeem_script`.
+pub fn htlc_script_pubkey(redeem_script: &Script, script_type: HtlcScriptType) -> Script {
+    match script_type {
+        HtlcScriptType::P2SH => Builder::build_p2sh(&dhash160(redeem_script)),
+        HtlcScriptType::P2WSH => Builder::build_p2wsh(&sha256(redeem_script)),
+    }
 }
 
 pub fn is_coin_protocol_supported(coin: &UtxoCoinFields, info: &Option<Vec<u8>>) -> bool {
@@ -2365,17 +3066,23 @@ where
     let (unspents, recently_spent) = list_unspent_ordered(coin, address).await?;
     let block_count = coin.as_ref().rpc_client.get_block_count().compat().await?;
 
+    let txids: HashSet<H256Json> = unspents
+        .iter()
+        .map(|unspent| unspent.outpoint.hash.reversed().into())
+        .collect();
+    let verbose_txs = coin
+        .get_verbose_transactions_from_cache_or_rpc(txids)
+        .compat()
+        .await
+        .map_to_mm(UtxoRpcError::Internal)?;
+
     let mut result = Vec::with_capacity(unspents.len());
     for unspent in unspents {
         let tx_hash: H256Json = unspent.outpoint.hash.reversed().into();
-        let tx_info = match coin
-            .get_verbose_transaction_from_cache_or_rpc(tx_hash.clone())
-            .compat()
-            .await
-        {
-            Ok(x) => x,
-            Err(err) => {
-                log!("Error " [err] " getting the transaction " [tx_hash] ", skip the unspent output");
+        let tx_info = match verbose_txs.get(&tx_hash) {
+            Some(x) => x.clone(),
+            None => {
+                log!("Couldn't get the transaction " [tx_hash] ", skip the unspent output");
                 continue;
             },
         };
@@ -2418,37 +3125,82 @@ pub fn is_unspent_mature(mature_confirmations: u32, output: &RpcTransaction) ->
 }
 
 #[cfg(not(target_arch = "wasm32"))]
-pub async fn get_verbose_transaction_from_cache_or_rpc(
+pub async fn get_verbose_transactions_from_cache_or_rpc(
     coin: &UtxoCoinFields,
-    txid: H256Json,
-) -> Result<VerboseTransactionFrom, String> {
+    txids: HashSet<H256Json>,
+) -> Result<HashMap<H256Json, VerboseTransactionFrom>, String> {
     let tx_cache_path = match &coin.tx_cache_directory {
         Some(p) => p.clone(),
         _ => {
             // the coin doesn't support TX local cache, don't try to load from cache and don't cache it
-            let tx = try_s!(coin.rpc_client.get_verbose_transaction(txid.clone()).compat().await);
-            return Ok(VerboseTransactionFrom::Rpc(tx));
+            let txs = request_verbose_transactions(coin, txids.into_iter().collect()).await;
+            return Ok(txs
+                .into_iter()
+                .map(|(txid, tx)| (txid, VerboseTransactionFrom::Rpc(tx)))
+                .collect());
         },
     };
 
-    match tx_cache::load_transaction_from_cache(&tx_cache_path, &txid).await {
-        Ok(Some(tx)) => return Ok(VerboseTransactionFrom::Cache(tx)),
-        Err(err) => log!("Error " [err] " loading the " [txid] " transaction. Try request tx using Rpc client"),
-        // txid just not found
-        _ => (),
+    let mut result = HashMap::with_capacity(txids.len());
+    let mut to_request = Vec::new();
+    for txid in txids {
+        match tx_cache::load_transaction_from_cache(&tx_cache_path, &txid).await {
+            Ok(Some(tx)) => {
+                result.insert(txid, VerboseTransactionFrom::Cache(tx));
+            },
+            Err(err) => {
+                log!("Error " [err] " loading the " [txid] " transaction. Try request tx using Rpc client");
+                to_request.push(txid);
+            },
+            // txid just not found
+            Ok(None) => to_request.push(txid),
+        }
     }
 
-    let tx = try_s!(coin.rpc_client.get_verbose_transaction(txid).compat().await);
-    Ok(VerboseTransactionFrom::Rpc(tx))
+    let txs = request_verbose_transactions(coin, to_request).await;
+    result.extend(
+        txs.into_iter()
+            .map(|(txid, tx)| (txid, VerboseTransactionFrom::Rpc(tx))),
+    );
+    Ok(result)
 }
 
 #[cfg(target_arch = "wasm32")]
-pub async fn get_verbose_transaction_from_cache_or_rpc(
+pub async fn get_verbose_transactions_from_cache_or_rpc(
     coin: &UtxoCoinFields,
-    txid: H256Json,
-) -> Result<VerboseTransactionFrom, String> {
-    let tx = try_s!(coin.rpc_client.get_verbose_transaction(txid.clone()).compat().await);
-    Ok(VerboseTransactionFrom::Rpc(tx))
+    txids: HashSet<H256Json>,
+) -> Result<HashMap<H256Json, VerboseTransactionFrom>, String> {
+    let txs = request_verbose_transactions(coin, txids.into_iter().collect()).await;
+    Ok(txs
+        .into_iter()
+        .map(|(txid, tx)| (txid, VerboseTransactionFrom::Rpc(tx)))
+        .collect())
+}
+
+/// Requests the verbose transactions in batches of `VERBOSE_TRANSACTIONS_BATCH_SIZE`.
+/// If a batch fails, its transactions are requested one by one, the failed ones are absent in the result.
+pub async fn request_verbose_transactions(
+    coin: &UtxoCoinFields,
+    txids: Vec<H256Json>,
+) -> HashMap<H256Json, RpcTransaction> {
+    let mut result = HashMap::with_capacity(txids.len());
+    for chunk in txids.chunks(VERBOSE_TRANSACTIONS_BATCH_SIZE) {
+        match coin.rpc_client.get_verbose_transactions(chunk).compat().await {
+            Ok(txs) => result.extend(chunk.iter().cloned().zip(txs)),
+            Err(err) => {
+                log!("Error " [err] " requesting the batch of " (chunk.len()) " transactions, request them one by one");
+                for txid in chunk {
+                    match coin.rpc_client.get_verbose_transaction(txid.clone()).compat().await {
+                        Ok(tx) => {
+                            result.insert(txid.clone(), tx);
+                        },
+                        Err(err) => log!("Error " [err] " getting the transaction " [txid]),
+                    }
+                }
+            },
+        }
+    }
+    result
 }
 
 #[cfg(not(target_arch = "wasm32"))]
@@ -2537,6 +3289,7 @@ pub fn address_from_raw_pubkey(
         checksum_type,
         hrp,
         addr_format,
+        taproot_output_key: None,
     })
 }
 
@@ -2592,17 +3345,24 @@ where
                 );
             }
 
-            let expected_output = TransactionOutput {
-                value: amount,
-                script_pubkey: Builder::build_p2sh(&dhash160(&expected_redeem)).into(),
-            };
+            let mut script_types = vec![HtlcScriptType::P2SH];
+            if p2wsh_htlc_hrp(coin.as_ref()).is_some() {
+                script_types.push(HtlcScriptType::P2WSH);
+            }
+            let expected_outputs: Vec<_> = script_types
+                .into_iter()
+                .map(|script_type| TransactionOutput {
+                    value: amount,
+                    script_pubkey: htlc_script_pubkey(&expected_redeem, script_type).into(),
+                })
+                .collect();
 
             let actual_output = tx.outputs.get(output_index);
-            if actual_output != Some(&expected_output) {
+            if !expected_outputs.iter().any(|expected| actual_output == Some(expected)) {
                 return ERR!(
                     "Provided payment tx output doesn't match expected {:?} {:?}",
                     actual_output,
-                    expected_output
+                    expected_outputs
                 );
             }
             return Ok(());
@@ -2625,12 +3385,15 @@ async fn search_for_swap_output_spend(
     let mut tx: UtxoTx = try_s!(deserialize(tx).map_err(|e| ERRL!("{:?}", e)));
     tx.tx_hash_algo = coin.tx_hash_algo;
     let script = payment_script(time_lock, secret_hash, first_pub, second_pub);
-    let expected_script_pubkey = Builder::build_p2sh(&dhash160(&script)).to_bytes();
-    if tx.outputs[0].script_pubkey != expected_script_pubkey {
+    let expected_script_pubkeys = [
+        htlc_script_pubkey(&script, HtlcScriptType::P2SH).to_bytes(),
+        htlc_script_pubkey(&script, HtlcScriptType::P2WSH).to_bytes(),
+    ];
+    if !expected_script_pubkeys.contains(&tx.outputs[0].script_pubkey) {
         return ERR!(
             "Transaction {:?} output 0 script_pubkey doesn't match expected {:?}",
             tx,
-            expected_script_pubkey
+            expected_script_pubkeys
         );
     }
 
@@ -2643,6 +3406,16 @@ async fn search_for_swap_output_spend(
     match spend {
         Some(mut tx) => {
             tx.tx_hash_algo = coin.tx_hash_algo;
+            if tx.inputs[0].has_witness() {
+                // the witness of the p2wsh spend is [signature, secret, OP_0, redeem_script]
+                // and the witness of the refund is [signature, OP_1, redeem_script]
+                return match tx.inputs[0].script_witness.len() {
+                    4 => Ok(Some(FoundSwapTxSpend::Spent(tx.into()))),
+                    3 => Ok(Some(FoundSwapTxSpend::Refunded(tx.into()))),
+                    _ => ERR!("Unexpected witness of input 0 of tx {:?}", tx),
+                };
+            }
+
             let script: Script = tx.inputs[0].script_sig.clone().into();
             if let Some(Ok(ref i)) = script.iter().nth(2) {
                 if i.opcode == Opcode::OP_0 {
@@ -2666,7 +3439,7 @@ async fn search_for_swap_output_spend(
 }
 
 struct SwapPaymentOutputsResult {
-    payment_address: Address,
+    payment_address: String,
     outputs: Vec<TransactionOutput>,
 }
 
@@ -2676,6 +3449,7 @@ fn generate_swap_payment_outputs<T>(
     other_pub: &[u8],
     secret_hash: &[u8],
     amount: BigDecimal,
+    script_type: HtlcScriptType,
 ) -> Result<SwapPaymentOutputsResult, String>
 where
     T: AsRef<UtxoCoinFields>,
@@ -2686,11 +3460,10 @@ where
         coin.as_ref().key_pair.public(),
         &try_s!(Public::from_slice(other_pub)),
     );
-    let redeem_script_hash = dhash160(&redeem_script);
     let amount = try_s!(sat_from_big_decimal(&amount, coin.as_ref().decimals));
     let htlc_out = TransactionOutput {
         value: amount,
-        script_pubkey: Builder::build_p2sh(&redeem_script_hash).into(),
+        script_pubkey: htlc_script_pubkey(&redeem_script, script_type).into(),
     };
     // record secret hash to blockchain too making it impossible to lose
     // lock time may be easily brute forced so it is not mandatory to record it
@@ -2710,13 +3483,23 @@ where
         script_pubkey: op_return_script,
     };
 
-    let payment_address = Address {
-        checksum_type: coin.as_ref().conf.checksum_type,
-        hash: redeem_script_hash,
-        prefix: coin.as_ref().conf.p2sh_addr_prefix,
-        t_addr_prefix: coin.as_ref().conf.p2sh_t_addr_prefix,
-        hrp: coin.as_ref().conf.bech32_hrp.clone(),
-        addr_format: UtxoAddressFormat::Standard,
+    let payment_address = match script_type {
+        HtlcScriptType::P2SH => {
+            let payment_address = Address {
+                checksum_type: coin.as_ref().conf.checksum_type,
+                hash: dhash160(&redeem_script),
+                prefix: coin.as_ref().conf.p2sh_addr_prefix,
+                t_addr_prefix: coin.as_ref().conf.p2sh_t_addr_prefix,
+                hrp: coin.as_ref().conf.bech32_hrp.clone(),
+                addr_format: UtxoAddressFormat::Standard,
+                taproot_output_key: None,
+            };
+            try_s!(payment_address.display_address())
+        },
+        HtlcScriptType::P2WSH => {
+            let hrp = try_s!(p2wsh_htlc_hrp(coin.as_ref()).ok_or("The coin doesn't support P2WSH"));
+            SegwitAddress::new_p2wsh(&sha256(&redeem_script), hrp.clone()).to_string()
+        },
     };
     let result = SwapPaymentOutputsResult {
         payment_address,
@@ -2769,7 +3552,7 @@ pub fn dex_fee_script(uuid: [u8; 16], time_lock: u32, watcher_pub: &Public, send
 pub fn p2sh_spend(
     signer: &TransactionInputSigner,
     input_index: usize,
-    key_pair: &KeyPair,
+    key_pair: &dyn Signer,
     script_data: Script,
     redeem_script: Script,
     signature_version: SignatureVersion,
@@ -2801,6 +3584,55 @@ pub fn p2sh_spend(
     })
 }
 
+/// Creates signed input spending hash time locked p2wsh output
+pub fn p2wsh_spend(
+    signer: &TransactionInputSigner,
+    input_index: usize,
+    key_pair: &dyn Signer,
+    script_data: Script,
+    witness_script: Script,
+    fork_id: u32,
+) -> Result<TransactionInput, String> {
+    let sighash = signer.signature_hash(
+        input_index,
+        signer.inputs[input_index].amount,
+        &witness_script,
+        SignatureVersion::WitnessV0,
+        1 | fork_id,
+    );
+
+    let sig = try_s!(script_sig(&sighash, key_pair, fork_id));
+
+    // the witness items are pushed to the stack in the same order as the script_sig data of the p2sh spend
+    let mut script_witness = vec![sig];
+    for instruction in script_data.iter() {
+        let instruction = try_s!(instruction);
+        let item = match instruction.opcode {
+            Opcode::OP_0 => Bytes::new(),
+            Opcode::OP_1 => Bytes::from(vec![1]),
+            _ => match instruction.data {
+                Some(data) => Bytes::from(data.to_vec()),
+                None => {
+                    return ERR!(
+                        "Unexpected opcode {:?} in script_data {:?}",
+                        instruction.opcode,
+                        script_data
+                    )
+                },
+            },
+        };
+        script_witness.push(item);
+    }
+    script_witness.push(witness_script.to_bytes());
+
+    Ok(TransactionInput {
+        script_sig: Bytes::new(),
+        sequence: signer.inputs[input_index].sequence,
+        script_witness,
+        previous_output: signer.inputs[input_index].previous_output.clone(),
+    })
+}
+
 #[allow(clippy::needless_lifetimes)]
 pub async fn list_unspent_ordered<'a, T>(
     coin: &'a T,
@@ -2890,9 +3722,11 @@ where
             let output = TransactionOutput { value, script_pubkey };
             let merge_tx_fut = generate_and_send_tx(
                 &coin,
+                Vec::new(),
                 unspents,
                 vec![output],
                 FeePolicy::DeductFromOutput(0),
+                None,
                 recently_spent,
             );
             match merge_tx_fut.await {
@@ -0,0 +1,381 @@
+//! BIP44 accounts of the UTXO coins.
+//!
+//! In the HD mode a UTXO coin is activated with the `m/purpose'/coin_type'/account'` account
+//! which has the external (receiving) and the internal (change) chains of addresses.
+//! The used addresses are discovered on the coin activation by scanning every chain
+//! until `gap_limit` consecutive addresses without transactions are found.
+//! The native node rescans the blockchain for the addresses it imports on the discovery only,
+//! the addresses derived later are fresh and are imported without rescan.
+//! In the watch-only mode the account is created from its extended public key
+//! and can't derive the private keys of the addresses.
+
+use super::rpc_clients::{electrum_script_hash, NativeClient, UtxoRpcClientEnum};
+use super::{output_script, Address, KeyPair, Private, ScriptType, UtxoAddressFormat, UtxoCoinConf};
+use crate::hd_wallet::Bip44Chain;
+use crate::watch_only::WatchOnlyError;
+use futures::compat::Future01CompatExt;
+use futures::future::try_join_all;
+use keys::bip32::{ChildNumber, DerivationPath, ExtendedPrivateKey, ExtendedPublicKey};
+use primitives::hash::{H264, H32};
+use script::KeySource;
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+/// The number of consecutive unused addresses after which the address discovery stops.
+/// https://github.com/bitcoin/bips/blob/master/bip-0044.mediawiki#address-gap-limit
+pub const DEFAULT_GAP_LIMIT: u32 = 20;
+
+#[derive(Clone, Debug)]
+pub struct UtxoHDAddress {
+    pub address: Address,
+    /// The compressed public key of the address.
+    pub pubkey: H264,
+    pub chain: Bip44Chain,
+    pub address_index: u32,
+    /// The full path `m/purpose'/coin_type'/account'/chain/address_index`.
+    pub derivation_path: DerivationPath,
+}
+
+#[derive(Debug)]
+pub struct UtxoHDAccount {
+    pub account_id: u32,
+    /// The `m/purpose'/coin_type'/account'` path.
+    pub derivation_path: DerivationPath,
+    pub gap_limit: u32,
+    /// The fingerprint of the master key. `None` if the account is watched with its extended public key.
+    master_fingerprint: Option<H32>,
+    /// `None` if the account is watched with its extended public key.
+    account_xprv: Option<ExtendedPrivateKey>,
+    account_xpub: ExtendedPublicKey,
+    address_format: UtxoAddressFormat,
+    /// The known addresses of the external chain ordered by the address index.
+    external_addresses: Mutex<Vec<UtxoHDAddress>>,
+    /// The known addresses of the internal chain ordered by the address index.
+    internal_addresses: Mutex<Vec<UtxoHDAddress>>,
+    /// The index of the fresh change address imported to the native node already.
+    imported_change_index: Mutex<Option<u32>>,
+}
+
+impl UtxoHDAccount {
+    pub fn new(
+        master_key: &ExtendedPrivateKey,
+        coin_path: &DerivationPath,
+        account_id: u32,
+        gap_limit: u32,
+        address_format: UtxoAddressFormat,
+    ) -> Result<UtxoHDAccount, String> {
+        if gap_limit == 0 {
+            return ERR!("'gap_limit' must be greater than zero");
+        }
+        let derivation_path = coin_path.child(try_s!(ChildNumber::hardened(account_id)));
+        let account_xprv = try_s!(master_key.derive_path(&derivation_path));
+        let account_xpub = try_s!(account_xprv.extended_public_key());
+        Ok(UtxoHDAccount {
+            account_id,
+            derivation_path,
+            gap_limit,
+            master_fingerprint: Some(try_s!(master_key.fingerprint())),
+            account_xprv: Some(account_xprv),
+            account_xpub,
+            address_format,
+            external_addresses: Mutex::new(Vec::new()),
+            internal_addresses: Mutex::new(Vec::new()),
+            imported_change_index: Mutex::new(None),
+        })
+    }
+
+    /// Creates the watch-only account from the extended public key of `m/purpose'/coin_type'/account'`.
+    pub fn from_xpub(
+        account_xpub: ExtendedPublicKey,
+        coin_path: &DerivationPath,
+        gap_limit: u32,
+        address_format: UtxoAddressFormat,
+    ) -> Result<UtxoHDAccount, String> {
+        if gap_limit == 0 {
+            return ERR!("'gap_limit' must be greater than zero");
+        }
+        if account_xpub.depth != 3 || !account_xpub.child_number.is_hardened() {
+            return ERR!("Expected the extended public key of the m/purpose'/coin_type'/account' BIP44 account");
+        }
+        Ok(UtxoHDAccount {
+            account_id: account_xpub.child_number.index(),
+            derivation_path: coin_path.child(account_xpub.child_number),
+            gap_limit,
+            master_fingerprint: None,
+            account_xprv: None,
+            account_xpub,
+            address_format,
+            external_addresses: Mutex::new(Vec::new()),
+            internal_addresses: Mutex::new(Vec::new()),
+            imported_change_index: Mutex::new(None),
+        })
+    }
+
+    /// Returns the known addresses of the given chain.
+    pub fn addresses(&self, chain: Bip44Chain) -> Vec<UtxoHDAddress> {
+        self.chain_addresses(chain).lock().unwrap().clone()
+    }
+
+    /// Returns the known addresses of the external chain followed by the internal ones.
+    pub fn all_addresses(&self) -> Vec<UtxoHDAddress> {
+        let mut addresses = self.addresses(Bip44Chain::External);
+        addresses.extend(self.addresses(Bip44Chain::Internal));
+        addresses
+    }
+
+    /// Finds the known address with the same hash as the given `address` has.
+    pub fn find_address(&self, address: &Address) -> Option<UtxoHDAddress> {
+        self.all_addresses()
+            .into_iter()
+            .find(|hd_address| hd_address.address.hash == address.hash)
+    }
+
+    /// Derives the next address of the given chain and adds it to the known addresses.
+    pub fn new_address(&self, conf: &UtxoCoinConf, chain: Bip44Chain) -> Result<UtxoHDAddress, String> {
+        let mut addresses = self.chain_addresses(chain).lock().unwrap();
+        let hd_address = try_s!(self.derive_address(conf, chain, addresses.len() as u32));
+        addresses.push(hd_address.clone());
+        Ok(hd_address)
+    }
+
+    /// Makes sure that every address of the chain up to `address_index` inclusive is known.
+    pub fn extend_addresses(&self, conf: &UtxoCoinConf, chain: Bip44Chain, address_index: u32) -> Result<(), String> {
+        let mut addresses = self.chain_addresses(chain).lock().unwrap();
+        for index in addresses.len() as u32..=address_index {
+            addresses.push(try_s!(self.derive_address(conf, chain, index)));
+        }
+        Ok(())
+    }
+
+    /// Derives the key pair of the given address.
+    pub fn key_pair(&self, conf: &UtxoCoinConf, hd_address: &UtxoHDAddress) -> Result<KeyPair, String> {
+        let path = DerivationPath::from(vec![
+            hd_address.chain.child_number(),
+            try_s!(ChildNumber::normal(hd_address.address_index)),
+        ]);
+        let account_xprv = match &self.account_xprv {
+            Some(xprv) => xprv,
+            None => return ERR!("{}", WatchOnlyError),
+        };
+        let xprv = try_s!(account_xprv.derive_path(&path));
+        let private = Private {
+            prefix: conf.wif_prefix,
+            secret: xprv.secret,
+            compressed: true,
+            checksum_type: conf.checksum_type,
+        };
+        Ok(try_s!(KeyPair::from_private(private)))
+    }
+
+    /// Returns the origin of the address public key for the PSBT.
+    /// The key is derived from the account extended public key if the master key is unknown.
+    pub fn key_source(&self, hd_address: &UtxoHDAddress) -> KeySource {
+        let (fingerprint, path) = match self.master_fingerprint {
+            Some(fingerprint) => (fingerprint, hd_address.derivation_path.as_slice()),
+            None => (
+                self.account_xpub.fingerprint(),
+                &hd_address.derivation_path.as_slice()[self.derivation_path.len()..],
+            ),
+        };
+        KeySource {
+            fingerprint,
+            path: path.iter().map(ChildNumber::value).collect(),
+        }
+    }
+
+    /// Scans the external and internal chains until `gap_limit` consecutive unused addresses are found
+    /// and remembers every address up to the last used one.
+    /// Both chains are scanned by the same rounds, so the native node rescans the blockchain once per round.
+    /// The next round is needed only if the addresses of the previous one are used.
+    /// The first external address is always known, even if it's not used.
+    pub async fn discover_addresses(&self, conf: &UtxoCoinConf, rpc_client: &UtxoRpcClientEnum) -> Result<(), String> {
+        // the scanned addresses and the number of the addresses up to the last used one by the chain
+        let mut scans = [
+            (Bip44Chain::External, Vec::new(), 0),
+            (Bip44Chain::Internal, Vec::new(), 0),
+        ];
+        loop {
+            let mut batch = Vec::new();
+            for (chain, scanned, used_number) in scans.iter() {
+                let from = scanned.len() as u32;
+                for address_index in from..*used_number as u32 + self.gap_limit {
+                    batch.push(try_s!(self.derive_address(conf, *chain, address_index)));
+                }
+            }
+            if batch.is_empty() {
+                break;
+            }
+
+            let used = try_s!(addresses_used(rpc_client, &batch, true).await);
+            for (hd_address, is_used) in batch.into_iter().zip(used) {
+                let (_, scanned, used_number) = &mut scans[hd_address.chain as usize];
+                scanned.push(hd_address);
+                if is_used {
+                    *used_number = scanned.len();
+                }
+            }
+        }
+
+        for (chain, scanned, used_number) in scans.iter_mut() {
+            if *chain == Bip44Chain::External && *used_number == 0 {
+                *used_number = 1;
+            }
+            scanned.truncate(*used_number);
+            *self.chain_addresses(*chain).lock().unwrap() = std::mem::take(scanned);
+        }
+        Ok(())
+    }
+
+    /// Returns the address of the internal chain following the known ones,
+    /// so the change is never sent to a used address.
+    /// A newly derived address isn't added to the known addresses, so the fee previews don't extend the chain.
+    /// It has to be remembered with `extend_addresses` once the change is actually sent to it.
+    /// The native node imports the address once without rescan since it's fresh,
+    /// so the transaction generation doesn't list the transactions of the whole wallet.
+    pub async fn fresh_change_address(
+        &self,
+        conf: &UtxoCoinConf,
+        rpc_client: &UtxoRpcClientEnum,
+    ) -> Result<UtxoHDAddress, String> {
+        loop {
+            let known_number = self.addresses(Bip44Chain::Internal).len() as u32;
+            let next = try_s!(self.derive_address(conf, Bip44Chain::Internal, known_number));
+            match rpc_client {
+                UtxoRpcClientEnum::Electrum(_) => {
+                    let used = try_s!(addresses_used(rpc_client, std::slice::from_ref(&next), false).await);
+                    if !used[0] {
+                        return Ok(next);
+                    }
+                    // the address is used by another wallet of the same account already
+                    try_s!(self.extend_addresses(conf, Bip44Chain::Internal, next.address_index));
+                },
+                UtxoRpcClientEnum::Native(client) => {
+                    let imported = *self.imported_change_index.lock().unwrap() == Some(next.address_index);
+                    if !imported {
+                        try_s!(import_addresses(client, &[next.address.to_string()], false).await);
+                        *self.imported_change_index.lock().unwrap() = Some(next.address_index);
+                    }
+                    return Ok(next);
+                },
+            }
+        }
+    }
+
+    /// Imports the newly derived address to the native node without rescan, so the node watches its transactions.
+    pub async fn import_fresh_address(
+        &self,
+        rpc_client: &UtxoRpcClientEnum,
+        hd_address: &UtxoHDAddress,
+    ) -> Result<(), String> {
+        match rpc_client {
+            UtxoRpcClientEnum::Electrum(_) => Ok(()),
+            UtxoRpcClientEnum::Native(client) => {
+                import_addresses(client, &[hd_address.address.to_string()], false).await
+            },
+        }
+    }
+
+    /// Returns the number of the known addresses at the end of the given chain that have no transactions.
+    pub async fn trailing_unused_addresses_number(
+        &self,
+        rpc_client: &UtxoRpcClientEnum,
+        chain: Bip44Chain,
+    ) -> Result<u32, String> {
+        let addresses = self.addresses(chain);
+        let used = try_s!(addresses_used(rpc_client, &addresses, false).await);
+        Ok(used.into_iter().rev().take_while(|is_used| !is_used).count() as u32)
+    }
+
+    fn chain_addresses(&self, chain: Bip44Chain) -> &Mutex<Vec<UtxoHDAddress>> {
+        match chain {
+            Bip44Chain::External => &self.external_addresses,
+            Bip44Chain::Internal => &self.internal_addresses,
+        }
+    }
+
+    fn derive_address(
+        &self,
+        conf: &UtxoCoinConf,
+        chain: Bip44Chain,
+        address_index: u32,
+    ) -> Result<UtxoHDAddress, String> {
+        let address_child = try_s!(ChildNumber::normal(address_index));
+        let relative_path = DerivationPath::from(vec![chain.child_number(), address_child]);
+        let public = try_s!(self.account_xpub.derive_path(&relative_path)).public();
+        let address = Address {
+            prefix: conf.pub_addr_prefix,
+            t_addr_prefix: conf.pub_t_addr_prefix,
+            hash: public.address_hash(),
+            checksum_type: conf.checksum_type,
+            hrp: conf.bech32_hrp.clone(),
+            addr_format: self.address_format.clone(),
+            taproot_output_key: None,
+        };
+        Ok(UtxoHDAddress {
+            address,
+            pubkey: H264::from(&*public),
+            chain,
+            address_index,
+            derivation_path: self.derivation_path.extend(vec![chain.child_number(), address_child]),
+        })
+    }
+}
+
+/// Checks whether each of the given addresses has at least one transaction.
+/// The native node knows the transactions of the wallet addresses only, so the unknown addresses are imported first.
+/// The blockchain is rescanned for the imported addresses if `rescan` is set, i.e. on the discovery,
+/// otherwise the imported addresses are considered unused.
+async fn addresses_used(
+    rpc_client: &UtxoRpcClientEnum,
+    addresses: &[UtxoHDAddress],
+    rescan: bool,
+) -> Result<Vec<bool>, String> {
+    match rpc_client {
+        UtxoRpcClientEnum::Electrum(client) => {
+            let futures = addresses.iter().map(|hd_address| {
+                let script = output_script(&hd_address.address, ScriptType::P2PKH);
+                let script_hash = hex::encode(electrum_script_hash(&script));
+                client.scripthash_get_history(&script_hash).compat()
+            });
+            let histories = try_s!(try_join_all(futures).await);
+            Ok(histories.into_iter().map(|history| !history.is_empty()).collect())
+        },
+        UtxoRpcClientEnum::Native(client) => {
+            let mut received = try_s!(client.list_received_by_address(0, true, true).compat().await);
+            let imported: HashSet<String> = received.iter().map(|item| item.address.clone()).collect();
+            let not_imported: Vec<String> = addresses
+                .iter()
+                .map(|hd_address| hd_address.address.to_string())
+                .filter(|address| !imported.contains(address))
+                .collect();
+            try_s!(import_addresses(client, &not_imported, rescan).await);
+            if rescan && !not_imported.is_empty() {
+                received = try_s!(client.list_received_by_address(0, true, true).compat().await);
+            }
+            let used: HashSet<String> = received
+                .into_iter()
+                .filter(|item| !item.txids.is_empty())
+                .map(|item| item.address)
+                .collect();
+            Ok(addresses
+                .iter()
+                .map(|hd_address| used.contains(&hd_address.address.to_string()))
+                .collect())
+        },
+    }
+}
+
+/// Imports the addresses to the native node wallet.
+/// The blockchain is rescanned once for all the addresses after the last one is imported if `rescan` is set.
+async fn import_addresses(client: &NativeClient, addresses: &[String], rescan: bool) -> Result<(), String> {
+    let last = addresses.len().saturating_sub(1);
+    for (i, address) in addresses.iter().enumerate() {
+        try_s!(
+            client
+                .import_address(address, address, rescan && i == last)
+                .compat()
+                .await
+        );
+    }
+    Ok(())
+}
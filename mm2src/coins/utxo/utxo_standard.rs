@@ -1,5 +1,5 @@
 use super::*;
-use crate::{CanRefundHtlc, CoinBalance, NegotiateSwapContractAddrErr, SwapOps, TradePreimageValue,
+use crate::{CanRefundHtlc, CoinBalance, CoinNotifications, NegotiateSwapContractAddrErr, SwapOps, TradePreimageValue,
             ValidateAddressResult, WithdrawFut};
 use common::mm_metrics::MetricsArc;
 use common::mm_number::MmNumber;
@@ -123,12 +123,12 @@ impl UtxoCommonOps for UtxoStandardCoin {
         utxo_common::ordered_mature_unspents(self, address).await
     }
 
-    fn get_verbose_transaction_from_cache_or_rpc(
+    fn get_verbose_transactions_from_cache_or_rpc(
         &self,
-        txid: H256Json,
-    ) -> Box<dyn Future<Item = VerboseTransactionFrom, Error = String> + Send> {
+        txids: HashSet<H256Json>,
+    ) -> Box<dyn Future<Item = HashMap<H256Json, VerboseTransactionFrom>, Error = String> + Send> {
         let selfi = self.clone();
-        let fut = async move { utxo_common::get_verbose_transaction_from_cache_or_rpc(&selfi.utxo_arc, txid).await };
+        let fut = async move { utxo_common::get_verbose_transactions_from_cache_or_rpc(&selfi.utxo_arc, txids).await };
         Box::new(fut.boxed().compat())
     }
 
@@ -176,6 +176,14 @@ impl UtxoStandardOps for UtxoStandardCoin {
         utxo_common::tx_details_by_hash(self, hash, input_transactions).await
     }
 
+    async fn tx_details_from_verbose_tx(
+        &self,
+        verbose_tx: RpcTransaction,
+        input_transactions: &mut HistoryUtxoTxMap,
+    ) -> Result<TransactionDetails, String> {
+        utxo_common::tx_details_from_verbose_tx(self, verbose_tx, input_transactions).await
+    }
+
     async fn request_tx_history(&self, metrics: MetricsArc) -> RequestTxHistoryResult {
         utxo_common::request_tx_history(self, metrics).await
     }
@@ -201,8 +209,16 @@ impl SwapOps for UtxoStandardCoin {
         secret_hash: &[u8],
         amount: BigDecimal,
         _swap_contract_address: &Option<BytesJson>,
+        other_protocol_info: &Option<Vec<u8>>,
     ) -> TransactionFut {
-        utxo_common::send_maker_payment(self.clone(), time_lock, taker_pub, secret_hash, amount)
+        utxo_common::send_maker_payment(
+            self.clone(),
+            time_lock,
+            taker_pub,
+            secret_hash,
+            amount,
+            other_protocol_info,
+        )
     }
 
     fn send_taker_payment(
@@ -212,8 +228,16 @@ impl SwapOps for UtxoStandardCoin {
         secret_hash: &[u8],
         amount: BigDecimal,
         _swap_contract_address: &Option<BytesJson>,
+        other_protocol_info: &Option<Vec<u8>>,
     ) -> TransactionFut {
-        utxo_common::send_taker_payment(self.clone(), time_lock, maker_pub, secret_hash, amount)
+        utxo_common::send_taker_payment(
+            self.clone(),
+            time_lock,
+            maker_pub,
+            secret_hash,
+            amount,
+            other_protocol_info,
+        )
     }
 
     fn send_maker_spends_taker_payment(
@@ -508,4 +532,8 @@ impl MmCoin for UtxoStandardCoin {
     fn is_coin_protocol_supported(&self, info: &Option<Vec<u8>>) -> bool {
         utxo_common::is_coin_protocol_supported(&self.utxo_arc, info)
     }
+
+    fn balance_update_notifications(&self) -> Option<CoinNotifications> {
+        utxo_common::balance_update_notifications(&self.utxo_arc)
+    }
 }
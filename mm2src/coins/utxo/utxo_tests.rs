@@ -1,19 +1,26 @@
-use super::rpc_clients::{ListSinceBlockRes, NetworkInfo};
+use super::rpc_clients::{electrum_process_json, ElectrumServerStats, ElectrumSubscriptions, ListSinceBlockRes,
+                         NetworkInfo};
 use super::*;
+use crate::message_signing::SignatureError;
 use crate::utxo::qtum::{qtum_coin_from_conf_and_request, QtumCoin};
 use crate::utxo::rpc_clients::{GetAddressInfoRes, UtxoRpcClientOps, ValidateAddressRes, VerboseBlock};
-use crate::utxo::utxo_common::{generate_transaction, UtxoArcBuilder};
+use crate::utxo::utxo_common::{big_decimal_from_sat_unsigned, coin_protocol_info, generate_transaction,
+                               generate_transaction_with_coin_selection, htlc_script_type, is_coin_protocol_supported,
+                               p2wsh_spend, payment_script, tx_size_in_v_bytes, HtlcScriptType, UtxoArcBuilder};
 use crate::utxo::utxo_standard::{utxo_standard_coin_from_conf_and_request, UtxoStandardCoin};
 #[cfg(not(target_arch = "wasm32"))] use crate::WithdrawFee;
-use crate::{CoinBalance, SwapOps, TradePreimageValue, TxFeeDetails};
+use crate::{CoinBalance, SwapOps, TradePreimageValue, TxFeeDetails, TxOutPoint, WithdrawOutput};
 use bigdecimal::BigDecimal;
+use chain::constants::{MAX_BIP125_RBF_SEQUENCE, SEQUENCE_FINAL};
 use chain::OutPoint;
 use common::mm_ctx::MmCtxBuilder;
 use common::privkey::key_pair_from_seed;
 use common::{block_on, now_ms, OrdRange, DEX_FEE_ADDR_RAW_PUBKEY};
 use futures::future::join_all;
+use keys::SegwitAddress;
 use mocktopus::mocking::*;
 use rpc::v1::types::H256 as H256Json;
+use script::Opcode;
 use serialization::{deserialize, CoinVariant};
 
 const TEST_COIN_NAME: &'static str = "RICK";
@@ -46,7 +53,7 @@ pub fn electrum_client_for_test(servers: &[&str]) -> ElectrumClient {
 #[cfg(not(target_arch = "wasm32"))]
 fn native_client_for_test() -> NativeClient { NativeClient(Arc::new(NativeClientImpl::default())) }
 
-fn utxo_coin_fields_for_test(
+pub(crate) fn utxo_coin_fields_for_test(
     rpc_client: UtxoRpcClientEnum,
     force_seed: Option<&str>,
     is_segwit_coin: bool,
@@ -74,6 +81,7 @@ fn utxo_coin_fields_for_test(
         checksum_type,
         hrp: None,
         addr_format: UtxoAddressFormat::Standard,
+        taproot_output_key: None,
     };
     let my_script_pubkey = Builder::build_p2pkh(&my_address.hash).to_bytes();
     let bech32_hrp = if is_segwit_coin {
@@ -111,21 +119,28 @@ fn utxo_coin_fields_for_test(
             estimate_fee_mode: None,
             mature_confirmations: MATURE_CONFIRMATIONS_DEFAULT,
             estimate_fee_blocks: 1,
+            signal_rbf: false,
+            coin_selection: CoinSelectionStrategy::default(),
+            sign_message_prefix: Some(String::from("Komodo Signed Message:\n")),
+            spv_conf: None,
         },
         decimals: 8,
         dust_amount: UTXO_DUST_AMOUNT,
         tx_fee: TxFee::FixedPerKb(1000),
         rpc_client,
-        key_pair,
+        key_pair: key_pair.into(),
         my_address,
         history_sync_state: Mutex::new(HistorySyncState::NotEnabled),
         tx_cache_directory: None,
         recently_spent_outpoints: AsyncMutex::new(RecentlySpentOutPoints::new(my_script_pubkey)),
+        frozen_outpoints: AsyncMutex::new(FrozenOutPoints::default()),
         tx_hash_algo: TxHashAlgo::DSHA256,
+        hd_account: None,
+        balance_listeners: Mutex::new(Vec::new()),
     }
 }
 
-fn utxo_coin_from_fields(coin: UtxoCoinFields) -> UtxoStandardCoin {
+pub(crate) fn utxo_coin_from_fields(coin: UtxoCoinFields) -> UtxoStandardCoin {
     let arc: UtxoArc = coin.into();
     arc.into()
 }
@@ -150,6 +165,65 @@ fn test_extract_secret() {
     assert_eq!(secret, expected_secret);
 }
 
+#[test]
+fn test_htlc_script_type_negotiation() {
+    let client = electrum_client_for_test(RICK_ELECTRUM_ADDRS);
+    let segwit_coin = utxo_coin_for_test(client.clone().into(), None, true);
+    let legacy_coin = utxo_coin_for_test(client.into(), None, false);
+
+    let segwit_info = Some(coin_protocol_info(segwit_coin.as_ref()));
+    let legacy_info = Some(coin_protocol_info(legacy_coin.as_ref()));
+    // the old versions send the address format only
+    let old_info = Some(rmp_serde::to_vec(&UtxoAddressFormat::Standard).unwrap());
+
+    let script_type = htlc_script_type(segwit_coin.as_ref(), &segwit_info);
+    assert_eq!(script_type, HtlcScriptType::P2WSH);
+    let script_type = htlc_script_type(segwit_coin.as_ref(), &legacy_info);
+    assert_eq!(script_type, HtlcScriptType::P2SH);
+    let script_type = htlc_script_type(segwit_coin.as_ref(), &old_info);
+    assert_eq!(script_type, HtlcScriptType::P2SH);
+    let script_type = htlc_script_type(segwit_coin.as_ref(), &None);
+    assert_eq!(script_type, HtlcScriptType::P2SH);
+    let script_type = htlc_script_type(legacy_coin.as_ref(), &segwit_info);
+    assert_eq!(script_type, HtlcScriptType::P2SH);
+
+    // the trailing list of the HTLC script types must not break the address format parsing
+    assert!(is_coin_protocol_supported(legacy_coin.as_ref(), &segwit_info));
+}
+
+#[test]
+fn test_p2wsh_htlc_spend_extract_secret() {
+    let client = electrum_client_for_test(RICK_ELECTRUM_ADDRS);
+    let coin = utxo_coin_for_test(client.into(), None, true);
+
+    let secret = [1; 32];
+    let secret_hash = dhash160(&secret);
+    let my_public = coin.as_ref().key_pair.public();
+    let redeem_script = payment_script(1000000000, &*secret_hash, my_public, my_public);
+    let script_data = Builder::default()
+        .push_data(&secret)
+        .push_opcode(Opcode::OP_0)
+        .into_script();
+
+    let mut unsigned_tx = UtxoTx::default();
+    unsigned_tx.inputs.push(TransactionInput::default());
+    let signer = TransactionInputSigner::from(unsigned_tx.clone());
+    let key_pair = coin.as_ref().key_pair.signer().unwrap();
+    let input = p2wsh_spend(&signer, 0, key_pair, script_data, redeem_script.clone(), 0).unwrap();
+    assert!(input.script_sig.is_empty());
+    assert_eq!(input.script_witness.len(), 4);
+    assert_eq!(input.script_witness[1], Bytes::from(secret.to_vec()));
+    assert!(input.script_witness[2].is_empty());
+    assert_eq!(input.script_witness[3], redeem_script.to_bytes());
+
+    let spend_tx = UtxoTx {
+        inputs: vec![input],
+        ..unsigned_tx
+    };
+    let actual_secret = coin.extract_secret(&*secret_hash, &serialize(&spend_tx)).unwrap();
+    assert_eq!(actual_secret, secret.to_vec());
+}
+
 #[test]
 fn test_generate_transaction() {
     let client = electrum_client_for_test(RICK_ELECTRUM_ADDRS);
@@ -491,6 +565,11 @@ fn test_withdraw_impl_set_fixed_fee() {
         fee: Some(WithdrawFee::UtxoFixed {
             amount: "0.1".parse().unwrap(),
         }),
+        outputs: Vec::new(),
+        memo: None,
+        inputs: Vec::new(),
+        coin_selection: None,
+        psbt: false,
     };
     let expected = Some(
         UtxoFeeDetails {
@@ -530,6 +609,11 @@ fn test_withdraw_impl_sat_per_kb_fee() {
         fee: Some(WithdrawFee::UtxoPerKbyte {
             amount: "0.1".parse().unwrap(),
         }),
+        outputs: Vec::new(),
+        memo: None,
+        inputs: Vec::new(),
+        coin_selection: None,
+        psbt: false,
     };
     // The resulting transaction size might be 244 or 245 bytes depending on signature size
     // MM2 always expects the worst case during fee calculation
@@ -572,6 +656,11 @@ fn test_withdraw_impl_sat_per_kb_fee_amount_equal_to_max() {
         fee: Some(WithdrawFee::UtxoPerKbyte {
             amount: "0.1".parse().unwrap(),
         }),
+        outputs: Vec::new(),
+        memo: None,
+        inputs: Vec::new(),
+        coin_selection: None,
+        psbt: false,
     };
     let tx_details = coin.withdraw(withdraw_req).wait().unwrap();
     // The resulting transaction size might be 210 or 211 bytes depending on signature size
@@ -616,6 +705,11 @@ fn test_withdraw_impl_sat_per_kb_fee_amount_equal_to_max_dust_included_to_fee()
         fee: Some(WithdrawFee::UtxoPerKbyte {
             amount: "0.09999999".parse().unwrap(),
         }),
+        outputs: Vec::new(),
+        memo: None,
+        inputs: Vec::new(),
+        coin_selection: None,
+        psbt: false,
     };
     let tx_details = coin.withdraw(withdraw_req).wait().unwrap();
     // The resulting transaction size might be 210 or 211 bytes depending on signature size
@@ -634,7 +728,962 @@ fn test_withdraw_impl_sat_per_kb_fee_amount_equal_to_max_dust_included_to_fee()
 
 #[test]
 #[cfg(not(target_arch = "wasm32"))]
-fn test_withdraw_impl_sat_per_kb_fee_amount_over_max() {
+fn test_withdraw_impl_sat_per_kb_fee_amount_over_max() {
+    UtxoStandardCoin::ordered_mature_unspents.mock_safe(|coin, _| {
+        let cache = block_on(coin.as_ref().recently_spent_outpoints.lock());
+        let unspents = vec![UnspentInfo {
+            outpoint: OutPoint {
+                hash: 1.into(),
+                index: 0,
+            },
+            value: 1000000000,
+            height: Default::default(),
+        }];
+        MockResult::Return(Box::pin(futures::future::ok((unspents, cache))))
+    });
+
+    let client = NativeClient(Arc::new(NativeClientImpl::default()));
+
+    let coin = utxo_coin_for_test(UtxoRpcClientEnum::Native(client), None, false);
+
+    let withdraw_req = WithdrawRequest {
+        amount: "9.97939455".parse().unwrap(),
+        to: "RQq6fWoy8aGGMLjvRfMY5mBNVm2RQxJyLa".to_string(),
+        coin: TEST_COIN_NAME.into(),
+        max: false,
+        fee: Some(WithdrawFee::UtxoPerKbyte {
+            amount: "0.1".parse().unwrap(),
+        }),
+        outputs: Vec::new(),
+        memo: None,
+        inputs: Vec::new(),
+        coin_selection: None,
+        psbt: false,
+    };
+    coin.withdraw(withdraw_req).wait().unwrap_err();
+}
+
+#[test]
+#[cfg(not(target_arch = "wasm32"))]
+fn test_withdraw_impl_sat_per_kb_fee_max() {
+    UtxoStandardCoin::ordered_mature_unspents.mock_safe(|coin, _| {
+        let cache = block_on(coin.as_ref().recently_spent_outpoints.lock());
+        let unspents = vec![UnspentInfo {
+            outpoint: OutPoint {
+                hash: 1.into(),
+                index: 0,
+            },
+            value: 1000000000,
+            height: Default::default(),
+        }];
+        MockResult::Return(Box::pin(futures::future::ok((unspents, cache))))
+    });
+
+    let client = NativeClient(Arc::new(NativeClientImpl::default()));
+
+    let coin = utxo_coin_for_test(UtxoRpcClientEnum::Native(client), None, false);
+
+    let withdraw_req = WithdrawRequest {
+        amount: 0.into(),
+        to: "RQq6fWoy8aGGMLjvRfMY5mBNVm2RQxJyLa".to_string(),
+        coin: TEST_COIN_NAME.into(),
+        max: true,
+        fee: Some(WithdrawFee::UtxoPerKbyte {
+            amount: "0.1".parse().unwrap(),
+        }),
+        outputs: Vec::new(),
+        memo: None,
+        inputs: Vec::new(),
+        coin_selection: None,
+        psbt: false,
+    };
+    // The resulting transaction size might be 210 or 211 bytes depending on signature size
+    // MM2 always expects the worst case during fee calculation
+    // 0.1 * 211 / 1000 = 0.0211
+    let expected = Some(
+        UtxoFeeDetails {
+            amount: "0.0211".parse().unwrap(),
+        }
+        .into(),
+    );
+    let tx_details = coin.withdraw(withdraw_req).wait().unwrap();
+    assert_eq!(expected, tx_details.fee_details);
+}
+
+#[test]
+#[cfg(not(target_arch = "wasm32"))]
+fn test_withdraw_batch_with_max_and_memo() {
+    UtxoStandardCoin::ordered_mature_unspents.mock_safe(|coin, _| {
+        let cache = block_on(coin.as_ref().recently_spent_outpoints.lock());
+        let unspents = vec![UnspentInfo {
+            outpoint: OutPoint {
+                hash: 1.into(),
+                index: 0,
+            },
+            value: 1000000000,
+            height: Default::default(),
+        }];
+        MockResult::Return(Box::pin(futures::future::ok((unspents, cache))))
+    });
+
+    let client = NativeClient(Arc::new(NativeClientImpl::default()));
+
+    let coin = utxo_coin_for_test(UtxoRpcClientEnum::Native(client), None, false);
+
+    let withdraw_req = WithdrawRequest {
+        amount: 0.into(),
+        to: String::new(),
+        coin: TEST_COIN_NAME.into(),
+        max: false,
+        fee: Some(WithdrawFee::UtxoFixed {
+            amount: "0.1".parse().unwrap(),
+        }),
+        outputs: vec![
+            WithdrawOutput {
+                to: "RQq6fWoy8aGGMLjvRfMY5mBNVm2RQxJyLa".to_string(),
+                amount: 1.into(),
+                max: false,
+            },
+            WithdrawOutput {
+                to: "RT9MpMyucqXiX8bZLimXBnrrn2ofmdGNKd".to_string(),
+                amount: 0.into(),
+                max: true,
+            },
+        ],
+        memo: Some("payout #1".into()),
+        inputs: Vec::new(),
+        coin_selection: None,
+        psbt: false,
+    };
+    let tx_details = coin.withdraw(withdraw_req).wait().unwrap();
+    assert_eq!(tx_details.to, vec![
+        "RQq6fWoy8aGGMLjvRfMY5mBNVm2RQxJyLa".to_string(),
+        "RT9MpMyucqXiX8bZLimXBnrrn2ofmdGNKd".to_string()
+    ]);
+
+    let transaction: UtxoTx = deserialize(tx_details.tx_hex.as_slice()).unwrap();
+    assert_eq!(transaction.outputs.len(), 3);
+    assert_eq!(transaction.outputs[0].value, 100000000);
+    // the fee is deducted from the `max` output
+    assert_eq!(transaction.outputs[1].value, 1000000000 - 100000000 - 10000000);
+    let expected_memo = Builder::default()
+        .push_opcode(Opcode::OP_RETURN)
+        .push_data(b"payout #1")
+        .into_bytes();
+    assert_eq!(transaction.outputs[2].script_pubkey, expected_memo);
+    assert_eq!(transaction.outputs[2].value, 0);
+}
+
+#[test]
+fn test_withdraw_batch_several_max_outputs() {
+    let client = NativeClient(Arc::new(NativeClientImpl::default()));
+    let coin = utxo_coin_for_test(UtxoRpcClientEnum::Native(client), None, false);
+
+    let max_output = WithdrawOutput {
+        to: "RQq6fWoy8aGGMLjvRfMY5mBNVm2RQxJyLa".to_string(),
+        amount: 0.into(),
+        max: true,
+    };
+    let withdraw_req = WithdrawRequest {
+        amount: 0.into(),
+        to: String::new(),
+        coin: TEST_COIN_NAME.into(),
+        max: false,
+        fee: None,
+        outputs: vec![max_output.clone(), max_output],
+        memo: None,
+        inputs: Vec::new(),
+        coin_selection: None,
+        psbt: false,
+    };
+    match coin.withdraw(withdraw_req).wait().unwrap_err().into_inner() {
+        WithdrawError::InvalidOutputs(_) => (),
+        e => panic!("Expected WithdrawError::InvalidOutputs, found {:?}", e),
+    }
+}
+
+#[test]
+#[cfg(not(target_arch = "wasm32"))]
+fn test_withdraw_explicit_inputs() {
+    UtxoStandardCoin::ordered_mature_unspents.mock_safe(|coin, _| {
+        let cache = block_on(coin.as_ref().recently_spent_outpoints.lock());
+        let unspents = vec![
+            UnspentInfo {
+                outpoint: OutPoint {
+                    hash: 1.into(),
+                    index: 0,
+                },
+                value: 1000000000,
+                height: Default::default(),
+            },
+            UnspentInfo {
+                outpoint: OutPoint {
+                    hash: 2.into(),
+                    index: 1,
+                },
+                value: 2000000000,
+                height: Default::default(),
+            },
+        ];
+        MockResult::Return(Box::pin(futures::future::ok((unspents, cache))))
+    });
+
+    let client = NativeClient(Arc::new(NativeClientImpl::default()));
+    let coin = utxo_coin_for_test(UtxoRpcClientEnum::Native(client), None, false);
+
+    let withdraw_req = |inputs| WithdrawRequest {
+        amount: 1.into(),
+        to: "RQq6fWoy8aGGMLjvRfMY5mBNVm2RQxJyLa".to_string(),
+        coin: TEST_COIN_NAME.into(),
+        max: false,
+        fee: Some(WithdrawFee::UtxoFixed {
+            amount: "0.1".parse().unwrap(),
+        }),
+        outputs: Vec::new(),
+        memo: None,
+        inputs,
+        coin_selection: None,
+        psbt: false,
+    };
+    let input = TxOutPoint::from(OutPoint {
+        hash: 2.into(),
+        index: 1,
+    });
+    let tx_details = coin.withdraw(withdraw_req(vec![input.clone()])).wait().unwrap();
+    let transaction: UtxoTx = deserialize(tx_details.tx_hex.as_slice()).unwrap();
+    assert_eq!(transaction.inputs.len(), 1);
+    assert_eq!(transaction.inputs[0].previous_output, OutPoint {
+        hash: 2.into(),
+        index: 1,
+    });
+
+    let unknown_input = TxOutPoint::from(OutPoint {
+        hash: 3.into(),
+        index: 0,
+    });
+    match coin
+        .withdraw(withdraw_req(vec![unknown_input]))
+        .wait()
+        .unwrap_err()
+        .into_inner()
+    {
+        WithdrawError::InvalidInputs(_) => (),
+        e => panic!("Expected WithdrawError::InvalidInputs, found {:?}", e),
+    }
+
+    // every listed input is spent even if one of them covers the amount
+    let other_input = TxOutPoint::from(OutPoint {
+        hash: 1.into(),
+        index: 0,
+    });
+    let tx_details = coin
+        .withdraw(withdraw_req(vec![other_input, input.clone()]))
+        .wait()
+        .unwrap();
+    let transaction: UtxoTx = deserialize(tx_details.tx_hex.as_slice()).unwrap();
+    assert_eq!(transaction.inputs.len(), 2);
+
+    match coin
+        .withdraw(withdraw_req(vec![input.clone(), input.clone()]))
+        .wait()
+        .unwrap_err()
+        .into_inner()
+    {
+        WithdrawError::InvalidInputs(_) => (),
+        e => panic!("Expected WithdrawError::InvalidInputs, found {:?}", e),
+    }
+
+    block_on(coin.as_ref().frozen_outpoints.lock())
+        .freeze(vec![OutPoint::from(input.clone())])
+        .unwrap();
+    match coin
+        .withdraw(withdraw_req(vec![input]))
+        .wait()
+        .unwrap_err()
+        .into_inner()
+    {
+        WithdrawError::InvalidInputs(_) => (),
+        e => panic!("Expected WithdrawError::InvalidInputs, found {:?}", e),
+    }
+}
+
+#[test]
+fn test_generate_transaction_skips_frozen_outpoints() {
+    let client = NativeClient(Arc::new(NativeClientImpl::default()));
+    let coin = utxo_coin_for_test(UtxoRpcClientEnum::Native(client), None, false);
+
+    let frozen = OutPoint {
+        hash: 1.into(),
+        index: 0,
+    };
+    block_on(coin.as_ref().frozen_outpoints.lock())
+        .freeze(vec![frozen.clone()])
+        .unwrap();
+
+    let unspents = vec![
+        UnspentInfo {
+            outpoint: frozen,
+            value: 1000000,
+            height: Default::default(),
+        },
+        UnspentInfo {
+            outpoint: OutPoint {
+                hash: 2.into(),
+                index: 0,
+            },
+            value: 100000,
+            height: Default::default(),
+        },
+    ];
+    let outputs = vec![TransactionOutput {
+        script_pubkey: vec![].into(),
+        value: 50000,
+    }];
+    let fee = Some(ActualTxFee::FixedPerKb(1000));
+    let (unsigned, data) =
+        block_on(coin.generate_transaction(unspents.clone(), outputs.clone(), FeePolicy::SendExact, fee, None))
+            .unwrap();
+    assert_eq!(unsigned.inputs.len(), 1);
+    assert_eq!(unsigned.inputs[0].previous_output, unspents[1].outpoint);
+    assert_eq!(data.spent_by_me, 100000);
+
+    // the frozen output is not spent even if the rest is not enough
+    let outputs = vec![TransactionOutput {
+        script_pubkey: vec![].into(),
+        value: 500000,
+    }];
+    let fee = Some(ActualTxFee::FixedPerKb(1000));
+    block_on(coin.generate_transaction(unspents, outputs, FeePolicy::SendExact, fee, None)).unwrap_err();
+}
+
+fn unspent_for_test(hash: u8, value: u64) -> UnspentInfo {
+    UnspentInfo {
+        outpoint: OutPoint {
+            hash: hash.into(),
+            index: 0,
+        },
+        value,
+        height: Default::default(),
+    }
+}
+
+#[test]
+fn test_coin_selection_largest_first() {
+    let client = NativeClient(Arc::new(NativeClientImpl::default()));
+    let coin = utxo_coin_for_test(UtxoRpcClientEnum::Native(client), None, false);
+
+    let unspents = vec![
+        unspent_for_test(1, 10000),
+        unspent_for_test(2, 20000),
+        unspent_for_test(3, 100000),
+    ];
+    let outputs = vec![TransactionOutput {
+        script_pubkey: vec![].into(),
+        value: 50000,
+    }];
+    let fee = Some(ActualTxFee::Dynamic(1000));
+    let (unsigned, _) = block_on(generate_transaction_with_coin_selection(
+        &coin,
+        Vec::new(),
+        unspents.clone(),
+        outputs.clone(),
+        FeePolicy::SendExact,
+        fee.clone(),
+        None,
+        CoinSelectionStrategy::SmallestFirst,
+    ))
+    .unwrap();
+    assert_eq!(unsigned.inputs.len(), 3);
+
+    let (unsigned, _) = block_on(generate_transaction_with_coin_selection(
+        &coin,
+        Vec::new(),
+        unspents,
+        outputs,
+        FeePolicy::SendExact,
+        fee,
+        None,
+        CoinSelectionStrategy::LargestFirst,
+    ))
+    .unwrap();
+    assert_eq!(unsigned.inputs.len(), 1);
+    assert_eq!(unsigned.inputs[0].amount, 100000);
+}
+
+#[test]
+fn test_coin_selection_branch_and_bound_avoids_change() {
+    let client = NativeClient(Arc::new(NativeClientImpl::default()));
+    let coin = utxo_coin_for_test(UtxoRpcClientEnum::Native(client), None, false);
+
+    let unspents = vec![
+        unspent_for_test(1, 30000),
+        unspent_for_test(2, 50000),
+        unspent_for_test(3, 70000),
+    ];
+    // 30000 + 70000 minus the fee of two inputs and the rest of the transaction leaves less than dust
+    let outputs = vec![TransactionOutput {
+        script_pubkey: vec![].into(),
+        value: 99304,
+    }];
+    let fee = Some(ActualTxFee::Dynamic(1000));
+    let (unsigned, data) = block_on(generate_transaction_with_coin_selection(
+        &coin,
+        Vec::new(),
+        unspents.clone(),
+        outputs.clone(),
+        FeePolicy::SendExact,
+        fee.clone(),
+        None,
+        CoinSelectionStrategy::SmallestFirst,
+    ))
+    .unwrap();
+    assert_eq!(unsigned.inputs.len(), 3);
+    assert_eq!(unsigned.outputs.len(), 2);
+
+    let (unsigned, data_bnb) = block_on(generate_transaction_with_coin_selection(
+        &coin,
+        Vec::new(),
+        unspents,
+        outputs,
+        FeePolicy::SendExact,
+        fee,
+        None,
+        CoinSelectionStrategy::BranchAndBound,
+    ))
+    .unwrap();
+    let mut spent: Vec<u64> = unsigned.inputs.iter().map(|input| input.amount).collect();
+    spent.sort_unstable();
+    assert_eq!(spent, vec![30000, 70000]);
+    assert_eq!(unsigned.outputs.len(), 1);
+    assert!(data_bnb.unused_change.is_some());
+    assert!(data_bnb.spent_by_me < data.spent_by_me);
+}
+
+#[test]
+fn test_coin_selection_consolidate() {
+    let client = NativeClient(Arc::new(NativeClientImpl::default()));
+    let coin = utxo_coin_for_test(UtxoRpcClientEnum::Native(client), None, false);
+
+    // the 100 satoshi unspent is not worth to be spent
+    let unspents = vec![
+        unspent_for_test(1, 100000),
+        unspent_for_test(2, 100),
+        unspent_for_test(3, 5000),
+        unspent_for_test(4, 6000),
+    ];
+    let outputs = vec![TransactionOutput {
+        script_pubkey: vec![].into(),
+        value: 50000,
+    }];
+    let fee = Some(ActualTxFee::Dynamic(1000));
+    let (unsigned, _) = block_on(generate_transaction_with_coin_selection(
+        &coin,
+        Vec::new(),
+        unspents,
+        outputs,
+        FeePolicy::SendExact,
+        fee,
+        None,
+        CoinSelectionStrategy::Consolidate,
+    ))
+    .unwrap();
+    let spent: Vec<u64> = unsigned.inputs.iter().map(|input| input.amount).collect();
+    assert_eq!(spent, vec![5000, 6000, 100000]);
+    // the change output
+    assert_eq!(unsigned.outputs.len(), 2);
+}
+
+#[test]
+fn test_coin_selection_must_spend() {
+    let client = NativeClient(Arc::new(NativeClientImpl::default()));
+    let coin = utxo_coin_for_test(UtxoRpcClientEnum::Native(client), None, false);
+
+    // e.g. the SLP-bearing dust outputs, one of them is frozen
+    let must_spend = vec![unspent_for_test(1, 546), unspent_for_test(2, 546)];
+    block_on(coin.as_ref().frozen_outpoints.lock())
+        .freeze(vec![must_spend[1].outpoint.clone()])
+        .unwrap();
+    let unspents = vec![
+        unspent_for_test(3, 10000),
+        unspent_for_test(4, 100000),
+        // the duplicate of the forced unspent is not spent twice
+        unspent_for_test(1, 546),
+    ];
+    let outputs = vec![TransactionOutput {
+        script_pubkey: vec![].into(),
+        value: 50000,
+    }];
+    let fee = Some(ActualTxFee::Dynamic(1000));
+    let (unsigned, _) = block_on(generate_transaction_with_coin_selection(
+        &coin,
+        must_spend.clone(),
+        unspents,
+        outputs,
+        FeePolicy::SendExact,
+        fee,
+        None,
+        CoinSelectionStrategy::LargestFirst,
+    ))
+    .unwrap();
+    let spent: Vec<_> = unsigned
+        .inputs
+        .iter()
+        .map(|input| input.previous_output.clone())
+        .collect();
+    let expected = vec![
+        must_spend[0].outpoint.clone(),
+        must_spend[1].outpoint.clone(),
+        unspent_for_test(4, 100000).outpoint,
+    ];
+    assert_eq!(spent, expected);
+}
+
+#[test]
+fn test_consolidate_utxos_preview() {
+    use crate::utxo::consolidation::{consolidate_utxo_coin_unspents, ConsolidateUtxosError};
+
+    UtxoStandardCoin::ordered_mature_unspents.mock_safe(|coin, _| {
+        let cache = block_on(coin.as_ref().recently_spent_outpoints.lock());
+        let unspents = vec![
+            unspent_for_test(1, 10000),
+            unspent_for_test(2, 20000),
+            unspent_for_test(3, 1000000),
+        ];
+        MockResult::Return(Box::pin(futures::future::ok((unspents, cache))))
+    });
+
+    let client = NativeClient(Arc::new(NativeClientImpl::default()));
+    let coin = utxo_coin_for_test(UtxoRpcClientEnum::Native(client), None, false);
+
+    // the coin fee is fixed 1000 satoshis per kbyte
+    let req = json::from_value(json!({
+        "coin": TEST_COIN_NAME,
+        "max_inputs": 2,
+        "max_fee_per_kbyte": "0.00001",
+    }))
+    .unwrap();
+    let preview = block_on(consolidate_utxo_coin_unspents(&coin, req)).unwrap();
+    assert_eq!(preview.inputs_count, 2);
+    assert_eq!(preview.spent_amount, "0.0003".parse().unwrap());
+    assert_eq!(preview.fee, "0.00001".parse().unwrap());
+    assert_eq!(preview.received_amount, "0.00029".parse().unwrap());
+    assert!(preview.tx_hash.is_none());
+
+    let req = json::from_value(json!({
+        "coin": TEST_COIN_NAME,
+        "max_inputs": 2,
+        "max_fee_per_kbyte": "0.000009",
+    }))
+    .unwrap();
+    match block_on(consolidate_utxo_coin_unspents(&coin, req))
+        .unwrap_err()
+        .into_inner()
+    {
+        ConsolidateUtxosError::FeeRateTooHigh { .. } => (),
+        e => panic!("Expected ConsolidateUtxosError::FeeRateTooHigh, found {:?}", e),
+    }
+}
+
+#[test]
+fn test_consolidate_utxos_broadcast_pays_checked_fee() {
+    use crate::utxo::consolidation::consolidate_utxo_coin_unspents;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static GET_TX_FEE_CALLED: AtomicUsize = AtomicUsize::new(0);
+
+    UtxoStandardCoin::ordered_mature_unspents.mock_safe(|coin, _| {
+        let cache = block_on(coin.as_ref().recently_spent_outpoints.lock());
+        let unspents = vec![unspent_for_test(1, 10000), unspent_for_test(2, 20000)];
+        MockResult::Return(Box::pin(futures::future::ok((unspents, cache))))
+    });
+    // the fee rate rises after the ceiling check, but the checked one has to be paid
+    UtxoStandardCoin::get_tx_fee.mock_safe(|_| {
+        let fee = match GET_TX_FEE_CALLED.fetch_add(1, Ordering::Relaxed) {
+            0 => 1000,
+            _ => 100000,
+        };
+        MockResult::Return(Box::pin(futures::future::ok(ActualTxFee::FixedPerKb(fee))))
+    });
+    NativeClient::send_transaction
+        .mock_safe(|_, tx| MockResult::Return(Box::new(futures01::future::ok(tx.hash().reversed().into()))));
+
+    let client = NativeClient(Arc::new(NativeClientImpl::default()));
+    let coin = utxo_coin_for_test(UtxoRpcClientEnum::Native(client), None, false);
+
+    let req = json::from_value(json!({
+        "coin": TEST_COIN_NAME,
+        "max_inputs": 2,
+        "max_fee_per_kbyte": "0.00001",
+        "broadcast": true,
+    }))
+    .unwrap();
+    let res = block_on(consolidate_utxo_coin_unspents(&coin, req)).unwrap();
+    assert_eq!(res.inputs_count, 2);
+    assert_eq!(res.fee, "0.00001".parse().unwrap());
+    assert_eq!(res.received_amount, "0.00029".parse().unwrap());
+    assert!(res.tx_hash.is_some());
+    assert!(res.tx_hex.is_some());
+}
+
+#[test]
+fn test_withdraw_psbt_sign_and_finalize() {
+    use crate::utxo::psbt::sign_utxo_psbt;
+    use script::Psbt;
+
+    let client = NativeClient(Arc::new(NativeClientImpl::default()));
+    let coin = utxo_coin_for_test(UtxoRpcClientEnum::Native(client), None, false);
+
+    let prev_tx = UtxoTx {
+        version: 1,
+        inputs: vec![TransactionInput::coinbase(vec![1, 2, 3].into())],
+        outputs: vec![TransactionOutput {
+            value: 1000000000,
+            script_pubkey: output_script(&coin.as_ref().my_address, ScriptType::P2PKH).to_bytes(),
+        }],
+        ..Default::default()
+    };
+    let prev_tx_hash = prev_tx.hash();
+    let prev_tx_bytes: BytesJson = serialize(&prev_tx).into();
+    let expected_txid: H256Json = prev_tx_hash.reversed().into();
+
+    UtxoStandardCoin::ordered_mature_unspents.mock_safe(move |coin, _| {
+        let cache = block_on(coin.as_ref().recently_spent_outpoints.lock());
+        let unspents = vec![UnspentInfo {
+            outpoint: OutPoint {
+                hash: prev_tx_hash.clone(),
+                index: 0,
+            },
+            value: 1000000000,
+            height: Default::default(),
+        }];
+        MockResult::Return(Box::pin(futures::future::ok((unspents, cache))))
+    });
+    NativeClient::get_transaction_bytes.mock_safe(move |_, txid| {
+        assert_eq!(txid, expected_txid);
+        MockResult::Return(Box::new(futures01::future::ok(prev_tx_bytes.clone())))
+    });
+
+    let withdraw_req = |psbt| WithdrawRequest {
+        amount: 1.into(),
+        to: "RQq6fWoy8aGGMLjvRfMY5mBNVm2RQxJyLa".to_string(),
+        coin: TEST_COIN_NAME.into(),
+        max: false,
+        fee: Some(WithdrawFee::UtxoFixed {
+            amount: "0.1".parse().unwrap(),
+        }),
+        outputs: Vec::new(),
+        memo: None,
+        inputs: Vec::new(),
+        coin_selection: None,
+        psbt,
+    };
+    let signed_details = coin.withdraw(withdraw_req(false)).wait().unwrap();
+    assert!(signed_details.psbt.is_none());
+
+    let psbt_details = coin.withdraw(withdraw_req(true)).wait().unwrap();
+    let mut psbt = Psbt::from_bytes(&psbt_details.psbt.unwrap()).unwrap();
+    assert_eq!(psbt_details.tx_hex, BytesJson::from(serialize(&psbt.unsigned_tx)));
+    assert_eq!(psbt.inputs[0].non_witness_utxo, Some(prev_tx));
+    assert_eq!(psbt.inputs[0].sighash_type, Some(1));
+
+    let signed_inputs = sign_utxo_psbt(coin.as_ref(), &mut psbt).unwrap();
+    assert_eq!(signed_inputs, 1);
+    psbt.finalize().unwrap();
+    let finalized = psbt.extract_tx().unwrap();
+    // the signatures are deterministic, so the finalized PSBT matches the transaction signed by the withdraw
+    assert_eq!(signed_details.tx_hex, BytesJson::from(serialize(&finalized)));
+}
+
+#[test]
+fn test_sign_psbt_prev_tx_of_coin_hash_algo() {
+    use crate::utxo::psbt::sign_utxo_psbt;
+    use chain::TxHashAlgo;
+    use script::Psbt;
+
+    let client = NativeClient(Arc::new(NativeClientImpl::default()));
+    let mut fields = utxo_coin_fields_for_test(UtxoRpcClientEnum::Native(client), None, false);
+    fields.tx_hash_algo = TxHashAlgo::SHA256;
+
+    let prev_tx = UtxoTx {
+        version: 1,
+        inputs: vec![TransactionInput::coinbase(vec![1, 2, 3].into())],
+        outputs: vec![TransactionOutput {
+            value: 1000000000,
+            script_pubkey: output_script(&fields.my_address, ScriptType::P2PKH).to_bytes(),
+        }],
+        tx_hash_algo: TxHashAlgo::SHA256,
+        ..Default::default()
+    };
+    let unsigned = UtxoTx {
+        version: 1,
+        inputs: vec![TransactionInput {
+            previous_output: OutPoint {
+                hash: prev_tx.hash(),
+                index: 0,
+            },
+            script_sig: Default::default(),
+            sequence: SEQUENCE_FINAL,
+            script_witness: Vec::new(),
+        }],
+        outputs: vec![TransactionOutput {
+            value: 900000000,
+            script_pubkey: output_script(&fields.my_address, ScriptType::P2PKH).to_bytes(),
+        }],
+        ..Default::default()
+    };
+    let mut psbt = Psbt::from_unsigned_tx(unsigned).unwrap();
+    psbt.inputs[0].non_witness_utxo = Some(prev_tx);
+    // the prev tx is deserialized with the default hash algo
+    let mut psbt = Psbt::from_bytes(&psbt.to_bytes()).unwrap();
+
+    let signed_inputs = sign_utxo_psbt(&fields, &mut psbt).unwrap();
+    assert_eq!(signed_inputs, 1);
+    psbt.finalize().unwrap();
+}
+
+#[test]
+#[cfg(not(target_arch = "wasm32"))]
+fn test_withdraw_hd_account_spends_several_addresses() {
+    use crate::hd_wallet::Bip44Chain;
+    use crate::utxo::rpc_clients::ReceivedByAddressItem;
+    use crate::utxo::utxo_hd_wallet::UtxoHDAccount;
+    use common::privkey::hd_master_key_from_mnemonic;
+
+    let client = NativeClient(Arc::new(NativeClientImpl::default()));
+    let mut fields = utxo_coin_fields_for_test(UtxoRpcClientEnum::Native(client), None, false);
+
+    let master_key = hd_master_key_from_mnemonic(
+        "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about",
+    )
+    .unwrap();
+    let hd_account = UtxoHDAccount::new(
+        &master_key,
+        &"m/44'/141'".parse().unwrap(),
+        0,
+        3,
+        UtxoAddressFormat::Standard,
+    )
+    .unwrap();
+    // derive the first two external addresses to mark them as used
+    hd_account
+        .extend_addresses(&fields.conf, Bip44Chain::External, 1)
+        .unwrap();
+    let used_addresses: Vec<_> = hd_account.addresses(Bip44Chain::External);
+    let used_addresses_str: Vec<String> = used_addresses.iter().map(|hd| hd.address.to_string()).collect();
+    NativeClientImpl::list_received_by_address.mock_safe(move |_, _, _, _| {
+        let received = used_addresses_str
+            .iter()
+            .map(|address| ReceivedByAddressItem {
+                account: String::new(),
+                address: address.clone(),
+                txids: vec![H256Json::default()],
+            })
+            .collect();
+        MockResult::Return(Box::new(futures01::future::ok(received)))
+    });
+    // the addresses unknown to the node are imported before checking their transactions
+    NativeClientImpl::import_address.mock_safe(|_, _, _, _| MockResult::Return(Box::new(futures01::future::ok(()))));
+
+    block_on(hd_account.discover_addresses(&fields.conf, &fields.rpc_client)).unwrap();
+    assert_eq!(hd_account.addresses(Bip44Chain::External).len(), 2);
+    assert!(hd_account.addresses(Bip44Chain::Internal).is_empty());
+
+    fields.key_pair = hd_account.key_pair(&fields.conf, &used_addresses[0]).unwrap().into();
+    fields.my_address = used_addresses[0].address.clone();
+    fields.hd_account = Some(hd_account);
+    let coin = utxo_coin_from_fields(fields);
+
+    // the fee preview doesn't remember the change address
+    let outputs = vec![TransactionOutput {
+        value: 50000000,
+        script_pubkey: output_script(&used_addresses[1].address, ScriptType::P2PKH).to_bytes(),
+    }];
+    let (_, data) = block_on(generate_transaction(
+        &coin,
+        vec![unspent_for_test(1, 100000000)],
+        outputs,
+        FeePolicy::SendExact,
+        None,
+        None,
+    ))
+    .unwrap();
+    assert!(data.change_address.is_some());
+    let hd_account = coin.as_ref().hd_account.as_ref().unwrap();
+    assert!(hd_account.addresses(Bip44Chain::Internal).is_empty());
+
+    let address_hashes: Vec<_> = used_addresses.iter().map(|hd| hd.address.hash.clone()).collect();
+    UtxoStandardCoin::ordered_mature_unspents.mock_safe(move |coin, address| {
+        let cache = block_on(coin.as_ref().recently_spent_outpoints.lock());
+        let index = address_hashes.iter().position(|hash| *hash == address.hash).unwrap();
+        let unspents = vec![UnspentInfo {
+            outpoint: OutPoint {
+                hash: (index as u8 + 1).into(),
+                index: 0,
+            },
+            value: 100000000,
+            height: Default::default(),
+        }];
+        MockResult::Return(Box::pin(futures::future::ok((unspents, cache))))
+    });
+
+    let withdraw_req = WithdrawRequest {
+        amount: "1.5".parse().unwrap(),
+        to: "RQq6fWoy8aGGMLjvRfMY5mBNVm2RQxJyLa".to_string(),
+        coin: TEST_COIN_NAME.into(),
+        max: false,
+        fee: Some(WithdrawFee::UtxoFixed {
+            amount: "0.1".parse().unwrap(),
+        }),
+        outputs: Vec::new(),
+        memo: None,
+        inputs: Vec::new(),
+        coin_selection: None,
+        psbt: false,
+    };
+    let tx_details = coin.withdraw(withdraw_req).wait().unwrap();
+    let expected_from: Vec<String> = used_addresses.iter().map(|hd| hd.address.to_string()).collect();
+    assert_eq!(tx_details.from, expected_from);
+
+    let tx: UtxoTx = deserialize(tx_details.tx_hex.as_slice()).unwrap();
+    assert_eq!(tx.inputs.len(), 2);
+    for (input, owner) in tx.inputs.iter().zip(used_addresses.iter()) {
+        assert!(input.script_sig.ends_with(&owner.pubkey.to_vec()));
+    }
+
+    // the change goes to the first address of the internal chain that is remembered by the withdraw
+    let change_address = hd_account.addresses(Bip44Chain::Internal).remove(0);
+    let change_script = output_script(&change_address.address, ScriptType::P2PKH).to_bytes();
+    assert_eq!(tx.outputs[1].script_pubkey, change_script);
+    assert_eq!(tx.outputs[1].value, 40000000);
+}
+
+#[test]
+#[cfg(not(target_arch = "wasm32"))]
+fn test_hd_account_native_rescans_on_discovery_only() {
+    use crate::hd_wallet::Bip44Chain;
+    use crate::utxo::rpc_clients::ReceivedByAddressItem;
+    use crate::utxo::utxo_hd_wallet::UtxoHDAccount;
+    use common::privkey::hd_master_key_from_mnemonic;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static LIST_RECEIVED_CALLED: AtomicUsize = AtomicUsize::new(0);
+    static IMPORTED: AtomicUsize = AtomicUsize::new(0);
+    static RESCANNED: AtomicUsize = AtomicUsize::new(0);
+
+    // no address has transactions
+    NativeClientImpl::list_received_by_address.mock_safe(|_, _, _, _| {
+        LIST_RECEIVED_CALLED.fetch_add(1, Ordering::Relaxed);
+        MockResult::Return(Box::new(futures01::future::ok(Vec::<ReceivedByAddressItem>::new())))
+    });
+    NativeClientImpl::import_address.mock_safe(|_, _, _, rescan| {
+        IMPORTED.fetch_add(1, Ordering::Relaxed);
+        if rescan {
+            RESCANNED.fetch_add(1, Ordering::Relaxed);
+        }
+        MockResult::Return(Box::new(futures01::future::ok(())))
+    });
+
+    let client = NativeClient(Arc::new(NativeClientImpl::default()));
+    let mut fields = utxo_coin_fields_for_test(UtxoRpcClientEnum::Native(client), None, false);
+    let master_key = hd_master_key_from_mnemonic(
+        "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about",
+    )
+    .unwrap();
+    let hd_account = UtxoHDAccount::new(
+        &master_key,
+        &"m/44'/141'".parse().unwrap(),
+        0,
+        3,
+        UtxoAddressFormat::Standard,
+    )
+    .unwrap();
+
+    // both chains are scanned by the same round, so the blockchain is rescanned once
+    block_on(hd_account.discover_addresses(&fields.conf, &fields.rpc_client)).unwrap();
+    assert_eq!(IMPORTED.load(Ordering::Relaxed), 6);
+    assert_eq!(RESCANNED.load(Ordering::Relaxed), 1);
+    assert_eq!(hd_account.addresses(Bip44Chain::External).len(), 1);
+
+    fields.hd_account = Some(hd_account);
+    let coin = utxo_coin_from_fields(fields);
+    let list_received_called = LIST_RECEIVED_CALLED.load(Ordering::Relaxed);
+    let outputs = vec![TransactionOutput {
+        value: 50000000,
+        script_pubkey: vec![].into(),
+    }];
+    for _ in 0..2 {
+        let (_, data) = block_on(generate_transaction(
+            &coin,
+            vec![unspent_for_test(1, 100000000)],
+            outputs.clone(),
+            FeePolicy::SendExact,
+            None,
+            None,
+        ))
+        .unwrap();
+        assert_eq!(data.change_address.unwrap().address_index, 0);
+    }
+    // the fresh change address is imported once without rescan and the wallet transactions aren't listed
+    assert_eq!(LIST_RECEIVED_CALLED.load(Ordering::Relaxed), list_received_called);
+    assert_eq!(IMPORTED.load(Ordering::Relaxed), 7);
+    assert_eq!(RESCANNED.load(Ordering::Relaxed), 1);
+}
+
+#[test]
+#[cfg(not(target_arch = "wasm32"))]
+fn test_hd_account_from_xpub_derives_same_addresses() {
+    use crate::hd_wallet::Bip44Chain;
+    use crate::utxo::utxo_hd_wallet::UtxoHDAccount;
+    use common::privkey::hd_master_key_from_mnemonic;
+
+    let client = NativeClient(Arc::new(NativeClientImpl::default()));
+    let fields = utxo_coin_fields_for_test(UtxoRpcClientEnum::Native(client), None, false);
+
+    let master_key = hd_master_key_from_mnemonic(
+        "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about",
+    )
+    .unwrap();
+    let coin_path = "m/44'/141'".parse().unwrap();
+    let hd_account = UtxoHDAccount::new(&master_key, &coin_path, 1, 20, UtxoAddressFormat::Standard).unwrap();
+    let account_xpub = master_key
+        .derive_path(&"m/44'/141'/1'".parse().unwrap())
+        .unwrap()
+        .extended_public_key()
+        .unwrap();
+    let watch_only_account =
+        UtxoHDAccount::from_xpub(account_xpub, &coin_path, 20, UtxoAddressFormat::Standard).unwrap();
+    assert_eq!(watch_only_account.account_id, 1);
+    assert_eq!(watch_only_account.derivation_path, hd_account.derivation_path);
+
+    for chain in [Bip44Chain::External, Bip44Chain::Internal].iter() {
+        hd_account.extend_addresses(&fields.conf, *chain, 2).unwrap();
+        watch_only_account.extend_addresses(&fields.conf, *chain, 2).unwrap();
+        let expected: Vec<_> = hd_account.addresses(*chain).into_iter().map(|hd| hd.address).collect();
+        let actual: Vec<_> = watch_only_account
+            .addresses(*chain)
+            .into_iter()
+            .map(|hd| hd.address)
+            .collect();
+        assert_eq!(actual, expected);
+    }
+
+    let first_address = watch_only_account.addresses(Bip44Chain::External).remove(0);
+    assert!(watch_only_account.key_pair(&fields.conf, &first_address).is_err());
+
+    // the xpub of the coin is not an account one
+    let coin_xpub = master_key
+        .derive_path(&coin_path)
+        .unwrap()
+        .extended_public_key()
+        .unwrap();
+    assert!(UtxoHDAccount::from_xpub(coin_xpub, &coin_path, 20, UtxoAddressFormat::Standard).is_err());
+}
+
+#[test]
+#[cfg(not(target_arch = "wasm32"))]
+fn test_watch_only_coin_fails_to_sign() {
+    use crate::watch_only::WatchOnlyError;
+
     UtxoStandardCoin::ordered_mature_unspents.mock_safe(|coin, _| {
         let cache = block_on(coin.as_ref().recently_spent_outpoints.lock());
         let unspents = vec![UnspentInfo {
@@ -647,27 +1696,7 @@ fn test_withdraw_impl_sat_per_kb_fee_amount_over_max() {
         }];
         MockResult::Return(Box::pin(futures::future::ok((unspents, cache))))
     });
-
-    let client = NativeClient(Arc::new(NativeClientImpl::default()));
-
-    let coin = utxo_coin_for_test(UtxoRpcClientEnum::Native(client), None, false);
-
-    let withdraw_req = WithdrawRequest {
-        amount: "9.97939455".parse().unwrap(),
-        to: "RQq6fWoy8aGGMLjvRfMY5mBNVm2RQxJyLa".to_string(),
-        coin: TEST_COIN_NAME.into(),
-        max: false,
-        fee: Some(WithdrawFee::UtxoPerKbyte {
-            amount: "0.1".parse().unwrap(),
-        }),
-    };
-    coin.withdraw(withdraw_req).wait().unwrap_err();
-}
-
-#[test]
-#[cfg(not(target_arch = "wasm32"))]
-fn test_withdraw_impl_sat_per_kb_fee_max() {
-    UtxoStandardCoin::ordered_mature_unspents.mock_safe(|coin, _| {
+    UtxoStandardCoin::list_unspent_ordered.mock_safe(|coin, _| {
         let cache = block_on(coin.as_ref().recently_spent_outpoints.lock());
         let unspents = vec![UnspentInfo {
             outpoint: OutPoint {
@@ -681,29 +1710,35 @@ fn test_withdraw_impl_sat_per_kb_fee_max() {
     });
 
     let client = NativeClient(Arc::new(NativeClientImpl::default()));
-
-    let coin = utxo_coin_for_test(UtxoRpcClientEnum::Native(client), None, false);
+    let mut fields = utxo_coin_fields_for_test(UtxoRpcClientEnum::Native(client), None, false);
+    let public = Public::from_slice(&**fields.key_pair.public()).unwrap();
+    fields.key_pair = UtxoKeyPair::WatchOnly(public);
+    let coin = utxo_coin_from_fields(fields);
 
     let withdraw_req = WithdrawRequest {
-        amount: 0.into(),
+        amount: 1.into(),
         to: "RQq6fWoy8aGGMLjvRfMY5mBNVm2RQxJyLa".to_string(),
         coin: TEST_COIN_NAME.into(),
-        max: true,
-        fee: Some(WithdrawFee::UtxoPerKbyte {
+        max: false,
+        fee: Some(WithdrawFee::UtxoFixed {
             amount: "0.1".parse().unwrap(),
         }),
+        outputs: Vec::new(),
+        memo: None,
+        inputs: Vec::new(),
+        coin_selection: None,
+        psbt: false,
     };
-    // The resulting transaction size might be 210 or 211 bytes depending on signature size
-    // MM2 always expects the worst case during fee calculation
-    // 0.1 * 211 / 1000 = 0.0211
-    let expected = Some(
-        UtxoFeeDetails {
-            amount: "0.0211".parse().unwrap(),
-        }
-        .into(),
-    );
-    let tx_details = coin.withdraw(withdraw_req).wait().unwrap();
-    assert_eq!(expected, tx_details.fee_details);
+    match coin.withdraw(withdraw_req).wait().unwrap_err().into_inner() {
+        WithdrawError::WatchOnly => (),
+        e => panic!("Expected WithdrawError::WatchOnly, found {:?}", e),
+    }
+
+    let error = coin
+        .send_taker_fee(&*DEX_FEE_ADDR_RAW_PUBKEY, 1.into())
+        .wait()
+        .unwrap_err();
+    assert_eq!(error, TransactionErr::WatchOnly(WatchOnlyError));
 }
 
 #[cfg(not(target_arch = "wasm32"))]
@@ -749,6 +1784,11 @@ fn test_withdraw_kmd_rewards_impl(
         coin: "KMD".to_owned(),
         max: false,
         fee: None,
+        outputs: Vec::new(),
+        memo: None,
+        inputs: Vec::new(),
+        coin_selection: None,
+        psbt: false,
     };
     let expected_fee = TxFeeDetails::Utxo(UtxoFeeDetails {
         amount: "0.00001".parse().unwrap(),
@@ -821,6 +1861,11 @@ fn test_withdraw_rick_rewards_none() {
         coin: "RICK".to_owned(),
         max: false,
         fee: None,
+        outputs: Vec::new(),
+        memo: None,
+        inputs: Vec::new(),
+        coin_selection: None,
+        psbt: false,
     };
     let expected_fee = TxFeeDetails::Utxo(UtxoFeeDetails {
         amount: "0.00001".parse().unwrap(),
@@ -1718,11 +2763,12 @@ fn test_ordered_mature_unspents_from_cache_impl(
     });
     ElectrumClient::get_block_count
         .mock_safe(move |_| MockResult::Return(Box::new(futures01::future::ok(block_count))));
-    UtxoStandardCoin::get_verbose_transaction_from_cache_or_rpc.mock_safe(move |_, txid| {
-        assert_eq!(txid, tx_hash);
-        MockResult::Return(Box::new(futures01::future::ok(VerboseTransactionFrom::Cache(
-            verbose.clone(),
-        ))))
+    UtxoStandardCoin::get_verbose_transactions_from_cache_or_rpc.mock_safe(move |_, txids| {
+        let expected: HashSet<H256Json> = std::iter::once(tx_hash.clone()).collect();
+        assert_eq!(txids, expected);
+        let mut verbose_txs = HashMap::new();
+        verbose_txs.insert(tx_hash.clone(), VerboseTransactionFrom::Cache(verbose.clone()));
+        MockResult::Return(Box::new(futures01::future::ok(verbose_txs)))
     });
     static mut IS_UNSPENT_MATURE_CALLED: bool = false;
     UtxoStandardCoin::is_unspent_mature.mock_safe(move |_, tx: &RpcTransaction| {
@@ -3001,6 +4047,7 @@ fn test_withdraw_to_p2pkh() {
         checksum_type: coin.as_ref().my_address.checksum_type,
         hrp: coin.as_ref().conf.bech32_hrp.clone(),
         addr_format: UtxoAddressFormat::Standard,
+        taproot_output_key: None,
     };
 
     let withdraw_req = WithdrawRequest {
@@ -3009,6 +4056,11 @@ fn test_withdraw_to_p2pkh() {
         coin: TEST_COIN_NAME.into(),
         max: false,
         fee: None,
+        outputs: Vec::new(),
+        memo: None,
+        inputs: Vec::new(),
+        coin_selection: None,
+        psbt: false,
     };
     let tx_details = coin.withdraw(withdraw_req).wait().unwrap();
     let transaction: UtxoTx = deserialize(tx_details.tx_hex.as_slice()).unwrap();
@@ -3047,6 +4099,7 @@ fn test_withdraw_to_p2sh() {
         checksum_type: coin.as_ref().my_address.checksum_type,
         hrp: coin.as_ref().conf.bech32_hrp.clone(),
         addr_format: UtxoAddressFormat::Standard,
+        taproot_output_key: None,
     };
 
     let withdraw_req = WithdrawRequest {
@@ -3055,6 +4108,11 @@ fn test_withdraw_to_p2sh() {
         coin: TEST_COIN_NAME.into(),
         max: false,
         fee: None,
+        outputs: Vec::new(),
+        memo: None,
+        inputs: Vec::new(),
+        coin_selection: None,
+        psbt: false,
     };
     let tx_details = coin.withdraw(withdraw_req).wait().unwrap();
     let transaction: UtxoTx = deserialize(tx_details.tx_hex.as_slice()).unwrap();
@@ -3093,6 +4151,7 @@ fn test_withdraw_to_p2wpkh() {
         checksum_type: coin.as_ref().my_address.checksum_type,
         hrp: coin.as_ref().conf.bech32_hrp.clone(),
         addr_format: UtxoAddressFormat::Segwit,
+        taproot_output_key: None,
     };
 
     let withdraw_req = WithdrawRequest {
@@ -3101,6 +4160,11 @@ fn test_withdraw_to_p2wpkh() {
         coin: TEST_COIN_NAME.into(),
         max: false,
         fee: None,
+        outputs: Vec::new(),
+        memo: None,
+        inputs: Vec::new(),
+        coin_selection: None,
+        psbt: false,
     };
     let tx_details = coin.withdraw(withdraw_req).wait().unwrap();
     let transaction: UtxoTx = deserialize(tx_details.tx_hex.as_slice()).unwrap();
@@ -3110,3 +4174,670 @@ fn test_withdraw_to_p2wpkh() {
 
     assert_eq!(output_script, expected_script);
 }
+
+#[test]
+#[cfg(not(target_arch = "wasm32"))]
+fn test_withdraw_to_p2tr() {
+    UtxoStandardCoin::ordered_mature_unspents.mock_safe(|coin, _| {
+        let cache = block_on(coin.as_ref().recently_spent_outpoints.lock());
+        let unspents = vec![UnspentInfo {
+            outpoint: OutPoint {
+                hash: 1.into(),
+                index: 0,
+            },
+            value: 1000000000,
+            height: Default::default(),
+        }];
+        MockResult::Return(Box::pin(futures::future::ok((unspents, cache))))
+    });
+
+    let client = NativeClient(Arc::new(NativeClientImpl::default()));
+
+    let coin = utxo_coin_for_test(UtxoRpcClientEnum::Native(client.clone()), None, true);
+
+    // Create a p2tr address for the test coin
+    let output_key: H256 = "a60869f0dbcf1dc659c9cecbaf8050135ea9e8cdc487053f1dc6880949dc684c".into();
+    let p2tr_address = SegwitAddress::new_p2tr(&output_key, coin.as_ref().conf.bech32_hrp.clone().unwrap()).to_string();
+
+    let validate_result = coin.validate_address(&p2tr_address);
+    assert!(validate_result.is_valid, "{:?}", validate_result.reason);
+
+    let withdraw_req = WithdrawRequest {
+        amount: 1.into(),
+        to: p2tr_address.clone(),
+        coin: TEST_COIN_NAME.into(),
+        max: false,
+        fee: None,
+        outputs: Vec::new(),
+        memo: None,
+        inputs: Vec::new(),
+        coin_selection: None,
+        psbt: false,
+    };
+    let tx_details = coin.withdraw(withdraw_req).wait().unwrap();
+    assert_eq!(tx_details.to, vec![p2tr_address]);
+    let transaction: UtxoTx = deserialize(tx_details.tx_hex.as_slice()).unwrap();
+    let output_script: Script = transaction.outputs[0].script_pubkey.clone().into();
+
+    let expected_script = Builder::build_p2tr(&output_key);
+
+    assert_eq!(output_script, expected_script);
+}
+
+#[test]
+fn test_convert_p2tr_address() {
+    let client = NativeClient(Arc::new(NativeClientImpl::default()));
+    let coin = utxo_coin_for_test(UtxoRpcClientEnum::Native(client), None, true);
+
+    let output_key: H256 = "a60869f0dbcf1dc659c9cecbaf8050135ea9e8cdc487053f1dc6880949dc684c".into();
+    let p2tr_address = SegwitAddress::new_p2tr(&output_key, coin.as_ref().conf.bech32_hrp.clone().unwrap()).to_string();
+
+    let converted = coin
+        .convert_to_address(&p2tr_address, json!({"format": "segwit"}))
+        .unwrap();
+    assert_eq!(converted, p2tr_address);
+
+    // the witness v1 output key can't be represented by the base58 or cashaddress formats
+    coin.convert_to_address(&p2tr_address, json!({"format": "standard"}))
+        .unwrap_err();
+    coin.convert_to_address(&p2tr_address, json!({"format": "cashaddress", "network": "bchtest"}))
+        .unwrap_err();
+}
+
+/// Returns the transaction funding `my_address` and the RBF-signalling transaction spending it.
+#[cfg(not(target_arch = "wasm32"))]
+fn rbf_txs_for_test(coin: &UtxoStandardCoin, sequence: u32) -> (UtxoTx, UtxoTx) {
+    let my_script = Builder::build_p2pkh(&coin.as_ref().my_address.hash).to_bytes();
+    let funding_tx = UtxoTx {
+        version: 1,
+        inputs: vec![TransactionInput {
+            previous_output: OutPoint {
+                hash: 1.into(),
+                index: 0,
+            },
+            script_sig: Bytes::default(),
+            sequence: SEQUENCE_FINAL,
+            script_witness: vec![],
+        }],
+        outputs: vec![TransactionOutput {
+            value: 200000000,
+            script_pubkey: my_script.clone(),
+        }],
+        ..Default::default()
+    };
+    let tx = UtxoTx {
+        version: 1,
+        inputs: vec![TransactionInput {
+            previous_output: OutPoint {
+                hash: funding_tx.hash(),
+                index: 0,
+            },
+            script_sig: Bytes::default(),
+            sequence,
+            script_witness: vec![],
+        }],
+        outputs: vec![
+            TransactionOutput {
+                value: 100000000,
+                script_pubkey: Builder::build_p2pkh(&Default::default()).to_bytes(),
+            },
+            TransactionOutput {
+                value: 99990000,
+                script_pubkey: my_script,
+            },
+        ],
+        ..Default::default()
+    };
+    (funding_tx, tx)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn mock_get_raw_transaction_bytes(txs: Vec<UtxoTx>) {
+    NativeClientImpl::get_raw_transaction_bytes.mock_safe(move |_, txid| {
+        let tx = txs
+            .iter()
+            .find(|tx| H256Json::from(tx.hash().reversed()) == txid)
+            .expect("Unexpected txid");
+        let bytes: BytesJson = serialize(tx).into();
+        MockResult::Return(Box::new(futures01::future::ok(bytes)))
+    });
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn mock_get_unconfirmed_verbose_transaction(tx: &UtxoTx) { mock_get_verbose_transaction(tx, 0) }
+
+#[cfg(not(target_arch = "wasm32"))]
+fn mock_get_verbose_transaction(tx: &UtxoTx, confirmations: u32) {
+    let hex: BytesJson = serialize(tx).into();
+    NativeClient::get_verbose_transaction.mock_safe(move |_, txid| {
+        let tx = RpcTransaction {
+            hex: hex.clone(),
+            txid,
+            hash: None,
+            size: Default::default(),
+            vsize: Default::default(),
+            version: 1,
+            locktime: 0,
+            vin: vec![],
+            vout: vec![],
+            blockhash: Default::default(),
+            confirmations,
+            rawconfirmations: None,
+            time: 0,
+            blocktime: 0,
+            height: None,
+        };
+        MockResult::Return(Box::new(futures01::future::ok(tx)))
+    });
+}
+
+#[test]
+#[cfg(not(target_arch = "wasm32"))]
+fn test_bump_fee() {
+    use crate::utxo::fee_bump::bump_utxo_tx_fee;
+
+    let coin = utxo_coin_for_test(UtxoRpcClientEnum::Native(native_client_for_test()), None, false);
+    let (funding_tx, tx) = rbf_txs_for_test(&coin, MAX_BIP125_RBF_SEQUENCE);
+    let tx_hash = tx.hash().reversed().into();
+    mock_get_raw_transaction_bytes(vec![funding_tx]);
+    mock_get_unconfirmed_verbose_transaction(&tx);
+    NativeClient::send_transaction
+        .mock_safe(|_, tx| MockResult::Return(Box::new(futures01::future::ok(tx.hash().reversed().into()))));
+
+    let funding_unspent = UnspentInfo {
+        outpoint: tx.inputs[0].previous_output.clone(),
+        value: 200000000,
+        height: None,
+    };
+    // the replaced transaction is sent by us recently
+    block_on(coin.as_ref().recently_spent_outpoints.lock()).add_spent(
+        vec![funding_unspent.clone()],
+        tx.hash(),
+        tx.outputs.clone(),
+    );
+
+    let fee = WithdrawFee::UtxoPerKbyte {
+        amount: "0.001".parse().unwrap(),
+    };
+    let response = block_on(bump_utxo_tx_fee(&coin, tx_hash, Some(fee))).unwrap();
+    assert_eq!(response.replaced_tx_hash, tx.hash().reversed().to_vec().into());
+
+    let replacement: UtxoTx = deserialize(response.tx_details.tx_hex.as_slice()).unwrap();
+    assert_eq!(replacement.inputs[0].previous_output, tx.inputs[0].previous_output);
+    assert_eq!(replacement.inputs[0].sequence, MAX_BIP125_RBF_SEQUENCE);
+    // the fee is taken from the change only
+    assert_eq!(replacement.outputs[0], tx.outputs[0]);
+
+    let new_fee = 200000000 - replacement.outputs[0].value - replacement.outputs[1].value;
+    let expected_fee_details = TxFeeDetails::Utxo(UtxoFeeDetails {
+        amount: big_decimal_from_sat_unsigned(new_fee, coin.as_ref().decimals),
+    });
+    assert_eq!(response.tx_details.fee_details, Some(expected_fee_details));
+    // 0.001 per kbyte of the ~225 bytes transaction is more than the replaced tx fee + the incremental relay fee
+    assert!(new_fee > 10225, "{}", new_fee);
+
+    let recently_spent = block_on(coin.as_ref().recently_spent_outpoints.lock());
+    // the change of the replaced transaction is dropped
+    assert!(!recently_spent
+        .output_to_input_map
+        .keys()
+        .any(|output| output.outpoint.hash == tx.hash()));
+    let unspents = recently_spent.replace_spent_outputs_with_cache(vec![funding_unspent].into_iter().collect());
+    // the funding output is spent by the replacement now
+    let expected_change = UnspentInfo {
+        outpoint: OutPoint {
+            hash: replacement.hash(),
+            index: 1,
+        },
+        value: replacement.outputs[1].value,
+        height: None,
+    };
+    assert_eq!(unspents, vec![expected_change].into_iter().collect());
+}
+
+#[test]
+#[cfg(not(target_arch = "wasm32"))]
+fn test_bump_fee_tx_does_not_signal_rbf() {
+    use crate::utxo::fee_bump::{bump_utxo_tx_fee, BumpFeeError};
+
+    let coin = utxo_coin_for_test(UtxoRpcClientEnum::Native(native_client_for_test()), None, false);
+    let (funding_tx, tx) = rbf_txs_for_test(&coin, SEQUENCE_FINAL);
+    let tx_hash = tx.hash().reversed().into();
+    mock_get_raw_transaction_bytes(vec![funding_tx]);
+    mock_get_unconfirmed_verbose_transaction(&tx);
+
+    let error = block_on(bump_utxo_tx_fee(&coin, tx_hash, None)).unwrap_err();
+    match error.into_inner() {
+        BumpFeeError::TxDoesNotSignalRbf => (),
+        e => panic!("Unexpected error {}", e),
+    }
+}
+
+#[test]
+#[cfg(not(target_arch = "wasm32"))]
+fn test_bump_fee_tx_is_confirmed() {
+    use crate::utxo::fee_bump::{bump_utxo_tx_fee, BumpFeeError};
+
+    let coin = utxo_coin_for_test(UtxoRpcClientEnum::Native(native_client_for_test()), None, false);
+    let (funding_tx, tx) = rbf_txs_for_test(&coin, MAX_BIP125_RBF_SEQUENCE);
+    let tx_hash = tx.hash().reversed().into();
+    mock_get_raw_transaction_bytes(vec![funding_tx]);
+    mock_get_verbose_transaction(&tx, 1);
+
+    let error = block_on(bump_utxo_tx_fee(&coin, tx_hash, None)).unwrap_err();
+    match error.into_inner() {
+        BumpFeeError::TxIsConfirmed => (),
+        e => panic!("Unexpected error {}", e),
+    }
+}
+
+#[test]
+#[cfg(not(target_arch = "wasm32"))]
+fn test_cpfp() {
+    use crate::utxo::fee_bump::utxo_cpfp;
+
+    let coin = utxo_coin_for_test(UtxoRpcClientEnum::Native(native_client_for_test()), None, false);
+    let my_script = Builder::build_p2pkh(&coin.as_ref().my_address.hash).to_bytes();
+    let other_script = Builder::build_p2pkh(&Default::default()).to_bytes();
+    // the parent is sent to us by another wallet
+    let funding_tx = UtxoTx {
+        version: 1,
+        inputs: vec![TransactionInput {
+            previous_output: OutPoint {
+                hash: 1.into(),
+                index: 0,
+            },
+            script_sig: Bytes::default(),
+            sequence: SEQUENCE_FINAL,
+            script_witness: vec![],
+        }],
+        outputs: vec![TransactionOutput {
+            value: 200000000,
+            script_pubkey: other_script.clone(),
+        }],
+        ..Default::default()
+    };
+    let parent = UtxoTx {
+        version: 1,
+        inputs: vec![TransactionInput {
+            previous_output: OutPoint {
+                hash: funding_tx.hash(),
+                index: 0,
+            },
+            script_sig: Bytes::default(),
+            sequence: SEQUENCE_FINAL,
+            script_witness: vec![],
+        }],
+        outputs: vec![
+            TransactionOutput {
+                value: 99990000,
+                script_pubkey: other_script,
+            },
+            TransactionOutput {
+                value: 100000000,
+                script_pubkey: my_script.clone(),
+            },
+        ],
+        ..Default::default()
+    };
+    let parent_hash = parent.hash().reversed().into();
+    mock_get_raw_transaction_bytes(vec![funding_tx]);
+    mock_get_unconfirmed_verbose_transaction(&parent);
+    NativeClient::send_transaction
+        .mock_safe(|_, tx| MockResult::Return(Box::new(futures01::future::ok(tx.hash().reversed().into()))));
+
+    let fee = WithdrawFee::UtxoPerKbyte {
+        amount: "0.001".parse().unwrap(),
+    };
+    let tx_details = block_on(utxo_cpfp(&coin, parent_hash, Some(fee))).unwrap();
+    let child: UtxoTx = deserialize(tx_details.tx_hex.as_slice()).unwrap();
+    assert_eq!(child.inputs.len(), 1);
+    assert_eq!(child.inputs[0].previous_output, OutPoint {
+        hash: parent.hash(),
+        index: 1,
+    });
+    assert_eq!(child.outputs.len(), 1);
+    assert_eq!(child.outputs[0].script_pubkey, my_script);
+
+    // the parent pays 10000 satoshis, the child pays the rest of the fee for both
+    let parent_v_size = serialize(&parent).len() as u64;
+    let unsigned_child = UtxoTx::from(TransactionInputSigner::from(child.clone()));
+    let child_v_size = tx_size_in_v_bytes(&UtxoAddressFormat::Standard, &unsigned_child) as u64;
+    let expected_fee = 100000 * (parent_v_size + child_v_size) / 1000 - 10000;
+    assert_eq!(child.outputs[0].value, 100000000 - expected_fee);
+    let expected_fee_details = TxFeeDetails::Utxo(UtxoFeeDetails {
+        amount: big_decimal_from_sat_unsigned(expected_fee, coin.as_ref().decimals),
+    });
+    assert_eq!(tx_details.fee_details, Some(expected_fee_details));
+}
+
+#[test]
+#[cfg(not(target_arch = "wasm32"))]
+fn test_cpfp_skips_frozen_outputs() {
+    use crate::utxo::fee_bump::utxo_cpfp;
+
+    let coin = utxo_coin_for_test(UtxoRpcClientEnum::Native(native_client_for_test()), None, false);
+    let my_script = Builder::build_p2pkh(&coin.as_ref().my_address.hash).to_bytes();
+    let other_script = Builder::build_p2pkh(&Default::default()).to_bytes();
+    let funding_tx = UtxoTx {
+        version: 1,
+        inputs: vec![TransactionInput {
+            previous_output: OutPoint {
+                hash: 1.into(),
+                index: 0,
+            },
+            script_sig: Bytes::default(),
+            sequence: SEQUENCE_FINAL,
+            script_witness: vec![],
+        }],
+        outputs: vec![TransactionOutput {
+            value: 200000000,
+            script_pubkey: other_script,
+        }],
+        ..Default::default()
+    };
+    let parent = UtxoTx {
+        version: 1,
+        inputs: vec![TransactionInput {
+            previous_output: OutPoint {
+                hash: funding_tx.hash(),
+                index: 0,
+            },
+            script_sig: Bytes::default(),
+            sequence: SEQUENCE_FINAL,
+            script_witness: vec![],
+        }],
+        outputs: vec![
+            TransactionOutput {
+                value: 100000000,
+                script_pubkey: my_script.clone(),
+            },
+            TransactionOutput {
+                value: 99990000,
+                script_pubkey: my_script,
+            },
+        ],
+        ..Default::default()
+    };
+    // the smaller output is spent first unless it's frozen
+    block_on(coin.as_ref().frozen_outpoints.lock())
+        .freeze(vec![OutPoint {
+            hash: parent.hash(),
+            index: 1,
+        }])
+        .unwrap();
+    let parent_hash = parent.hash().reversed().into();
+    mock_get_raw_transaction_bytes(vec![funding_tx]);
+    mock_get_unconfirmed_verbose_transaction(&parent);
+    NativeClient::send_transaction
+        .mock_safe(|_, tx| MockResult::Return(Box::new(futures01::future::ok(tx.hash().reversed().into()))));
+
+    let tx_details = block_on(utxo_cpfp(&coin, parent_hash, None)).unwrap();
+    let child: UtxoTx = deserialize(tx_details.tx_hex.as_slice()).unwrap();
+    assert_eq!(child.inputs.len(), 1);
+    assert_eq!(child.inputs[0].previous_output, OutPoint {
+        hash: parent.hash(),
+        index: 0,
+    });
+    assert_eq!(
+        tx_details.spent_by_me,
+        big_decimal_from_sat_unsigned(100000000, coin.as_ref().decimals)
+    );
+}
+
+#[test]
+#[cfg(not(target_arch = "wasm32"))]
+fn test_cpfp_no_outputs_to_me() {
+    use crate::utxo::fee_bump::{utxo_cpfp, BumpFeeError};
+
+    let coin = utxo_coin_for_test(UtxoRpcClientEnum::Native(native_client_for_test()), None, false);
+    let (funding_tx, tx) = rbf_txs_for_test(&coin, SEQUENCE_FINAL);
+    // the funding transaction pays to us, but the other one doesn't
+    let parent = UtxoTx {
+        outputs: vec![tx.outputs[0].clone()],
+        ..tx
+    };
+    let parent_hash = parent.hash().reversed().into();
+    mock_get_raw_transaction_bytes(vec![funding_tx]);
+    mock_get_unconfirmed_verbose_transaction(&parent);
+
+    let error = block_on(utxo_cpfp(&coin, parent_hash, None)).unwrap_err();
+    match error.into_inner() {
+        BumpFeeError::NoOutputsToMe => (),
+        e => panic!("Unexpected error {}", e),
+    }
+}
+
+#[test]
+fn test_sign_verify_message() {
+    let coin = utxo_coin_for_test(UtxoRpcClientEnum::Native(native_client_for_test()), None, false);
+    let my_address = coin.as_ref().my_address.to_string();
+    let message = "test";
+
+    let signature = utxo_common::sign_message(&coin, message).unwrap();
+    assert_eq!(base64::decode(&signature).unwrap().len(), 65);
+    assert!(utxo_common::verify_message(&coin, message, &signature, &my_address).unwrap());
+    assert!(!utxo_common::verify_message(&coin, "another message", &signature, &my_address).unwrap());
+
+    let other_address = Address {
+        hash: keys::AddressHash::default(),
+        ..coin.as_ref().my_address.clone()
+    };
+    assert!(!utxo_common::verify_message(&coin, message, &signature, &other_address.to_string()).unwrap());
+}
+
+#[test]
+fn test_sign_message_prefix_not_found() {
+    let mut fields = utxo_coin_fields_for_test(UtxoRpcClientEnum::Native(native_client_for_test()), None, false);
+    fields.conf.sign_message_prefix = None;
+    let coin = utxo_coin_from_fields(fields);
+
+    let error = utxo_common::sign_message(&coin, "test").unwrap_err();
+    match error.into_inner() {
+        SignatureError::PrefixNotFound { .. } => (),
+        e => panic!("Unexpected error {}", e),
+    }
+}
+
+#[test]
+fn test_electrum_subscription_notifications() {
+    let subscriptions = ElectrumSubscriptions::default();
+    let stats = Mutex::new(ElectrumServerStats::default());
+    let responses = Arc::new(AsyncMutex::new(HashMap::new()));
+    let script_hash = "8b01df4e368ea28f8dc0423bcf7a4923e3a12d307c875e47a0cfbf90b5c39161".to_owned();
+    let mut scripthash_notifications = subscriptions.add_scripthash_listener(&[script_hash.clone()]);
+    let mut header_notifications = subscriptions.add_header_listener();
+
+    let notification = json!({
+        "jsonrpc": "2.0",
+        "method": "blockchain.scripthash.subscribe",
+        "params": [script_hash, "9f5d53a5d4d2b8dd0b1b8a5a3bd2e7d0e7b43bd0c7c66a46f8a5e9d9b4b6e7a1"],
+    });
+    block_on(electrum_process_json(notification, &responses, &subscriptions, &stats));
+    assert_eq!(scripthash_notifications.try_next().unwrap(), Some(()));
+    assert!(header_notifications.try_next().is_err());
+
+    let notification = json!({
+        "jsonrpc": "2.0",
+        "method": "blockchain.scripthash.subscribe",
+        "params": ["0000000000000000000000000000000000000000000000000000000000000000", null],
+    });
+    block_on(electrum_process_json(notification, &responses, &subscriptions, &stats));
+    assert!(scripthash_notifications.try_next().is_err());
+
+    let notification = json!({
+        "jsonrpc": "2.0",
+        "method": "blockchain.headers.subscribe",
+        "params": [{"height": 1000, "hex": ""}],
+    });
+    block_on(electrum_process_json(notification, &responses, &subscriptions, &stats));
+    assert_eq!(header_notifications.try_next().unwrap(), Some(()));
+    assert!(scripthash_notifications.try_next().is_err());
+    assert_eq!(stats.lock().unwrap().tip_height(), Some(1000));
+}
+
+#[test]
+fn test_electrum_server_stats() {
+    let mut fast = ElectrumServerStats::default();
+    fast.on_success(100);
+    let mut slow = ElectrumServerStats::default();
+    slow.on_success(2000);
+    assert!(fast.score() > slow.score());
+
+    let mut failing = ElectrumServerStats::default();
+    failing.on_success(100);
+    assert!(!failing.on_failure());
+    assert!(failing.score() < fast.score());
+    // a successful request resets the failures in a row
+    failing.on_success(100);
+    assert!(!failing.on_failure());
+    assert!(!failing.on_failure());
+    assert!(!failing.is_suspended(now_ms()));
+    assert!(failing.on_failure());
+    assert!(failing.is_suspended(now_ms()));
+    // the server is retried after the suspension
+    assert!(!failing.is_suspended(now_ms() + 31000));
+}
+
+#[test]
+fn test_electrum_batch_response() {
+    let subscriptions = ElectrumSubscriptions::default();
+    let stats = Mutex::new(ElectrumServerStats::default());
+    let responses = Arc::new(AsyncMutex::new(HashMap::new()));
+    let (tx1, rx1) = futures::channel::oneshot::channel();
+    let (tx2, rx2) = futures::channel::oneshot::channel();
+    block_on(async {
+        let mut responses = responses.lock().await;
+        responses.insert("1".to_owned(), tx1);
+        responses.insert("2".to_owned(), tx2);
+    });
+
+    // the responses of the batch may be returned in any order
+    let batch = json!([
+        {"jsonrpc": "2.0", "id": "2", "result": null, "error": {"code": 2, "message": "missing transaction"}},
+        {"jsonrpc": "2.0", "id": "1", "result": "0400008085202f89"},
+    ]);
+    block_on(electrum_process_json(batch, &responses, &subscriptions, &stats));
+
+    let response1 = block_on(rx1).unwrap();
+    assert_eq!(response1.result, json!("0400008085202f89"));
+    assert!(response1.error.is_null());
+    let response2 = block_on(rx2).unwrap();
+    assert!(!response2.error.is_null());
+    assert!(block_on(responses.lock()).is_empty());
+}
+
+#[test]
+fn test_get_verbose_transactions_batch() {
+    let client = electrum_client_for_test(RICK_ELECTRUM_ADDRS);
+    // https://rick.explorer.dexstats.info/tx/7181400be323acc6b5f3164240e6c4601ff4c252f40ce7649f87e81634330209
+    // https://rick.explorer.dexstats.info/tx/0a0fda88364b960000f445351fe7678317a1e0c80584de0413377ede00ba696f
+    let txids: Vec<H256Json> = [
+        "7181400be323acc6b5f3164240e6c4601ff4c252f40ce7649f87e81634330209",
+        "0a0fda88364b960000f445351fe7678317a1e0c80584de0413377ede00ba696f",
+    ]
+    .iter()
+    .map(|txid| hex::decode(txid).unwrap().as_slice().into())
+    .collect();
+    let txs = client.get_verbose_transactions(&txids).wait().unwrap();
+    let actual: Vec<H256Json> = txs.into_iter().map(|tx| tx.txid).collect();
+    assert_eq!(actual, txids);
+}
+
+#[test]
+#[cfg(not(target_arch = "wasm32"))]
+fn test_native_client_cookie_auth() {
+    use super::rpc_clients::{NativeClientAuth, NativeClientAuthError};
+
+    let cookie_path = std::env::temp_dir().join(format!("test_native_client_cookie_auth_{}", now_ms()));
+    match NativeClientAuth::cookie(cookie_path.clone()) {
+        Err(NativeClientAuthError::CookieRead { .. }) => (),
+        other => panic!("Expected CookieRead error, found {:?}", other),
+    }
+
+    std::fs::write(&cookie_path, "__cookie__:0a1b2c\n").unwrap();
+    let auth = NativeClientAuth::cookie(cookie_path.clone()).unwrap();
+    assert_eq!(auth.header(), NativeClientAuth::basic("__cookie__", "0a1b2c").header());
+    assert!(!auth.refresh().unwrap());
+
+    // the daemon rotates the cookie on restart
+    std::fs::write(&cookie_path, "__cookie__:3d4e5f").unwrap();
+    assert!(auth.refresh().unwrap());
+    assert_eq!(auth.header(), NativeClientAuth::basic("__cookie__", "3d4e5f").header());
+
+    std::fs::write(&cookie_path, "no_separator").unwrap();
+    match auth.refresh() {
+        Err(NativeClientAuthError::InvalidCookie { .. }) => (),
+        other => panic!("Expected InvalidCookie error, found {:?}", other),
+    }
+    std::fs::remove_file(&cookie_path).unwrap();
+}
+
+#[test]
+#[cfg(not(target_arch = "wasm32"))]
+fn test_native_client_init_auth_error() {
+    use super::rpc_clients::NativeClientAuthError;
+
+    let ctx = MmCtxBuilder::new().into_mm_arc();
+    let conf = json!({"coin":"RICK","asset":"RICK","rpcport":8923,"confpath":"/non/existent/RICK.conf"});
+    let req = json!({"method":"enable","rpc_user":"user"});
+    let builder = UtxoArcBuilder::new(&ctx, "RICK", &conf, &req, &[1; 32]);
+    match builder.native_client().unwrap_err().into_inner() {
+        NativeClientInitError::Auth(NativeClientAuthError::IncompleteCredentials) => (),
+        e => panic!(
+            "Expected NativeClientInitError::Auth(IncompleteCredentials), found {:?}",
+            e
+        ),
+    }
+
+    let req = json!({"method":"enable","rpc_cookie_path":"/non/existent/.cookie"});
+    let builder = UtxoArcBuilder::new(&ctx, "RICK", &conf, &req, &[1; 32]);
+    match builder.native_client().unwrap_err().into_inner() {
+        NativeClientInitError::Auth(NativeClientAuthError::CookieRead { .. }) => (),
+        e => panic!("Expected NativeClientInitError::Auth(CookieRead), found {:?}", e),
+    }
+}
+
+#[test]
+#[cfg(not(target_arch = "wasm32"))]
+fn test_read_native_mode_conf_without_credentials() {
+    let conf_path = std::env::temp_dir().join(format!("test_read_native_mode_conf_{}.conf", now_ms()));
+    std::fs::write(
+        &conf_path,
+        "server=1\nrpccookiefile=auth.cookie\n[test]\nrpcport=18332\n",
+    )
+    .unwrap();
+
+    let conf = read_native_mode_conf(&conf_path, &BlockchainNetwork::Testnet).unwrap();
+    assert_eq!(conf.rpc_port, Some(18332));
+    assert_eq!(conf.rpc_user, None);
+    assert_eq!(conf.rpc_password, None);
+    assert_eq!(conf.rpc_cookie_file, Some("auth.cookie".to_owned()));
+
+    std::fs::write(&conf_path, "rpcuser=user\n").unwrap();
+    read_native_mode_conf(&conf_path, &BlockchainNetwork::Mainnet).unwrap_err();
+    std::fs::remove_file(&conf_path).unwrap();
+}
+
+#[test]
+fn test_electrum_subscriptions_add_script_hashes_once() {
+    let subscriptions = ElectrumSubscriptions::default();
+    let responses = Arc::new(AsyncMutex::new(HashMap::new()));
+    let script_hash = "8b01df4e368ea28f8dc0423bcf7a4923e3a12d307c875e47a0cfbf90b5c39161".to_owned();
+    let (tx, mut notifications) = futures::channel::mpsc::unbounded();
+
+    let added = subscriptions.add_script_hashes(vec![script_hash.clone()], &[tx.clone()]);
+    assert_eq!(added, vec![script_hash.clone()]);
+    // the script hash is subscribed on the servers once, e.g. if the same change address is derived again
+    let added = subscriptions.add_script_hashes(vec![script_hash.clone()], &[tx]);
+    assert!(added.is_empty());
+
+    let notification = json!({
+        "jsonrpc": "2.0",
+        "method": "blockchain.scripthash.subscribe",
+        "params": [script_hash, "9f5d53a5d4d2b8dd0b1b8a5a3bd2e7d0e7b43bd0c7c66a46f8a5e9d9b4b6e7a1"],
+    });
+    block_on(electrum_process_json(notification, &responses, &subscriptions));
+    assert_eq!(notifications.try_next().unwrap(), Some(()));
+    assert!(notifications.try_next().is_err());
+}
@@ -0,0 +1,84 @@
+//! The watch-only mode of MM2 (the `watch_only` config field).
+//!
+//! Instead of a passphrase the node is given the public keys of the coins:
+//!
+//! ```json
+//! "watch_only": {
+//!     "pubkey": "02...",
+//!     "coins": {
+//!         "KMD": {"xpub": "xpub6C..."},
+//!         "QTUM": {"pubkey": "03..."},
+//!         "ETH": {"address": "0x..."}
+//!     }
+//! }
+//! ```
+//!
+//! `xpub` is the extended public key of the BIP44 account `m/purpose'/coin_type'/account'`,
+//! `pubkey` is the compressed public key of the only address of the coin,
+//! `address` is the address of the ETH/ERC20 coin. ERC20 tokens fall back to the config of their platform coin.
+//! The optional top-level `pubkey` is the public key of the node being watched,
+//! it's used to pick the same `dbdir` subdirectory, so the swaps of that node can be viewed.
+//!
+//! The balances, the transaction history and the orderbook are available in this mode,
+//! but every call that has to sign something fails with [`WatchOnlyError`].
+
+use common::mm_ctx::MmArc;
+use derive_more::Display;
+use keys::bip32::ExtendedPublicKey;
+use keys::Public;
+use primitives::hash::H264;
+use serde_json::{self as json};
+
+/// The error returned on an attempt to sign a transaction or to place an order in the watch-only mode.
+#[derive(Clone, Debug, Display, PartialEq)]
+#[display(fmt = "MM2 runs in the watch-only mode, signing is not available")]
+pub struct WatchOnlyError;
+
+/// The public key the coin is watched with.
+#[derive(Debug)]
+pub enum WatchOnlyCoinKey {
+    /// The extended public key of the BIP44 account.
+    Xpub(ExtendedPublicKey),
+    /// The public key of the only address of the coin.
+    Pubkey(Public),
+    /// The address of the ETH/ERC20 coin.
+    Address(String),
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum WatchOnlyCoinConf {
+    Xpub(String),
+    Pubkey(String),
+    Address(String),
+}
+
+/// Returns the key the coin should be watched with.
+pub fn watch_only_coin_key(ctx: &MmArc, ticker: &str) -> Result<WatchOnlyCoinKey, String> {
+    let conf = &ctx.conf["watch_only"]["coins"][ticker];
+    if conf.is_null() {
+        return ERR!(
+            "None of 'xpub', 'pubkey' or 'address' of {} is set in the 'watch_only' config",
+            ticker
+        );
+    }
+    match try_s!(json::from_value::<WatchOnlyCoinConf>(conf.clone())) {
+        WatchOnlyCoinConf::Xpub(xpub) => match xpub.parse() {
+            Ok(xpub) => Ok(WatchOnlyCoinKey::Xpub(xpub)),
+            Err(e) => ERR!("Invalid 'xpub' {} of {}: {}", xpub, ticker, e),
+        },
+        WatchOnlyCoinConf::Pubkey(pubkey) => Ok(WatchOnlyCoinKey::Pubkey(try_s!(compressed_pubkey_from_hex(&pubkey)))),
+        WatchOnlyCoinConf::Address(address) => Ok(WatchOnlyCoinKey::Address(address)),
+    }
+}
+
+/// Parses the compressed public key from hex.
+pub fn compressed_pubkey_from_hex(pubkey: &str) -> Result<Public, String> {
+    let bytes = try_s!(hex::decode(pubkey));
+    if bytes.len() != 33 {
+        return ERR!("Expected a compressed public key, found {} bytes", bytes.len());
+    }
+    // check that the point is on the curve
+    try_s!(secp256k1::PublicKey::from_slice(&bytes));
+    Ok(Public::Compressed(H264::from(bytes.as_slice())))
+}
@@ -1,22 +1,24 @@
 use crate::utxo::rpc_clients::{UnspentInfo, UtxoRpcClientEnum, UtxoRpcError, UtxoRpcResult};
-use crate::utxo::utxo_common::{payment_script, UtxoArcBuilder};
-use crate::utxo::{utxo_common, ActualTxFee, AdditionalTxData, Address, FeePolicy, GenerateTxResult, HistoryUtxoTx,
-                  HistoryUtxoTxMap, RecentlySpentOutPoints, UtxoArc, UtxoCoinBuilder, UtxoCoinFields, UtxoCommonOps,
-                  VerboseTransactionFrom};
-use crate::{BalanceFut, CoinBalance, FeeApproxStage, FoundSwapTxSpend, HistorySyncState, MarketCoinOps, MmCoin,
-            NegotiateSwapContractAddrErr, SwapOps, TradeFee, TradePreimageFut, TradePreimageResult,
-            TradePreimageValue, TransactionDetails, TransactionEnum, TransactionFut, ValidateAddressResult,
-            WithdrawFut, WithdrawRequest};
+use crate::utxo::utxo_common::{big_decimal_from_sat, big_decimal_from_sat_unsigned, payment_script, UtxoArcBuilder};
+use crate::utxo::{sat_from_big_decimal, utxo_common, ActualTxFee, AdditionalTxData, Address, FeePolicy,
+                  GenerateTxResult, HistoryUtxoTx, HistoryUtxoTxMap, RecentlySpentOutPoints, UtxoArc, UtxoCoinBuilder,
+                  UtxoCoinFields, UtxoCommonOps, UtxoFeeDetails, VerboseTransactionFrom};
+use crate::{BalanceError, BalanceFut, CoinBalance, FeeApproxStage, FoundSwapTxSpend, HistorySyncState, MarketCoinOps,
+            MmCoin, NegotiateSwapContractAddrErr, SwapOps, TradeFee, TradePreimageFut, TradePreimageResult,
+            TradePreimageValue, TransactionDetails, TransactionEnum, TransactionErr, TransactionFut,
+            ValidateAddressResult, WithdrawError, WithdrawFut, WithdrawRequest, WithdrawResult};
 use async_trait::async_trait;
 use bitcrypto::dhash160;
 use chain::constants::SEQUENCE_FINAL;
 use chain::{Transaction as UtxoTx, TransactionOutput};
+use common::executor::spawn;
 use common::jsonrpc_client::JsonRpcError;
 use common::mm_ctx::MmArc;
 use common::mm_error::prelude::*;
 use common::mm_number::{BigDecimal, MmNumber};
 use common::now_ms;
 use derive_more::Display;
+use futures::compat::Future01CompatExt;
 use futures::lock::{Mutex as AsyncMutex, MutexGuard as AsyncMutexGuard};
 use futures::{FutureExt, TryFutureExt};
 use futures01::Future;
@@ -25,17 +27,33 @@ use primitives::bytes::Bytes;
 use rpc::v1::types::{Bytes as BytesJson, Transaction as RpcTransaction, H256 as H256Json};
 use script::{Builder as ScriptBuilder, Opcode, Script, TransactionInputSigner};
 use serde_json::Value as Json;
-use serialization::deserialize;
+use serialization::{deserialize, serialize};
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
-use zcash_client_backend::encoding::{encode_extended_spending_key, encode_payment_address};
-use zcash_primitives::{constants::mainnet as z_mainnet_constants, sapling::PaymentAddress, zip32::ExtendedSpendingKey};
+use zcash_client_backend::address::RecipientAddress;
+use zcash_client_backend::encoding::{decode_payment_address, encode_extended_spending_key, encode_payment_address};
+use zcash_primitives::block::BlockHeader;
+use zcash_primitives::consensus::{self, BlockHeight};
+use zcash_primitives::constants::mainnet as z_mainnet_constants;
+use zcash_primitives::legacy::TransparentAddress;
+use zcash_primitives::merkle_tree::{CommitmentTree, IncrementalWitness, MerklePath};
+use zcash_primitives::note_encryption::try_sapling_note_decryption;
+use zcash_primitives::sapling::{Diversifier, Node, Note, PaymentAddress, SaplingIvk};
+use zcash_primitives::serialize::CompactSize;
+use zcash_primitives::transaction::builder::{Builder as ZTxBuilder, DEFAULT_FEE};
+use zcash_primitives::transaction::components::Amount;
+use zcash_primitives::transaction::{Transaction as ZTransaction, TxId};
+use zcash_primitives::zip32::{ExtendedFullViewingKey, ExtendedSpendingKey};
 use zcash_proofs::prover::LocalTxProver;
 
 mod z_htlc;
 use z_htlc::{z_p2sh_spend, z_send_dex_fee, z_send_htlc};
 
+mod z_light_client;
+use z_light_client::{light_client_sync_loop, LightwalletdClient, ZLightClient, ZLightClientError};
+
 mod z_rpc;
-use z_rpc::ZRpcOps;
+use z_rpc::{ZRpcOps, ZUnspent};
 
 #[cfg(test)] mod z_coin_tests;
 
@@ -46,6 +64,9 @@ pub struct ZCoinFields {
     z_tx_prover: LocalTxProver,
     /// Mutex preventing concurrent transaction generation/same input usage
     z_tx_mutex: AsyncMutex<()>,
+    /// Finds the notes of the z_addr if the coin is enabled with the lightwalletd servers,
+    /// the native daemon is used otherwise.
+    light_client: Option<ZLightClient>,
 }
 
 impl std::fmt::Debug for ZCoinFields {
@@ -78,6 +99,11 @@ impl AsRef<UtxoCoinFields> for ZCoin {
 pub enum ZCoinBuildError {
     BuilderError(String),
     GetAddressError,
+    LightClientError(ZLightClientError),
+}
+
+impl From<ZLightClientError> for ZCoinBuildError {
+    fn from(e: ZLightClientError) -> Self { ZCoinBuildError::LightClientError(e) }
 }
 
 pub async fn z_coin_from_conf_and_request(
@@ -109,27 +135,478 @@ async fn z_coin_from_conf_and_request_with_z_key(
         .default_address()
         .map_err(|_| MmError::new(ZCoinBuildError::GetAddressError))?;
 
+    let light_wallet_d_servers: Option<Vec<String>> = serde_json::from_value(req["light_wallet_d_servers"].clone())
+        .map_to_mm(|e| ZCoinBuildError::BuilderError(e.to_string()))?;
+    let light_client = match light_wallet_d_servers {
+        Some(urls) => {
+            // the new wallet is synced from the chain tip if the height isn't set
+            let sync_from_height: Option<u64> = serde_json::from_value(req["sync_from_height"].clone())
+                .map_to_mm(|e| ZCoinBuildError::BuilderError(e.to_string()))?;
+            let block_source = LightwalletdClient::connect(&urls).await?;
+            let extfvk = ExtendedFullViewingKey::from(&z_spending_key);
+            let db_dir = ctx.dbdir().join("z_light_client");
+            let light_client =
+                ZLightClient::init(Box::new(block_source), &db_dir, ticker, extfvk, sync_from_height).await?;
+            Some(light_client)
+        },
+        None => None,
+    };
+
     let z_tx_prover = LocalTxProver::bundled();
     let z_addr_encoded = encode_payment_address(z_mainnet_constants::HRP_SAPLING_PAYMENT_ADDRESS, &z_addr);
-    let z_fields = ZCoinFields {
+    let z_fields = Arc::new(ZCoinFields {
         z_spending_key,
         z_addr,
         z_addr_encoded,
         z_tx_prover,
         z_tx_mutex: AsyncMutex::new(()),
+        light_client,
+    });
+    if z_fields.light_client.is_some() {
+        info!("Starting the light client sync loop for coin {}", ticker);
+        spawn(light_client_sync_loop(Arc::downgrade(&z_fields), ticker.to_owned()));
+    }
+    Ok(ZCoin { utxo_arc, z_fields })
+}
+
+/// Decodes the sapling payment address.
+/// Returns `None` if the address isn't encoded with the sapling HRP, e.g. it's a transparent address.
+fn decode_z_address(address: &str) -> Result<Option<PaymentAddress>, String> {
+    let hrp = z_mainnet_constants::HRP_SAPLING_PAYMENT_ADDRESS;
+    // the sapling address is a bech32 string starting with the HRP followed by the '1' separator
+    if !address.starts_with(&format!("{}1", hrp)) {
+        return Ok(None);
+    }
+    match decode_payment_address(hrp, address) {
+        Ok(Some(z_addr)) => Ok(Some(z_addr)),
+        Ok(None) => ERR!("Invalid sapling address {}", address),
+        Err(e) => ERR!("Invalid sapling address {}: {:?}", address, e),
+    }
+}
+
+/// Sends the coins from the coin's z_addr to either a sapling or a transparent address.
+/// The transaction is built and signed locally, it isn't broadcasted.
+async fn z_withdraw(coin: ZCoin, req: WithdrawRequest) -> WithdrawResult {
+    req.ensure_no_utxo_specific_params()?;
+    let validated = coin.validate_address(&req.to);
+    if !validated.is_valid {
+        let reason = validated.reason.unwrap_or_default();
+        return MmError::err(WithdrawError::InvalidAddress(reason));
+    }
+    // the transaction builder always pays the default fee
+    if let Some(ref fee_policy) = req.fee {
+        let error = format!(
+            "The shielded withdraw pays the default fee only, found {:?}",
+            fee_policy
+        );
+        return MmError::err(WithdrawError::InvalidFeePolicy(error));
+    }
+    match coin.z_fields.light_client {
+        Some(ref light_client) => z_withdraw_light(&coin, light_client, req).await,
+        None => z_withdraw_native(&coin, req).await,
+    }
+}
+
+/// Returns the withdrawn amount in satoshis and its decimal value rounded to the coin decimals.
+fn z_withdraw_amount(
+    coin: &ZCoin,
+    req: &WithdrawRequest,
+    balance: BigDecimal,
+    fee: &BigDecimal,
+) -> Result<(u64, BigDecimal), MmError<WithdrawError>> {
+    let amount = if req.max {
+        if balance == BigDecimal::from(0) {
+            return MmError::err(WithdrawError::ZeroBalanceToWithdrawMax);
+        }
+        if balance <= *fee {
+            return MmError::err(WithdrawError::NotSufficientBalance {
+                coin: coin.ticker().to_owned(),
+                available: balance,
+                required: fee.clone(),
+            });
+        }
+        &balance - fee
+    } else {
+        let required = &req.amount + fee;
+        if required > balance {
+            return MmError::err(WithdrawError::NotSufficientBalance {
+                coin: coin.ticker().to_owned(),
+                available: balance,
+                required,
+            });
+        }
+        req.amount.clone()
+    };
+    let amount_sat = sat_from_big_decimal(&amount, coin.utxo_arc.decimals)?;
+    if amount_sat == 0 {
+        return MmError::err(WithdrawError::AmountTooLow {
+            amount,
+            threshold: coin.min_tx_amount(),
+        });
+    }
+    // the daemon rejects the amounts having more decimals than the coin
+    Ok((
+        amount_sat,
+        big_decimal_from_sat_unsigned(amount_sat, coin.utxo_arc.decimals),
+    ))
+}
+
+fn z_withdraw_details(
+    coin: &ZCoin,
+    to: String,
+    amount: BigDecimal,
+    fee: BigDecimal,
+    tx: &UtxoTx,
+) -> TransactionDetails {
+    let received_by_me = if to == coin.z_fields.z_addr_encoded {
+        amount.clone()
+    } else {
+        0.into()
     };
-    Ok(ZCoin {
-        utxo_arc,
-        z_fields: Arc::new(z_fields),
+    let spent_by_me = &amount + &fee;
+    let my_balance_change = &received_by_me - &spent_by_me;
+    let fee_details = UtxoFeeDetails { amount: fee };
+
+    TransactionDetails {
+        from: vec![coin.z_fields.z_addr_encoded.clone()],
+        to: vec![to],
+        total_amount: spent_by_me.clone(),
+        spent_by_me,
+        received_by_me,
+        my_balance_change,
+        tx_hash: tx.hash().reversed().to_vec().into(),
+        tx_hex: serialize(tx).into(),
+        fee_details: Some(fee_details.into()),
+        block_height: 0,
+        coin: coin.ticker().to_owned(),
+        internal_id: vec![].into(),
+        timestamp: now_ms() / 1000,
+        kmd_rewards: None,
+        psbt: None,
+    }
+}
+
+/// The note spent by the withdraw.
+struct ZSpend {
+    diversifier: Diversifier,
+    note: Note,
+    merkle_path: MerklePath<Node>,
+}
+
+fn z_withdraw_recipient(coin: &ZCoin, to: &str) -> Result<RecipientAddress, MmError<WithdrawError>> {
+    match decode_z_address(to).map_to_mm(WithdrawError::InvalidAddress)? {
+        Some(z_addr) => Ok(RecipientAddress::Shielded(z_addr)),
+        None => {
+            let address = coin.address_from_str(to).map_to_mm(WithdrawError::InvalidAddress)?;
+            let conf = &coin.utxo_arc.conf;
+            if address.prefix == conf.pub_addr_prefix && address.t_addr_prefix == conf.pub_t_addr_prefix {
+                Ok(RecipientAddress::Transparent(TransparentAddress::PublicKey(
+                    address.hash.take(),
+                )))
+            } else {
+                Ok(RecipientAddress::Transparent(TransparentAddress::Script(
+                    address.hash.take(),
+                )))
+            }
+        },
+    }
+}
+
+/// Builds and signs the transaction spending the notes, the change goes back to the z_addr.
+fn z_build_withdraw_tx(
+    coin: &ZCoin,
+    target_height: BlockHeight,
+    spends: Vec<ZSpend>,
+    to: RecipientAddress,
+    value: Amount,
+) -> Result<UtxoTx, MmError<WithdrawError>> {
+    let mut tx_builder = ZTxBuilder::new(consensus::MAIN_NETWORK, target_height);
+    let extfvk = ExtendedFullViewingKey::from(&coin.z_fields.z_spending_key);
+    for spend in spends {
+        tx_builder
+            .add_sapling_spend(
+                coin.z_fields.z_spending_key.clone(),
+                spend.diversifier,
+                spend.note,
+                spend.merkle_path,
+            )
+            .map_to_mm(|e| WithdrawError::InternalError(format!("{:?}", e)))?;
+    }
+    match to {
+        RecipientAddress::Shielded(z_addr) => tx_builder.add_sapling_output(Some(extfvk.fvk.ovk), z_addr, value, None),
+        RecipientAddress::Transparent(t_addr) => tx_builder.add_transparent_output(&t_addr, value),
+    }
+    .map_to_mm(|e| WithdrawError::InternalError(format!("{:?}", e)))?;
+    tx_builder.send_change_to(extfvk.fvk.ovk, coin.z_fields.z_addr.clone());
+
+    let (zcash_tx, _) = tx_builder
+        .build(consensus::BranchId::Sapling, &coin.z_fields.z_tx_prover)
+        .map_to_mm(|e| WithdrawError::InternalError(format!("{:?}", e)))?;
+    let mut tx_buffer = Vec::with_capacity(1024);
+    zcash_tx
+        .write(&mut tx_buffer)
+        .map_to_mm(|e| WithdrawError::InternalError(e.to_string()))?;
+    deserialize(tx_buffer.as_slice())
+        .map_err(|e| ERRL!("{:?}", e))
+        .map_to_mm(WithdrawError::InternalError)
+}
+
+/// Builds and signs the transaction spending the notes found by the light client, the change goes back to the z_addr.
+async fn z_withdraw_light(coin: &ZCoin, light_client: &ZLightClient, req: WithdrawRequest) -> WithdrawResult {
+    let decimals = coin.utxo_arc.decimals;
+    let fee = big_decimal_from_sat(i64::from(DEFAULT_FEE), decimals);
+    let to = z_withdraw_recipient(coin, &req.to)?;
+
+    let _lock = coin.z_fields.z_tx_mutex.lock().await;
+    let balance = light_client
+        .balance()
+        .mm_err(|e| WithdrawError::InternalError(e.to_string()))?;
+    let balance = big_decimal_from_sat(balance.spendable, decimals);
+    let (amount_sat, amount) = z_withdraw_amount(coin, &req, balance, &fee)?;
+    let value =
+        Amount::from_u64(amount_sat).map_to_mm(|_| WithdrawError::InternalError("Invalid amount".to_owned()))?;
+
+    let (target_height, notes) = light_client
+        .select_spendable_notes(value + DEFAULT_FEE)
+        .mm_err(|e| WithdrawError::InternalError(e.to_string()))?;
+    let extfvk = ExtendedFullViewingKey::from(&coin.z_fields.z_spending_key);
+    let mut spends = Vec::with_capacity(notes.len());
+    for note in notes {
+        let note_value = i64::from(note.note_value) as u64;
+        let from = extfvk
+            .fvk
+            .vk
+            .to_payment_address(note.diversifier)
+            .or_mm_err(|| WithdrawError::InternalError("Invalid diversifier of the note".to_owned()))?;
+        let zcash_note = from
+            .create_note(note_value, note.rseed)
+            .or_mm_err(|| WithdrawError::InternalError("Invalid note".to_owned()))?;
+        let merkle_path = note
+            .witness
+            .path()
+            .or_mm_err(|| WithdrawError::InternalError("The witness of the note is empty".to_owned()))?;
+        spends.push(ZSpend {
+            diversifier: note.diversifier,
+            note: zcash_note,
+            merkle_path,
+        });
+    }
+    let tx = z_build_withdraw_tx(coin, target_height, spends, to, value)?;
+    Ok(z_withdraw_details(coin, req.to, amount, fee, &tx))
+}
+
+/// The note of the z_addr known to the native daemon.
+struct ZNativeNote {
+    txid: TxId,
+    out_index: usize,
+    height: u64,
+    diversifier: Diversifier,
+    note: Note,
+}
+
+/// Builds and signs the transaction spending the notes known to the native daemon, the change goes back to the z_addr.
+/// The daemon doesn't expose the note witnesses, so they're built from the sapling commitment tree preceding the oldest
+/// spent note and the commitments of the following blocks up to the chain tip, the tip is the anchor of the spends.
+async fn z_withdraw_native(coin: &ZCoin, req: WithdrawRequest) -> WithdrawResult {
+    let decimals = coin.utxo_arc.decimals;
+    let fee = big_decimal_from_sat(i64::from(DEFAULT_FEE), decimals);
+    let to = z_withdraw_recipient(coin, &req.to)?;
+
+    let _lock = coin.z_fields.z_tx_mutex.lock().await;
+    let tip = coin
+        .rpc_client()
+        .get_block_count()
+        .compat()
+        .await
+        .mm_err(|e| WithdrawError::Transport(e.to_string()))?;
+    // the notes having at least one confirmation are committed to the tree at the tip
+    let min_conf = 1;
+    let mut unspents = coin
+        .z_rpc()
+        .z_list_unspent(min_conf, &coin.z_fields.z_addr_encoded)
+        .compat()
+        .await
+        .mm_err(|e| WithdrawError::Transport(e.to_string()))?;
+    unspents.retain(|unspent| unspent.spendable);
+    let balance = unspents.iter().fold(BigDecimal::from(0), |balance, unspent| {
+        balance + unspent.amount.to_decimal()
+    });
+    let (amount_sat, amount) = z_withdraw_amount(coin, &req, balance, &fee)?;
+    let value =
+        Amount::from_u64(amount_sat).map_to_mm(|_| WithdrawError::InternalError("Invalid amount".to_owned()))?;
+
+    // the largest notes are spent first
+    unspents.sort_by(|a, b| b.amount.cmp(&a.amount));
+    let required = amount_sat + i64::from(DEFAULT_FEE) as u64;
+    let ivk = ExtendedFullViewingKey::from(&coin.z_fields.z_spending_key).fvk.vk.ivk();
+    let mut notes = Vec::new();
+    let mut notes_value = 0;
+    for unspent in unspents {
+        if notes_value >= required {
+            break;
+        }
+        let note = z_native_note(coin, &ivk, unspent).await?;
+        notes_value += note.note.value;
+        notes.push(note);
+    }
+
+    let witnesses = z_native_witnesses(coin, &notes, tip).await?;
+    let mut spends = Vec::with_capacity(notes.len());
+    for (note, witness) in notes.into_iter().zip(witnesses) {
+        let merkle_path = witness
+            .path()
+            .or_mm_err(|| WithdrawError::InternalError("The witness of the note is empty".to_owned()))?;
+        spends.push(ZSpend {
+            diversifier: note.diversifier,
+            note: note.note,
+            merkle_path,
+        });
+    }
+    let target_height = BlockHeight::from_u32(tip as u32 + 1);
+    let tx = z_build_withdraw_tx(coin, target_height, spends, to, value)?;
+    Ok(z_withdraw_details(coin, req.to, amount, fee, &tx))
+}
+
+/// Decrypts the note returned by `z_listunspent` from its transaction.
+async fn z_native_note(
+    coin: &ZCoin,
+    ivk: &SaplingIvk,
+    unspent: ZUnspent,
+) -> Result<ZNativeNote, MmError<WithdrawError>> {
+    let verbose = coin
+        .rpc_client()
+        .get_verbose_transaction(unspent.txid.clone())
+        .compat()
+        .await
+        .map_to_mm(|e| WithdrawError::Transport(e.to_string()))?;
+    let height = verbose.height.or_mm_err(|| {
+        WithdrawError::InternalError(format!("The note transaction {:?} is not mined yet", unspent.txid))
+    })?;
+    let tx = ZTransaction::read(verbose.hex.as_slice()).map_to_mm(|e| WithdrawError::InternalError(e.to_string()))?;
+    let output = tx.shielded_outputs.get(unspent.out_index).or_mm_err(|| {
+        WithdrawError::InternalError(format!("{:?} has no output {}", unspent.txid, unspent.out_index))
+    })?;
+    let (note, payment_address, _memo) = try_sapling_note_decryption(
+        &consensus::MAIN_NETWORK,
+        BlockHeight::from_u32(height as u32),
+        ivk,
+        &output.ephemeral_key,
+        &output.cmu,
+        &output.enc_ciphertext,
+    )
+    .or_mm_err(|| WithdrawError::InternalError(format!("The note of {:?} can't be decrypted", unspent.txid)))?;
+    Ok(ZNativeNote {
+        txid: tx.txid(),
+        out_index: unspent.out_index,
+        height,
+        diversifier: *payment_address.diversifier(),
+        note,
     })
 }
 
+/// Returns the witnesses of the notes at the `tip` block.
+async fn z_native_witnesses(
+    coin: &ZCoin,
+    notes: &[ZNativeNote],
+    tip: u64,
+) -> Result<Vec<IncrementalWitness<Node>>, MmError<WithdrawError>> {
+    let from_height = match notes.iter().map(|note| note.height).min() {
+        Some(height) => height,
+        None => return Ok(Vec::new()),
+    };
+    let mut tree = z_native_commitment_tree(coin, from_height - 1).await?;
+    let mut witnesses: Vec<Option<IncrementalWitness<Node>>> = vec![None; notes.len()];
+    for height in from_height..=tip {
+        let block = coin
+            .z_rpc()
+            .get_block_bytes(height)
+            .compat()
+            .await
+            .mm_err(|e| WithdrawError::Transport(e.to_string()))?;
+        let txs = z_block_transactions(block.as_slice()).map_to_mm(WithdrawError::InternalError)?;
+        for tx in txs {
+            let txid = tx.txid();
+            for (out_index, output) in tx.shielded_outputs.iter().enumerate() {
+                let node = Node::new(output.cmu.to_bytes());
+                tree.append(node)
+                    .map_err(|_| WithdrawError::InternalError("The commitment tree is full".to_owned()))?;
+                for witness in witnesses.iter_mut().flatten() {
+                    witness
+                        .append(node)
+                        .map_err(|_| WithdrawError::InternalError("The note witness is full".to_owned()))?;
+                }
+                if let Some(index) = notes
+                    .iter()
+                    .position(|note| note.txid == txid && note.out_index == out_index)
+                {
+                    witnesses[index] = Some(IncrementalWitness::from_tree(&tree));
+                }
+            }
+        }
+    }
+    witnesses
+        .into_iter()
+        .map(|witness| {
+            witness.or_mm_err(|| WithdrawError::InternalError("The note commitment is not found".to_owned()))
+        })
+        .collect()
+}
+
+/// Returns the sapling commitment tree after the block at the given height.
+async fn z_native_commitment_tree(coin: &ZCoin, height: u64) -> Result<CommitmentTree<Node>, MmError<WithdrawError>> {
+    let mut tree_state = coin
+        .z_rpc()
+        .z_get_tree_state(&height.to_string())
+        .compat()
+        .await
+        .mm_err(|e| WithdrawError::Transport(e.to_string()))?;
+    // the tree is stored with the block that has changed it last
+    if let Some(skip_hash) = tree_state.sapling.skip_hash.take() {
+        tree_state = coin
+            .z_rpc()
+            .z_get_tree_state(&format!("{:?}", skip_hash))
+            .compat()
+            .await
+            .mm_err(|e| WithdrawError::Transport(e.to_string()))?;
+    }
+    match tree_state.sapling.commitments {
+        Some(commitments) => CommitmentTree::read(commitments.final_state.as_slice())
+            .map_to_mm(|e| WithdrawError::InternalError(e.to_string())),
+        // the sapling isn't activated at this height yet
+        None => Ok(CommitmentTree::empty()),
+    }
+}
+
+/// Parses the transactions of the serialized block.
+fn z_block_transactions(mut block: &[u8]) -> Result<Vec<ZTransaction>, String> {
+    try_s!(BlockHeader::read(&mut block));
+    let tx_count = try_s!(CompactSize::read(&mut block));
+    let mut txs = Vec::with_capacity(tx_count);
+    for _ in 0..tx_count {
+        txs.push(try_s!(ZTransaction::read(&mut block)));
+    }
+    Ok(txs)
+}
+
 impl MarketCoinOps for ZCoin {
     fn ticker(&self) -> &str { &self.utxo_arc.conf.ticker }
 
     fn my_address(&self) -> Result<String, String> { Ok(self.z_fields.z_addr_encoded.clone()) }
 
     fn my_balance(&self) -> BalanceFut<CoinBalance> {
+        if let Some(ref light_client) = self.z_fields.light_client {
+            let decimals = self.utxo_arc.decimals;
+            // the notes become spendable when the anchor of the transaction is deep enough
+            let balance = light_client
+                .balance()
+                .map(|balance| CoinBalance {
+                    spendable: big_decimal_from_sat(balance.spendable, decimals),
+                    unspendable: big_decimal_from_sat(balance.unspendable, decimals),
+                })
+                .mm_err(|e| BalanceError::Internal(e.to_string()));
+            return Box::new(futures01::future::result(balance));
+        }
+
         let min_conf = 0;
         let fut = self
             .utxo_arc
@@ -214,7 +691,7 @@ impl SwapOps for ZCoin {
             );
             Ok(tx.into())
         };
-        Box::new(fut.boxed().compat())
+        Box::new(fut.boxed().compat().map_err(TransactionErr::Plain))
     }
 
     fn send_maker_payment(
@@ -224,15 +701,16 @@ impl SwapOps for ZCoin {
         secret_hash: &[u8],
         amount: BigDecimal,
         _swap_contract_address: &Option<BytesJson>,
+        _other_protocol_info: &Option<Vec<u8>>,
     ) -> TransactionFut {
         let selfi = self.clone();
-        let taker_pub = try_fus!(Public::from_slice(taker_pub));
+        let taker_pub = try_tx_fus!(Public::from_slice(taker_pub));
         let secret_hash = secret_hash.to_vec();
         let fut = async move {
             let utxo_tx = try_s!(z_send_htlc(&selfi, time_lock, &taker_pub, &secret_hash, amount).await);
             Ok(utxo_tx.into())
         };
-        Box::new(fut.boxed().compat())
+        Box::new(fut.boxed().compat().map_err(TransactionErr::Plain))
     }
 
     fn send_taker_payment(
@@ -242,15 +720,16 @@ impl SwapOps for ZCoin {
         secret_hash: &[u8],
         amount: BigDecimal,
         _swap_contract_address: &Option<BytesJson>,
+        _other_protocol_info: &Option<Vec<u8>>,
     ) -> TransactionFut {
         let selfi = self.clone();
-        let maker_pub = try_fus!(Public::from_slice(maker_pub));
+        let maker_pub = try_tx_fus!(Public::from_slice(maker_pub));
         let secret_hash = secret_hash.to_vec();
         let fut = async move {
             let utxo_tx = try_s!(z_send_htlc(&selfi, time_lock, &maker_pub, &secret_hash, amount).await);
             Ok(utxo_tx.into())
         };
-        Box::new(fut.boxed().compat())
+        Box::new(fut.boxed().compat().map_err(TransactionErr::Plain))
     }
 
     fn send_maker_spends_taker_payment(
@@ -261,7 +740,7 @@ impl SwapOps for ZCoin {
         secret: &[u8],
         _swap_contract_address: &Option<BytesJson>,
     ) -> TransactionFut {
-        let tx: UtxoTx = try_fus!(deserialize(taker_payment_tx).map_err(|e| ERRL!("{:?}", e)));
+        let tx: UtxoTx = try_tx_fus!(deserialize(taker_payment_tx).map_err(|e| ERRL!("{:?}", e)));
         let redeem_script = payment_script(
             time_lock,
             &*dhash160(secret),
@@ -278,7 +757,7 @@ impl SwapOps for ZCoin {
             let tx = try_s!(tx_fut.await);
             Ok(tx.into())
         };
-        Box::new(fut.boxed().compat())
+        Box::new(fut.boxed().compat().map_err(TransactionErr::Plain))
     }
 
     fn send_taker_spends_maker_payment(
@@ -289,7 +768,7 @@ impl SwapOps for ZCoin {
         secret: &[u8],
         _swap_contract_address: &Option<BytesJson>,
     ) -> TransactionFut {
-        let tx: UtxoTx = try_fus!(deserialize(maker_payment_tx).map_err(|e| ERRL!("{:?}", e)));
+        let tx: UtxoTx = try_tx_fus!(deserialize(maker_payment_tx).map_err(|e| ERRL!("{:?}", e)));
         let redeem_script = payment_script(
             time_lock,
             &*dhash160(secret),
@@ -306,7 +785,7 @@ impl SwapOps for ZCoin {
             let tx = try_s!(tx_fut.await);
             Ok(tx.into())
         };
-        Box::new(fut.boxed().compat())
+        Box::new(fut.boxed().compat().map_err(TransactionErr::Plain))
     }
 
     fn send_taker_refunds_payment(
@@ -317,7 +796,7 @@ impl SwapOps for ZCoin {
         secret_hash: &[u8],
         _swap_contract_address: &Option<BytesJson>,
     ) -> TransactionFut {
-        let tx: UtxoTx = try_fus!(deserialize(taker_payment_tx).map_err(|e| ERRL!("{:?}", e)));
+        let tx: UtxoTx = try_tx_fus!(deserialize(taker_payment_tx).map_err(|e| ERRL!("{:?}", e)));
         let redeem_script = payment_script(
             time_lock,
             secret_hash,
@@ -331,7 +810,7 @@ impl SwapOps for ZCoin {
             let tx = try_s!(tx_fut.await);
             Ok(tx.into())
         };
-        Box::new(fut.boxed().compat())
+        Box::new(fut.boxed().compat().map_err(TransactionErr::Plain))
     }
 
     fn send_maker_refunds_payment(
@@ -342,7 +821,7 @@ impl SwapOps for ZCoin {
         secret_hash: &[u8],
         _swap_contract_address: &Option<BytesJson>,
     ) -> TransactionFut {
-        let tx: UtxoTx = try_fus!(deserialize(maker_payment_tx).map_err(|e| ERRL!("{:?}", e)));
+        let tx: UtxoTx = try_tx_fus!(deserialize(maker_payment_tx).map_err(|e| ERRL!("{:?}", e)));
         let redeem_script = payment_script(
             time_lock,
             secret_hash,
@@ -356,7 +835,7 @@ impl SwapOps for ZCoin {
             let tx = try_s!(tx_fut.await);
             Ok(tx.into())
         };
-        Box::new(fut.boxed().compat())
+        Box::new(fut.boxed().compat().map_err(TransactionErr::Plain))
     }
 
     fn validate_fee(
@@ -461,17 +940,41 @@ impl SwapOps for ZCoin {
 impl MmCoin for ZCoin {
     fn is_asset_chain(&self) -> bool { self.utxo_arc.conf.asset_chain }
 
-    fn withdraw(&self, _req: WithdrawRequest) -> WithdrawFut { todo!() }
+    fn withdraw(&self, req: WithdrawRequest) -> WithdrawFut { Box::new(z_withdraw(self.clone(), req).boxed().compat()) }
 
     fn decimals(&self) -> u8 { self.utxo_arc.decimals }
 
-    fn convert_to_address(&self, _from: &str, _to_address_format: Json) -> Result<String, String> { todo!() }
+    fn convert_to_address(&self, from: &str, to_address_format: Json) -> Result<String, String> {
+        if try_s!(decode_z_address(from)).is_some() {
+            return ERR!("The sapling address {} can't be converted", from);
+        }
+        utxo_common::convert_to_address(self, from, to_address_format)
+    }
 
-    fn validate_address(&self, _address: &str) -> ValidateAddressResult { todo!() }
+    fn validate_address(&self, address: &str) -> ValidateAddressResult {
+        match decode_z_address(address) {
+            Ok(Some(_)) => ValidateAddressResult {
+                is_valid: true,
+                reason: None,
+            },
+            Ok(None) => utxo_common::validate_address(self, address),
+            Err(e) => ValidateAddressResult {
+                is_valid: false,
+                reason: Some(e),
+            },
+        }
+    }
 
-    fn process_history_loop(&self, _ctx: MmArc) { todo!() }
+    fn process_history_loop(&self, ctx: MmArc) -> Box<dyn Future<Item = (), Error = ()> + Send> {
+        ctx.log.log(
+            "🤔",
+            &[&"tx_history", &self.utxo_arc.conf.ticker],
+            &ERRL!("Transaction history of the shielded address is not supported yet"),
+        );
+        Box::new(futures01::future::ok(()))
+    }
 
-    fn history_sync_status(&self) -> HistorySyncState { todo!() }
+    fn history_sync_status(&self) -> HistorySyncState { HistorySyncState::NotEnabled }
 
     fn get_trade_fee(&self) -> Box<dyn Future<Item = TradeFee, Error = String> + Send> {
         utxo_common::get_trade_fee(self.clone())
@@ -509,7 +1012,7 @@ impl MmCoin for ZCoin {
 
     fn mature_confirmations(&self) -> Option<u32> { Some(self.utxo_arc.conf.mature_confirmations) }
 
-    fn coin_protocol_info(&self) -> Option<Vec<u8>> { utxo_common::coin_protocol_info(&self.utxo_arc) }
+    fn coin_protocol_info(&self) -> Vec<u8> { utxo_common::coin_protocol_info(&self.utxo_arc) }
 
     fn is_coin_protocol_supported(&self, info: &Option<Vec<u8>>) -> bool {
         utxo_common::is_coin_protocol_supported(&self.utxo_arc, info)
@@ -605,12 +1108,12 @@ impl UtxoCommonOps for ZCoin {
         utxo_common::ordered_mature_unspents(self, address).await
     }
 
-    fn get_verbose_transaction_from_cache_or_rpc(
+    fn get_verbose_transactions_from_cache_or_rpc(
         &self,
-        txid: H256Json,
-    ) -> Box<dyn Future<Item = VerboseTransactionFrom, Error = String> + Send> {
+        txids: HashSet<H256Json>,
+    ) -> Box<dyn Future<Item = HashMap<H256Json, VerboseTransactionFrom>, Error = String> + Send> {
         let selfi = self.clone();
-        let fut = async move { utxo_common::get_verbose_transaction_from_cache_or_rpc(&selfi.utxo_arc, txid).await };
+        let fut = async move { utxo_common::get_verbose_transactions_from_cache_or_rpc(&selfi.utxo_arc, txids).await };
         Box::new(fut.boxed().compat())
     }
 
@@ -675,3 +1178,20 @@ fn derive_z_key_from_mm_seed() {
         "zs1funuwrjr2stlr6fnhkdh7fyz3p7n0p8rxase9jnezdhc286v5mhs6q3myw0phzvad5mvqgfxpam"
     );
 }
+
+#[test]
+fn test_decode_z_address() {
+    let z_addr = "zs182ht30wnnnr8jjhj2j9v5dkx3qsknnr5r00jfwk2nczdtqy7w0v836kyy840kv2r8xle5gcl549";
+    let decoded = decode_z_address(z_addr).unwrap().unwrap();
+    assert_eq!(
+        encode_payment_address(z_mainnet_constants::HRP_SAPLING_PAYMENT_ADDRESS, &decoded),
+        z_addr
+    );
+
+    // the checksum is broken
+    let invalid = "zs182ht30wnnnr8jjhj2j9v5dkx3qsknnr5r00jfwk2nczdtqy7w0v836kyy840kv2r8xle5gcl548";
+    decode_z_address(invalid).unwrap_err();
+
+    // the transparent addresses are decoded as the UTXO ones
+    assert_eq!(decode_z_address("RFFcjmNAVBAFS3Awv7gsBcc5cLSLBZ4HYy").unwrap(), None);
+}
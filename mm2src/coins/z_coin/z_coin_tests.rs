@@ -1,9 +1,169 @@
 use super::*;
 use crate::z_coin::z_htlc::z_send_dex_fee;
+use crate::z_coin::z_light_client::{CompactBlock, CompactBlockSource, CompactOutput, CompactTx, TreeState, ZBalance,
+                                    ZLightClientResult};
 use common::block_on;
 use common::mm_ctx::MmCtxBuilder;
 use common::now_ms;
+use common::privkey::key_pair_from_seed;
+use secp256k1_bindings::SecretKey;
+use std::path::Path;
+use std::sync::Mutex;
 use zcash_client_backend::encoding::decode_extended_spending_key;
+use zcash_primitives::consensus::BlockHeight;
+use zcash_primitives::legacy::Script as ZCashScript;
+use zcash_primitives::transaction::components::{OutPoint as ZCashOutpoint, TxOut};
+
+const TEST_Z_KEY: &str = "secret-extended-key-main1q0k2ga2cqqqqpq8m8j6yl0say83cagrqp53zqz54w38ezs8ly9ly5ptamqwfpq85u87w0df4k8t2lwyde3n9v0gcr69nu4ryv60t0kfcsvkr8h83skwqex2nf0vr32794fmzk89cpmjptzc22lgu5wfhhp8lgf3f5vn2l3sge0udvxnm95k6dtxj2jwlfyccnum7nz297ecyhmd5ph526pxndww0rqq0qly84l635mec0x4yedf95hzn6kcgq8yxts26k98j9g32kjc8y83fe";
+/// The height the fixture wallet is synced from.
+const FIXTURE_BIRTHDAY: u64 = 1000;
+
+/// Serves the fixture chain instead of a lightwalletd server.
+struct FixtureBlockSource {
+    /// The blocks following the birthday, can be replaced to emulate the chain reorganization.
+    blocks: Arc<Mutex<Vec<CompactBlock>>>,
+}
+
+#[async_trait]
+impl CompactBlockSource for FixtureBlockSource {
+    async fn latest_height(&self) -> ZLightClientResult<u64> {
+        Ok(self
+            .blocks
+            .lock()
+            .unwrap()
+            .last()
+            .map_or(FIXTURE_BIRTHDAY, |block| block.height))
+    }
+
+    async fn tree_state(&self, height: u64) -> ZLightClientResult<TreeState> {
+        assert_eq!(height, FIXTURE_BIRTHDAY);
+        let mut hash = fixture_block_hash(FIXTURE_BIRTHDAY, 0);
+        hash.reverse();
+        Ok(TreeState {
+            network: "main".to_owned(),
+            height,
+            hash: hex::encode(&hash),
+            time: 1600000000,
+            // the empty Sapling commitment tree
+            tree: "000000".to_owned(),
+        })
+    }
+
+    async fn compact_blocks(&self, from: u64, to: u64) -> ZLightClientResult<Vec<CompactBlock>> {
+        let blocks = self.blocks.lock().unwrap();
+        Ok(blocks
+            .iter()
+            .filter(|block| block.height >= from && block.height <= to)
+            .cloned()
+            .collect())
+    }
+}
+
+/// The hash of the fixture block, the blocks of the different branches have the different `branch_id`.
+fn fixture_block_hash(height: u64, branch_id: u8) -> Vec<u8> {
+    let mut hash = vec![branch_id; 32];
+    hash[..8].copy_from_slice(&height.to_le_bytes());
+    hash
+}
+
+/// The chain of the blocks without the shielded transactions from `from` to `to` inclusive.
+/// The blocks from `fork_height` belong to the `branch_id` branch.
+fn fixture_chain(from: u64, to: u64, fork_height: u64, branch_id: u8) -> Vec<CompactBlock> {
+    let branch_at = |height: u64| if height >= fork_height { branch_id } else { 0 };
+    (from..=to)
+        .map(|height| CompactBlock {
+            proto_version: 1,
+            height,
+            hash: fixture_block_hash(height, branch_at(height)),
+            prev_hash: fixture_block_hash(height - 1, branch_at(height - 1)),
+            time: 1600000000 + (height - FIXTURE_BIRTHDAY) as u32 * 60,
+            header: Vec::new(),
+            vtx: Vec::new(),
+        })
+        .collect()
+}
+
+fn test_z_key() -> ExtendedSpendingKey {
+    decode_extended_spending_key(z_mainnet_constants::HRP_SAPLING_EXTENDED_SPENDING_KEY, TEST_Z_KEY)
+        .unwrap()
+        .unwrap()
+}
+
+/// The compact transaction of the `height` block sending the `value` note to the z_addr of `TEST_Z_KEY`.
+fn fixture_tx_to_test_z_key(height: u64, value: u64) -> CompactTx {
+    let (_, z_addr) = test_z_key().default_address().unwrap();
+    let mut tx_builder = ZTxBuilder::new(consensus::MAIN_NETWORK, BlockHeight::from_u32(height as u32));
+
+    // the transparent input funding the note isn't checked by the light client
+    let key_pair = key_pair_from_seed("z light client fixture").unwrap();
+    let secret = SecretKey::from_slice(&*key_pair.private().secret).unwrap();
+    let prev_script = ScriptBuilder::build_p2pkh(&key_pair.public().address_hash());
+    let tx_out = TxOut {
+        value: Amount::from_u64(value).unwrap() + DEFAULT_FEE,
+        script_pubkey: ZCashScript(prev_script.to_vec()),
+    };
+    tx_builder
+        .add_transparent_input(
+            secret,
+            ZCashOutpoint::new([height as u8; 32], 0),
+            SEQUENCE_FINAL,
+            ZCashScript(Vec::new()),
+            tx_out,
+        )
+        .unwrap();
+    tx_builder
+        .add_sapling_output(None, z_addr, Amount::from_u64(value).unwrap(), None)
+        .unwrap();
+    let (zcash_tx, _) = tx_builder
+        .build(consensus::BranchId::Sapling, &LocalTxProver::bundled())
+        .unwrap();
+
+    let mut tx_buffer = Vec::with_capacity(1024);
+    zcash_tx.write(&mut tx_buffer).unwrap();
+    let tx: UtxoTx = deserialize(tx_buffer.as_slice()).unwrap();
+    let outputs = tx
+        .shielded_outputs
+        .iter()
+        .map(|output| CompactOutput {
+            cmu: output.cmu.as_slice().to_vec(),
+            epk: output.ephemeral_key.as_slice().to_vec(),
+            // the compact ciphertext is the note plaintext without the memo
+            ciphertext: output.enc_cipher_text.as_slice()[..52].to_vec(),
+        })
+        .collect();
+    CompactTx {
+        index: 1,
+        hash: tx.hash().as_slice().to_vec(),
+        fee: 0,
+        spends: Vec::new(),
+        outputs,
+    }
+}
+
+/// Returns the light client and the blocks served to it.
+fn fixture_light_client(test_name: &str, blocks: Vec<CompactBlock>) -> (ZLightClient, Arc<Mutex<Vec<CompactBlock>>>) {
+    let db_dir = std::env::temp_dir().join(format!("{}_{}", test_name, now_ms()));
+    let blocks = Arc::new(Mutex::new(blocks));
+    let light_client = fixture_light_client_in_dir(&db_dir, &test_z_key(), blocks.clone());
+    (light_client, blocks)
+}
+
+/// Opens the light client of the `z_key` in the `db_dir` serving it the `blocks`.
+fn fixture_light_client_in_dir(
+    db_dir: &Path,
+    z_key: &ExtendedSpendingKey,
+    blocks: Arc<Mutex<Vec<CompactBlock>>>,
+) -> ZLightClient {
+    let source = FixtureBlockSource { blocks };
+    block_on(ZLightClient::init(
+        Box::new(source),
+        db_dir,
+        "ZOMBIE",
+        ExtendedFullViewingKey::from(z_key),
+        Some(FIXTURE_BIRTHDAY),
+    ))
+    .unwrap()
+}
 
 #[test]
 fn zombie_coin_send_and_refund_maker_payment() {
@@ -33,7 +193,14 @@ fn zombie_coin_send_and_refund_maker_payment() {
     let taker_pub = coin.utxo_arc.key_pair.public();
     let secret_hash = [0; 20];
     let tx = coin
-        .send_maker_payment(lock_time, &*taker_pub, &secret_hash, "0.01".parse().unwrap(), &None)
+        .send_maker_payment(
+            lock_time,
+            &*taker_pub,
+            &secret_hash,
+            "0.01".parse().unwrap(),
+            &None,
+            &None,
+        )
         .wait()
         .unwrap();
     println!("swap tx {}", hex::encode(&tx.tx_hash().0));
@@ -74,7 +241,14 @@ fn zombie_coin_send_and_spend_maker_payment() {
     let secret = [0; 32];
     let secret_hash = dhash160(&secret);
     let tx = coin
-        .send_maker_payment(lock_time, &*taker_pub, &*secret_hash, "0.01".parse().unwrap(), &None)
+        .send_maker_payment(
+            lock_time,
+            &*taker_pub,
+            &*secret_hash,
+            "0.01".parse().unwrap(),
+            &None,
+            &None,
+        )
         .wait()
         .unwrap();
     println!("swap tx {}", hex::encode(&tx.tx_hash().0));
@@ -170,3 +344,144 @@ fn zombie_coin_send_and_spend_dex_fee() {
     .unwrap();
     println!("dex fee spend tx {}", hex::encode(&*spend.hash().reversed()));
 }
+
+#[test]
+fn zombie_coin_native_withdraw() {
+    let conf = json!({
+        "coin": "ZOMBIE",
+        "asset": "ZOMBIE",
+        "fname": "ZOMBIE (TESTCOIN)",
+        "txversion": 4,
+        "overwintered": 1,
+        "mm2": 1,
+    });
+    let req = json!({
+        "method": "enable",
+        "coin": "ZOMBIE"
+    });
+
+    let ctx = MmCtxBuilder::default().into_mm_arc();
+    let priv_key = [1; 32];
+    let z_key = decode_extended_spending_key(z_mainnet_constants::HRP_SAPLING_EXTENDED_SPENDING_KEY, TEST_Z_KEY)
+        .unwrap()
+        .unwrap();
+
+    let coin = block_on(z_coin_from_conf_and_request_with_z_key(
+        &ctx, "ZOMBIE", &conf, &req, &priv_key, z_key,
+    ))
+    .unwrap();
+
+    // the transaction spending the notes known to the daemon is built and signed locally, but not broadcasted
+    let to = coin.utxo_arc.my_address.to_string();
+    let withdraw_req = serde_json::from_value(json!({
+        "coin": "ZOMBIE",
+        "to": to,
+        "amount": "0.01",
+    }))
+    .unwrap();
+    let tx_details = coin.withdraw(withdraw_req).wait().unwrap();
+    assert_eq!(tx_details.to, vec![to]);
+    let fee = big_decimal_from_sat(i64::from(DEFAULT_FEE), coin.utxo_arc.decimals);
+    assert_eq!(tx_details.spent_by_me, "0.01".parse::<BigDecimal>().unwrap() + fee);
+
+    let tx = ZTransaction::read(tx_details.tx_hex.as_slice()).unwrap();
+    assert!(!tx.shielded_spends.is_empty());
+    assert_eq!(tx.vout.len(), 1);
+}
+
+#[test]
+fn test_light_client_sync_from_fixtures() {
+    let blocks = fixture_chain(FIXTURE_BIRTHDAY + 1, FIXTURE_BIRTHDAY + 1500, u64::MAX, 0);
+    let (light_client, _source) = fixture_light_client("test_light_client_sync_from_fixtures", blocks);
+    assert_eq!(light_client.scanned_height().unwrap(), Some(FIXTURE_BIRTHDAY));
+
+    // the blocks are downloaded by batches
+    assert!(!block_on(light_client.sync_once()).unwrap());
+    assert_eq!(light_client.scanned_height().unwrap(), Some(FIXTURE_BIRTHDAY + 1000));
+    assert!(block_on(light_client.sync_once()).unwrap());
+    assert_eq!(light_client.scanned_height().unwrap(), Some(FIXTURE_BIRTHDAY + 1500));
+
+    // none of the fixture outputs is decrypted with the key
+    let expected = ZBalance {
+        spendable: 0,
+        unspendable: 0,
+    };
+    assert_eq!(light_client.balance().unwrap(), expected);
+}
+
+#[test]
+fn test_light_client_finds_notes_of_the_key() {
+    let mut blocks = fixture_chain(FIXTURE_BIRTHDAY + 1, FIXTURE_BIRTHDAY + 20, u64::MAX, 0);
+    // the old note is spendable, the one of the chain tip block doesn't have enough confirmations yet
+    blocks[1]
+        .vtx
+        .push(fixture_tx_to_test_z_key(FIXTURE_BIRTHDAY + 2, 100000));
+    blocks[19]
+        .vtx
+        .push(fixture_tx_to_test_z_key(FIXTURE_BIRTHDAY + 20, 50000));
+    let (light_client, _source) = fixture_light_client("test_light_client_finds_notes_of_the_key", blocks);
+    assert!(block_on(light_client.sync_once()).unwrap());
+
+    let expected = ZBalance {
+        spendable: 100000,
+        unspendable: 50000,
+    };
+    assert_eq!(light_client.balance().unwrap(), expected);
+
+    // the value of the selected notes is less than the target as the second note isn't spendable
+    let (target_height, notes) = light_client
+        .select_spendable_notes(Amount::from_u64(120000).unwrap())
+        .unwrap();
+    assert_eq!(target_height, BlockHeight::from_u32(FIXTURE_BIRTHDAY as u32 + 21));
+    assert_eq!(notes.len(), 1);
+    assert_eq!(notes[0].note_value, Amount::from_u64(100000).unwrap());
+    // the witness of the note is updated up to the anchor, so the note can be spent
+    assert!(notes[0].witness.path().is_some());
+}
+
+#[test]
+fn test_light_client_keeps_wallets_of_different_keys_apart() {
+    let mut blocks = fixture_chain(FIXTURE_BIRTHDAY + 1, FIXTURE_BIRTHDAY + 20, u64::MAX, 0);
+    blocks[1]
+        .vtx
+        .push(fixture_tx_to_test_z_key(FIXTURE_BIRTHDAY + 2, 100000));
+    let blocks = Arc::new(Mutex::new(blocks));
+    let db_dir = std::env::temp_dir().join(format!(
+        "test_light_client_keeps_wallets_of_different_keys_apart_{}",
+        now_ms()
+    ));
+    let light_client = fixture_light_client_in_dir(&db_dir, &test_z_key(), blocks.clone());
+    assert!(block_on(light_client.sync_once()).unwrap());
+    assert_eq!(light_client.balance().unwrap().spendable, 100000);
+    drop(light_client);
+
+    // the coin is enabled with the other key, its wallet doesn't have the notes of the first key
+    let other_z_key = ExtendedSpendingKey::master(&[1; 32]);
+    let light_client = fixture_light_client_in_dir(&db_dir, &other_z_key, blocks.clone());
+    assert_eq!(light_client.scanned_height().unwrap(), Some(FIXTURE_BIRTHDAY));
+    assert!(block_on(light_client.sync_once()).unwrap());
+    assert_eq!(light_client.balance().unwrap().spendable, 0);
+    drop(light_client);
+
+    // the wallet of the first key is synced already
+    let light_client = fixture_light_client_in_dir(&db_dir, &test_z_key(), blocks);
+    assert_eq!(light_client.scanned_height().unwrap(), Some(FIXTURE_BIRTHDAY + 20));
+    assert_eq!(light_client.balance().unwrap().spendable, 100000);
+}
+
+#[test]
+fn test_light_client_rewinds_on_reorg() {
+    let blocks = fixture_chain(FIXTURE_BIRTHDAY + 1, FIXTURE_BIRTHDAY + 20, u64::MAX, 0);
+    let (light_client, blocks) = fixture_light_client("test_light_client_rewinds_on_reorg", blocks);
+    assert!(block_on(light_client.sync_once()).unwrap());
+    assert_eq!(light_client.scanned_height().unwrap(), Some(FIXTURE_BIRTHDAY + 20));
+
+    // the longer branch forks from the 15th block
+    *blocks.lock().unwrap() = fixture_chain(FIXTURE_BIRTHDAY + 1, FIXTURE_BIRTHDAY + 25, FIXTURE_BIRTHDAY + 15, 1);
+    block_on(light_client.sync_once()).unwrap();
+    let scanned = light_client.scanned_height().unwrap().unwrap();
+    assert!(scanned < FIXTURE_BIRTHDAY + 15, "the wallet isn't rewound: {}", scanned);
+
+    assert!(block_on(light_client.sync_once()).unwrap());
+    assert_eq!(light_client.scanned_height().unwrap(), Some(FIXTURE_BIRTHDAY + 25));
+}
@@ -10,6 +10,8 @@ use super::ZCoin;
 use crate::utxo::rpc_clients::{UtxoRpcClientEnum, UtxoRpcError};
 use crate::utxo::sat_from_big_decimal;
 use crate::utxo::utxo_common::{big_decimal_from_sat_unsigned, dex_fee_script, payment_script};
+use crate::utxo::UtxoKeyPair;
+use crate::watch_only::WatchOnlyError;
 use bigdecimal::BigDecimal;
 use bitcrypto::dhash160;
 use chain::Transaction as UtxoTx;
@@ -34,6 +36,8 @@ pub enum ZSendHtlcError {
     ZOperationFailed(Vec<ZOperationStatus<ZOperationTxid>>),
     ZOperationStatusesEmpty,
     RpcError(UtxoRpcError),
+    #[display(fmt = "The HTLC can't be sent by the light client yet")]
+    NotSupportedByLightClient,
 }
 
 impl From<UtxoRpcError> for ZSendHtlcError {
@@ -48,6 +52,10 @@ pub async fn z_send_htlc(
     secret_hash: &[u8],
     amount: BigDecimal,
 ) -> Result<UtxoTx, MmError<ZSendHtlcError>> {
+    // the HTLC is sent by `z_sendmany` of the native daemon
+    if coin.z_fields.light_client.is_some() {
+        return MmError::err(ZSendHtlcError::NotSupportedByLightClient);
+    }
     let _lock = coin.z_fields.z_tx_mutex.lock().await;
     let payment_script = payment_script(time_lock, secret_hash, coin.utxo_arc.key_pair.public(), &other_pub);
     let hash = dhash160(&payment_script);
@@ -56,6 +64,7 @@ pub async fn z_send_htlc(
         t_addr_prefix: coin.utxo_arc.conf.p2sh_t_addr_prefix,
         hash,
         checksum_type: coin.utxo_arc.conf.checksum_type,
+        taproot_output_key: None,
     };
 
     let amount_sat = sat_from_big_decimal(&amount, coin.utxo_arc.decimals).expect("temporary code");
@@ -109,6 +118,9 @@ pub async fn z_send_dex_fee(
     watcher_pub: &Public,
     amount: BigDecimal,
 ) -> Result<(UtxoTx, Script), MmError<ZSendHtlcError>> {
+    if coin.z_fields.light_client.is_some() {
+        return MmError::err(ZSendHtlcError::NotSupportedByLightClient);
+    }
     let _lock = coin.z_fields.z_tx_mutex.lock().await;
     let payment_script = dex_fee_script([0; 16], time_lock, watcher_pub, coin.utxo_arc.key_pair.public());
     let hash = dhash160(&payment_script);
@@ -117,6 +129,7 @@ pub async fn z_send_dex_fee(
         t_addr_prefix: coin.utxo_arc.conf.p2sh_t_addr_prefix,
         hash,
         checksum_type: coin.utxo_arc.conf.checksum_type,
+        taproot_output_key: None,
     };
 
     let amount_sat = sat_from_big_decimal(&amount, coin.utxo_arc.decimals).expect("temporary code");
@@ -175,6 +188,11 @@ pub async fn z_send_dex_fee(
 pub enum ZP2SHSpendError {
     ZTxBuilderError(ZTxBuilderError),
     Rpc(UtxoRpcError),
+    WatchOnly(WatchOnlyError),
+}
+
+impl From<WatchOnlyError> for ZP2SHSpendError {
+    fn from(e: WatchOnlyError) -> ZP2SHSpendError { ZP2SHSpendError::WatchOnly(e) }
 }
 
 impl From<ZTxBuilderError> for ZP2SHSpendError {
@@ -198,8 +216,13 @@ pub async fn z_p2sh_spend(
     let mut tx_builder = ZTxBuilder::new(consensus::MAIN_NETWORK, current_block.into());
     tx_builder.set_lock_time(tx_locktime);
 
-    let secp_secret =
-        SecretKey::from_slice(&*coin.utxo_arc.key_pair.private().secret).expect("Keypair contains a valid secret key");
+    // the ZCash transaction builder signs the transparent input itself, so the private key has to be kept by MM2
+    // (ZHTLC coins can't be activated with the external signer)
+    let key_pair = match &coin.utxo_arc.key_pair {
+        UtxoKeyPair::KeyPair(key_pair) => key_pair,
+        UtxoKeyPair::External(_) | UtxoKeyPair::WatchOnly(_) => return MmError::err(WatchOnlyError.into()),
+    };
+    let secp_secret = SecretKey::from_slice(&*key_pair.private().secret).expect("Keypair contains a valid secret key");
 
     let outpoint = ZCashOutpoint::new(p2sh_tx.hash().into(), 0);
     let tx_out = TxOut {
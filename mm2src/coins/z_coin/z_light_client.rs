@@ -0,0 +1,418 @@
+//! The light client finding the notes of the z_addr without the native daemon.
+//!
+//! The compact blocks are downloaded from a lightwalletd server to the blocks cache DB,
+//! then they are scanned with the viewing key of the coin and the found notes and their witnesses
+//! are stored in the wallet DB. Both DBs are SQLite files in the MM2 DB directory
+//! named after the coin ticker and the fingerprint of the viewing key.
+
+use super::ZCoinFields;
+use async_trait::async_trait;
+use bitcrypto::dhash160;
+use common::executor::{spawn_blocking, Timer};
+use common::mm_error::prelude::*;
+use common::rusqlite::{Connection, Error as SqlError, ToSql, NO_PARAMS};
+use derive_more::Display;
+use http::Uri;
+use prost::Message;
+use std::path::Path;
+use std::sync::{Arc, Mutex, Weak};
+use tonic::transport::{Channel, ClientTlsConfig};
+use zcash_client_backend::data_api::chain::{scan_cached_blocks, validate_chain};
+use zcash_client_backend::data_api::error::Error as ChainError;
+use zcash_client_backend::data_api::{WalletRead, WalletWrite};
+use zcash_client_backend::wallet::{AccountId, SpendableNote};
+use zcash_client_sqlite::chain::init::init_cache_database;
+use zcash_client_sqlite::error::SqliteClientError;
+use zcash_client_sqlite::wallet::get_balance;
+use zcash_client_sqlite::wallet::init::{init_accounts_table, init_blocks_table, init_wallet_db};
+use zcash_client_sqlite::{BlockDb, WalletDb};
+use zcash_primitives::block::BlockHash;
+use zcash_primitives::consensus::{BlockHeight, MainNetwork, MAIN_NETWORK};
+use zcash_primitives::transaction::components::Amount;
+use zcash_primitives::zip32::ExtendedFullViewingKey;
+
+mod z_coin_grpc {
+    tonic::include_proto!("cash.z.wallet.sdk.rpc");
+}
+use z_coin_grpc::compact_tx_streamer_client::CompactTxStreamerClient;
+pub use z_coin_grpc::{BlockId, BlockRange, ChainSpec, CompactBlock, CompactOutput, CompactSpend, CompactTx, TreeState};
+
+/// The wallet has the only account derived from the `z_spending_key` of the coin.
+const ACCOUNT: AccountId = AccountId(0);
+/// The max number of the blocks downloaded at once.
+const BLOCKS_BATCH_SIZE: u64 = 1000;
+/// The number of the blocks rescanned on the chain reorganization.
+const REWIND_DEPTH: u32 = 10;
+/// The interval of the sync when the wallet is up to date with the chain tip.
+const SYNC_INTERVAL: f64 = 10.;
+
+pub type ZLightClientResult<T> = Result<T, MmError<ZLightClientError>>;
+
+#[derive(Debug, Display)]
+pub enum ZLightClientError {
+    #[display(fmt = "lightwalletd transport error: {}", _0)]
+    Transport(String),
+    #[display(fmt = "lightwalletd error: {}", _0)]
+    Grpc(String),
+    #[display(fmt = "Wallet DB error: {}", _0)]
+    WalletDb(String),
+    #[display(fmt = "Invalid response: {}", _0)]
+    InvalidResponse(String),
+}
+
+impl From<tonic::Status> for ZLightClientError {
+    fn from(status: tonic::Status) -> Self { ZLightClientError::Grpc(status.to_string()) }
+}
+
+impl From<tonic::transport::Error> for ZLightClientError {
+    fn from(e: tonic::transport::Error) -> Self { ZLightClientError::Transport(e.to_string()) }
+}
+
+impl From<SqliteClientError> for ZLightClientError {
+    fn from(e: SqliteClientError) -> Self { ZLightClientError::WalletDb(e.to_string()) }
+}
+
+impl From<SqlError> for ZLightClientError {
+    fn from(e: SqlError) -> Self { ZLightClientError::WalletDb(e.to_string()) }
+}
+
+/// The source of the compact blocks and the Sapling commitment tree states.
+#[async_trait]
+pub trait CompactBlockSource: Send + Sync {
+    async fn latest_height(&self) -> ZLightClientResult<u64>;
+
+    /// Returns the Sapling commitment tree state at the given block.
+    async fn tree_state(&self, height: u64) -> ZLightClientResult<TreeState>;
+
+    /// Returns the blocks from `from` to `to` inclusive.
+    async fn compact_blocks(&self, from: u64, to: u64) -> ZLightClientResult<Vec<CompactBlock>>;
+}
+
+/// The gRPC client of the lightwalletd server.
+pub struct LightwalletdClient {
+    client: CompactTxStreamerClient<Channel>,
+}
+
+impl LightwalletdClient {
+    /// Connects to the first available server of the list.
+    pub async fn connect(urls: &[String]) -> ZLightClientResult<LightwalletdClient> {
+        let mut errors = Vec::with_capacity(urls.len());
+        for url in urls {
+            match Self::connect_to(url).await {
+                Ok(client) => return Ok(client),
+                Err(e) => errors.push(format!("{}: {}", url, e)),
+            }
+        }
+        MmError::err(ZLightClientError::Transport(format!(
+            "Couldn't connect to any of the lightwalletd servers: {:?}",
+            errors
+        )))
+    }
+
+    async fn connect_to(url: &str) -> ZLightClientResult<LightwalletdClient> {
+        let uri: Uri = url
+            .parse()
+            .map_to_mm(|e: http::uri::InvalidUri| ZLightClientError::Transport(e.to_string()))?;
+        let mut endpoint = Channel::builder(uri.clone());
+        if uri.scheme_str() == Some("https") {
+            endpoint = endpoint.tls_config(ClientTlsConfig::new())?;
+        }
+        let channel = endpoint.connect().await?;
+        Ok(LightwalletdClient {
+            client: CompactTxStreamerClient::new(channel),
+        })
+    }
+}
+
+#[async_trait]
+impl CompactBlockSource for LightwalletdClient {
+    async fn latest_height(&self) -> ZLightClientResult<u64> {
+        // the client is a cheap handle of the shared channel
+        let mut client = self.client.clone();
+        let block_id = client.get_latest_block(ChainSpec {}).await?.into_inner();
+        Ok(block_id.height)
+    }
+
+    async fn tree_state(&self, height: u64) -> ZLightClientResult<TreeState> {
+        let mut client = self.client.clone();
+        let request = BlockId {
+            height,
+            hash: Vec::new(),
+        };
+        Ok(client.get_tree_state(request).await?.into_inner())
+    }
+
+    async fn compact_blocks(&self, from: u64, to: u64) -> ZLightClientResult<Vec<CompactBlock>> {
+        let mut client = self.client.clone();
+        let request = BlockRange {
+            start: Some(BlockId {
+                height: from,
+                hash: Vec::new(),
+            }),
+            end: Some(BlockId {
+                height: to,
+                hash: Vec::new(),
+            }),
+        };
+        let mut stream = client.get_block_range(request).await?.into_inner();
+        let mut blocks = Vec::with_capacity((to + 1).saturating_sub(from) as usize);
+        while let Some(block) = stream.message().await? {
+            blocks.push(block);
+        }
+        Ok(blocks)
+    }
+}
+
+/// The balance of the z_addr known to the wallet DB in satoshis.
+#[derive(Debug, PartialEq)]
+pub struct ZBalance {
+    /// The value of the notes having enough confirmations to be spent.
+    pub spendable: i64,
+    /// The value of the notes that can't be spent yet.
+    pub unspendable: i64,
+}
+
+pub struct ZLightClient {
+    block_source: Box<dyn CompactBlockSource>,
+    /// The DBs are shared with the blocking threads scanning the blocks.
+    dbs: Arc<ZLightClientDbs>,
+}
+
+struct ZLightClientDbs {
+    /// The wallet DB storing the found notes, their witnesses and nullifiers.
+    wallet_db: Mutex<WalletDb<MainNetwork>>,
+    /// The blocks cache read by the scanner.
+    blocks_db: Mutex<BlockDb>,
+    /// The connection to the blocks cache DB adding and removing the blocks, `BlockDb` only reads them.
+    cache_conn: Mutex<Connection>,
+}
+
+impl std::fmt::Debug for ZLightClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result { write!(f, "ZLightClient") }
+}
+
+impl ZLightClient {
+    /// Opens the DBs of the `ticker` and the `extfvk` in the `db_dir` creating them if required.
+    /// The DBs of the other viewing key of the same coin are kept apart, so its notes are never mixed in.
+    /// The new wallet is synced starting from the `sync_from_height` or from the chain tip if it's not set,
+    /// so the notes received before that height are not found.
+    pub async fn init(
+        block_source: Box<dyn CompactBlockSource>,
+        db_dir: &Path,
+        ticker: &str,
+        extfvk: ExtendedFullViewingKey,
+        sync_from_height: Option<u64>,
+    ) -> ZLightClientResult<ZLightClient> {
+        std::fs::create_dir_all(db_dir).map_to_mm(|e| ZLightClientError::WalletDb(e.to_string()))?;
+        let mut extfvk_bytes = Vec::new();
+        extfvk
+            .write(&mut extfvk_bytes)
+            .map_to_mm(|e| ZLightClientError::WalletDb(e.to_string()))?;
+        let fingerprint = hex::encode(&dhash160(&extfvk_bytes).as_slice()[..8]);
+        let wallet_db_path = db_dir.join(format!("{}_{}_wallet.db", ticker, fingerprint));
+        let cache_db_path = db_dir.join(format!("{}_{}_cache.db", ticker, fingerprint));
+
+        let wallet_db = WalletDb::for_path(&wallet_db_path, MAIN_NETWORK)?;
+        init_wallet_db(&wallet_db)?;
+        let blocks_db = BlockDb::for_path(&cache_db_path)?;
+        init_cache_database(&blocks_db)?;
+        let cache_conn = Connection::open(&cache_db_path)?;
+
+        let is_new_wallet = wallet_db.get_extended_full_viewing_keys()?.is_empty();
+        if is_new_wallet {
+            let height = match sync_from_height {
+                Some(height) => height,
+                None => block_source.latest_height().await?,
+            };
+            let tree_state = block_source.tree_state(height).await?;
+            // the hash of the block is displayed in the reversed order
+            let mut hash =
+                hex::decode(&tree_state.hash).map_to_mm(|e| ZLightClientError::InvalidResponse(e.to_string()))?;
+            hash.reverse();
+            let sapling_tree =
+                hex::decode(&tree_state.tree).map_to_mm(|e| ZLightClientError::InvalidResponse(e.to_string()))?;
+            init_accounts_table(&wallet_db, &[extfvk])?;
+            init_blocks_table(
+                &wallet_db,
+                BlockHeight::from_u32(tree_state.height as u32),
+                BlockHash::from_slice(&hash),
+                tree_state.time,
+                &sapling_tree,
+            )?;
+        }
+
+        let dbs = ZLightClientDbs {
+            wallet_db: Mutex::new(wallet_db),
+            blocks_db: Mutex::new(blocks_db),
+            cache_conn: Mutex::new(cache_conn),
+        };
+        Ok(ZLightClient {
+            block_source,
+            dbs: Arc::new(dbs),
+        })
+    }
+
+    /// Downloads the next batch of the blocks and scans them.
+    /// Returns whether the wallet is synced up to the chain tip.
+    pub async fn sync_once(&self) -> ZLightClientResult<bool> {
+        let tip = self.block_source.latest_height().await?;
+        let dbs = self.dbs.clone();
+        let from = spawn_blocking(move || dbs.last_known_height()).await? + 1;
+        let to = tip.min(from + BLOCKS_BATCH_SIZE - 1);
+        let blocks = if from <= to {
+            self.block_source.compact_blocks(from, to).await?
+        } else {
+            Vec::new()
+        };
+        // the trial decryption of the outputs of the whole batch takes a while
+        let dbs = self.dbs.clone();
+        spawn_blocking(move || {
+            dbs.insert_into_cache(blocks)?;
+            dbs.scan_cached_blocks()
+        })
+        .await?;
+        Ok(to >= tip)
+    }
+
+    /// Returns the height of the last scanned block or `None` if the wallet isn't synced yet.
+    pub fn scanned_height(&self) -> ZLightClientResult<Option<u64>> {
+        let extrema = self.dbs.wallet_db.lock().unwrap().block_height_extrema()?;
+        Ok(extrema.map(|(_, max)| u32::from(max) as u64))
+    }
+
+    pub fn balance(&self) -> ZLightClientResult<ZBalance> {
+        let wallet_db = self.dbs.wallet_db.lock().unwrap();
+        let anchor_height = match wallet_db.get_target_and_anchor_heights()? {
+            Some((_, anchor_height)) => anchor_height,
+            None => {
+                return Ok(ZBalance {
+                    spendable: 0,
+                    unspendable: 0,
+                })
+            },
+        };
+        let spendable = i64::from(wallet_db.get_balance_at(ACCOUNT, anchor_height)?);
+        let total = i64::from(get_balance(&wallet_db, ACCOUNT)?);
+        Ok(ZBalance {
+            spendable,
+            unspendable: total - spendable,
+        })
+    }
+
+    /// Selects the notes to spend the `target_value` at least.
+    /// Returns the height of the transaction to be built and the notes,
+    /// the value of the notes is less than the target if the balance isn't sufficient.
+    pub fn select_spendable_notes(
+        &self,
+        target_value: Amount,
+    ) -> ZLightClientResult<(BlockHeight, Vec<SpendableNote>)> {
+        let wallet_db = self.dbs.wallet_db.lock().unwrap();
+        let (target_height, anchor_height) = wallet_db
+            .get_target_and_anchor_heights()?
+            .or_mm_err(|| ZLightClientError::WalletDb("The wallet isn't synced yet".to_owned()))?;
+        let notes = wallet_db.select_spendable_notes(ACCOUNT, target_value, anchor_height)?;
+        Ok((target_height, notes))
+    }
+}
+
+impl ZLightClientDbs {
+    /// Returns the last block height that is either scanned or cached.
+    fn last_known_height(&self) -> ZLightClientResult<u64> {
+        let scanned = match self.wallet_db.lock().unwrap().block_height_extrema()? {
+            Some((_, max)) => u32::from(max) as u64,
+            None => 0,
+        };
+        // `MAX` returns NULL if the cache is empty
+        let cached: Option<u32> =
+            self.cache_conn
+                .lock()
+                .unwrap()
+                .query_row("SELECT MAX(height) FROM compactblocks", NO_PARAMS, |row| row.get(0))?;
+        Ok(scanned.max(cached.unwrap_or_default() as u64))
+    }
+
+    fn insert_into_cache(&self, blocks: Vec<CompactBlock>) -> ZLightClientResult<()> {
+        let mut conn = self.cache_conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        for block in blocks {
+            let height = block.height as i64;
+            let data = block.encode_to_vec();
+            let params: [&dyn ToSql; 2] = [&height, &data];
+            tx.execute(
+                "INSERT OR REPLACE INTO compactblocks (height, data) VALUES (?1, ?2)",
+                &params,
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Validates the cached blocks against the scanned ones, rewinds the wallet on the chain reorganization
+    /// and scans the valid blocks removing them from the cache.
+    fn scan_cached_blocks(&self) -> ZLightClientResult<()> {
+        let wallet_db = self.wallet_db.lock().unwrap();
+        let blocks_db = self.blocks_db.lock().unwrap();
+        let mut wallet_ops = wallet_db.get_update_ops()?;
+
+        match validate_chain(&MAIN_NETWORK, &*blocks_db, wallet_db.get_max_height_hash()?) {
+            Ok(()) => (),
+            Err(SqliteClientError::BackendError(ChainError::InvalidChain(lower_bound, _))) => {
+                // the wallet can't be rewound below the height it's synced from
+                let birthday = match wallet_db.block_height_extrema()? {
+                    Some((min, _)) => min,
+                    None => lower_bound,
+                };
+                let rewind_height = if lower_bound > birthday + REWIND_DEPTH {
+                    lower_bound - REWIND_DEPTH
+                } else {
+                    birthday
+                };
+                wallet_ops.rewind_to_height(rewind_height)?;
+                // the blocks of the abandoned branch are downloaded again
+                let params = [u32::from(rewind_height)];
+                self.cache_conn
+                    .lock()
+                    .unwrap()
+                    .execute("DELETE FROM compactblocks WHERE height > ?", &params)?;
+                return Ok(());
+            },
+            Err(e) => return MmError::err(e.into()),
+        }
+
+        scan_cached_blocks(&MAIN_NETWORK, &*blocks_db, &mut wallet_ops, None)?;
+        if let Some((_, scanned)) = wallet_db.block_height_extrema()? {
+            let params = [u32::from(scanned)];
+            self.cache_conn
+                .lock()
+                .unwrap()
+                .execute("DELETE FROM compactblocks WHERE height <= ?", &params)?;
+        }
+        Ok(())
+    }
+}
+
+/// Syncs the light client while the coin is enabled.
+pub async fn light_client_sync_loop(weak: Weak<ZCoinFields>, ticker: String) {
+    loop {
+        let fields = match weak.upgrade() {
+            Some(fields) => fields,
+            // the coin has been disabled
+            None => break,
+        };
+        let light_client = match fields.light_client {
+            Some(ref light_client) => light_client,
+            None => break,
+        };
+        let interval = match light_client.sync_once().await {
+            // there are more blocks to download
+            Ok(false) => 0.,
+            Ok(true) => SYNC_INTERVAL,
+            Err(e) => {
+                error!("{} light client sync error: {}", ticker, e);
+                SYNC_INTERVAL
+            },
+        };
+        drop(fields);
+        Timer::sleep(interval).await;
+    }
+}
@@ -58,6 +58,42 @@ pub enum ZOperationStatus<T> {
     },
 }
 
+/// The note of the z_addr returned by `z_listunspent`.
+#[derive(Debug, Deserialize)]
+pub struct ZUnspent {
+    pub txid: H256Json,
+    /// The index of the sapling output of the transaction.
+    #[serde(rename = "outindex")]
+    pub out_index: usize,
+    pub confirmations: u32,
+    pub spendable: bool,
+    pub amount: MmNumber,
+}
+
+/// The state of the note commitment trees after the block, returned by `z_gettreestate`.
+#[derive(Debug, Deserialize)]
+pub struct ZTreeState {
+    pub hash: H256Json,
+    pub height: u64,
+    pub sapling: ZSaplingTreeState,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ZSaplingTreeState {
+    /// The hash of the last block having changed the sapling tree, it's set instead of `commitments`
+    /// if the tree isn't changed by this block.
+    #[serde(rename = "skipHash")]
+    pub skip_hash: Option<H256Json>,
+    pub commitments: Option<ZSaplingCommitments>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ZSaplingCommitments {
+    /// The serialized sapling commitment tree.
+    #[serde(rename = "finalState")]
+    pub final_state: BytesJson,
+}
+
 #[derive(Debug, Serialize)]
 pub struct ZSendManyHtlcParams {
     pub pubkey: H264Json,
@@ -75,6 +111,14 @@ pub trait ZRpcOps {
     fn z_get_send_many_status(&self, op_ids: &[&str]) -> UtxoRpcFut<Vec<ZOperationStatus<ZOperationTxid>>>;
 
     fn z_send_many(&self, from_address: &str, send_to: Vec<ZSendManyItem>) -> UtxoRpcFut<String>;
+
+    fn z_list_unspent(&self, min_conf: u32, address: &str) -> UtxoRpcFut<Vec<ZUnspent>>;
+
+    /// `hash_or_height` is the hash or the height of the block as a string.
+    fn z_get_tree_state(&self, hash_or_height: &str) -> UtxoRpcFut<ZTreeState>;
+
+    /// Returns the serialized block of the main chain.
+    fn get_block_bytes(&self, height: u64) -> UtxoRpcFut<BytesJson>;
 }
 
 impl ZRpcOps for NativeClient {
@@ -92,6 +136,26 @@ impl ZRpcOps for NativeClient {
         let fut = rpc_func!(self, "z_sendmany", from_address, send_to);
         Box::new(fut.map_to_mm_fut(UtxoRpcError::from))
     }
+
+    fn z_list_unspent(&self, min_conf: u32, address: &str) -> UtxoRpcFut<Vec<ZUnspent>> {
+        let max_conf = 9999999;
+        let include_watch_only = false;
+        let fut = rpc_func!(self, "z_listunspent", min_conf, max_conf, include_watch_only, [address]);
+        Box::new(fut.map_to_mm_fut(UtxoRpcError::from))
+    }
+
+    fn z_get_tree_state(&self, hash_or_height: &str) -> UtxoRpcFut<ZTreeState> {
+        let fut = rpc_func!(self, "z_gettreestate", hash_or_height);
+        Box::new(fut.map_to_mm_fut(UtxoRpcError::from))
+    }
+
+    fn get_block_bytes(&self, height: u64) -> UtxoRpcFut<BytesJson> {
+        // the daemon accepts the block hash or the height as a string
+        let hash_or_height = height.to_string();
+        let verbosity = 0;
+        let fut = rpc_func!(self, "getblock", hash_or_height, verbosity);
+        Box::new(fut.map_to_mm_fut(UtxoRpcError::from))
+    }
 }
 
 impl AsRef<dyn ZRpcOps + Send + Sync> for UtxoRpcClientEnum {
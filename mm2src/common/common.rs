@@ -98,6 +98,7 @@ pub mod mm_ctx;
 #[path = "mm_error/mm_error.rs"] pub mod mm_error;
 pub mod mm_number;
 pub mod privkey;
+pub mod proxy;
 pub mod seri;
 #[path = "patterns/state_machine.rs"] pub mod state_machine;
 pub mod time_cache;
@@ -669,6 +670,7 @@ pub mod wio {
     use futures_cpupool::CpuPool;
     use gstuff::{duration_to_float, now_float};
     use http::{HeaderMap, Request, StatusCode};
+    use hyper::client::connect::Connect;
     use hyper::client::HttpConnector;
     use hyper::{Body, Client};
     use hyper_rustls::HttpsConnector;
@@ -851,11 +853,17 @@ pub mod wio {
     }
 
     /// Executes a Hyper request, returning the response status, headers and body.
-    pub async fn slurp_req(request: Request<Vec<u8>>) -> SlurpRes {
+    pub async fn slurp_req(request: Request<Vec<u8>>) -> SlurpRes { slurp_req_with_client(&HYPER, request).await }
+
+    /// Executes a Hyper request with the given client, e.g. the one connecting through a proxy.
+    pub async fn slurp_req_with_client<C>(client: &Client<C>, request: Request<Vec<u8>>) -> SlurpRes
+    where
+        C: Connect + Clone + Send + Sync + 'static,
+    {
         let (head, body) = request.into_parts();
         let request = Request::from_parts(head, Body::from(body));
 
-        let request_f = HYPER.request(request);
+        let request_f = client.request(request);
         let response = try_s!(try_s!(drive03(request_f).await));
         let status = response.status();
         let headers = response.headers().clone();
@@ -1051,6 +1059,19 @@ pub mod executor {
 
     pub fn spawn_boxed(future: Box<dyn Future03<Output = ()> + Send + Unpin + 'static>) { spawn(future); }
 
+    /// Runs the blocking `f` (file I/O, DB queries, heavy computations) on the blocking threads of the shared reactor,
+    /// so it doesn't stall the other futures. The panic of `f` is resumed in the caller.
+    pub async fn spawn_blocking<F, R>(f: F) -> R
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        match crate::wio::CORE.0.spawn_blocking(f).await {
+            Ok(res) => res,
+            Err(e) => std::panic::resume_unwind(e.into_panic()),
+        }
+    }
+
     /// Schedule the given `future` to be executed shortly after the given `utc` time is reached.
     pub fn spawn_after(utc: f64, future: impl Future03<Output = ()> + Send + 'static) {
         use crossbeam::channel;
@@ -1,6 +1,7 @@
 use futures01::Future;
 use serde::de::DeserializeOwned;
 use serde_json::{self as json, Value as Json};
+use std::collections::HashMap;
 use std::fmt;
 
 /// Macro generating functions for RPC requests.
@@ -105,6 +106,8 @@ pub enum JsonRpcErrorType {
     Parse(JsonRpcRemoteAddr, String),
     /// The JSON-RPC error returned from server
     Response(JsonRpcRemoteAddr, Json),
+    /// The remote endpoint rejected the credentials of the request
+    Unauthorized(String),
 }
 
 impl JsonRpcErrorType {
@@ -116,7 +119,9 @@ impl fmt::Display for JsonRpcError {
 }
 
 pub type JsonRpcResponseFut =
-    Box<dyn Future<Item = (JsonRpcRemoteAddr, JsonRpcResponse), Error = String> + Send + 'static>;
+    Box<dyn Future<Item = (JsonRpcRemoteAddr, JsonRpcResponse), Error = JsonRpcErrorType> + Send + 'static>;
+pub type JsonRpcBatchResponseFut =
+    Box<dyn Future<Item = (JsonRpcRemoteAddr, Vec<JsonRpcResponse>), Error = JsonRpcErrorType> + Send + 'static>;
 pub type RpcRes<T> = Box<dyn Future<Item = T, Error = JsonRpcError> + Send + 'static>;
 
 pub trait JsonRpcClient {
@@ -155,18 +160,86 @@ pub trait JsonRpcMultiClient: JsonRpcClient {
     }
 }
 
+/// The trait is used when the remote endpoint supports JSON-RPC batches:
+/// several requests are sent as an array and the responses are received as an array.
+pub trait JsonRpcBatchClient: JsonRpcClient {
+    fn transport_batch(&self, requests: Vec<JsonRpcRequest>) -> JsonRpcBatchResponseFut;
+
+    /// Sends the requests in one batch, the results are returned in the order of the requests.
+    /// The batch fails if any of the requests fails.
+    fn send_batch_request<T: DeserializeOwned + Send + 'static>(
+        &self,
+        requests: Vec<JsonRpcRequest>,
+    ) -> RpcRes<Vec<T>> {
+        if requests.is_empty() {
+            return Box::new(futures01::future::ok(Vec::new()));
+        }
+        let client_info = self.client_info();
+        Box::new(
+            self.transport_batch(requests.clone())
+                .then(move |result| process_batch_transport_result(result, client_info, requests)),
+        )
+    }
+}
+
+fn process_batch_transport_result<T: DeserializeOwned + Send + 'static>(
+    result: Result<(JsonRpcRemoteAddr, Vec<JsonRpcResponse>), JsonRpcErrorType>,
+    client_info: String,
+    requests: Vec<JsonRpcRequest>,
+) -> Result<Vec<T>, JsonRpcError> {
+    let (remote_addr, responses) = match result {
+        Ok(r) => r,
+        Err(e) => {
+            let error = match e {
+                JsonRpcErrorType::Transport(e) => {
+                    JsonRpcErrorType::Transport(ERRL!("Batch of {} requests failed: {}", requests.len(), e))
+                },
+                e => e,
+            };
+            return Err(JsonRpcError {
+                client_info,
+                // the batch is reported by its first request
+                request: requests[0].clone(),
+                error,
+            });
+        },
+    };
+
+    // the responses of the batch may be returned in any order
+    let mut responses: HashMap<String, JsonRpcResponse> = responses
+        .into_iter()
+        .map(|response| (response.id.clone(), response))
+        .collect();
+    requests
+        .into_iter()
+        .map(|request| match responses.remove(request.get_id()) {
+            Some(response) => {
+                process_transport_result(Ok((remote_addr.clone(), response)), client_info.clone(), request)
+            },
+            None => Err(JsonRpcError {
+                client_info: client_info.clone(),
+                error: JsonRpcErrorType::Parse(
+                    remote_addr.clone(),
+                    ERRL!("No response to the request {:?} in the batch", request.get_id()),
+                ),
+                request,
+            }),
+        })
+        .collect()
+}
+
 fn process_transport_result<T: DeserializeOwned + Send + 'static>(
-    result: Result<(JsonRpcRemoteAddr, JsonRpcResponse), String>,
+    result: Result<(JsonRpcRemoteAddr, JsonRpcResponse), JsonRpcErrorType>,
     client_info: String,
     request: JsonRpcRequest,
 ) -> Result<T, JsonRpcError> {
     let (remote_addr, response) = match result {
         Ok(r) => r,
-        Err(e) => {
+        Err(error) => {
             return Err(JsonRpcError {
                 client_info,
                 request,
-                error: JsonRpcErrorType::Transport(e),
+                error,
             })
         },
     };
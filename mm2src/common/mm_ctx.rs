@@ -2,8 +2,9 @@ use crate::log::{self, LogState};
 use crate::mm_metrics::{MetricsArc, MetricsOps};
 use crate::{bits256, small_rng};
 use gstuff::Constructible;
+use keys::bip32::ExtendedPrivateKey;
 use keys::KeyPair;
-use primitives::hash::H160;
+use primitives::hash::{H160, H264};
 use rand::Rng;
 use serde_bytes::ByteBuf;
 use serde_json::{self as json, Value as Json};
@@ -85,6 +86,12 @@ pub struct MmCtx {
     /// secp256k1 key pair derived from passphrase.
     /// cf. `key_pair_from_seed`.
     pub secp256k1_key_pair: Constructible<KeyPair>,
+    /// BIP32 master key derived from the BIP39 mnemonic passphrase.
+    /// Set by `lp_passphrase_init` in the HD mode only (`"enable_hd": true`).
+    pub hd_master_key: Constructible<ExtendedPrivateKey>,
+    /// The node public key kept by the external signer.
+    /// Set by `lp_wallet_init` if `external_signer` is configured, the swap payments are locked with this key.
+    pub external_signer_pubkey: Constructible<H264>,
     /// Coins that should be enabled to kick start the interrupted swaps and orders.
     pub coins_needed_for_kick_start: Mutex<HashSet<String>>,
     /// The context belonging to the `lp_swap` mod: `SwapsContext`.
@@ -117,6 +124,8 @@ impl MmCtx {
             coins_ctx: Mutex::new(None),
             rmd160: Constructible::default(),
             secp256k1_key_pair: Constructible::default(),
+            hd_master_key: Constructible::default(),
+            external_signer_pubkey: Constructible::default(),
             coins_needed_for_kick_start: Mutex::new(HashSet::new()),
             swaps_ctx: Mutex::new(None),
             stats_ctx: Mutex::new(None),
@@ -163,18 +172,18 @@ impl MmCtx {
     ///     "dbdir": "c:/Users/mm2user/.mm2-db"
     ///
     /// No checks in this method, the paths should be checked in the `fn fix_directories` instead.
-    pub fn dbdir(&self) -> PathBuf {
-        let path = if let Some(dbdir) = self.conf["dbdir"].as_str() {
+    pub fn dbdir(&self) -> PathBuf { self.root_dbdir().join(hex::encode(&**self.rmd160())) }
+
+    /// The "dbdir" configuration field without the `rmd160` subdirectory.  
+    /// Used to store the data that is needed before the passphrase is known (e.g. the encrypted keystore).
+    pub fn root_dbdir(&self) -> PathBuf {
+        if let Some(dbdir) = self.conf["dbdir"].as_str() {
             let dbdir = dbdir.trim();
             if !dbdir.is_empty() {
-                Path::new(dbdir)
-            } else {
-                Path::new("DB")
+                return Path::new(dbdir).into();
             }
-        } else {
-            Path::new("DB")
-        };
-        path.join(hex::encode(&**self.rmd160()))
+        }
+        Path::new("DB").into()
     }
 
     pub fn netid(&self) -> u16 {
@@ -224,6 +233,20 @@ impl MmCtx {
         }
     }
 
+    /// Get a reference to the BIP32 master key if MM2 is running in the HD mode.
+    pub fn hd_master_key(&self) -> Option<&ExtendedPrivateKey> { self.hd_master_key.as_option() }
+
+    /// True if the coin keys are derived from the BIP39 mnemonic along the BIP44 paths.
+    pub fn enable_hd(&self) -> bool { self.hd_master_key.as_option().is_some() }
+
+    /// True if MM2 is started with the public keys of the coins instead of a passphrase.
+    /// `secp256k1_key_pair` is random then and can't be used to sign anything but the P2P messages.
+    pub fn is_watch_only(&self) -> bool { !self.conf["watch_only"].is_null() }
+
+    /// True if the private keys of the coins are kept by a separate signer process (`coins::signer`).
+    /// `secp256k1_key_pair` is random then like in the watch-only mode.
+    pub fn uses_external_signer(&self) -> bool { !self.conf["external_signer"].is_null() }
+
     /// This is our public ID, allowing us to be different from other peers.
     /// This should also be our public key which we'd use for message verification.
     pub fn public_id(&self) -> Result<bits256, String> {
@@ -18,6 +18,8 @@
 //
 
 use bitcrypto::{sha256, ChecksumType};
+use keys::bip32::{DerivationPath, ExtendedPrivateKey};
+use keys::mnemonic::seed_from_mnemonic;
 use keys::{Error as KeysError, KeyPair, Private};
 use primitives::hash::H256;
 
@@ -67,3 +69,22 @@ pub fn key_pair_from_seed(seed: &str) -> Result<KeyPair, String> {
     assert_eq!(pair.public().len(), 33);
     Ok(pair)
 }
+
+/// Derives the BIP32 master key from the given BIP39 mnemonic (without a BIP39 passphrase),
+/// so the same keys can be restored in any other BIP39 compatible wallet.
+pub fn hd_master_key_from_mnemonic(mnemonic: &str) -> Result<ExtendedPrivateKey, String> {
+    let seed = try_s!(seed_from_mnemonic(mnemonic, ""));
+    Ok(try_s!(ExtendedPrivateKey::from_seed(&seed)))
+}
+
+/// Derives the compressed key pair at the given `path` from the BIP32 master key.
+pub fn key_pair_from_hd_master_key(master: &ExtendedPrivateKey, path: &DerivationPath) -> Result<KeyPair, String> {
+    let child = try_s!(master.derive_path(path));
+    let private = Private {
+        prefix: 0,
+        secret: child.secret,
+        compressed: true,
+        checksum_type: ChecksumType::DSHA256,
+    };
+    Ok(try_s!(KeyPair::from_private(private)))
+}
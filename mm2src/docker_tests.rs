@@ -435,7 +435,7 @@ mod docker_tests {
 
         let time_lock = (now_ms() / 1000) as u32 - 3600;
         let tx = coin
-            .send_taker_payment(time_lock, &*coin.my_public_key(), &[0; 20], 1.into(), &None)
+            .send_taker_payment(time_lock, &*coin.my_public_key(), &[0; 20], 1.into(), &None, &None)
             .wait()
             .unwrap();
 
@@ -466,7 +466,7 @@ mod docker_tests {
 
         let time_lock = (now_ms() / 1000) as u32 - 3600;
         let tx = coin
-            .send_maker_payment(time_lock, &*coin.my_public_key(), &[0; 20], 1.into(), &None)
+            .send_maker_payment(time_lock, &*coin.my_public_key(), &[0; 20], 1.into(), &None, &None)
             .wait()
             .unwrap();
 
@@ -498,7 +498,14 @@ mod docker_tests {
 
         let time_lock = (now_ms() / 1000) as u32 - 3600;
         let tx = coin
-            .send_taker_payment(time_lock, &*coin.my_public_key(), &*dhash160(&secret), 1.into(), &None)
+            .send_taker_payment(
+                time_lock,
+                &*coin.my_public_key(),
+                &*dhash160(&secret),
+                1.into(),
+                &None,
+                &None,
+            )
             .wait()
             .unwrap();
 
@@ -537,7 +544,14 @@ mod docker_tests {
 
         let time_lock = (now_ms() / 1000) as u32 - 3600;
         let tx = coin
-            .send_maker_payment(time_lock, &*coin.my_public_key(), &*dhash160(&secret), 1.into(), &None)
+            .send_maker_payment(
+                time_lock,
+                &*coin.my_public_key(),
+                &*dhash160(&secret),
+                1.into(),
+                &None,
+                &None,
+            )
             .wait()
             .unwrap();
 
@@ -586,6 +600,7 @@ mod docker_tests {
                     &*dhash160(&secret),
                     1.into(),
                     &coin.swap_contract_address(),
+                    &None,
                 )
                 .wait()
                 .unwrap();
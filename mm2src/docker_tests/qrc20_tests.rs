@@ -651,6 +651,7 @@ fn test_taker_spends_maker_payment() {
             secret_hash,
             amount.clone(),
             &maker_coin.swap_contract_address(),
+            &None,
         )
         .wait()
         .unwrap();
@@ -740,6 +741,7 @@ fn test_maker_spends_taker_payment() {
             secret_hash,
             amount.clone(),
             &taker_coin.swap_contract_address(),
+            &None,
         )
         .wait()
         .unwrap();
@@ -818,6 +820,7 @@ fn test_maker_refunds_payment() {
             secret_hash,
             amount.clone(),
             &coin.swap_contract_address(),
+            &None,
         )
         .wait()
         .unwrap();
@@ -877,6 +880,7 @@ fn test_taker_refunds_payment() {
             secret_hash,
             amount.clone(),
             &coin.swap_contract_address(),
+            &None,
         )
         .wait()
         .unwrap();
@@ -927,7 +931,14 @@ fn test_check_if_my_payment_sent() {
     let amount = BigDecimal::from_str("0.2").unwrap();
 
     let payment = coin
-        .send_maker_payment(timelock, &taker_pub, secret_hash, amount, &coin.swap_contract_address())
+        .send_maker_payment(
+            timelock,
+            &taker_pub,
+            secret_hash,
+            amount,
+            &coin.swap_contract_address(),
+            &None,
+        )
         .wait()
         .unwrap();
     let payment_tx_hash = payment.tx_hash();
@@ -976,6 +987,7 @@ fn test_search_for_swap_tx_spend_taker_spent() {
             secret_hash,
             amount,
             &maker_coin.swap_contract_address(),
+            &None,
         )
         .wait()
         .unwrap();
@@ -1042,6 +1054,7 @@ fn test_search_for_swap_tx_spend_maker_refunded() {
             secret_hash,
             amount,
             &maker_coin.swap_contract_address(),
+            &None,
         )
         .wait()
         .unwrap();
@@ -1108,6 +1121,7 @@ fn test_search_for_swap_tx_spend_not_spent() {
             secret_hash,
             amount,
             &maker_coin.swap_contract_address(),
+            &None,
         )
         .wait()
         .unwrap();
@@ -1156,6 +1170,7 @@ fn test_wait_for_tx_spend() {
             secret_hash,
             amount,
             &maker_coin.swap_contract_address(),
+            &None,
         )
         .wait()
         .unwrap();
@@ -1184,7 +1199,7 @@ fn test_wait_for_tx_spend() {
         .wait()
         .expect_err("Expected 'Waited too long' error");
     log!("error: "[err]);
-    assert!(err.contains("Waited too long"));
+    assert!(err.to_string().contains("Waited too long"));
 
     // also spends the maker payment and try to check if the wait_for_tx_spend() returns the correct tx
     static mut SPEND_TX: Option<TransactionEnum> = None;
@@ -1466,6 +1481,7 @@ fn test_get_max_taker_vol_and_trade_with_dynamic_trade_fee(coin: QtumCoin, priv_
             secret_hash,
             expected_max_taker_vol.to_decimal(),
             &None,
+            &None,
         )
         .wait()
         .expect("!send_taker_payment");
@@ -1772,7 +1788,7 @@ fn test_search_for_segwit_swap_tx_spend_native_was_refunded_maker() {
 
     let time_lock = (now_ms() / 1000) as u32 - 3600;
     let tx = coin
-        .send_maker_payment(time_lock, &*coin.my_public_key(), &[0; 20], 1.into(), &None)
+        .send_maker_payment(time_lock, &*coin.my_public_key(), &[0; 20], 1.into(), &None, &None)
         .wait()
         .unwrap();
 
@@ -1804,7 +1820,7 @@ fn test_search_for_segwit_swap_tx_spend_native_was_refunded_taker() {
 
     let time_lock = (now_ms() / 1000) as u32 - 3600;
     let tx = coin
-        .send_taker_payment(time_lock, &*coin.my_public_key(), &[0; 20], 1.into(), &None)
+        .send_taker_payment(time_lock, &*coin.my_public_key(), &[0; 20], 1.into(), &None, &None)
         .wait()
         .unwrap();
 
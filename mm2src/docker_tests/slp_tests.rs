@@ -42,7 +42,7 @@ fn mint_slp_token() {
     let time_lock = (now_ms() / 1000) as u32;
 
     let tx = slp
-        .send_maker_payment(time_lock, &*keypair.public(), &*secret_hash, 1.into(), &None)
+        .send_maker_payment(time_lock, &*keypair.public(), &*secret_hash, 1.into(), &None, &None)
         .wait()
         .unwrap();
     println!("{}", hex::encode(tx.tx_hex()));
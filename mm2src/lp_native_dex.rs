@@ -18,7 +18,7 @@
 //
 
 use coins::register_balance_update_handler;
-use mm2_libp2p::{spawn_gossipsub, NodeType};
+use mm2_libp2p::{spawn_gossipsub, NodeType, ProxyDialer};
 use rand::rngs::SmallRng;
 use rand::{random, Rng, SeedableRng};
 use serde_json::{self as json};
@@ -34,14 +34,17 @@ use crate::mm2::lp_network::{lp_ports, p2p_event_process_loop, P2PContext};
 use crate::mm2::lp_ordermatch::{broadcast_maker_orders_keep_alive_loop, clean_memory_loop, init_ordermatch_context,
                                 lp_ordermatch_loop, orders_kick_start, BalanceUpdateOrdermatchHandler};
 use crate::mm2::lp_swap::{running_swaps_num, swap_kick_starts};
+use crate::mm2::lp_wallet::lp_wallet_init;
 use crate::mm2::rpc::spawn_rpc;
 use crate::mm2::{MM_DATETIME, MM_VERSION};
 use bitcrypto::sha256;
 use common::executor::{spawn, spawn_boxed, Timer};
 use common::log::{error, info, warn};
 use common::mm_ctx::{MmArc, MmCtx};
-use common::privkey::key_pair_from_seed;
+use common::privkey::{hd_master_key_from_mnemonic, key_pair_from_hd_master_key, key_pair_from_seed};
+use common::proxy::mm_proxy;
 use common::slurp_url;
+use keys::bip32::DerivationPath;
 
 const IP_PROVIDERS: [&str; 2] = ["http://checkip.amazonaws.com/", "http://api.ipify.org"];
 const NETID_7777_SEEDNODES: [&str; 3] = ["seed1.defimania.live", "seed2.defimania.live", "seed3.defimania.live"];
@@ -71,6 +74,49 @@ fn default_seednodes(netid: u16) -> Vec<String> {
     }
 }
 
+/// The seed nodes dialed through the proxy: the domain names are resolved by the proxy rather than locally.
+#[cfg(not(target_arch = "wasm32"))]
+fn default_proxied_seednodes(netid: u16) -> Result<Vec<String>, String> {
+    if netid != 7777 {
+        return Ok(Vec::new());
+    }
+    let (_, network_port, _) = try_s!(lp_ports(netid));
+    Ok(NETID_7777_SEEDNODES
+        .iter()
+        .map(|dns| format!("/dns/{}/tcp/{}", dns, network_port))
+        .collect())
+}
+
+/// Returns the dialer connecting to the p2p peers through the proxy configured with the `proxy` entry of MM2 config.
+#[cfg(not(target_arch = "wasm32"))]
+fn p2p_proxy_dialer(ctx: &MmArc) -> Result<Option<ProxyDialer>, String> {
+    use futures::FutureExt;
+    use std::sync::Arc;
+
+    let proxy = match try_s!(mm_proxy(&ctx.conf)) {
+        Some(proxy) => proxy,
+        None => return Ok(None),
+    };
+    let dialer: ProxyDialer = Arc::new(move |host, port| {
+        let proxy = proxy.clone();
+        async move {
+            proxy
+                .connect(&host, port)
+                .await
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))
+        }
+        .boxed()
+    });
+    Ok(Some(dialer))
+}
+
+/// The proxy is rejected by `mm_proxy` in the browser, the WebSocket connections are established by the browser itself.
+#[cfg(target_arch = "wasm32")]
+fn p2p_proxy_dialer(ctx: &MmArc) -> Result<Option<ProxyDialer>, String> {
+    try_s!(mm_proxy(&ctx.conf));
+    Ok(None)
+}
+
 /// Invokes `OS_ensure_directory`,
 /// then prints an error and returns `false` if the directory is not writable.
 fn ensure_dir_is_writable(dir_path: &Path) -> bool {
@@ -242,6 +288,10 @@ fn migrate_db(ctx: &MmArc) -> Result<(), String> {
 #[cfg(not(target_arch = "wasm32"))]
 fn migration_1(_ctx: &MmArc) {}
 
+/// The derivation path of the node identity (P2P and swap) key in the HD mode.
+/// It matches the first KMD address of the mnemonic.
+const MM2_INTERNAL_DERIVATION_PATH: &str = "m/44'/141'/0'/0/0";
+
 /// Resets the context (most of which resides currently in `lp::G` but eventually would move into `MmCtx`).
 /// Restarts the peer connections.
 /// Reloads the coin keys.
@@ -252,14 +302,23 @@ fn migration_1(_ctx: &MmArc) {}
 /// AG: If possible, I think we should avoid calling this function on a working MM, using it for initialization only,
 ///     in order to avoid the possibility of invalid state.
 /// AP: Totally agree, moreover maybe we even `must` deny calling this on a working MM as it's being refactored
-pub fn lp_passphrase_init(ctx: &MmArc) -> Result<(), String> {
-    let passphrase = ctx.conf["passphrase"].as_str();
-    let passphrase = match passphrase {
-        None | Some("") => return ERR!("jeezy says we cant use the nullstring as passphrase and I agree"),
-        Some(s) => s.to_string(),
-    };
+pub fn lp_passphrase_init(ctx: &MmArc, passphrase: &str) -> Result<(), String> {
+    if passphrase.is_empty() {
+        return ERR!("jeezy says we cant use the nullstring as passphrase and I agree");
+    }
 
-    let key_pair = try_s!(key_pair_from_seed(&passphrase));
+    let key_pair = if ctx.conf["enable_hd"].as_bool().unwrap_or(false) {
+        let master_key = match hd_master_key_from_mnemonic(passphrase) {
+            Ok(key) => key,
+            Err(e) => return ERR!("'passphrase' must be a valid BIP39 mnemonic in the HD mode: {}", e),
+        };
+        let path: DerivationPath = try_s!(MM2_INTERNAL_DERIVATION_PATH.parse());
+        let key_pair = try_s!(key_pair_from_hd_master_key(&master_key, &path));
+        try_s!(ctx.hd_master_key.pin(master_key));
+        key_pair
+    } else {
+        try_s!(key_pair_from_seed(passphrase))
+    };
     let key_pair = try_s!(ctx.secp256k1_key_pair.pin(key_pair));
     try_s!(ctx.rmd160.pin(key_pair.public().address_hash()));
     Ok(())
@@ -314,7 +373,7 @@ fn test_ip(ctx: &MmArc, ip: IpAddr) -> Result<(), String> {
 /// * `ctx_cb` - callback used to share the `MmCtx` ID with the call site.
 pub async fn lp_init(ctx: MmArc) -> Result<(), String> {
     info!("Version: {} DT {}", MM_VERSION, MM_DATETIME);
-    try_s!(lp_passphrase_init(&ctx));
+    let rpc_is_spawned = try_s!(lp_wallet_init(&ctx).await);
 
     try_s!(fix_directories(&ctx));
     #[cfg(not(target_arch = "wasm32"))]
@@ -330,16 +389,17 @@ pub async fn lp_init(ctx: MmArc) -> Result<(), String> {
     let balance_update_ordermatch_handler = BalanceUpdateOrdermatchHandler::new(ctx.clone());
     register_balance_update_handler(ctx.clone(), Box::new(balance_update_ordermatch_handler)).await;
 
-    try_s!(ctx.initialized.pin(true));
-
+    // the watch-only node can't sign the swap transactions and the order messages of the watched node
     #[cfg(not(target_arch = "wasm32"))]
-    {
+    if !ctx.is_watch_only() {
         // launch kickstart threads before RPC is available, this will prevent the API user to place
         // an order and start new swap that might get started 2 times because of kick-start
         let mut coins_needed_for_kick_start = swap_kick_starts(ctx.clone());
         coins_needed_for_kick_start.extend(try_s!(orders_kick_start(&ctx).await));
         *(try_s!(ctx.coins_needed_for_kick_start.lock())) = coins_needed_for_kick_start;
     }
+    // the RPC methods other than the wallet ones are rejected until this point if the RPC is spawned by `lp_wallet_init`
+    try_s!(ctx.initialized.pin(true));
 
     spawn(lp_ordermatch_loop(ctx.clone()));
 
@@ -349,7 +409,9 @@ pub async fn lp_init(ctx: MmArc) -> Result<(), String> {
 
     let ctx_id = try_s!(ctx.ffi_handle());
 
-    spawn_rpc(ctx_id);
+    if !rpc_is_spawned {
+        spawn_rpc(ctx_id);
+    }
     let ctx_c = ctx.clone();
     spawn(async move {
         if let Err(err) = ctx_c.init_metrics() {
@@ -488,8 +550,21 @@ async fn init_p2p(ctx: MmArc) -> Result<(), String> {
     let i_am_seed = ctx.conf["i_am_seed"].as_bool().unwrap_or(false);
     let netid = ctx.netid();
 
+    let proxy_dialer = try_s!(p2p_proxy_dialer(&ctx));
+
     let seednodes: Vec<String> = if ctx.conf["seednodes"].is_null() {
-        default_seednodes(netid)
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            if proxy_dialer.is_some() {
+                try_s!(default_proxied_seednodes(netid))
+            } else {
+                default_seednodes(netid)
+            }
+        }
+        #[cfg(target_arch = "wasm32")]
+        {
+            default_seednodes(netid)
+        }
     } else {
         try_s!(json::from_value(ctx.conf["seednodes"].clone()))
     };
@@ -521,8 +596,14 @@ async fn init_p2p(ctx: MmArc) -> Result<(), String> {
         NodeType::Light { network_port }
     };
 
-    let (cmd_tx, event_rx, peer_id, p2p_abort) =
-        spawn_gossipsub(netid, force_p2p_key, spawn_boxed, seednodes, node_type, move |swarm| {
+    let (cmd_tx, event_rx, peer_id, p2p_abort) = spawn_gossipsub(
+        netid,
+        force_p2p_key,
+        spawn_boxed,
+        seednodes,
+        node_type,
+        proxy_dialer,
+        move |swarm| {
             let behaviour = swarm.behaviour();
             mm_gauge!(
                 ctx_on_poll.metrics,
@@ -550,8 +631,9 @@ async fn init_p2p(ctx: MmArc) -> Result<(), String> {
                 "p2p.connected_peers.count",
                 connected_peers_count as i64
             );
-        })
-        .await;
+        },
+    )
+    .await;
     let mut p2p_abort = Some(p2p_abort);
     ctx.on_stop(Box::new(move || {
         if let Some(handle) = p2p_abort.take() {
@@ -23,7 +23,8 @@ use best_orders::BestOrdersAction;
 use bigdecimal::BigDecimal;
 use blake2::digest::{Update, VariableOutput};
 use blake2::VarBlake2b;
-use coins::utxo::{compressed_pub_key_from_priv_raw, ChecksumType};
+use coins::signer::ExternalSigner;
+use coins::watch_only::WatchOnlyError;
 use coins::{find_pair, lp_coinfind, BalanceTradeFeeUpdatedHandler, FeeApproxStage, MmCoinEnum};
 use common::executor::{spawn, Timer};
 use common::log::error;
@@ -59,8 +60,9 @@ use uuid::Uuid;
 use crate::mm2::lp_network::{broadcast_p2p_msg, request_any_relay, request_one_peer, subscribe_to_topic, P2PRequest};
 use crate::mm2::lp_swap::{calc_max_maker_vol, check_balance_for_maker_swap, check_balance_for_taker_swap,
                           check_other_coin_balance_for_swap, insert_new_swap_to_db, is_pubkey_banned,
-                          lp_atomic_locktime, run_maker_swap, run_taker_swap, AtomicLocktimeVersion, MakerSwap,
-                          RunMakerSwapInput, RunTakerSwapInput, SwapConfirmationsSettings, TakerSwap};
+                          lp_atomic_locktime, my_persistent_pub, run_maker_swap, run_taker_swap,
+                          AtomicLocktimeVersion, MakerSwap, RunMakerSwapInput, RunTakerSwapInput,
+                          SwapConfirmationsSettings, TakerSwap};
 
 pub use best_orders::best_orders_rpc;
 pub use orderbook_depth::orderbook_depth_rpc;
@@ -2537,8 +2539,7 @@ fn lp_connect_start_bob(ctx: MmArc, maker_match: MakerMatch, maker_order: MakerO
         let alice = bits256::from(maker_match.request.sender_pubkey.0);
         let maker_amount = maker_match.reserved.get_base_amount().to_decimal();
         let taker_amount = maker_match.reserved.get_rel_amount().to_decimal();
-        let privkey = &ctx.secp256k1_key_pair().private().secret;
-        let my_persistent_pub = compressed_pub_key_from_priv_raw(&privkey[..], ChecksumType::DSHA256).unwrap();
+        let my_persistent_pub = my_persistent_pub(&ctx);
         let uuid = maker_match.request.uuid;
         let my_conf_settings = choose_maker_confs_and_notas(
             maker_order.conf_settings,
@@ -2588,6 +2589,7 @@ fn lp_connect_start_bob(ctx: MmArc, maker_match: MakerMatch, maker_order: MakerO
             maker_coin,
             taker_coin,
             lock_time,
+            maker_match.request.base_protocol_info_for_maker().clone(),
         );
         run_maker_swap(RunMakerSwapInput::StartNew(maker_swap), ctx).await;
     });
@@ -2623,8 +2625,7 @@ fn lp_connected_alice(ctx: MmArc, taker_order: TakerOrder, taker_match: TakerMat
             },
         };
 
-        let privkey = &ctx.secp256k1_key_pair().private().secret;
-        let my_persistent_pub = compressed_pub_key_from_priv_raw(&privkey[..], ChecksumType::DSHA256).unwrap();
+        let my_persistent_pub = my_persistent_pub(&ctx);
         let maker_amount = taker_match.reserved.get_base_amount().clone();
         let taker_amount = taker_match.reserved.get_rel_amount().clone();
         let uuid = taker_match.reserved.taker_order_uuid;
@@ -2676,6 +2677,7 @@ fn lp_connected_alice(ctx: MmArc, taker_order: TakerOrder, taker_match: TakerMat
             maker_coin,
             taker_coin,
             locktime,
+            taker_match.reserved.rel_protocol_info.clone(),
         );
         run_taker_swap(RunTakerSwapInput::StartNew(taker_swap), ctx).await
     });
@@ -3139,8 +3141,34 @@ pub struct AutoBuyInput {
     save_in_history: bool,
 }
 
+/// The swaps expect the coins to be signed with the node key (see `lp_swap::my_persistent_pub`),
+/// which is random in the watch-only mode.
+fn check_trading_is_available(ctx: &MmArc, base: &str, rel: &str) -> Result<(), String> {
+    if ctx.is_watch_only() {
+        return ERR!("{}", WatchOnlyError);
+    }
+    if ctx.uses_external_signer() {
+        let node_pubkey = my_persistent_pub(ctx);
+        for ticker in &[base, rel] {
+            let signer = try_s!(ExternalSigner::new(ctx, Some(ticker)));
+            if **signer.public_key() != *node_pubkey {
+                return ERR!(
+                    "The external signer signs {} with a key other than the node key, the swaps are not available",
+                    ticker
+                );
+            }
+        }
+    }
+    // the swaps are signed by the node persistent key that differs from the coin keys derived in the HD mode
+    if ctx.enable_hd() {
+        return ERR!("Trading is not available in the HD wallet mode yet");
+    }
+    Ok(())
+}
+
 pub async fn buy(ctx: MmArc, req: Json) -> Result<Response<Vec<u8>>, String> {
     let input: AutoBuyInput = try_s!(json::from_value(req));
+    try_s!(check_trading_is_available(&ctx, &input.base, &input.rel));
     if input.base == input.rel {
         return ERR!("Base and rel must be different coins");
     }
@@ -3173,6 +3201,7 @@ pub async fn buy(ctx: MmArc, req: Json) -> Result<Response<Vec<u8>>, String> {
 
 pub async fn sell(ctx: MmArc, req: Json) -> Result<Response<Vec<u8>>, String> {
     let input: AutoBuyInput = try_s!(json::from_value(req));
+    try_s!(check_trading_is_available(&ctx, &input.base, &input.rel));
     if input.base == input.rel {
         return ERR!("Base and rel must be different coins");
     }
@@ -3873,6 +3902,7 @@ async fn get_max_volume(ctx: &MmArc, my_coin: &MmCoinEnum, other_coin: &MmCoinEn
 
 pub async fn set_price(ctx: MmArc, req: Json) -> Result<Response<Vec<u8>>, String> {
     let req: SetPriceReq = try_s!(json::from_value(req));
+    try_s!(check_trading_is_available(&ctx, &req.base, &req.rel));
 
     let base_coin: MmCoinEnum = match try_s!(lp_coinfind(&ctx, &req.base).await) {
         Some(coin) => coin,
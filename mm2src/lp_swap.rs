@@ -89,9 +89,11 @@ use uuid::Uuid;
 
 #[path = "lp_swap/pubkey_banning.rs"] mod pubkey_banning;
 
+#[path = "lp_swap/bump_fee.rs"] mod bump_fee;
 #[path = "lp_swap/check_balance.rs"] mod check_balance;
 #[path = "lp_swap/trade_preimage.rs"] mod trade_preimage;
 
+pub use bump_fee::bump_fee_rpc;
 pub use check_balance::{check_other_coin_balance_for_swap, CheckBalanceError};
 pub use maker_swap::{calc_max_maker_vol, check_balance_for_maker_swap, maker_swap_trade_preimage, run_maker_swap,
                      stats_maker_swap_dir, MakerSavedSwap, MakerSwap, MakerTradePreimage, RunMakerSwapInput};
@@ -200,6 +202,15 @@ pub fn process_msg(ctx: MmArc, topic: &str, msg: &[u8]) {
 
 pub fn swap_topic(uuid: &Uuid) -> String { pub_sub_topic(SWAP_PREFIX, &uuid.to_string()) }
 
+/// Returns the public key the swap payments of this node are locked with.
+/// It's the node key kept by the external signer if it's configured, since the coins are signed by the signer then.
+pub fn my_persistent_pub(ctx: &MmArc) -> H264 {
+    match ctx.external_signer_pubkey.as_option() {
+        Some(pubkey) => pubkey.clone(),
+        None => H264::from(&**ctx.secp256k1_key_pair().public()),
+    }
+}
+
 async fn recv_swap_msg<T>(
     ctx: MmArc,
     mut getter: impl FnMut(&mut SwapMsgStore) -> Option<T>,
@@ -267,6 +278,9 @@ pub trait AtomicSwap: Send + Sync {
     fn maker_coin(&self) -> &str;
 
     fn taker_coin(&self) -> &str;
+
+    /// Replaces the transaction sent by us with the fee bumped one, see [`bump_fee::bump_fee_rpc`].
+    fn replace_my_tx(&self, replaced_tx_hash: &BytesJson, replacement: &TransactionIdentifier);
 }
 
 #[derive(Serialize)]
@@ -294,6 +308,9 @@ struct SwapsContext {
     /// Very unpleasant consequences
     shutdown_rx: async_std_sync::Receiver<()>,
     swap_msgs: Mutex<HashMap<Uuid, SwapMsgStore>>,
+    /// Guards the saved swap events, so the events saved by the running swaps and applied to their state
+    /// are not lost when `bump_fee_rpc` rewrites the swap files.
+    saved_swaps_lock: Mutex<()>,
 }
 
 impl SwapsContext {
@@ -318,6 +335,7 @@ impl SwapsContext {
                 running_swaps: Mutex::new(vec![]),
                 banned_pubkeys: Mutex::new(HashMap::new()),
                 swap_msgs: Mutex::new(HashMap::new()),
+                saved_swaps_lock: Mutex::new(()),
                 shutdown_rx,
             })
         })))
@@ -743,6 +761,14 @@ impl SavedSwap {
         }
     }
 
+    /// Replaces the transaction sent by us in the swap events, returns true if the transaction is found.
+    fn replace_my_tx(&mut self, replaced_tx_hash: &BytesJson, replacement: &TransactionIdentifier) -> bool {
+        match self {
+            SavedSwap::Maker(swap) => swap.replace_my_tx(replaced_tx_hash, replacement),
+            SavedSwap::Taker(swap) => swap.replace_my_tx(replaced_tx_hash, replacement),
+        }
+    }
+
     fn save_to_db(&self, ctx: &MmArc) -> Result<(), String> {
         let path = my_swap_file_path(ctx, self.uuid());
         if path.exists() {
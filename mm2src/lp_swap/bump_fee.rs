@@ -0,0 +1,166 @@
+use super::{my_swaps_dir, AtomicSwap, SavedSwap, SwapsContext, TransactionIdentifier};
+use coins::utxo::fee_bump::{prepare_bump_fee, BumpFeeError, BumpFeeRequest, BumpFeeResult};
+use common::file_lock::FileLock;
+use common::log::error;
+use common::mm_ctx::MmArc;
+use common::mm_error::prelude::*;
+use common::{read_dir, slurp, write};
+use rpc::v1::types::Bytes as BytesJson;
+use serde_json::{self as json};
+use std::ffi::OsStr;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Bumps the fee of the transaction sent by us (see [`coins::utxo::fee_bump`]).
+/// If the transaction is the taker fee, the taker payment or the maker payment of a swap,
+/// the swap is switched to the replacement so it waits for the confirmation of the new transaction.
+/// The swap events are rewritten before the replacement is broadcasted,
+/// so a restarted swap never waits for the transaction that is dropped from the mempool.
+pub async fn bump_fee_rpc(ctx: MmArc, req: BumpFeeRequest) -> BumpFeeResult {
+    let prepared = prepare_bump_fee(&ctx, req).await?;
+    let replaced = TransactionIdentifier {
+        tx_hex: prepared.replaced_tx_hex(),
+        tx_hash: prepared.response().replaced_tx_hash.clone(),
+    };
+    let replacement = TransactionIdentifier {
+        tx_hex: prepared.response().tx_details.tx_hex.clone(),
+        tx_hash: prepared.response().tx_details.tx_hash.clone(),
+    };
+
+    replace_my_tx_in_swaps(&ctx, &replaced.tx_hash, &replacement).map_to_mm(BumpFeeError::InternalError)?;
+    match prepared.send().await {
+        Ok(response) => Ok(response),
+        Err(e) => {
+            // the replaced transaction stays in the mempool
+            if let Err(revert_error) = replace_my_tx_in_swaps(&ctx, &replacement.tx_hash, &replaced) {
+                error!(
+                    "Error {} on the revert of {:x} replacement in the swaps",
+                    revert_error, replaced.tx_hash
+                );
+            }
+            Err(e)
+        },
+    }
+}
+
+/// Replaces the transaction sent by us in the running and the saved unfinished swaps.
+/// The swap files are rewritten under [`SwapsContext::saved_swaps_lock`] the running swaps save their events under,
+/// the swaps that are not running are locked with the swap file lock, so they can't be kick-started meanwhile.
+fn replace_my_tx_in_swaps(
+    ctx: &MmArc,
+    replaced_tx_hash: &BytesJson,
+    replacement: &TransactionIdentifier,
+) -> Result<(), String> {
+    let swap_ctx = try_s!(SwapsContext::from_ctx(ctx));
+    let _saved_swaps_lock = swap_ctx.saved_swaps_lock.lock().unwrap();
+    let running_swaps: Vec<Arc<dyn AtomicSwap>> = swap_ctx
+        .running_swaps
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(|swap| swap.upgrade())
+        .collect();
+
+    // all swaps are locked before any of them is changed
+    let mut to_rewrite = Vec::new();
+    let entries = try_s!(read_dir(&my_swaps_dir(ctx)));
+    for (_lm, path) in entries {
+        if path.extension() != Some(OsStr::new("json")) {
+            continue;
+        }
+        let mut swap: SavedSwap = match json::from_slice(&try_s!(slurp(&path))) {
+            Ok(swap) => swap,
+            Err(_) => continue,
+        };
+        if swap.is_finished() || !swap.replace_my_tx(replaced_tx_hash, replacement) {
+            continue;
+        }
+        let running = running_swaps
+            .iter()
+            .find(|running| running.uuid() == swap.uuid())
+            .cloned();
+        // the running swap holds its file lock itself
+        let file_lock = match running {
+            Some(_) => None,
+            None => {
+                let lock_path = my_swaps_dir(ctx).join(format!("{}.lock", swap.uuid()));
+                match try_s!(FileLock::lock(lock_path, 40.)) {
+                    Some(file_lock) => Some(file_lock),
+                    None => return ERR!("Swap {} file lock is acquired by another process", swap.uuid()),
+                }
+            },
+        };
+        to_rewrite.push(SwapToRewrite {
+            path,
+            swap,
+            running,
+            _file_lock: file_lock,
+        });
+    }
+
+    for to_rewrite in to_rewrite {
+        let content = try_s!(json::to_vec(&to_rewrite.swap));
+        try_s!(write(&to_rewrite.path, &content));
+        if let Some(running) = to_rewrite.running {
+            running.replace_my_tx(replaced_tx_hash, replacement);
+        }
+    }
+    Ok(())
+}
+
+struct SwapToRewrite {
+    path: PathBuf,
+    swap: SavedSwap,
+    running: Option<Arc<dyn AtomicSwap>>,
+    _file_lock: Option<FileLock<PathBuf>>,
+}
+
+#[cfg(test)]
+mod bump_fee_tests {
+    use super::*;
+    use crate::mm2::lp_swap::my_swap_file_path;
+    use common::mm_ctx::MmCtxBuilder;
+    use common::privkey::key_pair_from_seed;
+    use common::{new_uuid, temp_dir};
+    use std::fs;
+
+    #[test]
+    fn test_replace_my_tx_in_saved_swaps() {
+        let key_pair = key_pair_from_seed("test_replace_my_tx_in_saved_swaps").unwrap();
+        let dbdir = temp_dir().join(new_uuid().to_string());
+        let ctx = MmCtxBuilder::default()
+            .with_conf(json!({ "dbdir": dbdir.to_str().unwrap() }))
+            .with_secp256k1_key_pair(key_pair)
+            .into_mm_arc();
+        fs::create_dir_all(my_swaps_dir(&ctx)).unwrap();
+
+        // the unfinished taker swap waiting for the maker payment confirmation
+        let saved_json = r#"{"error_events":["StartFailed","NegotiateFailed","TakerFeeSendFailed","MakerPaymentValidateFailed","MakerPaymentWaitConfirmFailed","TakerPaymentTransactionFailed","TakerPaymentWaitConfirmFailed","TakerPaymentDataSendFailed","TakerPaymentWaitForSpendFailed","MakerPaymentSpendFailed","TakerPaymentWaitRefundStarted","TakerPaymentRefunded","TakerPaymentRefundFailed"],"events":[{"event":{"data":{"lock_duration":7800,"maker":"1bb83b58ec130e28e0a6d5d2acf2eb01b0d3f1670e021d47d31db8a858219da8","maker_amount":"0.12596566232185483","maker_coin":"KMD","maker_coin_start_block":1458035,"maker_payment_confirmations":1,"maker_payment_wait":1564053079,"my_persistent_pub":"0326846707a52a233cfc49a61ef51b1698bbe6aa78fa8b8d411c02743c09688f0a","started_at":1564050479,"taker_amount":"50.000000000000001504212457800000","taker_coin":"DOGE","taker_coin_start_block":2823448,"taker_payment_confirmations":1,"taker_payment_lock":1564058279,"uuid":"41383f43-46a5-478c-9386-3b2cce0aca20"},"type":"Started"},"timestamp":1564050480269},{"event":{"data":{"maker_payment_locktime":1564066080,"maker_pubkey":"031bb83b58ec130e28e0a6d5d2acf2eb01b0d3f1670e021d47d31db8a858219da8","secret_hash":"3669eb83a007a3c507448d79f45a9f06ec2f36a8"},"type":"Negotiated"},"timestamp":1564050540991},{"event":{"data":{"tx_hash":"bdde828b492d6d1cc25cd2322fd592dafd722fcc7d8b0fedce4d3bb4a1a8c8ff","tx_hex":"0100000002c7efa995c8b7be0a8b6c2d526c6c444c1634d65584e9ee89904e9d8675eac88c010000006a473044022051f34d5e3b7d0b9098d5e35333f3550f9cb9e57df83d5e4635b7a8d2986d6d5602200288c98da05de6950e01229a637110a1800ba643e75cfec59d4eb1021ad9b40801210326846707a52a233cfc49a61ef51b1698bbe6aa78fa8b8d411c02743c09688f0affffffffae6c233989efa7c7d2aa6534adc96078917ff395b7f09f734a147b2f44ade164000000006a4730440220393a784c2da74d0e2a28ec4f7df6c8f9d8b2af6ae6957f1e68346d744223a8fd02201b7a96954ac06815a43a6c7668d829ae9cbb5de76fa77189ddfd9e3038df662c01210326846707a52a233cfc49a61ef51b1698bbe6aa78fa8b8d411c02743c09688f0affffffff02115f5800000000001976a914ca1e04745e8ca0c60d8c5881531d51bec470743f88ac41a84641020000001976a914444f0e1099709ba4d742454a7d98a5c9c162ceab88ac6d84395d"},"type":"TakerFeeSent"},"timestamp":1564050545296},{"event":{"data":{"tx_hash":"0a0f11fa82802c2c30862c50ab2162185dae8de7f7235f32c506f814c142b382","tx_hex":"0400008085202f8902ace337db2dd4c56b0697f58fb8cfb6bd1cd6f469d925fc0376d1dcfb7581bf82000000006b483045022100d1f95be235c5c8880f5d703ace287e2768548792c58c5dbd27f5578881b30ea70220030596106e21c7e0057ee0dab283f9a1fe273f15208cba80870c447bd559ef0d0121031bb83b58ec130e28e0a6d5d2acf2eb01b0d3f1670e021d47d31db8a858219da8ffffffff9f339752567c404427fd77f2b35cecdb4c21489edc64e25e729fdb281785e423000000006a47304402203179e95877dbc107123a417f1e648e3ff13d384890f1e4a67b6dd5087235152e0220102a8ab799fadb26b5d89ceb9c7bc721a7e0c2a0d0d7e46bbe0cf3d130010d430121031bb83b58ec130e28e0a6d5d2acf2eb01b0d3f1670e021d47d31db8a858219da8ffffffff025635c0000000000017a91480a95d366d65e34a465ab17b0c9eb1d5a33bae08876cbfce05000000001976a914c3f710deb7320b0efa6edb14e3ebeeb9155fa90d88ac8d7c395d000000000000000000000000000000"},"type":"MakerPaymentReceived"},"timestamp":1564050588176},{"event":{"type":"MakerPaymentWaitConfirmStarted"},"timestamp":1564050588178}],"success_events":["Started","Negotiated","TakerFeeSent","MakerPaymentReceived","MakerPaymentWaitConfirmStarted","MakerPaymentValidatedAndConfirmed","TakerPaymentSent","TakerPaymentSpent","MakerPaymentSpent","Finished"],"type":"Taker","uuid":"41383f43-46a5-478c-9386-3b2cce0aca20"}"#;
+        let saved: SavedSwap = json::from_str(saved_json).unwrap();
+        let swap_path = my_swap_file_path(&ctx, saved.uuid());
+        fs::write(&swap_path, saved_json).unwrap();
+
+        let taker_fee_hash: BytesJson = hex::decode("bdde828b492d6d1cc25cd2322fd592dafd722fcc7d8b0fedce4d3bb4a1a8c8ff")
+            .unwrap()
+            .into();
+        let replacement = TransactionIdentifier {
+            tx_hex: vec![1; 32].into(),
+            tx_hash: vec![2; 32].into(),
+        };
+        replace_my_tx_in_swaps(&ctx, &taker_fee_hash, &replacement).unwrap();
+
+        let mut expected = saved;
+        assert!(expected.replace_my_tx(&taker_fee_hash, &replacement));
+        let actual: SavedSwap = json::from_slice(&fs::read(&swap_path).unwrap()).unwrap();
+        assert_eq!(json::to_value(&actual).unwrap(), json::to_value(&expected).unwrap());
+        // the swap file lock is released
+        assert!(!my_swaps_dir(&ctx).join(format!("{}.lock", actual.uuid())).exists());
+
+        // the maker payment is sent by the counterparty, it's not replaced
+        let maker_payment_hash: BytesJson =
+            hex::decode("0a0f11fa82802c2c30862c50ab2162185dae8de7f7235f32c506f814c142b382")
+                .unwrap()
+                .into();
+        assert!(!expected.replace_my_tx(&maker_payment_hash, &replacement));
+    }
+}
@@ -3,10 +3,10 @@ use super::check_balance::{check_base_coin_balance_for_swap, check_my_coin_balan
 use super::pubkey_banning::ban_pubkey_on_failed_swap;
 use super::trade_preimage::{TradePreimageRequest, TradePreimageRpcError, TradePreimageRpcResult};
 use super::{broadcast_my_swap_status, broadcast_swap_message_every, check_other_coin_balance_for_swap,
-            dex_fee_amount_from_taker_coin, get_locked_amount, my_swap_file_path, my_swaps_dir, recv_swap_msg,
-            swap_topic, AtomicSwap, LockedAmount, MySwapInfo, NegotiationDataMsg, NegotiationDataV2, RecoveredSwap,
-            RecoveredSwapAction, SavedSwap, SavedTradeFee, SwapConfirmationsSettings, SwapError, SwapMsg,
-            SwapsContext, TransactionIdentifier, WAIT_CONFIRM_INTERVAL};
+            dex_fee_amount_from_taker_coin, get_locked_amount, my_persistent_pub, my_swap_file_path, my_swaps_dir,
+            recv_swap_msg, swap_topic, AtomicSwap, LockedAmount, MySwapInfo, NegotiationDataMsg, NegotiationDataV2,
+            RecoveredSwap, RecoveredSwapAction, SavedSwap, SavedTradeFee, SwapConfirmationsSettings, SwapError,
+            SwapMsg, SwapsContext, TransactionIdentifier, WAIT_CONFIRM_INTERVAL};
 
 use crate::mm2::lp_network::subscribe_to_topic;
 use crate::mm2::lp_ordermatch::{MakerOrderBuilder, OrderConfirmationsSettings};
@@ -132,6 +132,10 @@ pub struct MakerSwapData {
     maker_coin_swap_contract_address: Option<BytesJson>,
     #[serde(skip_serializing_if = "Option::is_none")]
     taker_coin_swap_contract_address: Option<BytesJson>,
+    /// The `coin_protocol_info` of the maker coin received from the taker.
+    /// Defines the type of the maker payment HTLC output.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    other_maker_coin_protocol_info: Option<BytesJson>,
 }
 
 pub struct MakerSwapMut {
@@ -163,6 +167,7 @@ pub struct MakerSwap {
     mutable: RwLock<MakerSwapMut>,
     conf_settings: SwapConfirmationsSettings,
     payment_locktime: u64,
+    other_maker_coin_protocol_info: Option<Vec<u8>>,
 }
 
 impl MakerSwap {
@@ -249,6 +254,7 @@ impl MakerSwap {
         maker_coin: MmCoinEnum,
         taker_coin: MmCoinEnum,
         payment_locktime: u64,
+        other_maker_coin_protocol_info: Option<Vec<u8>>,
     ) -> Self {
         MakerSwap {
             ctx,
@@ -266,6 +272,7 @@ impl MakerSwap {
             taker_payment_confirmed: AtomicBool::new(false),
             conf_settings,
             payment_locktime,
+            other_maker_coin_protocol_info,
             mutable: RwLock::new(MakerSwapMut {
                 data: MakerSwapData::default(),
                 other_persistent_pub: H264::default(),
@@ -371,6 +378,7 @@ impl MakerSwap {
             taker_payment_spend_trade_fee: Some(SavedTradeFee::from(taker_payment_spend_trade_fee)),
             maker_coin_swap_contract_address,
             taker_coin_swap_contract_address,
+            other_maker_coin_protocol_info: self.other_maker_coin_protocol_info.clone().map(BytesJson::from),
         };
 
         Ok((Some(MakerSwapCommand::Negotiate), vec![MakerSwapEvent::Started(data)]))
@@ -577,6 +585,7 @@ impl MakerSwap {
                         &*dhash160(&self.r().data.secret.0),
                         self.maker_amount.clone(),
                         &self.r().data.maker_coin_swap_contract_address,
+                        &self.other_maker_coin_protocol_info,
                     );
 
                     match payment_fut.compat().await {
@@ -895,7 +904,7 @@ impl MakerSwap {
 
         let mut taker = bits256::from([0; 32]);
         taker.bytes = data.taker.0;
-        let my_persistent_pub = H264::from(&**ctx.secp256k1_key_pair().public());
+        let my_persistent_pub = my_persistent_pub(&ctx);
         let conf_settings = SwapConfirmationsSettings {
             maker_coin_confs: data.maker_payment_confirmations,
             maker_coin_nota: data
@@ -918,6 +927,7 @@ impl MakerSwap {
             maker_coin,
             taker_coin,
             data.lock_duration,
+            data.other_maker_coin_protocol_info.clone().map(|info| info.0),
         );
         let command = saved.events.last().unwrap().get_command();
         for saved_event in saved.events {
@@ -1101,6 +1111,14 @@ impl AtomicSwap for MakerSwap {
     fn maker_coin(&self) -> &str { self.maker_coin.ticker() }
 
     fn taker_coin(&self) -> &str { self.taker_coin.ticker() }
+
+    fn replace_my_tx(&self, replaced_tx_hash: &BytesJson, replacement: &TransactionIdentifier) {
+        if let Some(ref mut maker_payment) = self.w().maker_payment {
+            if &maker_payment.tx_hash == replaced_tx_hash {
+                *maker_payment = replacement.clone();
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -1367,6 +1385,20 @@ impl MakerSavedSwap {
 
         Ok(true)
     }
+
+    /// Replaces the maker payment, returns true if it's found.
+    pub fn replace_my_tx(&mut self, replaced_tx_hash: &BytesJson, replacement: &TransactionIdentifier) -> bool {
+        let mut is_replaced = false;
+        for event in self.events.iter_mut() {
+            if let MakerSwapEvent::MakerPaymentSent(ref mut tx) = event.event {
+                if &tx.tx_hash == replaced_tx_hash {
+                    *tx = replacement.clone();
+                    is_replaced = true;
+                }
+            }
+        }
+        is_replaced
+    }
 }
 
 #[allow(clippy::large_enum_variant)]
@@ -1475,6 +1507,8 @@ pub async fn run_maker_swap(swap: RunMakerSwapInput, ctx: MmArc) {
                 let res = running_swap.handle_command(command).await.expect("!handle_command");
                 events = res.1;
                 for event in events {
+                    // the saved events are rewritten by `bump_fee_rpc` under this lock too
+                    let _saved_swaps_lock = swap_ctx.saved_swaps_lock.lock().unwrap();
                     let to_save = MakerSavedEvent {
                         timestamp: now_ms(),
                         event: event.clone(),
@@ -3,9 +3,9 @@ use super::check_balance::{check_my_coin_balance_for_swap, CheckBalanceError, Ch
 use super::pubkey_banning::ban_pubkey_on_failed_swap;
 use super::trade_preimage::{TradePreimageRequest, TradePreimageRpcError, TradePreimageRpcResult};
 use super::{broadcast_my_swap_status, broadcast_swap_message_every, check_other_coin_balance_for_swap,
-            dex_fee_amount_from_taker_coin, dex_fee_rate, dex_fee_threshold, get_locked_amount, my_swap_file_path,
-            my_swaps_dir, recv_swap_msg, swap_topic, AtomicSwap, LockedAmount, MySwapInfo, NegotiationDataMsg,
-            NegotiationDataV2, RecoveredSwap, RecoveredSwapAction, SavedSwap, SavedTradeFee,
+            dex_fee_amount_from_taker_coin, dex_fee_rate, dex_fee_threshold, get_locked_amount, my_persistent_pub,
+            my_swap_file_path, my_swaps_dir, recv_swap_msg, swap_topic, AtomicSwap, LockedAmount, MySwapInfo,
+            NegotiationDataMsg, NegotiationDataV2, RecoveredSwap, RecoveredSwapAction, SavedSwap, SavedTradeFee,
             SwapConfirmationsSettings, SwapError, SwapMsg, SwapsContext, TransactionIdentifier, WAIT_CONFIRM_INTERVAL};
 use crate::mm2::lp_network::subscribe_to_topic;
 use crate::mm2::lp_ordermatch::{MatchBy, OrderConfirmationsSettings, TakerAction, TakerOrderBuilder};
@@ -243,6 +243,22 @@ impl TakerSavedSwap {
 
         Ok(true)
     }
+
+    /// Replaces the taker fee or the taker payment, returns true if it's found.
+    pub fn replace_my_tx(&mut self, replaced_tx_hash: &BytesJson, replacement: &TransactionIdentifier) -> bool {
+        let mut is_replaced = false;
+        for event in self.events.iter_mut() {
+            let my_tx = match event.event {
+                TakerSwapEvent::TakerFeeSent(ref mut tx) | TakerSwapEvent::TakerPaymentSent(ref mut tx) => tx,
+                _ => continue,
+            };
+            if &my_tx.tx_hash == replaced_tx_hash {
+                *my_tx = replacement.clone();
+                is_replaced = true;
+            }
+        }
+        is_replaced
+    }
 }
 
 #[allow(clippy::large_enum_variant)]
@@ -347,6 +363,8 @@ pub async fn run_taker_swap(swap: RunTakerSwapInput, ctx: MmArc) {
                 let res = running_swap.handle_command(command).await.expect("!handle_command");
                 events = res.1;
                 for event in events {
+                    // the saved events are rewritten by `bump_fee_rpc` under this lock too
+                    let _saved_swaps_lock = swap_ctx.saved_swaps_lock.lock().unwrap();
                     let to_save = TakerSavedEvent {
                         timestamp: now_ms(),
                         event: event.clone(),
@@ -421,6 +439,10 @@ pub struct TakerSwapData {
     maker_coin_swap_contract_address: Option<BytesJson>,
     #[serde(skip_serializing_if = "Option::is_none")]
     taker_coin_swap_contract_address: Option<BytesJson>,
+    /// The `coin_protocol_info` of the taker coin received from the maker.
+    /// Defines the type of the taker payment HTLC output.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    other_taker_coin_protocol_info: Option<BytesJson>,
 }
 
 pub struct TakerSwapMut {
@@ -453,6 +475,7 @@ pub struct TakerSwap {
     mutable: RwLock<TakerSwapMut>,
     conf_settings: SwapConfirmationsSettings,
     payment_locktime: u64,
+    other_taker_coin_protocol_info: Option<Vec<u8>>,
 }
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
@@ -657,6 +680,7 @@ impl TakerSwap {
         maker_coin: MmCoinEnum,
         taker_coin: MmCoinEnum,
         payment_locktime: u64,
+        other_taker_coin_protocol_info: Option<Vec<u8>>,
     ) -> Self {
         TakerSwap {
             ctx,
@@ -674,6 +698,7 @@ impl TakerSwap {
             errors: PaMutex::new(Vec::new()),
             conf_settings,
             payment_locktime,
+            other_taker_coin_protocol_info,
             mutable: RwLock::new(TakerSwapMut {
                 data: TakerSwapData::default(),
                 other_persistent_pub: H264::default(),
@@ -792,6 +817,7 @@ impl TakerSwap {
             maker_payment_spend_trade_fee: Some(SavedTradeFee::from(maker_payment_spend_trade_fee)),
             maker_coin_swap_contract_address,
             taker_coin_swap_contract_address,
+            other_taker_coin_protocol_info: self.other_taker_coin_protocol_info.clone().map(BytesJson::from),
         };
 
         Ok((Some(TakerSwapCommand::Negotiate), vec![TakerSwapEvent::Started(data)]))
@@ -1059,6 +1085,7 @@ impl TakerSwap {
                         &self.r().secret_hash.0,
                         self.taker_amount.to_decimal(),
                         &self.r().data.taker_coin_swap_contract_address,
+                        &self.other_taker_coin_protocol_info,
                     );
 
                     match payment_fut.compat().await {
@@ -1128,7 +1155,7 @@ impl TakerSwap {
             Ok(t) => t,
             Err(e) => {
                 return Ok((Some(TakerSwapCommand::RefundTakerPayment), vec![
-                    TakerSwapEvent::TakerPaymentWaitForSpendFailed(e.into()),
+                    TakerSwapEvent::TakerPaymentWaitForSpendFailed(e.to_string().into()),
                     TakerSwapEvent::TakerPaymentWaitRefundStarted {
                         wait_until: self.wait_refund_until(),
                     },
@@ -1273,7 +1300,7 @@ impl TakerSwap {
 
         let mut maker = bits256::from([0; 32]);
         maker.bytes = data.maker.0;
-        let my_persistent_pub = H264::from(&**ctx.secp256k1_key_pair().public());
+        let my_persistent_pub = my_persistent_pub(&ctx);
         let conf_settings = SwapConfirmationsSettings {
             maker_coin_confs: data.maker_payment_confirmations,
             maker_coin_nota: data
@@ -1297,6 +1324,7 @@ impl TakerSwap {
             maker_coin,
             taker_coin,
             data.lock_duration,
+            data.other_taker_coin_protocol_info.clone().map(|info| info.0),
         );
         let command = saved.events.last().unwrap().get_command();
         for saved_event in saved.events {
@@ -1503,6 +1531,16 @@ impl AtomicSwap for TakerSwap {
     fn maker_coin(&self) -> &str { self.maker_coin.ticker() }
 
     fn taker_coin(&self) -> &str { self.taker_coin.ticker() }
+
+    fn replace_my_tx(&self, replaced_tx_hash: &BytesJson, replacement: &TransactionIdentifier) {
+        let mut swap = self.w();
+        let swap = &mut *swap;
+        for tx in swap.taker_fee.iter_mut().chain(swap.taker_payment.iter_mut()) {
+            if &tx.tx_hash == replaced_tx_hash {
+                *tx = replacement.clone();
+            }
+        }
+    }
 }
 
 pub struct TakerSwapPreparedParams {
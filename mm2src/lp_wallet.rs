@@ -0,0 +1,491 @@
+//! Encrypted on-disk keystore of the wallet passphrase.
+//!
+//! The passphrase is encrypted with AES-256-GCM using a key derived from the wallet password with Argon2id.
+//! The keystore is stored in the root of the `dbdir` since the `rmd160` subdirectory isn't known until unlocked.
+//!
+//! The wallet can be unlocked at startup with the `wallet_password` config field
+//! or through the `unlock_wallet` RPC if neither `passphrase` nor `wallet_password` is set.
+//! There is no passphrase at all in the watch-only mode (see [`coins::watch_only`])
+//! and if the keys are kept by the external signer (see [`coins::signer`]).
+
+use crate::mm2::lp_native_dex::lp_passphrase_init;
+use crate::mm2::password_policy;
+use crate::mm2::rpc::spawn_rpc;
+use aes_gcm::aead::{Aead, NewAead};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use argon2::{Config as Argon2Config, ThreadMode, Variant as Argon2Variant};
+use coins::signer::ExternalSigner;
+use coins::watch_only::compressed_pubkey_from_hex;
+#[cfg(not(target_arch = "wasm32"))]
+use common::executor::spawn_blocking;
+use common::executor::Timer;
+use common::log::info;
+use common::mm_ctx::MmArc;
+use common::mm_error::prelude::*;
+use common::privkey::key_pair_from_seed;
+#[cfg(target_arch = "wasm32")] use common::write;
+use common::{slurp, HttpStatusCode};
+use derive_more::Display;
+use http::StatusCode;
+use primitives::hash::H264;
+use rand::random;
+use rpc::v1::types::Bytes as BytesJson;
+use serde_json::{self as json};
+use std::path::{Path, PathBuf};
+
+const KEYSTORE_FILE_NAME: &str = "wallet.keystore.json";
+const KEYSTORE_VERSION: u32 = 1;
+const KDF_KEY_LEN: u32 = 32;
+/// The Argon2id parameters recommended by RFC 9106 for memory-constrained environments.
+const ARGON2_MEM_COST_KIB: u32 = 64 * 1024;
+const ARGON2_TIME_COST: u32 = 3;
+const ARGON2_LANES: u32 = 4;
+
+pub type WalletResult<T> = Result<T, MmError<WalletError>>;
+
+#[derive(Debug, Display, Serialize, SerializeErrorType)]
+#[serde(tag = "error_type", content = "error_data")]
+pub enum WalletError {
+    #[display(fmt = "Invalid request: {}", _0)]
+    InvalidRequest(String),
+    #[display(fmt = "Password doesn't satisfy the policy: {}", _0)]
+    WeakPassword(String),
+    #[display(fmt = "Invalid wallet password")]
+    InvalidPassword,
+    #[display(fmt = "Keystore is not found at {}", _0)]
+    KeystoreNotFound(String),
+    #[display(fmt = "Keystore already exists at {}", _0)]
+    KeystoreAlreadyExists(String),
+    #[display(fmt = "Error parsing keystore: {}", _0)]
+    InvalidKeystore(String),
+    #[display(fmt = "Wallet is unlocked already")]
+    AlreadyUnlocked,
+    #[display(fmt = "Internal error: {}", _0)]
+    Internal(String),
+}
+
+impl HttpStatusCode for WalletError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            WalletError::InvalidRequest(_) | WalletError::WeakPassword(_) => StatusCode::BAD_REQUEST,
+            WalletError::InvalidPassword => StatusCode::UNAUTHORIZED,
+            WalletError::KeystoreNotFound(_) => StatusCode::NOT_FOUND,
+            WalletError::KeystoreAlreadyExists(_) | WalletError::AlreadyUnlocked => StatusCode::METHOD_NOT_ALLOWED,
+            WalletError::InvalidKeystore(_) | WalletError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "algorithm")]
+pub enum KeystoreKdf {
+    #[serde(rename = "argon2id")]
+    Argon2id {
+        salt: BytesJson,
+        /// Memory size in KiB.
+        mem_cost: u32,
+        time_cost: u32,
+        lanes: u32,
+    },
+}
+
+impl KeystoreKdf {
+    fn new_argon2id() -> KeystoreKdf {
+        KeystoreKdf::Argon2id {
+            salt: random::<[u8; 16]>().to_vec().into(),
+            mem_cost: ARGON2_MEM_COST_KIB,
+            time_cost: ARGON2_TIME_COST,
+            lanes: ARGON2_LANES,
+        }
+    }
+
+    fn derive_key(&self, password: &str) -> WalletResult<Vec<u8>> {
+        match self {
+            KeystoreKdf::Argon2id {
+                salt,
+                mem_cost,
+                time_cost,
+                lanes,
+            } => {
+                let config = Argon2Config {
+                    variant: Argon2Variant::Argon2id,
+                    mem_cost: *mem_cost,
+                    time_cost: *time_cost,
+                    lanes: *lanes,
+                    thread_mode: ThreadMode::Sequential,
+                    hash_length: KDF_KEY_LEN,
+                    ..Argon2Config::default()
+                };
+                argon2::hash_raw(password.as_bytes(), salt, &config)
+                    .map_to_mm(|e| WalletError::InvalidKeystore(e.to_string()))
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "algorithm")]
+pub enum KeystoreCipher {
+    #[serde(rename = "aes-256-gcm")]
+    Aes256Gcm { nonce: BytesJson },
+}
+
+/// The content of the keystore file.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct EncryptedKeystore {
+    pub version: u32,
+    pub kdf: KeystoreKdf,
+    pub cipher: KeystoreCipher,
+    pub ciphertext: BytesJson,
+}
+
+impl EncryptedKeystore {
+    /// Encrypts the passphrase with the key derived from the password.
+    pub fn encrypt(passphrase: &str, password: &str) -> WalletResult<EncryptedKeystore> {
+        EncryptedKeystore::encrypt_with_kdf(passphrase, password, KeystoreKdf::new_argon2id())
+    }
+
+    fn encrypt_with_kdf(passphrase: &str, password: &str, kdf: KeystoreKdf) -> WalletResult<EncryptedKeystore> {
+        let key = kdf.derive_key(password)?;
+        let nonce = random::<[u8; 12]>();
+        let cipher = Aes256Gcm::new(Key::from_slice(&key));
+        let ciphertext = cipher
+            .encrypt(Nonce::from_slice(&nonce), passphrase.as_bytes())
+            .map_to_mm(|e| WalletError::Internal(format!("Error encrypting passphrase: {}", e)))?;
+        Ok(EncryptedKeystore {
+            version: KEYSTORE_VERSION,
+            kdf,
+            cipher: KeystoreCipher::Aes256Gcm {
+                nonce: nonce.to_vec().into(),
+            },
+            ciphertext: ciphertext.into(),
+        })
+    }
+
+    /// Decrypts the passphrase. Returns `WalletError::InvalidPassword` if the authentication tag doesn't match.
+    pub fn decrypt(&self, password: &str) -> WalletResult<String> {
+        if self.version != KEYSTORE_VERSION {
+            return MmError::err(WalletError::InvalidKeystore(format!(
+                "Unsupported keystore version {}",
+                self.version
+            )));
+        }
+        let key = self.kdf.derive_key(password)?;
+        let plaintext = match &self.cipher {
+            KeystoreCipher::Aes256Gcm { nonce } => {
+                if nonce.len() != 12 {
+                    return MmError::err(WalletError::InvalidKeystore("Invalid AES-256-GCM nonce".into()));
+                }
+                let cipher = Aes256Gcm::new(Key::from_slice(&key));
+                cipher
+                    .decrypt(Nonce::from_slice(nonce), &self.ciphertext[..])
+                    .map_to_mm(|_| WalletError::InvalidPassword)?
+            },
+        };
+        String::from_utf8(plaintext).map_to_mm(|e| WalletError::InvalidKeystore(e.to_string()))
+    }
+}
+
+fn keystore_path(ctx: &MmArc) -> PathBuf { ctx.root_dbdir().join(KEYSTORE_FILE_NAME) }
+
+fn read_keystore(ctx: &MmArc) -> WalletResult<EncryptedKeystore> {
+    let path = keystore_path(ctx);
+    let content = slurp(&path).map_to_mm(WalletError::Internal)?;
+    if content.is_empty() {
+        return MmError::err(WalletError::KeystoreNotFound(path.display().to_string()));
+    }
+    json::from_slice(&content).map_to_mm(|e| WalletError::InvalidKeystore(e.to_string()))
+}
+
+fn write_keystore(ctx: &MmArc, keystore: &EncryptedKeystore) -> WalletResult<()> {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let root_dbdir = ctx.root_dbdir();
+        std::fs::create_dir_all(&root_dbdir)
+            .map_to_mm(|e| WalletError::Internal(format!("Error creating {}: {}", root_dbdir.display(), e)))?;
+    }
+    let content = json::to_vec(keystore).map_to_mm(|e| WalletError::Internal(e.to_string()))?;
+    write_keystore_file(&keystore_path(ctx), &content).map_to_mm(WalletError::Internal)
+}
+
+/// Writes the keystore readable by the owner only to a temporary file and renames it,
+/// so the only copy of the encrypted passphrase is never left half-written.
+#[cfg(not(target_arch = "wasm32"))]
+fn write_keystore_file(path: &Path, content: &[u8]) -> Result<(), String> {
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+
+    let tmp_path = format!("{}.tmp", path.display());
+    // the permissions are set on the file creation only
+    if Path::new(&tmp_path).exists() {
+        try_s!(fs::remove_file(&tmp_path));
+    }
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = try_s!(options.open(&tmp_path));
+    try_s!(file.write_all(content));
+    try_s!(file.sync_all());
+    try_s!(fs::rename(&tmp_path, path));
+    Ok(())
+}
+
+#[cfg(target_arch = "wasm32")]
+fn write_keystore_file(path: &Path, content: &[u8]) -> Result<(), String> { write(&path, &content) }
+
+/// Runs the Argon2 key derivation `f` on the blocking threads, so it doesn't stall the RPC server and the other futures.
+async fn run_kdf<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        spawn_blocking(f).await
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        f()
+    }
+}
+
+/// The wallet password must always satisfy the policy, `allow_weak_password` relaxes the `rpc_password` policy only.
+fn check_password_policy(password: &str) -> WalletResult<()> {
+    password_policy(password).mm_err(|e| WalletError::WeakPassword(e.to_string()))
+}
+
+/// Initializes the node key pair from the `passphrase` or from the keystore unlocked with the `wallet_password`.
+/// If neither is set, spawns the RPC server and waits for the `unlock_wallet` call.
+///
+/// Returns `true` if the RPC server has been spawned.
+pub async fn lp_wallet_init(ctx: &MmArc) -> Result<bool, String> {
+    if ctx.is_watch_only() {
+        if !ctx.conf["passphrase"].is_null() || !ctx.conf["wallet_password"].is_null() || ctx.uses_external_signer() {
+            return ERR!(
+                "'watch_only' can't be used together with 'passphrase', 'wallet_password' or 'external_signer'"
+            );
+        }
+        try_s!(watch_only_init(ctx));
+        return Ok(false);
+    }
+
+    if ctx.uses_external_signer() {
+        if !ctx.conf["passphrase"].is_null() || !ctx.conf["wallet_password"].is_null() {
+            return ERR!("'external_signer' can't be used together with 'passphrase' or 'wallet_password'");
+        }
+        try_s!(external_signer_init(ctx));
+        return Ok(false);
+    }
+
+    if !ctx.conf["passphrase"].is_null() {
+        let passphrase = ctx.conf["passphrase"].as_str().unwrap_or_default();
+        try_s!(lp_passphrase_init(ctx, passphrase));
+        return Ok(false);
+    }
+
+    if let Some(password) = ctx.conf["wallet_password"].as_str() {
+        let keystore = try_s!(read_keystore(ctx));
+        let password = password.to_owned();
+        let passphrase = try_s!(run_kdf(move || keystore.decrypt(&password)).await);
+        try_s!(lp_passphrase_init(ctx, &passphrase));
+        return Ok(false);
+    }
+
+    info!("Neither 'passphrase' nor 'wallet_password' is set, waiting for the 'unlock_wallet' RPC call");
+    spawn_rpc(try_s!(ctx.ffi_handle()));
+    while !is_wallet_unlocked(ctx) {
+        if ctx.is_stopping() {
+            return ERR!("MM2 stopped before the wallet was unlocked");
+        }
+        Timer::sleep(0.2).await
+    }
+    Ok(true)
+}
+
+/// Initializes the node key pair with a random key since it only identifies the node in the P2P network.
+/// `rmd160` is taken from the optional `watch_only.pubkey`, so the swaps of the watched node can be viewed.
+fn watch_only_init(ctx: &MmArc) -> Result<(), String> {
+    let key_pair = try_s!(key_pair_from_seed(&hex::encode(random::<[u8; 32]>())));
+    let key_pair = try_s!(ctx.secp256k1_key_pair.pin(key_pair));
+    let rmd160 = match ctx.conf["watch_only"]["pubkey"].as_str() {
+        Some(pubkey) => try_s!(compressed_pubkey_from_hex(pubkey)).address_hash(),
+        None => key_pair.public().address_hash(),
+    };
+    try_s!(ctx.rmd160.pin(rmd160));
+    info!("MM2 runs in the watch-only mode");
+    Ok(())
+}
+
+/// Initializes the node key pair with a random key since it only identifies the node in the P2P network.
+/// `rmd160` is taken from the node public key kept by the external signer, so `dbdir` persists between the restarts.
+/// The swaps lock the payments with this public key as well.
+fn external_signer_init(ctx: &MmArc) -> Result<(), String> {
+    let signer = try_s!(ExternalSigner::new(ctx, None));
+    let key_pair = try_s!(key_pair_from_seed(&hex::encode(random::<[u8; 32]>())));
+    try_s!(ctx.secp256k1_key_pair.pin(key_pair));
+    try_s!(ctx.rmd160.pin(signer.public_key().address_hash()));
+    try_s!(ctx.external_signer_pubkey.pin(H264::from(&**signer.public_key())));
+    info!("MM2 signs the transactions with the external signer");
+    Ok(())
+}
+
+/// Whether the node key pair is initialized.
+pub fn is_wallet_unlocked(ctx: &MmArc) -> bool { ctx.secp256k1_key_pair.as_option().is_some() }
+
+#[derive(Deserialize)]
+pub struct CreateWalletRequest {
+    passphrase: String,
+    password: String,
+}
+
+#[derive(Serialize)]
+pub struct CreateWalletResponse {
+    keystore_path: String,
+}
+
+/// Encrypts the given passphrase and saves it to the keystore.
+/// The keystore can be unlocked at the next startup or by the `unlock_wallet` call.
+pub async fn create_wallet(ctx: MmArc, req: CreateWalletRequest) -> WalletResult<CreateWalletResponse> {
+    if req.passphrase.is_empty() {
+        return MmError::err(WalletError::InvalidRequest("'passphrase' must not be empty".into()));
+    }
+    check_password_policy(&req.password)?;
+
+    let path = keystore_path(&ctx);
+    match read_keystore(&ctx) {
+        Ok(_) => return MmError::err(WalletError::KeystoreAlreadyExists(path.display().to_string())),
+        Err(e) => match e.get_inner() {
+            WalletError::KeystoreNotFound(_) => (),
+            _ => return Err(e),
+        },
+    }
+
+    let keystore = run_kdf(move || EncryptedKeystore::encrypt(&req.passphrase, &req.password)).await?;
+    write_keystore(&ctx, &keystore)?;
+    Ok(CreateWalletResponse {
+        keystore_path: path.display().to_string(),
+    })
+}
+
+#[derive(Deserialize)]
+pub struct UnlockWalletRequest {
+    password: String,
+}
+
+#[derive(Serialize)]
+pub struct UnlockWalletResponse {
+    public_key: String,
+}
+
+/// Decrypts the keystore and initializes the node key pair.
+pub async fn unlock_wallet(ctx: MmArc, req: UnlockWalletRequest) -> WalletResult<UnlockWalletResponse> {
+    if is_wallet_unlocked(&ctx) {
+        return MmError::err(WalletError::AlreadyUnlocked);
+    }
+    let keystore = read_keystore(&ctx)?;
+    let passphrase = run_kdf(move || keystore.decrypt(&req.password)).await?;
+    lp_passphrase_init(&ctx, &passphrase).map_to_mm(WalletError::Internal)?;
+    Ok(UnlockWalletResponse {
+        public_key: hex::encode(&**ctx.secp256k1_key_pair().public()),
+    })
+}
+
+#[derive(Deserialize)]
+pub struct ChangeWalletPasswordRequest {
+    current_password: String,
+    new_password: String,
+}
+
+/// Re-encrypts the keystore with the new password.
+pub async fn change_wallet_password(ctx: MmArc, req: ChangeWalletPasswordRequest) -> WalletResult<()> {
+    check_password_policy(&req.new_password)?;
+    let keystore = read_keystore(&ctx)?;
+    let keystore = run_kdf(move || {
+        let passphrase = keystore.decrypt(&req.current_password)?;
+        EncryptedKeystore::encrypt(&passphrase, &req.new_password)
+    })
+    .await?;
+    write_keystore(&ctx, &keystore)
+}
+
+#[derive(Deserialize)]
+pub struct ExportWalletRequest {
+    password: String,
+}
+
+/// Returns the content of the keystore file so it can be backed up.
+/// The password is required to make sure the backup can be restored.
+pub async fn export_wallet(ctx: MmArc, req: ExportWalletRequest) -> WalletResult<EncryptedKeystore> {
+    let keystore = read_keystore(&ctx)?;
+    let checked = keystore.clone();
+    run_kdf(move || checked.decrypt(&req.password)).await?;
+    Ok(keystore)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Cheap KDF parameters to keep the tests fast.
+    fn test_kdf() -> KeystoreKdf {
+        KeystoreKdf::Argon2id {
+            salt: vec![1; 16].into(),
+            mem_cost: 64,
+            time_cost: 1,
+            lanes: 1,
+        }
+    }
+
+    #[test]
+    fn test_encrypt_decrypt_keystore() {
+        let passphrase = "spice describe gravity federal blast come thank unfair canal monkey style afraid";
+        let keystore = EncryptedKeystore::encrypt_with_kdf(passphrase, "StrongPass123*", test_kdf()).unwrap();
+        assert_eq!(keystore.decrypt("StrongPass123*").unwrap(), passphrase);
+
+        let error = keystore.decrypt("StrongPass123&").unwrap_err().into_inner();
+        match error {
+            WalletError::InvalidPassword => (),
+            e => panic!("Expected WalletError::InvalidPassword, found {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_keystore_serialization() {
+        let keystore = EncryptedKeystore::encrypt_with_kdf("passphrase", "StrongPass123*", test_kdf()).unwrap();
+        let serialized = json::to_value(&keystore).unwrap();
+        assert_eq!(serialized["kdf"]["algorithm"], "argon2id");
+        assert_eq!(serialized["cipher"]["algorithm"], "aes-256-gcm");
+
+        let deserialized: EncryptedKeystore = json::from_value(serialized).unwrap();
+        assert_eq!(deserialized, keystore);
+        assert_eq!(deserialized.decrypt("StrongPass123*").unwrap(), "passphrase");
+    }
+
+    #[test]
+    fn test_check_password_policy() {
+        match check_password_policy("weak").unwrap_err().into_inner() {
+            WalletError::WeakPassword(_) => (),
+            e => panic!("Expected WalletError::WeakPassword, found {:?}", e),
+        }
+        check_password_policy("StrongPass123*").unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_write_keystore_file() {
+        use common::now_ms;
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join(format!("test_write_keystore_file_{}.json", now_ms()));
+        write_keystore_file(&path, b"first").unwrap();
+        write_keystore_file(&path, b"second").unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"second");
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
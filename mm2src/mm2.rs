@@ -55,6 +55,7 @@ pub mod database;
 #[path = "lp_ordermatch.rs"] pub mod lp_ordermatch;
 #[path = "lp_stats.rs"] pub mod lp_stats;
 #[path = "lp_swap.rs"] pub mod lp_swap;
+#[path = "lp_wallet.rs"] pub mod lp_wallet;
 #[path = "rpc.rs"] pub mod rpc;
 
 #[cfg(any(test, target_arch = "wasm32"))]
@@ -213,7 +214,8 @@ pub async fn lp_main(params: LpMainParams, ctx_cb: &dyn Fn(u32)) -> Result<(), S
         }
     }
 
-    if conf["passphrase"].is_string() {
+    // The passphrase can be omitted if the wallet is unlocked from the encrypted keystore, cf. `lp_wallet_init`.
+    if conf["passphrase"].is_null() || conf["passphrase"].is_string() {
         let ctx = MmCtxBuilder::new()
             .with_conf(conf)
             .with_version(MM_VERSION.into())
@@ -260,13 +262,18 @@ fn help() {
             "                     If the field isn't present on the command line then we try loading it from the 'coins' file.\n"
             "  crash          ..  Simulate a crash to check how the crash handling works.\n"
             "  dbdir          ..  MM database path. 'DB' by default.\n"
+            "  enable_hd      ..  `true` to derive the coin keys from the BIP39 mnemonic 'passphrase' along the BIP44 paths.\n"
+            "                     Every coin config must have the 'derivation_path' field then, e.g. \"m/44'/141'\".\n"
+            "                     Swaps are not supported in this mode yet. Defaults to `false`.\n"
             "  gui            ..  The information about GUI app using MM2 instance. Included in swap statuses shared with network.\n"
             "                 ..  It's recommended to put essential info to this field (application name, OS, version, etc).\n"
             "                 ..  e.g. AtomicDEX iOS 1.0.1000.\n"
             "  myipaddr       ..  IP address to bind to for P2P networking.\n"
             "  netid          ..  Subnetwork. Affects ports and keys.\n"
-            "  passphrase *   ..  Wallet seed.\n"
+            "  passphrase     ..  Wallet seed.\n"
+            "                     Can be omitted to use the encrypted keystore, see 'wallet_password'.\n"
             "                     Compressed WIFs and hexadecimal ECDSA keys (prefixed with 0x) are also accepted.\n"
+            "                     Must be a 12 or 24 words BIP39 mnemonic if 'enable_hd' is `true`.\n"
             "  panic          ..  Simulate a panic to see if backtrace works.\n"
             // cf. https://github.com/atomiclabs/hyperdex/pull/563/commits/6d17c0c994693b768e30130855c679a7849a2b27
             "  rpccors        ..  Access-Control-Allow-Origin header value to be used in all the RPC responses.\n"
@@ -283,6 +290,8 @@ fn help() {
             "                     At least one seed IP must be present if the node is not a seed itself.\n"
             "  stderr         ..  Print a message to stderr and exit.\n"
             "  userhome       ..  System home directory of a user ('/root' by default).\n"
+            "  wallet_password .. The password to unlock the encrypted keystore created by the 'create_wallet' RPC.\n"
+            "                     If neither 'passphrase' nor 'wallet_password' is set, MM waits for the 'unlock_wallet' RPC.\n"
             "  wif            ..  `1` to add WIFs to the information we provide about a coin.\n"
             "\n"
             "Environment variables:\n"
@@ -5,7 +5,7 @@ use hex::FromHex;
 use ser::{deserialize, serialize, Deserializable, Reader, Serializable, Stream};
 use std::io;
 use transaction::{deserialize_tx, TxType};
-use {OutPoint, Transaction};
+use {OutPoint, Transaction, U256};
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum BlockHeaderNonce {
@@ -288,6 +288,25 @@ impl BlockHeader {
     pub fn hash(&self) -> H256 { dhash256(&serialize(self)) }
 
     pub fn is_prog_pow(&self) -> bool { self.version == MTP_POW_VERSION && self.time >= PROG_POW_SWITCH_TIME }
+
+    /// Returns the target the header hash must not exceed, or `None` if `bits` encode a negative or overflowed value.
+    pub fn target(&self) -> Option<U256> {
+        let compact = match self.bits {
+            BlockHeaderBits::Compact(compact) => compact,
+            BlockHeaderBits::U32(bits) => Compact::new(bits),
+        };
+        compact.to_u256().ok()
+    }
+
+    /// Checks that the header hash satisfies the target encoded in `bits`.
+    /// Note the double SHA-256 header hash is the proof-of-work hash of Bitcoin and Zcash-like chains only,
+    /// the headers of the coins with other PoW algorithms (Scrypt, merged mining, etc.) are never valid.
+    pub fn is_pow_valid(&self) -> bool {
+        match self.target() {
+            Some(target) => !target.is_zero() && U256::from_little_endian(&*self.hash()) <= target,
+            None => false,
+        }
+    }
 }
 
 impl From<&'static str> for BlockHeader {
@@ -298,6 +317,7 @@ impl From<&'static str> for BlockHeader {
 mod tests {
     use block_header::{BlockHeader, BlockHeaderBits, BlockHeaderNonce, AUX_POW_VERSION_DOGE, AUX_POW_VERSION_SYS,
                        KAWPOW_VERSION, MTP_POW_VERSION, PROG_POW_SWITCH_TIME, QTUM_BLOCK_HEADER_VERSION};
+    use hash::H256;
     use hex::FromHex;
     use ser::{deserialize, serialize, serialize_list, CoinVariant, Error as ReaderError, Reader, Stream};
 
@@ -2296,4 +2316,20 @@ mod tests {
         let serialized = serialize_list(&headers);
         assert_eq!(serialized.take(), headers_bytes);
     }
+
+    #[test]
+    fn test_block_header_pow() {
+        // Bitcoin genesis block header
+        let header_hex = "0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c";
+        let header: BlockHeader = deserialize(header_hex.from_hex::<Vec<u8>>().unwrap().as_slice()).unwrap();
+        let expected_hash = H256::from_reversed_str("000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f");
+        assert_eq!(header.hash(), expected_hash);
+        assert!(header.is_pow_valid());
+
+        let tampered = BlockHeader {
+            nonce: BlockHeaderNonce::U32(0),
+            ..header
+        };
+        assert!(!tampered.is_pow_valid());
+    }
 }
@@ -7,6 +7,10 @@ pub const SEQUENCE_LOCKTIME_DISABLE_FLAG: u32 = 1u32 << 31;
 // disables nLockTime.
 pub const SEQUENCE_FINAL: u32 = 0xffffffff;
 
+// The maximum nSequence value an input may have to signal the opt-in Replace-by-Fee.
+// https://github.com/bitcoin/bips/blob/master/bip-0125.mediawiki
+pub const MAX_BIP125_RBF_SEQUENCE: u32 = 0xfffffffd;
+
 // If CTxIn::nSequence encodes a relative lock-time and this flag
 // is set, the relative lock-time has units of 512 seconds,
 // otherwise it specifies blocks with a granularity of 1.
@@ -22,7 +22,7 @@ pub use primitives::{bytes, compact, hash, U256};
 
 pub use block::Block;
 pub use block_header::BlockHeader;
-pub use merkle_root::{merkle_node_hash, merkle_root};
+pub use merkle_root::{merkle_node_hash, merkle_root, merkle_root_from_branch};
 pub use transaction::{JoinSplit, OutPoint, ShieldedOutput, ShieldedSpend, Transaction, TransactionInput,
                       TransactionOutput, TxHashAlgo};
 
@@ -46,9 +46,24 @@ where
     dhash256(&*concat(left, right))
 }
 
+/// Calculates the merkle root from the hash of the transaction at `pos` in the block and its merkle branch,
+/// the hashes of the sibling nodes from the leaves up to the root.
+pub fn merkle_root_from_branch(tx_hash: &H256, branch: &[H256], mut pos: usize) -> H256 {
+    let mut result = tx_hash.clone();
+    for hash in branch {
+        result = if pos & 1 == 0 {
+            merkle_node_hash(&result, hash)
+        } else {
+            merkle_node_hash(hash, &result)
+        };
+        pos >>= 1;
+    }
+    result
+}
+
 #[cfg(test)]
 mod tests {
-    use super::merkle_root;
+    use super::{merkle_root, merkle_root_from_branch};
     use hash::H256;
 
     // block 80_000
@@ -64,4 +79,15 @@ mod tests {
         assert_eq!(result, expected);
         assert_eq!(result2, expected);
     }
+
+    #[test]
+    fn test_merkle_root_from_branch() {
+        let tx1 = H256::from_reversed_str("c06fbab289f723c6261d3030ddb6be121f7d2508d77862bb1e484f5cd7f92b25");
+        let tx2 = H256::from_reversed_str("5a4ebf66822b0b2d56bd9dc64ece0bc38ee7844a23ff1d7320a88c5fdb2ad3e2");
+        let expected = H256::from_reversed_str("8fb300e3fdb6f30a4c67233b997f99fdd518b968b9a3fd65857bfe78b2600719");
+
+        assert_eq!(merkle_root_from_branch(&tx1, &[tx2.clone()], 0), expected);
+        assert_eq!(merkle_root_from_branch(&tx2, &[tx1.clone()], 1), expected);
+        assert_ne!(merkle_root_from_branch(&tx1, &[tx2], 1), expected);
+    }
 }
@@ -1,4 +1,5 @@
 extern crate groestl;
+extern crate hmac;
 extern crate primitives;
 extern crate ripemd160;
 extern crate sha1;
@@ -7,10 +8,11 @@ extern crate sha3;
 extern crate siphasher;
 
 use groestl::Groestl512;
+use hmac::{Hmac, Mac};
 use primitives::hash::{H160, H256, H32, H512};
 use ripemd160::Ripemd160;
 use sha1::Sha1;
-use sha2::{Digest, Sha256};
+use sha2::{Digest, Sha256, Sha512};
 use sha3::Keccak256;
 use siphasher::sip::SipHasher24;
 use std::hash::Hasher;
@@ -95,6 +97,15 @@ pub fn siphash24(key0: u64, key1: u64, input: &[u8]) -> u64 {
 #[inline]
 pub fn dgroestl512(input: &[u8]) -> H512 { groestl512(&*groestl512(input)) }
 
+/// HMAC-SHA512
+#[inline]
+pub fn hmac_sha512(key: &[u8], input: &[u8]) -> H512 {
+    // HMAC accepts keys of any length, so `new_varkey` can't fail here.
+    let mut mac = Hmac::<Sha512>::new_varkey(key).expect("HMAC can take key of any size");
+    mac.input(input);
+    (*mac.result().code()).into()
+}
+
 /// Data checksum
 #[inline]
 pub fn checksum(data: &[u8], sum_type: &ChecksumType) -> H32 {
@@ -109,9 +120,9 @@ pub fn checksum(data: &[u8], sum_type: &ChecksumType) -> H32 {
 
 #[cfg(test)]
 mod tests {
-    use super::{checksum, dhash160, dhash256, ripemd160, sha1, sha256, siphash24};
+    use super::{checksum, dhash160, dhash256, hmac_sha512, ripemd160, sha1, sha256, siphash24};
     use primitives::bytes::Bytes;
-    use primitives::hash::{H160, H256, H32};
+    use primitives::hash::{H160, H256, H32, H512};
     use ChecksumType;
 
     #[test]
@@ -162,6 +173,14 @@ mod tests {
         assert_eq!(result, expected);
     }
 
+    #[test]
+    fn test_hmac_sha512() {
+        // RFC 4231 test case 2
+        let expected: H512 = "164b7a7bfcf819e2e395fbe73b56e0a387bd64222e831fd610270cd7ea2505549758bf75c05a994a6d034f65f8f0e6fdcaeab1a34d4a6b4b636e070a38bce737".into();
+        let result = hmac_sha512(b"Jefe", b"what do ya want for nothing?");
+        assert_eq!(result, expected);
+    }
+
     #[test]
     fn test_checksum() {
         assert_eq!(checksum(b"hello", &ChecksumType::DSHA256), H32::from("9595c9df"));
@@ -8,6 +8,7 @@
 use base58::{FromBase58, ToBase58};
 use crypto::{checksum, dgroestl512, dhash256, keccak256, ChecksumType};
 use derive_more::Display;
+use hash::H256;
 use serde::{Deserialize, Serialize};
 use std::fmt;
 use std::ops::Deref;
@@ -31,6 +32,11 @@ pub enum Type {
     /// Common P2WPKH which begins with the human readable part followed by 1, eg: bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4.
     /// https://github.com/bitcoin/bips/blob/master/bip-0173.mediawiki
     P2WPKH,
+    /// Pay to Taproot
+    /// The witness v1 output of the 32-byte output key, it begins with the human readable part followed by 1p,
+    /// eg: bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr.
+    /// https://github.com/bitcoin/bips/blob/master/bip-0341.mediawiki
+    P2TR,
 }
 
 #[derive(Clone, Debug, Display, Deserialize, PartialEq, Serialize)]
@@ -86,6 +92,10 @@ pub struct Address {
     pub checksum_type: ChecksumType,
     /// Address Format
     pub addr_format: AddressFormat,
+    /// The 32 bytes long x-only output key of the taproot (witness v1) address.
+    /// `hash` isn't used if the key is set.
+    /// https://github.com/bitcoin/bips/blob/master/bip-0341.mediawiki
+    pub taproot_output_key: Option<H256>,
 }
 
 // Todo: add segwit checksum detection
@@ -148,6 +158,7 @@ impl DisplayLayout for Address {
                     checksum_type: sum_type,
                     hrp: None,
                     addr_format: AddressFormat::Standard,
+                    taproot_output_key: None,
                 };
 
                 Ok(address)
@@ -165,6 +176,7 @@ impl DisplayLayout for Address {
                     checksum_type: sum_type,
                     hrp: None,
                     addr_format: AddressFormat::Standard,
+                    taproot_output_key: None,
                 };
 
                 Ok(address)
@@ -177,11 +189,11 @@ impl DisplayLayout for Address {
 impl fmt::Display for Address {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match &self.addr_format {
-            AddressFormat::Segwit => {
-                SegwitAddress::new(&self.hash, self.hrp.clone().expect("Segwit address should have an hrp"))
-                    .to_string()
-                    .fmt(f)
-            },
+            AddressFormat::Segwit => self
+                .to_segwitaddress()
+                .expect("Segwit address should have an hrp")
+                .to_string()
+                .fmt(f),
             _ => self.layout().to_base58().fmt(f),
         }
     }
@@ -204,12 +216,15 @@ impl From<&'static str> for Address {
 }
 
 impl Address {
+    /// Whether the address is the pay-to-taproot (witness v1) address.
+    pub fn is_taproot(&self) -> bool { self.taproot_output_key.is_some() }
+
     pub fn display_address(&self) -> Result<String, String> {
         match &self.addr_format {
             AddressFormat::Standard => Ok(self.to_string()),
-            AddressFormat::Segwit => match &self.hrp {
-                Some(hrp) => Ok(SegwitAddress::new(&self.hash, hrp.clone()).to_string()),
-                None => Err("Cannot display segwit address for a coin with no bech32_hrp in config".into()),
+            AddressFormat::Segwit => match self.to_segwitaddress() {
+                Ok(segwit_address) => Ok(segwit_address.to_string()),
+                Err(_) => Err("Cannot display segwit address for a coin with no bech32_hrp in config".into()),
             },
 
             AddressFormat::CashAddress {
@@ -254,6 +269,7 @@ impl Address {
                 pub_addr_prefix: p2pkh_prefix,
                 p2sh_addr_prefix: p2sh_prefix,
             },
+            taproot_output_key: None,
         })
     }
 
@@ -263,6 +279,10 @@ impl Address {
         p2pkh_prefix: u8,
         p2sh_prefix: u8,
     ) -> Result<CashAddress, String> {
+        if self.is_taproot() {
+            return Err("Taproot address can't be converted to CashAddress".into());
+        }
+
         let address_type = if self.prefix == p2pkh_prefix {
             CashAddrType::P2PKH
         } else if self.prefix == p2sh_prefix {
@@ -285,12 +305,15 @@ impl Address {
     ) -> Result<Address, String> {
         let address = SegwitAddress::from_str(segaddr).map_err(|e| e.to_string())?;
 
-        if address.program.len() != 20 {
-            return Err("Expect 20 bytes long hash".into());
-        }
-
         let mut hash: AddressHash = Default::default();
-        hash.copy_from_slice(address.program.as_slice());
+        let mut taproot_output_key = None;
+        match (address.version(), address.program.len()) {
+            (0, 20) => hash.copy_from_slice(address.program.as_slice()),
+            (1, 32) => taproot_output_key = Some(H256::from(address.program.as_slice())),
+            (0, _) => return Err("Expect 20 bytes long hash".into()),
+            (1, _) => return Err("Expect 32 bytes long taproot output key".into()),
+            (version, _) => return Err(format!("Unsupported witness version {}", version)),
+        }
 
         let hrp = Some(address.hrp);
 
@@ -301,20 +324,22 @@ impl Address {
             checksum_type,
             hrp,
             addr_format: AddressFormat::Segwit,
+            taproot_output_key,
         })
     }
 
     pub fn to_segwitaddress(&self) -> Result<SegwitAddress, String> {
-        match &self.hrp {
-            Some(hrp) => Ok(SegwitAddress::new(&self.hash, hrp.to_string())),
-            None => Err("hrp must be provided for segwit address".into()),
+        match (&self.hrp, &self.taproot_output_key) {
+            (Some(hrp), Some(output_key)) => Ok(SegwitAddress::new_p2tr(output_key, hrp.to_string())),
+            (Some(hrp), None) => Ok(SegwitAddress::new(&self.hash, hrp.to_string())),
+            (None, _) => Err("hrp must be provided for segwit address".into()),
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{Address, AddressFormat, CashAddrType, CashAddress, ChecksumType};
+    use super::{Address, AddressFormat, CashAddrType, CashAddress, ChecksumType, H256};
     use crate::NetworkPrefix;
 
     #[test]
@@ -326,6 +351,7 @@ mod tests {
             checksum_type: ChecksumType::DSHA256,
             hrp: None,
             addr_format: AddressFormat::Standard,
+            taproot_output_key: None,
         };
 
         assert_eq!("16meyfSoQV6twkAAxPe51RtMVz7PGRmWna".to_owned(), address.to_string());
@@ -340,6 +366,7 @@ mod tests {
             checksum_type: ChecksumType::DSHA256,
             hrp: None,
             addr_format: AddressFormat::Standard,
+            taproot_output_key: None,
         };
 
         assert_eq!("R9o9xTocqr6CeEDGDH6mEYpwLoMz6jNjMW".to_owned(), address.to_string());
@@ -354,6 +381,7 @@ mod tests {
             checksum_type: ChecksumType::DSHA256,
             hrp: None,
             addr_format: AddressFormat::Standard,
+            taproot_output_key: None,
         };
 
         assert_eq!("tmAEKD7psc1ajK76QMGEW8WGQSBBHf9SqCp".to_owned(), address.to_string());
@@ -368,6 +396,7 @@ mod tests {
             checksum_type: ChecksumType::DSHA256,
             hrp: None,
             addr_format: AddressFormat::Standard,
+            taproot_output_key: None,
         };
 
         assert_eq!("bX9bppqdGvmCCAujd76Tq76zs1suuPnB9A".to_owned(), address.to_string());
@@ -382,6 +411,7 @@ mod tests {
             checksum_type: ChecksumType::DSHA256,
             hrp: None,
             addr_format: AddressFormat::Standard,
+            taproot_output_key: None,
         };
 
         assert_eq!(address, "16meyfSoQV6twkAAxPe51RtMVz7PGRmWna".into());
@@ -397,6 +427,7 @@ mod tests {
             checksum_type: ChecksumType::DSHA256,
             hrp: None,
             addr_format: AddressFormat::Standard,
+            taproot_output_key: None,
         };
 
         assert_eq!(address, "R9o9xTocqr6CeEDGDH6mEYpwLoMz6jNjMW".into());
@@ -412,6 +443,7 @@ mod tests {
             checksum_type: ChecksumType::DSHA256,
             hrp: None,
             addr_format: AddressFormat::Standard,
+            taproot_output_key: None,
         };
 
         assert_eq!(address, "tmAEKD7psc1ajK76QMGEW8WGQSBBHf9SqCp".into());
@@ -427,6 +459,7 @@ mod tests {
             checksum_type: ChecksumType::DSHA256,
             hrp: None,
             addr_format: AddressFormat::Standard,
+            taproot_output_key: None,
         };
 
         assert_eq!(address, "bX9bppqdGvmCCAujd76Tq76zs1suuPnB9A".into());
@@ -442,6 +475,7 @@ mod tests {
             checksum_type: ChecksumType::DGROESTL512,
             hrp: None,
             addr_format: AddressFormat::Standard,
+            taproot_output_key: None,
         };
 
         assert_eq!(address, "Fo2tBkpzaWQgtjFUkemsYnKyfvd2i8yTki".into());
@@ -457,6 +491,7 @@ mod tests {
             checksum_type: ChecksumType::KECCAK256,
             hrp: None,
             addr_format: AddressFormat::Standard,
+            taproot_output_key: None,
         };
 
         assert_eq!(address, "SVCbBs6FvPYxJrYoJc4TdCe47QNCgmTabv".into());
@@ -521,6 +556,7 @@ mod tests {
                 pub_addr_prefix: 0,
                 p2sh_addr_prefix: 5,
             },
+            taproot_output_key: None,
         };
 
         assert_eq!(
@@ -541,4 +577,27 @@ mod tests {
         let address: Address = "1DmFp16U73RrVZtYUbo2Ectt8mAnYScpqM".into();
         assert_eq!(address.to_cashaddress("prefix", 0, 5).unwrap(), expected_address);
     }
+
+    #[test]
+    fn test_taproot_address_from_to_segwitaddress() {
+        // https://github.com/bitcoin/bips/blob/master/bip-0086.mediawiki#test-vectors
+        let segaddr = "bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr";
+        let address = Address::from_segwitaddress(segaddr, ChecksumType::DSHA256, 0, 0).unwrap();
+        let expected_key: H256 = "a60869f0dbcf1dc659c9cecbaf8050135ea9e8cdc487053f1dc6880949dc684c".into();
+        assert_eq!(address.taproot_output_key, Some(expected_key));
+        assert!(address.is_taproot());
+        assert_eq!(address.to_string(), segaddr);
+        assert_eq!(address.display_address().unwrap(), segaddr);
+        assert!(address.to_cashaddress("bitcoincash", 0, 5).is_err());
+
+        let p2wpkh = Address::from_segwitaddress(
+            "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4",
+            ChecksumType::DSHA256,
+            0,
+            0,
+        )
+        .unwrap();
+        assert!(!p2wpkh.is_taproot());
+        assert_eq!(p2wpkh.to_string(), "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4");
+    }
 }
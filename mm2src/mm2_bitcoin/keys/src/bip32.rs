@@ -0,0 +1,472 @@
+//! BIP32 hierarchical deterministic keys.
+//! https://github.com/bitcoin/bips/blob/master/bip-0032.mediawiki
+
+use crate::{SECP_SIGN, SECP_VERIFY};
+use base58::{FromBase58, ToBase58};
+use crypto::{checksum, dhash160, hmac_sha512, ChecksumType};
+use hash::{H256, H264, H32};
+use secp256k1::{PublicKey, SecretKey};
+use std::fmt;
+use std::str::FromStr;
+use {Error, Public, Secret};
+
+/// Child numbers greater or equal to this one are hardened.
+pub const HARDENED_OFFSET: u32 = 0x8000_0000;
+/// The HMAC key used to derive the master key from a seed.
+const MASTER_KEY_HMAC_KEY: &[u8] = b"Bitcoin seed";
+/// Mainnet `xprv` version bytes.
+const XPRV_VERSION: [u8; 4] = [0x04, 0x88, 0xAD, 0xE4];
+/// Mainnet `xpub` version bytes.
+const XPUB_VERSION: [u8; 4] = [0x04, 0x88, 0xB2, 0x1E];
+/// 4 (version) + 1 (depth) + 4 (fingerprint) + 4 (child number) + 32 (chain code) + 33 (key) + 4 (checksum).
+const EXTENDED_KEY_SERIALIZED_LEN: usize = 82;
+
+/// A single BIP32 path component.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct ChildNumber(u32);
+
+impl ChildNumber {
+    pub fn normal(index: u32) -> Result<ChildNumber, Error> {
+        if index >= HARDENED_OFFSET {
+            return Err(Error::InvalidDerivationPath);
+        }
+        Ok(ChildNumber(index))
+    }
+
+    pub fn hardened(index: u32) -> Result<ChildNumber, Error> {
+        if index >= HARDENED_OFFSET {
+            return Err(Error::InvalidDerivationPath);
+        }
+        Ok(ChildNumber(index | HARDENED_OFFSET))
+    }
+
+    pub fn is_hardened(&self) -> bool { self.0 >= HARDENED_OFFSET }
+
+    /// The index without the hardened bit.
+    pub fn index(&self) -> u32 { self.0 & !HARDENED_OFFSET }
+
+    /// The raw value including the hardened bit.
+    pub fn value(&self) -> u32 { self.0 }
+}
+
+impl From<u32> for ChildNumber {
+    fn from(value: u32) -> Self { ChildNumber(value) }
+}
+
+impl fmt::Display for ChildNumber {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_hardened() {
+            write!(f, "{}'", self.index())
+        } else {
+            write!(f, "{}", self.index())
+        }
+    }
+}
+
+impl FromStr for ChildNumber {
+    type Err = Error;
+
+    /// Parses `44'`, `44h`, `44H` as hardened and `44` as a normal child number.
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let (index, hardened) = match s.strip_suffix(|c: char| c == '\'' || c == 'h' || c == 'H') {
+            Some(index) => (index, true),
+            None => (s, false),
+        };
+        let index: u32 = index.parse().map_err(|_| Error::InvalidDerivationPath)?;
+        if hardened {
+            ChildNumber::hardened(index)
+        } else {
+            ChildNumber::normal(index)
+        }
+    }
+}
+
+/// BIP32 derivation path like `m/44'/141'/0'/0/0`.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct DerivationPath(Vec<ChildNumber>);
+
+impl DerivationPath {
+    pub fn as_slice(&self) -> &[ChildNumber] { &self.0 }
+
+    pub fn len(&self) -> usize { self.0.len() }
+
+    pub fn is_empty(&self) -> bool { self.0.is_empty() }
+
+    /// Returns a new path with the given child number appended.
+    pub fn child(&self, child: ChildNumber) -> DerivationPath {
+        let mut path = self.0.clone();
+        path.push(child);
+        DerivationPath(path)
+    }
+
+    /// Returns a new path with the given child numbers appended.
+    pub fn extend<I: IntoIterator<Item = ChildNumber>>(&self, children: I) -> DerivationPath {
+        let mut path = self.0.clone();
+        path.extend(children);
+        DerivationPath(path)
+    }
+}
+
+impl From<Vec<ChildNumber>> for DerivationPath {
+    fn from(path: Vec<ChildNumber>) -> Self { DerivationPath(path) }
+}
+
+impl fmt::Display for DerivationPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "m")?;
+        for child in self.0.iter() {
+            write!(f, "/{}", child)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for DerivationPath {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let mut parts = s.split('/');
+        if parts.next() != Some("m") {
+            return Err(Error::InvalidDerivationPath);
+        }
+        let path = parts.map(ChildNumber::from_str).collect::<Result<Vec<_>, _>>()?;
+        Ok(DerivationPath(path))
+    }
+}
+
+/// BIP32 extended private key.
+#[derive(Clone, PartialEq)]
+pub struct ExtendedPrivateKey {
+    pub depth: u8,
+    pub parent_fingerprint: H32,
+    pub child_number: ChildNumber,
+    pub chain_code: H256,
+    pub secret: Secret,
+}
+
+impl ExtendedPrivateKey {
+    /// Generates the master key from the given seed (usually derived from a BIP39 mnemonic).
+    pub fn from_seed(seed: &[u8]) -> Result<ExtendedPrivateKey, Error> {
+        let hmac = hmac_sha512(MASTER_KEY_HMAC_KEY, seed);
+        // Check if the left part of the HMAC is a valid secp256k1 secret.
+        SecretKey::from_slice(&hmac[..32])?;
+        Ok(ExtendedPrivateKey {
+            depth: 0,
+            parent_fingerprint: H32::default(),
+            child_number: ChildNumber(0),
+            chain_code: H256::from(&hmac[32..]),
+            secret: H256::from(&hmac[..32]),
+        })
+    }
+
+    /// Derives the child key using the CKDpriv function.
+    pub fn derive_child(&self, child: ChildNumber) -> Result<ExtendedPrivateKey, Error> {
+        let mut data = Vec::with_capacity(37);
+        if child.is_hardened() {
+            data.push(0);
+            data.extend_from_slice(&*self.secret);
+        } else {
+            data.extend_from_slice(&self.public_key()?.serialize());
+        }
+        data.extend_from_slice(&child.value().to_be_bytes());
+
+        let hmac = hmac_sha512(&*self.chain_code, &data);
+        let mut secret = SecretKey::from_slice(&hmac[..32])?;
+        secret.add_assign(&*self.secret)?;
+
+        let depth = self.depth.checked_add(1).ok_or(Error::InvalidDerivationPath)?;
+        Ok(ExtendedPrivateKey {
+            depth,
+            parent_fingerprint: self.fingerprint()?,
+            child_number: child,
+            chain_code: H256::from(&hmac[32..]),
+            secret: H256::from(&secret[..]),
+        })
+    }
+
+    /// Derives the key at the given path relative to this key.
+    pub fn derive_path(&self, path: &DerivationPath) -> Result<ExtendedPrivateKey, Error> {
+        path.as_slice()
+            .iter()
+            .try_fold(self.clone(), |key, child| key.derive_child(*child))
+    }
+
+    /// Returns the compressed public key.
+    pub fn public(&self) -> Result<Public, Error> {
+        let public = H264::from(&self.public_key()?.serialize()[..]);
+        Ok(Public::Compressed(public))
+    }
+
+    /// Returns the neutered version of this key.
+    pub fn extended_public_key(&self) -> Result<ExtendedPublicKey, Error> {
+        Ok(ExtendedPublicKey {
+            depth: self.depth,
+            parent_fingerprint: self.parent_fingerprint.clone(),
+            child_number: self.child_number,
+            chain_code: self.chain_code.clone(),
+            public: self.public_key()?,
+        })
+    }
+
+    /// The first 4 bytes of the key identifier.
+    pub fn fingerprint(&self) -> Result<H32, Error> { Ok(fingerprint(&self.public_key()?)) }
+
+    fn public_key(&self) -> Result<PublicKey, Error> {
+        let secret = SecretKey::from_slice(&*self.secret)?;
+        Ok(PublicKey::from_secret_key(&SECP_SIGN, &secret))
+    }
+}
+
+impl fmt::Debug for ExtendedPrivateKey {
+    /// Do not print the secret.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ExtendedPrivateKey")
+            .field("depth", &self.depth)
+            .field("parent_fingerprint", &self.parent_fingerprint)
+            .field("child_number", &self.child_number)
+            .finish()
+    }
+}
+
+impl fmt::Display for ExtendedPrivateKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut key = [0; 33];
+        key[1..].copy_from_slice(&*self.secret);
+        let layout = encode_extended_key(
+            XPRV_VERSION,
+            self.depth,
+            &self.parent_fingerprint,
+            self.child_number,
+            &self.chain_code,
+            &key,
+        );
+        layout.to_base58().fmt(f)
+    }
+}
+
+impl FromStr for ExtendedPrivateKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let (version, depth, parent_fingerprint, child_number, chain_code, key) = decode_extended_key(s)?;
+        if version != XPRV_VERSION || key[0] != 0 {
+            return Err(Error::InvalidExtendedKey);
+        }
+        SecretKey::from_slice(&key[1..])?;
+        Ok(ExtendedPrivateKey {
+            depth,
+            parent_fingerprint,
+            child_number,
+            chain_code,
+            secret: H256::from(&key[1..]),
+        })
+    }
+}
+
+/// BIP32 extended public key.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExtendedPublicKey {
+    pub depth: u8,
+    pub parent_fingerprint: H32,
+    pub child_number: ChildNumber,
+    pub chain_code: H256,
+    pub public: PublicKey,
+}
+
+impl ExtendedPublicKey {
+    /// Derives the child key using the CKDpub function.
+    /// Hardened child keys can't be derived from a public key.
+    pub fn derive_child(&self, child: ChildNumber) -> Result<ExtendedPublicKey, Error> {
+        if child.is_hardened() {
+            return Err(Error::InvalidDerivationPath);
+        }
+        let mut data = Vec::with_capacity(37);
+        data.extend_from_slice(&self.public.serialize());
+        data.extend_from_slice(&child.value().to_be_bytes());
+
+        let hmac = hmac_sha512(&*self.chain_code, &data);
+        let mut public = self.public;
+        public.add_exp_assign(&SECP_VERIFY, &hmac[..32])?;
+
+        let depth = self.depth.checked_add(1).ok_or(Error::InvalidDerivationPath)?;
+        Ok(ExtendedPublicKey {
+            depth,
+            parent_fingerprint: self.fingerprint(),
+            child_number: child,
+            chain_code: H256::from(&hmac[32..]),
+            public,
+        })
+    }
+
+    /// Derives the key at the given path relative to this key.
+    pub fn derive_path(&self, path: &DerivationPath) -> Result<ExtendedPublicKey, Error> {
+        path.as_slice()
+            .iter()
+            .try_fold(self.clone(), |key, child| key.derive_child(*child))
+    }
+
+    /// Returns the compressed public key.
+    pub fn public(&self) -> Public { Public::Compressed(H264::from(&self.public.serialize()[..])) }
+
+    /// The first 4 bytes of the key identifier.
+    pub fn fingerprint(&self) -> H32 { fingerprint(&self.public) }
+}
+
+impl fmt::Display for ExtendedPublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let layout = encode_extended_key(
+            XPUB_VERSION,
+            self.depth,
+            &self.parent_fingerprint,
+            self.child_number,
+            &self.chain_code,
+            &self.public.serialize(),
+        );
+        layout.to_base58().fmt(f)
+    }
+}
+
+impl FromStr for ExtendedPublicKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let (version, depth, parent_fingerprint, child_number, chain_code, key) = decode_extended_key(s)?;
+        if version != XPUB_VERSION {
+            return Err(Error::InvalidExtendedKey);
+        }
+        let public = PublicKey::from_slice(&key)?;
+        Ok(ExtendedPublicKey {
+            depth,
+            parent_fingerprint,
+            child_number,
+            chain_code,
+            public,
+        })
+    }
+}
+
+fn fingerprint(public: &PublicKey) -> H32 {
+    let identifier = dhash160(&public.serialize());
+    H32::from(&identifier[..4])
+}
+
+fn encode_extended_key(
+    version: [u8; 4],
+    depth: u8,
+    parent_fingerprint: &H32,
+    child_number: ChildNumber,
+    chain_code: &H256,
+    key: &[u8; 33],
+) -> Vec<u8> {
+    let mut result = Vec::with_capacity(EXTENDED_KEY_SERIALIZED_LEN);
+    result.extend_from_slice(&version);
+    result.push(depth);
+    result.extend_from_slice(&**parent_fingerprint);
+    result.extend_from_slice(&child_number.value().to_be_bytes());
+    result.extend_from_slice(&**chain_code);
+    result.extend_from_slice(key);
+    let cs = checksum(&result, &ChecksumType::DSHA256);
+    result.extend_from_slice(&*cs);
+    result
+}
+
+#[allow(clippy::type_complexity)]
+fn decode_extended_key(s: &str) -> Result<([u8; 4], u8, H32, ChildNumber, H256, [u8; 33]), Error> {
+    let data = s.from_base58().map_err(|_| Error::InvalidExtendedKey)?;
+    if data.len() != EXTENDED_KEY_SERIALIZED_LEN {
+        return Err(Error::InvalidExtendedKey);
+    }
+    let cs = checksum(&data[..78], &ChecksumType::DSHA256);
+    if data[78..] != *cs {
+        return Err(Error::InvalidChecksum);
+    }
+
+    let mut version = [0; 4];
+    version.copy_from_slice(&data[0..4]);
+    let depth = data[4];
+    let parent_fingerprint = H32::from(&data[5..9]);
+    let mut child_number = [0; 4];
+    child_number.copy_from_slice(&data[9..13]);
+    let child_number = ChildNumber(u32::from_be_bytes(child_number));
+    let chain_code = H256::from(&data[13..45]);
+    let mut key = [0; 33];
+    key.copy_from_slice(&data[45..78]);
+    Ok((version, depth, parent_fingerprint, child_number, chain_code, key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ChildNumber, DerivationPath, ExtendedPrivateKey, ExtendedPublicKey, HARDENED_OFFSET};
+    use hex::FromHex;
+    use Error;
+
+    /// Test vector 1 from https://github.com/bitcoin/bips/blob/master/bip-0032.mediawiki#test-vector-1
+    #[test]
+    fn test_bip32_vector_1() {
+        let seed: Vec<u8> = "000102030405060708090a0b0c0d0e0f".from_hex().unwrap();
+        let master = ExtendedPrivateKey::from_seed(&seed).unwrap();
+        assert_eq!(master.to_string(), "xprv9s21ZrQH143K3QTDL4LXw2F7HEK3wJUD2nW2nRk4stbPy6cq3jPPqjiChkVvvNKmPGJxWUtg6LnF5kejMRNNU3TGtRBeJgk33yuGBxrMPHi");
+        assert_eq!(master.extended_public_key().unwrap().to_string(), "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8");
+
+        let path: DerivationPath = "m/0H".parse().unwrap();
+        let child = master.derive_path(&path).unwrap();
+        assert_eq!(child.to_string(), "xprv9uHRZZhk6KAJC1avXpDAp4MDc3sQKNxDiPvvkX8Br5ngLNv1TxvUxt4cV1rGL5hj6KCesnDYUhd7oWgT11eZG7XnxHrnYeSvkzY7d2bhkJ7");
+
+        let path: DerivationPath = "m/0'/1".parse().unwrap();
+        let child = master.derive_path(&path).unwrap();
+        assert_eq!(child.to_string(), "xprv9wTYmMFdV23N2TdNG573QoEsfRrWKQgWeibmLntzniatZvR9BmLnvSxqu53Kw1UmYPxLgboyZQaXwTCg8MSY3H2EU4pWcQDnRnrVA1xe8fs");
+    }
+
+    #[test]
+    fn test_public_derivation_matches_private() {
+        let seed: Vec<u8> = "000102030405060708090a0b0c0d0e0f".from_hex().unwrap();
+        let account = ExtendedPrivateKey::from_seed(&seed)
+            .unwrap()
+            .derive_path(&"m/44'/0'/0'".parse().unwrap())
+            .unwrap();
+        let relative: DerivationPath = "m/0/5".parse().unwrap();
+
+        let expected = account.derive_path(&relative).unwrap().extended_public_key().unwrap();
+        let actual = account.extended_public_key().unwrap().derive_path(&relative).unwrap();
+        assert_eq!(actual, expected);
+
+        let hardened = account
+            .extended_public_key()
+            .unwrap()
+            .derive_child(ChildNumber::from(HARDENED_OFFSET));
+        assert_eq!(hardened, Err(Error::InvalidDerivationPath));
+    }
+
+    #[test]
+    fn test_extended_key_from_str() {
+        let xprv = "xprv9wTYmMFdV23N2TdNG573QoEsfRrWKQgWeibmLntzniatZvR9BmLnvSxqu53Kw1UmYPxLgboyZQaXwTCg8MSY3H2EU4pWcQDnRnrVA1xe8fs";
+        let key: ExtendedPrivateKey = xprv.parse().unwrap();
+        assert_eq!(key.depth, 2);
+        assert_eq!(key.to_string(), xprv);
+
+        let xpub = "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8";
+        let key: ExtendedPublicKey = xpub.parse().unwrap();
+        assert_eq!(key.to_string(), xpub);
+
+        assert_eq!(xpub.parse::<ExtendedPrivateKey>(), Err(Error::InvalidExtendedKey));
+        assert_eq!(xprv.parse::<ExtendedPublicKey>(), Err(Error::InvalidExtendedKey));
+    }
+
+    #[test]
+    fn test_derivation_path_from_str() {
+        let path: DerivationPath = "m/44'/141'/0'/0/1".parse().unwrap();
+        assert_eq!(path.len(), 5);
+        assert!(path.as_slice()[1].is_hardened());
+        assert_eq!(path.as_slice()[1].index(), 141);
+        assert!(!path.as_slice()[4].is_hardened());
+        assert_eq!(path.to_string(), "m/44'/141'/0'/0/1");
+
+        assert_eq!("m".parse::<DerivationPath>(), Ok(DerivationPath::default()));
+        assert_eq!("44'/0'".parse::<DerivationPath>(), Err(Error::InvalidDerivationPath));
+        assert_eq!("m/x".parse::<DerivationPath>(), Err(Error::InvalidDerivationPath));
+        assert_eq!(
+            "m/2147483648".parse::<DerivationPath>(),
+            Err(Error::InvalidDerivationPath)
+        );
+    }
+}
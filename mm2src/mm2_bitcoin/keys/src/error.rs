@@ -12,6 +12,9 @@ pub enum Error {
     InvalidPrivate,
     InvalidAddress,
     FailedKeyGeneration,
+    InvalidDerivationPath,
+    InvalidExtendedKey,
+    InvalidMnemonic,
 }
 
 impl fmt::Display for Error {
@@ -26,6 +29,9 @@ impl fmt::Display for Error {
             Error::InvalidPrivate => "Invalid Private",
             Error::InvalidAddress => "Invalid Address",
             Error::FailedKeyGeneration => "Key generation failed",
+            Error::InvalidDerivationPath => "Invalid Derivation Path",
+            Error::InvalidExtendedKey => "Invalid Extended Key",
+            Error::InvalidMnemonic => "Invalid Mnemonic",
         };
 
         msg.fmt(f)
@@ -76,6 +76,7 @@ impl KeyPair {
 mod tests {
     use super::KeyPair;
     use crypto::dhash256;
+    use Public;
 
     /// Tests from:
     /// https://github.com/bitcoin/bitcoin/blob/a6a860796a44a2805a58391a009ba22752f64e32/src/test/key_tests.cpp
@@ -86,13 +87,9 @@ mod tests {
     const SECRET_2C: &'static str = "L3Hq7a8FEQwJkW1M2GNKDW28546Vp5miewcCzSqUD9kCAXrJdS3g";
     const SIGN_1: &'static str = "304402205dbbddda71772d95ce91cd2d14b592cfbc1dd0aabd6a394b6c2d377bbe59d31d022014ddda21494a4e221f0824f0b8b924c43fa43c0ad57dccdaa11f81a6bd4582f6";
     const SIGN_2: &'static str = "3044022052d8a32079c11e79db95af63bb9600c5b04f21a9ca33dc129c2bfa8ac9dc1cd5022061d8ae5e0f6c1a16bde3719c64c2fd70e404b6428ab9a69566962e8771b5944d";
-    #[allow(dead_code)]
     const SIGN_COMPACT_1: &'static str = "1c5dbbddda71772d95ce91cd2d14b592cfbc1dd0aabd6a394b6c2d377bbe59d31d14ddda21494a4e221f0824f0b8b924c43fa43c0ad57dccdaa11f81a6bd4582f6";
-    #[allow(dead_code)]
     const SIGN_COMPACT_1C: &'static str = "205dbbddda71772d95ce91cd2d14b592cfbc1dd0aabd6a394b6c2d377bbe59d31d14ddda21494a4e221f0824f0b8b924c43fa43c0ad57dccdaa11f81a6bd4582f6";
-    #[allow(dead_code)]
     const SIGN_COMPACT_2: &'static str = "1c52d8a32079c11e79db95af63bb9600c5b04f21a9ca33dc129c2bfa8ac9dc1cd561d8ae5e0f6c1a16bde3719c64c2fd70e404b6428ab9a69566962e8771b5944d";
-    #[allow(dead_code)]
     const SIGN_COMPACT_2C: &'static str = "2052d8a32079c11e79db95af63bb9600c5b04f21a9ca33dc129c2bfa8ac9dc1cd561d8ae5e0f6c1a16bde3719c64c2fd70e404b6428ab9a69566962e8771b5944d";
 
     fn check_compressed(secret: &'static str, compressed: bool) -> bool {
@@ -112,6 +109,20 @@ mod tests {
         kp.public().verify(&message, &signature.into()).unwrap()
     }
 
+    fn check_sign_compact(secret: &'static str, raw_message: &[u8], signature: &'static str) -> bool {
+        let message = dhash256(raw_message);
+        let kp = KeyPair::from_private(secret.into()).unwrap();
+        kp.private().sign_compact(&message).unwrap() == signature.into()
+    }
+
+    fn check_recover_compact(secret: &'static str, raw_message: &[u8]) -> bool {
+        let message = dhash256(raw_message);
+        let kp = KeyPair::from_private(secret.into()).unwrap();
+        let signature = kp.private().sign_compact(&message).unwrap();
+        let recovered = Public::recover_compact(&message, &signature).unwrap();
+        kp.public() == &recovered
+    }
+
     #[test]
     fn test_keypair_is_compressed() {
         assert!(check_compressed(SECRET_0, false));
@@ -140,4 +151,24 @@ mod tests {
         assert!(check_verify(SECRET_2C, message, SIGN_2));
         assert!(!check_verify(SECRET_2C, b"", SIGN_2));
     }
+
+    #[test]
+    fn test_sign_compact() {
+        let message = b"Very deterministic message";
+        assert!(check_sign_compact(SECRET_1, message, SIGN_COMPACT_1));
+        assert!(check_sign_compact(SECRET_1C, message, SIGN_COMPACT_1C));
+        assert!(check_sign_compact(SECRET_2, message, SIGN_COMPACT_2));
+        assert!(check_sign_compact(SECRET_2C, message, SIGN_COMPACT_2C));
+        assert!(!check_sign_compact(SECRET_2C, b"", SIGN_COMPACT_2C));
+    }
+
+    #[test]
+    fn test_recover_compact() {
+        let message = b"Very deterministic message";
+        assert!(check_recover_compact(SECRET_0, message));
+        assert!(check_recover_compact(SECRET_1, message));
+        assert!(check_recover_compact(SECRET_1C, message));
+        assert!(check_recover_compact(SECRET_2, message));
+        assert!(check_recover_compact(SECRET_2C, message));
+    }
 }
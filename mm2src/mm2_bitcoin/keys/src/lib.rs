@@ -2,6 +2,7 @@
 
 extern crate base58;
 extern crate bech32;
+extern crate bip39;
 extern crate bitcrypto as crypto;
 extern crate derive_more;
 extern crate lazy_static;
@@ -11,10 +12,12 @@ extern crate secp256k1;
 extern crate serde;
 
 mod address;
+pub mod bip32;
 mod cashaddress;
 mod display;
 mod error;
 mod keypair;
+pub mod mnemonic;
 mod network;
 mod private;
 mod public;
@@ -0,0 +1,74 @@
+//! BIP39 mnemonic code for generating deterministic keys.
+//! https://github.com/bitcoin/bips/blob/master/bip-0039.mediawiki
+
+use bip39::{Language, Mnemonic};
+use Error;
+
+/// Checks if the given phrase is a valid English BIP39 mnemonic.
+pub fn is_valid_mnemonic(phrase: &str) -> bool { Mnemonic::parse_in_normalized(Language::English, phrase).is_ok() }
+
+/// Converts the given BIP39 mnemonic and an optional passphrase into a 64 bytes seed.
+/// The seed can be used to generate the BIP32 master key.
+pub fn seed_from_mnemonic(phrase: &str, passphrase: &str) -> Result<[u8; 64], Error> {
+    let mnemonic = Mnemonic::parse_in_normalized(Language::English, phrase).map_err(|_| Error::InvalidMnemonic)?;
+    Ok(mnemonic.to_seed_normalized(passphrase))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_valid_mnemonic, seed_from_mnemonic};
+    use address::{Address, AddressFormat};
+    use bip32::ExtendedPrivateKey;
+    use crypto::ChecksumType;
+    use hex::ToHex;
+    use Error;
+
+    const ABANDON_MNEMONIC: &str =
+        "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+
+    #[test]
+    fn test_seed_from_mnemonic() {
+        let seed = seed_from_mnemonic(ABANDON_MNEMONIC, "").unwrap();
+        assert_eq!(
+            seed.to_hex::<String>(),
+            "5eb00bbddcf069084889a8ab9155568165f5c453ccb85e70811aaed6f6da5fc19a5ac40b389cd370d086206dec8aa6c43daea6690f20ad3d8d48b2d2ce9e38e4"
+        );
+
+        let seed = seed_from_mnemonic(ABANDON_MNEMONIC, "TREZOR").unwrap();
+        assert_eq!(
+            seed.to_hex::<String>(),
+            "c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e53495531f09a6987599d18264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04"
+        );
+    }
+
+    #[test]
+    fn test_invalid_mnemonic() {
+        // invalid checksum
+        let phrase = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon";
+        assert!(!is_valid_mnemonic(phrase));
+        assert_eq!(seed_from_mnemonic(phrase, ""), Err(Error::InvalidMnemonic));
+        // a brain-wallet passphrase
+        assert!(!is_valid_mnemonic("my secret passphrase"));
+        assert!(is_valid_mnemonic(ABANDON_MNEMONIC));
+    }
+
+    /// The first BIP44 address of the well-known test mnemonic, the same as other BIP39 wallets show.
+    #[test]
+    fn test_bip44_address_from_mnemonic() {
+        let seed = seed_from_mnemonic(ABANDON_MNEMONIC, "").unwrap();
+        let key = ExtendedPrivateKey::from_seed(&seed)
+            .unwrap()
+            .derive_path(&"m/44'/0'/0'/0/0".parse().unwrap())
+            .unwrap();
+        let address = Address {
+            prefix: 0,
+            t_addr_prefix: 0,
+            hash: key.public().unwrap().address_hash(),
+            checksum_type: ChecksumType::DSHA256,
+            hrp: None,
+            addr_format: AddressFormat::Standard,
+            taproot_output_key: None,
+        };
+        assert_eq!(address.to_string(), "1LqBGSKuX5yYUonjxT5qGfpUsXKYYWeabA");
+    }
+}
@@ -8,7 +8,7 @@ use hex::ToHex;
 use secp256k1::{Message as SecpMessage, SecretKey};
 use std::fmt;
 use std::str::FromStr;
-use {DisplayLayout, Error, Message, Secret, Signature};
+use {CompactSignature, DisplayLayout, Error, Message, Secret, Signature};
 
 /// Secret with additional network prefix and format type
 #[derive(Default, PartialEq, Clone)]
@@ -31,6 +31,19 @@ impl Private {
         let data = signature.serialize_der();
         Ok(data.as_ref().to_vec().into())
     }
+
+    /// Signs the message so that the public key can be recovered from the signature.
+    pub fn sign_compact(&self, message: &Message) -> Result<CompactSignature, Error> {
+        let secret = SecretKey::from_slice(&*self.secret)?;
+        let message = SecpMessage::from_slice(&**message)?;
+        let signature = SECP_SIGN.sign_recoverable(&message, &secret);
+        let (recovery_id, data) = signature.serialize_compact();
+        Ok(CompactSignature::from_parts(
+            recovery_id.to_i32() as u8,
+            &data,
+            self.compressed,
+        ))
+    }
 }
 
 impl DisplayLayout for Private {
@@ -2,9 +2,10 @@ use crate::SECP_VERIFY;
 use crypto::dhash160;
 use hash::{H264, H520};
 use hex::ToHex;
+use secp256k1::recovery::{RecoverableSignature, RecoveryId};
 use secp256k1::{Message as SecpMessage, PublicKey, Signature as SecpSignature};
 use std::{fmt, ops};
-use {AddressHash, Error, Message, Signature};
+use {AddressHash, CompactSignature, Error, Message, Signature};
 
 /// Secret public key
 pub enum Public {
@@ -47,6 +48,21 @@ impl Public {
         let message = SecpMessage::from_slice(&**message)?;
         Ok(SECP_VERIFY.verify(&message, &signature, &public).is_ok())
     }
+
+    /// Recovers the public key from the compact signature of the message.
+    /// The key is compressed if the signature header says so.
+    pub fn recover_compact(message: &Message, signature: &CompactSignature) -> Result<Self, Error> {
+        let (recovery_id, compressed) = signature.recovery_params()?;
+        let recovery_id = RecoveryId::from_i32(recovery_id as i32)?;
+        let signature = RecoverableSignature::from_compact(&signature[1..65], recovery_id)?;
+        let message = SecpMessage::from_slice(&**message)?;
+        let public = SECP_VERIFY.recover(&message, &signature)?;
+        if compressed {
+            Ok(Public::Compressed(H264::from(public.serialize())))
+        } else {
+            Ok(Public::Normal(H520::from(public.serialize_uncompressed())))
+        }
+    }
 }
 
 impl ops::Deref for Public {
@@ -2,6 +2,7 @@ use std::fmt;
 use std::str::FromStr;
 
 use bech32;
+use hash::H256;
 use AddressHash;
 
 /// Address error.
@@ -21,6 +22,11 @@ pub enum Error {
     InvalidSegwitV0ProgramLength(usize),
     /// An uncompressed pubkey was used where it is not allowed.
     UncompressedPubkey,
+    /// The witness v0 address must be encoded with bech32 and the others with bech32m.
+    InvalidBech32Variant {
+        expected: bech32::Variant,
+        found: bech32::Variant,
+    },
 }
 
 impl fmt::Display for Error {
@@ -41,6 +47,9 @@ impl fmt::Display for Error {
                 l,
             ),
             Error::UncompressedPubkey => write!(f, "an uncompressed pubkey was used where it is not allowed",),
+            Error::InvalidBech32Variant { expected, found } => {
+                write!(f, "invalid bech32 variant: expected {:?}, found {:?}", expected, found)
+            },
         }
     }
 }
@@ -56,9 +65,11 @@ pub enum AddressType {
     P2wpkh,
     /// pay-to-witness-script-hash
     P2wsh,
+    /// pay-to-taproot
+    P2tr,
 }
 
-#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 /// A Bitcoin segwit address
 pub struct SegwitAddress {
     /// The human-readable part
@@ -78,6 +89,28 @@ impl SegwitAddress {
         }
     }
 
+    /// Creates the P2WSH address from the sha256 hash of the witness script.
+    pub fn new_p2wsh(script_hash: &H256, hrp: String) -> SegwitAddress {
+        SegwitAddress {
+            hrp,
+            version: bech32::u5::try_from_u8(0).expect("0<32"),
+            program: script_hash.to_vec(),
+        }
+    }
+
+    /// Creates the P2TR address from the 32 bytes long x-only output key.
+    /// https://github.com/bitcoin/bips/blob/master/bip-0341.mediawiki
+    pub fn new_p2tr(output_key: &H256, hrp: String) -> SegwitAddress {
+        SegwitAddress {
+            hrp,
+            version: bech32::u5::try_from_u8(1).expect("1<32"),
+            program: output_key.to_vec(),
+        }
+    }
+
+    /// The witness program version.
+    pub fn version(&self) -> u8 { self.version.to_u8() }
+
     /// Get the address type of the address.
     /// None if unknown or non-standard.
     pub fn address_type(&self) -> Option<AddressType> {
@@ -88,6 +121,10 @@ impl SegwitAddress {
                 32 => Some(AddressType::P2wsh),
                 _ => None,
             },
+            1 => match self.program.len() {
+                32 => Some(AddressType::P2tr),
+                _ => None,
+            },
             _ => None,
         }
     }
@@ -122,12 +159,22 @@ impl fmt::Display for SegwitAddress {
         } else {
             fmt as &mut dyn fmt::Write
         };
-        let mut bech32_writer = bech32::Bech32Writer::new(self.hrp.as_str(), bech32::Variant::Bech32, writer)?;
+        let mut bech32_writer = bech32::Bech32Writer::new(self.hrp.as_str(), bech32_variant(self.version), writer)?;
         bech32::WriteBase32::write_u5(&mut bech32_writer, self.version)?;
         bech32::ToBase32::write_base32(&self.program, &mut bech32_writer)
     }
 }
 
+/// The witness v0 addresses are encoded with bech32 and the newer versions with bech32m.
+/// https://github.com/bitcoin/bips/blob/master/bip-0350.mediawiki
+fn bech32_variant(version: bech32::u5) -> bech32::Variant {
+    if version.to_u8() == 0 {
+        bech32::Variant::Bech32
+    } else {
+        bech32::Variant::Bech32m
+    }
+}
+
 /// Extract the bech32 prefix.
 /// Returns the same slice when no prefix is found.
 fn find_bech32_prefix(bech32: &str) -> Option<&str> {
@@ -148,9 +195,7 @@ impl FromStr for SegwitAddress {
             Some(hrp) => hrp.to_string(),
             None => return Err(Error::InvalidSegwitAddressFormat),
         };
-        // decode as bech32, should use Variant if Bech32m is used alongside Bech32
-        // The improved Bech32m variant described in [BIP-0350](https://github.com/bitcoin/bips/blob/master/bip-0350.mediawiki)
-        let (_, payload, _) = bech32::decode(s)?;
+        let (_, payload, variant) = bech32::decode(s)?;
         if payload.is_empty() {
             return Err(Error::EmptyBech32Payload);
         }
@@ -174,6 +219,14 @@ impl FromStr for SegwitAddress {
             return Err(Error::InvalidSegwitV0ProgramLength(program.len()));
         }
 
+        let expected_variant = bech32_variant(version);
+        if variant != expected_variant {
+            return Err(Error::InvalidBech32Variant {
+                expected: expected_variant,
+                found: variant,
+            });
+        }
+
         Ok(SegwitAddress { hrp, version, program })
     }
 }
@@ -206,4 +259,58 @@ mod tests {
         assert_eq!(&addr.to_string(), "bc1qvzvkjn4q3nszqxrv3nraga2r822xjty3ykvkuw");
         assert_eq!(addr.address_type(), Some(AddressType::P2wpkh));
     }
+
+    #[test]
+    fn test_p2wsh_address() {
+        // https://github.com/bitcoin/bips/blob/master/bip-0173.mediawiki#examples
+        let script = hex_to_bytes("210279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798ac").unwrap();
+        let script_hash = crypto::sha256(&script);
+        let addr = SegwitAddress::new_p2wsh(&script_hash, "bc".to_string());
+        assert_eq!(
+            &addr.to_string(),
+            "bc1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3qccfmv3"
+        );
+        assert_eq!(addr.address_type(), Some(AddressType::P2wsh));
+    }
+
+    #[test]
+    fn test_p2tr_address() {
+        // https://github.com/bitcoin/bips/blob/master/bip-0086.mediawiki#test-vectors
+        let output_key = hex_to_bytes("a60869f0dbcf1dc659c9cecbaf8050135ea9e8cdc487053f1dc6880949dc684c").unwrap();
+        let addr = SegwitAddress::new_p2tr(&H256::from(output_key.as_slice()), "bc".to_string());
+        let expected = "bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr";
+        assert_eq!(&addr.to_string(), expected);
+        assert_eq!(addr.address_type(), Some(AddressType::P2tr));
+
+        let parsed = SegwitAddress::from_str(expected).unwrap();
+        assert_eq!(parsed, addr);
+        assert_eq!(parsed.version(), 1);
+    }
+
+    #[test]
+    fn test_invalid_bech32_variant() {
+        fn encode(version: u8, program: &[u8], variant: bech32::Variant) -> String {
+            let mut data = vec![bech32::u5::try_from_u8(version).unwrap()];
+            data.extend(bech32::ToBase32::to_base32(&program));
+            bech32::encode("bc", data, variant).unwrap()
+        }
+
+        let v0_bech32m = encode(0, &[0x75; 20], bech32::Variant::Bech32m);
+        assert_eq!(
+            SegwitAddress::from_str(&v0_bech32m).unwrap_err(),
+            Error::InvalidBech32Variant {
+                expected: bech32::Variant::Bech32,
+                found: bech32::Variant::Bech32m,
+            }
+        );
+
+        let v1_bech32 = encode(1, &[0x75; 32], bech32::Variant::Bech32);
+        assert_eq!(
+            SegwitAddress::from_str(&v1_bech32).unwrap_err(),
+            Error::InvalidBech32Variant {
+                expected: bech32::Variant::Bech32m,
+                found: bech32::Variant::Bech32,
+            }
+        );
+    }
 }
@@ -90,3 +90,29 @@ impl From<&'static str> for CompactSignature {
 impl From<H520> for CompactSignature {
     fn from(h: H520) -> Self { CompactSignature(h) }
 }
+
+impl CompactSignature {
+    /// The header byte is `27 + recovery_id`, plus 4 if the public key is compressed.
+    const HEADER_BASE: u8 = 27;
+
+    /// Creates the signature from the recovery id and the 64-byte `r || s`.
+    pub fn from_parts(recovery_id: u8, rs: &[u8], compressed: bool) -> CompactSignature {
+        let mut signature = H520::default();
+        signature[0] = CompactSignature::HEADER_BASE + recovery_id;
+        if compressed {
+            signature[0] += 4;
+        }
+        signature[1..65].copy_from_slice(&rs[0..64]);
+        CompactSignature(signature)
+    }
+
+    /// Returns the recovery id and whether the public key is compressed.
+    pub fn recovery_params(&self) -> Result<(u8, bool), Error> {
+        let header = self.0[0];
+        if header < CompactSignature::HEADER_BASE || header > CompactSignature::HEADER_BASE + 7 {
+            return Err(Error::InvalidSignature);
+        }
+        let params = header - CompactSignature::HEADER_BASE;
+        Ok((params & 3, params & 4 != 0))
+    }
+}
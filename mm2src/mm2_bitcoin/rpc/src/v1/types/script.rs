@@ -13,6 +13,7 @@ pub enum ScriptType {
     NullData,
     WitnessScript,
     WitnessKey,
+    WitnessTaproot,
     // Qtum specific
     CallSender,
     CreateSender,
@@ -32,6 +33,7 @@ impl From<GlobalScriptType> for ScriptType {
             GlobalScriptType::NullData => ScriptType::NullData,
             GlobalScriptType::WitnessScript => ScriptType::WitnessScript,
             GlobalScriptType::WitnessKey => ScriptType::WitnessKey,
+            GlobalScriptType::WitnessTaproot => ScriptType::WitnessTaproot,
             GlobalScriptType::CallSender => ScriptType::CallSender,
             GlobalScriptType::CreateSender => ScriptType::CreateSender,
             GlobalScriptType::Call => ScriptType::Call,
@@ -54,6 +56,7 @@ impl Serialize for ScriptType {
             ScriptType::NullData => "nulldata".serialize(serializer),
             ScriptType::WitnessScript => "witness_v0_scripthash".serialize(serializer),
             ScriptType::WitnessKey => "witness_v0_keyhash".serialize(serializer),
+            ScriptType::WitnessTaproot => "witness_v1_taproot".serialize(serializer),
             ScriptType::CallSender => "call_sender".serialize(serializer),
             ScriptType::CreateSender => "create_sender".serialize(serializer),
             ScriptType::Call => "call".serialize(serializer),
@@ -90,6 +93,7 @@ impl<'a> Deserialize<'a> for ScriptType {
                     "nulldata" => Ok(ScriptType::NullData),
                     "witness_v0_scripthash" => Ok(ScriptType::WitnessScript),
                     "witness_v0_keyhash" => Ok(ScriptType::WitnessKey),
+                    "witness_v1_taproot" => Ok(ScriptType::WitnessTaproot),
                     "call_sender" => Ok(ScriptType::CallSender),
                     "create_sender" => Ok(ScriptType::CreateSender),
                     "call" => Ok(ScriptType::Call),
@@ -134,6 +138,10 @@ mod tests {
             serde_json::to_string(&ScriptType::WitnessKey).unwrap(),
             r#""witness_v0_keyhash""#
         );
+        assert_eq!(
+            serde_json::to_string(&ScriptType::WitnessTaproot).unwrap(),
+            r#""witness_v1_taproot""#
+        );
         assert_eq!(
             serde_json::to_string(&ScriptType::CallSender).unwrap(),
             r#""call_sender""#
@@ -180,6 +188,10 @@ mod tests {
             serde_json::from_str::<ScriptType>(r#""witness_v0_keyhash""#).unwrap(),
             ScriptType::WitnessKey
         );
+        assert_eq!(
+            serde_json::from_str::<ScriptType>(r#""witness_v1_taproot""#).unwrap(),
+            ScriptType::WitnessTaproot
+        );
         assert_eq!(
             serde_json::from_str::<ScriptType>(r#""call_sender""#).unwrap(),
             ScriptType::CallSender
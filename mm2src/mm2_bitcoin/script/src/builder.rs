@@ -1,6 +1,7 @@
 //! Script builder
 
 use bytes::Bytes;
+use hash::H256;
 use keys::{AddressHash, Public};
 use {Num, Opcode, Script};
 
@@ -47,6 +48,22 @@ impl Builder {
             .into_script()
     }
 
+    /// Builds p2wsh script pubkey from the sha256 hash of the witness script
+    pub fn build_p2wsh(script_hash: &H256) -> Script {
+        Builder::default()
+            .push_opcode(Opcode::OP_0)
+            .push_bytes(&**script_hash)
+            .into_script()
+    }
+
+    /// Builds p2tr script pubkey from the x-only taproot output key
+    pub fn build_p2tr(output_key: &H256) -> Script {
+        Builder::default()
+            .push_opcode(Opcode::OP_1)
+            .push_bytes(&**output_key)
+            .into_script()
+    }
+
     /// Builds op_return script
     pub fn build_nulldata(bytes: &[u8]) -> Script {
         Builder::default()
@@ -12,6 +12,7 @@ mod error;
 mod flags;
 mod num;
 mod opcode;
+mod psbt;
 mod script;
 mod sign;
 mod stack;
@@ -24,6 +25,7 @@ pub use self::error::Error;
 pub use self::flags::VerificationFlags;
 pub use self::num::Num;
 pub use self::opcode::Opcode;
+pub use self::psbt::{KeySource, Psbt, PsbtError, PsbtInput, PsbtOutput};
 pub use self::script::{is_witness_commitment_script, Script, ScriptAddress, ScriptType, ScriptWitness};
 pub use self::sign::{SignatureVersion, SignerHashAlgo, TransactionInputSigner, UnsignedTransactionInput};
 pub use self::stack::Stack;
@@ -0,0 +1,679 @@
+//! Partially Signed Bitcoin Transaction format
+//! https://github.com/bitcoin/bips/blob/master/bip-0174.mediawiki
+
+use bytes::Bytes;
+use chain::{Transaction, TransactionOutput};
+use crypto::dhash160;
+use hash::H32;
+use ser::{deserialize, serialize, serialize_with_flags, CompactInteger, Error as SerError, Reader, Stream,
+          SERIALIZE_TRANSACTION_WITNESS};
+use std::collections::BTreeMap;
+use std::fmt;
+use {Builder, Script};
+
+/// `psbt` followed by the 0xff separator.
+const PSBT_MAGIC: &[u8] = b"psbt\xff";
+
+const PSBT_GLOBAL_UNSIGNED_TX: u8 = 0x00;
+
+const PSBT_IN_NON_WITNESS_UTXO: u8 = 0x00;
+const PSBT_IN_WITNESS_UTXO: u8 = 0x01;
+const PSBT_IN_PARTIAL_SIG: u8 = 0x02;
+const PSBT_IN_SIGHASH_TYPE: u8 = 0x03;
+const PSBT_IN_REDEEM_SCRIPT: u8 = 0x04;
+const PSBT_IN_WITNESS_SCRIPT: u8 = 0x05;
+const PSBT_IN_BIP32_DERIVATION: u8 = 0x06;
+const PSBT_IN_FINAL_SCRIPTSIG: u8 = 0x07;
+const PSBT_IN_FINAL_SCRIPTWITNESS: u8 = 0x08;
+
+const PSBT_OUT_REDEEM_SCRIPT: u8 = 0x00;
+const PSBT_OUT_WITNESS_SCRIPT: u8 = 0x01;
+const PSBT_OUT_BIP32_DERIVATION: u8 = 0x02;
+
+#[derive(Debug, PartialEq)]
+pub enum PsbtError {
+    InvalidMagic,
+    Serialization(SerError),
+    DuplicateKey(Vec<u8>),
+    InvalidKey(Vec<u8>),
+    InvalidValue {
+        key: Vec<u8>,
+        error: SerError,
+    },
+    MissingUnsignedTx,
+    /// The unsigned transaction must not have script sigs or witnesses.
+    UnsignedTxHasSignatures,
+    /// The number of the input or output maps doesn't match the unsigned transaction.
+    MapsCountMismatch,
+    /// The combined PSBTs are for the different transactions.
+    DifferentUnsignedTx,
+    InputIndexOutOfRange(usize),
+    MissingUtxo(usize),
+    /// The `non_witness_utxo` hash doesn't match the spent outpoint.
+    InvalidNonWitnessUtxo(usize),
+    UnsupportedScript(usize),
+    MissingSignature(usize),
+    NotFinalized(usize),
+}
+
+impl From<SerError> for PsbtError {
+    fn from(e: SerError) -> Self { PsbtError::Serialization(e) }
+}
+
+impl fmt::Display for PsbtError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PsbtError::InvalidMagic => write!(f, "Invalid PSBT magic bytes"),
+            PsbtError::Serialization(e) => write!(f, "PSBT serialization error: {:?}", e),
+            PsbtError::DuplicateKey(key) => write!(f, "Duplicate PSBT key {:?}", key),
+            PsbtError::InvalidKey(key) => write!(f, "Invalid PSBT key {:?}", key),
+            PsbtError::InvalidValue { key, error } => write!(f, "Invalid value of the PSBT key {:?}: {:?}", key, error),
+            PsbtError::MissingUnsignedTx => write!(f, "PSBT doesn't contain the unsigned transaction"),
+            PsbtError::UnsignedTxHasSignatures => write!(f, "PSBT unsigned transaction has script sigs or witnesses"),
+            PsbtError::MapsCountMismatch => write!(f, "PSBT maps don't match the unsigned transaction"),
+            PsbtError::DifferentUnsignedTx => write!(f, "PSBTs are for the different transactions"),
+            PsbtError::InputIndexOutOfRange(idx) => write!(f, "PSBT doesn't have the input {}", idx),
+            PsbtError::MissingUtxo(idx) => write!(f, "PSBT input {} doesn't have the spent output", idx),
+            PsbtError::InvalidNonWitnessUtxo(idx) => {
+                write!(f, "PSBT input {} non-witness UTXO doesn't match the outpoint", idx)
+            },
+            PsbtError::UnsupportedScript(idx) => write!(f, "PSBT input {} spends an unsupported script", idx),
+            PsbtError::MissingSignature(idx) => write!(f, "PSBT input {} doesn't have the required signature", idx),
+            PsbtError::NotFinalized(idx) => write!(f, "PSBT input {} is not finalized", idx),
+        }
+    }
+}
+
+/// The master key fingerprint and the derivation path of the public key.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct KeySource {
+    pub fingerprint: H32,
+    pub path: Vec<u32>,
+}
+
+impl KeySource {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut stream = Stream::default();
+        stream.append(&self.fingerprint);
+        for child in self.path.iter() {
+            stream.append(child);
+        }
+        stream.out().take()
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<KeySource, SerError> {
+        // the fingerprint is required, the path may be empty
+        if bytes.len() < 4 || bytes.len() % 4 != 0 {
+            return Err(SerError::MalformedData);
+        }
+        let mut reader = Reader::new(bytes);
+        let fingerprint = reader.read()?;
+        let mut path = Vec::with_capacity(bytes.len() / 4 - 1);
+        while !reader.is_finished() {
+            path.push(reader.read()?);
+        }
+        Ok(KeySource { fingerprint, path })
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PsbtInput {
+    /// The transaction the spent output belongs to. Required to sign the non-segwit inputs.
+    pub non_witness_utxo: Option<Transaction>,
+    /// The spent output. Enough to sign the segwit inputs.
+    pub witness_utxo: Option<TransactionOutput>,
+    /// The signatures followed by the sighash type byte by the public keys.
+    pub partial_sigs: BTreeMap<Vec<u8>, Vec<u8>>,
+    pub sighash_type: Option<u32>,
+    pub redeem_script: Option<Bytes>,
+    pub witness_script: Option<Bytes>,
+    pub bip32_derivation: BTreeMap<Vec<u8>, KeySource>,
+    pub final_script_sig: Option<Bytes>,
+    pub final_script_witness: Option<Vec<Bytes>>,
+    /// The key-value pairs of the unknown types are kept as is.
+    pub unknown: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl PsbtInput {
+    pub fn is_finalized(&self) -> bool { self.final_script_sig.is_some() || self.final_script_witness.is_some() }
+
+    fn merge(&mut self, other: PsbtInput) {
+        merge_option(&mut self.non_witness_utxo, other.non_witness_utxo);
+        merge_option(&mut self.witness_utxo, other.witness_utxo);
+        merge_option(&mut self.sighash_type, other.sighash_type);
+        merge_option(&mut self.redeem_script, other.redeem_script);
+        merge_option(&mut self.witness_script, other.witness_script);
+        merge_option(&mut self.final_script_sig, other.final_script_sig);
+        merge_option(&mut self.final_script_witness, other.final_script_witness);
+        merge_map(&mut self.partial_sigs, other.partial_sigs);
+        merge_map(&mut self.bip32_derivation, other.bip32_derivation);
+        merge_map(&mut self.unknown, other.unknown);
+    }
+
+    fn serialize(&self, stream: &mut Stream) {
+        if let Some(ref tx) = self.non_witness_utxo {
+            let tx = serialize_with_flags(tx, SERIALIZE_TRANSACTION_WITNESS);
+            append_pair(stream, PSBT_IN_NON_WITNESS_UTXO, &[], &tx);
+        }
+        if let Some(ref output) = self.witness_utxo {
+            append_pair(stream, PSBT_IN_WITNESS_UTXO, &[], &serialize(output));
+        }
+        for (pubkey, signature) in self.partial_sigs.iter() {
+            append_pair(stream, PSBT_IN_PARTIAL_SIG, pubkey, signature);
+        }
+        if let Some(sighash_type) = self.sighash_type {
+            append_pair(stream, PSBT_IN_SIGHASH_TYPE, &[], &serialize(&sighash_type));
+        }
+        if let Some(ref script) = self.redeem_script {
+            append_pair(stream, PSBT_IN_REDEEM_SCRIPT, &[], script);
+        }
+        if let Some(ref script) = self.witness_script {
+            append_pair(stream, PSBT_IN_WITNESS_SCRIPT, &[], script);
+        }
+        for (pubkey, key_source) in self.bip32_derivation.iter() {
+            append_pair(stream, PSBT_IN_BIP32_DERIVATION, pubkey, &key_source.to_bytes());
+        }
+        if let Some(ref script_sig) = self.final_script_sig {
+            append_pair(stream, PSBT_IN_FINAL_SCRIPTSIG, &[], script_sig);
+        }
+        if let Some(ref witness) = self.final_script_witness {
+            let mut witness_stream = Stream::default();
+            witness_stream.append_list(witness);
+            append_pair(stream, PSBT_IN_FINAL_SCRIPTWITNESS, &[], &witness_stream.out());
+        }
+        append_unknown(stream, &self.unknown);
+    }
+
+    fn deserialize(pairs: Vec<(Vec<u8>, Vec<u8>)>) -> Result<PsbtInput, PsbtError> {
+        let mut input = PsbtInput::default();
+        for (key, value) in pairs {
+            let (key_type, key_data) = (key[0], &key[1..]);
+            let invalid_value = |error| PsbtError::InvalidValue {
+                key: key.clone(),
+                error,
+            };
+            match key_type {
+                PSBT_IN_NON_WITNESS_UTXO => {
+                    expect_empty_key_data(&key)?;
+                    input.non_witness_utxo = Some(deserialize(value.as_slice()).map_err(invalid_value)?);
+                },
+                PSBT_IN_WITNESS_UTXO => {
+                    expect_empty_key_data(&key)?;
+                    input.witness_utxo = Some(deserialize(value.as_slice()).map_err(invalid_value)?);
+                },
+                PSBT_IN_PARTIAL_SIG => {
+                    input.partial_sigs.insert(key_data.to_vec(), value);
+                },
+                PSBT_IN_SIGHASH_TYPE => {
+                    expect_empty_key_data(&key)?;
+                    input.sighash_type = Some(deserialize(value.as_slice()).map_err(invalid_value)?);
+                },
+                PSBT_IN_REDEEM_SCRIPT => {
+                    expect_empty_key_data(&key)?;
+                    input.redeem_script = Some(value.into());
+                },
+                PSBT_IN_WITNESS_SCRIPT => {
+                    expect_empty_key_data(&key)?;
+                    input.witness_script = Some(value.into());
+                },
+                PSBT_IN_BIP32_DERIVATION => {
+                    let key_source = KeySource::from_bytes(&value).map_err(invalid_value)?;
+                    input.bip32_derivation.insert(key_data.to_vec(), key_source);
+                },
+                PSBT_IN_FINAL_SCRIPTSIG => {
+                    expect_empty_key_data(&key)?;
+                    input.final_script_sig = Some(value.into());
+                },
+                PSBT_IN_FINAL_SCRIPTWITNESS => {
+                    expect_empty_key_data(&key)?;
+                    let witness = Reader::new(&value).read_list().map_err(invalid_value)?;
+                    input.final_script_witness = Some(witness);
+                },
+                _ => {
+                    input.unknown.insert(key, value);
+                },
+            }
+        }
+        Ok(input)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PsbtOutput {
+    pub redeem_script: Option<Bytes>,
+    pub witness_script: Option<Bytes>,
+    pub bip32_derivation: BTreeMap<Vec<u8>, KeySource>,
+    /// The key-value pairs of the unknown types are kept as is.
+    pub unknown: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl PsbtOutput {
+    fn merge(&mut self, other: PsbtOutput) {
+        merge_option(&mut self.redeem_script, other.redeem_script);
+        merge_option(&mut self.witness_script, other.witness_script);
+        merge_map(&mut self.bip32_derivation, other.bip32_derivation);
+        merge_map(&mut self.unknown, other.unknown);
+    }
+
+    fn serialize(&self, stream: &mut Stream) {
+        if let Some(ref script) = self.redeem_script {
+            append_pair(stream, PSBT_OUT_REDEEM_SCRIPT, &[], script);
+        }
+        if let Some(ref script) = self.witness_script {
+            append_pair(stream, PSBT_OUT_WITNESS_SCRIPT, &[], script);
+        }
+        for (pubkey, key_source) in self.bip32_derivation.iter() {
+            append_pair(stream, PSBT_OUT_BIP32_DERIVATION, pubkey, &key_source.to_bytes());
+        }
+        append_unknown(stream, &self.unknown);
+    }
+
+    fn deserialize(pairs: Vec<(Vec<u8>, Vec<u8>)>) -> Result<PsbtOutput, PsbtError> {
+        let mut output = PsbtOutput::default();
+        for (key, value) in pairs {
+            match key[0] {
+                PSBT_OUT_REDEEM_SCRIPT => {
+                    expect_empty_key_data(&key)?;
+                    output.redeem_script = Some(value.into());
+                },
+                PSBT_OUT_WITNESS_SCRIPT => {
+                    expect_empty_key_data(&key)?;
+                    output.witness_script = Some(value.into());
+                },
+                PSBT_OUT_BIP32_DERIVATION => {
+                    let key_source = KeySource::from_bytes(&value).map_err(|error| PsbtError::InvalidValue {
+                        key: key.clone(),
+                        error,
+                    })?;
+                    output.bip32_derivation.insert(key[1..].to_vec(), key_source);
+                },
+                _ => {
+                    output.unknown.insert(key, value);
+                },
+            }
+        }
+        Ok(output)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Psbt {
+    /// The transaction with empty script sigs and witnesses.
+    pub unsigned_tx: Transaction,
+    pub inputs: Vec<PsbtInput>,
+    pub outputs: Vec<PsbtOutput>,
+    /// The global key-value pairs of the unknown types are kept as is.
+    pub unknown: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl Psbt {
+    /// Creates the PSBT with the empty input and output maps.
+    pub fn from_unsigned_tx(unsigned_tx: Transaction) -> Result<Psbt, PsbtError> {
+        let has_signatures = unsigned_tx
+            .inputs
+            .iter()
+            .any(|input| !input.script_sig.is_empty() || input.has_witness());
+        if has_signatures {
+            return Err(PsbtError::UnsignedTxHasSignatures);
+        }
+        Ok(Psbt {
+            inputs: vec![PsbtInput::default(); unsigned_tx.inputs.len()],
+            outputs: vec![PsbtOutput::default(); unsigned_tx.outputs.len()],
+            unsigned_tx,
+            unknown: BTreeMap::new(),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Bytes {
+        let mut stream = Stream::default();
+        stream.append_slice(PSBT_MAGIC);
+        append_pair(&mut stream, PSBT_GLOBAL_UNSIGNED_TX, &[], &serialize(&self.unsigned_tx));
+        append_unknown(&mut stream, &self.unknown);
+        stream.append(&0u8);
+        for input in self.inputs.iter() {
+            input.serialize(&mut stream);
+            stream.append(&0u8);
+        }
+        for output in self.outputs.iter() {
+            output.serialize(&mut stream);
+            stream.append(&0u8);
+        }
+        stream.out()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Psbt, PsbtError> {
+        if !bytes.starts_with(PSBT_MAGIC) {
+            return Err(PsbtError::InvalidMagic);
+        }
+        let mut reader = Reader::new(&bytes[PSBT_MAGIC.len()..]);
+
+        let mut unsigned_tx = None;
+        let mut unknown = BTreeMap::new();
+        for (key, value) in read_map(&mut reader)? {
+            match key[0] {
+                PSBT_GLOBAL_UNSIGNED_TX => {
+                    expect_empty_key_data(&key)?;
+                    let tx: Transaction = deserialize(value.as_slice()).map_err(|error| PsbtError::InvalidValue {
+                        key: key.clone(),
+                        error,
+                    })?;
+                    unsigned_tx = Some(tx);
+                },
+                _ => {
+                    unknown.insert(key, value);
+                },
+            }
+        }
+        let unsigned_tx = unsigned_tx.ok_or(PsbtError::MissingUnsignedTx)?;
+        let mut psbt = Psbt::from_unsigned_tx(unsigned_tx)?;
+        psbt.unknown = unknown;
+
+        for input in psbt.inputs.iter_mut() {
+            *input = PsbtInput::deserialize(read_map(&mut reader)?)?;
+        }
+        for output in psbt.outputs.iter_mut() {
+            *output = PsbtOutput::deserialize(read_map(&mut reader)?)?;
+        }
+        if !reader.is_finished() {
+            return Err(PsbtError::MapsCountMismatch);
+        }
+        Ok(psbt)
+    }
+
+    /// Combines the signatures and the other data of the PSBT for the same transaction.
+    pub fn merge(&mut self, other: Psbt) -> Result<(), PsbtError> {
+        if self.unsigned_tx.hash() != other.unsigned_tx.hash() {
+            return Err(PsbtError::DifferentUnsignedTx);
+        }
+        for (input, other_input) in self.inputs.iter_mut().zip(other.inputs) {
+            input.merge(other_input);
+        }
+        for (output, other_output) in self.outputs.iter_mut().zip(other.outputs) {
+            output.merge(other_output);
+        }
+        merge_map(&mut self.unknown, other.unknown);
+        Ok(())
+    }
+
+    /// Returns the output spent by the input.
+    pub fn spent_output(&self, input_index: usize) -> Result<TransactionOutput, PsbtError> {
+        let psbt_input = self
+            .inputs
+            .get(input_index)
+            .ok_or(PsbtError::InputIndexOutOfRange(input_index))?;
+        if let Some(ref output) = psbt_input.witness_utxo {
+            return Ok(output.clone());
+        }
+        let prev_tx = psbt_input
+            .non_witness_utxo
+            .as_ref()
+            .ok_or(PsbtError::MissingUtxo(input_index))?;
+        let outpoint = &self.unsigned_tx.inputs[input_index].previous_output;
+        if prev_tx.hash() != outpoint.hash {
+            return Err(PsbtError::InvalidNonWitnessUtxo(input_index));
+        }
+        prev_tx
+            .outputs
+            .get(outpoint.index as usize)
+            .cloned()
+            .ok_or(PsbtError::InvalidNonWitnessUtxo(input_index))
+    }
+
+    /// Builds the final script sigs and witnesses of the P2PKH and P2WPKH inputs from their partial signatures.
+    /// The inputs finalized already are left as is.
+    pub fn finalize(&mut self) -> Result<(), PsbtError> {
+        for input_index in 0..self.inputs.len() {
+            if self.inputs[input_index].is_finalized() {
+                continue;
+            }
+            let script: Script = self.spent_output(input_index)?.script_pubkey.into();
+            let (key_hash, is_witness) = if script.is_pay_to_public_key_hash() {
+                (&script[3..23], false)
+            } else if script.is_pay_to_witness_key_hash() {
+                (&script[2..22], true)
+            } else {
+                return Err(PsbtError::UnsupportedScript(input_index));
+            };
+
+            let input = &mut self.inputs[input_index];
+            let (pubkey, signature) = input
+                .partial_sigs
+                .iter()
+                .find(|(pubkey, _)| &*dhash160(pubkey) == key_hash)
+                .map(|(pubkey, signature)| (pubkey.clone(), signature.clone()))
+                .ok_or(PsbtError::MissingSignature(input_index))?;
+            if is_witness {
+                input.final_script_witness = Some(vec![signature.into(), pubkey.into()]);
+            } else {
+                let script_sig = Builder::default().push_data(&signature).push_data(&pubkey).into_bytes();
+                input.final_script_sig = Some(script_sig);
+            }
+
+            // the finalizer removes the data not required anymore
+            input.partial_sigs.clear();
+            input.sighash_type = None;
+            input.redeem_script = None;
+            input.witness_script = None;
+            input.bip32_derivation.clear();
+        }
+        Ok(())
+    }
+
+    /// Returns the signed transaction if all the inputs are finalized.
+    pub fn extract_tx(&self) -> Result<Transaction, PsbtError> {
+        let mut tx = self.unsigned_tx.clone();
+        for (input_index, (tx_input, psbt_input)) in tx.inputs.iter_mut().zip(self.inputs.iter()).enumerate() {
+            if !psbt_input.is_finalized() {
+                return Err(PsbtError::NotFinalized(input_index));
+            }
+            tx_input.script_sig = psbt_input.final_script_sig.clone().unwrap_or_default();
+            tx_input.script_witness = psbt_input.final_script_witness.clone().unwrap_or_default();
+        }
+        Ok(tx)
+    }
+}
+
+fn merge_option<T>(value: &mut Option<T>, other: Option<T>) {
+    if value.is_none() {
+        *value = other;
+    }
+}
+
+fn merge_map<V>(map: &mut BTreeMap<Vec<u8>, V>, other: BTreeMap<Vec<u8>, V>) {
+    for (key, value) in other {
+        map.entry(key).or_insert(value);
+    }
+}
+
+fn append_pair(stream: &mut Stream, key_type: u8, key_data: &[u8], value: &[u8]) {
+    stream
+        .append(&CompactInteger::from(key_data.len() + 1))
+        .append(&key_type)
+        .append_slice(key_data)
+        .append(&CompactInteger::from(value.len()))
+        .append_slice(value);
+}
+
+fn append_unknown(stream: &mut Stream, unknown: &BTreeMap<Vec<u8>, Vec<u8>>) {
+    for (key, value) in unknown.iter() {
+        stream
+            .append(&CompactInteger::from(key.len()))
+            .append_slice(key)
+            .append(&CompactInteger::from(value.len()))
+            .append_slice(value);
+    }
+}
+
+/// Reads the key-value pairs until the 0x00 separator.
+/// Every returned key has the key type at least.
+fn read_map(reader: &mut Reader<&[u8]>) -> Result<Vec<(Vec<u8>, Vec<u8>)>, PsbtError> {
+    let mut pairs: Vec<(Vec<u8>, Vec<u8>)> = Vec::new();
+    loop {
+        let key: Bytes = reader.read()?;
+        if key.is_empty() {
+            return Ok(pairs);
+        }
+        let value: Bytes = reader.read()?;
+        let key = key.take();
+        if pairs.iter().any(|(known, _)| *known == key) {
+            return Err(PsbtError::DuplicateKey(key));
+        }
+        pairs.push((key, value.take()));
+    }
+}
+
+fn expect_empty_key_data(key: &[u8]) -> Result<(), PsbtError> {
+    if key.len() != 1 {
+        return Err(PsbtError::InvalidKey(key.to_vec()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{KeySource, Psbt, PsbtError, PSBT_IN_BIP32_DERIVATION};
+    use chain::{OutPoint, Transaction, TransactionInput, TransactionOutput};
+    use keys::{KeyPair, Private};
+    use ser::Error as SerError;
+    use {Builder, Script};
+
+    fn unsigned_tx(prev_tx: &Transaction) -> Transaction {
+        Transaction {
+            version: 2,
+            inputs: vec![TransactionInput {
+                previous_output: OutPoint {
+                    hash: prev_tx.hash(),
+                    index: 0,
+                },
+                script_sig: Default::default(),
+                sequence: 0xffffffff,
+                script_witness: vec![],
+            }],
+            outputs: vec![TransactionOutput {
+                value: 90000,
+                script_pubkey: "76a9140000000000000000000000000000000000000000000088ac".into(),
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn prev_tx(script_pubkey: Script) -> Transaction {
+        Transaction {
+            version: 1,
+            inputs: vec![TransactionInput::coinbase(vec![1, 2, 3].into())],
+            outputs: vec![TransactionOutput {
+                value: 100000,
+                script_pubkey: script_pubkey.to_bytes(),
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn key_pair() -> KeyPair {
+        let private: Private = "5HxWvvfubhXpYYpS3tJkw6fq9jE9j18THftkZjHHfmFiWtmAbrj".into();
+        KeyPair::from_private(private).unwrap()
+    }
+
+    #[test]
+    fn test_psbt_serialization_roundtrip() {
+        let key_pair = key_pair();
+        let prev_tx = prev_tx(Builder::build_p2pkh(&key_pair.public().address_hash()));
+        let mut psbt = Psbt::from_unsigned_tx(unsigned_tx(&prev_tx)).unwrap();
+        psbt.inputs[0].non_witness_utxo = Some(prev_tx);
+        psbt.inputs[0].sighash_type = Some(1);
+        psbt.inputs[0]
+            .bip32_derivation
+            .insert(key_pair.public().to_vec(), KeySource {
+                fingerprint: [1, 2, 3, 4].into(),
+                path: vec![0x8000002c, 0x80000000, 0x80000000, 0, 1],
+            });
+        psbt.outputs[0].unknown.insert(vec![0xfc, 1], vec![2, 3]);
+        psbt.unknown.insert(vec![0xfc, 4], vec![5]);
+
+        let bytes = psbt.to_bytes();
+        assert!(bytes.starts_with(b"psbt\xff"));
+        let actual = Psbt::from_bytes(&bytes).unwrap();
+        assert_eq!(actual, psbt);
+
+        assert_eq!(Psbt::from_bytes(&bytes[1..]), Err(PsbtError::InvalidMagic));
+    }
+
+    #[test]
+    fn test_psbt_empty_derivation_value() {
+        let key_pair = key_pair();
+        let prev_tx = prev_tx(Builder::build_p2pkh(&key_pair.public().address_hash()));
+        let mut psbt = Psbt::from_unsigned_tx(unsigned_tx(&prev_tx)).unwrap();
+        // the BIP32 derivation key with the empty value is serialized as is from the unknown pairs
+        let mut key = vec![PSBT_IN_BIP32_DERIVATION];
+        key.extend_from_slice(key_pair.public());
+        psbt.inputs[0].unknown.insert(key.clone(), Vec::new());
+
+        let bytes = psbt.to_bytes();
+        assert_eq!(
+            Psbt::from_bytes(&bytes),
+            Err(PsbtError::InvalidValue {
+                key,
+                error: SerError::MalformedData,
+            })
+        );
+    }
+
+    #[test]
+    fn test_psbt_merge_and_finalize_p2pkh() {
+        let key_pair = key_pair();
+        let prev_tx = prev_tx(Builder::build_p2pkh(&key_pair.public().address_hash()));
+        let mut psbt = Psbt::from_unsigned_tx(unsigned_tx(&prev_tx)).unwrap();
+        psbt.inputs[0].non_witness_utxo = Some(prev_tx);
+        assert_eq!(psbt.finalize(), Err(PsbtError::MissingSignature(0)));
+        assert_eq!(psbt.extract_tx(), Err(PsbtError::NotFinalized(0)));
+
+        let mut signed = psbt.clone();
+        signed.inputs[0]
+            .partial_sigs
+            .insert(key_pair.public().to_vec(), vec![0x30, 0x01]);
+        psbt.merge(signed).unwrap();
+        psbt.finalize().unwrap();
+        assert!(psbt.inputs[0].partial_sigs.is_empty());
+
+        let tx = psbt.extract_tx().unwrap();
+        let expected_script_sig = Builder::default()
+            .push_data(&[0x30, 0x01])
+            .push_data(key_pair.public())
+            .into_bytes();
+        assert_eq!(tx.inputs[0].script_sig, expected_script_sig);
+        assert!(tx.inputs[0].script_witness.is_empty());
+    }
+
+    #[test]
+    fn test_psbt_finalize_p2wpkh() {
+        let key_pair = key_pair();
+        let script_pubkey = Builder::build_p2wpkh(&key_pair.public().address_hash());
+        let prev_tx = prev_tx(script_pubkey.clone());
+        let mut psbt = Psbt::from_unsigned_tx(unsigned_tx(&prev_tx)).unwrap();
+        psbt.inputs[0].witness_utxo = Some(TransactionOutput {
+            value: 100000,
+            script_pubkey: script_pubkey.to_bytes(),
+        });
+        psbt.inputs[0]
+            .partial_sigs
+            .insert(key_pair.public().to_vec(), vec![0x30, 0x02]);
+        psbt.finalize().unwrap();
+
+        let tx = psbt.extract_tx().unwrap();
+        assert!(tx.inputs[0].script_sig.is_empty());
+        assert_eq!(tx.inputs[0].script_witness, vec![
+            vec![0x30, 0x02].into(),
+            key_pair.public().to_vec().into()
+        ]);
+    }
+
+    #[test]
+    fn test_psbt_merge_different_tx() {
+        let prev_tx = prev_tx(Builder::build_p2pkh(&Default::default()));
+        let mut psbt = Psbt::from_unsigned_tx(unsigned_tx(&prev_tx)).unwrap();
+        let mut other_tx = unsigned_tx(&prev_tx);
+        other_tx.lock_time = 1;
+        let other = Psbt::from_unsigned_tx(other_tx).unwrap();
+        assert_eq!(psbt.merge(other), Err(PsbtError::DifferentUnsignedTx));
+    }
+}
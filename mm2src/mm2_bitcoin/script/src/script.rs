@@ -1,6 +1,7 @@
 //! Serialized script, used inside transaction inputs and outputs.
 
 use bytes::Bytes;
+use hash::H256;
 use keys::{self, AddressHash, Public};
 use std::{fmt, ops};
 use {Error, Opcode};
@@ -19,6 +20,7 @@ pub enum ScriptType {
     NullData,
     WitnessScript,
     WitnessKey,
+    WitnessTaproot,
     // Qtum specific
     CallSender,
     CreateSender,
@@ -33,6 +35,8 @@ pub struct ScriptAddress {
     pub kind: keys::Type,
     /// Public key hash.
     pub hash: AddressHash,
+    /// The x-only output key of the taproot (witness v1) address, `hash` isn't used if it's set.
+    pub taproot_output_key: Option<H256>,
 }
 
 impl ScriptAddress {
@@ -41,6 +45,7 @@ impl ScriptAddress {
         ScriptAddress {
             kind: keys::Type::P2PKH,
             hash,
+            taproot_output_key: None,
         }
     }
 
@@ -49,6 +54,7 @@ impl ScriptAddress {
         ScriptAddress {
             kind: keys::Type::P2SH,
             hash,
+            taproot_output_key: None,
         }
     }
 
@@ -57,6 +63,16 @@ impl ScriptAddress {
         ScriptAddress {
             kind: keys::Type::P2WPKH,
             hash,
+            taproot_output_key: None,
+        }
+    }
+
+    /// Creates P2TR-type ScriptAddress
+    pub fn new_p2tr(output_key: H256) -> Self {
+        ScriptAddress {
+            kind: keys::Type::P2TR,
+            hash: Default::default(),
+            taproot_output_key: Some(output_key),
         }
     }
 }
@@ -149,6 +165,11 @@ impl Script {
         self.data.len() == 34 && self.data[0] == Opcode::OP_0 as u8 && self.data[1] == Opcode::OP_PUSHBYTES_32 as u8
     }
 
+    /// Extra-fast test for pay-to-taproot scripts.
+    pub fn is_pay_to_taproot(&self) -> bool {
+        self.data.len() == 34 && self.data[0] == Opcode::OP_1 as u8 && self.data[1] == Opcode::OP_PUSHBYTES_32 as u8
+    }
+
     /// Extra-fast test for multisig scripts.
     pub fn is_multisig_script(&self) -> bool {
         if self.data.len() < 3 {
@@ -337,6 +358,8 @@ impl Script {
             ScriptType::WitnessKey
         } else if self.is_pay_to_witness_script_hash() {
             ScriptType::WitnessScript
+        } else if self.is_pay_to_taproot() {
+            ScriptType::WitnessTaproot
         // TODO add Call
         } else {
             ScriptType::NonStandard
@@ -431,6 +454,7 @@ impl Script {
                 Ok(vec![]) // TODO
             },
             ScriptType::WitnessKey => Ok(vec![ScriptAddress::new_p2wpkh(self.data[2..22].into())]),
+            ScriptType::WitnessTaproot => Ok(vec![ScriptAddress::new_p2tr(self.data[2..34].into())]),
             ScriptType::CallSender => {
                 Ok(vec![]) // TODO
             },
@@ -584,8 +608,9 @@ pub fn is_witness_commitment_script(script: &[u8]) -> bool {
 #[cfg(test)]
 mod tests {
     use super::{Script, ScriptAddress, ScriptType};
-    use crypto::ChecksumType;
-    use keys::{Address, Public};
+    use crypto::{sha256, ChecksumType};
+    use hash::H256;
+    use keys::{self, Address, Public};
     use {Builder, Error, Opcode};
 
     /// Maximum number of bytes pushable to the stack
@@ -615,6 +640,14 @@ mod tests {
         assert!(!script2.is_pay_to_witness_script_hash());
     }
 
+    #[test]
+    fn test_is_pay_to_taproot() {
+        let script: Script = "5120a60869f0dbcf1dc659c9cecbaf8050135ea9e8cdc487053f1dc6880949dc684c".into();
+        let script2: Script = "0020a60869f0dbcf1dc659c9cecbaf8050135ea9e8cdc487053f1dc6880949dc684c".into();
+        assert!(script.is_pay_to_taproot());
+        assert!(!script2.is_pay_to_taproot());
+    }
+
     #[test]
     fn test_script_debug() {
         use std::fmt::Write;
@@ -799,6 +832,35 @@ OP_ADD
         );
     }
 
+    #[test]
+    fn test_build_p2wsh() {
+        let redeem_script: Script = "210279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798ac".into();
+        let script = Builder::build_p2wsh(&sha256(&redeem_script));
+        assert_eq!(script.script_type(), ScriptType::WitnessScript);
+        assert_eq!(
+            script,
+            "00201863143c14c5166804bd19203356da136c985678cd4d27a1b8c6329604903262".into()
+        );
+    }
+
+    #[test]
+    fn test_build_p2tr() {
+        // https://github.com/bitcoin/bips/blob/master/bip-0086.mediawiki#test-vectors
+        let output_key: H256 = "a60869f0dbcf1dc659c9cecbaf8050135ea9e8cdc487053f1dc6880949dc684c".into();
+        let script = Builder::build_p2tr(&output_key);
+        assert_eq!(script.script_type(), ScriptType::WitnessTaproot);
+        assert_eq!(
+            script,
+            "5120a60869f0dbcf1dc659c9cecbaf8050135ea9e8cdc487053f1dc6880949dc684c".into()
+        );
+        assert_eq!(script.parse_witness_program(), Some((1, &*output_key as &[u8])));
+        assert_eq!(
+            script.extract_destinations(),
+            Ok(vec![ScriptAddress::new_p2tr(output_key.clone())])
+        );
+        assert_eq!(ScriptAddress::new_p2tr(output_key).kind, keys::Type::P2TR);
+    }
+
     #[test]
     fn test_extract_destinations_multisig() {
         let pubkey1_bytes = [0; 33];
@@ -1,3 +1,6 @@
+#[cfg(not(target_arch = "wasm32"))]
+use crate::proxy_transport::ProxyTcpTransport;
+use crate::ProxyDialer;
 use crate::{adex_ping::AdexPing,
             peers_exchange::{PeerAddresses, PeersExchange},
             request_response::{build_request_response_behaviour, PeerRequest, PeerResponse, RequestResponseBehaviour,
@@ -9,6 +12,8 @@ use futures::{channel::{mpsc::{channel, Receiver, Sender},
                         oneshot},
               future::{abortable, join_all, poll_fn, AbortHandle},
               Future, SinkExt, StreamExt};
+#[cfg(not(target_arch = "wasm32"))]
+use libp2p::core::either::EitherTransport;
 use libp2p::swarm::{IntoProtocolsHandler, NetworkBehaviour, ProtocolsHandler};
 use libp2p::{core::{ConnectedPoint, Multiaddr, Transport},
              identity,
@@ -622,12 +627,13 @@ pub async fn spawn_gossipsub(
     spawn_fn: fn(Box<dyn Future<Output = ()> + Send + Unpin + 'static>) -> (),
     to_dial: Vec<String>,
     node_type: NodeType,
+    proxy_dialer: Option<ProxyDialer>,
     on_poll: impl Fn(&AtomicDexSwarm) + Send + 'static,
 ) -> (Sender<AdexBehaviourCmd>, AdexEventRx, PeerId, AbortHandle) {
     let (result_tx, result_rx) = futures::channel::oneshot::channel();
     let fut = async move {
         let (cmd_tx, event_rx, peer_id, p2p_abort) =
-            start_gossipsub(netid, force_key, spawn_fn, to_dial, node_type, on_poll);
+            start_gossipsub(netid, force_key, spawn_fn, to_dial, node_type, proxy_dialer, on_poll);
         result_tx.send((cmd_tx, event_rx, peer_id, p2p_abort)).unwrap();
     };
 
@@ -652,6 +658,7 @@ fn start_gossipsub(
     spawn_fn: fn(Box<dyn Future<Output = ()> + Send + Unpin + 'static>) -> (),
     to_dial: Vec<String>,
     node_type: NodeType,
+    proxy_dialer: Option<ProxyDialer>,
     on_poll: impl Fn(&AtomicDexSwarm) + Send + 'static,
 ) -> (Sender<AdexBehaviourCmd>, AdexEventRx, PeerId, AbortHandle) {
     let i_am_relay = node_type.is_relay();
@@ -663,6 +670,9 @@ fn start_gossipsub(
 
     #[cfg(target_arch = "wasm32")]
     let transport = {
+        if let Some(proxy_dialer) = proxy_dialer {
+            void::unreachable(proxy_dialer);
+        }
         let websocket = libp2p::wasm_ext::ffi::websocket_transport();
         libp2p::wasm_ext::ExtTransport::new(websocket)
     };
@@ -670,11 +680,22 @@ fn start_gossipsub(
     #[cfg(not(target_arch = "wasm32"))]
     let transport = {
         let tcp = libp2p::tcp::TokioTcpConfig::new().nodelay(true);
-        let dns_tcp =
-            libp2p::dns::TokioDnsConfig::custom(tcp, libp2p::dns::ResolverConfig::google(), Default::default())
-                .unwrap();
-        let ws_dns_tcp = libp2p::websocket::WsConfig::new(dns_tcp.clone());
-        dns_tcp.or_transport(ws_dns_tcp)
+        match proxy_dialer {
+            Some(proxy_dialer) => {
+                let proxy_tcp = ProxyTcpTransport::new(proxy_dialer);
+                let ws_proxy_tcp = libp2p::websocket::WsConfig::new(proxy_tcp.clone());
+                // the outgoing connections are established through the proxy only,
+                // the plain TCP transport is used to listen for the incoming ones
+                EitherTransport::Left(proxy_tcp.or_transport(ws_proxy_tcp).or_transport(tcp))
+            },
+            None => {
+                let dns_tcp =
+                    libp2p::dns::TokioDnsConfig::custom(tcp, libp2p::dns::ResolverConfig::google(), Default::default())
+                        .unwrap();
+                let ws_dns_tcp = libp2p::websocket::WsConfig::new(dns_tcp.clone());
+                EitherTransport::Right(dns_tcp.or_transport(ws_dns_tcp))
+            },
+        }
     };
 
     let noise_keys = noise::Keypair::<noise::X25519Spec>::new()
@@ -870,10 +891,15 @@ pub fn parse_relay_address(addr: String, port: u16) -> Multiaddr {
     format!("/ip4/{}/tcp/{}", addr, port).parse().unwrap()
 }
 
-/// The `addr` is expected to be an IP of the relay.
+/// The `addr` is expected to be either an IP of the relay or `/dns/<DOMAIN>/tcp/<PORT>`.
+/// The latter is used when the relays are dialed through the proxy resolving the domain names.
 #[cfg(all(not(test), not(target_arch = "wasm32")))]
-pub fn parse_relay_address(ipv4_addr: String, port: u16) -> Multiaddr {
-    format!("/ip4/{}/tcp/{}", ipv4_addr, port).parse().unwrap()
+pub fn parse_relay_address(addr: String, port: u16) -> Multiaddr {
+    if addr.starts_with("/dns/") && addr.contains("/tcp/") {
+        return addr.parse().unwrap();
+    }
+
+    format!("/ip4/{}/tcp/{}", addr, port).parse().unwrap()
 }
 
 /// Request the peers sequential until a `PeerResponse::Ok()` will not be received.
@@ -31,7 +31,7 @@ impl Node {
             network_ws_port: network_port + 10,
         };
         let (cmd_tx, mut event_rx, peer_id, _) =
-            spawn_gossipsub(333, None, spawn_boxed, seednodes, node_type, |_| {}).await;
+            spawn_gossipsub(333, None, spawn_boxed, seednodes, node_type, None, |_| {}).await;
 
         // spawn a response future
         let cmd_tx_fut = cmd_tx.clone();
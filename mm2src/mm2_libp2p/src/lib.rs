@@ -3,6 +3,7 @@
 mod adex_ping;
 pub mod atomicdex_behaviour;
 pub mod peers_exchange;
+#[cfg(not(target_arch = "wasm32"))] pub mod proxy_transport;
 pub mod request_response;
 mod runtime;
 
@@ -16,6 +17,12 @@ pub use atomicdex_behaviour::{spawn_gossipsub, NodeType};
 pub use atomicdex_gossipsub::{GossipsubEvent, GossipsubMessage, MessageId};
 pub use libp2p::PeerId;
 pub use peers_exchange::PeerAddresses;
+#[cfg(not(target_arch = "wasm32"))]
+pub use proxy_transport::ProxyDialer;
+
+/// The outgoing connections can't be proxied in a browser.
+#[cfg(target_arch = "wasm32")]
+pub type ProxyDialer = void::Void;
 
 lazy_static! {
     static ref SECP_VERIFY: Secp256k1<VerifyOnly> = Secp256k1::verification_only();
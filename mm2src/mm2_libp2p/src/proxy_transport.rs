@@ -0,0 +1,100 @@
+//! The TCP transport dialing the peers through a proxy, e.g. Tor.
+//!
+//! The connection to the proxy itself is established by the [`ProxyDialer`] provided by the caller.
+//! The host names of the `/dns` addresses are passed to the dialer as is, so they are resolved by the proxy.
+//! The transport doesn't listen, the incoming connections should be accepted by another transport.
+
+use futures::future::{BoxFuture, Ready};
+use futures::stream::BoxStream;
+use futures::FutureExt;
+use libp2p::core::transport::{ListenerEvent, TransportError};
+use libp2p::core::{Multiaddr, Transport};
+use libp2p::multiaddr::Protocol;
+use std::io;
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tokio_util::compat::{Compat, TokioAsyncReadCompatExt};
+
+pub type ProxyDialFut = BoxFuture<'static, io::Result<TcpStream>>;
+
+/// Opens a TCP connection to the `host` and `port` through the proxy.
+pub type ProxyDialer = Arc<dyn Fn(String, u16) -> ProxyDialFut + Send + Sync>;
+
+#[derive(Clone)]
+pub struct ProxyTcpTransport {
+    dialer: ProxyDialer,
+}
+
+impl ProxyTcpTransport {
+    pub fn new(dialer: ProxyDialer) -> ProxyTcpTransport { ProxyTcpTransport { dialer } }
+}
+
+impl Transport for ProxyTcpTransport {
+    type Output = Compat<TcpStream>;
+    type Error = io::Error;
+    type Listener = BoxStream<'static, Result<ListenerEvent<Self::ListenerUpgrade, Self::Error>, Self::Error>>;
+    type ListenerUpgrade = Ready<Result<Self::Output, Self::Error>>;
+    type Dial = BoxFuture<'static, Result<Self::Output, Self::Error>>;
+
+    fn listen_on(self, addr: Multiaddr) -> Result<Self::Listener, TransportError<Self::Error>> {
+        Err(TransportError::MultiaddrNotSupported(addr))
+    }
+
+    fn dial(self, addr: Multiaddr) -> Result<Self::Dial, TransportError<Self::Error>> {
+        let (host, port) = match multiaddr_to_host_port(&addr) {
+            Some(host_port) => host_port,
+            None => return Err(TransportError::MultiaddrNotSupported(addr)),
+        };
+        let dial_fut = (self.dialer)(host, port);
+        Ok(async move {
+            let stream = dial_fut.await?;
+            stream.set_nodelay(true)?;
+            Ok(stream.compat())
+        }
+        .boxed())
+    }
+
+    fn address_translation(&self, _listen: &Multiaddr, _observed: &Multiaddr) -> Option<Multiaddr> { None }
+}
+
+/// Returns the host and the port of the `/ip4`, `/ip6` or `/dns` TCP address optionally followed by `/p2p`.
+fn multiaddr_to_host_port(addr: &Multiaddr) -> Option<(String, u16)> {
+    let mut iter = addr.iter();
+    let host = match iter.next()? {
+        Protocol::Ip4(ip) => ip.to_string(),
+        Protocol::Ip6(ip) => ip.to_string(),
+        Protocol::Dns(name) | Protocol::Dns4(name) | Protocol::Dns6(name) => name.into_owned(),
+        _ => return None,
+    };
+    let port = match iter.next()? {
+        Protocol::Tcp(port) => port,
+        _ => return None,
+    };
+    // the address is still dialed through the proxy if it's followed by the peer id,
+    // so the plain TCP transport never dials it directly
+    match iter.next() {
+        None | Some(Protocol::P2p(_)) => Some((host, port)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_multiaddr_to_host_port() {
+        let addr: Multiaddr = "/dns/seed.example.com/tcp/38890".parse().unwrap();
+        assert_eq!(
+            multiaddr_to_host_port(&addr),
+            Some(("seed.example.com".to_owned(), 38890))
+        );
+
+        let addr: Multiaddr = "/ip4/127.0.0.1/tcp/38890".parse().unwrap();
+        assert_eq!(multiaddr_to_host_port(&addr), Some(("127.0.0.1".to_owned(), 38890)));
+
+        // the websocket addresses are handled by `WsConfig` wrapping this transport
+        let addr: Multiaddr = "/dns/seed.example.com/tcp/38900/ws".parse().unwrap();
+        assert_eq!(multiaddr_to_host_port(&addr), None);
+    }
+}
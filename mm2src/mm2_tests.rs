@@ -8249,6 +8249,75 @@ fn test_get_public_key() {
     )
 }
 
+#[test]
+#[cfg(not(target_arch = "wasm32"))]
+fn test_create_and_unlock_wallet() {
+    let coins = json!([
+        {"coin":"RICK","asset":"RICK","rpcport":8923,"txversion":4,"protocol":{"type":"UTXO"}},
+    ]);
+
+    // Neither "passphrase" nor "wallet_password" is set, so MM2 should wait for the "unlock_wallet" call.
+    let mm = MarketMakerIt::start(
+        json!({
+            "gui": "nogui",
+            "netid": 9998,
+            "rpc_password": "password",
+            "coins": coins,
+            "i_am_seed": true,
+        }),
+        "password".into(),
+        None,
+    )
+    .unwrap();
+    let (_dump_log, _dump_dashboard) = mm.mm_dump();
+    log!({"Log path: {}", mm.log_path.display()});
+    block_on(mm.wait_for_log(22., |log| log.contains(">>>>>>>>> DEX stats "))).unwrap();
+
+    let wallet_rpc = |method: &str, params: Json| {
+        block_on(mm.rpc(json!({
+            "userpass": "password",
+            "mmrpc": "2.0",
+            "method": method,
+            "params": params,
+            "id": 0
+        })))
+        .unwrap()
+    };
+
+    let rc = wallet_rpc("get_public_key", json!({}));
+    assert_eq!(
+        rc.0, 403,
+        "get_public_key must be forbidden until the wallet is unlocked: {}",
+        rc.1
+    );
+
+    let rc = wallet_rpc(
+        "create_wallet",
+        json!({"passphrase": "bob passphrase", "password": "weak"}),
+    );
+    assert_eq!(rc.0, 400, "create_wallet must fail on a weak password: {}", rc.1);
+
+    let rc = wallet_rpc(
+        "create_wallet",
+        json!({"passphrase": "bob passphrase", "password": "StrongPass123*"}),
+    );
+    assert!(rc.0.is_success(), "!create_wallet: {}", rc.1);
+
+    let rc = wallet_rpc("unlock_wallet", json!({"password": "StrongPass123&"}));
+    assert_eq!(rc.0, 401, "unlock_wallet must fail on an invalid password: {}", rc.1);
+
+    let rc = wallet_rpc("unlock_wallet", json!({"password": "StrongPass123*"}));
+    assert!(rc.0.is_success(), "!unlock_wallet: {}", rc.1);
+    let res: Json = json::from_str(&rc.1).unwrap();
+    assert_eq!(
+        res["result"]["public_key"],
+        "022cd3021a2197361fb70b862c412bc8e44cff6951fa1de45ceabfdd9b4c520420"
+    );
+
+    let rc = wallet_rpc("get_public_key", json!({}));
+    assert!(rc.0.is_success(), "!get_public_key: {}", rc.1);
+}
+
 #[test]
 #[cfg(not(target_arch = "wasm32"))]
 fn test_get_orderbook_with_same_orderbook_ticker() {
@@ -8376,6 +8445,7 @@ mod wasm_bindgen_tests {
             eth_taker,
             jst_taker,
             PAYMENT_LOCKTIME,
+            None,
         );
 
         let maker_swap = MakerSwap::new(
@@ -8390,6 +8460,7 @@ mod wasm_bindgen_tests {
             eth_maker,
             jst_maker,
             PAYMENT_LOCKTIME,
+            None,
         );
 
         let taker_swap_fut = run_taker_swap(RunTakerSwapInput::StartNew(taker_swap), ctx_taker);
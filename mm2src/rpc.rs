@@ -70,6 +70,10 @@ const PUBLIC_METHODS: &[Option<&str>] = &[
     None,
 ];
 
+/// Lists the RPC methods that can be called before the wallet is unlocked, cf. `lp_wallet_init`.
+/// The other methods are available after `lp_init` has initialized the databases, P2P and the swap kick-starts.
+const LOCKED_WALLET_METHODS: &[&str] = &["create_wallet", "help", "stop", "unlock_wallet", "version"];
+
 pub type DispatcherResult<T> = Result<T, MmError<DispatcherError>>;
 
 #[derive(Display, Serialize, SerializeErrorType)]
@@ -89,6 +93,10 @@ pub enum DispatcherError {
     UserpassIsInvalid(RateLimitError),
     #[display(fmt = "Error parsing mmrpc version: {}", _0)]
     InvalidMmRpcVersion(String),
+    #[display(fmt = "Wallet is locked, please call 'unlock_wallet' first")]
+    WalletIsLocked,
+    #[display(fmt = "MM2 is not initialized yet, please retry later")]
+    NotInitialized,
 }
 
 impl HttpStatusCode for DispatcherError {
@@ -100,7 +108,9 @@ impl HttpStatusCode for DispatcherError {
             DispatcherError::LocalHostOnly
             | DispatcherError::UserpassIsNotSet
             | DispatcherError::UserpassIsInvalid(_)
-            | DispatcherError::Banned => StatusCode::FORBIDDEN,
+            | DispatcherError::Banned
+            | DispatcherError::WalletIsLocked => StatusCode::FORBIDDEN,
+            DispatcherError::NotInitialized => StatusCode::SERVICE_UNAVAILABLE,
         }
     }
 }
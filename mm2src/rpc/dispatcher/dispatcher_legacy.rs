@@ -1,4 +1,4 @@
-use super::PUBLIC_METHODS;
+use super::{LOCKED_WALLET_METHODS, PUBLIC_METHODS};
 use common::mm_ctx::MmArc;
 #[cfg(not(target_arch = "wasm32"))] use common::wio::CPUPOOL;
 use common::HyRes;
@@ -15,6 +15,7 @@ use crate::mm2::lp_ordermatch::{best_orders_rpc, buy, cancel_all_orders, cancel_
 use crate::mm2::lp_swap::{active_swaps_rpc, all_swaps_uuids_by_filter, ban_pubkey_rpc, coins_needed_for_kick_start,
                           import_swaps, list_banned_pubkeys_rpc, max_taker_vol, my_recent_swaps, my_swap_status,
                           recover_funds_of_swap, stats_swap_status, unban_pubkeys_rpc};
+use crate::mm2::lp_wallet::is_wallet_unlocked;
 use crate::mm2::rpc::rate_limiter::{process_rate_limit, RateLimitContext};
 use coins::{convert_address, convert_utxo_address, get_enabled_coins, get_trade_fee, kmd_rewards_info, my_tx_history,
             send_raw_transaction, set_required_confirmations, set_requires_notarization, show_priv_key,
@@ -152,6 +153,12 @@ pub async fn process_single_request(
         return ERR!("Your ip is banned.");
     }
     try_s!(auth(&req, &ctx, &client).await);
+    if !ctx.initialized.copy_or(false) && !LOCKED_WALLET_METHODS.contains(&req["method"].as_str().unwrap_or_default()) {
+        if !is_wallet_unlocked(&ctx) {
+            return ERR!("Wallet is locked, please call 'unlock_wallet' first");
+        }
+        return ERR!("MM2 is not initialized yet, please retry later");
+    }
 
     let handler = match dispatcher(req, ctx.clone()) {
         DispatcherRes::Match(handler) => handler,
@@ -1,10 +1,19 @@
 use super::lp_protocol::{MmRpcBuilder, MmRpcRequest};
-use super::{DispatcherError, DispatcherResult, PUBLIC_METHODS};
+use super::{DispatcherError, DispatcherResult, LOCKED_WALLET_METHODS, PUBLIC_METHODS};
 use crate::mm2::rpc::rate_limiter::{process_rate_limit, RateLimitContext};
 use crate::{mm2::lp_stats::{add_node_to_version_stat, remove_node_from_version_stat, start_version_stat_collection,
                             stop_version_stat_collection, update_version_stat_collection},
-            mm2::lp_swap::trade_preimage_rpc,
+            mm2::lp_swap::{bump_fee_rpc, trade_preimage_rpc},
+            mm2::lp_wallet::{change_wallet_password, create_wallet, export_wallet, is_wallet_unlocked, unlock_wallet},
             mm2::rpc::get_public_key::get_public_key};
+use coins::hd_wallet::{get_new_address, list_addresses};
+use coins::message_signing::{sign_message, verify_message};
+use coins::utxo::coin_control::{freeze_utxos, list_unspents, unfreeze_utxos};
+use coins::utxo::consolidation::consolidate_utxos;
+use coins::utxo::electrum_status::electrum_servers_status;
+use coins::utxo::fee_bump::cpfp;
+use coins::utxo::psbt::{finalize_psbt, sign_psbt};
+use coins::utxo::qtum_delegation::{add_delegation, get_delegation_info, remove_delegation};
 use coins::withdraw;
 use common::log::{error, warn};
 use common::mm_ctx::MmArc;
@@ -36,6 +45,12 @@ pub async fn process_single_request(
     }
 
     auth(&request, &ctx, &client).await?;
+    if !ctx.initialized.copy_or(false) && !LOCKED_WALLET_METHODS.contains(&request.method.as_str()) {
+        if !is_wallet_unlocked(&ctx) {
+            return MmError::err(DispatcherError::WalletIsLocked);
+        }
+        return MmError::err(DispatcherError::NotInitialized);
+    }
     dispatcher(request, ctx).await
 }
 
@@ -92,13 +107,33 @@ async fn auth(request: &MmRpcRequest, ctx: &MmArc, client: &SocketAddr) -> Dispa
 
 async fn dispatcher(request: MmRpcRequest, ctx: MmArc) -> DispatcherResult<Response<Vec<u8>>> {
     match request.method.as_str() {
+        "add_delegation" => handle_mmrpc(ctx, request, add_delegation).await,
         "add_node_to_version_stat" => handle_mmrpc(ctx, request, add_node_to_version_stat).await,
+        "bump_fee" => handle_mmrpc(ctx, request, bump_fee_rpc).await,
+        "change_wallet_password" => handle_mmrpc(ctx, request, change_wallet_password).await,
+        "consolidate_utxos" => handle_mmrpc(ctx, request, consolidate_utxos).await,
+        "cpfp" => handle_mmrpc(ctx, request, cpfp).await,
+        "create_wallet" => handle_mmrpc(ctx, request, create_wallet).await,
+        "electrum_servers_status" => handle_mmrpc(ctx, request, electrum_servers_status).await,
+        "export_wallet" => handle_mmrpc(ctx, request, export_wallet).await,
+        "finalize_psbt" => handle_mmrpc(ctx, request, finalize_psbt).await,
+        "freeze_utxos" => handle_mmrpc(ctx, request, freeze_utxos).await,
+        "get_delegation_info" => handle_mmrpc(ctx, request, get_delegation_info).await,
+        "get_new_address" => handle_mmrpc(ctx, request, get_new_address).await,
         "get_public_key" => handle_mmrpc(ctx, request, get_public_key).await,
+        "list_addresses" => handle_mmrpc(ctx, request, list_addresses).await,
+        "list_unspents" => handle_mmrpc(ctx, request, list_unspents).await,
+        "remove_delegation" => handle_mmrpc(ctx, request, remove_delegation).await,
         "remove_node_from_version_stat" => handle_mmrpc(ctx, request, remove_node_from_version_stat).await,
+        "sign_message" => handle_mmrpc(ctx, request, sign_message).await,
+        "sign_psbt" => handle_mmrpc(ctx, request, sign_psbt).await,
         "start_version_stat_collection" => handle_mmrpc(ctx, request, start_version_stat_collection).await,
         "stop_version_stat_collection" => handle_mmrpc(ctx, request, stop_version_stat_collection).await,
         "update_version_stat_collection" => handle_mmrpc(ctx, request, update_version_stat_collection).await,
         "trade_preimage" => handle_mmrpc(ctx, request, trade_preimage_rpc).await,
+        "unfreeze_utxos" => handle_mmrpc(ctx, request, unfreeze_utxos).await,
+        "unlock_wallet" => handle_mmrpc(ctx, request, unlock_wallet).await,
+        "verify_message" => handle_mmrpc(ctx, request, verify_message).await,
         "withdraw" => handle_mmrpc(ctx, request, withdraw).await,
         _ => MmError::err(DispatcherError::NoSuchMethod),
     }